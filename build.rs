@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // the service of the gRPC server, generated with a protoc of its own so
+    // that none needs to be installed
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        // without the `connect` of clients, written for a later edition
+        tonic_prost_build::configure()
+            .build_transport(false)
+            .compile_protos(&["proto/palladiumdb.proto"], &["proto"])
+            .expect("failed to generate the gRPC service");
+    }
+}
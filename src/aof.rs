@@ -0,0 +1,520 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::Instant;
+
+use crate::checksum::crc32_update;
+use crate::codec::{invalid_data, Decode, Encode};
+use crate::collections::map::{Map, MapBuilder};
+use crate::files;
+use crate::wal::{apply_mutation, Mutation, SyncPolicy};
+
+/// The file is rewritten once it grows past this size, unless configured
+/// otherwise.
+pub const DEFAULT_REWRITE_MIN_SIZE: u64 = 64 << 20;
+
+/// Length of the header of a command: payload length and CRC-32.
+const HEADER_LEN: usize = 8;
+
+/// Appends the command `payload` to `buf`, along with its header.
+fn frame(buf: &mut Vec<u8>, payload: &[u8]) {
+    let crc = crc32_update(0, payload);
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf.extend_from_slice(payload);
+}
+
+/// Reads as many bytes as available into `buf`, up to its length.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(read)
+}
+
+/// Configures an [`Aof`], see [`Aof::with_builders`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::aof::{Aof, AofBuilder};
+/// use palladiumdb::collections::map::MapBuilder;
+/// use palladiumdb::wal::SyncPolicy;
+///
+/// let path = std::env::temp_dir().join("palladiumdb-doc-aof-builder.aof");
+/// # let _ = std::fs::remove_file(&path);
+/// let builder = AofBuilder::new()
+///     .sync_policy(SyncPolicy::EveryNMillis(1000))
+///     .rewrite_percentage(50)
+///     .rewrite_min_size(1 << 20);
+/// let aof: Aof<u64, u64> = Aof::with_builders(MapBuilder::new(), builder, &path).unwrap();
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct AofBuilder {
+    sync_policy: SyncPolicy,
+    rewrite_percentage: u64,
+    rewrite_min_size: u64,
+}
+
+impl Default for AofBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AofBuilder {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        AofBuilder {
+            sync_policy: SyncPolicy::default(),
+            rewrite_percentage: 100,
+            rewrite_min_size: DEFAULT_REWRITE_MIN_SIZE,
+        }
+    }
+
+    /// Sets when appended commands are synced to stable storage.
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Sets by how many percent the file must have grown since it was
+    /// opened or last rewritten to be rewritten on its own, 0 disabling
+    /// automatic rewrites.
+    pub fn rewrite_percentage(mut self, rewrite_percentage: u64) -> Self {
+        self.rewrite_percentage = rewrite_percentage;
+        self
+    }
+
+    /// Sets the size in bytes under which the file is never rewritten on
+    /// its own.
+    pub fn rewrite_min_size(mut self, rewrite_min_size: u64) -> Self {
+        self.rewrite_min_size = rewrite_min_size;
+        self
+    }
+}
+
+/// The file commands are appended to.
+struct Appender {
+    file: File,
+    len: u64,
+    /// Length of the file when it was opened or last rewritten.
+    base_len: u64,
+    last_sync: Instant,
+    /// Commands appended since a rewrite started, to be appended to the
+    /// rewritten file as well.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+/// Thread-Safe [`Map`] whose writes are logged as commands to an
+/// append-only file, in the manner of the Redis AOF.
+///
+/// Unlike a [`Durable`](crate::wal::Durable) map, whose log only grows, the
+/// file compacts itself by being rewritten from a live snapshot of the map,
+/// with one command per entry. The rewrite copies the map while writers
+/// keep going, the commands appended in the meantime being buffered and
+/// added to the rewritten file before it replaces the current one. Rewrites
+/// are run by [`Aof::rewrite`], or by the write that grows the file past
+/// the configured size.
+///
+/// Opening an `Aof` replays its commands. A command left partially written
+/// at the end of the file by a crash is cut off.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::aof::Aof;
+///
+/// let path = std::env::temp_dir().join("palladiumdb-doc-aof.aof");
+/// # let _ = std::fs::remove_file(&path);
+/// let map = Aof::open(&path).unwrap();
+/// for i in 0..100u64 {
+///     map.put(&"counter".to_string(), i).unwrap();
+/// }
+/// let len = map.file_len();
+/// map.rewrite().unwrap();
+/// assert!(map.file_len() < len);
+/// drop(map);
+///
+/// let map: Aof<String, u64> = Aof::open(&path).unwrap();
+/// assert_eq!(map.get(&"counter".to_string()), Some(99));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct Aof<K, V, H = RandomState> {
+    map: Map<K, V, H>,
+    path: PathBuf,
+    options: AofBuilder,
+    appender: Mutex<Appender>,
+    /// Serializes rewrites.
+    rewriting: Mutex<()>,
+}
+
+impl<K, V> Aof<K, V, RandomState>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+{
+    /// Opens the map logged to the file at `path` with the default
+    /// configuration, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_builders(MapBuilder::new(), AofBuilder::new(), path)
+    }
+}
+
+impl<K, V, H> Aof<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+    H: BuildHasher,
+{
+    /// Opens the map logged to the file at `path`, configured by
+    /// `map_builder`, with its file configured by `aof_builder`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a command before the last one fails
+    /// its checksum, or was not written for these key and value types.
+    pub fn with_builders<P: AsRef<Path>>(
+        map_builder: MapBuilder<H>,
+        aof_builder: AofBuilder,
+        path: P,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let map = map_builder.build();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut len = 0;
+        loop {
+            let mut header = [0u8; HEADER_LEN];
+            let read = read_up_to(&mut reader, &mut header)?;
+            if read < HEADER_LEN {
+                break;
+            }
+            let command_len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let mut payload = vec![0; command_len as usize];
+            if read_up_to(&mut reader, &mut payload)? < payload.len() {
+                break;
+            }
+            let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            let end = len + (HEADER_LEN + payload.len()) as u64;
+            if crc32_update(0, &payload) != crc {
+                // the last command may have been torn by a crash
+                if end == file_len {
+                    break;
+                }
+                return Err(invalid_data("command checksum mismatch"));
+            }
+            apply_mutation(&map, &payload)?;
+            len = end;
+        }
+        drop(reader);
+        if file_len > len {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        Ok(Aof {
+            map,
+            path,
+            options: aof_builder,
+            appender: Mutex::new(Appender {
+                file,
+                len,
+                base_len: len,
+                last_sync: Instant::now(),
+                rewrite_buffer: None,
+            }),
+            rewriting: Mutex::new(()),
+        })
+    }
+
+    /// Returns the path of the file the map is logged to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the length in bytes of the file the map is logged to.
+    pub fn file_len(&self) -> u64 {
+        self.appender.lock().unwrap().len
+    }
+
+    /// Appends `command` to the file, and to the buffer of the rewrite in
+    /// progress if any, syncing the file if `sync` is set or the sync policy
+    /// is due.
+    fn append(&self, command: &[u8], sync: bool) -> io::Result<()> {
+        let mut record = Vec::with_capacity(HEADER_LEN + command.len());
+        frame(&mut record, command);
+
+        let mut appender = self.appender.lock().unwrap();
+        appender.file.write_all(&record)?;
+        appender.len += record.len() as u64;
+        if let Some(buffer) = &mut appender.rewrite_buffer {
+            buffer.extend_from_slice(&record);
+        }
+        if sync || self.options.sync_policy.is_due(appender.last_sync) {
+            appender.file.sync_data()?;
+            appender.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file if it grew past the configured size, unless a
+    /// rewrite is already running.
+    fn rewrite_if_due(&self) -> io::Result<()> {
+        let options = &self.options;
+        let due = {
+            let appender = self.appender.lock().unwrap();
+            let growth = appender.base_len / 100 * options.rewrite_percentage;
+            options.rewrite_percentage > 0
+                && appender.len >= options.rewrite_min_size
+                && appender.len >= appender.base_len + growth
+        };
+        if !due {
+            return Ok(());
+        }
+        match self.rewriting.try_lock() {
+            Ok(rewriting) => self.rewrite_locked(rewriting),
+            Err(TryLockError::WouldBlock) => Ok(()),
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        }
+    }
+
+    /// Logs then establishes a key value mapping for the key value pair.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the file, in which case the map is left
+    /// unchanged, or of the rewrite the write started.
+    pub fn put(&self, key: &K, value: V) -> io::Result<()> {
+        self.put_with(key, value, false)
+    }
+
+    /// Logs then establishes a key value mapping for the key value pair,
+    /// syncing the file before returning whatever the sync policy.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the file, in which case the map is left
+    /// unchanged, or of the rewrite the write started.
+    pub fn put_durable(&self, key: &K, value: V) -> io::Result<()> {
+        self.put_with(key, value, true)
+    }
+
+    fn put_with(&self, key: &K, value: V, sync: bool) -> io::Result<()> {
+        let mut command = Vec::new();
+        Mutation::encode_put(&mut command, key, &value);
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            self.append(&command, sync)?;
+            locked.put(key, value);
+            Ok::<_, io::Error>(())
+        })?;
+        self.rewrite_if_due()
+    }
+
+    /// Logs then erases the value associated with `key`, returning it if it
+    /// was present. Nothing is logged if the key is absent.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the file, in which case the map is left
+    /// unchanged, or of the rewrite the write started.
+    pub fn remove(&self, key: &K) -> io::Result<Option<V>> {
+        let value = self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            let value = locked.get(key);
+            if value.is_some() {
+                let mut command = Vec::new();
+                Mutation::<K, V>::encode_remove(&mut command, key);
+                self.append(&command, false)?;
+                locked.unmap(key);
+            }
+            Ok::<_, io::Error>(value)
+        })?;
+        self.rewrite_if_due()?;
+        Ok(value)
+    }
+
+    /// Returns the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Flushes every appended command to stable storage, regardless of the
+    /// sync policy.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut appender = self.appender.lock().unwrap();
+        appender.file.sync_data()?;
+        appender.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Rewrites the file with one command per entry of the map, waiting for
+    /// the rewrite in progress if any.
+    ///
+    /// The rewritten file is written next to the current one and renamed
+    /// over it once synced, so a crash while rewriting leaves the current
+    /// file intact.
+    pub fn rewrite(&self) -> io::Result<()> {
+        self.rewrite_locked(self.rewriting.lock().unwrap())
+    }
+
+    fn rewrite_locked(&self, _rewriting: MutexGuard<'_, ()>) -> io::Result<()> {
+        let mut temp = self.path.as_os_str().to_owned();
+        temp.push(".rewrite");
+        let mut out = BufWriter::new(File::create(&temp)?);
+
+        self.appender.lock().unwrap().rewrite_buffer = Some(Vec::new());
+        let mut copy = || -> io::Result<()> {
+            let (mut command, mut record) = (Vec::new(), Vec::new());
+            for (key, value) in self.map.iter() {
+                command.clear();
+                record.clear();
+                Mutation::encode_put(&mut command, &key, &value);
+                frame(&mut record, &command);
+                out.write_all(&record)?;
+            }
+            Ok(())
+        };
+        let copied = copy();
+
+        let mut appender = self.appender.lock().unwrap();
+        let buffer = appender.rewrite_buffer.take().unwrap();
+        let file = copied
+            .and_then(|()| out.write_all(&buffer))
+            .and_then(|()| out.into_inner().map_err(io::IntoInnerError::into_error))
+            .and_then(|file| file.sync_all().map(|()| file))
+            .and_then(|file| files::rename_durably(&temp, &self.path).map(|()| file));
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                return Err(err);
+            }
+        };
+        appender.len = file.metadata()?.len();
+        appender.base_len = appender.len;
+        appender.file = file;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aof, AofBuilder};
+    use crate::collections::map::MapBuilder;
+    use crate::wal::SyncPolicy;
+    use std::fs::{self, OpenOptions};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_durable_puts_sync_whatever_the_policy() {
+        let path =
+            std::env::temp_dir().join(format!("palladiumdb-aof-sync-{}.aof", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let builder = AofBuilder::new().sync_policy(SyncPolicy::Never);
+        let aof = Aof::<u64, u64>::with_builders(MapBuilder::new(), builder, &path).unwrap();
+        let last_sync = || aof.appender.lock().unwrap().last_sync;
+
+        let opened = last_sync();
+        thread::sleep(Duration::from_millis(1));
+        aof.put(&1, 1).unwrap();
+        aof.remove(&1).unwrap();
+        assert_eq!(last_sync(), opened);
+        aof.put_durable(&2, 2).unwrap();
+        assert!(last_sync() > opened);
+        drop(aof);
+
+        let aof = Aof::<u64, u64>::open(&path).unwrap();
+        assert_eq!((aof.get(&1), aof.get(&2)), (None, Some(2)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrites_under_writes_and_cuts_torn_tail() {
+        let path = std::env::temp_dir().join(format!("palladiumdb-aof-{}.aof", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let builder = AofBuilder::new()
+            .sync_policy(SyncPolicy::Never)
+            .rewrite_min_size(16 << 10);
+        let open = || Aof::<u64, u64>::with_builders(MapBuilder::new(), builder.clone(), &path);
+
+        let aof = Arc::new(open().unwrap());
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let aof = aof.clone();
+                thread::spawn(move || {
+                    for round in 0..20 {
+                        for key in (thread..400).step_by(4) {
+                            aof.put(&key, round).unwrap();
+                        }
+                        if round % 5 == 0 {
+                            aof.rewrite().unwrap();
+                        }
+                    }
+                    for key in (thread..400).step_by(8) {
+                        assert_eq!(aof.remove(&key).unwrap(), Some(19));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        // rewritten on its own as well, 8000 puts taking about 200 KiB
+        assert!(aof.file_len() < 64 << 10, "{}", aof.file_len());
+        aof.rewrite().unwrap();
+        aof.put(&1000, 1).unwrap();
+        aof.sync().unwrap();
+        drop(aof);
+
+        // simulate a crash in the middle of appending the last put
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 3).unwrap();
+
+        let aof = open().unwrap();
+        assert!(aof.file_len() < len - 3);
+        assert_eq!(aof.len(), 200);
+        for key in 0..400 {
+            let expected = match key % 8 < 4 {
+                true => None,
+                false => Some(19),
+            };
+            assert_eq!(aof.get(&key), expected);
+        }
+        assert_eq!(aof.get(&1000), None);
+        aof.put(&1000, 2).unwrap();
+        drop(aof);
+        assert_eq!(open().unwrap().get(&1000), Some(2));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+//! Interactive client of a palladiumdb server, or of a database directory
+//! opened directly.
+//!
+//! ```text
+//! palladium-cli [--connect ADDRESS | --dir DIR] [--keyspace NAME]
+//! ```
+//!
+//! Servers listening on a Unix domain socket are connected to at `unix://`
+//! followed by its path. Keys and values are typed as words, or between double quotes, where
+//! `\xNN` escapes any byte. Commands are completed with tab, and remembered
+//! across sessions in `~/.palladium_history`.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process;
+
+use palladiumdb::codec::{decode_all, Encode};
+use palladiumdb::db::Database;
+use palladiumdb::json::{self, JsonValue};
+use palladiumdb::protocol::{self, Request, Response};
+use palladiumdb::server::{self, DEFAULT_ADDRESS};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+const USAGE: &str = "usage: palladium-cli [--connect ADDRESS | --dir DIR] [--keyspace NAME]";
+
+const HELP: &str = "\
+get KEY               print the value of KEY
+put KEY VALUE         map KEY to VALUE
+del KEY               remove the entry of KEY
+scan [PREFIX] [LIMIT] list the entries whose key starts with PREFIX
+stats                 count the entries of the keyspace and their bytes
+dump [FILE]           write the entries of the keyspace as JSON
+info [SECTION]        print the statistics of the server or database
+use KEYSPACE          switch to another keyspace
+auth USER PASSWORD    authenticate with the server
+help                  print this help
+quit                  leave";
+
+const COMMANDS: &[&str] = &[
+    "get", "put", "del", "scan", "stats", "dump", "info", "use", "auth", "help", "quit",
+];
+
+/// A connection to a server, over TCP or a Unix socket.
+trait Socket: Read + Write {}
+
+impl<S: Read + Write> Socket for S {}
+
+/// Where requests are carried out.
+enum Backend {
+    Remote(Box<dyn Socket>),
+    Local(Box<Database>),
+}
+
+impl Backend {
+    fn execute(&mut self, request: Request) -> Result<Response, String> {
+        let response = match self {
+            Backend::Remote(stream) => {
+                let mut payload = Vec::new();
+                request.encode(&mut payload);
+                let mut out = BufWriter::new(&mut **stream);
+                protocol::write_frame(&mut out, &payload).map_err(|err| err.to_string())?;
+                out.flush().map_err(|err| err.to_string())?;
+                drop(out);
+                match protocol::read_frame(&mut BufReader::new(&mut **stream)) {
+                    Ok(Some(payload)) => decode_all(&payload).map_err(|err| err.to_string())?,
+                    Ok(None) => return Err("the server closed the connection".to_string()),
+                    Err(err) => return Err(err.to_string()),
+                }
+            }
+            Backend::Local(db) => server::execute(db, request).map_err(|err| err.to_string())?,
+        };
+        match response {
+            Response::Error(message) => Err(message),
+            response => Ok(response),
+        }
+    }
+
+    fn scan(&mut self, keyspace: &str, prefix: Vec<u8>, limit: u64) -> Result<Vec<Entry>, String> {
+        let scan = Request::Scan {
+            keyspace: keyspace.to_string(),
+            prefix,
+            limit,
+        };
+        match self.execute(scan)? {
+            Response::Entries(entries) => Ok(entries),
+            response => Err(format!("unexpected response {:?}", response)),
+        }
+    }
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Splits `line` into arguments, separated by whitespace unless between
+/// double quotes, where `\xNN`, `\n`, `\t`, `\"` and `\\` are escapes.
+fn split_args(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = Vec::new();
+    let mut bytes = line.bytes().peekable();
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        let mut arg = Vec::new();
+        match bytes.peek() {
+            None => return Ok(args),
+            Some(b'"') => {
+                bytes.next();
+                loop {
+                    match bytes.next().ok_or("unbalanced quotes")? {
+                        b'"' => break,
+                        b'\\' => arg.push(match bytes.next().ok_or("unbalanced quotes")? {
+                            b'n' => b'\n',
+                            b't' => b'\t',
+                            b'x' => {
+                                let hex = [bytes.next(), bytes.next()];
+                                let hex: Option<Vec<u8>> = hex.iter().copied().collect();
+                                hex.and_then(|hex| String::from_utf8(hex).ok())
+                                    .and_then(|hex| u8::from_str_radix(&hex, 16).ok())
+                                    .ok_or("invalid \\x escape")?
+                            }
+                            byte => byte,
+                        }),
+                        byte => arg.push(byte),
+                    }
+                }
+                if bytes.peek().is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                    return Err("closing quote followed by a character".to_string());
+                }
+            }
+            Some(_) => {
+                while let Some(byte) = bytes.next_if(|byte| !byte.is_ascii_whitespace()) {
+                    arg.push(byte);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Writes `bytes` as typed in, quoted and escaped unless they are printable
+/// ASCII without spaces or quotes.
+fn display(bytes: &[u8]) -> String {
+    let plain = |byte: &u8| byte.is_ascii_graphic() && !matches!(byte, b'"' | b'\\');
+    if !bytes.is_empty() && bytes.iter().all(plain) {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    let mut quoted = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b' ' => quoted.push(' '),
+            byte if plain(&byte) => quoted.push(byte as char),
+            byte => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes `bytes` as a JSON string if they are valid UTF-8, as an array of
+/// bytes otherwise.
+fn bytes_to_json(bytes: Vec<u8>) -> JsonValue {
+    match String::from_utf8(bytes) {
+        Ok(text) => JsonValue::String(text),
+        Err(err) => JsonValue::Array(
+            err.into_bytes()
+                .into_iter()
+                .map(|byte| JsonValue::Number(byte.to_string()))
+                .collect(),
+        ),
+    }
+}
+
+/// Completes the names of the commands.
+struct CliHelper;
+
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let word = &line[..pos];
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|command| command.to_string())
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+struct Session {
+    backend: Backend,
+    keyspace: String,
+}
+
+impl Session {
+    /// Carries out the command made of `args`, printing its outcome.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the user quits.
+    fn run(&mut self, args: Vec<Vec<u8>>) -> Result<bool, String> {
+        let mut args = args.into_iter();
+        let command = match args.next() {
+            Some(command) => String::from_utf8_lossy(&command).to_ascii_lowercase(),
+            None => return Ok(true),
+        };
+        let args: Vec<_> = args.collect();
+        let keyspace = self.keyspace.clone();
+        match (command.as_str(), &args[..]) {
+            ("get", [key]) => match self.backend.execute(Request::Get {
+                keyspace,
+                key: key.clone(),
+            })? {
+                Response::Value(Some(value)) => println!("{}", display(&value)),
+                _ => println!("(none)"),
+            },
+            ("put", [key, value]) => {
+                self.backend.execute(Request::Put {
+                    keyspace,
+                    key: key.clone(),
+                    value: value.clone(),
+                })?;
+                println!("OK");
+            }
+            ("del", [key]) => match self.backend.execute(Request::Delete {
+                keyspace,
+                key: key.clone(),
+            })? {
+                Response::Deleted(true) => println!("(deleted)"),
+                _ => println!("(none)"),
+            },
+            ("scan", rest) if rest.len() <= 2 => {
+                let limit = match rest.get(1) {
+                    Some(limit) => String::from_utf8_lossy(limit)
+                        .parse()
+                        .map_err(|_| "LIMIT must be a number")?,
+                    None => u64::MAX,
+                };
+                let prefix = rest.first().cloned().unwrap_or_default();
+                let entries = self.backend.scan(&keyspace, prefix, limit)?;
+                for (key, value) in &entries {
+                    println!("{} = {}", display(key), display(value));
+                }
+                println!("({} entries)", entries.len());
+            }
+            ("stats", []) => {
+                let entries = self.backend.scan(&keyspace, Vec::new(), u64::MAX)?;
+                let key_bytes: usize = entries.iter().map(|(key, _)| key.len()).sum();
+                let value_bytes: usize = entries.iter().map(|(_, value)| value.len()).sum();
+                println!("keyspace:    {}", keyspace);
+                println!("entries:     {}", entries.len());
+                println!("key bytes:   {}", key_bytes);
+                println!("value bytes: {}", value_bytes);
+                if let Backend::Local(db) = &self.backend {
+                    println!("keyspaces:   {}", db.map_names().join(", "));
+                }
+            }
+            ("dump", rest) if rest.len() <= 1 => {
+                let entries = self.backend.scan(&keyspace, Vec::new(), u64::MAX)?;
+                let entries = entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        JsonValue::Object(vec![
+                            ("key".to_string(), bytes_to_json(key)),
+                            ("value".to_string(), bytes_to_json(value)),
+                        ])
+                    })
+                    .collect();
+                let dump = json::to_string(&JsonValue::Array(entries));
+                match rest.first() {
+                    Some(path) => {
+                        let path = PathBuf::from(String::from_utf8_lossy(path).into_owned());
+                        let mut file = File::create(&path).map_err(|err| err.to_string())?;
+                        writeln!(file, "{}", dump).map_err(|err| err.to_string())?;
+                        println!("written to {}", path.display());
+                    }
+                    None => println!("{}", dump),
+                }
+            }
+            ("info", rest) if rest.len() <= 1 => {
+                let section = rest
+                    .first()
+                    .map(|section| String::from_utf8_lossy(section).into_owned());
+                match self.backend.execute(Request::Info { section })? {
+                    Response::Value(Some(report)) => print!("{}", String::from_utf8_lossy(&report)),
+                    response => return Err(format!("unexpected response {:?}", response)),
+                }
+            }
+            ("use", [name]) => {
+                self.keyspace = String::from_utf8(name.clone()).map_err(|_| "not UTF-8")?;
+            }
+            ("auth", [user, password]) => {
+                let text = |arg: &Vec<u8>| String::from_utf8_lossy(arg).into_owned();
+                self.backend.execute(Request::Auth {
+                    user: text(user),
+                    password: text(password),
+                })?;
+                println!("OK");
+            }
+            ("help", []) => println!("{}", HELP),
+            ("quit", []) | ("exit", []) => return Ok(false),
+            (command, _) if COMMANDS.contains(&command) => {
+                return Err(format!("wrong arguments for {}, see help", command))
+            }
+            (command, _) => return Err(format!("unknown command {}, see help", command)),
+        }
+        Ok(true)
+    }
+}
+
+/// Connects to the server at `address`, through a Unix socket if it starts
+/// with `unix://`.
+fn connect(address: &str) -> io::Result<Box<dyn Socket>> {
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix(server::UNIX_SCHEME) {
+        return Ok(Box::new(UnixStream::connect(path)?));
+    }
+    Ok(Box::new(TcpStream::connect(address)?))
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("palladium-cli: {}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let (mut address, mut dir, mut keyspace) = (DEFAULT_ADDRESS.to_string(), None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--connect" => address = value(),
+            "--dir" => dir = Some(value()),
+            "--keyspace" => keyspace = Some(value()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(USAGE),
+        }
+    }
+    let (backend, prompt) = match dir {
+        Some(dir) => match Database::open(&dir) {
+            Ok(db) => (Backend::Local(Box::new(db)), dir),
+            Err(err) => fail(&err.to_string()),
+        },
+        None => match connect(&address) {
+            Ok(stream) => (Backend::Remote(stream), address),
+            Err(err) => fail(&format!("cannot connect to {}: {}", address, err)),
+        },
+    };
+    let mut session = Session {
+        backend,
+        keyspace: keyspace.unwrap_or_else(|| server::http::DEFAULT_KEYSPACE.to_string()),
+    };
+
+    let mut editor: Editor<CliHelper, DefaultHistory> =
+        Editor::new().unwrap_or_else(|err| fail(&err.to_string()));
+    editor.set_helper(Some(CliHelper));
+    let history =
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".palladium_history"));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+    loop {
+        let line = match editor.readline(&format!("{} [{}]> ", prompt, session.keyspace)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => fail(&err.to_string()),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match split_args(&line).and_then(|args| session.run(args)) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(message) => eprintln!("error: {}", message),
+        }
+    }
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    if let Backend::Local(db) = &session.backend {
+        if let Err(err) = db.close() {
+            fail(&err.to_string());
+        }
+    }
+    let _ = io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display, split_args};
+
+    #[test]
+    fn test_args_are_split_and_displayed_as_typed() {
+        let args = split_args(r#"put  "user \"1\"" "\x00\xffa b" plain"#).unwrap();
+        let expected = vec![
+            b"put".to_vec(),
+            b"user \"1\"".to_vec(),
+            b"\x00\xffa b".to_vec(),
+            b"plain".to_vec(),
+        ];
+        assert_eq!(args, expected);
+        for arg in &expected {
+            assert_eq!(split_args(&display(arg)).unwrap(), vec![arg.clone()]);
+        }
+        assert_eq!(display(b""), r#""""#);
+        assert!(split_args(r#"get "open"#).is_err());
+        assert!(split_args(r#"get "a"b"#).is_err());
+        assert!(split_args("   ").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+//! Serves a palladiumdb database over TCP, see `palladiumdb::server`.
+//!
+//! ```text
+//! palladiumdb-server [--dir DIR] [--bind ADDRESS] [--unix-socket-mode MODE] [--workers N]
+//!                    [--resp | --http | --grpc]
+//!                    [--tls-cert FILE --tls-key FILE [--tls-client-ca FILE]]
+//!                    [--acl FILE] [--notify-keyspace-events keys|values]
+//!                    [--replication-bind ADDRESS | --replica-of ADDRESS]
+//!                    [--gossip-bind ADDRESS [--gossip-seeds ADDRESS,...]]
+//! ```
+//!
+//! Without `--dir`, the database is kept in memory only. Bound to
+//! `unix://` followed by a path, the server listens on a Unix domain socket
+//! instead, whose file is given the octal permissions of
+//! `--unix-socket-mode`, as in `660`. With `--resp`,
+//! the server speaks to Redis clients rather than in its own protocol, with
+//! `--http` to HTTP clients in JSON, with `--grpc` to gRPC clients, if built
+//! with the `grpc` feature. With `--tls-cert` and `--tls-key`, PEM files of
+//! the certificate chain and private key of the server, connections are
+//! encrypted, and with `--tls-client-ca` only clients presenting a
+//! certificate signed by its certificate authorities are accepted, if built
+//! with the `tls` feature. With `--acl`, clients authenticate as the users
+//! of the file, see `palladiumdb::server::acl`. With
+//! `--notify-keyspace-events`, the writes of clients are published on
+//! channels, with their values for `values`, see
+//! `palladiumdb::server::notify`. With `--replication-bind`, followers
+//! connecting to the address replicate the database, and with
+//! `--replica-of` the database follows the primary at the address, serving
+//! reads only, both requiring `--dir`, see `palladiumdb::replication`. With
+//! `--gossip-bind`, the server joins the cluster of the members at
+//! `--gossip-seeds` by gossip, named after the address it listens on and
+//! sharing it, with that it replicates on, if built with the `cluster`
+//! feature, see `palladiumdb::cluster::gossip`. The
+//! server shuts down on SIGINT or SIGTERM, answering the requests in flight
+//! and closing the database first, which checkpoints it and records the
+//! clean shutdown for the next start to skip recovery.
+
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use palladiumdb::db::Database;
+use palladiumdb::replication::{Primary, Replica};
+use palladiumdb::server::acl::Acl;
+use palladiumdb::server::notify::KeyspaceEvents;
+use palladiumdb::server::{Protocol, ServerBuilder, ShutdownHandle, DEFAULT_ADDRESS, UNIX_SCHEME};
+
+const USAGE: &str = "usage: palladiumdb-server [--dir DIR] [--bind ADDRESS] \
+    [--unix-socket-mode MODE] [--workers N] [--resp | --http | --grpc] [--tls-cert FILE --tls-key FILE [--tls-client-ca FILE]] [--acl FILE] \
+    [--notify-keyspace-events keys|values] [--replication-bind ADDRESS | --replica-of ADDRESS] \
+    [--gossip-bind ADDRESS [--gossip-seeds ADDRESS,...]]";
+
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    SIGNALED.store(true, Ordering::SeqCst);
+}
+
+/// Shuts the server down through `shutdown` once signaled.
+fn watch_signals(shutdown: ShutdownHandle) {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+    thread::spawn(move || {
+        while !SIGNALED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        shutdown.shutdown();
+    });
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("palladiumdb-server: {}", message);
+    process::exit(1)
+}
+
+/// Returns the TLS of the server read from the files given, if any.
+#[cfg(feature = "tls")]
+fn tls(
+    cert: Option<String>,
+    key: Option<String>,
+    client_ca: Option<String>,
+) -> Option<palladiumdb::tls::ServerTls> {
+    use palladiumdb::tls::ServerTls;
+
+    let read = |path: &str| {
+        std::fs::read(path).unwrap_or_else(|err| fail(&format!("cannot read {}: {}", path, err)))
+    };
+    let (cert, key) = match (cert, key) {
+        (Some(cert), Some(key)) => (read(&cert), read(&key)),
+        (None, None) if client_ca.is_none() => return None,
+        _ => fail(USAGE),
+    };
+    let tls = match client_ca {
+        Some(client_ca) => ServerTls::mutual(&cert, &key, &read(&client_ca)),
+        None => ServerTls::new(&cert, &key),
+    };
+    Some(tls.unwrap_or_else(|err| fail(&err.to_string())))
+}
+
+/// Joins the cluster of `seeds` by gossip if bound to `bind`, sharing the
+/// address of the server and that it replicates on, if any.
+#[cfg(feature = "cluster")]
+fn gossip(
+    bind: Option<String>,
+    seeds: Vec<String>,
+    server: &str,
+    replication: Option<String>,
+) -> Option<palladiumdb::cluster::gossip::Gossip> {
+    use palladiumdb::cluster::gossip::{GossipBuilder, REPLICATION, SERVER};
+
+    let bind = bind?;
+    let seeds: Vec<_> = seeds.iter().map(String::as_str).collect();
+    let mut builder = GossipBuilder::new()
+        .bind_address(&bind)
+        .seeds(&seeds)
+        .metadata(SERVER, server);
+    if let Some(replication) = replication {
+        builder = builder.metadata(REPLICATION, &replication);
+    }
+    let gossip = builder
+        .start(server)
+        .unwrap_or_else(|err| fail(&err.to_string()));
+    eprintln!("palladiumdb-server: gossiping on {}", gossip.local_addr());
+    Some(gossip)
+}
+
+fn main() {
+    let mut dir = None;
+    let (mut replication_bind, mut replica_of) = (None, None);
+    let (mut gossip_bind, mut gossip_seeds) = (None, Vec::new());
+    let (mut tls_cert, mut tls_key, mut tls_client_ca) = (None, None, None);
+    let mut bind = DEFAULT_ADDRESS.to_string();
+    let mut builder = ServerBuilder::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--dir" => dir = Some(value()),
+            "--bind" => bind = value(),
+            #[cfg(unix)]
+            "--unix-socket-mode" => match u32::from_str_radix(&value(), 8) {
+                Ok(mode) => builder = builder.unix_socket_mode(mode),
+                Err(_) => fail(USAGE),
+            },
+            "--workers" => match value().parse() {
+                Ok(workers) => builder = builder.workers(workers),
+                Err(_) => fail(USAGE),
+            },
+            "--resp" => builder = builder.protocol(Protocol::Resp),
+            "--http" => builder = builder.protocol(Protocol::Http),
+            #[cfg(feature = "grpc")]
+            "--grpc" => builder = builder.protocol(Protocol::Grpc),
+            #[cfg(not(feature = "grpc"))]
+            "--grpc" => fail("built without the grpc feature"),
+            "--tls-cert" => tls_cert = Some(value()),
+            "--tls-key" => tls_key = Some(value()),
+            "--tls-client-ca" => tls_client_ca = Some(value()),
+            "--acl" => {
+                let path = value();
+                let acl = fs::read_to_string(&path).and_then(|text| Acl::parse(&text));
+                let acl = acl.unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+                builder = builder.acl(Arc::new(acl));
+            }
+            "--notify-keyspace-events" => match value().as_str() {
+                "keys" => builder = builder.keyspace_events(KeyspaceEvents::Keys),
+                "values" => builder = builder.keyspace_events(KeyspaceEvents::Values),
+                _ => fail(USAGE),
+            },
+            "--replication-bind" => replication_bind = Some(value()),
+            "--replica-of" => replica_of = Some(value()),
+            "--gossip-bind" => gossip_bind = Some(value()),
+            "--gossip-seeds" => gossip_seeds = value().split(',').map(str::to_string).collect(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(USAGE),
+        }
+    }
+    if gossip_bind.is_none() && !gossip_seeds.is_empty() {
+        fail(USAGE);
+    }
+    if gossip_bind.is_some() && bind.starts_with(UNIX_SCHEME) {
+        fail("gossip requires --bind to a TCP address");
+    }
+    #[cfg(not(feature = "cluster"))]
+    if gossip_bind.is_some() {
+        fail("built without the cluster feature");
+    }
+    #[cfg(feature = "tls")]
+    if let Some(tls) = tls(tls_cert, tls_key, tls_client_ca) {
+        builder = builder.tls(tls);
+    }
+    #[cfg(not(feature = "tls"))]
+    if tls_cert.or(tls_key).or(tls_client_ca).is_some() {
+        fail("built without the tls feature");
+    }
+
+    let db = match &dir {
+        Some(dir) => Database::open(dir).unwrap_or_else(|err| fail(&err.to_string())),
+        None => Database::new(),
+    };
+    let unclean = db
+        .recovery_report()
+        .filter(|report| !report.clean_shutdown && report.records_replayed > 0);
+    if let Some(report) = unclean {
+        eprintln!(
+            "palladiumdb-server: not shut down cleanly, replayed {} records",
+            report.records_replayed
+        );
+    }
+    let db = Arc::new(db);
+    let (primary, replica) = match (replication_bind, replica_of) {
+        (Some(_), _) | (_, Some(_)) if dir.is_none() => fail("replication requires --dir"),
+        (Some(_), Some(_)) => fail(USAGE),
+        (Some(address), None) => {
+            let primary = Primary::start(db.clone(), &address);
+            let primary = primary.unwrap_or_else(|err| fail(&err.to_string()));
+            eprintln!(
+                "palladiumdb-server: replicating on {}",
+                primary.local_addr()
+            );
+            (Some(primary), None)
+        }
+        (None, Some(address)) => {
+            let replica = Replica::start(db.clone(), &address);
+            let replica = replica.unwrap_or_else(|err| fail(&err.to_string()));
+            (None, Some(replica))
+        }
+        (None, None) => (None, None),
+    };
+    let server = builder
+        .bind_address(&bind)
+        .bind(db.clone())
+        .unwrap_or_else(|err| fail(&err.to_string()));
+    let address = match bind.starts_with(UNIX_SCHEME) {
+        true => bind,
+        false => server
+            .local_addr()
+            .unwrap_or_else(|err| fail(&err.to_string()))
+            .to_string(),
+    };
+    eprintln!("palladiumdb-server: listening on {}", address);
+    #[cfg(feature = "cluster")]
+    let gossip = gossip(
+        gossip_bind,
+        gossip_seeds,
+        &address,
+        primary
+            .as_ref()
+            .map(|primary| primary.local_addr().to_string()),
+    );
+    watch_signals(server.shutdown_handle());
+    if let Err(err) = server.run() {
+        fail(&err.to_string());
+    }
+    #[cfg(feature = "cluster")]
+    drop(gossip);
+    drop((primary, replica));
+    if let Err(err) = db.close() {
+        fail(&err.to_string());
+    }
+}
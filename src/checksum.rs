@@ -0,0 +1,79 @@
+/// Lookup table of the CRC-32 (IEEE 802.3) polynomial, one entry per byte.
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// Returns the CRC-32 of `bytes`, continuing from the CRC `crc` of the bytes
+/// before them, 0 for none.
+pub(crate) fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!crc, |crc, byte| {
+        TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Lookup table of the CRC-64 (Jones) polynomial, one entry per byte, as
+/// used by Redis.
+const TABLE_64: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// Returns the CRC-64 (Jones) of `bytes`, continuing from the CRC `crc` of
+/// the bytes before them, 0 for none.
+pub(crate) fn crc64_update(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, byte| {
+        TABLE_64[((crc ^ u64::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32_update, crc64_update};
+
+    #[test]
+    fn test_crc32_matches_reference_values() {
+        assert_eq!(crc32_update(0, b""), 0);
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32_update(crc32_update(0, b"12345"), b"6789"),
+            0xcbf4_3926
+        );
+        assert_eq!(crc64_update(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+        assert_eq!(
+            crc64_update(crc64_update(0, b"12345"), b"6789"),
+            0xe9c6_d914_c4b8_d9ca
+        );
+    }
+}
@@ -0,0 +1,1373 @@
+//! Async client of the [server](crate::server), speaking its
+//! [wire protocol](crate::protocol), with the `client` feature.
+//!
+//! A [`Client`] holds a pool of connections, opened as they are first used
+//! and opened again once the server closes them. Requests are pipelined:
+//! those made at once on a connection are all sent before their responses
+//! are awaited, responses being matched to requests by their order.
+//! Requests may be batched in a single frame as well, see [`Client::batch`],
+//! and carried out as a whole, see [`Client::atomic`].
+//!
+//! Values are written with [`Encode`] and read with [`Decode`], serde types
+//! going through [`Bincode`](crate::codec::Bincode) with the `serde`
+//! feature, whereas keys are byte strings, so that scans by prefix work.
+//! With the `tls` feature, connections may be encrypted, see
+//! `ClientBuilder::tls`. Servers listening on a Unix domain socket are
+//! connected to at `unix://` followed by its path, see
+//! [`ClientBuilder::connect`]. Clients of servers with an
+//! [`Acl`](crate::server::acl::Acl) authenticate every connection, see
+//! [`ClientBuilder::credentials`].
+//!
+//! Reads may go to the servers of followers of the primary, see
+//! [`crate::replication`], as the [`ReadPreference`] of the client says, so
+//! that reading stale values is a choice, bounded in time if need be.
+//!
+//! Values read may be cached by the client, see [`ClientBuilder::cache`],
+//! the server telling it which ones were written since, see
+//! [`crate::server::tracking`].
+//!
+//! A [`ShardedClient`] spreads keys over several servers by consistent
+//! hashing, each holding a shard of the data.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::client::ClientBuilder;
+//! use palladiumdb::db::Database;
+//! use palladiumdb::server::ServerBuilder;
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! let server = ServerBuilder::new()
+//!     .bind_address("127.0.0.1:0")
+//!     .workers(4)
+//!     .bind(Arc::new(Database::new()))
+//!     .unwrap();
+//! let address = server.local_addr().unwrap().to_string();
+//! let shutdown = server.shutdown_handle();
+//! let running = thread::spawn(move || server.run());
+//!
+//! let runtime = tokio::runtime::Runtime::new().unwrap();
+//! runtime.block_on(async {
+//!     let client = ClientBuilder::new()
+//!         .pool_size(4)
+//!         .connect(&address)
+//!         .await
+//!         .unwrap();
+//!     client.put("users", b"alice", &42u64).await.unwrap();
+//!     assert_eq!(client.get::<u64>("users", b"alice").await.unwrap(), Some(42));
+//! });
+//! drop(runtime);
+//!
+//! shutdown.shutdown();
+//! running.join().unwrap().unwrap();
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+
+use crate::codec::{decode_all, invalid_data, Decode, Encode};
+use crate::collections::cache::LruCache;
+use crate::protocol::{self, Request, Response, MAX_FRAME_LEN};
+#[cfg(unix)]
+use crate::server::UNIX_SCHEME;
+#[cfg(feature = "tls")]
+use crate::tls::ClientTls;
+
+mod sharded;
+
+pub use sharded::{ShardedClient, ShardedClientBuilder};
+
+/// Errors returned by a [`Client`].
+#[derive(Debug)]
+pub enum Error {
+    /// The server failed to carry out the request, for the reason given.
+    Server(String),
+    /// Connecting to the server, or talking to it, failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Server(message) => write!(f, "server error: {}", message),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Result of the requests of a [`Client`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How long a replica a read failed to reach is passed over.
+const RETRY_REPLICA: Duration = Duration::from_secs(1);
+
+/// How often a replica is asked how stale it is, at most.
+const STALENESS_POLL: Duration = Duration::from_millis(100);
+
+/// Where the reads of a [`Client`] go, gets and scans, its other requests
+/// going to the primary, see [`ClientBuilder::read_preference`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadPreference {
+    /// Reads go to the primary, seeing every write made.
+    #[default]
+    Primary,
+    /// Reads go to the replica answering the fastest, which may not have
+    /// replicated the latest writes yet, or to the primary if none can be
+    /// reached.
+    NearestReplica,
+    /// Reads go to the nearest replica caught up with the primary at most
+    /// that long ago, or to the primary if none was. Replicas caught up with
+    /// an idle primary once per
+    /// [`HEARTBEAT`](crate::replication::HEARTBEAT), bounds shorter than
+    /// that mostly read from the primary.
+    MaxStaleness(Duration),
+}
+
+/// Configures and connects a [`Client`].
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    pool_size: usize,
+    connect_timeout: Duration,
+    credentials: Option<(String, String)>,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTls>,
+    replicas: Vec<String>,
+    read_preference: ReadPreference,
+    cache: Option<usize>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder {
+    /// Creates a builder of clients with up to 4 connections, giving up
+    /// connecting after 5 seconds, reading from the primary.
+    pub fn new() -> Self {
+        ClientBuilder {
+            pool_size: 4,
+            connect_timeout: Duration::from_secs(5),
+            credentials: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            replicas: Vec::new(),
+            read_preference: ReadPreference::Primary,
+            cache: None,
+        }
+    }
+
+    /// Sets the number of connections requests are spread over. Each one
+    /// takes a worker of the server, see
+    /// [`ServerBuilder::workers`](crate::server::ServerBuilder::workers).
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size.max(1);
+        self
+    }
+
+    /// Sets how long connecting, TLS handshake included, may take before
+    /// failing.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Authenticates every connection as `user`, with `password`.
+    pub fn credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Encrypts connections with `tls`, with the `tls` feature, for servers
+    /// built with [`ServerBuilder::tls`](crate::server::ServerBuilder::tls).
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: ClientTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Adds the server at `address`, serving a follower of the primary, to
+    /// those reads may go to, see [`ClientBuilder::read_preference`]. Its
+    /// connections are pooled as those to the primary, and opened as they
+    /// are first used.
+    pub fn replica(mut self, address: &str) -> Self {
+        self.replicas.push(address.to_string());
+        self
+    }
+
+    /// Sets where reads go, [`ReadPreference::Primary`] by default. With
+    /// [`ReadPreference::MaxStaleness`], replicas are asked how stale they
+    /// are through [`Client::info`], which the user must be allowed.
+    pub fn read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.read_preference = read_preference;
+        self
+    }
+
+    /// Caches up to `max_entries` values read by [`Client::get`], the server
+    /// tracking the keys read to tell the client when they are written, see
+    /// [`Request::Tracking`]. Cached gets then read from the primary, what
+    /// the read preference says notwithstanding, and the cache is emptied
+    /// whenever a connection closes, invalidations being lost with it.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_entries` is 0.
+    pub fn cache(mut self, max_entries: usize) -> Self {
+        assert!(max_entries > 0, "the cache must hold an entry at least");
+        self.cache = Some(max_entries);
+        self
+    }
+
+    /// Connects a client to the server at `address`, from within a tokio
+    /// runtime, the first connection being opened right away. Servers on
+    /// the same host may be connected to through the Unix domain socket
+    /// they listen on, its path following [`UNIX_SCHEME`], on Unix, TLS
+    /// then checking the certificate of the server against the name set by
+    /// `ClientTls::server_name`.
+    ///
+    /// # Returns
+    ///
+    /// The error of opening the first connection, of kind
+    /// `PermissionDenied` if the server refused the credentials.
+    pub async fn connect(self, address: &str) -> io::Result<Client> {
+        let replicas = self.replicas.iter().map(|replica| Replica {
+            pool: self.pool(replica, None),
+            health: Mutex::default(),
+        });
+        let cache = self
+            .cache
+            .map(|max_entries| Arc::new(Cache::new(max_entries)));
+        let client = Client {
+            pool: Arc::new(self.pool(address, cache)),
+            replicas: Arc::new(replicas.collect()),
+            read_preference: self.read_preference,
+        };
+        client.pool.connection(0).await?;
+        Ok(client)
+    }
+
+    /// Returns a pool of connections to the server at `address`, tracking
+    /// the keys read for `cache` if set.
+    fn pool(&self, address: &str, cache: Option<Arc<Cache>>) -> Pool {
+        Pool {
+            address: address.to_string(),
+            connect_timeout: self.connect_timeout,
+            credentials: self.credentials.clone(),
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone(),
+            slots: (0..self.pool_size).map(|_| AsyncMutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+            cache,
+        }
+    }
+}
+
+/// Client of a server, see [`crate::client`]. Clones share their pool of
+/// connections.
+#[derive(Clone)]
+pub struct Client {
+    pool: Arc<Pool>,
+    replicas: Arc<Vec<Replica>>,
+    read_preference: ReadPreference,
+}
+
+impl Client {
+    /// Reads the value of `key` in `keyspace`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the value is not a `V`.
+    pub async fn get<V: Decode>(&self, keyspace: &str, key: &[u8]) -> Result<Option<V>> {
+        let get = Request::Get {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        let response = match &self.pool.cache {
+            Some(cache) => match cache.get(&(keyspace.to_string(), key.to_vec())) {
+                Some(value) => Response::Value(value),
+                // cached once answered, see `read_responses`
+                None => self.execute(get).await?,
+            },
+            None => self.read(get).await?,
+        };
+        match response {
+            Response::Value(Some(value)) => Ok(Some(decode_all(&value)?)),
+            Response::Value(None) => Ok(None),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Maps `key` to `value` in `keyspace`.
+    pub async fn put<V: Encode + ?Sized>(
+        &self,
+        keyspace: &str,
+        key: &[u8],
+        value: &V,
+    ) -> Result<()> {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes);
+        let put = Request::Put {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+            value: bytes,
+        };
+        match self.execute(put).await? {
+            Response::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Removes the entry of `key` in `keyspace`.
+    ///
+    /// # Returns
+    ///
+    /// Whether `key` was mapped.
+    pub async fn delete(&self, keyspace: &str, key: &[u8]) -> Result<bool> {
+        let delete = Request::Delete {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        match self.execute(delete).await? {
+            Response::Deleted(deleted) => Ok(deleted),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Lists the entries of `keyspace` whose key starts with `prefix`,
+    /// sorted by key, at most `limit` of them.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a value is not a `V`.
+    pub async fn scan<V: Decode>(
+        &self,
+        keyspace: &str,
+        prefix: &[u8],
+        limit: u64,
+    ) -> Result<Vec<(Vec<u8>, V)>> {
+        let scan = Request::Scan {
+            keyspace: keyspace.to_string(),
+            prefix: prefix.to_vec(),
+            limit,
+        };
+        match self.read(scan).await? {
+            Response::Entries(entries) => Ok(entries
+                .into_iter()
+                .map(|(key, value)| Ok((key, decode_all(&value)?)))
+                .collect::<io::Result<_>>()?),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends `request` as is, to the primary.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Server`] if the server answers with [`Response::Error`].
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        self.pool.execute(request).await
+    }
+
+    /// Sends `requests` to the primary on a connection in a single write,
+    /// then awaits their responses. They are carried out in order, but each
+    /// on its own: the failure of one does not undo those before it.
+    ///
+    /// # Returns
+    ///
+    /// The responses, in the order of the requests, failures included as
+    /// [`Response::Error`].
+    pub async fn pipeline(&self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        self.pool.pipeline(requests).await
+    }
+
+    /// Sends `requests` in a single frame, to be carried out in order, each
+    /// on its own, see [`Request::Batch`].
+    ///
+    /// # Returns
+    ///
+    /// The responses, in the order of the requests, failures included as
+    /// [`Response::Error`].
+    pub async fn batch(&self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        match self.execute(Request::Batch { requests }).await? {
+            Response::Batch(responses) => Ok(responses),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends `requests` in a single frame, to be carried out in order and
+    /// as a whole, see [`Request::Atomic`].
+    ///
+    /// # Returns
+    ///
+    /// The responses, in the order of the requests, [`Error::Server`] if
+    /// one of them failed, in which case none of their writes is made.
+    pub async fn atomic(&self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        match self.execute(Request::Atomic { requests }).await? {
+            Response::Batch(responses) => Ok(responses),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs the Lua `script` with `args` as a whole, see [`Request::Eval`].
+    ///
+    /// # Returns
+    ///
+    /// The response the script answers with, [`Error::Server`] if it
+    /// failed, in which case none of its writes is made.
+    pub async fn eval(&self, script: &str, args: Vec<Vec<u8>>) -> Result<Response> {
+        let script = script.to_string();
+        self.execute(Request::Eval { script, args }).await
+    }
+
+    /// Returns the statistics of the server, its `section` only if set, see
+    /// [`Request::Info`].
+    ///
+    /// # Returns
+    ///
+    /// The `key:value` lines of the report, [`Error::Server`] if the user
+    /// may not run `info`.
+    pub async fn info(&self, section: Option<&str>) -> Result<String> {
+        let section = section.map(str::to_string);
+        match self.execute(Request::Info { section }).await? {
+            Response::Value(Some(report)) => String::from_utf8(report)
+                .map_err(|_| Error::Io(invalid_data("the report is not UTF-8"))),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends the read `request` where the read preference says, to the
+    /// primary if the replica chosen cannot be reached.
+    async fn read(&self, request: Request) -> Result<Response> {
+        if let Some(replica) = self.replica().await {
+            let started = Instant::now();
+            match replica.pool.execute(request.clone()).await {
+                Err(Error::Io(_)) => replica.health.lock().unwrap().failed = Some(Instant::now()),
+                result => {
+                    replica.answered(started.elapsed());
+                    return result;
+                }
+            }
+        }
+        self.execute(request).await
+    }
+
+    /// Returns the replica reads go to, `None` for the primary.
+    async fn replica(&self) -> Option<&Replica> {
+        let max_staleness = match self.read_preference {
+            ReadPreference::Primary => return None,
+            ReadPreference::NearestReplica => None,
+            ReadPreference::MaxStaleness(max_staleness) => Some(max_staleness),
+        };
+        let mut nearest: Option<(&Replica, Duration)> = None;
+        for replica in self.replicas.iter() {
+            let latency = match replica.latency() {
+                Some(latency) => latency,
+                None => continue,
+            };
+            if let Some(max_staleness) = max_staleness {
+                if !replica.caught_up_within(max_staleness).await {
+                    continue;
+                }
+            }
+            if nearest.is_none_or(|(_, nearest)| latency < nearest) {
+                nearest = Some((replica, latency));
+            }
+        }
+        nearest.map(|(replica, _)| replica)
+    }
+}
+
+/// The server of a follower reads may go to, see [`ReadPreference`].
+struct Replica {
+    pool: Pool,
+    health: Mutex<Health>,
+}
+
+/// How a [`Replica`] is doing, as its client saw it.
+#[derive(Default)]
+struct Health {
+    /// The average round trip of the reads, `None` until one is made.
+    latency: Option<Duration>,
+    /// When a read last failed to reach it.
+    failed: Option<Instant>,
+    /// How long before it was asked the replica was last caught up with
+    /// its primary, `None` if it did not say, and when it was asked.
+    staleness: Option<(Option<Duration>, Instant)>,
+}
+
+impl Replica {
+    /// Returns the average round trip of the reads, zero for a replica not
+    /// read from yet so that it is tried, `None` if one recently failed to
+    /// reach it.
+    fn latency(&self) -> Option<Duration> {
+        let health = self.health.lock().unwrap();
+        match health.failed {
+            Some(failed) if failed.elapsed() < RETRY_REPLICA => None,
+            _ => Some(health.latency.unwrap_or_default()),
+        }
+    }
+
+    /// Accounts for a read answered in `latency`.
+    fn answered(&self, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.failed = None;
+        health.latency = Some(match health.latency {
+            Some(average) => (average * 7 + latency) / 8,
+            None => latency,
+        });
+    }
+
+    /// Returns whether the replica was caught up with its primary at most
+    /// `max_staleness` ago, asking it again unless it recently told.
+    async fn caught_up_within(&self, max_staleness: Duration) -> bool {
+        // how long ago it was caught up at most, as of when it was asked
+        let bound = |staleness: Option<(Option<Duration>, Instant)>| match staleness {
+            Some((Some(staleness), asked)) => Some(staleness + asked.elapsed()),
+            _ => None,
+        };
+        let staleness = self.health.lock().unwrap().staleness;
+        if bound(staleness).is_some_and(|bound| bound <= max_staleness) {
+            return true;
+        }
+        if staleness.is_some_and(|(_, asked)| asked.elapsed() < STALENESS_POLL) {
+            return false;
+        }
+        let asked = Instant::now();
+        let info = Request::Info {
+            section: Some("replication".to_string()),
+        };
+        let reported = match self.pool.execute(info).await {
+            Ok(Response::Value(Some(report))) => staleness_of(&report),
+            Err(Error::Io(_)) => {
+                self.health.lock().unwrap().failed = Some(Instant::now());
+                return false;
+            }
+            _ => None,
+        };
+        let staleness = Some((reported, asked));
+        self.health.lock().unwrap().staleness = staleness;
+        bound(staleness).is_some_and(|bound| bound <= max_staleness)
+    }
+}
+
+/// Returns the staleness a follower reports in the replication section of
+/// its `report`, see [`Client::info`].
+fn staleness_of(report: &[u8]) -> Option<Duration> {
+    let report = std::str::from_utf8(report).ok()?;
+    let millis = report
+        .lines()
+        .find_map(|line| line.strip_prefix("follower_staleness_ms:"))?;
+    millis.trim().parse().ok().map(Duration::from_millis)
+}
+
+fn unexpected(response: Response) -> Error {
+    Error::Io(invalid_data(&format!("unexpected response {:?}", response)))
+}
+
+fn closed() -> io::Error {
+    io::Error::new(
+        ErrorKind::ConnectionAborted,
+        "the server closed the connection",
+    )
+}
+
+/// The connections of a [`Client`], each slot holding one once it is
+/// opened.
+struct Pool {
+    address: String,
+    connect_timeout: Duration,
+    credentials: Option<(String, String)>,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTls>,
+    slots: Vec<AsyncMutex<Option<Connection>>>,
+    next: AtomicUsize,
+    /// The values read, for pools of clients caching them.
+    cache: Option<Arc<Cache>>,
+}
+
+impl Pool {
+    /// Sends `request` as is.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Server`] if the server answers with [`Response::Error`].
+    async fn execute(&self, request: Request) -> Result<Response> {
+        let mut responses = self.pipeline(vec![request]).await?;
+        match responses.pop() {
+            Some(Response::Error(message)) => Err(Error::Server(message)),
+            Some(response) => Ok(response),
+            None => Err(invalid_data("missing response").into()),
+        }
+    }
+
+    /// Sends `requests` on a connection in a single write, then awaits
+    /// their responses.
+    async fn pipeline(&self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        let mut frames = Vec::new();
+        let mut payload = Vec::new();
+        for request in &requests {
+            payload.clear();
+            request.encode(&mut payload);
+            protocol::write_frame(&mut frames, &payload)?;
+        }
+        let cached: Vec<_> = requests
+            .iter()
+            .map(|request| match (request, &self.cache) {
+                (Request::Get { keyspace, key }, Some(_)) => Some((keyspace.clone(), key.clone())),
+                _ => None,
+            })
+            .collect();
+        // a connection found closed before anything was sent on it is
+        // opened again once
+        let mut receivers = None;
+        for _ in 0..2 {
+            let connection = self.next_connection().await?;
+            receivers = connection.send(&frames, &cached).await?;
+            if receivers.is_some() {
+                break;
+            }
+        }
+        let receivers = match receivers {
+            Some(receivers) => receivers,
+            None => return Err(closed().into()),
+        };
+        let mut responses = Vec::with_capacity(receivers.len());
+        for receiver in receivers {
+            responses.push(receiver.await.unwrap_or_else(|_| Err(closed()))?);
+        }
+        // the client reads its own writes, whichever connection the server
+        // sends their invalidations on
+        if let Some(cache) = &self.cache {
+            for request in &requests {
+                if let Request::Put { keyspace, key, .. } | Request::Delete { keyspace, key } =
+                    request
+                {
+                    cache.remove(&(keyspace.clone(), key.clone()));
+                }
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Returns the connections in turn.
+    async fn next_connection(&self) -> io::Result<Connection> {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        self.connection(slot).await
+    }
+
+    /// Returns the connection of `slot`, opening it if it is not open.
+    async fn connection(&self, slot: usize) -> io::Result<Connection> {
+        let mut slot = self.slots[slot].lock().await;
+        if let Some(connection) = &*slot {
+            if !connection.state.lock().unwrap().closed {
+                return Ok(connection.clone());
+            }
+        }
+        let (reader, writer) = match tokio::time::timeout(self.connect_timeout, self.open()).await {
+            Ok(halves) => halves?,
+            Err(_) => return Err(io::Error::new(ErrorKind::TimedOut, "connecting timed out")),
+        };
+        let state = Arc::new(Mutex::new(State {
+            cache: self.cache.clone(),
+            ..State::default()
+        }));
+        let reading = tokio::spawn(read_responses(state.clone(), reader));
+        let connection = Connection {
+            writer: Arc::new(AsyncMutex::new(writer)),
+            state,
+            _reading: Arc::new(Reading(reading)),
+        };
+        *slot = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Opens a connection to the server, authenticated if the pool has
+    /// credentials, tracking the keys read if it caches them.
+    ///
+    /// # Returns
+    ///
+    /// The halves the connection is read and written through.
+    async fn open(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        let (mut reader, mut writer) = self.connect().await?;
+        if let Some((user, password)) = &self.credentials {
+            let auth = Request::Auth {
+                user: user.clone(),
+                password: password.clone(),
+            };
+            match exchange(&mut reader, &mut writer, &auth).await? {
+                Response::Ok => {}
+                Response::Error(message) => {
+                    return Err(io::Error::new(ErrorKind::PermissionDenied, message))
+                }
+                response => return Err(invalid_data(&format!("unexpected {:?}", response))),
+            }
+        }
+        if self.cache.is_some() {
+            let tracking = Request::Tracking { enabled: true };
+            match exchange(&mut reader, &mut writer, &tracking).await? {
+                Response::Ok => {}
+                Response::Error(message) => return Err(io::Error::other(message)),
+                response => return Err(invalid_data(&format!("unexpected {:?}", response))),
+            }
+        }
+        Ok((reader, writer))
+    }
+
+    /// Connects to the server, through a Unix socket for addresses starting
+    /// with `unix://`.
+    async fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        #[cfg(unix)]
+        if let Some(path) = self.address.strip_prefix(UNIX_SCHEME) {
+            let stream = UnixStream::connect(path).await?;
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &self.tls {
+                return self.encrypt(tls, stream).await;
+            }
+            let (reader, writer) = stream.into_split();
+            return Ok((Box::new(reader), Box::new(writer)));
+        }
+        let stream = TcpStream::connect(&*self.address).await?;
+        stream.set_nodelay(true)?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return self.encrypt(tls, stream).await;
+        }
+        let (reader, writer) = stream.into_split();
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+
+    /// Encrypts `stream` with `tls`.
+    #[cfg(feature = "tls")]
+    async fn encrypt<S>(&self, tls: &ClientTls, stream: S) -> io::Result<(ReadHalf, WriteHalf)>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let server_name = tls.server_name_of(&self.address)?;
+        let connector = tokio_rustls::TlsConnector::from(tls.config().clone());
+        let stream = connector.connect(server_name, stream).await?;
+        let (reader, writer) = tokio::io::split(stream);
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+type Responder = oneshot::Sender<io::Result<Response>>;
+
+/// A key of a keyspace, as cached.
+type CacheKey = (String, Vec<u8>);
+
+/// The values read by a [`Client`] caching them, see
+/// [`ClientBuilder::cache`], `None` for keys not mapped.
+struct Cache {
+    max_entries: usize,
+    /// Replaced by an empty cache to clear it.
+    entries: Mutex<Arc<LruCache<CacheKey, Option<Vec<u8>>>>>,
+}
+
+impl Cache {
+    fn new(max_entries: usize) -> Self {
+        Cache {
+            max_entries,
+            entries: Mutex::new(Arc::new(LruCache::new(max_entries))),
+        }
+    }
+
+    fn entries(&self) -> Arc<LruCache<CacheKey, Option<Vec<u8>>>> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Option<Vec<u8>>> {
+        self.entries().get(key)
+    }
+
+    fn put(&self, key: &CacheKey, value: Option<Vec<u8>>) {
+        self.entries().put(key, value)
+    }
+
+    fn remove(&self, key: &CacheKey) {
+        self.entries().remove(key);
+    }
+
+    fn clear(&self) {
+        *self.entries.lock().unwrap() = Arc::new(LruCache::new(self.max_entries));
+    }
+}
+
+/// A request sent and not answered yet.
+struct Pending {
+    responder: Responder,
+    /// The key read, for gets whose value is cached once answered.
+    cached: Option<CacheKey>,
+}
+
+/// What a connection and the task reading its responses share.
+#[derive(Default)]
+struct State {
+    /// Whether the connection failed or was closed by the server.
+    closed: bool,
+    /// The requests sent and not answered yet, in order.
+    pending: VecDeque<Pending>,
+    /// The values read, for connections tracking the keys read.
+    cache: Option<Arc<Cache>>,
+}
+
+impl State {
+    /// Marks the connection closed, failing the requests not answered and
+    /// emptying the cache, whose invalidations are no longer received.
+    fn close(&mut self, err: &io::Error) {
+        self.closed = true;
+        for pending in self.pending.drain(..) {
+            let err = io::Error::new(err.kind(), err.to_string());
+            let _ = pending.responder.send(Err(err));
+        }
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+}
+
+/// A connection of the pool, closed once every clone is dropped, the task
+/// reading its responses being aborted.
+#[derive(Clone)]
+struct Connection {
+    writer: Arc<AsyncMutex<WriteHalf>>,
+    state: Arc<Mutex<State>>,
+    /// Held for the task to be aborted once the last clone is dropped.
+    _reading: Arc<Reading>,
+}
+
+/// The task reading the responses of a connection, aborted when dropped.
+struct Reading(JoinHandle<()>);
+
+impl Drop for Reading {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl Connection {
+    /// Writes `frames`, holding a request per key of `cached`, those set
+    /// naming the key read by gets to cache.
+    ///
+    /// # Returns
+    ///
+    /// The receivers of the responses, or `None` if the connection was
+    /// already closed.
+    async fn send(
+        &self,
+        frames: &[u8],
+        cached: &[Option<CacheKey>],
+    ) -> io::Result<Option<Vec<oneshot::Receiver<io::Result<Response>>>>> {
+        // responders are queued in the order of the writes
+        let mut writer = self.writer.lock().await;
+        let mut receivers = Vec::with_capacity(cached.len());
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Ok(None);
+            }
+            for cached in cached {
+                let (responder, receiver) = oneshot::channel();
+                state.pending.push_back(Pending {
+                    responder,
+                    cached: cached.clone(),
+                });
+                receivers.push(receiver);
+            }
+        }
+        let written = match writer.write_all(frames).await {
+            Ok(()) => writer.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            self.state.lock().unwrap().close(&err);
+            return Err(err);
+        }
+        Ok(Some(receivers))
+    }
+}
+
+/// Hands the responses read from `reader` to the requests, in order, until
+/// the connection closes, caching the values read and removing those
+/// invalidated from the cache of the connection.
+async fn read_responses(state: Arc<Mutex<State>>, reader: ReadHalf) {
+    let mut reader = BufReader::new(reader);
+    let cache = state.lock().unwrap().cache.clone();
+    let err = loop {
+        let payload = match read_frame(&mut reader).await {
+            Ok(Some(payload)) => payload,
+            Ok(None) => break closed(),
+            Err(err) => break err,
+        };
+        let response = decode_all(&payload);
+        if let (Ok(Response::Invalidate { keyspace, key }), Some(cache)) = (&response, &cache) {
+            cache.remove(&(keyspace.clone(), key.clone()));
+            continue;
+        }
+        let pending = state.lock().unwrap().pending.pop_front();
+        match pending {
+            Some(pending) => {
+                // cached before the invalidations read after it are applied
+                if let (Ok(Response::Value(value)), Some(key), Some(cache)) =
+                    (&response, &pending.cached, &cache)
+                {
+                    cache.put(key, value.clone());
+                }
+                let _ = pending.responder.send(response);
+            }
+            None => break invalid_data("response to no request"),
+        }
+    };
+    state.lock().unwrap().close(&err);
+}
+
+/// Sends `request` on a connection not yet read by [`read_responses`].
+///
+/// # Returns
+///
+/// The response of the server.
+async fn exchange(
+    reader: &mut ReadHalf,
+    writer: &mut WriteHalf,
+    request: &Request,
+) -> io::Result<Response> {
+    let (mut payload, mut frame) = (Vec::new(), Vec::new());
+    request.encode(&mut payload);
+    protocol::write_frame(&mut frame, &payload)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    let payload = read_frame(reader).await?.ok_or_else(closed)?;
+    decode_all(&payload)
+}
+
+/// Reads a frame as [`protocol::read_frame`] does.
+async fn read_frame<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match input.read(&mut len[..1]).await? {
+        0 => return Ok(None),
+        _ => input.read_exact(&mut len[1..]).await?,
+    };
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("frame too long"));
+    }
+    let mut payload = vec![0; len];
+    input.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientBuilder, Error, ReadPreference};
+    use crate::db::Database;
+    use crate::protocol::{Request, Response};
+    use crate::replication::{Primary, Replica};
+    use crate::server::ServerBuilder;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_pooled_client_pipelines_and_reconnects() {
+        let db = Arc::new(Database::new());
+        db.open_map::<u64, u64>("typed").unwrap();
+        let serve = |db: Arc<Database>, address: &str| {
+            let server = ServerBuilder::new()
+                .bind_address(address)
+                .workers(4)
+                .bind(db)
+                .unwrap();
+            let address = server.local_addr().unwrap().to_string();
+            let shutdown = server.shutdown_handle();
+            (address, shutdown, thread::spawn(move || server.run()))
+        };
+        let (address, shutdown, running) = serve(db.clone(), "127.0.0.1:0");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = runtime.block_on(async {
+            let client = ClientBuilder::new()
+                .pool_size(2)
+                .connect(&address)
+                .await
+                .unwrap();
+            client.put("users", b"alice", &1u64).await.unwrap();
+            client.put("users", b"bob", &2u64).await.unwrap();
+            client.put("users", b"carol", "three").await.unwrap();
+            assert_eq!(client.get::<u64>("users", b"alice").await.unwrap(), Some(1));
+            assert_eq!(client.get::<u64>("users", b"dave").await.unwrap(), None);
+            assert!(matches!(
+                client.get::<u64>("users", b"carol").await,
+                Err(Error::Io(_))
+            ));
+            assert!(matches!(
+                client.get::<u64>("typed", b"alice").await,
+                Err(Error::Server(_))
+            ));
+
+            // requests made at once share the connections
+            let tasks: Vec<_> = (0..32u64)
+                .map(|i| {
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        let key = format!("counter{:02}", i);
+                        client.put("counters", key.as_bytes(), &i).await.unwrap();
+                        client.get::<u64>("counters", key.as_bytes()).await.unwrap()
+                    })
+                })
+                .collect();
+            for (i, task) in tasks.into_iter().enumerate() {
+                assert_eq!(task.await.unwrap(), Some(i as u64));
+            }
+            let scanned = client
+                .scan::<u64>("counters", b"counter1", 3)
+                .await
+                .unwrap();
+            let expected: Vec<_> = (10..13u64)
+                .map(|i| (format!("counter{}", i).into_bytes(), i))
+                .collect();
+            assert_eq!(scanned, expected);
+
+            let delete = |key: &[u8]| Request::Delete {
+                keyspace: "users".to_string(),
+                key: key.to_vec(),
+            };
+            let get = Request::Get {
+                keyspace: "typed".to_string(),
+                key: b"alice".to_vec(),
+            };
+            let responses = client
+                .pipeline(vec![delete(b"alice"), get, delete(b"alice")])
+                .await
+                .unwrap();
+            assert_eq!(responses[0], Response::Deleted(true));
+            assert!(matches!(responses[1], Response::Error(_)));
+            assert_eq!(responses[2], Response::Deleted(false));
+
+            // batches travel in a single frame, atomic ones making none of
+            // their writes if one of their requests fails
+            let put = |key: &[u8]| Request::Put {
+                keyspace: "batched".to_string(),
+                key: key.to_vec(),
+                value: b"1".to_vec(),
+            };
+            let read = |keyspace: &str, key: &[u8]| Request::Get {
+                keyspace: keyspace.to_string(),
+                key: key.to_vec(),
+            };
+            let subscribe = Request::Subscribe {
+                pattern: "*".to_string(),
+            };
+            let responses = client
+                .batch(vec![put(b"a"), read("typed", b"a"), subscribe])
+                .await
+                .unwrap();
+            assert_eq!(responses[0], Response::Ok);
+            assert!(matches!(responses[1], Response::Error(_)));
+            assert!(matches!(responses[2], Response::Error(_)));
+            let remove = Request::Delete {
+                keyspace: "batched".to_string(),
+                key: b"a".to_vec(),
+            };
+            let failed = client
+                .atomic(vec![put(b"b"), remove, read("typed", b"a")])
+                .await;
+            assert!(matches!(failed, Err(Error::Server(_))));
+            let responses = client
+                .atomic(vec![read("batched", b"a"), read("batched", b"b")])
+                .await
+                .unwrap();
+            let one = Response::Value(Some(b"1".to_vec()));
+            assert_eq!(responses, vec![one, Response::Value(None)]);
+            client
+        });
+
+        // the connections closed by the server are opened again
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        let (_, shutdown, running) = serve(db, &address);
+        thread::sleep(Duration::from_millis(100));
+        runtime.block_on(async {
+            for _ in 0..4 {
+                assert_eq!(client.get::<u64>("users", b"bob").await.unwrap(), Some(2));
+            }
+        });
+        drop(client);
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_client_over_mutual_tls() {
+        use crate::tls::{ClientTls, ServerTls, TestCertificates};
+
+        let certificates = TestCertificates::generate();
+        let tls = ServerTls::mutual(
+            certificates.server.as_bytes(),
+            certificates.server_key.as_bytes(),
+            certificates.ca.as_bytes(),
+        );
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(2)
+            .tls(tls.unwrap())
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let tls = ClientTls::mutual(
+                certificates.ca.as_bytes(),
+                certificates.client.as_bytes(),
+                certificates.client_key.as_bytes(),
+            );
+            let client = ClientBuilder::new()
+                .pool_size(1)
+                .tls(tls.unwrap().server_name("localhost").unwrap())
+                .connect(&address)
+                .await
+                .unwrap();
+            client.put("users", b"alice", "secret").await.unwrap();
+            let value = client.get::<String>("users", b"alice").await.unwrap();
+            assert_eq!(value.as_deref(), Some("secret"));
+
+            // the certificate of the server must name the host
+            let tls = ClientTls::new(certificates.ca.as_bytes()).unwrap();
+            let connecting = ClientBuilder::new().tls(tls).connect(&address).await;
+            assert!(connecting.is_err());
+        });
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_cached_reads_are_invalidated_by_writes() {
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(4)
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let cached = ClientBuilder::new()
+                .pool_size(2)
+                .cache(100)
+                .connect(&address)
+                .await
+                .unwrap();
+            let writer = ClientBuilder::new()
+                .pool_size(1)
+                .connect(&address)
+                .await
+                .unwrap();
+            let cache = cached.pool.cache.clone().unwrap();
+            let key = |key: &[u8]| ("users".to_string(), key.to_vec());
+            let invalidated = |key: (String, Vec<u8>)| {
+                let cache = cache.clone();
+                async move {
+                    let started = Instant::now();
+                    while cache.get(&key).is_some() {
+                        assert!(started.elapsed() < Duration::from_secs(10), "timed out");
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                }
+            };
+
+            // values and misses are cached until written by any client
+            writer.put("users", b"alice", &1u64).await.unwrap();
+            assert_eq!(cached.get::<u64>("users", b"alice").await.unwrap(), Some(1));
+            assert_eq!(cached.get::<u64>("users", b"bob").await.unwrap(), None);
+            assert!(cache.get(&key(b"alice")).is_some());
+            assert_eq!(cache.get(&key(b"bob")), Some(None));
+            writer.put("users", b"alice", &2u64).await.unwrap();
+            writer.put("users", b"bob", &3u64).await.unwrap();
+            invalidated(key(b"alice")).await;
+            invalidated(key(b"bob")).await;
+            assert_eq!(cached.get::<u64>("users", b"alice").await.unwrap(), Some(2));
+            assert_eq!(cached.get::<u64>("users", b"bob").await.unwrap(), Some(3));
+            assert!(writer.delete("users", b"bob").await.unwrap());
+            invalidated(key(b"bob")).await;
+
+            // the client reads its own writes right away
+            cached.put("users", b"alice", &4u64).await.unwrap();
+            assert_eq!(cached.get::<u64>("users", b"alice").await.unwrap(), Some(4));
+
+            // tracking is for servers
+            let tracking = Request::Tracking { enabled: true };
+            let db = Database::new();
+            let response = crate::server::execute(&db, tracking).unwrap();
+            assert!(matches!(response, Response::Error(_)));
+        });
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_client_over_a_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("palladiumdb-unix-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.sock");
+        let address = format!("unix://{}", path.display());
+        // the file left by a server gone is taken over
+        drop(UnixListener::bind(&path).unwrap());
+        let server = ServerBuilder::new()
+            .bind_address(&address)
+            .unix_socket_mode(0o600)
+            .workers(2)
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        assert!(server.local_addr().is_err());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let taken = ServerBuilder::new()
+            .bind_address(&address)
+            .bind(Arc::new(Database::new()));
+        assert_eq!(taken.err().unwrap().kind(), std::io::ErrorKind::AddrInUse);
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let client = ClientBuilder::new()
+                .pool_size(1)
+                .connect(&address)
+                .await
+                .unwrap();
+            client.put("users", b"alice", &42u64).await.unwrap();
+            assert_eq!(
+                client.get::<u64>("users", b"alice").await.unwrap(),
+                Some(42)
+            );
+        });
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        // the file is removed once the server stops
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reads_follow_the_read_preference() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-client-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let serve = |db: Arc<Database>| {
+            let server = ServerBuilder::new()
+                .bind_address("127.0.0.1:0")
+                .workers(4)
+                .bind(db)
+                .unwrap();
+            let address = server.local_addr().unwrap().to_string();
+            let shutdown = server.shutdown_handle();
+            (address, shutdown, thread::spawn(move || server.run()))
+        };
+        let db = Arc::new(Database::open(dir.join("primary")).unwrap());
+        let primary = Primary::start(db.clone(), "127.0.0.1:0").unwrap();
+        let follower = Arc::new(Database::open(dir.join("follower")).unwrap());
+        let replica = Replica::start(follower.clone(), &primary.local_addr().to_string()).unwrap();
+        let (address, shutdown, running) = serve(db);
+        let (replica_address, replica_shutdown, replica_running) = serve(follower);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let client = |read_preference| {
+                ClientBuilder::new()
+                    .pool_size(1)
+                    .replica(&replica_address)
+                    .read_preference(read_preference)
+                    .connect(&address)
+            };
+            let nearest = client(ReadPreference::NearestReplica).await.unwrap();
+            let bounded = client(ReadPreference::MaxStaleness(Duration::from_millis(500)))
+                .await
+                .unwrap();
+            let primary = client(ReadPreference::Primary).await.unwrap();
+            nearest.put("users", b"alice", &1u64).await.unwrap();
+            let started = Instant::now();
+            while nearest.get::<u64>("users", b"alice").await.unwrap() != Some(1) {
+                assert!(started.elapsed() < Duration::from_secs(10), "timed out");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            // the follower no longer catching up, its reads grow stale
+            drop(replica);
+            primary.put("users", b"alice", &2u64).await.unwrap();
+            assert_eq!(
+                nearest.get::<u64>("users", b"alice").await.unwrap(),
+                Some(1)
+            );
+            assert_eq!(
+                bounded.get::<u64>("users", b"alice").await.unwrap(),
+                Some(1)
+            );
+            assert_eq!(
+                primary.get::<u64>("users", b"alice").await.unwrap(),
+                Some(2)
+            );
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            assert_eq!(
+                bounded.get::<u64>("users", b"alice").await.unwrap(),
+                Some(2)
+            );
+
+            // replicas out of reach are passed over
+            replica_shutdown.shutdown();
+            replica_running.join().unwrap().unwrap();
+            assert_eq!(
+                nearest.get::<u64>("users", b"alice").await.unwrap(),
+                Some(2)
+            );
+        });
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        drop(primary);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
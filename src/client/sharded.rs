@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{unexpected, Client, ClientBuilder, Error, Result};
+use crate::codec::{decode_all, Decode, Encode};
+use crate::protocol::{Request, Response};
+
+/// Configures and connects a [`ShardedClient`].
+#[derive(Clone, Debug)]
+pub struct ShardedClientBuilder {
+    client: ClientBuilder,
+    virtual_nodes: usize,
+    retries: usize,
+    retry_backoff: Duration,
+}
+
+impl Default for ShardedClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardedClientBuilder {
+    /// Creates a builder of clients placing every server at 160 points of
+    /// the ring, retrying requests twice, 50 milliseconds apart at first,
+    /// with the pools of [`ClientBuilder::new`].
+    pub fn new() -> Self {
+        ShardedClientBuilder {
+            client: ClientBuilder::new(),
+            virtual_nodes: 160,
+            retries: 2,
+            retry_backoff: Duration::from_millis(50),
+        }
+    }
+
+    /// Sets how the pool of connections to every server is configured.
+    pub fn client(mut self, client: ClientBuilder) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets the number of points of the ring each server is placed at, the
+    /// more the evener the keys are spread.
+    pub fn virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes.max(1);
+        self
+    }
+
+    /// Sets how many times requests failing to reach their server are sent
+    /// again, waiting twice as long every time.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long requests wait before they are first sent again.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Connects a client to the servers at `addresses`, from within a tokio
+    /// runtime, the first connection to each one being opened right away.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if there is no address or one is
+    /// repeated, the error of opening a first connection otherwise.
+    pub async fn connect(self, addresses: &[&str]) -> io::Result<ShardedClient> {
+        let distinct: HashSet<_> = addresses.iter().collect();
+        if addresses.is_empty() || distinct.len() < addresses.len() {
+            let message = "shards need distinct addresses, one at least";
+            return Err(io::Error::new(ErrorKind::InvalidInput, message));
+        }
+        let mut shards = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let client = self.client.clone().connect(address).await?;
+            shards.push((address.to_string(), client));
+        }
+        let mut ring: Vec<(u64, usize)> = addresses
+            .iter()
+            .enumerate()
+            .flat_map(|(shard, address)| {
+                (0..self.virtual_nodes)
+                    .map(move |point| (hash(format!("{}#{}", address, point).as_bytes()), shard))
+            })
+            .collect();
+        ring.sort_unstable();
+        Ok(ShardedClient {
+            shards: Arc::new(shards),
+            ring: Arc::new(ring),
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+        })
+    }
+}
+
+/// Returns the position of `bytes` on the ring, the same on every client.
+fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a, stable across platforms and releases unlike std hashers, then
+    // mixed so that close inputs land apart
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Client of servers holding a shard of the data each, see
+/// [`crate::client`]. Clones share their pools of connections.
+///
+/// Keys are placed on a ring of hashes, along with every server at many
+/// points, and belong to the server at the first point following them.
+/// Adding a server to the ring thus only moves the keys it takes over to
+/// it. A key goes to the same server in every keyspace, and scans go to
+/// every server. Requests failing to reach their server are sent again,
+/// which may carry them out twice.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::client::ShardedClientBuilder;
+/// use palladiumdb::db::Database;
+/// use palladiumdb::server::ServerBuilder;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let mut addresses = Vec::new();
+/// let mut running = Vec::new();
+/// for _ in 0..3 {
+///     let server = ServerBuilder::new()
+///         .bind_address("127.0.0.1:0")
+///         .workers(4)
+///         .bind(Arc::new(Database::new()))
+///         .unwrap();
+///     addresses.push(server.local_addr().unwrap().to_string());
+///     running.push((server.shutdown_handle(), thread::spawn(move || server.run())));
+/// }
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let addresses: Vec<_> = addresses.iter().map(String::as_str).collect();
+///     let client = ShardedClientBuilder::new()
+///         .connect(&addresses)
+///         .await
+///         .unwrap();
+///     client.put("users", b"alice", &42u64).await.unwrap();
+///     client.put("users", b"bob", &7u64).await.unwrap();
+///     assert_eq!(client.get::<u64>("users", b"alice").await.unwrap(), Some(42));
+///     assert_eq!(client.scan::<u64>("users", b"", 10).await.unwrap().len(), 2);
+/// });
+/// drop(runtime);
+///
+/// for (shutdown, running) in running {
+///     shutdown.shutdown();
+///     running.join().unwrap().unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ShardedClient {
+    /// The servers, with the address they were given by.
+    shards: Arc<Vec<(String, Client)>>,
+    /// The points of the ring, sorted, with the server placed there.
+    ring: Arc<Vec<(u64, usize)>>,
+    retries: usize,
+    retry_backoff: Duration,
+}
+
+impl ShardedClient {
+    /// Returns the address of the server `key` belongs to.
+    pub fn shard_of(&self, key: &[u8]) -> &str {
+        &self.shards[self.shard(key)].0
+    }
+
+    fn shard(&self, key: &[u8]) -> usize {
+        let hash = hash(key);
+        let point = self.ring.partition_point(|&(point, _)| point < hash);
+        self.ring[point % self.ring.len()].1
+    }
+
+    /// Reads the value of `key` in `keyspace`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the value is not a `V`.
+    pub async fn get<V: Decode>(&self, keyspace: &str, key: &[u8]) -> Result<Option<V>> {
+        let get = Request::Get {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        match self.execute(get).await? {
+            Response::Value(Some(value)) => Ok(Some(decode_all(&value)?)),
+            Response::Value(None) => Ok(None),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Maps `key` to `value` in `keyspace`.
+    pub async fn put<V: Encode + ?Sized>(
+        &self,
+        keyspace: &str,
+        key: &[u8],
+        value: &V,
+    ) -> Result<()> {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes);
+        let put = Request::Put {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+            value: bytes,
+        };
+        match self.execute(put).await? {
+            Response::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Removes the entry of `key` in `keyspace`.
+    ///
+    /// # Returns
+    ///
+    /// Whether `key` was mapped.
+    pub async fn delete(&self, keyspace: &str, key: &[u8]) -> Result<bool> {
+        let delete = Request::Delete {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        match self.execute(delete).await? {
+            Response::Deleted(deleted) => Ok(deleted),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Lists the entries of `keyspace` whose key starts with `prefix`,
+    /// sorted by key, at most `limit` of them, gathered from every server.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a value is not a `V`.
+    pub async fn scan<V: Decode>(
+        &self,
+        keyspace: &str,
+        prefix: &[u8],
+        limit: u64,
+    ) -> Result<Vec<(Vec<u8>, V)>> {
+        let scan = Request::Scan {
+            keyspace: keyspace.to_string(),
+            prefix: prefix.to_vec(),
+            limit,
+        };
+        match self.execute(scan).await? {
+            Response::Entries(entries) => Ok(entries
+                .into_iter()
+                .map(|(key, value)| Ok((key, decode_all(&value)?)))
+                .collect::<io::Result<_>>()?),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends `request` to the server its key belongs to, scans to every
+    /// server, their entries being merged.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Server`] if a server answers with [`Response::Error`], an
+    /// error of kind `InvalidInput` for requests of no key but scans.
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        let shard = match &request {
+            Request::Get { key, .. } | Request::Put { key, .. } | Request::Delete { key, .. } => {
+                self.shard(key)
+            }
+            Request::Scan { limit, .. } => {
+                let limit = *limit;
+                let tasks: Vec<_> = (0..self.shards.len())
+                    .map(|shard| {
+                        let (client, request) = (self.clone(), request.clone());
+                        tokio::spawn(async move { client.send(shard, request).await })
+                    })
+                    .collect();
+                let mut entries = Vec::new();
+                for task in tasks {
+                    let response = task.await.map_err(io::Error::other)??;
+                    match response {
+                        Response::Entries(shard) => entries.extend(shard),
+                        response => return Err(unexpected(response)),
+                    }
+                }
+                entries.sort_unstable();
+                entries.truncate(usize::try_from(limit).unwrap_or(usize::MAX));
+                return Ok(Response::Entries(entries));
+            }
+            _ => {
+                let message = "only requests of a key and scans are sharded";
+                return Err(io::Error::new(ErrorKind::InvalidInput, message).into());
+            }
+        };
+        self.send(shard, request).await
+    }
+
+    /// Sends `request` to the server `shard`, again while it cannot be
+    /// reached and retries are left.
+    async fn send(&self, shard: usize, request: Request) -> Result<Response> {
+        let client = &self.shards[shard].1;
+        let mut backoff = self.retry_backoff;
+        for _ in 0..self.retries {
+            match client.execute(request.clone()).await {
+                Err(Error::Io(err)) if unreachable(&err) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+        client.execute(request).await
+    }
+}
+
+/// Returns whether `err` tells the server could not be reached, rather than
+/// refused the request.
+fn unreachable(err: &io::Error) -> bool {
+    !matches!(
+        err.kind(),
+        ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::PermissionDenied
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedClientBuilder;
+    use crate::client::ClientBuilder;
+    use crate::db::Database;
+    use crate::server::{ServerBuilder, ShutdownHandle};
+    use std::collections::HashMap;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    type Running = (ShutdownHandle, JoinHandle<std::io::Result<()>>);
+
+    fn serve(db: Arc<Database>, address: &str) -> (String, Running) {
+        let server = ServerBuilder::new()
+            .bind_address(address)
+            .workers(4)
+            .bind(db)
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let shutdown = server.shutdown_handle();
+        (address, (shutdown, thread::spawn(move || server.run())))
+    }
+
+    fn stop((shutdown, running): Running) {
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_keys_spread_over_shards_and_survive_restarts() {
+        let dbs: Vec<_> = (0..4).map(|_| Arc::new(Database::new())).collect();
+        let (mut addresses, mut running) = (Vec::new(), Vec::new());
+        for db in &dbs {
+            let (address, server) = serve(db.clone(), "127.0.0.1:0");
+            addresses.push(address);
+            running.push(Some(server));
+        }
+        let addresses: Vec<_> = addresses.iter().map(String::as_str).collect();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let keys: Vec<_> = (0..200).map(|i| format!("user{:03}", i)).collect();
+
+        let (three, four) = runtime.block_on(async {
+            let builder = ShardedClientBuilder::new()
+                .client(ClientBuilder::new().pool_size(1))
+                .retry_backoff(Duration::from_millis(20));
+            let duplicated = builder.clone().connect(&[addresses[0], addresses[0]]).await;
+            assert_eq!(duplicated.err().unwrap().kind(), ErrorKind::InvalidInput);
+            let three = builder.clone().connect(&addresses[..3]).await.unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                three
+                    .put("users", key.as_bytes(), &(i as u64))
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(
+                three.get::<u64>("users", b"user042").await.unwrap(),
+                Some(42)
+            );
+            assert!(three.delete("users", b"user199").await.unwrap());
+            let scanned = three.scan::<u64>("users", b"user0", 150).await.unwrap();
+            assert_eq!(scanned.len(), 100);
+            assert!(scanned.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            let four = builder.retries(5).connect(&addresses).await.unwrap();
+            (three, four)
+        });
+
+        // keys are spread evenly enough, each stored by its shard only
+        let shard_of: HashMap<_, _> = addresses.iter().zip(&dbs).collect();
+        let mut counts = HashMap::new();
+        for key in &keys[..199] {
+            let address = three.shard_of(key.as_bytes());
+            *counts.entry(address).or_insert(0) += 1;
+            let users = shard_of[&address]
+                .open_map::<Vec<u8>, Vec<u8>>("users")
+                .unwrap();
+            assert!(users.get(&key.clone().into_bytes()).is_some());
+        }
+        assert!(counts.values().all(|&count| count > 30), "{:?}", counts);
+        // a shard added only takes keys over
+        let moved = keys
+            .iter()
+            .filter(|key| four.shard_of(key.as_bytes()) != three.shard_of(key.as_bytes()))
+            .inspect(|key| assert_eq!(four.shard_of(key.as_bytes()), addresses[3]))
+            .count();
+        assert!(moved > 20 && moved < 100, "{} moved", moved);
+
+        // requests are retried while their shard restarts
+        let key = keys
+            .iter()
+            .find(|key| three.shard_of(key.as_bytes()) == addresses[1])
+            .unwrap();
+        stop(running[1].take().unwrap());
+        let restarted = thread::spawn({
+            let (db, address) = (dbs[1].clone(), addresses[1].to_string());
+            move || {
+                thread::sleep(Duration::from_millis(100));
+                serve(db, &address).1
+            }
+        });
+        runtime.block_on(async {
+            assert!(four
+                .get::<u64>("users", key.as_bytes())
+                .await
+                .unwrap()
+                .is_some());
+        });
+        running[1] = Some(restarted.join().unwrap());
+        drop((three, four, runtime));
+        for server in running.into_iter().flatten() {
+            stop(server);
+        }
+    }
+}
@@ -0,0 +1,883 @@
+//! Membership of a cluster and detection of the failures of its members by
+//! gossip, following the SWIM protocol, with the `cluster` feature.
+//!
+//! Every member of a cluster runs a [`Gossip`], which joins the cluster
+//! through the members it is given as seeds, then probes another member at
+//! a time, once every [`GossipBuilder::probe_interval`], over UDP. Members
+//! failing to acknowledge a probe, even when asked by a few other members
+//! on its behalf, are suspected of having failed, then declared dead unless
+//! they refute the suspicion in time. Members learn of each other, of the
+//! suspicions and of the deaths through the updates piggybacked on the
+//! probes and their acknowledgements, so that news spread through the
+//! whole cluster in a few rounds of probes, whatever its size.
+//!
+//! Members share metadata, such as the address their [server] serves
+//! clients on, under [`SERVER`], or serves followers on, under
+//! [`REPLICATION`], so that clients and followers find them as the cluster
+//! changes, rather than being configured with lists of addresses, see
+//! [`Gossip::addresses`]. A member whose metadata changes, or which is
+//! suspected, bumps its incarnation, the updates of greater incarnations
+//! overriding the others.
+//!
+//! [server]: crate::server
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::cluster::gossip::{GossipBuilder, SERVER};
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let first = GossipBuilder::new()
+//!     .bind_address("127.0.0.1:0")
+//!     .metadata(SERVER, "127.0.0.1:7070")
+//!     .start("first")
+//!     .unwrap();
+//! let seed = first.local_addr().to_string();
+//! let second = GossipBuilder::new()
+//!     .bind_address("127.0.0.1:0")
+//!     .seeds(&[&seed])
+//!     .metadata(SERVER, "127.0.0.1:7071")
+//!     .start("second")
+//!     .unwrap();
+//!
+//! while first.addresses(SERVER).len() < 2 {
+//!     thread::sleep(Duration::from_millis(10));
+//! }
+//! assert_eq!(
+//!     first.addresses(SERVER),
+//!     vec!["127.0.0.1:7070", "127.0.0.1:7071"]
+//! );
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::codec::{decode_all, invalid_data, Decode, Encode};
+use crate::collections::utils::random;
+
+/// The address members listen on by default.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7946";
+
+/// The metadata naming the address a member serves clients on.
+pub const SERVER: &str = "server";
+
+/// The metadata naming the address a member serves followers on, see
+/// [`crate::replication::Primary`].
+pub const REPLICATION: &str = "replication";
+
+/// The largest payload of a UDP datagram.
+const MAX_PACKET_LEN: usize = 65_507;
+
+/// The most updates piggybacked on a packet, beyond those sent in full to
+/// members joining.
+const MAX_UPDATES: usize = 32;
+
+const PING: u8 = 0;
+const PING_REQ: u8 = 1;
+const ACK: u8 = 2;
+
+const ALIVE: u8 = 0;
+const SUSPECT: u8 = 1;
+const DEAD: u8 = 2;
+
+/// The state of a member, as seen by another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Alive,
+    /// Failed to acknowledge a probe, declared dead unless it refutes it.
+    Suspect,
+    /// Left the cluster or failed, until it joins again.
+    Dead,
+}
+
+/// A member of a cluster, as seen by another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    /// The address the member gossips on.
+    pub address: String,
+    pub state: State,
+    /// The version of the state of the member, bumped by the member only.
+    pub incarnation: u64,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Encode for Member {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.name.encode(buf);
+        self.address.encode(buf);
+        buf.push(match self.state {
+            State::Alive => ALIVE,
+            State::Suspect => SUSPECT,
+            State::Dead => DEAD,
+        });
+        self.incarnation.encode(buf);
+        self.metadata.iter().collect::<Vec<_>>().encode(buf);
+    }
+}
+
+impl Decode for Member {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(Member {
+            name: String::decode(input)?,
+            address: String::decode(input)?,
+            state: match u8::decode(input)? {
+                ALIVE => State::Alive,
+                SUSPECT => State::Suspect,
+                DEAD => State::Dead,
+                _ => return Err(invalid_data("unknown member state")),
+            },
+            incarnation: u64::decode(input)?,
+            metadata: Vec::<(String, String)>::decode(input)?
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
+/// A datagram between members, sent by member `from`, with updates about
+/// members piggybacked, that of the sender first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Packet {
+    from: String,
+    kind: Kind,
+    updates: Vec<Member>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Probes the member, answered by [`Kind::Ack`] of the same `seq`.
+    Ping {
+        seq: u64,
+    },
+    /// Asks the member to probe the member at `target`, forwarding its
+    /// acknowledgement as [`Kind::Ack`] of the same `seq`.
+    PingReq {
+        seq: u64,
+        target: String,
+    },
+    Ack {
+        seq: u64,
+    },
+}
+
+impl Encode for Packet {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.from.encode(buf);
+        match &self.kind {
+            Kind::Ping { seq } => {
+                buf.push(PING);
+                seq.encode(buf);
+            }
+            Kind::PingReq { seq, target } => {
+                buf.push(PING_REQ);
+                seq.encode(buf);
+                target.encode(buf);
+            }
+            Kind::Ack { seq } => {
+                buf.push(ACK);
+                seq.encode(buf);
+            }
+        }
+        self.updates.encode(buf);
+    }
+}
+
+impl Decode for Packet {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let from = String::decode(input)?;
+        let kind = match u8::decode(input)? {
+            PING => Kind::Ping {
+                seq: u64::decode(input)?,
+            },
+            PING_REQ => Kind::PingReq {
+                seq: u64::decode(input)?,
+                target: String::decode(input)?,
+            },
+            ACK => Kind::Ack {
+                seq: u64::decode(input)?,
+            },
+            _ => return Err(invalid_data("unknown gossip packet")),
+        };
+        Ok(Packet {
+            from,
+            kind,
+            updates: Vec::decode(input)?,
+        })
+    }
+}
+
+/// Configures and starts a [`Gossip`].
+#[derive(Clone, Debug)]
+pub struct GossipBuilder {
+    bind_address: String,
+    advertise_address: Option<String>,
+    seeds: Vec<String>,
+    metadata: BTreeMap<String, String>,
+    probe_interval: Duration,
+    probe_timeout: Duration,
+    indirect_probes: usize,
+    suspect_timeout: Duration,
+}
+
+impl Default for GossipBuilder {
+    fn default() -> Self {
+        GossipBuilder::new()
+    }
+}
+
+impl GossipBuilder {
+    /// Creates a builder binding to [`DEFAULT_ADDRESS`], of no seeds nor
+    /// metadata, probing every 500 milliseconds, through 3 other members
+    /// after 200 milliseconds without acknowledgement, and declaring
+    /// members dead after 2 seconds of suspicion.
+    pub fn new() -> Self {
+        GossipBuilder {
+            bind_address: DEFAULT_ADDRESS.to_string(),
+            advertise_address: None,
+            seeds: Vec::new(),
+            metadata: BTreeMap::new(),
+            probe_interval: Duration::from_millis(500),
+            probe_timeout: Duration::from_millis(200),
+            indirect_probes: 3,
+            suspect_timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Sets the address the member gossips on, port 0 picking a free port.
+    pub fn bind_address(mut self, bind_address: &str) -> Self {
+        self.bind_address = bind_address.to_string();
+        self
+    }
+
+    /// Sets the address the other members reach the member at, the address
+    /// it is bound to by default.
+    pub fn advertise_address(mut self, advertise_address: &str) -> Self {
+        self.advertise_address = Some(advertise_address.to_string());
+        self
+    }
+
+    /// Sets the addresses of members to join the cluster through, tried
+    /// until one of them answers. The first member of a cluster has none.
+    pub fn seeds(mut self, seeds: &[&str]) -> Self {
+        self.seeds = seeds.iter().map(|seed| seed.to_string()).collect();
+        self
+    }
+
+    /// Shares `value` under `key` with the other members.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets how often a member is probed.
+    pub fn probe_interval(mut self, probe_interval: Duration) -> Self {
+        self.probe_interval = probe_interval;
+        self
+    }
+
+    /// Sets how long probes wait to be acknowledged before other members
+    /// are asked to probe on their behalf, shorter than the interval of
+    /// probes.
+    pub fn probe_timeout(mut self, probe_timeout: Duration) -> Self {
+        self.probe_timeout = probe_timeout;
+        self
+    }
+
+    /// Sets how many other members are asked to probe a member failing to
+    /// acknowledge a probe.
+    pub fn indirect_probes(mut self, indirect_probes: usize) -> Self {
+        self.indirect_probes = indirect_probes;
+        self
+    }
+
+    /// Sets how long members are suspected before being declared dead.
+    pub fn suspect_timeout(mut self, suspect_timeout: Duration) -> Self {
+        self.suspect_timeout = suspect_timeout;
+        self
+    }
+
+    /// Starts gossiping as the member `name`, unique in the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The error of binding the socket.
+    pub fn start(self, name: &str) -> io::Result<Gossip> {
+        let socket = UdpSocket::bind(&self.bind_address)?;
+        socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+        let local_addr = socket.local_addr()?;
+        let address = match &self.advertise_address {
+            Some(address) => address.clone(),
+            None => local_addr.to_string(),
+        };
+        let me = Member {
+            name: name.to_string(),
+            address,
+            state: State::Alive,
+            incarnation: 0,
+            metadata: self.metadata.clone(),
+        };
+        let mut table = Table {
+            name: name.to_string(),
+            members: BTreeMap::new(),
+            queue: HashMap::new(),
+            suspected: HashMap::new(),
+        };
+        table.members.insert(me.name.clone(), me);
+        let shared = Arc::new(Shared {
+            name: name.to_string(),
+            table: Mutex::new(table),
+            stopped: AtomicBool::new(false),
+        });
+        let mut runner = Runner {
+            socket,
+            shared: shared.clone(),
+            config: self,
+            seq: 0,
+            probe: None,
+            order: Vec::new(),
+            forwards: HashMap::new(),
+            next_probe: Instant::now(),
+        };
+        let thread = thread::Builder::new()
+            .name("palladiumdb-gossip".to_string())
+            .spawn(move || runner.run())?;
+        Ok(Gossip {
+            shared,
+            local_addr,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A member of a cluster gossiping with the others, see
+/// [`crate::cluster::gossip`]. It leaves the cluster once dropped.
+pub struct Gossip {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Gossip {
+    /// Returns the name of the member.
+    pub fn name(&self) -> &str {
+        &self.shared.name
+    }
+
+    /// Returns the address the member is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the members known, the member itself and the dead included,
+    /// sorted by name.
+    pub fn members(&self) -> Vec<Member> {
+        let table = self.shared.table.lock().unwrap();
+        table.members.values().cloned().collect()
+    }
+
+    /// Returns the values of the metadata `key` of the members not
+    /// declared dead, sorted, such as the addresses of their servers for
+    /// [`SERVER`].
+    pub fn addresses(&self, key: &str) -> Vec<String> {
+        let table = self.shared.table.lock().unwrap();
+        let mut addresses: Vec<_> = table
+            .members
+            .values()
+            .filter(|member| member.state != State::Dead)
+            .filter_map(|member| member.metadata.get(key).cloned())
+            .collect();
+        addresses.sort_unstable();
+        addresses
+    }
+
+    /// Shares `value` under `key` with the other members, no value
+    /// removing the metadata.
+    pub fn set_metadata(&self, key: &str, value: Option<&str>) {
+        let mut table = self.shared.table.lock().unwrap();
+        let name = self.shared.name.clone();
+        let me = table.members.get_mut(&name).unwrap();
+        match value {
+            Some(value) => me.metadata.insert(key.to_string(), value.to_string()),
+            None => me.metadata.remove(key),
+        };
+        me.incarnation += 1;
+        table.queue.insert(name, 0);
+    }
+}
+
+impl Drop for Gossip {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Shared {
+    name: String,
+    table: Mutex<Table>,
+    stopped: AtomicBool,
+}
+
+/// The members known to a member, with the updates left to gossip.
+struct Table {
+    name: String,
+    members: BTreeMap<String, Member>,
+    /// The names of the members whose state is to be gossiped, with the
+    /// number of times it was.
+    queue: HashMap<String, u32>,
+    /// The members suspected, with when they started to be.
+    suspected: HashMap<String, Instant>,
+}
+
+impl Table {
+    /// Applies `update` if it overrides the state known of its member, a
+    /// suspicion of the member itself being refuted.
+    ///
+    /// # Returns
+    ///
+    /// Whether the update was applied.
+    fn apply(&mut self, update: Member) -> bool {
+        if update.name == self.name {
+            let me = self.members.get_mut(&self.name).unwrap();
+            if update.state != State::Alive && update.incarnation >= me.incarnation {
+                me.incarnation = update.incarnation + 1;
+                self.queue.insert(self.name.clone(), 0);
+            }
+            return false;
+        }
+        let applies = match self.members.get(&update.name) {
+            None => update.state != State::Dead,
+            Some(known) => match update.state {
+                State::Alive => update.incarnation > known.incarnation,
+                State::Suspect => {
+                    update.incarnation > known.incarnation
+                        || (update.incarnation == known.incarnation && known.state == State::Alive)
+                }
+                State::Dead => {
+                    update.incarnation > known.incarnation
+                        || (update.incarnation == known.incarnation && known.state != State::Dead)
+                }
+            },
+        };
+        if applies {
+            match update.state {
+                State::Suspect => {
+                    self.suspected.insert(update.name.clone(), Instant::now());
+                }
+                _ => {
+                    self.suspected.remove(&update.name);
+                }
+            }
+            self.queue.insert(update.name.clone(), 0);
+            self.members.insert(update.name.clone(), update);
+        }
+        applies
+    }
+
+    /// Marks the member `name` as `state` in its current incarnation.
+    fn declare(&mut self, name: &str, state: State) {
+        if let Some(member) = self.members.get(name) {
+            let update = Member {
+                state,
+                ..member.clone()
+            };
+            self.apply(update);
+        }
+    }
+
+    /// Returns the updates to piggyback on a packet to the member
+    /// `recipient`: that of the member itself, that of the recipient if it
+    /// is not known alive, so that it refutes it, then those gossiped the
+    /// least, or all of them if `full`.
+    fn updates(&mut self, recipient: Option<&str>, full: bool) -> Vec<Member> {
+        let mut updates = vec![self.members[&self.name].clone()];
+        if let Some(recipient) = recipient.and_then(|name| self.members.get(name)) {
+            if recipient.state != State::Alive {
+                updates.push(recipient.clone());
+            }
+        }
+        if full {
+            updates.extend(
+                self.members
+                    .values()
+                    .filter(|member| member.name != self.name)
+                    .cloned(),
+            );
+            return updates;
+        }
+        // updates are gossiped a number of times growing with the log of
+        // the size of the cluster, enough for them to reach every member
+        let limit = 3 * (64 - (self.members.len() as u64).leading_zeros());
+        let mut queued: Vec<_> = self
+            .queue
+            .iter()
+            .map(|(name, count)| (*count, name.clone()))
+            .collect();
+        queued.sort_unstable();
+        for (count, name) in queued.into_iter().take(MAX_UPDATES) {
+            if count + 1 >= limit {
+                self.queue.remove(&name);
+            } else {
+                self.queue.insert(name.clone(), count + 1);
+            }
+            if let Some(member) = self.members.get(&name) {
+                updates.push(member.clone());
+            }
+        }
+        updates
+    }
+
+    /// Returns the members other than the member itself not declared dead.
+    fn live(&self) -> impl Iterator<Item = &Member> {
+        self.members
+            .values()
+            .filter(move |member| member.name != self.name && member.state != State::Dead)
+    }
+}
+
+/// A probe awaiting its acknowledgement.
+struct Probe {
+    name: String,
+    seq: u64,
+    sent_at: Instant,
+    indirect: bool,
+    acked: bool,
+}
+
+/// Runs the protocol on the thread of a [`Gossip`].
+struct Runner {
+    socket: UdpSocket,
+    shared: Arc<Shared>,
+    config: GossipBuilder,
+    seq: u64,
+    probe: Option<Probe>,
+    /// The names of the members left to probe in this round, in the
+    /// reverse order.
+    order: Vec<String>,
+    /// The probes made on behalf of other members, by sequence number,
+    /// with the address and sequence number of the member to acknowledge.
+    forwards: HashMap<u64, (SocketAddr, u64, Instant)>,
+    next_probe: Instant,
+}
+
+impl Runner {
+    fn run(&mut self) {
+        let mut buf = vec![0; MAX_PACKET_LEN];
+        while !self.shared.stopped.load(Ordering::SeqCst) {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    if let Ok(packet) = decode_all::<Packet>(&buf[..len]) {
+                        self.handle(packet, from);
+                    }
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                // such as the refusal of a previous datagram
+                Err(_) => {}
+            }
+            self.tick(Instant::now());
+        }
+        self.leave();
+    }
+
+    fn handle(&mut self, packet: Packet, from: SocketAddr) {
+        let joining = {
+            let mut table = self.shared.table.lock().unwrap();
+            let known = table
+                .members
+                .get(&packet.from)
+                .is_some_and(|member| member.state != State::Dead);
+            for update in packet.updates {
+                table.apply(update);
+            }
+            !known
+        };
+        match packet.kind {
+            Kind::Ping { seq } => {
+                // members joining are told of the whole cluster at once
+                self.send(from, Kind::Ack { seq }, Some(&packet.from), joining);
+            }
+            Kind::PingReq { seq, target } => {
+                self.seq += 1;
+                self.forwards.insert(self.seq, (from, seq, Instant::now()));
+                let ping = Kind::Ping { seq: self.seq };
+                self.send(target.as_str(), ping, None, false);
+            }
+            Kind::Ack { seq } => match &mut self.probe {
+                Some(probe) if probe.seq == seq => probe.acked = true,
+                _ => {
+                    if let Some((requester, seq, _)) = self.forwards.remove(&seq) {
+                        self.send(requester, Kind::Ack { seq }, None, false);
+                    }
+                }
+            },
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        let config = &self.config;
+        self.forwards
+            .retain(|_, (_, _, at)| now.duration_since(*at) < config.probe_interval);
+        {
+            let mut table = self.shared.table.lock().unwrap();
+            let expired: Vec<_> = table
+                .suspected
+                .iter()
+                .filter(|(_, since)| now.duration_since(**since) >= config.suspect_timeout)
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in expired {
+                table.declare(&name, State::Dead);
+            }
+        }
+        let timed_out = self.probe.as_ref().is_some_and(|probe| {
+            !probe.acked
+                && !probe.indirect
+                && now.duration_since(probe.sent_at) >= config.probe_timeout
+        });
+        if timed_out {
+            self.probe_indirectly();
+        }
+        if now < self.next_probe {
+            return;
+        }
+        self.next_probe = now + self.config.probe_interval;
+        if let Some(probe) = self.probe.take() {
+            if !probe.acked {
+                let mut table = self.shared.table.lock().unwrap();
+                if table
+                    .members
+                    .get(&probe.name)
+                    .is_some_and(|member| member.state == State::Alive)
+                {
+                    table.declare(&probe.name, State::Suspect);
+                }
+            }
+        }
+        match self.next_target() {
+            Some((name, address)) => {
+                self.seq += 1;
+                self.probe = Some(Probe {
+                    name: name.clone(),
+                    seq: self.seq,
+                    sent_at: now,
+                    indirect: false,
+                    acked: false,
+                });
+                self.send(
+                    address.as_str(),
+                    Kind::Ping { seq: self.seq },
+                    Some(&name),
+                    false,
+                );
+            }
+            // alone, the member tries to join the cluster
+            None => {
+                for seed in self.config.seeds.clone() {
+                    self.seq += 1;
+                    self.send(seed.as_str(), Kind::Ping { seq: self.seq }, None, false);
+                }
+            }
+        }
+    }
+
+    /// Returns the name and address of the next member to probe, the
+    /// members being probed in turn, in an order shuffled every round.
+    fn next_target(&mut self) -> Option<(String, String)> {
+        let table = self.shared.table.lock().unwrap();
+        for _ in 0..2 {
+            while let Some(name) = self.order.pop() {
+                let member = table.members.get(&name);
+                if let Some(member) = member.filter(|member| member.state != State::Dead) {
+                    return Some((name, member.address.clone()));
+                }
+            }
+            self.order = table.live().map(|member| member.name.clone()).collect();
+            for i in (1..self.order.len()).rev() {
+                let j = (random() % (i as u64 + 1)) as usize;
+                self.order.swap(i, j);
+            }
+        }
+        None
+    }
+
+    /// Asks a few other members, picked at random, to probe the member the
+    /// probe awaiting its acknowledgement was sent to.
+    fn probe_indirectly(&mut self) {
+        let probe = self.probe.as_mut().unwrap();
+        probe.indirect = true;
+        let (name, seq) = (probe.name.clone(), probe.seq);
+        let (target, mut others) = {
+            let table = self.shared.table.lock().unwrap();
+            let target = match table.members.get(&name) {
+                Some(target) => target.address.clone(),
+                None => return,
+            };
+            let others: Vec<_> = table
+                .live()
+                .filter(|member| member.name != name && member.state == State::Alive)
+                .map(|member| member.address.clone())
+                .collect();
+            (target, others)
+        };
+        for _ in 0..self.config.indirect_probes {
+            if others.is_empty() {
+                break;
+            }
+            let other = others.swap_remove((random() % others.len() as u64) as usize);
+            let ping_req = Kind::PingReq {
+                seq,
+                target: target.clone(),
+            };
+            self.send(other.as_str(), ping_req, None, false);
+        }
+    }
+
+    /// Tells the members not declared dead that the member leaves.
+    fn leave(&mut self) {
+        let addresses: Vec<_> = {
+            let mut table = self.shared.table.lock().unwrap();
+            let name = self.shared.name.clone();
+            let me = table.members.get_mut(&name).unwrap();
+            me.state = State::Dead;
+            me.incarnation += 1;
+            table.live().map(|member| member.address.clone()).collect()
+        };
+        for address in addresses {
+            self.seq += 1;
+            self.send(address.as_str(), Kind::Ping { seq: self.seq }, None, false);
+        }
+    }
+
+    /// Sends a packet of `kind` to `to`, the member `recipient`, with as
+    /// many updates as fit.
+    fn send(&self, to: impl ToSocketAddrs, kind: Kind, recipient: Option<&str>, full: bool) {
+        let updates = self.shared.table.lock().unwrap().updates(recipient, full);
+        let mut packet = Packet {
+            from: self.shared.name.clone(),
+            kind,
+            updates,
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        while buf.len() > MAX_PACKET_LEN && packet.updates.len() > 1 {
+            packet.updates.truncate(packet.updates.len() / 2);
+            buf.clear();
+            packet.encode(&mut buf);
+        }
+        let _ = self.socket.send_to(&buf, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gossip, GossipBuilder, Kind, Member, Packet, State, SERVER};
+    use crate::codec::{decode_all, Encode};
+    use std::collections::BTreeMap;
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn start(name: &str, seeds: &[&str]) -> Gossip {
+        GossipBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .seeds(seeds)
+            .metadata(SERVER, &format!("{}:7070", name))
+            .probe_interval(Duration::from_millis(50))
+            .probe_timeout(Duration::from_millis(20))
+            .suspect_timeout(Duration::from_millis(300))
+            .start(name)
+            .unwrap()
+    }
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn state(gossip: &Gossip, name: &str) -> Option<State> {
+        let members = gossip.members();
+        let member = members.into_iter().find(|member| member.name == name);
+        member.map(|member| member.state)
+    }
+
+    #[test]
+    fn test_members_join_share_metadata_and_detect_failures() {
+        let a = start("a", &[]);
+        let seed = a.local_addr().to_string();
+        let b = start("b", &[&seed]);
+        let c = start("c", &[&seed]);
+        let all = vec!["a:7070", "b:7070", "c:7070"];
+        wait_for(|| {
+            [&a, &b, &c]
+                .iter()
+                .all(|gossip| gossip.addresses(SERVER) == all)
+        });
+
+        // metadata changes spread
+        c.set_metadata(SERVER, Some("c:7071"));
+        c.set_metadata("shards", Some("0-63"));
+        wait_for(|| {
+            [&a, &b]
+                .iter()
+                .all(|gossip| gossip.members()[2].incarnation == 2)
+        });
+        assert_eq!(a.addresses(SERVER), vec!["a:7070", "b:7070", "c:7071"]);
+        let members = b.members();
+        assert_eq!(members[2].metadata["shards"], "0-63");
+        assert_eq!(members[2].address, c.local_addr().to_string());
+
+        // a member joining without acknowledging probes is suspected, then
+        // declared dead
+        let ghost = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ghost
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let packet = Packet {
+            from: "ghost".to_string(),
+            kind: Kind::Ping { seq: 1 },
+            updates: vec![Member {
+                name: "ghost".to_string(),
+                address: ghost.local_addr().unwrap().to_string(),
+                state: State::Alive,
+                incarnation: 0,
+                metadata: BTreeMap::new(),
+            }],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        ghost.send_to(&buf, &seed).unwrap();
+        let mut reply = vec![0; 65_536];
+        let len = ghost.recv(&mut reply).unwrap();
+        let ack = decode_all::<Packet>(&reply[..len]).unwrap();
+        assert_eq!(ack.kind, Kind::Ack { seq: 1 });
+        assert_eq!(ack.updates.len(), 4);
+        wait_for(|| state(&b, "ghost") == Some(State::Suspect));
+        wait_for(|| {
+            [&a, &b, &c]
+                .iter()
+                .all(|gossip| state(gossip, "ghost") == Some(State::Dead))
+        });
+
+        // a member leaving is declared dead, then alive again once back
+        drop(c);
+        wait_for(|| state(&a, "c") == Some(State::Dead) && state(&b, "c") == Some(State::Dead));
+        assert_eq!(b.addresses(SERVER), vec!["a:7070", "b:7070"]);
+        let c = start("c", &[&seed]);
+        wait_for(|| state(&b, "c") == Some(State::Alive));
+        assert_eq!(b.addresses(SERVER), all);
+        assert!(c.members()[2].incarnation > 2);
+        assert_eq!(c.addresses(SERVER), all);
+    }
+}
@@ -0,0 +1,11 @@
+//! Databases spread over several nodes, with the `cluster` feature.
+//!
+//! [`raft`] keeps a database consistent across the 3 or 5 nodes of a
+//! cluster, writes being linearizable and surviving the loss of a minority
+//! of the nodes, where [replication](crate::replication) copies it to
+//! followers on a best-effort basis. Through [`gossip`], the members of a
+//! cluster find each other and tell which of them failed, sharing the
+//! addresses clients and followers reach them at.
+
+pub mod gossip;
+pub mod raft;
@@ -0,0 +1,151 @@
+use std::io;
+
+use super::storage::{Entry, SnapshotMeta};
+use super::NodeId;
+use crate::codec::{decode_bytes, encode_bytes, invalid_data, Decode, Encode};
+
+const VOTE: u8 = 0;
+const VOTE_REPLY: u8 = 1;
+const APPEND: u8 = 2;
+const APPEND_REPLY: u8 = 3;
+const SNAPSHOT: u8 = 4;
+const SNAPSHOT_REPLY: u8 = 5;
+
+/// A message between the nodes of a cluster, sent by node `from`, reached
+/// at `address`, in its term `term`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Message {
+    pub(super) from: NodeId,
+    pub(super) address: String,
+    pub(super) term: u64,
+    pub(super) body: Body,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Body {
+    /// Asks for the vote of the node, for a candidate whose log ends with
+    /// an entry at `last_index` of term `last_term`.
+    Vote {
+        last_index: u64,
+        last_term: u64,
+    },
+    VoteReply {
+        granted: bool,
+    },
+    /// Appends `entries` to the log of a follower, if it holds the entry at
+    /// `prev_index` of term `prev_term` they follow, the entries up to
+    /// `commit` being committed.
+    Append {
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    },
+    /// Answers [`Body::Append`] with the index of the last entry the
+    /// follower holds of the leader if it `success`ed, the index to retry
+    /// from less one otherwise.
+    AppendReply {
+        success: bool,
+        index: u64,
+    },
+    /// Replaces the database and log of a follower too far behind for the
+    /// log of the leader by a snapshot.
+    Snapshot {
+        meta: SnapshotMeta,
+        data: Vec<u8>,
+    },
+    /// Answers [`Body::Snapshot`] with the index of the last entry the
+    /// follower holds of the leader.
+    SnapshotReply {
+        index: u64,
+    },
+}
+
+impl Encode for Message {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.from.encode(buf);
+        self.address.encode(buf);
+        self.term.encode(buf);
+        match &self.body {
+            Body::Vote {
+                last_index,
+                last_term,
+            } => {
+                buf.push(VOTE);
+                last_index.encode(buf);
+                last_term.encode(buf);
+            }
+            Body::VoteReply { granted } => {
+                buf.push(VOTE_REPLY);
+                granted.encode(buf);
+            }
+            Body::Append {
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                buf.push(APPEND);
+                prev_index.encode(buf);
+                prev_term.encode(buf);
+                entries.encode(buf);
+                commit.encode(buf);
+            }
+            Body::AppendReply { success, index } => {
+                buf.push(APPEND_REPLY);
+                success.encode(buf);
+                index.encode(buf);
+            }
+            Body::Snapshot { meta, data } => {
+                buf.push(SNAPSHOT);
+                meta.encode(buf);
+                encode_bytes(data, buf);
+            }
+            Body::SnapshotReply { index } => {
+                buf.push(SNAPSHOT_REPLY);
+                index.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let from = NodeId::decode(input)?;
+        let address = String::decode(input)?;
+        let term = u64::decode(input)?;
+        let body = match u8::decode(input)? {
+            VOTE => Body::Vote {
+                last_index: u64::decode(input)?,
+                last_term: u64::decode(input)?,
+            },
+            VOTE_REPLY => Body::VoteReply {
+                granted: bool::decode(input)?,
+            },
+            APPEND => Body::Append {
+                prev_index: u64::decode(input)?,
+                prev_term: u64::decode(input)?,
+                entries: Vec::decode(input)?,
+                commit: u64::decode(input)?,
+            },
+            APPEND_REPLY => Body::AppendReply {
+                success: bool::decode(input)?,
+                index: u64::decode(input)?,
+            },
+            SNAPSHOT => Body::Snapshot {
+                meta: SnapshotMeta::decode(input)?,
+                data: decode_bytes(input)?.to_vec(),
+            },
+            SNAPSHOT_REPLY => Body::SnapshotReply {
+                index: u64::decode(input)?,
+            },
+            _ => return Err(invalid_data("unknown raft message")),
+        };
+        Ok(Message {
+            from,
+            address,
+            term,
+            body,
+        })
+    }
+}
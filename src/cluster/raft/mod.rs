@@ -0,0 +1,968 @@
+//! Consensus of a cluster of nodes on a [`Database`], following the Raft
+//! algorithm, with the `cluster` feature.
+//!
+//! The nodes of a cluster elect a leader, which appends the requests made
+//! of the cluster to its log and replicates it to the other nodes. Every
+//! node carries out the requests on its own database, its state machine,
+//! once a majority of the nodes hold them. Requests are made of the leader
+//! through [`Raft::execute`], reads included, so that both reads and writes
+//! are linearizable, other nodes failing them with [`Error::NotLeader`],
+//! which names the leader to retry with. The cluster keeps working as long
+//! as a majority of its members do, electing another leader when the
+//! leader fails, which is why clusters are made of 3 or 5 nodes, losing 1
+//! or 2 of them at most.
+//!
+//! Every node keeps its term, vote and log in a directory of its own, with
+//! the last snapshot of its database, taken every
+//! [`RaftBuilder::snapshot_entries`] entries applied, after which the log
+//! is compacted. Nodes too far behind for the log of the leader are sent
+//! its snapshot instead. A node rebuilds its database from its snapshot and
+//! log as it starts: the keyspaces of the database are those of the
+//! cluster, whose keys and values are byte strings as for a
+//! [server], and should not be written to otherwise.
+//!
+//! A cluster starts from a single node, see [`RaftBuilder::bootstrap`], and
+//! grows one node at a time through [`Raft::add_node`], the nodes started
+//! without bootstrapping waiting to be added, or shrinks through
+//! [`Raft::remove_node`]. Nodes talk to each other over TCP, in frames of
+//! the [protocol].
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::cluster::raft::RaftBuilder;
+//! use palladiumdb::db::Database;
+//! use palladiumdb::protocol::{Request, Response};
+//! use std::sync::Arc;
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let dir = std::env::temp_dir().join("palladiumdb-doc-raft");
+//! # let _ = std::fs::remove_dir_all(&dir);
+//! let node = RaftBuilder::new()
+//!     .bind_address("127.0.0.1:0")
+//!     .bootstrap(true)
+//!     .start(1, dir.join("1"), Arc::new(Database::new()))
+//!     .unwrap();
+//! while node.status().leader.is_none() {
+//!     thread::sleep(Duration::from_millis(10));
+//! }
+//!
+//! let put = Request::Put {
+//!     keyspace: "users".to_string(),
+//!     key: b"alice".to_vec(),
+//!     value: b"31".to_vec(),
+//! };
+//! assert_eq!(node.execute(put).unwrap(), Response::Ok);
+//! let get = Request::Get {
+//!     keyspace: "users".to_string(),
+//!     key: b"alice".to_vec(),
+//! };
+//! assert_eq!(node.execute(get).unwrap(), Response::Value(Some(b"31".to_vec())));
+//! # drop(node);
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::codec::{decode_all, Encode};
+use crate::db::{self, Database};
+use crate::protocol::{self, Request, Response};
+use crate::server;
+use crate::storage::StorageEngine;
+use message::Message;
+use node::Node;
+use storage::{Command, Storage};
+
+mod message;
+mod node;
+mod storage;
+
+/// Identifies a node within its cluster.
+pub type NodeId = u64;
+
+/// Address nodes bind to, unless [`RaftBuilder::bind_address`] sets
+/// another one.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7480";
+
+/// How often the listener checks for a shutdown while no node connects.
+const ACCEPT_POLL: Duration = Duration::from_millis(10);
+
+/// How long a node tries to connect to another, and waits before trying
+/// again once it failed.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long a node waits on another reading its messages.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages queued for a node beyond that many are dropped, as lost by the
+/// network.
+const PEER_QUEUE: usize = 1024;
+
+/// A key value pair of a keyspace.
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Errors returned by a [`Raft`] node.
+#[derive(Debug)]
+pub enum Error {
+    /// The node does not lead the cluster, `leader`, with its address,
+    /// doing so if known.
+    NotLeader { leader: Option<(NodeId, String)> },
+    /// A change of the members of the cluster is in progress already.
+    MembershipChanging,
+    /// The request was not carried out within the timeout of the node, see
+    /// [`RaftBuilder::request_timeout`], and may still be.
+    Timeout,
+    /// The node stopped, once dropped or having failed to write to its
+    /// directory.
+    Stopped,
+    /// Rebuilding the database failed.
+    Db(db::Error),
+    /// Reading or writing the directory of the node, or binding to its
+    /// address, failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotLeader {
+                leader: Some((id, address)),
+            } => write!(f, "node {} at {} leads the cluster", id, address),
+            Error::NotLeader { leader: None } => write!(f, "the cluster has no leader"),
+            Error::MembershipChanging => write!(f, "members of the cluster are changing"),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::Stopped => write!(f, "node is stopped"),
+            Error::Db(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Db(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<db::Error> for Error {
+    fn from(err: db::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+/// Result of the fallible operations of a [`Raft`] node.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The part a node plays in its cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Replicates the log of the leader.
+    Follower,
+    /// Stands for election, having heard from no leader.
+    Candidate,
+    /// Replicates its log to the other nodes.
+    Leader,
+}
+
+/// The state of a [`Raft`] node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RaftStatus {
+    pub role: Role,
+    /// The current term, counting the elections.
+    pub term: u64,
+    /// The leader of the current term, if known.
+    pub leader: Option<NodeId>,
+    /// The index of the last entry of the log known to be committed.
+    pub commit_index: u64,
+    /// The index of the last entry carried out on the database.
+    pub applied_index: u64,
+    /// The index of the last entry the snapshot covers.
+    pub snapshot_index: u64,
+    /// The members of the cluster, with their address.
+    pub members: BTreeMap<NodeId, String>,
+}
+
+/// Configures and starts a [`Raft`] node.
+pub struct RaftBuilder {
+    bind_address: String,
+    advertise_address: Option<String>,
+    bootstrap: bool,
+    heartbeat_interval: Duration,
+    election_timeout: Duration,
+    request_timeout: Duration,
+    snapshot_entries: u64,
+}
+
+impl Default for RaftBuilder {
+    fn default() -> Self {
+        RaftBuilder::new()
+    }
+}
+
+impl RaftBuilder {
+    /// Creates a builder binding to [`DEFAULT_ADDRESS`], with heartbeats
+    /// every 50 milliseconds, elections after 500 milliseconds without
+    /// one, requests timing out after 5 seconds and snapshots every 10000
+    /// entries.
+    pub fn new() -> Self {
+        RaftBuilder {
+            bind_address: DEFAULT_ADDRESS.to_string(),
+            advertise_address: None,
+            bootstrap: false,
+            heartbeat_interval: Duration::from_millis(50),
+            election_timeout: Duration::from_millis(500),
+            request_timeout: Duration::from_secs(5),
+            snapshot_entries: 10_000,
+        }
+    }
+
+    /// Sets the address the node listens on for the other nodes, port 0
+    /// picking a free port.
+    pub fn bind_address(mut self, bind_address: &str) -> Self {
+        self.bind_address = bind_address.to_string();
+        self
+    }
+
+    /// Sets the address the other nodes reach the node at, the address it
+    /// is bound to by default.
+    pub fn advertise_address(mut self, advertise_address: &str) -> Self {
+        self.advertise_address = Some(advertise_address.to_string());
+        self
+    }
+
+    /// Makes the node the only member of a new cluster if its directory
+    /// holds none yet, rather than waiting to be added to one.
+    pub fn bootstrap(mut self, bootstrap: bool) -> Self {
+        self.bootstrap = bootstrap;
+        self
+    }
+
+    /// Sets how often the leader replicates its log, if only to tell the
+    /// other nodes it is alive.
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Sets how long followers wait without hearing from the leader before
+    /// standing for election, from once to twice as long, picked at
+    /// random.
+    pub fn election_timeout(mut self, election_timeout: Duration) -> Self {
+        self.election_timeout = election_timeout;
+        self
+    }
+
+    /// Sets how long requests wait to be carried out before failing with
+    /// [`Error::Timeout`].
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Sets how many entries are applied between two snapshots of the
+    /// database, after which the log is compacted.
+    pub fn snapshot_entries(mut self, snapshot_entries: u64) -> Self {
+        self.snapshot_entries = snapshot_entries.max(1);
+        self
+    }
+
+    /// Starts node `id` of the cluster, keeping its state in `dir`, created
+    /// if missing, and carrying out the requests of the cluster on `db`,
+    /// rebuilt from the snapshot of the node first.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Io`] if the directory cannot be read or the address bound
+    /// to, [`Error::Db`] if the database cannot be rebuilt.
+    pub fn start<H, E>(
+        self,
+        id: NodeId,
+        dir: impl AsRef<Path>,
+        db: Arc<Database<H, E>>,
+    ) -> Result<Raft>
+    where
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        let storage = Storage::open(dir.as_ref())?;
+        let addresses: Vec<_> = self.bind_address.to_socket_addrs()?.collect();
+        let listener = TcpListener::bind(&addresses[..])?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let advertised = self
+            .advertise_address
+            .unwrap_or_else(|| address.to_string());
+        let heartbeat = self.heartbeat_interval.max(Duration::from_millis(1));
+        let election_ticks = self.election_timeout.as_millis() / heartbeat.as_millis();
+        let mut node = Node::new(id, advertised, storage, election_ticks.max(2) as u32, 1);
+        if self.bootstrap {
+            node.bootstrap()?;
+        }
+        restore(&db, &node.snapshot_data()?)?;
+
+        let shared = Arc::new(Shared {
+            stopped: AtomicBool::new(false),
+            status: Mutex::new(status(&node)),
+            connections: Mutex::default(),
+            next_connection: AtomicU64::new(0),
+        });
+        let (inputs, received) = mpsc::channel();
+        let (accepting, sending) = (shared.clone(), inputs.clone());
+        let listener = thread::Builder::new()
+            .name(format!("palladiumdb-raft-{}-listener", id))
+            .spawn(move || accept(listener, &sending, &accepting))?;
+        let runner = Runner {
+            node,
+            db,
+            shared: shared.clone(),
+            peers: HashMap::new(),
+            addresses: HashMap::new(),
+            pending: BTreeMap::new(),
+            snapshot_entries: self.snapshot_entries,
+        };
+        let runner = thread::Builder::new()
+            .name(format!("palladiumdb-raft-{}", id))
+            .spawn(move || runner.run(received, heartbeat));
+        let runner = match runner {
+            Ok(runner) => runner,
+            Err(err) => {
+                shared.stopped.store(true, Ordering::SeqCst);
+                let _ = listener.join();
+                return Err(err.into());
+            }
+        };
+        Ok(Raft {
+            id,
+            address,
+            inputs,
+            shared,
+            threads: vec![listener, runner],
+            request_timeout: self.request_timeout,
+        })
+    }
+}
+
+/// What a node is asked to do.
+enum Input {
+    Message(Message),
+    Request {
+        request: Request,
+        reply: Sender<Result<Response>>,
+    },
+    Change {
+        change: Change,
+        reply: Sender<Result<Response>>,
+    },
+}
+
+/// A change of the members of a cluster.
+enum Change {
+    Add(NodeId, String),
+    Remove(NodeId),
+}
+
+/// State shared by the threads of a [`Raft`] node.
+struct Shared {
+    stopped: AtomicBool,
+    status: Mutex<RaftStatus>,
+    /// The connections of the other nodes, by id, shut down when stopping.
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    next_connection: AtomicU64,
+}
+
+/// A node of a cluster, until dropped.
+pub struct Raft {
+    id: NodeId,
+    address: SocketAddr,
+    inputs: Sender<Input>,
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+    request_timeout: Duration,
+}
+
+impl Raft {
+    /// Returns the id of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the address the node is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the state of the node.
+    pub fn status(&self) -> RaftStatus {
+        self.shared.status.lock().unwrap().clone()
+    }
+
+    /// Carries out `request` on the database of every node, once a majority
+    /// of them logged it, as [`server::execute`] does.
+    ///
+    /// # Returns
+    ///
+    /// The response of the database of the node, [`Error::NotLeader`] if
+    /// the node does not lead the cluster or stopped doing so before the
+    /// request was committed, [`Error::Timeout`] if it was not carried out
+    /// in time, [`Error::Stopped`] if the node stopped.
+    pub fn execute(&self, request: Request) -> Result<Response> {
+        self.call(|reply| Input::Request { request, reply })
+    }
+
+    /// Adds node `id`, reached at `address`, to the cluster, or updates its
+    /// address, once the change is committed. The node is sent the log, or
+    /// a snapshot, of the leader.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::NotLeader`] if the node does not lead the cluster,
+    /// [`Error::MembershipChanging`] if another change is in progress.
+    pub fn add_node(&self, id: NodeId, address: &str) -> Result<()> {
+        let change = Change::Add(id, address.to_string());
+        self.call(|reply| Input::Change { change, reply }).map(drop)
+    }
+
+    /// Removes node `id` from the cluster, once the change is committed,
+    /// the leader stepping down if it removes itself. Nodes removed keep
+    /// running until dropped, but for the cluster.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::NotLeader`] if the node does not lead the cluster,
+    /// [`Error::MembershipChanging`] if another change is in progress.
+    pub fn remove_node(&self, id: NodeId) -> Result<()> {
+        let change = Change::Remove(id);
+        self.call(|reply| Input::Change { change, reply }).map(drop)
+    }
+
+    fn call(&self, input: impl FnOnce(Sender<Result<Response>>) -> Input) -> Result<Response> {
+        let (reply, replied) = mpsc::channel();
+        self.inputs.send(input(reply)).map_err(|_| Error::Stopped)?;
+        match replied.recv_timeout(self.request_timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Stopped),
+        }
+    }
+}
+
+impl Drop for Raft {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn status(node: &Node) -> RaftStatus {
+    RaftStatus {
+        role: node.role(),
+        term: node.term(),
+        leader: node.leader(),
+        commit_index: node.commit(),
+        applied_index: node.applied(),
+        snapshot_index: node.snapshot().index,
+        members: node.members().clone(),
+    }
+}
+
+/// Returns the keyspaces of `db` holding byte strings, encoded.
+fn snapshot<H, E>(db: &Database<H, E>) -> db::Result<Vec<u8>>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let durable = db.recovery_report().is_some();
+    let mut keyspaces = Vec::new();
+    for name in db.map_names() {
+        let entries: db::Result<Vec<Entry>> = match durable {
+            true => db
+                .open_durable(&name)
+                .map(|keyspace| keyspace.map().iter().collect()),
+            false => db.open_map(&name).map(|keyspace| keyspace.iter().collect()),
+        };
+        match entries {
+            Ok(entries) => keyspaces.push((name, entries)),
+            Err(db::Error::TypeMismatch { .. }) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let mut data = Vec::new();
+    keyspaces.encode(&mut data);
+    Ok(data)
+}
+
+/// Replaces the keyspaces of `db` by those of `data`, written by
+/// [`snapshot`], none if empty.
+fn restore<H, E>(db: &Database<H, E>, data: &[u8]) -> Result<()>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let keyspaces: Vec<(String, Vec<Entry>)> = match data.is_empty() {
+        true => Vec::new(),
+        false => decode_all(data)?,
+    };
+    for name in db.map_names() {
+        db.drop_map(&name)?;
+    }
+    let durable = db.recovery_report().is_some();
+    for (name, entries) in keyspaces {
+        if durable {
+            let keyspace = db.open_durable::<Vec<u8>, Vec<u8>>(&name)?;
+            for (key, value) in entries {
+                keyspace.put(&key, value)?;
+            }
+        } else {
+            let keyspace = db.open_map::<Vec<u8>, Vec<u8>>(&name)?;
+            for (key, value) in entries {
+                keyspace.put(&key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the consensus of a node, carrying out the entries committed on its
+/// database.
+struct Runner<H, E> {
+    node: Node,
+    db: Arc<Database<H, E>>,
+    shared: Arc<Shared>,
+    peers: HashMap<NodeId, Peer>,
+    /// The addresses of the nodes that sent messages, members or not.
+    addresses: HashMap<NodeId, String>,
+    /// The requests proposed, by index, with the term of their entry.
+    pending: BTreeMap<u64, (u64, Sender<Result<Response>>)>,
+    snapshot_entries: u64,
+}
+
+impl<H, E> Runner<H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    /// Handles `inputs`, ticking the clock of the node every `tick`, until
+    /// stopped or failing.
+    fn run(mut self, inputs: Receiver<Input>, tick: Duration) {
+        let mut next_tick = Instant::now() + tick;
+        while !self.shared.stopped.load(Ordering::SeqCst) {
+            let input =
+                match inputs.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                    Ok(input) => Some(input),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+            if self.handle(input).is_err() {
+                break;
+            }
+            if Instant::now() >= next_tick {
+                next_tick = Instant::now() + tick;
+                if self.node.tick().is_err() || self.progress().is_err() {
+                    break;
+                }
+            }
+        }
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        for (_, peer) in self.peers.drain() {
+            drop(peer.queue);
+            let _ = peer.thread.join();
+        }
+    }
+
+    fn handle(&mut self, input: Option<Input>) -> Result<()> {
+        match input {
+            Some(Input::Message(message)) => {
+                self.addresses.insert(message.from, message.address.clone());
+                self.node.step(message)?;
+            }
+            Some(Input::Request { request, reply }) => {
+                let mut payload = Vec::new();
+                request.encode(&mut payload);
+                self.propose(Command::Request(payload), reply)?;
+            }
+            Some(Input::Change { change, reply }) => {
+                let mut members = self.node.members().clone();
+                let changed = match change {
+                    Change::Add(id, address) => {
+                        members.insert(id, address.clone()) != Some(address)
+                    }
+                    Change::Remove(id) => members.remove(&id).is_some(),
+                };
+                if self.node.role() != Role::Leader {
+                    let _ = reply.send(Err(self.not_leader()));
+                } else if !changed {
+                    let _ = reply.send(Ok(Response::Ok));
+                } else if self.node.changing_members() {
+                    let _ = reply.send(Err(Error::MembershipChanging));
+                } else if members.is_empty() {
+                    let message = "clusters keep a member at least";
+                    let err = io::Error::new(ErrorKind::InvalidInput, message);
+                    let _ = reply.send(Err(err.into()));
+                } else {
+                    self.propose(Command::Members(members), reply)?;
+                }
+            }
+            None => {}
+        }
+        self.progress()
+    }
+
+    fn propose(&mut self, command: Command, reply: Sender<Result<Response>>) -> Result<()> {
+        match self.node.propose(command)? {
+            Some(index) => {
+                self.pending.insert(index, (self.node.term(), reply));
+            }
+            None => {
+                let _ = reply.send(Err(self.not_leader()));
+            }
+        }
+        Ok(())
+    }
+
+    fn not_leader(&self) -> Error {
+        let leader = self
+            .node
+            .leader()
+            .filter(|&leader| leader != self.node.id());
+        let leader = leader.and_then(|leader| {
+            let address = self
+                .node
+                .members()
+                .get(&leader)
+                .or_else(|| self.addresses.get(&leader));
+            address.map(|address| (leader, address.clone()))
+        });
+        Error::NotLeader { leader }
+    }
+
+    /// Sends the messages of the node, carries out the entries committed
+    /// and takes a snapshot if due.
+    fn progress(&mut self) -> Result<()> {
+        for (to, message) in self.node.take_messages() {
+            let address = match self
+                .node
+                .members()
+                .get(&to)
+                .or_else(|| self.addresses.get(&to))
+            {
+                Some(address) => address.clone(),
+                None => continue,
+            };
+            self.send(to, address, message);
+        }
+        if self.node.take_installed().is_some() {
+            restore(&self.db, &self.node.snapshot_data()?)?;
+        }
+        for entry in self.node.take_committed() {
+            let response = match &entry.command {
+                Command::Request(payload) => match decode_all::<Request>(payload) {
+                    Ok(request) => server::execute(&self.db, request)
+                        .unwrap_or_else(|err| Response::Error(err.to_string())),
+                    Err(err) => Response::Error(err.to_string()),
+                },
+                Command::Noop | Command::Members(_) => Response::Ok,
+            };
+            if let Some((term, reply)) = self.pending.remove(&entry.index) {
+                let result = match term == entry.term {
+                    true => Ok(response),
+                    false => Err(self.not_leader()),
+                };
+                let _ = reply.send(result);
+            }
+        }
+        // replaced by other entries, or covered by a snapshot installed
+        let pending = self.pending.split_off(&(self.node.applied() + 1));
+        for (_, (_, reply)) in std::mem::replace(&mut self.pending, pending) {
+            let _ = reply.send(Err(self.not_leader()));
+        }
+        if self.node.applied() - self.node.snapshot().index >= self.snapshot_entries {
+            let data = snapshot(&self.db)?;
+            self.node.compact(&data)?;
+        }
+        *self.shared.status.lock().unwrap() = status(&self.node);
+        Ok(())
+    }
+
+    /// Queues `message` for node `to`, reached at `address`.
+    fn send(&mut self, to: NodeId, address: String, message: Message) {
+        if self
+            .peers
+            .get(&to)
+            .is_none_or(|peer| peer.address != address)
+        {
+            let (queue, queued) = mpsc::sync_channel(PEER_QUEUE);
+            let target = address.clone();
+            let thread = thread::Builder::new()
+                .name(format!("palladiumdb-raft-peer-{}", to))
+                .spawn(move || deliver(&target, queued));
+            let thread = match thread {
+                Ok(thread) => thread,
+                Err(_) => return,
+            };
+            if let Some(previous) = self.peers.insert(
+                to,
+                Peer {
+                    address,
+                    queue,
+                    thread,
+                },
+            ) {
+                drop(previous.queue);
+                let _ = previous.thread.join();
+            }
+        }
+        // dropped if the node lags, as lost by the network
+        let _ = self.peers[&to].queue.try_send(message);
+    }
+}
+
+/// The connection of a node to another.
+struct Peer {
+    address: String,
+    queue: SyncSender<Message>,
+    thread: JoinHandle<()>,
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(ErrorKind::NotFound, "address resolves to nothing");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(err) => last = err,
+        }
+    }
+    Err(last)
+}
+
+/// Sends the messages `queued` to the node at `address`, connecting again
+/// when the connection fails, until the queue is dropped. Messages that
+/// cannot be sent are dropped.
+fn deliver(address: &str, queued: Receiver<Message>) {
+    let mut out: Option<BufWriter<TcpStream>> = None;
+    let mut failed: Option<Instant> = None;
+    let mut payload = Vec::new();
+    for message in queued.iter() {
+        if out.is_none() {
+            if failed.is_some_and(|failed| failed.elapsed() < CONNECT_TIMEOUT) {
+                continue;
+            }
+            match connect(address) {
+                Ok(stream) => out = Some(BufWriter::new(stream)),
+                Err(_) => {
+                    failed = Some(Instant::now());
+                    continue;
+                }
+            }
+        }
+        payload.clear();
+        message.encode(&mut payload);
+        if let Some(writer) = &mut out {
+            let written = protocol::write_frame(writer, &payload).and_then(|_| writer.flush());
+            if written.is_err() {
+                out = None;
+            }
+        }
+    }
+}
+
+/// Hands the messages of the nodes connecting to `listener` to `inputs`
+/// until stopped.
+fn accept(listener: TcpListener, inputs: &Sender<Input>, shared: &Arc<Shared>) {
+    let mut readers = Vec::new();
+    while !shared.stopped.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let connection = shared.next_connection.fetch_add(1, Ordering::SeqCst);
+        let registered = stream.try_clone().map(|clone| {
+            shared.connections.lock().unwrap().insert(connection, clone);
+        });
+        let (inputs, reading) = (inputs.clone(), shared.clone());
+        let spawned = registered.and_then(|_| {
+            thread::Builder::new()
+                .name(format!("palladiumdb-raft-connection-{}", connection))
+                .spawn(move || {
+                    let _ = read(&stream, &inputs);
+                    reading.connections.lock().unwrap().remove(&connection);
+                })
+        });
+        match spawned {
+            Ok(reader) => readers.push(reader),
+            Err(_) => {
+                shared.connections.lock().unwrap().remove(&connection);
+            }
+        }
+        readers.retain(|reader| !reader.is_finished());
+    }
+    for stream in shared.connections.lock().unwrap().values() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+}
+
+/// Hands the messages read from `stream` to `inputs` until it closes.
+fn read(stream: &TcpStream, inputs: &Sender<Input>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut input = BufReader::new(stream);
+    while let Some(payload) = protocol::read_frame(&mut input)? {
+        let message = decode_all::<Message>(&payload)?;
+        if inputs.send(Input::Message(message)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Raft, RaftBuilder, Role};
+    use crate::db::Database;
+    use crate::protocol::{Request, Response};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn start(dir: &Path, id: u64, address: &str, bootstrap: bool) -> (Raft, Arc<Database>) {
+        let db = Arc::new(Database::new());
+        let node = RaftBuilder::new()
+            .bind_address(address)
+            .bootstrap(bootstrap)
+            .heartbeat_interval(Duration::from_millis(10))
+            .election_timeout(Duration::from_millis(100))
+            .snapshot_entries(8)
+            .start(id, dir.join(id.to_string()), db.clone())
+            .unwrap();
+        (node, db)
+    }
+
+    fn leader<'a>(nodes: &[&'a Raft]) -> &'a Raft {
+        let started = Instant::now();
+        loop {
+            let leaders: Vec<_> = nodes
+                .iter()
+                .filter(|node| node.status().role == Role::Leader)
+                .collect();
+            if let [leader] = leaders[..] {
+                return leader;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "no leader");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn put(key: &str, value: &str) -> Request {
+        Request::Put {
+            keyspace: "users".to_string(),
+            key: key.as_bytes().to_vec(),
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    fn get(key: &str) -> Request {
+        Request::Get {
+            keyspace: "users".to_string(),
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_cluster_grows_fails_over_and_restarts() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-raft-cluster-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (first, _) = start(&dir, 1, "127.0.0.1:0", true);
+        leader(&[&first]);
+        for key in 0..10 {
+            let response = first.execute(put(&key.to_string(), "a")).unwrap();
+            assert_eq!(response, Response::Ok);
+        }
+        assert!(first.status().snapshot_index > 0);
+
+        // added nodes are sent the snapshot, then the log
+        let (second, second_db) = start(&dir, 2, "127.0.0.1:0", false);
+        let (third, _) = start(&dir, 3, "127.0.0.1:0", false);
+        first.add_node(2, &second.local_addr().to_string()).unwrap();
+        first.add_node(3, &third.local_addr().to_string()).unwrap();
+        first.execute(put("0", "b")).unwrap();
+        let users = second_db.open_map::<Vec<u8>, Vec<u8>>("users").unwrap();
+        let started = Instant::now();
+        while users.get(&b"0".to_vec()) != Some(b"b".to_vec()) {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(users.len(), 10);
+        assert_eq!(second.status().members.len(), 3);
+        match second.execute(get("0")) {
+            Err(Error::NotLeader {
+                leader: Some((1, address)),
+            }) => {
+                assert_eq!(address, first.local_addr().to_string());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // another leader takes over once the leader stops
+        drop(first);
+        let next = leader(&[&second, &third]);
+        assert_eq!(
+            next.execute(get("0")).unwrap(),
+            Response::Value(Some(b"b".to_vec()))
+        );
+        next.execute(put("1", "c")).unwrap();
+        next.remove_node(1).unwrap();
+        assert_eq!(next.status().members.len(), 2);
+
+        // restarted nodes rebuild their database from the snapshot and log
+        let addresses = [second.local_addr(), third.local_addr()];
+        drop((second, third, users));
+        let (second, _) = start(&dir, 2, &addresses[0].to_string(), false);
+        let (third, _) = start(&dir, 3, &addresses[1].to_string(), true);
+        let next = leader(&[&second, &third]);
+        assert_eq!(
+            next.execute(get("1")).unwrap(),
+            Response::Value(Some(b"c".to_vec()))
+        );
+        assert_eq!(
+            next.execute(get("9")).unwrap(),
+            Response::Value(Some(b"a".to_vec()))
+        );
+        drop((second, third));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,859 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use super::message::{Body, Message};
+use super::storage::{Command, Entry, Members, SnapshotMeta, Storage};
+use super::{NodeId, Role};
+use crate::collections::utils::random;
+
+/// Entries are sent to followers in messages of about that many bytes.
+const MAX_APPEND_BYTES: usize = 1 << 20;
+
+/// How the leader replicates its log to a follower.
+#[derive(Debug)]
+struct Progress {
+    /// The index of the next entry to send.
+    next: u64,
+    /// The index of the last entry the follower is known to hold.
+    matched: u64,
+    /// Whether the follower answered since the leader last checked.
+    active: bool,
+    /// Ticks since a snapshot was sent, `None` if none is in flight.
+    snapshot_sent: Option<u32>,
+}
+
+/// The consensus of a node, driven by the messages it receives and the
+/// ticks of its clock, and handing out the messages to send and the
+/// entries committed. The log, term and vote are kept in its [`Storage`].
+pub(super) struct Node {
+    id: NodeId,
+    address: String,
+    storage: Storage,
+    role: Role,
+    leader: Option<NodeId>,
+    /// The members as of the last entry of the log, and the index of the
+    /// entry that made them.
+    members: Members,
+    members_index: u64,
+    commit: u64,
+    applied: u64,
+    votes: BTreeSet<NodeId>,
+    progress: BTreeMap<NodeId, Progress>,
+    /// Ticks since the leader was heard from, or since the leader checked
+    /// that a quorum of followers answers.
+    elapsed: u32,
+    /// Ticks without hearing from a leader after which a follower stands
+    /// for election, picked at random once in every term.
+    timeout: u32,
+    heartbeat_elapsed: u32,
+    election_ticks: u32,
+    heartbeat_ticks: u32,
+    outbox: Vec<(NodeId, Message)>,
+    /// The index of a snapshot installed but not yet applied.
+    installed: Option<u64>,
+}
+
+impl Node {
+    /// Returns a follower with the state of `storage`, reached at
+    /// `address`, standing for election after `election_ticks` to twice as
+    /// many ticks without hearing from a leader, which sends heartbeats
+    /// every `heartbeat_ticks`.
+    pub(super) fn new(
+        id: NodeId,
+        address: String,
+        storage: Storage,
+        election_ticks: u32,
+        heartbeat_ticks: u32,
+    ) -> Node {
+        let (members_index, members) = storage.members(storage.last_index());
+        let snapshot = storage.snapshot().index;
+        let mut node = Node {
+            id,
+            address,
+            storage,
+            role: Role::Follower,
+            leader: None,
+            members,
+            members_index,
+            commit: snapshot,
+            applied: snapshot,
+            votes: BTreeSet::new(),
+            progress: BTreeMap::new(),
+            elapsed: 0,
+            timeout: 0,
+            heartbeat_elapsed: 0,
+            election_ticks: election_ticks.max(1),
+            heartbeat_ticks: heartbeat_ticks.max(1),
+            outbox: Vec::new(),
+            installed: None,
+        };
+        node.reset_timeout();
+        node
+    }
+
+    /// Makes the node the only member of a new cluster, if its log is
+    /// empty.
+    pub(super) fn bootstrap(&mut self) -> io::Result<()> {
+        if self.storage.last_index() > 0 || self.storage.term() > 0 {
+            return Ok(());
+        }
+        self.storage.set_state(1, None)?;
+        let members: Members = vec![(self.id, self.address.clone())].into_iter().collect();
+        let entry = Entry {
+            index: 1,
+            term: 1,
+            command: Command::Members(members),
+        };
+        self.storage.append(&[entry])?;
+        self.update_members();
+        Ok(())
+    }
+
+    pub(super) fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub(super) fn role(&self) -> Role {
+        self.role
+    }
+
+    pub(super) fn term(&self) -> u64 {
+        self.storage.term()
+    }
+
+    pub(super) fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub(super) fn members(&self) -> &Members {
+        &self.members
+    }
+
+    pub(super) fn commit(&self) -> u64 {
+        self.commit
+    }
+
+    pub(super) fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub(super) fn snapshot(&self) -> &SnapshotMeta {
+        self.storage.snapshot()
+    }
+
+    /// Returns whether a change of the members is not committed yet, or
+    /// the leader has not committed an entry of its term, before which it
+    /// may not know of one.
+    pub(super) fn changing_members(&self) -> bool {
+        self.members_index > self.commit
+            || self.storage.term_at(self.commit) != Some(self.storage.term())
+    }
+
+    /// Advances the clock of the node by a tick.
+    pub(super) fn tick(&mut self) -> io::Result<()> {
+        self.elapsed += 1;
+        if self.role != Role::Leader {
+            // nodes not members, joining or removed, wait for the leader
+            if self.elapsed >= self.timeout && self.members.contains_key(&self.id) {
+                self.campaign()?;
+            }
+            return Ok(());
+        }
+        for progress in self.progress.values_mut() {
+            if let Some(ticks) = &mut progress.snapshot_sent {
+                *ticks += 1;
+            }
+        }
+        if self.elapsed >= self.election_ticks {
+            self.elapsed = 0;
+            // leaders cut off from a quorum step down, letting it elect
+            // another
+            let active: BTreeSet<_> = self
+                .progress
+                .iter_mut()
+                .filter(|(_, progress)| progress.active)
+                .map(|(&id, progress)| {
+                    progress.active = false;
+                    id
+                })
+                .chain(Some(self.id))
+                .collect();
+            if !self.has_quorum(&active) {
+                let term = self.storage.term();
+                return self.become_follower(term, None);
+            }
+        }
+        self.heartbeat_elapsed += 1;
+        if self.heartbeat_elapsed >= self.heartbeat_ticks {
+            self.heartbeat_elapsed = 0;
+            self.broadcast()?;
+        }
+        Ok(())
+    }
+
+    /// Handles `message`, received from another node.
+    pub(super) fn step(&mut self, message: Message) -> io::Result<()> {
+        let term = self.storage.term();
+        if message.term > term {
+            // nodes hearing from their leader disregard candidates, such as
+            // removed members campaigning
+            let heard = self.leader.is_some() && self.elapsed < self.election_ticks;
+            if let Body::Vote { .. } = message.body {
+                if heard {
+                    return Ok(());
+                }
+            }
+            let leader = match message.body {
+                Body::Append { .. } | Body::Snapshot { .. } => Some(message.from),
+                _ => None,
+            };
+            self.become_follower(message.term, leader)?;
+        } else if message.term < term {
+            // tells stale leaders and candidates of the term
+            match message.body {
+                Body::Append { .. } | Body::Snapshot { .. } => {
+                    let index = self.storage.last_index();
+                    self.send(
+                        message.from,
+                        Body::AppendReply {
+                            success: false,
+                            index,
+                        },
+                    );
+                }
+                Body::Vote { .. } => self.send(message.from, Body::VoteReply { granted: false }),
+                _ => {}
+            }
+            return Ok(());
+        }
+        let from = message.from;
+        match message.body {
+            Body::Vote {
+                last_index,
+                last_term,
+            } => self.on_vote(from, last_index, last_term),
+            Body::VoteReply { granted } => self.on_vote_reply(from, granted),
+            Body::Append {
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => self.on_append(from, prev_index, prev_term, entries, commit),
+            Body::AppendReply { success, index } => self.on_append_reply(from, success, index),
+            Body::Snapshot { meta, data } => self.on_snapshot(from, meta, &data),
+            Body::SnapshotReply { index } => self.on_snapshot_reply(from, index),
+        }
+    }
+
+    /// Appends `command` to the log, if the node leads the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The index of the entry appended, `None` if the node is not the
+    /// leader.
+    pub(super) fn propose(&mut self, command: Command) -> io::Result<Option<u64>> {
+        if self.role != Role::Leader {
+            return Ok(None);
+        }
+        let index = self.append(command)?;
+        self.broadcast()?;
+        Ok(Some(index))
+    }
+
+    /// Takes the messages to send, with the node to send them to.
+    pub(super) fn take_messages(&mut self) -> Vec<(NodeId, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Takes the index of the snapshot installed since last called, to
+    /// replace the database by.
+    pub(super) fn take_installed(&mut self) -> Option<u64> {
+        self.installed.take()
+    }
+
+    /// Takes the entries committed since last called, to apply to the
+    /// database in order.
+    pub(super) fn take_committed(&mut self) -> Vec<Entry> {
+        let committed: Vec<Entry> = (self.applied + 1..=self.commit)
+            .filter_map(|index| self.storage.entry(index).cloned())
+            .collect();
+        self.applied = self.commit;
+        committed
+    }
+
+    /// Reads the database of the last snapshot.
+    pub(super) fn snapshot_data(&self) -> io::Result<Vec<u8>> {
+        self.storage.snapshot_data()
+    }
+
+    /// Replaces the snapshot by `data`, the database with the entries
+    /// applied, removing them from the log.
+    pub(super) fn compact(&mut self, data: &[u8]) -> io::Result<()> {
+        self.storage.compact(self.applied, data)
+    }
+
+    fn reset_timeout(&mut self) {
+        self.timeout = self.election_ticks + (random() % u64::from(self.election_ticks)) as u32;
+    }
+
+    fn update_members(&mut self) {
+        let (index, members) = self.storage.members(self.storage.last_index());
+        self.members_index = index;
+        self.members = members;
+    }
+
+    /// Returns whether `nodes` are a majority of the members.
+    fn has_quorum(&self, nodes: &BTreeSet<NodeId>) -> bool {
+        let members = self.members.keys().filter(|id| nodes.contains(id)).count();
+        members * 2 > self.members.len()
+    }
+
+    fn send(&mut self, to: NodeId, body: Body) {
+        let message = Message {
+            from: self.id,
+            address: self.address.clone(),
+            term: self.storage.term(),
+            body,
+        };
+        self.outbox.push((to, message));
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) -> io::Result<()> {
+        if term > self.storage.term() {
+            self.storage.set_state(term, None)?;
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.elapsed = 0;
+        self.votes.clear();
+        self.progress.clear();
+        self.reset_timeout();
+        Ok(())
+    }
+
+    fn campaign(&mut self) -> io::Result<()> {
+        let term = self.storage.term() + 1;
+        self.storage.set_state(term, Some(self.id))?;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.elapsed = 0;
+        self.reset_timeout();
+        self.votes = Some(self.id).into_iter().collect();
+        if self.has_quorum(&self.votes) {
+            return self.become_leader();
+        }
+        let (last_index, last_term) = (self.storage.last_index(), self.storage.last_term());
+        let peers: Vec<_> = self
+            .members
+            .keys()
+            .copied()
+            .filter(|&id| id != self.id)
+            .collect();
+        for peer in peers {
+            self.send(
+                peer,
+                Body::Vote {
+                    last_index,
+                    last_term,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> io::Result<()> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.elapsed = 0;
+        self.heartbeat_elapsed = 0;
+        let next = self.storage.last_index() + 1;
+        self.progress = self
+            .members
+            .keys()
+            .filter(|&&id| id != self.id)
+            .map(|&id| {
+                let progress = Progress {
+                    next,
+                    matched: 0,
+                    active: true,
+                    snapshot_sent: None,
+                };
+                (id, progress)
+            })
+            .collect();
+        // commits the entries of the terms before
+        self.append(Command::Noop)?;
+        self.broadcast()
+    }
+
+    /// Appends `command` to the log of the leader.
+    fn append(&mut self, command: Command) -> io::Result<u64> {
+        let index = self.storage.last_index() + 1;
+        let changes_members = matches!(command, Command::Members(_));
+        let entry = Entry {
+            index,
+            term: self.storage.term(),
+            command,
+        };
+        self.storage.append(&[entry])?;
+        if changes_members {
+            self.update_members();
+            let members = &self.members;
+            self.progress.retain(|id, _| members.contains_key(id));
+            for &id in members.keys() {
+                if id != self.id {
+                    self.progress.entry(id).or_insert(Progress {
+                        next: index,
+                        matched: 0,
+                        active: true,
+                        snapshot_sent: None,
+                    });
+                }
+            }
+        }
+        self.advance_commit()?;
+        Ok(index)
+    }
+
+    fn broadcast(&mut self) -> io::Result<()> {
+        let peers: Vec<_> = self.progress.keys().copied().collect();
+        for peer in peers {
+            self.send_append(peer)?;
+        }
+        Ok(())
+    }
+
+    /// Sends `peer` the entries it lacks, or the snapshot if they were
+    /// compacted into it.
+    fn send_append(&mut self, peer: NodeId) -> io::Result<()> {
+        let snapshot = self.storage.snapshot().clone();
+        let election_ticks = self.election_ticks;
+        let progress = match self.progress.get_mut(&peer) {
+            Some(progress) => progress,
+            None => return Ok(()),
+        };
+        if progress.next <= snapshot.index {
+            // sent again only if lost
+            if progress
+                .snapshot_sent
+                .is_some_and(|ticks| ticks < election_ticks)
+            {
+                return Ok(());
+            }
+            progress.snapshot_sent = Some(0);
+            let data = self.storage.snapshot_data()?;
+            self.send(
+                peer,
+                Body::Snapshot {
+                    meta: snapshot,
+                    data,
+                },
+            );
+            return Ok(());
+        }
+        let prev_index = progress.next - 1;
+        let entries = self.storage.entries(progress.next, MAX_APPEND_BYTES);
+        if let Some(last) = entries.last() {
+            progress.next = last.index + 1;
+        }
+        let body = Body::Append {
+            prev_index,
+            prev_term: self.storage.term_at(prev_index).unwrap_or(0),
+            entries,
+            commit: self.commit,
+        };
+        self.send(peer, body);
+        Ok(())
+    }
+
+    /// Commits the entries of the term of the leader a majority holds.
+    fn advance_commit(&mut self) -> io::Result<()> {
+        if self.members.is_empty() {
+            return Ok(());
+        }
+        let last_index = self.storage.last_index();
+        let mut matched: Vec<u64> = self
+            .members
+            .keys()
+            .map(|id| match self.progress.get(id) {
+                Some(progress) => progress.matched,
+                None if *id == self.id => last_index,
+                None => 0,
+            })
+            .collect();
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let index = matched[self.members.len() / 2];
+        if index <= self.commit || self.storage.term_at(index) != Some(self.storage.term()) {
+            return Ok(());
+        }
+        self.commit = index;
+        // leaders removed from the cluster step down once it is committed
+        if !self.members.contains_key(&self.id) && self.members_index <= self.commit {
+            self.broadcast()?;
+            let term = self.storage.term();
+            self.become_follower(term, None)?;
+        }
+        Ok(())
+    }
+
+    fn on_vote(&mut self, from: NodeId, last_index: u64, last_term: u64) -> io::Result<()> {
+        let log = (self.storage.last_term(), self.storage.last_index());
+        let granted = (last_term, last_index) >= log
+            && self.storage.voted_for().is_none_or(|voted| voted == from);
+        if granted {
+            if self.storage.voted_for().is_none() {
+                let term = self.storage.term();
+                self.storage.set_state(term, Some(from))?;
+            }
+            self.elapsed = 0;
+        }
+        self.send(from, Body::VoteReply { granted });
+        Ok(())
+    }
+
+    fn on_vote_reply(&mut self, from: NodeId, granted: bool) -> io::Result<()> {
+        if self.role != Role::Candidate || !granted {
+            return Ok(());
+        }
+        self.votes.insert(from);
+        match self.has_quorum(&self.votes) {
+            true => self.become_leader(),
+            false => Ok(()),
+        }
+    }
+
+    fn on_append(
+        &mut self,
+        from: NodeId,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    ) -> io::Result<()> {
+        self.role = Role::Follower;
+        self.leader = Some(from);
+        self.elapsed = 0;
+        let last_index = self.storage.last_index();
+        let snapshot = self.storage.snapshot().index;
+        if prev_index > last_index {
+            self.send(
+                from,
+                Body::AppendReply {
+                    success: false,
+                    index: last_index,
+                },
+            );
+            return Ok(());
+        }
+        // entries compacted into the snapshot are committed, matching those
+        // of any leader
+        if prev_index > snapshot && self.storage.term_at(prev_index) != Some(prev_term) {
+            let conflicting = self.storage.term_at(prev_index);
+            let mut index = prev_index - 1;
+            while index > self.commit.max(snapshot) && self.storage.term_at(index) == conflicting {
+                index -= 1;
+            }
+            self.send(
+                from,
+                Body::AppendReply {
+                    success: false,
+                    index,
+                },
+            );
+            return Ok(());
+        }
+        let last_new = prev_index + entries.len() as u64;
+        let mut appended = Vec::new();
+        let mut changed = false;
+        for entry in entries {
+            if entry.index <= snapshot {
+                continue;
+            }
+            if appended.is_empty() {
+                match self.storage.term_at(entry.index) {
+                    Some(term) if term == entry.term => continue,
+                    Some(_) => {
+                        self.storage.truncate(entry.index)?;
+                        changed = true;
+                    }
+                    None => {}
+                }
+            }
+            changed |= matches!(entry.command, Command::Members(_));
+            appended.push(entry);
+        }
+        if !appended.is_empty() {
+            self.storage.append(&appended)?;
+        }
+        if changed {
+            self.update_members();
+        }
+        if commit > self.commit {
+            self.commit = commit.min(last_new).max(self.commit);
+        }
+        self.send(
+            from,
+            Body::AppendReply {
+                success: true,
+                index: last_new,
+            },
+        );
+        Ok(())
+    }
+
+    fn on_append_reply(&mut self, from: NodeId, success: bool, index: u64) -> io::Result<()> {
+        if self.role != Role::Leader {
+            return Ok(());
+        }
+        let last_index = self.storage.last_index();
+        let progress = match self.progress.get_mut(&from) {
+            Some(progress) => progress,
+            None => return Ok(()),
+        };
+        progress.active = true;
+        if success {
+            progress.matched = progress.matched.max(index);
+            progress.next = progress.next.max(index + 1);
+            let behind = progress.next <= last_index;
+            self.advance_commit()?;
+            if behind {
+                self.send_append(from)?;
+            }
+        } else {
+            progress.next = (index + 1).max(progress.matched + 1);
+            self.send_append(from)?;
+        }
+        Ok(())
+    }
+
+    fn on_snapshot(&mut self, from: NodeId, meta: SnapshotMeta, data: &[u8]) -> io::Result<()> {
+        self.role = Role::Follower;
+        self.leader = Some(from);
+        self.elapsed = 0;
+        if meta.index > self.commit {
+            let index = meta.index;
+            self.storage.install(meta, data)?;
+            self.commit = index;
+            self.applied = index;
+            self.installed = Some(index);
+            self.update_members();
+        }
+        let index = self.commit;
+        self.send(from, Body::SnapshotReply { index });
+        Ok(())
+    }
+
+    fn on_snapshot_reply(&mut self, from: NodeId, index: u64) -> io::Result<()> {
+        if self.role != Role::Leader {
+            return Ok(());
+        }
+        let last_index = self.storage.last_index();
+        let progress = match self.progress.get_mut(&from) {
+            Some(progress) => progress,
+            None => return Ok(()),
+        };
+        progress.active = true;
+        progress.snapshot_sent = None;
+        progress.matched = progress.matched.max(index);
+        progress.next = progress.next.max(index + 1);
+        let behind = progress.next <= last_index;
+        self.advance_commit()?;
+        if behind {
+            self.send_append(from)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Node, Role};
+    use crate::cluster::raft::storage::Storage;
+    use crate::cluster::raft::NodeId;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::path::Path;
+
+    /// Nodes exchanging messages in memory, but with the nodes cut off.
+    struct Cluster {
+        nodes: BTreeMap<NodeId, Node>,
+        down: BTreeSet<NodeId>,
+        applied: BTreeMap<NodeId, Vec<Vec<u8>>>,
+    }
+
+    impl Cluster {
+        fn add(&mut self, dir: &Path, id: NodeId) {
+            let storage = Storage::open(&dir.join(id.to_string())).unwrap();
+            let node = Node::new(id, format!("node-{}", id), storage, 10, 1);
+            self.nodes.insert(id, node);
+            self.applied.insert(id, Vec::new());
+        }
+
+        /// Ticks every node up, then delivers messages until none is left.
+        fn run(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                for (id, node) in &mut self.nodes {
+                    if !self.down.contains(id) {
+                        node.tick().unwrap();
+                    }
+                }
+                self.deliver();
+            }
+        }
+
+        fn deliver(&mut self) {
+            loop {
+                let mut messages = Vec::new();
+                for (id, node) in &mut self.nodes {
+                    let sent = node.take_messages();
+                    if !self.down.contains(id) {
+                        messages.extend(sent);
+                    }
+                    if node.take_installed().is_some() {
+                        self.applied.get_mut(id).unwrap().clear();
+                    }
+                    for entry in node.take_committed() {
+                        if let Command::Request(request) = entry.command {
+                            self.applied.get_mut(id).unwrap().push(request);
+                        }
+                    }
+                }
+                if messages.is_empty() {
+                    return;
+                }
+                for (to, message) in messages {
+                    if let (Some(node), false) = (self.nodes.get_mut(&to), self.down.contains(&to))
+                    {
+                        node.step(message).unwrap();
+                    }
+                }
+            }
+        }
+
+        fn leader(&self) -> Option<NodeId> {
+            let leaders: Vec<_> = self
+                .nodes
+                .iter()
+                .filter(|(id, node)| node.role() == Role::Leader && !self.down.contains(id))
+                .map(|(id, _)| *id)
+                .collect();
+            match leaders[..] {
+                [leader] => Some(leader),
+                _ => None,
+            }
+        }
+
+        fn elect(&mut self) -> NodeId {
+            for _ in 0..100 {
+                self.run(1);
+                if let Some(leader) = self.leader() {
+                    return leader;
+                }
+            }
+            panic!("no leader elected");
+        }
+
+        fn propose(&mut self, leader: NodeId, request: &[u8]) {
+            let node = self.nodes.get_mut(&leader).unwrap();
+            assert!(node
+                .propose(Command::Request(request.to_vec()))
+                .unwrap()
+                .is_some());
+            self.deliver();
+        }
+
+        fn change(&mut self, leader: NodeId, id: NodeId, add: bool) {
+            let node = self.nodes.get_mut(&leader).unwrap();
+            assert!(!node.changing_members());
+            let mut members = node.members().clone();
+            match add {
+                true => members.insert(id, format!("node-{}", id)),
+                false => members.remove(&id),
+            };
+            node.propose(Command::Members(members)).unwrap().unwrap();
+            self.deliver();
+            self.run(1);
+        }
+    }
+
+    #[test]
+    fn test_nodes_elect_a_leader_replicate_and_change_members() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-raft-node-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cluster = Cluster {
+            nodes: BTreeMap::new(),
+            down: BTreeSet::new(),
+            applied: BTreeMap::new(),
+        };
+        for id in 1..=3 {
+            cluster.add(&dir, id);
+        }
+        // nodes not members wait for a leader to add them
+        cluster.run(50);
+        assert_eq!(cluster.leader(), None);
+        cluster.nodes.get_mut(&1).unwrap().bootstrap().unwrap();
+        assert_eq!(cluster.elect(), 1);
+        cluster.propose(1, b"a");
+        assert_eq!(cluster.applied[&1], vec![b"a".to_vec()]);
+        cluster.change(1, 2, true);
+        cluster.change(1, 3, true);
+        cluster.propose(1, b"b");
+        cluster.run(2);
+        for id in 1..=3 {
+            assert_eq!(cluster.applied[&id], vec![b"a".to_vec(), b"b".to_vec()]);
+            assert_eq!(cluster.nodes[&id].members().len(), 3);
+        }
+
+        // another leader is elected once the leader is cut off, whose
+        // entries not committed are overwritten when it comes back
+        cluster.down.insert(1);
+        let term = cluster.nodes[&1].term();
+        let node = cluster.nodes.get_mut(&1).unwrap();
+        node.propose(Command::Request(b"lost".to_vec())).unwrap();
+        let leader = cluster.elect();
+        assert_ne!(leader, 1);
+        assert!(cluster.nodes[&leader].term() > term);
+        cluster.propose(leader, b"c");
+        cluster.run(1);
+        cluster.down.clear();
+        cluster.run(30);
+        assert_eq!(cluster.leader(), Some(leader));
+        for id in 1..=3 {
+            let applied = &cluster.applied[&id];
+            assert_eq!(applied.last(), Some(&b"c".to_vec()));
+            assert!(!applied.contains(&b"lost".to_vec()));
+        }
+
+        // followers too far behind are sent a snapshot
+        let behind = if leader == 2 { 3 } else { 2 };
+        cluster.down.insert(behind);
+        for request in [b"d", b"e", b"f"] {
+            cluster.propose(leader, request);
+        }
+        let node = cluster.nodes.get_mut(&leader).unwrap();
+        node.compact(b"snapshot").unwrap();
+        assert_eq!(node.snapshot().index, node.commit());
+        cluster.down.clear();
+        cluster.run(2);
+        let node = &cluster.nodes[&behind];
+        assert_eq!(node.commit(), cluster.nodes[&leader].commit());
+        assert_eq!(node.snapshot_data().unwrap(), b"snapshot");
+        assert!(cluster.applied[&behind].is_empty());
+        cluster.propose(leader, b"g");
+        cluster.run(1);
+        assert_eq!(cluster.applied[&behind], vec![b"g".to_vec()]);
+
+        // leaders removed step down once the change commits
+        cluster.change(leader, leader, false);
+        assert_eq!(cluster.nodes[&leader].role(), Role::Follower);
+        cluster.run(60);
+        let next = cluster.leader().unwrap();
+        assert_ne!(next, leader);
+        assert_eq!(cluster.nodes[&next].members().len(), 2);
+        cluster.propose(next, b"h");
+        cluster.run(1);
+        assert_eq!(cluster.applied[&behind].last(), Some(&b"h".to_vec()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
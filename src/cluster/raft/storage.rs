@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::NodeId;
+use crate::checksum::crc32_update;
+use crate::codec::{decode_all, decode_bytes, encode_bytes, invalid_data, take, Decode, Encode};
+use crate::files;
+
+/// File holding the current term of a node and the candidate it voted for
+/// in it.
+const STATE_FILE: &str = "STATE";
+/// File holding the entries of the log following the snapshot.
+const LOG_FILE: &str = "LOG";
+/// File holding the last snapshot of the database taken or installed.
+const SNAPSHOT_FILE: &str = "SNAPSHOT";
+
+/// The members of a cluster, by id, with the address they are reached at.
+pub(super) type Members = BTreeMap<NodeId, String>;
+
+const NOOP: u8 = 0;
+const REQUEST: u8 = 1;
+const MEMBERS: u8 = 2;
+
+/// What an entry of the log does once committed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Command {
+    /// Appended by leaders as they are elected, committing the entries of
+    /// the terms before.
+    Noop,
+    /// Carries out an encoded [`Request`](crate::protocol::Request) on the
+    /// database.
+    Request(Vec<u8>),
+    /// Makes the members of the cluster those of the map, from the moment
+    /// it is appended.
+    Members(Members),
+}
+
+/// An entry of the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Entry {
+    pub(super) index: u64,
+    pub(super) term: u64,
+    pub(super) command: Command,
+}
+
+impl Entry {
+    /// Returns roughly how many bytes the entry takes in a message.
+    fn size(&self) -> usize {
+        match &self.command {
+            Command::Noop => 16,
+            Command::Request(request) => 16 + request.len(),
+            Command::Members(members) => 16 + members.values().map(|a| 8 + a.len()).sum::<usize>(),
+        }
+    }
+}
+
+fn encode_members(members: &Members, buf: &mut Vec<u8>) {
+    members.len().encode(buf);
+    for (id, address) in members {
+        id.encode(buf);
+        address.encode(buf);
+    }
+}
+
+fn decode_members(input: &mut &[u8]) -> io::Result<Members> {
+    let len = usize::decode(input)?;
+    let mut members = Members::new();
+    for _ in 0..len {
+        members.insert(NodeId::decode(input)?, String::decode(input)?);
+    }
+    Ok(members)
+}
+
+impl Encode for Entry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.index.encode(buf);
+        self.term.encode(buf);
+        match &self.command {
+            Command::Noop => buf.push(NOOP),
+            Command::Request(request) => {
+                buf.push(REQUEST);
+                encode_bytes(request, buf);
+            }
+            Command::Members(members) => {
+                buf.push(MEMBERS);
+                encode_members(members, buf);
+            }
+        }
+    }
+}
+
+impl Decode for Entry {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let index = u64::decode(input)?;
+        let term = u64::decode(input)?;
+        let command = match u8::decode(input)? {
+            NOOP => Command::Noop,
+            REQUEST => Command::Request(decode_bytes(input)?.to_vec()),
+            MEMBERS => Command::Members(decode_members(input)?),
+            _ => return Err(invalid_data("unknown raft command")),
+        };
+        Ok(Entry {
+            index,
+            term,
+            command,
+        })
+    }
+}
+
+/// What a snapshot of the database covers: the entries of the log up to
+/// `index`, of term `term`, after which the cluster had `members`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(super) struct SnapshotMeta {
+    pub(super) index: u64,
+    pub(super) term: u64,
+    pub(super) members: Members,
+}
+
+impl Encode for SnapshotMeta {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.index.encode(buf);
+        self.term.encode(buf);
+        encode_members(&self.members, buf);
+    }
+}
+
+impl Decode for SnapshotMeta {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(SnapshotMeta {
+            index: u64::decode(input)?,
+            term: u64::decode(input)?,
+            members: decode_members(input)?,
+        })
+    }
+}
+
+/// Appends `payload` to `buf`, prefixed by its length and checksum.
+fn encode_record(payload: &[u8], buf: &mut Vec<u8>) {
+    (payload.len() as u32).encode(buf);
+    crc32_update(0, payload).encode(buf);
+    buf.extend_from_slice(payload);
+}
+
+/// Splits a record written by [`encode_record`] off `input`.
+///
+/// # Returns
+///
+/// An error of kind `UnexpectedEof` if the record is cut short,
+/// `InvalidData` if its checksum does not match.
+fn decode_record<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = u32::decode(input)? as usize;
+    let crc = u32::decode(input)?;
+    let payload = take(input, len)?;
+    if crc32_update(0, payload) != crc {
+        return Err(invalid_data("raft record checksum mismatch"));
+    }
+    Ok(payload)
+}
+
+/// Writes `payload` as the record of the file `name` of `dir`, replacing it
+/// at once.
+fn write_file(dir: &Path, name: &str, payload: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(payload.len() + 8);
+    encode_record(payload, &mut buf);
+    let temp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&temp)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    files::rename_durably(&temp, dir.join(name))
+}
+
+/// Reads the record of the file `name` of `dir`, `None` if there is none.
+fn read_file(dir: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let bytes = match fs::read(dir.join(name)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut input = &bytes[..];
+    Ok(Some(decode_record(&mut input)?.to_vec()))
+}
+
+/// The state of a node kept on disk: its term and vote, its log, and the
+/// last snapshot of the database, which the log follows.
+pub(super) struct Storage {
+    dir: PathBuf,
+    term: u64,
+    voted_for: Option<NodeId>,
+    snapshot: SnapshotMeta,
+    /// The entries following the snapshot, in order.
+    entries: Vec<Entry>,
+    /// The offset of every entry in the log file.
+    offsets: Vec<u64>,
+    log: File,
+    len: u64,
+}
+
+impl Storage {
+    /// Opens the storage of the node in `dir`, created if missing. A log
+    /// cut short by a crash is truncated after its last whole entry.
+    pub(super) fn open(dir: &Path) -> io::Result<Storage> {
+        fs::create_dir_all(dir)?;
+        let (term, voted_for) = match read_file(dir, STATE_FILE)? {
+            Some(state) => decode_all(&state)?,
+            None => (0, None),
+        };
+        let snapshot = match read_file(dir, SNAPSHOT_FILE)? {
+            Some(snapshot) => SnapshotMeta::decode(&mut &snapshot[..])?,
+            None => SnapshotMeta::default(),
+        };
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+        let mut storage = Storage {
+            dir: dir.to_path_buf(),
+            term,
+            voted_for,
+            snapshot,
+            entries: Vec::new(),
+            offsets: Vec::new(),
+            log,
+            len: 0,
+        };
+        let mut input = &bytes[..];
+        while !input.is_empty() {
+            let entry = match decode_record(&mut input).and_then(decode_all::<Entry>) {
+                Ok(entry) => entry,
+                // torn by a crash while appended
+                Err(_) => break,
+            };
+            let offset = storage.len;
+            storage.len = (bytes.len() - input.len()) as u64;
+            // left over by a crash while compacted
+            if entry.index <= storage.snapshot.index {
+                continue;
+            }
+            if entry.index != storage.last_index() + 1 {
+                return Err(invalid_data("raft log is not contiguous"));
+            }
+            storage.entries.push(entry);
+            storage.offsets.push(offset);
+        }
+        if storage.len < bytes.len() as u64 {
+            storage.log.set_len(storage.len)?;
+        }
+        Ok(storage)
+    }
+
+    /// Returns the current term.
+    pub(super) fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Returns the candidate voted for in the current term, if any.
+    pub(super) fn voted_for(&self) -> Option<NodeId> {
+        self.voted_for
+    }
+
+    /// Records the current term and the candidate voted for in it.
+    pub(super) fn set_state(&mut self, term: u64, voted_for: Option<NodeId>) -> io::Result<()> {
+        let mut state = Vec::new();
+        (term, voted_for).encode(&mut state);
+        write_file(&self.dir, STATE_FILE, &state)?;
+        self.term = term;
+        self.voted_for = voted_for;
+        Ok(())
+    }
+
+    /// Returns what the last snapshot covers.
+    pub(super) fn snapshot(&self) -> &SnapshotMeta {
+        &self.snapshot
+    }
+
+    /// Reads the database of the last snapshot, empty if there is none.
+    pub(super) fn snapshot_data(&self) -> io::Result<Vec<u8>> {
+        let snapshot = match read_file(&self.dir, SNAPSHOT_FILE)? {
+            Some(snapshot) => snapshot,
+            None => return Ok(Vec::new()),
+        };
+        let mut input = &snapshot[..];
+        SnapshotMeta::decode(&mut input)?;
+        Ok(input.to_vec())
+    }
+
+    /// Returns the index of the last entry, that of the snapshot if the log
+    /// holds none.
+    pub(super) fn last_index(&self) -> u64 {
+        self.snapshot.index + self.entries.len() as u64
+    }
+
+    /// Returns the term of the last entry.
+    pub(super) fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot.term, |entry| entry.term)
+    }
+
+    /// Returns the term of the entry at `index`, `None` if it is past the
+    /// log or was compacted into the snapshot.
+    pub(super) fn term_at(&self, index: u64) -> Option<u64> {
+        match index.checked_sub(self.snapshot.index) {
+            Some(0) => Some(self.snapshot.term),
+            Some(offset) => self
+                .entries
+                .get(offset as usize - 1)
+                .map(|entry| entry.term),
+            None => None,
+        }
+    }
+
+    /// Returns the entry at `index`, `None` if it is past the log or was
+    /// compacted into the snapshot.
+    pub(super) fn entry(&self, index: u64) -> Option<&Entry> {
+        match index.checked_sub(self.snapshot.index + 1) {
+            Some(offset) => self.entries.get(offset as usize),
+            None => None,
+        }
+    }
+
+    /// Returns the entries from `index` on, as many as fit in about
+    /// `max_bytes`, one at least if there is any.
+    pub(super) fn entries(&self, index: u64, max_bytes: usize) -> Vec<Entry> {
+        let offset = index.saturating_sub(self.snapshot.index + 1) as usize;
+        let mut bytes = 0;
+        let mut entries = Vec::new();
+        for entry in self.entries.iter().skip(offset) {
+            bytes += entry.size();
+            if bytes > max_bytes && !entries.is_empty() {
+                break;
+            }
+            entries.push(entry.clone());
+        }
+        entries
+    }
+
+    /// Returns the index of the last entry changing the members up to
+    /// `index`, and the members it made, those of the snapshot if none.
+    pub(super) fn members(&self, index: u64) -> (u64, Members) {
+        let offset = index.saturating_sub(self.snapshot.index) as usize;
+        let changed = self.entries[..offset.min(self.entries.len())]
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.command {
+                Command::Members(members) => Some((entry.index, members.clone())),
+                _ => None,
+            });
+        changed.unwrap_or_else(|| (self.snapshot.index, self.snapshot.members.clone()))
+    }
+
+    /// Appends `entries`, which follow the last one, syncing them to disk.
+    pub(super) fn append(&mut self, entries: &[Entry]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut payload = Vec::new();
+        let mut offsets = Vec::with_capacity(entries.len());
+        for entry in entries {
+            offsets.push(self.len + buf.len() as u64);
+            payload.clear();
+            entry.encode(&mut payload);
+            encode_record(&payload, &mut buf);
+        }
+        self.log.write_all(&buf)?;
+        self.log.sync_data()?;
+        self.len += buf.len() as u64;
+        self.entries.extend_from_slice(entries);
+        self.offsets.extend(offsets);
+        Ok(())
+    }
+
+    /// Removes the entries from `index` on, conflicting with those of the
+    /// leader.
+    pub(super) fn truncate(&mut self, index: u64) -> io::Result<()> {
+        let offset = index.saturating_sub(self.snapshot.index + 1) as usize;
+        if offset >= self.entries.len() {
+            return Ok(());
+        }
+        self.len = self.offsets[offset];
+        self.log.set_len(self.len)?;
+        self.log.sync_data()?;
+        self.entries.truncate(offset);
+        self.offsets.truncate(offset);
+        Ok(())
+    }
+
+    /// Replaces the snapshot by `data`, the database once the entries up to
+    /// `index` are applied, removing them from the log.
+    pub(super) fn compact(&mut self, index: u64, data: &[u8]) -> io::Result<()> {
+        let term = match self.term_at(index) {
+            Some(term) if index > self.snapshot.index => term,
+            _ => return Ok(()),
+        };
+        let meta = SnapshotMeta {
+            index,
+            term,
+            members: self.members(index).1,
+        };
+        self.write_snapshot(&meta, data)?;
+        let offset = (index - self.snapshot.index) as usize;
+        let entries = self.entries.split_off(offset);
+        self.snapshot = meta;
+        self.rewrite(entries)
+    }
+
+    /// Replaces the snapshot by `data`, sent by the leader, keeping the
+    /// entries following it if the log holds the last entry it covers.
+    pub(super) fn install(&mut self, meta: SnapshotMeta, data: &[u8]) -> io::Result<()> {
+        self.write_snapshot(&meta, data)?;
+        let entries = match self.term_at(meta.index) {
+            Some(term) if term == meta.term && meta.index >= self.snapshot.index => {
+                let offset = (meta.index - self.snapshot.index) as usize;
+                self.entries.split_off(offset)
+            }
+            _ => Vec::new(),
+        };
+        self.snapshot = meta;
+        self.rewrite(entries)
+    }
+
+    fn write_snapshot(&self, meta: &SnapshotMeta, data: &[u8]) -> io::Result<()> {
+        let mut snapshot = Vec::with_capacity(data.len() + 64);
+        meta.encode(&mut snapshot);
+        snapshot.extend_from_slice(data);
+        write_file(&self.dir, SNAPSHOT_FILE, &snapshot)
+    }
+
+    /// Replaces the log file by one holding `entries`.
+    fn rewrite(&mut self, entries: Vec<Entry>) -> io::Result<()> {
+        let temp = self.dir.join(format!("{}.tmp", LOG_FILE));
+        File::create(&temp)?;
+        self.log = OpenOptions::new().append(true).open(&temp)?;
+        self.len = 0;
+        self.entries.clear();
+        self.offsets.clear();
+        self.append(&entries)?;
+        files::rename_durably(&temp, self.dir.join(LOG_FILE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Entry, Members, SnapshotMeta, Storage, LOG_FILE};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn entry(index: u64, term: u64) -> Entry {
+        Entry {
+            index,
+            term,
+            command: Command::Request(vec![index as u8]),
+        }
+    }
+
+    #[test]
+    fn test_storage_survives_reopening_truncation_and_compaction() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-raft-storage-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!((storage.term(), storage.last_index()), (0, 0));
+        assert_eq!(storage.term_at(0), Some(0));
+        storage.set_state(3, Some(2)).unwrap();
+        let members: Members = vec![(1, "a".to_string()), (2, "b".to_string())]
+            .into_iter()
+            .collect();
+        let config = Entry {
+            index: 1,
+            term: 1,
+            command: Command::Members(members.clone()),
+        };
+        storage.append(&[config, entry(2, 1), entry(3, 2)]).unwrap();
+        storage.append(&[entry(4, 2)]).unwrap();
+        storage.truncate(4).unwrap();
+        storage.append(&[entry(4, 3), entry(5, 3)]).unwrap();
+        assert_eq!(storage.members(5), (1, members.clone()));
+        assert_eq!(storage.entries(2, 0).len(), 1);
+        assert_eq!(storage.entries(4, 1 << 20), vec![entry(4, 3), entry(5, 3)]);
+        drop(storage);
+
+        // cut short as by a crash while appending
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(&[42, 0, 0]).unwrap();
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!((storage.term(), storage.voted_for()), (3, Some(2)));
+        assert_eq!((storage.last_index(), storage.last_term()), (5, 3));
+        assert_eq!(storage.entry(4), Some(&entry(4, 3)));
+
+        storage.compact(3, b"data").unwrap();
+        assert_eq!(storage.entry(3), None);
+        assert_eq!(storage.term_at(3), Some(2));
+        assert_eq!(storage.members(5), (3, members.clone()));
+        storage.append(&[entry(6, 3)]).unwrap();
+        drop(storage);
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.snapshot().index, 3);
+        assert_eq!(storage.snapshot_data().unwrap(), b"data");
+        assert_eq!(storage.entries(0, 1 << 20).len(), 3);
+
+        // installing a snapshot the log holds keeps the entries following
+        let meta = SnapshotMeta {
+            index: 5,
+            term: 3,
+            members: Members::new(),
+        };
+        storage.install(meta.clone(), b"newer").unwrap();
+        assert_eq!(storage.entries(0, 1 << 20), vec![entry(6, 3)]);
+        let meta = SnapshotMeta { index: 9, ..meta };
+        storage.install(meta, b"newest").unwrap();
+        assert_eq!((storage.last_index(), storage.last_term()), (9, 3));
+        drop(storage);
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.last_index(), 9);
+        assert_eq!(storage.snapshot_data().unwrap(), b"newest");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
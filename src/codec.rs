@@ -0,0 +1,350 @@
+use std::io::{self, ErrorKind};
+
+/// Types that can be written in the binary format of palladiumdb files.
+///
+/// Integers are written in little endian, and lengths as LEB128 varints, so
+/// the encoding of a value does not depend on the platform that wrote it.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::codec::{Decode, Encode};
+///
+/// let mut buf = Vec::new();
+/// (42u32, "answer".to_string()).encode(&mut buf);
+///
+/// let mut input = &buf[..];
+/// let decoded = <(u32, String)>::decode(&mut input).unwrap();
+/// assert_eq!(decoded, (42, "answer".to_string()));
+/// assert!(input.is_empty());
+/// ```
+pub trait Encode {
+    /// Appends the encoding of `self` to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+/// Types that can be read back from the binary format written by
+/// [`Encode`].
+pub trait Decode: Sized {
+    /// Decodes a value from the front of `input`, advancing it past the
+    /// bytes read.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `UnexpectedEof` if `input` ends before the value
+    /// does, `InvalidData` if the bytes are not a valid encoding.
+    fn decode(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+/// Decodes a value from the whole of `bytes`.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if bytes are left over after the value.
+pub fn decode_all<T: Decode>(mut bytes: &[u8]) -> io::Result<T> {
+    let value = T::decode(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(invalid_data("trailing bytes after value"));
+    }
+    Ok(value)
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Splits the first `len` bytes off `input`.
+pub(crate) fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+pub(crate) fn encode_len(len: usize, buf: &mut Vec<u8>) {
+    let mut len = len as u64;
+    while len >= 0x80 {
+        buf.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    buf.push(len as u8);
+}
+
+pub(crate) fn decode_len(input: &mut &[u8]) -> io::Result<usize> {
+    let mut len = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input, 1)?[0];
+        len |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(len as usize);
+        }
+    }
+    Err(invalid_data("length overflows 64 bits"))
+}
+
+/// Appends `bytes` to `buf`, prefixed by their length.
+pub(crate) fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_len(bytes.len(), buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Splits a byte string written by [`encode_bytes`] off `input`.
+pub(crate) fn decode_bytes<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = decode_len(input)?;
+    take(input, len)
+}
+
+macro_rules! impl_codec_for_numbers {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                    let mut bytes = [0u8; std::mem::size_of::<$ty>()];
+                    let len = bytes.len();
+                    bytes.copy_from_slice(take(input, len)?);
+                    Ok(<$ty>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_for_numbers!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Encode for usize {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u64).encode(buf)
+    }
+}
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(u64::decode(input)? as usize)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8)
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u32).encode(buf)
+    }
+}
+
+impl Decode for char {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        std::char::from_u32(u32::decode(input)?).ok_or_else(|| invalid_data("invalid char"))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), buf)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_str().encode(buf)
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let bytes = decode_bytes(input)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("invalid utf-8"))
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_len(self.len(), buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_slice().encode(buf)
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = decode_len(input)?;
+        // every item takes a byte at least, do not trust larger lengths
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                buf.push(1);
+                value.encode(buf);
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match bool::decode(input)? {
+            true => Ok(Some(T::decode(input)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (**self).encode(buf)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (**self).encode(buf)
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        T::decode(input).map(Box::new)
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_input: &mut &[u8]) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_codec_for_tuples {
+    ($(($($name:ident),+)),*) => {
+        $(
+            impl<$($name: Encode),+> Encode for ($($name,)+) {
+                #[allow(non_snake_case)]
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    let ($($name,)+) = self;
+                    $($name.encode(buf);)+
+                }
+            }
+
+            impl<$($name: Decode),+> Decode for ($($name,)+) {
+                fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                    Ok(($($name::decode(input)?,)+))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_for_tuples!((A), (A, B), (A, B, C), (A, B, C, D));
+
+/// Wrapper storing any serde type with [`Encode`] and [`Decode`], serialized
+/// by bincode.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::codec::{decode_all, Bincode, Encode};
+/// use std::collections::BTreeMap;
+///
+/// let tags: BTreeMap<String, u32> = vec![("red".to_string(), 3)].into_iter().collect();
+/// let mut buf = Vec::new();
+/// Bincode(tags.clone()).encode(&mut buf);
+///
+/// let decoded: Bincode<BTreeMap<String, u32>> = decode_all(&buf).unwrap();
+/// assert_eq!(decoded.0, tags);
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bincode<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> Encode for Bincode<T> {
+    /// # Panics
+    ///
+    /// This function will panic if bincode cannot serialize the value, such
+    /// as a sequence of unknown length.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        bincode::serialize_into(buf, &self.0).expect("value is not serializable by bincode")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Decode for Bincode<T> {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match bincode::deserialize_from(input) {
+            Ok(value) => Ok(Bincode(value)),
+            Err(error) => match *error {
+                bincode::ErrorKind::Io(error) => Err(error),
+                error => Err(invalid_data(&error.to_string())),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_all, Decode, Encode};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_round_trips_and_rejects_truncated_input() {
+        let value = (
+            vec![Some("palladium".to_string()), None],
+            u64::MAX,
+            -3i16,
+            ('é', true, 1.5f64),
+        );
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        assert_eq!(
+            decode_all::<(Vec<Option<String>>, u64, i16, (char, bool, f64))>(&buf).unwrap(),
+            value
+        );
+
+        for len in 0..buf.len() {
+            let error =
+                <(Vec<Option<String>>, u64, i16, (char, bool, f64))>::decode(&mut &buf[..len])
+                    .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+mod node;
+
+use std::collections::VecDeque;
+use std::mem;
+
+use self::node::{Node, Removed};
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+struct Tree<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+/// Thread-Safe ordered map keyed by byte strings, implemented as adaptive
+/// radix tree.
+///
+/// Inner nodes grow and shrink between four representations according to
+/// their number of children, and store the bytes shared by all keys below
+/// them only once. For large keyspaces with long shared prefixes this takes
+/// far less memory than a [`SortedMap`](super::sorted::SortedMap), which
+/// stores every key in full and a tower of links per entry.
+///
+/// Readers share a tree-wide lock and writers take it exclusively.
+/// Iterators only hold the lock while collecting the next batch of entries,
+/// so long scans do not block writers.
+pub struct ArtMap<V> {
+    tree: PriorityRwLock<Tree<V>>,
+    lock_policy: LockPolicy,
+}
+
+impl<V> Default for ArtMap<V>
+where
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> ArtMap<V>
+where
+    V: Clone,
+{
+    /// Number of entries collected by an iterator every time it takes the
+    /// lock.
+    const BATCH: usize = 64;
+
+    /// Creates an empty `ArtMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::art::ArtMap;
+    /// let map: ArtMap<u64> = ArtMap::new();
+    /// ```
+    pub fn new() -> Self {
+        ArtMap {
+            tree: PriorityRwLock::new(Tree {
+                root: Node::Empty,
+                len: 0,
+            }),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    /// Maps `key` to `value`, returning the value previously mapped to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::art::ArtMap;
+    ///
+    /// let map = ArtMap::new();
+    /// assert_eq!(map.insert(b"user:1", 'a'), None);
+    /// assert_eq!(map.insert(b"user:1", 'b'), Some('a'));
+    /// ```
+    pub fn insert<K: AsRef<[u8]>>(&self, key: K, value: V) -> Option<V> {
+        let mut tree = self.tree.write(self.lock_policy.write);
+        let previous = tree.root.insert(key.as_ref(), 0, value);
+        if previous.is_none() {
+            tree.len += 1;
+        }
+        previous
+    }
+
+    /// Returns the value mapped to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::art::ArtMap;
+    ///
+    /// let map = ArtMap::new();
+    /// map.insert(b"user:1", 'a');
+    /// assert_eq!(map.get(b"user:1"), Some('a'));
+    /// assert_eq!(map.get(b"user"), None);
+    /// ```
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<V> {
+        let tree = self.tree.read(self.lock_policy.read);
+        tree.root.get(key.as_ref()).cloned()
+    }
+
+    /// Returns `true` if `key` is mapped to a value.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        let tree = self.tree.read(self.lock_policy.read);
+        tree.root.get(key.as_ref()).is_some()
+    }
+
+    /// Removes the mapping of `key`, returning its value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::art::ArtMap;
+    ///
+    /// let map = ArtMap::new();
+    /// map.insert(b"user:1", 'a');
+    /// assert_eq!(map.remove(b"user:1"), Some('a'));
+    /// assert_eq!(map.remove(b"user:1"), None);
+    /// ```
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Option<V> {
+        let mut tree = self.tree.write(self.lock_policy.write);
+        let value = match tree.root.remove(key.as_ref(), 0)? {
+            Removed::Value(value) => value,
+            Removed::Node => match mem::take(&mut tree.root) {
+                Node::Leaf(leaf) => leaf.value,
+                _ => unreachable!(),
+            },
+        };
+        tree.len -= 1;
+        Some(value)
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix`,
+    /// in ascending byte order of the keys.
+    ///
+    /// Entries present for the whole duration of the iteration are always
+    /// yielded, entries inserted or removed concurrently may or may not be.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::art::ArtMap;
+    ///
+    /// let map = ArtMap::new();
+    /// map.insert("user:2", 2);
+    /// map.insert("user:1", 1);
+    /// map.insert("order:1", 3);
+    ///
+    /// let values: Vec<_> = map.iter_prefix("user:").map(|(_, value)| value).collect();
+    /// assert_eq!(values, vec![1, 2]);
+    /// ```
+    pub fn iter_prefix<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<'_, V> {
+        Iter {
+            map: self,
+            prefix: prefix.as_ref().to_vec(),
+            after: None,
+            buffer: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Returns an iterator over all entries, in ascending byte order of the
+    /// keys. See [`ArtMap::iter_prefix`].
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.iter_prefix([])
+    }
+
+    /// Returns the number of entries in the `ArtMap`.
+    pub fn len(&self) -> usize {
+        self.tree.read(self.lock_policy.read).len
+    }
+
+    /// Returns `true` if the `ArtMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An iterator over the entries of an [`ArtMap`], see
+/// [`ArtMap::iter_prefix`].
+pub struct Iter<'a, V> {
+    map: &'a ArtMap<V>,
+    prefix: Vec<u8>,
+    after: Option<Vec<u8>>,
+    buffer: VecDeque<(Vec<u8>, V)>,
+    exhausted: bool,
+}
+
+impl<'a, V> Iterator for Iter<'a, V>
+where
+    V: Clone,
+{
+    type Item = (Vec<u8>, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.exhausted {
+            let limit = ArtMap::<V>::BATCH;
+            let mut batch = Vec::with_capacity(limit);
+            {
+                let tree = self.map.tree.read(self.map.lock_policy.read);
+                tree.root.scan(
+                    &mut Vec::new(),
+                    &self.prefix,
+                    self.after.as_deref(),
+                    &mut batch,
+                    limit,
+                );
+            }
+            // resume after the last key collected once the batch runs out
+            self.exhausted = batch.len() < limit;
+            self.after = batch.last().map(|(key, _)| key.clone());
+            self.buffer.extend(batch);
+        }
+        self.buffer.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArtMap;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_matches_btree_map_through_node_growth_and_shrinking() {
+        let map = ArtMap::new();
+        let mut expected = BTreeMap::new();
+
+        // keys sharing prefixes and fanning out to every byte value
+        let keys: Vec<Vec<u8>> = (0..4096u32)
+            .map(|i| {
+                let mut key = b"prefix/".to_vec();
+                key.extend_from_slice(&(i % 300).to_be_bytes());
+                key.truncate(7 + (i as usize % 5));
+                key.push((i * 7) as u8);
+                key
+            })
+            .collect();
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.insert(key, i), expected.insert(key.clone(), i));
+        }
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.clone().into_iter()));
+
+        for key in keys.iter().step_by(3) {
+            assert_eq!(map.remove(key), expected.remove(key));
+        }
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.clone().into_iter()));
+        for key in &keys {
+            assert_eq!(map.get(key), expected.get(key).copied());
+        }
+
+        let prefix = b"prefix/\0\0";
+        assert!(map.iter_prefix(prefix).eq(expected
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(prefix))));
+    }
+}
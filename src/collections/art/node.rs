@@ -0,0 +1,519 @@
+use std::mem;
+
+/// Node of an adaptive radix tree.
+///
+/// Keys are only stored in full by leaves. Inner nodes store the bytes
+/// shared by every key below them as a compressed prefix, followed by one
+/// child per distinct next byte.
+#[derive(Default)]
+pub enum Node<V> {
+    /// Absent node. Only the root of an empty tree and the free slots of
+    /// a [`Children::Node256`] are empty.
+    #[default]
+    Empty,
+    Leaf(Box<Leaf<V>>),
+    Inner(Box<Inner<V>>),
+}
+
+pub struct Leaf<V> {
+    pub key: Box<[u8]>,
+    pub value: V,
+}
+
+/// Inner node. Every inner node holds at least two keys, counting the key
+/// ending at the node itself.
+pub struct Inner<V> {
+    pub prefix: Vec<u8>,
+    /// Value of the key ending right after `prefix`.
+    pub terminal: Option<V>,
+    pub children: Children<V>,
+}
+
+/// Outcome of a removal from a subtree.
+pub enum Removed<V> {
+    /// The subtree is a leaf holding the key, and has to be unlinked by the
+    /// caller.
+    Node,
+    Value(V),
+}
+
+fn leaf<V>(key: &[u8], value: V) -> Node<V> {
+    Node::Leaf(Box::new(Leaf {
+        key: key.into(),
+        value,
+    }))
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+impl<V> Inner<V> {
+    fn new(prefix: &[u8]) -> Self {
+        Inner {
+            prefix: prefix.to_vec(),
+            terminal: None,
+            children: Children::default(),
+        }
+    }
+
+    /// Adds a leaf to the node, whose prefix ends at `depth` of the leaf's
+    /// key.
+    fn place(&mut self, leaf: Box<Leaf<V>>, depth: usize) {
+        if leaf.key.len() == depth {
+            self.terminal = Some(leaf.value);
+        } else {
+            self.children.insert(leaf.key[depth], Node::Leaf(leaf));
+        }
+    }
+}
+
+impl<V> Node<V> {
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Empty => return None,
+                Node::Leaf(leaf) => return (*leaf.key == *key).then_some(&leaf.value),
+                Node::Inner(inner) => {
+                    if !key[depth..].starts_with(&inner.prefix) {
+                        return None;
+                    }
+                    depth += inner.prefix.len();
+                    if depth == key.len() {
+                        return inner.terminal.as_ref();
+                    }
+                    node = inner.children.get(key[depth])?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Maps `key` to `value` in the subtree whose keys share the first
+    /// `depth` bytes of `key`, returning the previous value.
+    pub fn insert(&mut self, key: &[u8], depth: usize, value: V) -> Option<V> {
+        match self {
+            Node::Empty => {
+                *self = leaf(key, value);
+                None
+            }
+            Node::Leaf(existing) => {
+                if *existing.key == *key {
+                    return Some(mem::replace(&mut existing.value, value));
+                }
+                let existing = match mem::take(self) {
+                    Node::Leaf(existing) => existing,
+                    _ => unreachable!(),
+                };
+
+                let common = common_prefix_len(&existing.key[depth..], &key[depth..]);
+                let mut inner = Inner::new(&key[depth..depth + common]);
+                inner.place(existing, depth + common);
+                inner.place(
+                    Box::new(Leaf {
+                        key: key.into(),
+                        value,
+                    }),
+                    depth + common,
+                );
+                *self = Node::Inner(Box::new(inner));
+                None
+            }
+            Node::Inner(inner) => {
+                let common = common_prefix_len(&inner.prefix, &key[depth..]);
+                if common < inner.prefix.len() {
+                    // the key diverges within the prefix, split it
+                    let mut existing = match mem::take(self) {
+                        Node::Inner(existing) => existing,
+                        _ => unreachable!(),
+                    };
+                    let mut parent = Inner::new(&existing.prefix[..common]);
+                    let byte = existing.prefix[common];
+                    existing.prefix.drain(..=common);
+                    parent.children.insert(byte, Node::Inner(existing));
+                    parent.place(
+                        Box::new(Leaf {
+                            key: key.into(),
+                            value,
+                        }),
+                        depth + common,
+                    );
+                    *self = Node::Inner(Box::new(parent));
+                    return None;
+                }
+
+                let depth = depth + inner.prefix.len();
+                if depth == key.len() {
+                    return inner.terminal.replace(value);
+                }
+                match inner.children.get_mut(key[depth]) {
+                    Some(child) => child.insert(key, depth + 1, value),
+                    None => {
+                        inner.children.insert(key[depth], leaf(key, value));
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes `key` from the subtree whose keys share the first `depth`
+    /// bytes of `key`.
+    pub fn remove(&mut self, key: &[u8], depth: usize) -> Option<Removed<V>> {
+        let inner = match self {
+            Node::Empty => return None,
+            Node::Leaf(leaf) => return (*leaf.key == *key).then_some(Removed::Node),
+            Node::Inner(inner) => inner,
+        };
+        if !key[depth..].starts_with(&inner.prefix) {
+            return None;
+        }
+
+        let depth = depth + inner.prefix.len();
+        let value = if depth == key.len() {
+            inner.terminal.take()?
+        } else {
+            let byte = key[depth];
+            match inner.children.get_mut(byte)?.remove(key, depth + 1)? {
+                Removed::Value(value) => return Some(Removed::Value(value)),
+                Removed::Node => match inner.children.remove(byte) {
+                    Some(Node::Leaf(leaf)) => leaf.value,
+                    _ => unreachable!(),
+                },
+            }
+        };
+        self.collapse(&key[..depth]);
+        Some(Removed::Value(value))
+    }
+
+    /// Replaces an inner node left with a single key by that key's leaf or
+    /// subtree, keeping prefixes compressed. `path` is the key ending at
+    /// the node.
+    fn collapse(&mut self, path: &[u8]) {
+        match self {
+            Node::Inner(inner)
+                if inner.children.len() + (inner.terminal.is_some() as usize) < 2 => {}
+            _ => return,
+        }
+        let mut inner = match mem::take(self) {
+            Node::Inner(inner) => inner,
+            _ => unreachable!(),
+        };
+
+        if let Some(value) = inner.terminal.take() {
+            *self = leaf(path, value);
+            return;
+        }
+        let (byte, child) = inner.children.into_entries().pop().unwrap();
+        *self = match child {
+            Node::Inner(mut child) => {
+                let mut prefix = mem::take(&mut inner.prefix);
+                prefix.push(byte);
+                prefix.append(&mut child.prefix);
+                child.prefix = prefix;
+                Node::Inner(child)
+            }
+            child => child,
+        };
+    }
+
+    /// Appends to `out` the entries of the subtree with keys starting with
+    /// `prefix` and greater than `after`, in ascending key order, until
+    /// `out` holds `limit` entries. `path` holds the bytes shared by every
+    /// key of the subtree.
+    pub fn scan(
+        &self,
+        path: &mut Vec<u8>,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        out: &mut Vec<(Vec<u8>, V)>,
+        limit: usize,
+    ) where
+        V: Clone,
+    {
+        let in_range =
+            |key: &[u8]| key.starts_with(prefix) && after.is_none_or(|after| key > after);
+        match self {
+            Node::Empty => {}
+            Node::Leaf(leaf) => {
+                if out.len() < limit && in_range(&leaf.key) {
+                    out.push((leaf.key.to_vec(), leaf.value.clone()));
+                }
+            }
+            Node::Inner(inner) => {
+                let base = path.len();
+                path.extend_from_slice(&inner.prefix);
+                if !path.starts_with(prefix) && !prefix.starts_with(path) {
+                    path.truncate(base);
+                    return;
+                }
+                // every key below starts with `path`, compare it to `after`
+                let after = match after {
+                    Some(after) => {
+                        let shared = path.len().min(after.len());
+                        match path[..shared].cmp(&after[..shared]) {
+                            std::cmp::Ordering::Less => {
+                                path.truncate(base);
+                                return;
+                            }
+                            std::cmp::Ordering::Greater => None,
+                            std::cmp::Ordering::Equal if path.len() > after.len() => None,
+                            std::cmp::Ordering::Equal => Some(after),
+                        }
+                    }
+                    None => None,
+                };
+
+                if let Some(value) = &inner.terminal {
+                    if out.len() < limit && in_range(path) {
+                        out.push((path.clone(), value.clone()));
+                    }
+                }
+                for (byte, child) in inner.children.iter() {
+                    if out.len() >= limit {
+                        break;
+                    }
+                    path.push(byte);
+                    child.scan(path, prefix, after, out, limit);
+                    path.pop();
+                }
+                path.truncate(base);
+            }
+        }
+    }
+}
+
+/// Children of an inner node, in a representation adapted to their number.
+pub enum Children<V> {
+    /// Up to 4 children, keyed by the sorted `keys`.
+    Node4 { keys: [u8; 4], nodes: Vec<Node<V>> },
+    /// Up to 16 children, keyed by the sorted `keys`.
+    Node16 { keys: [u8; 16], nodes: Vec<Node<V>> },
+    /// Up to 48 children, `index` maps every byte to its child's position
+    /// in `nodes` plus one, or 0.
+    Node48 {
+        index: Box<[u8; 256]>,
+        nodes: Vec<Node<V>>,
+    },
+    /// One slot per byte.
+    Node256 {
+        nodes: Box<[Node<V>; 256]>,
+        len: usize,
+    },
+}
+
+impl<V> Default for Children<V> {
+    fn default() -> Self {
+        Children::Node4 {
+            keys: [0; 4],
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl<V> Children<V> {
+    /// Creates an empty representation for up to `capacity` children.
+    fn with_capacity(capacity: usize) -> Self {
+        match capacity {
+            4 => Children::Node4 {
+                keys: [0; 4],
+                nodes: Vec::with_capacity(4),
+            },
+            16 => Children::Node16 {
+                keys: [0; 16],
+                nodes: Vec::with_capacity(16),
+            },
+            48 => Children::Node48 {
+                index: Box::new([0; 256]),
+                nodes: Vec::with_capacity(48),
+            },
+            _ => Children::Node256 {
+                nodes: Box::new(std::array::from_fn(|_| Node::Empty)),
+                len: 0,
+            },
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Children::Node4 { .. } => 4,
+            Children::Node16 { .. } => 16,
+            Children::Node48 { .. } => 48,
+            Children::Node256 { .. } => 256,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Children::Node4 { nodes, .. }
+            | Children::Node16 { nodes, .. }
+            | Children::Node48 { nodes, .. } => nodes.len(),
+            Children::Node256 { len, .. } => *len,
+        }
+    }
+
+    fn position(&self, byte: u8) -> Option<usize> {
+        match self {
+            Children::Node4 { keys, nodes } => keys[..nodes.len()].iter().position(|k| *k == byte),
+            Children::Node16 { keys, nodes } => keys[..nodes.len()].binary_search(&byte).ok(),
+            Children::Node48 { index, .. } => index[byte as usize].checked_sub(1).map(usize::from),
+            Children::Node256 { nodes, .. } => match nodes[byte as usize] {
+                Node::Empty => None,
+                _ => Some(byte as usize),
+            },
+        }
+    }
+
+    pub fn get(&self, byte: u8) -> Option<&Node<V>> {
+        let position = self.position(byte)?;
+        Some(match self {
+            Children::Node4 { nodes, .. }
+            | Children::Node16 { nodes, .. }
+            | Children::Node48 { nodes, .. } => &nodes[position],
+            Children::Node256 { nodes, .. } => &nodes[position],
+        })
+    }
+
+    pub fn get_mut(&mut self, byte: u8) -> Option<&mut Node<V>> {
+        let position = self.position(byte)?;
+        Some(match self {
+            Children::Node4 { nodes, .. }
+            | Children::Node16 { nodes, .. }
+            | Children::Node48 { nodes, .. } => &mut nodes[position],
+            Children::Node256 { nodes, .. } => &mut nodes[position],
+        })
+    }
+
+    /// Adds the child for `byte`, which must not have a child yet.
+    pub fn insert(&mut self, byte: u8, node: Node<V>) {
+        if self.len() == self.capacity() {
+            let capacity = match self.capacity() {
+                4 => 16,
+                16 => 48,
+                _ => 256,
+            };
+            self.resize(capacity);
+        }
+        match self {
+            Children::Node4 { keys, nodes } => insert_sorted(keys, nodes, byte, node),
+            Children::Node16 { keys, nodes } => insert_sorted(keys, nodes, byte, node),
+            Children::Node48 { index, nodes } => {
+                nodes.push(node);
+                index[byte as usize] = nodes.len() as u8;
+            }
+            Children::Node256 { nodes, len } => {
+                nodes[byte as usize] = node;
+                *len += 1;
+            }
+        }
+    }
+
+    /// Unlinks the child for `byte`.
+    pub fn remove(&mut self, byte: u8) -> Option<Node<V>> {
+        let position = self.position(byte)?;
+        let removed = match self {
+            Children::Node4 { keys, nodes } => remove_sorted(keys, nodes, position),
+            Children::Node16 { keys, nodes } => remove_sorted(keys, nodes, position),
+            Children::Node48 { index, nodes } => {
+                index[byte as usize] = 0;
+                let removed = nodes.swap_remove(position);
+                // the last child moved into the freed position
+                if let Some(moved) = index
+                    .iter_mut()
+                    .find(|slot| **slot as usize == nodes.len() + 1)
+                {
+                    *moved = position as u8 + 1;
+                }
+                removed
+            }
+            Children::Node256 { nodes, len } => {
+                *len -= 1;
+                mem::take(&mut nodes[position])
+            }
+        };
+
+        let len = self.len();
+        match self.capacity() {
+            256 if len <= 40 => self.resize(48),
+            48 if len <= 12 => self.resize(16),
+            16 if len <= 3 => self.resize(4),
+            _ => {}
+        }
+        Some(removed)
+    }
+
+    /// Returns the children in ascending byte order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (u8, &Node<V>)> + '_> {
+        match self {
+            Children::Node4 { keys, nodes } => Box::new(keys.iter().copied().zip(nodes.iter())),
+            Children::Node16 { keys, nodes } => Box::new(keys.iter().copied().zip(nodes.iter())),
+            Children::Node48 { index, nodes } => Box::new(
+                (0..=u8::MAX)
+                    .filter(move |byte| index[*byte as usize] != 0)
+                    .map(move |byte| (byte, &nodes[index[byte as usize] as usize - 1])),
+            ),
+            Children::Node256 { nodes, .. } => Box::new(
+                (0..=u8::MAX)
+                    .zip(nodes.iter())
+                    .filter(|(_, node)| !matches!(node, Node::Empty)),
+            ),
+        }
+    }
+
+    /// Returns the children in ascending byte order, consuming them.
+    pub fn into_entries(self) -> Vec<(u8, Node<V>)> {
+        match self {
+            Children::Node4 { keys, nodes } => keys.iter().copied().zip(nodes).collect(),
+            Children::Node16 { keys, nodes } => keys.iter().copied().zip(nodes).collect(),
+            Children::Node48 { index, nodes } => {
+                let mut slots: Vec<Option<Node<V>>> = nodes.into_iter().map(Some).collect();
+                (0..=u8::MAX)
+                    .filter(|byte| index[*byte as usize] != 0)
+                    .map(|byte| {
+                        (
+                            byte,
+                            slots[index[byte as usize] as usize - 1].take().unwrap(),
+                        )
+                    })
+                    .collect()
+            }
+            Children::Node256 { nodes, .. } => (0..=u8::MAX)
+                .zip(Vec::from(nodes as Box<[Node<V>]>))
+                .filter(|(_, node)| !matches!(node, Node::Empty))
+                .collect(),
+        }
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        let entries = mem::take(self).into_entries();
+        let mut resized = Children::with_capacity(capacity);
+        for (byte, node) in entries {
+            resized.insert(byte, node);
+        }
+        *self = resized;
+    }
+}
+
+fn insert_sorted<V, const N: usize>(
+    keys: &mut [u8; N],
+    nodes: &mut Vec<Node<V>>,
+    byte: u8,
+    node: Node<V>,
+) {
+    let len = nodes.len();
+    let position = keys[..len].partition_point(|key| *key < byte);
+    keys.copy_within(position..len, position + 1);
+    keys[position] = byte;
+    nodes.insert(position, node);
+}
+
+fn remove_sorted<V, const N: usize>(
+    keys: &mut [u8; N],
+    nodes: &mut Vec<Node<V>>,
+    position: usize,
+) -> Node<V> {
+    keys.copy_within(position + 1..nodes.len(), position);
+    nodes.remove(position)
+}
@@ -0,0 +1,377 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLockWriteGuard;
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Both directions of the pairs hashed to a bucket: the left values hashed
+/// to it with their right counterpart, and the right values hashed to it
+/// with their left counterpart.
+struct Sides<L, R> {
+    left: Vec<(L, R)>,
+    right: Vec<(R, L)>,
+}
+
+impl<L, R> Sides<L, R> {
+    fn new() -> Self {
+        Sides {
+            left: Vec::new(),
+            right: Vec::new(),
+        }
+    }
+}
+
+type Guards<'a, L, R> = Vec<(usize, RwLockWriteGuard<'a, Sides<L, R>>)>;
+
+fn guard_for<'g, 'a, L, R>(guards: &'g mut Guards<'a, L, R>, index: usize) -> &'g mut Sides<L, R> {
+    guards
+        .iter_mut()
+        .find(|(locked, _)| *locked == index)
+        .map(|(_, guard)| &mut **guard)
+        .expect("bucket was not locked")
+}
+
+/// Thread-Safe bidirectional map implemented as hash table, mapping every
+/// left value to exactly one right value and back.
+///
+/// Both directions of a pair are updated under the write locks of all the
+/// buckets involved, acquired in index order, so a lookup from either side
+/// never observes a pair the other side disagrees with.
+pub struct BiMap<L, R, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<PriorityRwLock<Sides<L, R>>>,
+    lock_policy: LockPolicy,
+    len: AtomicUsize,
+}
+
+impl<L, R> Default for BiMap<L, R, RandomState>
+where
+    L: Hash + Eq + Clone,
+    R: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L, R> BiMap<L, R, RandomState>
+where
+    L: Hash + Eq + Clone,
+    R: Hash + Eq + Clone,
+{
+    /// Creates an empty `BiMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::bimap::BiMap;
+    /// let map: BiMap<u64, String> = BiMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `BiMap` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::bimap::BiMap;
+    /// let map: BiMap<u64, String> = BiMap::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<L, R, H> BiMap<L, R, H>
+where
+    L: Hash + Eq + Clone,
+    R: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `BiMap` with `bucket_count` buckets allocated, using
+    /// `hash_builder` to hash the values of both sides.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Sides::new()));
+
+        BiMap {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn index_of<T: Hash>(&self, value: &T) -> usize {
+        self.hash_builder.hash_one(value) as usize % self.buckets.len()
+    }
+
+    fn lock(&self, mut indices: Vec<usize>) -> Guards<'_, L, R> {
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+            .into_iter()
+            .map(|index| (index, self.buckets[index].write(self.lock_policy.write)))
+            .collect()
+    }
+
+    /// Associates `left` with `right`.
+    ///
+    /// Any pair previously holding `left` or `right` is removed, so each
+    /// value stays associated with at most one value of the other side.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bimap::BiMap;
+    ///
+    /// let map = BiMap::new();
+    /// map.insert(&1, &"alice");
+    /// map.insert(&1, &"bob");
+    ///
+    /// assert_eq!(map.get_by_left(&1), Some("bob"));
+    /// assert_eq!(map.get_by_right(&"alice"), None);
+    /// ```
+    pub fn insert(&self, left: &L, right: &R) {
+        let (left_index, right_index) = (self.index_of(left), self.index_of(right));
+        loop {
+            // the buckets of the displaced pairs are only known after a
+            // lookup; lock all of them in order and retry if they changed
+            let old_right = self.get_by_left(left);
+            let old_left = self.get_by_right(right);
+
+            let mut indices = vec![left_index, right_index];
+            indices.extend(old_right.as_ref().map(|old| self.index_of(old)));
+            indices.extend(old_left.as_ref().map(|old| self.index_of(old)));
+            let mut guards = self.lock(indices);
+
+            let current_right = find(&guard_for(&mut guards, left_index).left, left);
+            let current_left = find(&guard_for(&mut guards, right_index).right, right);
+            if current_right != old_right || current_left != old_left {
+                continue;
+            }
+
+            let mut removed = 0;
+            if let Some(old_right) = old_right {
+                remove(&mut guard_for(&mut guards, left_index).left, left);
+                remove(
+                    &mut guard_for(&mut guards, self.index_of(&old_right)).right,
+                    &old_right,
+                );
+                removed += 1;
+            }
+            if let Some(old_left) = old_left {
+                // the pair was removed above already when `left` held `right`
+                if remove(&mut guard_for(&mut guards, right_index).right, right).is_some() {
+                    remove(
+                        &mut guard_for(&mut guards, self.index_of(&old_left)).left,
+                        &old_left,
+                    );
+                    removed += 1;
+                }
+            }
+
+            guard_for(&mut guards, left_index)
+                .left
+                .push((left.clone(), right.clone()));
+            guard_for(&mut guards, right_index)
+                .right
+                .push((right.clone(), left.clone()));
+
+            self.len.fetch_add(1, Ordering::SeqCst);
+            self.len.fetch_sub(removed, Ordering::SeqCst);
+            return;
+        }
+    }
+
+    /// Returns the right value associated with `left`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bimap::BiMap;
+    ///
+    /// let map = BiMap::new();
+    /// map.insert(&7, &"seven");
+    /// assert_eq!(map.get_by_left(&7), Some("seven"));
+    /// assert_eq!(map.get_by_left(&8), None);
+    /// ```
+    pub fn get_by_left(&self, left: &L) -> Option<R> {
+        let bucket = self.buckets[self.index_of(left)].read(self.lock_policy.read);
+        find(&bucket.left, left)
+    }
+
+    /// Returns the left value associated with `right`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bimap::BiMap;
+    ///
+    /// let map = BiMap::new();
+    /// map.insert(&7, &"seven");
+    /// assert_eq!(map.get_by_right(&"seven"), Some(7));
+    /// ```
+    pub fn get_by_right(&self, right: &R) -> Option<L> {
+        let bucket = self.buckets[self.index_of(right)].read(self.lock_policy.read);
+        find(&bucket.right, right)
+    }
+
+    /// Returns `true` if `left` is associated with a right value.
+    pub fn contains_left(&self, left: &L) -> bool {
+        self.get_by_left(left).is_some()
+    }
+
+    /// Returns `true` if `right` is associated with a left value.
+    pub fn contains_right(&self, right: &R) -> bool {
+        self.get_by_right(right).is_some()
+    }
+
+    /// Removes the pair holding `left`, returning its right value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bimap::BiMap;
+    ///
+    /// let map = BiMap::new();
+    /// map.insert(&7, &"seven");
+    ///
+    /// assert_eq!(map.remove_by_left(&7), Some("seven"));
+    /// assert!(!map.contains_right(&"seven"));
+    /// ```
+    pub fn remove_by_left(&self, left: &L) -> Option<R> {
+        let left_index = self.index_of(left);
+        loop {
+            let right = self.get_by_left(left)?;
+            let right_index = self.index_of(&right);
+            let mut guards = self.lock(vec![left_index, right_index]);
+
+            if find(&guard_for(&mut guards, left_index).left, left).as_ref() != Some(&right) {
+                continue;
+            }
+            remove(&mut guard_for(&mut guards, left_index).left, left);
+            remove(&mut guard_for(&mut guards, right_index).right, &right);
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return Some(right);
+        }
+    }
+
+    /// Removes the pair holding `right`, returning its left value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bimap::BiMap;
+    ///
+    /// let map = BiMap::new();
+    /// map.insert(&7, &"seven");
+    ///
+    /// assert_eq!(map.remove_by_right(&"seven"), Some(7));
+    /// assert!(!map.contains_left(&7));
+    /// ```
+    pub fn remove_by_right(&self, right: &R) -> Option<L> {
+        let right_index = self.index_of(right);
+        loop {
+            let left = self.get_by_right(right)?;
+            let left_index = self.index_of(&left);
+            let mut guards = self.lock(vec![left_index, right_index]);
+
+            if find(&guard_for(&mut guards, right_index).right, right).as_ref() != Some(&left) {
+                continue;
+            }
+            remove(&mut guard_for(&mut guards, right_index).right, right);
+            remove(&mut guard_for(&mut guards, left_index).left, &left);
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return Some(left);
+        }
+    }
+
+    /// Returns the number of pairs in the `BiMap`.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `BiMap` contains no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn find<A: Eq, B: Clone>(entries: &[(A, B)], key: &A) -> Option<B> {
+    entries
+        .iter()
+        .find(|(elem_key, _)| elem_key == key)
+        .map(|(_, value)| value.clone())
+}
+
+fn remove<A: Eq, B>(entries: &mut Vec<(A, B)>, key: &A) -> Option<B> {
+    let index = entries.iter().position(|(elem_key, _)| elem_key == key)?;
+    Some(entries.swap_remove(index).1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BiMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_bimap_directions_never_drift() {
+        let map = Arc::new(BiMap::with_bucket_count(3));
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..2000u64 {
+                        let (left, right) =
+                            ((i * 7 + thread) % 16, (i * 3 + thread * 5) % 16 + 100);
+                        match i % 3 {
+                            0 => {
+                                map.remove_by_left(&left);
+                            }
+                            1 => {
+                                map.remove_by_right(&right);
+                            }
+                            _ => map.insert(&left, &right),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut pairs = 0;
+        for left in 0..16u64 {
+            if let Some(right) = map.get_by_left(&left) {
+                assert_eq!(map.get_by_right(&right), Some(left));
+                pairs += 1;
+            }
+        }
+        for right in 100..116u64 {
+            if let Some(left) = map.get_by_right(&right) {
+                assert_eq!(map.get_by_left(&left), Some(right));
+            }
+        }
+        assert_eq!(map.len(), pairs);
+    }
+}
@@ -0,0 +1,190 @@
+/// Number of values above which a container switches to a bitmap, where
+/// both representations take 8 KiB.
+const ARRAY_MAX: usize = 4096;
+
+/// Set of the low 16 bits of the values of a chunk, in a representation
+/// adapted to its number of values.
+#[derive(Clone)]
+pub enum Container {
+    /// Sorted values, for sparse chunks.
+    Array(Vec<u16>),
+    /// One bit per value, for dense chunks.
+    Bitmap { words: Box<[u64; 1024]>, len: usize },
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Container::Array(Vec::new())
+    }
+}
+
+fn bit(value: u16) -> (usize, u64) {
+    (value as usize / 64, 1 << (value % 64))
+}
+
+impl Container {
+    pub fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bitmap { len, .. } => *len,
+        }
+    }
+
+    pub fn contains(&self, value: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&value).is_ok(),
+            Container::Bitmap { words, .. } => {
+                let (word, mask) = bit(value);
+                words[word] & mask != 0
+            }
+        }
+    }
+
+    /// Adds `value`, returning `true` if it was not present.
+    pub fn insert(&mut self, value: u16) -> bool {
+        match self {
+            Container::Array(values) => match values.binary_search(&value) {
+                Ok(_) => false,
+                Err(position) => {
+                    values.insert(position, value);
+                    if values.len() > ARRAY_MAX {
+                        *self = Self::bitmap(values.iter().copied());
+                    }
+                    true
+                }
+            },
+            Container::Bitmap { words, len } => {
+                let (word, mask) = bit(value);
+                let inserted = words[word] & mask == 0;
+                words[word] |= mask;
+                *len += inserted as usize;
+                inserted
+            }
+        }
+    }
+
+    /// Removes `value`, returning `true` if it was present.
+    pub fn remove(&mut self, value: u16) -> bool {
+        match self {
+            Container::Array(values) => match values.binary_search(&value) {
+                Ok(position) => {
+                    values.remove(position);
+                    true
+                }
+                Err(_) => false,
+            },
+            Container::Bitmap { words, len } => {
+                let (word, mask) = bit(value);
+                let removed = words[word] & mask != 0;
+                words[word] &= !mask;
+                *len -= removed as usize;
+                if *len <= ARRAY_MAX {
+                    *self = Container::Array(self.values().collect());
+                }
+                removed
+            }
+        }
+    }
+
+    /// Returns the values in ascending order.
+    pub fn values(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(values) => Box::new(values.iter().copied()),
+            Container::Bitmap { words, .. } => {
+                Box::new(words.iter().enumerate().flat_map(|(index, word)| {
+                    let mut word = *word;
+                    std::iter::from_fn(move || {
+                        (word != 0).then(|| {
+                            let offset = word.trailing_zeros();
+                            word &= word - 1;
+                            (index * 64) as u16 + offset as u16
+                        })
+                    })
+                }))
+            }
+        }
+    }
+
+    fn bitmap<I: Iterator<Item = u16>>(values: I) -> Self {
+        let mut words = Box::new([0u64; 1024]);
+        let mut len = 0;
+        for value in values {
+            let (word, mask) = bit(value);
+            len += (words[word] & mask == 0) as usize;
+            words[word] |= mask;
+        }
+        Container::Bitmap { words, len }
+    }
+
+    /// Picks the representation for a bitmap of `words`.
+    fn from_words(words: Box<[u64; 1024]>) -> Self {
+        let len = words.iter().map(|word| word.count_ones() as usize).sum();
+        let bitmap = Container::Bitmap { words, len };
+        if len <= ARRAY_MAX {
+            Container::Array(bitmap.values().collect())
+        } else {
+            bitmap
+        }
+    }
+
+    pub fn union(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(left), Container::Array(right)) => {
+                let mut values = Vec::with_capacity(left.len() + right.len());
+                let (mut left, mut right) = (left.iter().peekable(), right.iter().peekable());
+                while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+                    match a.cmp(b) {
+                        std::cmp::Ordering::Less => values.push(*left.next().unwrap()),
+                        std::cmp::Ordering::Greater => values.push(*right.next().unwrap()),
+                        std::cmp::Ordering::Equal => {
+                            values.push(*left.next().unwrap());
+                            right.next();
+                        }
+                    }
+                }
+                values.extend(left.chain(right));
+                if values.len() > ARRAY_MAX {
+                    Self::bitmap(values.into_iter())
+                } else {
+                    Container::Array(values)
+                }
+            }
+            (Container::Bitmap { words: left, .. }, Container::Bitmap { words: right, .. }) => {
+                let mut words = left.clone();
+                for (word, other) in words.iter_mut().zip(right.iter()) {
+                    *word |= other;
+                }
+                Self::from_words(words)
+            }
+            (bitmap @ Container::Bitmap { .. }, Container::Array(values))
+            | (Container::Array(values), bitmap @ Container::Bitmap { .. }) => {
+                let mut union = bitmap.clone();
+                for value in values {
+                    union.insert(*value);
+                }
+                union
+            }
+        }
+    }
+
+    pub fn intersection(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Bitmap { words: left, .. }, Container::Bitmap { words: right, .. }) => {
+                let mut words = left.clone();
+                for (word, other) in words.iter_mut().zip(right.iter()) {
+                    *word &= other;
+                }
+                Self::from_words(words)
+            }
+            (Container::Array(values), other) | (other, Container::Array(values)) => {
+                Container::Array(
+                    values
+                        .iter()
+                        .copied()
+                        .filter(|value| other.contains(*value))
+                        .collect(),
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+mod container;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use self::container::Container;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Thread-Safe set of `u32` values, implemented as roaring bitmap.
+///
+/// Values are split into chunks by their high 16 bits. Every chunk stores
+/// the low 16 bits of its values either as a sorted array or, once it holds
+/// more than 4096 values, as a bitmap, so sparse and dense sets of IDs both
+/// take little memory. Each chunk has its own lock, and the lock on the
+/// whole set is only taken exclusively to add or drop a chunk.
+pub struct ConcurrentBitmap {
+    chunks: PriorityRwLock<BTreeMap<u16, PriorityRwLock<Container>>>,
+    len: AtomicU64,
+    lock_policy: LockPolicy,
+}
+
+impl Default for ConcurrentBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split(value: u32) -> (u16, u16) {
+    ((value >> 16) as u16, value as u16)
+}
+
+fn join(high: u16, low: u16) -> u32 {
+    (u32::from(high) << 16) | u32::from(low)
+}
+
+impl ConcurrentBitmap {
+    /// Creates an empty `ConcurrentBitmap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::bitset::ConcurrentBitmap;
+    /// let bitmap = ConcurrentBitmap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_containers(BTreeMap::new())
+    }
+
+    fn with_containers(containers: BTreeMap<u16, Container>) -> Self {
+        let len = containers
+            .values()
+            .map(|container| container.len() as u64)
+            .sum();
+        ConcurrentBitmap {
+            chunks: PriorityRwLock::new(
+                containers
+                    .into_iter()
+                    .map(|(high, container)| (high, PriorityRwLock::new(container)))
+                    .collect(),
+            ),
+            len: AtomicU64::new(len),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    /// Adds `value` to the set.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was not in the set before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bitset::ConcurrentBitmap;
+    ///
+    /// let bitmap = ConcurrentBitmap::new();
+    /// assert!(bitmap.set(42));
+    /// assert!(!bitmap.set(42));
+    /// ```
+    pub fn set(&self, value: u32) -> bool {
+        let (high, low) = split(value);
+        let inserted = {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            match chunks.get(&high) {
+                Some(chunk) => chunk.write(self.lock_policy.write).insert(low),
+                None => {
+                    drop(chunks);
+                    let mut chunks = self.chunks.write(self.lock_policy.write);
+                    let chunk = chunks
+                        .entry(high)
+                        .or_insert_with(|| PriorityRwLock::new(Container::default()));
+                    let inserted = chunk.write(self.lock_policy.write).insert(low);
+                    inserted
+                }
+            }
+        };
+        if inserted {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        inserted
+    }
+
+    /// Removes `value` from the set.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bitset::ConcurrentBitmap;
+    ///
+    /// let bitmap = ConcurrentBitmap::new();
+    /// bitmap.set(42);
+    /// assert!(bitmap.clear(42));
+    /// assert!(!bitmap.contains(42));
+    /// ```
+    pub fn clear(&self, value: u32) -> bool {
+        let (high, low) = split(value);
+        let emptied = {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            let mut container = match chunks.get(&high) {
+                Some(chunk) => chunk.write(self.lock_policy.write),
+                None => return false,
+            };
+            if !container.remove(low) {
+                return false;
+            }
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            container.len() == 0
+        };
+
+        if emptied {
+            let mut chunks = self.chunks.write(self.lock_policy.write);
+            // the chunk may have been refilled in between
+            if chunks
+                .get(&high)
+                .is_some_and(|chunk| chunk.read(self.lock_policy.read).len() == 0)
+            {
+                chunks.remove(&high);
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if `value` is in the set.
+    pub fn contains(&self, value: u32) -> bool {
+        let (high, low) = split(value);
+        let chunks = self.chunks.read(self.lock_policy.read);
+        chunks
+            .get(&high)
+            .is_some_and(|chunk| chunk.read(self.lock_policy.read).contains(low))
+    }
+
+    /// Returns the number of values in the set.
+    pub fn cardinality(&self) -> u64 {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the set contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.cardinality() == 0
+    }
+
+    /// Returns a copy of every chunk's container.
+    fn containers(&self) -> BTreeMap<u16, Container> {
+        let chunks = self.chunks.read(self.lock_policy.read);
+        chunks
+            .iter()
+            .map(|(high, chunk)| (*high, chunk.read(self.lock_policy.read).clone()))
+            .collect()
+    }
+
+    /// Returns a set of the values in either set.
+    ///
+    /// Each set is read chunk by chunk, values added or removed
+    /// concurrently may or may not be taken into account.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bitset::ConcurrentBitmap;
+    ///
+    /// let readers = ConcurrentBitmap::new();
+    /// let writers = ConcurrentBitmap::new();
+    /// readers.set(1);
+    /// writers.set(2);
+    ///
+    /// let union = readers.union(&writers);
+    /// assert!(union.contains(1) && union.contains(2));
+    /// assert_eq!(union.cardinality(), 2);
+    /// ```
+    pub fn union(&self, other: &ConcurrentBitmap) -> ConcurrentBitmap {
+        let mut containers = self.containers();
+        for (high, container) in other.containers() {
+            let merged = match containers.get(&high) {
+                Some(existing) => existing.union(&container),
+                None => container,
+            };
+            containers.insert(high, merged);
+        }
+        Self::with_containers(containers)
+    }
+
+    /// Returns a set of the values in both sets. See
+    /// [`ConcurrentBitmap::union`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bitset::ConcurrentBitmap;
+    ///
+    /// let active = ConcurrentBitmap::new();
+    /// let premium = ConcurrentBitmap::new();
+    /// for id in 0..100 {
+    ///     active.set(id);
+    /// }
+    /// premium.set(7);
+    /// premium.set(1_000);
+    ///
+    /// let both = active.intersection(&premium);
+    /// assert_eq!(both.iter().collect::<Vec<_>>(), vec![7]);
+    /// ```
+    pub fn intersection(&self, other: &ConcurrentBitmap) -> ConcurrentBitmap {
+        let containers = self.containers();
+        let chunks = other.chunks.read(other.lock_policy.read);
+        let intersection = containers
+            .into_iter()
+            .filter_map(|(high, container)| {
+                let other = chunks.get(&high)?.read(other.lock_policy.read);
+                let intersection = container.intersection(&other);
+                (intersection.len() > 0).then_some((high, intersection))
+            })
+            .collect();
+        Self::with_containers(intersection)
+    }
+
+    /// Returns an iterator over the values of the set, in ascending order.
+    ///
+    /// The iterator locks one chunk at a time, values added or removed
+    /// concurrently may or may not be yielded.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            bitmap: self,
+            next_chunk: Some(0),
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+/// An iterator over the values of a [`ConcurrentBitmap`], see
+/// [`ConcurrentBitmap::iter`].
+pub struct Iter<'a> {
+    bitmap: &'a ConcurrentBitmap,
+    next_chunk: Option<u16>,
+    buffer: VecDeque<u32>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        while self.buffer.is_empty() {
+            let next_chunk = self.next_chunk?;
+            let chunks = self.bitmap.chunks.read(self.bitmap.lock_policy.read);
+            let (high, chunk) = match chunks.range(next_chunk..).next() {
+                Some(entry) => entry,
+                None => {
+                    self.next_chunk = None;
+                    return None;
+                }
+            };
+            let container = chunk.read(self.bitmap.lock_policy.read);
+            self.buffer
+                .extend(container.values().map(|low| join(*high, low)));
+            self.next_chunk = high.checked_add(1);
+        }
+        self.buffer.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcurrentBitmap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_sets_across_container_kinds() {
+        let bitmap = Arc::new(ConcurrentBitmap::new());
+
+        // dense chunks become bitmaps, sparse ones stay arrays
+        let writers: Vec<_> = (0..4u32)
+            .map(|thread| {
+                let bitmap = bitmap.clone();
+                thread::spawn(move || {
+                    for i in 0..20_000u32 {
+                        bitmap.set(i * 4 + thread);
+                        bitmap.set(u32::MAX - i * 1_000 - thread);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let dense = 80_000;
+        assert!(bitmap.iter().take(dense).eq(0..dense as u32));
+        assert_eq!(bitmap.cardinality() as usize, bitmap.iter().count());
+        assert!(bitmap
+            .iter()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .all(|w| w[0] < w[1]));
+
+        let evens = ConcurrentBitmap::new();
+        for value in (0..200_000).step_by(2) {
+            evens.set(value);
+        }
+        let intersection = bitmap.intersection(&evens);
+        assert!(intersection.iter().eq((0..dense as u32).step_by(2)));
+        let union = bitmap.union(&evens);
+        assert_eq!(
+            union.cardinality(),
+            bitmap.cardinality() + evens.cardinality() - intersection.cardinality()
+        );
+
+        for value in 0..dense as u32 {
+            assert!(bitmap.clear(value));
+        }
+        assert!(!bitmap.contains(0));
+        assert!(bitmap.iter().all(|value| value > 1 << 31));
+    }
+}
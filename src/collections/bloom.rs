@@ -0,0 +1,220 @@
+use std::collections::hash_map::RandomState;
+use std::f64::consts::LN_2;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::collections::utils::mix;
+
+/// Thread-Safe Bloom filter over an atomic bit array.
+///
+/// A Bloom filter answers whether an item was inserted with no false
+/// negatives and a configurable rate of false positives, in a fraction of
+/// the memory a [`Set`](super::set::Set) of the items would take. Inserts
+/// and lookups never lock.
+pub struct BloomFilter<T, H = RandomState> {
+    hash_builder: H,
+    words: Box<[AtomicU64]>,
+    probes: u32,
+    _items: PhantomData<fn(&T)>,
+}
+
+impl<T> BloomFilter<T, RandomState>
+where
+    T: Hash,
+{
+    /// Creates an empty `BloomFilter` sized to report false positives at
+    /// about `false_positive_rate` once `expected_items` items are inserted.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `expected_items` is 0 or if
+    /// `false_positive_rate` is not strictly between 0 and 1.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::bloom::BloomFilter;
+    /// let filter: BloomFilter<u64> = BloomFilter::new(1_000_000, 0.01);
+    /// ```
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        Self::with_hasher(RandomState::new(), expected_items, false_positive_rate)
+    }
+}
+
+impl<T, H> BloomFilter<T, H>
+where
+    T: Hash,
+    H: BuildHasher,
+{
+    /// Creates an empty `BloomFilter` like [`BloomFilter::new`], using
+    /// `hash_builder` to hash the items.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `expected_items` is 0 or if
+    /// `false_positive_rate` is not strictly between 0 and 1.
+    pub fn with_hasher(hash_builder: H, expected_items: usize, false_positive_rate: f64) -> Self {
+        if expected_items == 0 || !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            panic!()
+        }
+
+        let items = expected_items as f64;
+        let bits = (-items * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as usize;
+        let probes = ((bits as f64 / items) * LN_2).round().max(1.0) as u32;
+        Self::with_dimensions(hash_builder, bits, probes)
+    }
+
+    fn with_dimensions(hash_builder: H, bits: usize, probes: u32) -> Self {
+        let words = (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect();
+        BloomFilter {
+            hash_builder,
+            words,
+            probes,
+            _items: PhantomData,
+        }
+    }
+
+    /// Returns the number of bits of the filter.
+    pub fn bits(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    /// Returns the number of bits set for every inserted item.
+    pub fn probes(&self) -> u32 {
+        self.probes
+    }
+
+    fn positions(&self, item: &T) -> impl Iterator<Item = (usize, u64)> {
+        let mixed = mix(self.hash_builder.hash_one(item));
+        let (h1, h2) = (mixed & 0xffff_ffff, (mixed >> 32) | 1);
+        let bits = self.bits() as u64;
+        (0..u64::from(self.probes)).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+            ((bit / 64) as usize, 1u64 << (bit % 64))
+        })
+    }
+
+    /// Adds `item` to the filter.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `item` was certainly not in the filter before, which makes
+    /// `insert` usable for deduplication with occasional false duplicates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bloom::BloomFilter;
+    ///
+    /// let filter = BloomFilter::new(100, 0.01);
+    /// assert!(filter.insert(&"alice"));
+    /// assert!(!filter.insert(&"alice"));
+    /// ```
+    pub fn insert(&self, item: &T) -> bool {
+        let mut inserted = false;
+        for (word, mask) in self.positions(item) {
+            inserted |= self.words[word].fetch_or(mask, Ordering::SeqCst) & mask == 0;
+        }
+        inserted
+    }
+
+    /// Returns `false` if `item` was certainly never inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bloom::BloomFilter;
+    ///
+    /// let filter = BloomFilter::new(100, 0.01);
+    /// filter.insert(&1);
+    /// assert!(filter.maybe_contains(&1));
+    /// ```
+    pub fn maybe_contains(&self, item: &T) -> bool {
+        self.positions(item)
+            .all(|(word, mask)| self.words[word].load(Ordering::SeqCst) & mask != 0)
+    }
+
+    /// Returns an empty filter of the same dimensions and hasher, which can
+    /// be merged with this one.
+    pub fn empty_clone(&self) -> Self
+    where
+        H: Clone,
+    {
+        Self::with_dimensions(self.hash_builder.clone(), self.bits(), self.probes)
+    }
+
+    /// Adds every item of `other` to this filter.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the filters differ in dimensions. The
+    /// filters must also share their hasher, see
+    /// [`BloomFilter::empty_clone`], which cannot be checked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::bloom::BloomFilter;
+    ///
+    /// let filter = BloomFilter::new(100, 0.01);
+    /// let other = filter.empty_clone();
+    /// other.insert(&"bob");
+    ///
+    /// filter.merge(&other);
+    /// assert!(filter.maybe_contains(&"bob"));
+    /// ```
+    pub fn merge(&self, other: &BloomFilter<T, H>) {
+        if self.words.len() != other.words.len() || self.probes != other.probes {
+            panic!()
+        }
+        for (word, other) in self.words.iter().zip(other.words.iter()) {
+            word.fetch_or(other.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+    }
+
+    /// Returns a filter containing the items of both filters.
+    ///
+    /// # Panics
+    ///
+    /// See [`BloomFilter::merge`].
+    pub fn union(&self, other: &BloomFilter<T, H>) -> Self
+    where
+        H: Clone,
+    {
+        let union = self.empty_clone();
+        union.merge(self);
+        union.merge(other);
+        union
+    }
+
+    /// Removes every item from the filter.
+    pub fn clear(&self) {
+        for word in self.words.iter() {
+            word.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn test_false_positive_rate_is_close_to_configured() {
+        let filter = BloomFilter::new(10_000, 0.01);
+        for item in 0..10_000u64 {
+            filter.insert(&item);
+        }
+        assert!((0..10_000u64).all(|item| filter.maybe_contains(&item)));
+
+        let false_positives = (10_000..110_000u64)
+            .filter(|item| filter.maybe_contains(item))
+            .count();
+        assert!(
+            false_positives < 2_000,
+            "{} false positives",
+            false_positives
+        );
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Maximum number of entries of a leaf, and of separator keys of an inner
+/// node.
+const CAPACITY: usize = 32;
+
+type NodeRef<K, V> = Arc<PriorityRwLock<Node<K, V>>>;
+
+/// A node of the tree. The keys of `children[i]` are at least `keys[i - 1]`
+/// and less than `keys[i]`.
+enum Node<K, V> {
+    Leaf(Vec<(K, V)>),
+    Inner {
+        keys: Vec<K>,
+        children: Vec<NodeRef<K, V>>,
+    },
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Ord + Clone,
+{
+    fn is_full(&self) -> bool {
+        match self {
+            Node::Leaf(entries) => entries.len() >= CAPACITY,
+            Node::Inner { keys, .. } => keys.len() >= CAPACITY,
+        }
+    }
+
+    /// Moves the upper half of the node to a new node, returning it along
+    /// with the key separating both.
+    fn split(&mut self) -> (K, Node<K, V>) {
+        match self {
+            Node::Leaf(entries) => {
+                let upper = entries.split_off(entries.len() / 2);
+                (upper[0].0.clone(), Node::Leaf(upper))
+            }
+            Node::Inner { keys, children } => {
+                let middle = keys.len() / 2;
+                let upper_keys = keys.split_off(middle + 1);
+                let upper_children = children.split_off(middle + 1);
+                let separator = keys.pop().unwrap();
+                let upper = Node::Inner {
+                    keys: upper_keys,
+                    children: upper_children,
+                };
+                (separator, upper)
+            }
+        }
+    }
+}
+
+/// Index of the child of an inner node whose keys `key` falls within.
+fn child_index<K: Ord>(keys: &[K], key: &K) -> usize {
+    keys.partition_point(|separator| separator <= key)
+}
+
+/// The root of the tree, along with its distance to the leaves.
+struct Root<K, V> {
+    node: NodeRef<K, V>,
+    height: usize,
+}
+
+/// Thread-Safe ordered map implemented as a B+ tree.
+///
+/// Entries are stored sorted in leaves of up to 32 entries, which makes
+/// lookups and scans touch far fewer cache lines than the nodes of a
+/// [`SortedMap`].
+///
+/// Operations couple locks on their way down: a node is locked before the
+/// lock on its parent is released. Lookups and scans only take shared locks.
+/// Writers optimistically do too, locking only the leaf exclusively, and
+/// restart with exclusive locks from the root when the leaf is full and has
+/// to be split. Full nodes are then split on the way down, so that a split
+/// never propagates upwards and at most two nodes are locked at a time.
+///
+/// Nodes are never merged: a leaf emptied by removals stays in the tree
+/// until insertions fill it again.
+///
+/// [`SortedMap`]: crate::collections::sorted::SortedMap
+pub struct BTreeMap<K, V> {
+    root: PriorityRwLock<Root<K, V>>,
+    len: AtomicUsize,
+    lock_policy: LockPolicy,
+}
+
+impl<K, V> Default for BTreeMap<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> BTreeMap<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    /// Creates an empty `BTreeMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::btree::BTreeMap;
+    /// let map: BTreeMap<&str, i32> = BTreeMap::new();
+    /// ```
+    pub fn new() -> Self {
+        BTreeMap {
+            root: PriorityRwLock::new(Root {
+                node: Arc::new(PriorityRwLock::new(Node::Leaf(Vec::new()))),
+                height: 0,
+            }),
+            len: AtomicUsize::new(0),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    /// Descends from `node` to the leaf `key` falls within under shared
+    /// locks, and runs `f` on its entries.
+    ///
+    /// `parent` is the guard on whatever led to `node`, released once
+    /// `node` is locked.
+    fn read_leaf<G, F, R>(&self, node: &NodeRef<K, V>, parent: G, key: &K, f: F) -> R
+    where
+        F: FnOnce(&[(K, V)]) -> R,
+    {
+        let guard = node.read(self.lock_policy.read);
+        drop(parent);
+        let child = match &*guard {
+            Node::Leaf(entries) => return f(entries),
+            Node::Inner { keys, children } => Arc::clone(&children[child_index(keys, key)]),
+        };
+        self.read_leaf(&child, guard, key, f)
+    }
+
+    /// Descends from `node`, `height` levels above the leaves, to the leaf
+    /// `key` falls within, and runs `f` on its entries under an exclusive
+    /// lock. Inner nodes are only locked shared.
+    fn write_leaf<G, F, R>(
+        &self,
+        node: &NodeRef<K, V>,
+        parent: G,
+        height: usize,
+        key: &K,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce(&mut Vec<(K, V)>) -> R,
+    {
+        if height == 0 {
+            let mut guard = node.write(self.lock_policy.write);
+            drop(parent);
+            return match &mut *guard {
+                Node::Leaf(entries) => f(entries),
+                Node::Inner { .. } => unreachable!(),
+            };
+        }
+
+        let guard = node.read(self.lock_policy.read);
+        drop(parent);
+        let child = match &*guard {
+            Node::Inner { keys, children } => Arc::clone(&children[child_index(keys, key)]),
+            Node::Leaf(_) => unreachable!(),
+        };
+        self.write_leaf(&child, guard, height - 1, key, f)
+    }
+
+    /// Runs `f` on the entries of the leaf `key` falls within.
+    fn with_leaf<F, R>(&self, key: &K, f: F) -> R
+    where
+        F: FnOnce(&[(K, V)]) -> R,
+    {
+        let root = self.root.read(self.lock_policy.read);
+        let node = Arc::clone(&root.node);
+        self.read_leaf(&node, root, key, f)
+    }
+
+    /// Runs `f` on the entries of the leaf `key` falls within, locked
+    /// exclusively.
+    fn with_leaf_mut<F, R>(&self, key: &K, f: F) -> R
+    where
+        F: FnOnce(&mut Vec<(K, V)>) -> R,
+    {
+        let root = self.root.read(self.lock_policy.read);
+        let (node, height) = (Arc::clone(&root.node), root.height);
+        self.write_leaf(&node, root, height, key, f)
+    }
+
+    /// Inserts an entry into the leaf `key` falls within, descending from
+    /// `node` under exclusive locks and splitting full nodes on the way.
+    ///
+    /// `node` itself is never full.
+    fn put_splitting<G>(&self, node: &NodeRef<K, V>, parent: G, key: &K, value: V) -> bool {
+        let mut guard = node.write(self.lock_policy.write);
+        drop(parent);
+        let child = match &mut *guard {
+            Node::Leaf(entries) => return insert_entry(entries, key, value),
+            Node::Inner { keys, children } => {
+                let mut index = child_index(keys, key);
+                let split = {
+                    let mut child = children[index].write(self.lock_policy.write);
+                    if child.is_full() {
+                        Some(child.split())
+                    } else {
+                        None
+                    }
+                };
+                if let Some((separator, upper)) = split {
+                    let descend_upper = *key >= separator;
+                    keys.insert(index, separator);
+                    children.insert(index + 1, Arc::new(PriorityRwLock::new(upper)));
+                    if descend_upper {
+                        index += 1;
+                    }
+                }
+                Arc::clone(&children[index])
+            }
+        };
+        self.put_splitting(&child, guard, key, value)
+    }
+
+    /// Establishes a key value mapping for the key value pair.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `key` was not mapped before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// assert!(map.put(&"b", 2));
+    /// assert!(!map.put(&"b", 3));
+    /// assert_eq!(map.get(&"b"), Some(3));
+    /// ```
+    pub fn put(&self, key: &K, value: V) -> bool {
+        let mut value = Some(value);
+        let inserted = self.with_leaf_mut(key, |entries| {
+            match entries.binary_search_by(|(elem_key, _)| elem_key.cmp(key)) {
+                Err(_) if entries.len() >= CAPACITY => None,
+                _ => Some(insert_entry(entries, key, value.take().unwrap())),
+            }
+        });
+        let inserted = match inserted {
+            Some(inserted) => inserted,
+            // the leaf is full, restart splitting from the root
+            None => {
+                let mut root = self.root.write(self.lock_policy.write);
+                let full = root.node.read(self.lock_policy.read).is_full();
+                if full {
+                    let (separator, upper) = root.node.write(self.lock_policy.write).split();
+                    let lower = Arc::clone(&root.node);
+                    root.node = Arc::new(PriorityRwLock::new(Node::Inner {
+                        keys: vec![separator],
+                        children: vec![lower, Arc::new(PriorityRwLock::new(upper))],
+                    }));
+                    root.height += 1;
+                }
+                let node = Arc::clone(&root.node);
+                self.put_splitting(&node, root, key, value.take().unwrap())
+            }
+        };
+        if inserted {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        inserted
+    }
+
+    /// Returns the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.get(&1), Some('a'));
+    /// assert_eq!(map.get(&2), None);
+    /// ```
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.with_leaf(key, |entries| {
+            let index = entries
+                .binary_search_by(|(elem_key, _)| elem_key.cmp(key))
+                .ok()?;
+            Some(entries[index].1.clone())
+        })
+    }
+
+    /// Returns `true` if the `BTreeMap` contains a mapping for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.with_leaf(key, |entries| {
+            entries
+                .binary_search_by(|(elem_key, _)| elem_key.cmp(key))
+                .is_ok()
+        })
+    }
+
+    /// Removes the mapping for `key`, returning its value if it was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.remove(&1), Some('a'));
+    /// assert_eq!(map.remove(&1), None);
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let removed = self.with_leaf_mut(key, |entries| {
+            let index = entries
+                .binary_search_by(|(elem_key, _)| elem_key.cmp(key))
+                .ok()?;
+            Some(entries.remove(index).1)
+        });
+        if removed.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    /// Descends from `node` to the leaf holding the first keys after
+    /// `start`, returning its entries after `start` along with the smallest
+    /// key of the next leaf, if any.
+    ///
+    /// `fence` is the smallest key after the subtree of `node`.
+    fn scan_from<G>(
+        &self,
+        node: &NodeRef<K, V>,
+        parent: G,
+        start: Bound<&K>,
+        fence: Option<K>,
+    ) -> (Vec<(K, V)>, Option<K>) {
+        let guard = node.read(self.lock_policy.read);
+        drop(parent);
+        let (child, fence) = match &*guard {
+            Node::Leaf(entries) => {
+                let from = match start {
+                    Bound::Included(start) => entries.partition_point(|(key, _)| key < start),
+                    Bound::Excluded(start) => entries.partition_point(|(key, _)| key <= start),
+                    Bound::Unbounded => 0,
+                };
+                return (entries[from..].to_vec(), fence);
+            }
+            Node::Inner { keys, children } => {
+                let index = match start {
+                    Bound::Included(start) | Bound::Excluded(start) => child_index(keys, start),
+                    Bound::Unbounded => 0,
+                };
+                (
+                    Arc::clone(&children[index]),
+                    keys.get(index).cloned().or(fence),
+                )
+            }
+        };
+        self.scan_from(&child, guard, start, fence)
+    }
+
+    fn scan(&self, start: Bound<&K>) -> (Vec<(K, V)>, Option<K>) {
+        let root = self.root.read(self.lock_policy.read);
+        let node = Arc::clone(&root.node);
+        self.scan_from(&node, root, start, None)
+    }
+
+    /// Returns the entry with the smallest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// map.put(&2, 'b');
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.first(), Some((1, 'a')));
+    /// ```
+    pub fn first(&self) -> Option<(K, V)> {
+        self.iter().next()
+    }
+
+    /// Returns the entry with the largest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// map.put(&2, 'b');
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.last(), Some((2, 'b')));
+    /// ```
+    pub fn last(&self) -> Option<(K, V)> {
+        let root = self.root.read(self.lock_policy.read);
+        let node = Arc::clone(&root.node);
+        let last = self.last_from(&node, root);
+        // the rightmost leaf was emptied by removals, fall back to a scan
+        last.or_else(|| self.iter().last())
+    }
+
+    fn last_from<G>(&self, node: &NodeRef<K, V>, parent: G) -> Option<(K, V)> {
+        let guard = node.read(self.lock_policy.read);
+        drop(parent);
+        let child = match &*guard {
+            Node::Leaf(entries) => return entries.last().cloned(),
+            Node::Inner { children, .. } => Arc::clone(children.last().unwrap()),
+        };
+        self.last_from(&child, guard)
+    }
+
+    /// Returns a cursor over the entries with keys within `range`, in
+    /// ascending key order.
+    ///
+    /// The cursor copies entries a leaf at a time and holds no lock between
+    /// calls. Entries present for the whole duration of the iteration are
+    /// always yielded, entries inserted or removed concurrently may or may
+    /// not be.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// for i in 0..100 {
+    ///     map.put(&i, i * 10);
+    /// }
+    ///
+    /// let keys: Vec<_> = map.range(30..60).map(|(key, _)| key).collect();
+    /// assert_eq!(keys, (30..60).collect::<Vec<_>>());
+    /// ```
+    pub fn range<R>(&self, range: R) -> Cursor<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        Cursor {
+            map: self,
+            next: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            batch: VecDeque::new(),
+        }
+    }
+
+    /// Returns a cursor over all entries, in ascending key order.
+    ///
+    /// See [`BTreeMap::range`] for what is observed of concurrent
+    /// modifications.
+    pub fn iter(&self) -> Cursor<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Returns the number of entries in the `BTreeMap`.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `BTreeMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Inserts or replaces the entry of `key` in the sorted `entries`,
+/// returning `true` if it was inserted.
+fn insert_entry<K: Ord + Clone, V>(entries: &mut Vec<(K, V)>, key: &K, value: V) -> bool {
+    match entries.binary_search_by(|(elem_key, _)| elem_key.cmp(key)) {
+        Ok(index) => {
+            entries[index].1 = value;
+            false
+        }
+        Err(index) => {
+            entries.insert(index, (key.clone(), value));
+            true
+        }
+    }
+}
+
+/// An ordered cursor over a range of entries of a [`BTreeMap`], see
+/// [`BTreeMap::range`].
+pub struct Cursor<'a, K, V> {
+    map: &'a BTreeMap<K, V>,
+    // where the next leaf is looked up, `None` past the last leaf
+    next: Option<Bound<K>>,
+    end: Bound<K>,
+    batch: VecDeque<(K, V)>,
+}
+
+impl<'a, K, V> Cursor<'a, K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    /// Moves the cursor to the first entry with a key at least `key`,
+    /// keeping the end of its range.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::new();
+    /// for i in 0..10 {
+    ///     map.put(&i, ());
+    /// }
+    ///
+    /// let mut cursor = map.iter();
+    /// assert_eq!(cursor.next(), Some((0, ())));
+    /// cursor.seek(&7);
+    /// assert_eq!(cursor.next(), Some((7, ())));
+    /// ```
+    pub fn seek(&mut self, key: &K) {
+        self.batch.clear();
+        self.next = Some(Bound::Included(key.clone()));
+    }
+}
+
+impl<'a, K, V> Iterator for Cursor<'a, K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.batch.is_empty() {
+            let next = self.next.take()?;
+            let (entries, fence) = self.map.scan(next.as_ref());
+            self.batch.extend(entries);
+            self.next = fence.map(Bound::Included);
+        }
+
+        let (key, value) = self.batch.pop_front()?;
+        let in_range = match &self.end {
+            Bound::Included(end) => key <= *end,
+            Bound::Excluded(end) => key < *end,
+            Bound::Unbounded => true,
+        };
+        if !in_range {
+            self.batch.clear();
+            self.next = None;
+            return None;
+        }
+        Some((key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::BTreeMap;
+
+    #[test]
+    fn test_concurrent_put_and_remove_keep_order() {
+        let map = Arc::new(BTreeMap::new());
+
+        let writers: Vec<_> = (0..4)
+            .map(|worker| {
+                let m = Arc::clone(&map);
+                std::thread::spawn(move || {
+                    for i in 0..5000 {
+                        let key = i * 4 + worker;
+                        assert!(m.put(&key, key));
+                        if key % 3 == 0 {
+                            assert_eq!(m.remove(&key), Some(key));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let keys: Vec<_> = map.iter().map(|(key, _)| key).collect();
+        let expected: Vec<_> = (0..20_000).filter(|key| key % 3 != 0).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(map.len(), expected.len());
+        assert_eq!(map.first(), Some((1, 1)));
+        assert_eq!(map.last(), Some((19_999, 19_999)));
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Mutex;
+
+use super::recency::Recency;
+use super::sketch::FrequencySketch;
+use super::{shards_for, weigh_bytes, weigh_entry};
+use crate::collections::map::{Map, MapBuilder, MeasureSize};
+
+/// W-TinyLFU eviction state of one cache shard.
+///
+/// New entries enter a small LRU window. Entries leaving the window are
+/// only admitted to the main space if they were accessed more often than
+/// the entry they would evict from it, as estimated by a frequency sketch.
+/// The main space is a segmented LRU: entries start out on probation, and
+/// are promoted to the protected segment when accessed again.
+struct Policy<K> {
+    window: Recency<K>,
+    probation: Recency<K>,
+    protected: Recency<K>,
+    sketch: FrequencySketch,
+    window_limit: usize,
+    main_limit: usize,
+    protected_limit: usize,
+}
+
+impl<K> Policy<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new(limit: usize, expected_keys: usize) -> Self {
+        let window_limit = limit.div_ceil(100);
+        let main_limit = limit - window_limit;
+        Policy {
+            window: Recency::new(),
+            probation: Recency::new(),
+            protected: Recency::new(),
+            sketch: FrequencySketch::new(expected_keys),
+            window_limit,
+            main_limit,
+            protected_limit: main_limit / 10 * 8,
+        }
+    }
+
+    fn main_weight(&self) -> usize {
+        self.probation.weight() + self.protected.weight()
+    }
+
+    /// Moves `key` from probation to the protected segment, demoting the
+    /// least recently used protected entries back to probation.
+    fn promote(&mut self, key: &K, weight: usize) {
+        self.protected.insert(key.clone(), weight);
+        while self.protected.weight() > self.protected_limit {
+            let (demoted, weight) = self.protected.pop_lru().unwrap();
+            self.probation.insert(demoted, weight);
+        }
+    }
+
+    /// Records a hit on `key`.
+    fn on_get(&mut self, key: &K, hash: u64) {
+        self.sketch.increment(hash);
+        if let Some(weight) = self.probation.remove(key) {
+            self.promote(key, weight);
+        } else {
+            self.window.touch(key);
+            self.protected.touch(key);
+        }
+    }
+
+    /// Records a write of `key`, charged `weight`, using `hash_of` to look
+    /// up the frequency of other keys.
+    ///
+    /// # Returns
+    ///
+    /// The keys evicted to make room.
+    fn on_put<F>(&mut self, key: &K, weight: usize, hash_of: F) -> Vec<K>
+    where
+        F: Fn(&K) -> u64,
+    {
+        self.sketch.increment(hash_of(key));
+        if self.probation.remove(key).is_some() {
+            self.promote(key, weight);
+        } else if self.protected.remove(key).is_some() {
+            self.protected.insert(key.clone(), weight);
+        } else {
+            self.window.insert(key.clone(), weight);
+        }
+
+        let mut evicted = Vec::new();
+        while self.window.weight() > self.window_limit {
+            let (candidate, weight) = self.window.pop_lru().unwrap();
+            self.admit(candidate, weight, &hash_of, &mut evicted);
+        }
+        // an updated protected entry may have outgrown the main space
+        while self.main_weight() > self.main_limit {
+            let (victim, _) = match self.probation.pop_lru() {
+                Some(victim) => victim,
+                None => self.protected.pop_lru().unwrap(),
+            };
+            evicted.push(victim);
+        }
+        evicted
+    }
+
+    /// Moves `candidate` from the window to the main space if it is used
+    /// more often than every entry it has to evict there, or evicts it.
+    fn admit<F>(&mut self, candidate: K, weight: usize, hash_of: &F, evicted: &mut Vec<K>)
+    where
+        F: Fn(&K) -> u64,
+    {
+        if weight > self.main_limit {
+            evicted.push(candidate);
+            return;
+        }
+
+        let frequency = self.sketch.frequency(hash_of(&candidate));
+        while self.main_weight() + weight > self.main_limit {
+            let segment = if self.probation.weight() > 0 {
+                &mut self.probation
+            } else {
+                &mut self.protected
+            };
+            let victim = segment.peek_lru().unwrap();
+            if frequency <= self.sketch.frequency(hash_of(victim)) {
+                evicted.push(candidate);
+                return;
+            }
+            let (victim, _) = segment.pop_lru().unwrap();
+            evicted.push(victim);
+        }
+        self.probation.insert(candidate, weight);
+    }
+
+    fn remove(&mut self, key: &K) {
+        self.window.remove(key);
+        self.probation.remove(key);
+        self.protected.remove(key);
+    }
+}
+
+/// Thread-Safe cache admitting and evicting entries by their estimated
+/// access frequency, following the W-TinyLFU policy.
+///
+/// Unlike [`LruCache`](super::LruCache), a burst of entries accessed only
+/// once cannot flush frequently used entries out of the cache, which makes
+/// for much higher hit rates on skewed access patterns. Like the
+/// `LruCache`, entries are stored in a [`Map`] and every bucket of the map
+/// enforces its share of the capacity with its own eviction state.
+pub struct LfuCache<K, V, H = RandomState> {
+    map: Map<K, V, H>,
+    shards: Vec<Mutex<Policy<K>>>,
+    weigh: fn(&K, &V) -> usize,
+}
+
+impl<K, V> LfuCache<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `LfuCache` holding at most `max_entries` entries.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_entries` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cache::LfuCache;
+    /// let cache: LfuCache<u64, String> = LfuCache::new(10_000);
+    /// ```
+    pub fn new(max_entries: usize) -> Self {
+        let builder = MapBuilder::new().bucket_count(shards_for(max_entries, 64));
+        Self::with_builder(builder, max_entries)
+    }
+
+    /// Creates an empty `LfuCache` holding entries of at most `max_bytes`
+    /// bytes in total, as measured by [`MeasureSize`].
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_bytes` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cache::LfuCache;
+    /// let cache: LfuCache<u64, String> = LfuCache::with_max_bytes(64 << 20);
+    /// ```
+    pub fn with_max_bytes(max_bytes: usize) -> Self
+    where
+        K: MeasureSize,
+        V: MeasureSize,
+    {
+        let builder = MapBuilder::new().bucket_count(shards_for(max_bytes, 64 << 10));
+        Self::with_builder_and_max_bytes(builder, max_bytes)
+    }
+}
+
+impl<K, V, H> LfuCache<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    fn with_weigher(
+        builder: MapBuilder<H>,
+        limit: usize,
+        expected_keys: usize,
+        weigh: fn(&K, &V) -> usize,
+    ) -> Self {
+        if limit == 0usize {
+            panic!()
+        }
+
+        let map: Map<K, V, H> = builder.build();
+        let shard_count = map.bucket_count();
+        let shards = (0..shard_count)
+            .map(|_| {
+                Mutex::new(Policy::new(
+                    limit.div_ceil(shard_count),
+                    expected_keys.div_ceil(shard_count),
+                ))
+            })
+            .collect();
+        LfuCache { map, shards, weigh }
+    }
+
+    /// Creates an empty `LfuCache` holding at most `max_entries` entries,
+    /// stored in a map configured by `builder`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_entries` is 0.
+    pub fn with_builder(builder: MapBuilder<H>, max_entries: usize) -> Self {
+        Self::with_weigher(builder, max_entries, max_entries, weigh_entry)
+    }
+
+    /// Creates an empty `LfuCache` holding entries of at most `max_bytes`
+    /// bytes in total, stored in a map configured by `builder`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_bytes` is 0.
+    pub fn with_builder_and_max_bytes(builder: MapBuilder<H>, max_bytes: usize) -> Self
+    where
+        K: MeasureSize,
+        V: MeasureSize,
+    {
+        // frequencies are tracked for an assumed average entry of 256 bytes
+        Self::with_weigher(builder, max_bytes, max_bytes / 256, weigh_bytes)
+    }
+
+    fn shard_for(&self, key: &K) -> &Mutex<Policy<K>> {
+        &self.shards[self.map.bucket_index(key)]
+    }
+
+    /// Establishes a key value mapping, then evicts entries until the cache
+    /// is within capacity again.
+    ///
+    /// A new entry may itself be evicted right away, if it was accessed
+    /// less often than the entries it would displace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cache::LfuCache;
+    ///
+    /// let cache = LfuCache::new(100);
+    /// cache.put(&1, 'a');
+    /// assert_eq!(cache.get(&1), Some('a'));
+    /// ```
+    pub fn put(&self, key: &K, value: V) {
+        let weight = (self.weigh)(key, &value);
+        // the map is only written under the shard's lock, which keeps the
+        // eviction state and the map in agreement
+        let mut shard = self.shard_for(key).lock().unwrap();
+        self.map.put(key, value);
+        for evicted in shard.on_put(key, weight, |key| self.map.hash(key)) {
+            self.map.unmap(&evicted);
+        }
+    }
+
+    /// Returns the value mapped to `key`, recording the access.
+    ///
+    /// Misses are recorded as well, so a key looked up often enough is
+    /// admitted once it is put.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.map.get(key);
+        let mut shard = self.shard_for(key).lock().unwrap();
+        if value.is_some() {
+            shard.on_get(key, self.map.hash(key));
+        } else {
+            shard.sketch.increment(self.map.hash(key));
+        }
+        value
+    }
+
+    /// Returns the value mapped to `key` without recording the access.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    /// Returns `true` if `key` is cached, without recording the access.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    /// Removes the entry of `key` from the cache, returning its value.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut shard = self.shard_for(key).lock().unwrap();
+        shard.remove(key);
+        self.map.remove(key)
+    }
+
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LfuCache;
+
+    #[test]
+    fn test_scan_does_not_flush_frequent_entries() {
+        let cache = LfuCache::new(100);
+        for _ in 0..5 {
+            for key in 0..50u64 {
+                if cache.get(&key).is_none() {
+                    cache.put(&key, key);
+                }
+            }
+        }
+
+        // a scan of keys accessed only once
+        for key in 1000..2000u64 {
+            cache.get(&key);
+            cache.put(&key, key);
+        }
+
+        let retained = (0..50u64).filter(|key| cache.contains_key(key)).count();
+        assert!(retained >= 45, "only {} frequent keys retained", retained);
+        assert!(cache.len() <= 100);
+        let tracked: usize = cache
+            .shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard.window.weight() + shard.main_weight()
+            })
+            .sum();
+        assert_eq!(tracked, cache.len());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Mutex;
+
+use super::recency::Recency;
+use super::{shards_for, weigh_bytes, weigh_entry};
+use crate::collections::map::{Map, MapBuilder, MeasureSize};
+
+/// Thread-Safe cache evicting the least recently used entries once over
+/// capacity.
+///
+/// Entries are stored in a [`Map`], and every bucket of the map has its own
+/// recency list. Capacity is split evenly among the buckets and enforced
+/// per bucket, so eviction follows the cache-wide recency order only
+/// approximately, in exchange for lookups on different buckets never
+/// contending.
+pub struct LruCache<K, V, H = RandomState> {
+    map: Map<K, V, H>,
+    shards: Vec<Mutex<Recency<K>>>,
+    shard_limit: usize,
+    weigh: fn(&K, &V) -> usize,
+}
+
+impl<K, V> LruCache<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `LruCache` holding at most `max_entries` entries.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_entries` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cache::LruCache;
+    /// let cache: LruCache<u64, String> = LruCache::new(10_000);
+    /// ```
+    pub fn new(max_entries: usize) -> Self {
+        let builder = MapBuilder::new().bucket_count(shards_for(max_entries, 64));
+        Self::with_builder(builder, max_entries)
+    }
+
+    /// Creates an empty `LruCache` holding entries of at most `max_bytes`
+    /// bytes in total, as measured by [`MeasureSize`].
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_bytes` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cache::LruCache;
+    /// let cache: LruCache<u64, String> = LruCache::with_max_bytes(64 << 20);
+    /// ```
+    pub fn with_max_bytes(max_bytes: usize) -> Self
+    where
+        K: MeasureSize,
+        V: MeasureSize,
+    {
+        let builder = MapBuilder::new().bucket_count(shards_for(max_bytes, 64 << 10));
+        Self::with_builder_and_max_bytes(builder, max_bytes)
+    }
+}
+
+impl<K, V, H> LruCache<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    fn with_weigher(builder: MapBuilder<H>, limit: usize, weigh: fn(&K, &V) -> usize) -> Self {
+        if limit == 0usize {
+            panic!()
+        }
+
+        let map: Map<K, V, H> = builder.build();
+        let shards = (0..map.bucket_count())
+            .map(|_| Mutex::new(Recency::new()))
+            .collect::<Vec<_>>();
+        LruCache {
+            shard_limit: limit.div_ceil(shards.len()),
+            map,
+            shards,
+            weigh,
+        }
+    }
+
+    /// Creates an empty `LruCache` holding at most `max_entries` entries,
+    /// stored in a map configured by `builder`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_entries` is 0.
+    pub fn with_builder(builder: MapBuilder<H>, max_entries: usize) -> Self {
+        Self::with_weigher(builder, max_entries, weigh_entry)
+    }
+
+    /// Creates an empty `LruCache` holding entries of at most `max_bytes`
+    /// bytes in total, stored in a map configured by `builder`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_bytes` is 0.
+    pub fn with_builder_and_max_bytes(builder: MapBuilder<H>, max_bytes: usize) -> Self
+    where
+        K: MeasureSize,
+        V: MeasureSize,
+    {
+        Self::with_weigher(builder, max_bytes, weigh_bytes)
+    }
+
+    fn shard_for(&self, key: &K) -> &Mutex<Recency<K>> {
+        &self.shards[self.map.bucket_index(key)]
+    }
+
+    /// Establishes a key value mapping as the most recently used entry,
+    /// then evicts least recently used entries until the cache is within
+    /// capacity again.
+    ///
+    /// An entry larger than the whole capacity of its shard is evicted
+    /// right away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cache::LruCache;
+    ///
+    /// let cache = LruCache::new(2);
+    /// cache.put(&1, 'a');
+    /// cache.put(&2, 'b');
+    /// cache.get(&1);
+    /// cache.put(&3, 'c');
+    ///
+    /// assert_eq!(cache.get(&2), None);
+    /// assert_eq!(cache.get(&1), Some('a'));
+    /// ```
+    pub fn put(&self, key: &K, value: V) {
+        let weight = (self.weigh)(key, &value);
+        // the map is only written under the shard's lock, which keeps the
+        // recency list and the map in agreement
+        let mut shard = self.shard_for(key).lock().unwrap();
+        self.map.put(key, value);
+        shard.insert(key.clone(), weight);
+        while shard.weight() > self.shard_limit {
+            let (evicted, _) = shard.pop_lru().unwrap();
+            self.map.unmap(&evicted);
+        }
+    }
+
+    /// Returns the value mapped to `key` and marks the entry as the most
+    /// recently used.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.map.get(key)?;
+        self.shard_for(key).lock().unwrap().touch(key);
+        Some(value)
+    }
+
+    /// Returns the value mapped to `key` without marking it as used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cache::LruCache;
+    ///
+    /// let cache = LruCache::new(2);
+    /// cache.put(&1, 'a');
+    /// cache.put(&2, 'b');
+    /// cache.peek(&1);
+    /// cache.put(&3, 'c');
+    ///
+    /// assert_eq!(cache.peek(&1), None);
+    /// ```
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    /// Returns `true` if `key` is cached, without marking it as used.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    /// Removes the entry of `key` from the cache, returning its value.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut shard = self.shard_for(key).lock().unwrap();
+        shard.remove(key);
+        self.map.remove(key)
+    }
+
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LruCache;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_puts_stay_within_capacity() {
+        let cache = Arc::new(LruCache::new(1000));
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..5000u64 {
+                        cache.put(&(thread * 5000 + i), i);
+                        cache.get(&(thread * 5000 + i / 2));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let shards = cache.shards.len();
+        assert!(cache.len() <= 1000usize.div_ceil(shards) * shards);
+        let tracked: usize = cache
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().weight())
+            .sum();
+        assert_eq!(tracked, cache.len());
+    }
+}
@@ -0,0 +1,28 @@
+mod lfu;
+mod lru;
+pub(crate) mod recency;
+mod sketch;
+
+pub use self::lfu::LfuCache;
+pub use self::lru::LruCache;
+
+use std::mem;
+
+use crate::collections::map::{MeasureSize, DEFAULT_BUCKET_COUNT};
+
+/// Returns the number of shards for a cache holding `limit` units of
+/// capacity, keeping shards large enough for eviction to stay close to the
+/// cache-wide order.
+fn shards_for(limit: usize, units_per_shard: usize) -> usize {
+    (limit / units_per_shard).clamp(1, DEFAULT_BUCKET_COUNT)
+}
+
+/// Charges an entry as one unit of capacity.
+fn weigh_entry<K, V>(_: &K, _: &V) -> usize {
+    1
+}
+
+/// Charges an entry by the memory of its key and value.
+pub(crate) fn weigh_bytes<K: MeasureSize, V: MeasureSize>(key: &K, value: &V) -> usize {
+    mem::size_of::<K>() + key.heap_size() + mem::size_of::<V>() + value.heap_size()
+}
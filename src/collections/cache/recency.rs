@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Recency order of the keys of one cache shard, along with the capacity
+/// they are charged.
+pub struct Recency<K> {
+    entries: HashMap<K, (u64, usize)>,
+    order: BTreeMap<u64, K>,
+    weight: usize,
+    tick: u64,
+}
+
+impl<K> Recency<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        Recency {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            weight: 0,
+            tick: 0,
+        }
+    }
+
+    /// Returns the capacity charged for every key of the shard.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Marks `key` as the most recently used, if tracked.
+    pub fn touch(&mut self, key: &K) {
+        let tick = self.next_tick();
+        if let Some((used, _)) = self.entries.get_mut(key) {
+            let key = self.order.remove(used).unwrap();
+            *used = tick;
+            self.order.insert(tick, key);
+        }
+    }
+
+    /// Tracks `key` as the most recently used, charged `weight`.
+    pub fn insert(&mut self, key: K, weight: usize) {
+        self.remove(&key);
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, (tick, weight));
+        self.weight += weight;
+    }
+
+    /// Stops tracking `key`, returning the capacity it was charged.
+    pub fn remove(&mut self, key: &K) -> Option<usize> {
+        let (used, weight) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        self.weight -= weight;
+        Some(weight)
+    }
+
+    /// Returns the least recently used key.
+    pub fn peek_lru(&self) -> Option<&K> {
+        self.order.values().next()
+    }
+
+    /// Stops tracking the least recently used key, returning it along with
+    /// the capacity it was charged.
+    pub fn pop_lru(&mut self) -> Option<(K, usize)> {
+        let (_, key) = self.order.pop_first()?;
+        let (_, weight) = self.entries.remove(&key).unwrap();
+        self.weight -= weight;
+        Some((key, weight))
+    }
+}
@@ -0,0 +1,67 @@
+/// Count-min sketch estimating how often keys were accessed recently.
+///
+/// Counters are 4 bits wide and halved every time the number of recorded
+/// accesses reaches ten times the width of the sketch, so the estimates
+/// favour recent popularity over all-time popularity.
+pub struct FrequencySketch {
+    rows: [Vec<u8>; FrequencySketch::DEPTH],
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    const DEPTH: usize = 4;
+    const MAX_COUNT: u8 = 15;
+    const SEEDS: [u64; FrequencySketch::DEPTH] = [
+        0x9e37_79b9_7f4a_7c15,
+        0xc2b2_ae3d_27d4_eb4f,
+        0x1656_67b1_9e37_79f9,
+        0x85eb_ca77_c2b2_ae63,
+    ];
+
+    /// Creates a sketch sized for tracking about `expected_keys` keys.
+    pub fn new(expected_keys: usize) -> Self {
+        let width = expected_keys.clamp(16, 1 << 16).next_power_of_two();
+        FrequencySketch {
+            rows: std::array::from_fn(|_| vec![0; width]),
+            mask: width - 1,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn indices(&self, hash: u64) -> impl Iterator<Item = usize> + '_ {
+        Self::SEEDS.iter().map(move |seed| {
+            let mixed = (hash ^ seed).wrapping_mul(0x2545_f491_4f6c_dd1d);
+            (mixed >> 32) as usize & self.mask
+        })
+    }
+
+    /// Returns the estimated number of recent accesses of the key with the
+    /// given `hash`.
+    pub fn frequency(&self, hash: u64) -> u8 {
+        self.indices(hash)
+            .zip(self.rows.iter())
+            .map(|(index, row)| row[index])
+            .min()
+            .unwrap()
+    }
+
+    /// Records an access of the key with the given `hash`.
+    pub fn increment(&mut self, hash: u64) {
+        let indices: Vec<usize> = self.indices(hash).collect();
+        for (index, row) in indices.into_iter().zip(self.rows.iter_mut()) {
+            row[index] = (row[index] + 1).min(Self::MAX_COUNT);
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.rows
+                .iter_mut()
+                .flat_map(|row| row.iter_mut())
+                .for_each(|count| *count /= 2);
+            self.additions /= 2;
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type CounterBucket<K> = PriorityRwLock<Vec<(K, AtomicU64)>>;
+
+/// Thread-Safe set of named `u64` counters, implemented as hash table.
+///
+/// Every counter is an atomic cell updated in place under the bucket's read
+/// lock, so concurrent updates of existing counters never wait for each
+/// other and no value is ever cloned. The bucket's write lock is only taken
+/// to create or remove a counter.
+pub struct Counters<K, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<CounterBucket<K>>,
+    lock_policy: LockPolicy,
+}
+
+impl<K> Default for Counters<K, RandomState>
+where
+    K: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Counters<K, RandomState>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates an empty `Counters`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::counter::Counters;
+    /// let counters: Counters<&str> = Counters::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `Counters` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::counter::Counters;
+    /// let counters: Counters<&str> = Counters::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<K, H> Counters<K, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `Counters` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Vec::new()));
+
+        Counters {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+        }
+    }
+
+    fn get_bucket(&self, key: &K) -> &CounterBucket<K> {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        &self.buckets[hash % self.buckets.len()]
+    }
+
+    /// Applies `update` to the counter of `key`, creating the counter at 0
+    /// first if it does not exist yet.
+    ///
+    /// # Returns
+    ///
+    /// The value of the counter after the update.
+    fn update<F>(&self, key: &K, update: F) -> u64
+    where
+        F: Fn(&AtomicU64) -> u64,
+    {
+        let bucket = self.get_bucket(key);
+        {
+            let counters = bucket.read(self.lock_policy.read);
+            if let Some((_, counter)) = counters.iter().find(|(elem_key, _)| elem_key == key) {
+                return update(counter);
+            }
+        }
+
+        let mut counters = bucket.write(self.lock_policy.write);
+        let index = match counters.iter().position(|(elem_key, _)| elem_key == key) {
+            Some(index) => index,
+            None => {
+                counters.push((key.clone(), AtomicU64::new(0)));
+                counters.len() - 1
+            }
+        };
+        update(&counters[index].1)
+    }
+
+    /// Adds `delta` to the counter of `key`, creating it if needed. The
+    /// counter wraps around on overflow.
+    ///
+    /// # Returns
+    ///
+    /// The value of the counter after the increment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::counter::Counters;
+    ///
+    /// let counters = Counters::new();
+    /// assert_eq!(counters.incr(&"requests", 1), 1);
+    /// assert_eq!(counters.incr(&"requests", 2), 3);
+    /// ```
+    pub fn incr(&self, key: &K, delta: u64) -> u64 {
+        self.update(key, |counter| {
+            counter
+                .fetch_add(delta, Ordering::SeqCst)
+                .wrapping_add(delta)
+        })
+    }
+
+    /// Subtracts `delta` from the counter of `key`, creating it if needed.
+    /// The counter saturates at 0.
+    ///
+    /// # Returns
+    ///
+    /// The value of the counter after the decrement.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::counter::Counters;
+    ///
+    /// let counters = Counters::new();
+    /// counters.incr(&"connections", 2);
+    /// assert_eq!(counters.decr(&"connections", 1), 1);
+    /// assert_eq!(counters.decr(&"connections", 5), 0);
+    /// ```
+    pub fn decr(&self, key: &K, delta: u64) -> u64 {
+        self.update(key, |counter| {
+            let previous = counter
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                    Some(value.saturating_sub(delta))
+                })
+                .unwrap();
+            previous.saturating_sub(delta)
+        })
+    }
+
+    /// Returns the value of the counter of `key`, or 0 if it does not
+    /// exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::counter::Counters;
+    ///
+    /// let counters = Counters::new();
+    /// assert_eq!(counters.get(&"requests"), 0);
+    /// ```
+    pub fn get(&self, key: &K) -> u64 {
+        let counters = self.get_bucket(key).read(self.lock_policy.read);
+        counters
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map_or(0, |(_, counter)| counter.load(Ordering::SeqCst))
+    }
+
+    /// Sets the counter of `key` back to 0, returning its previous value.
+    pub fn reset(&self, key: &K) -> u64 {
+        let counters = self.get_bucket(key).read(self.lock_policy.read);
+        counters
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map_or(0, |(_, counter)| counter.swap(0, Ordering::SeqCst))
+    }
+
+    /// Removes the counter of `key`, returning its value.
+    pub fn remove(&self, key: &K) -> Option<u64> {
+        let mut counters = self.get_bucket(key).write(self.lock_policy.write);
+        let index = counters.iter().position(|(elem_key, _)| elem_key == key)?;
+        Some(counters.swap_remove(index).1.into_inner())
+    }
+
+    /// Returns the values of all counters.
+    ///
+    /// Buckets are read one after another, so the snapshot is consistent per
+    /// bucket but not across buckets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::counter::Counters;
+    ///
+    /// let counters = Counters::new();
+    /// counters.incr(&"hits", 3);
+    /// counters.incr(&"misses", 1);
+    ///
+    /// let snapshot = counters.snapshot();
+    /// assert_eq!(snapshot[&"hits"], 3);
+    /// assert_eq!(snapshot[&"misses"], 1);
+    /// ```
+    pub fn snapshot(&self) -> HashMap<K, u64> {
+        let mut snapshot = HashMap::new();
+        for bucket in &self.buckets {
+            let counters = bucket.read(self.lock_policy.read);
+            snapshot.extend(
+                counters
+                    .iter()
+                    .map(|(key, counter)| (key.clone(), counter.load(Ordering::SeqCst))),
+            );
+        }
+        snapshot
+    }
+
+    /// Returns the number of counters.
+    pub fn len(&self) -> usize {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.read(self.lock_policy.read).len())
+            .sum()
+    }
+
+    /// Returns `true` if there are no counters.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Counters;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_increments_are_not_lost() {
+        let counters = Arc::new(Counters::with_bucket_count(3));
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let counters = counters.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000u64 {
+                        counters.incr(&(i % 10), 1);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.len(), 10);
+        assert!(snapshot.values().all(|value| *value == 8 * 1000));
+    }
+}
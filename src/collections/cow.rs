@@ -0,0 +1,311 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type CowBucket<K, V> = PriorityRwLock<Arc<Vec<(K, V)>>>;
+
+/// Buckets of a [`CowMap`], shared by every handle forked from it until one
+/// of them writes.
+struct Table<K, V> {
+    buckets: Vec<CowBucket<K, V>>,
+    len: AtomicUsize,
+}
+
+impl<K, V> Table<K, V> {
+    /// Returns a table sharing the entries of every bucket with this one.
+    fn fork(&self, lock_policy: LockPolicy) -> Self {
+        Table {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| PriorityRwLock::new(Arc::clone(&bucket.read(lock_policy.read))))
+                .collect(),
+            len: AtomicUsize::new(self.len.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+/// Thread-Safe map implemented as hash table, whose handles are forked
+/// copy-on-write.
+///
+/// Cloning a `CowMap` is O(1) and yields an independent map with the same
+/// entries: writes through either handle are never visible through the
+/// other. The first write through a handle sharing its buckets gives it its
+/// own bucket table, pointing to the same bucket entries, and a write to a
+/// bucket copies the entries of that bucket alone if they are still shared.
+/// Forks are thus cheap enough for what-if computations on a whole dataset.
+///
+/// Every handle is itself safe to share between threads. Cloning a handle
+/// waits for the writes in progress through it.
+pub struct CowMap<K, V, H = RandomState> {
+    hash_builder: H,
+    table: PriorityRwLock<Arc<Table<K, V>>>,
+    lock_policy: LockPolicy,
+}
+
+impl<K, V> Default for CowMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> CowMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `CowMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cow::CowMap;
+    /// let map: CowMap<&str, i32> = CowMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `CowMap` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::cow::CowMap;
+    /// let map: CowMap<&str, i32> = CowMap::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<K, V, H> CowMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `CowMap` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Arc::new(Vec::new())));
+
+        CowMap {
+            hash_builder,
+            table: PriorityRwLock::new(Arc::new(Table {
+                buckets,
+                len: AtomicUsize::new(0),
+            })),
+            lock_policy: LockPolicy::default(),
+        }
+    }
+
+    fn bucket_index(&self, key: &K, table: &Table<K, V>) -> usize {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        hash % table.buckets.len()
+    }
+
+    /// Runs `f` on the entries of the bucket of `key`.
+    fn with_bucket<F, R>(&self, key: &K, f: F) -> R
+    where
+        F: FnOnce(&[(K, V)]) -> R,
+    {
+        let table = self.table.read(self.lock_policy.read);
+        let bucket = table.buckets[self.bucket_index(key, &table)].read(self.lock_policy.read);
+        f(&bucket)
+    }
+
+    /// Runs `f` on the entries of the bucket of `key`, copied first if
+    /// shared with another handle, and on the entry count of the table.
+    fn with_bucket_mut<F, R>(&self, key: &K, f: F) -> R
+    where
+        F: FnOnce(&mut Vec<(K, V)>, &AtomicUsize) -> R,
+    {
+        loop {
+            {
+                let table = self.table.read(self.lock_policy.read);
+                // handles are only cloned under the write lock, so the table
+                // stays ours while the read lock is held
+                if Arc::strong_count(&table) == 1 {
+                    let index = self.bucket_index(key, &table);
+                    let mut bucket = table.buckets[index].write(self.lock_policy.write);
+                    return f(Arc::make_mut(&mut bucket), &table.len);
+                }
+            }
+
+            let mut table = self.table.write(self.lock_policy.write);
+            if Arc::strong_count(&table) > 1 {
+                *table = Arc::new(table.fork(self.lock_policy));
+            }
+        }
+    }
+
+    /// Establishes a key value mapping for the key value pair, returning
+    /// the value previously mapped to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cow::CowMap;
+    ///
+    /// let map = CowMap::new();
+    /// assert_eq!(map.put(&"a", 1), None);
+    /// assert_eq!(map.put(&"a", 2), Some(1));
+    /// ```
+    pub fn put(&self, key: &K, value: V) -> Option<V> {
+        self.with_bucket_mut(key, |entries, len| {
+            match entries.iter_mut().find(|(elem_key, _)| elem_key == key) {
+                Some((_, existing)) => Some(std::mem::replace(existing, value)),
+                None => {
+                    entries.push((key.clone(), value));
+                    len.fetch_add(1, Ordering::SeqCst);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Returns the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.with_bucket(key, |entries| {
+            entries
+                .iter()
+                .find(|(elem_key, _)| elem_key == key)
+                .map(|(_, value)| value.clone())
+        })
+    }
+
+    /// Returns `true` if the `CowMap` contains a mapping for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.with_bucket(key, |entries| {
+            entries.iter().any(|(elem_key, _)| elem_key == key)
+        })
+    }
+
+    /// Removes the mapping for `key`, returning its value if it was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cow::CowMap;
+    ///
+    /// let map = CowMap::new();
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.remove(&1), Some('a'));
+    /// assert_eq!(map.remove(&1), None);
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        // avoid copying a shared bucket that does not contain the key
+        if !self.contains_key(key) {
+            return None;
+        }
+        self.with_bucket_mut(key, |entries, len| {
+            let index = entries.iter().position(|(elem_key, _)| elem_key == key)?;
+            len.fetch_sub(1, Ordering::SeqCst);
+            Some(entries.swap_remove(index).1)
+        })
+    }
+
+    /// Returns the number of entries in the `CowMap`.
+    pub fn len(&self) -> usize {
+        self.table
+            .read(self.lock_policy.read)
+            .len
+            .load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `CowMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V, H> Clone for CowMap<K, V, H>
+where
+    H: Clone,
+{
+    /// Forks the `CowMap`, in O(1).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::cow::CowMap;
+    ///
+    /// let live = CowMap::new();
+    /// live.put(&"price", 100);
+    ///
+    /// let what_if = live.clone();
+    /// what_if.put(&"price", 120);
+    ///
+    /// assert_eq!(live.get(&"price"), Some(100));
+    /// assert_eq!(what_if.get(&"price"), Some(120));
+    /// ```
+    fn clone(&self) -> Self {
+        let table = self.table.write(self.lock_policy.write);
+        CowMap {
+            hash_builder: self.hash_builder.clone(),
+            table: PriorityRwLock::new(Arc::clone(&table)),
+            lock_policy: self.lock_policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_forks_never_see_each_others_writes() {
+        let base = Arc::new(CowMap::new());
+        for key in 0..1000u64 {
+            base.put(&key, 0u64);
+        }
+
+        let workers: Vec<_> = (1..=4u64)
+            .map(|fork_id| {
+                let base = base.clone();
+                thread::spawn(move || {
+                    let fork = (*base).clone();
+                    for key in 0..1000u64 {
+                        fork.put(&key, fork_id);
+                        base.put(&(1000 + key * 4 + fork_id), 0);
+                    }
+                    fork.remove(&0);
+                    assert!((1..1000u64).all(|key| fork.get(&key) == Some(fork_id)));
+                    fork.len()
+                })
+            })
+            .collect();
+        for worker in workers {
+            let len = worker.join().unwrap();
+            // the fork was taken with 1000 base entries or more
+            assert!(len >= 999);
+        }
+
+        assert_eq!(base.len(), 5000);
+        assert!((0..1000u64).all(|key| base.get(&key) == Some(0)));
+    }
+}
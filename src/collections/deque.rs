@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Thread-Safe double-ended queue.
+///
+/// Besides pushing and popping at both ends, a `Deque` supports the
+/// operations of a work-stealing scheduler: the owning worker pushes and
+/// pops jobs at the back, while idle workers [`steal`](Deque::steal) from
+/// the front, taking the oldest jobs without waiting behind the owner.
+pub struct Deque<T> {
+    elements: PriorityRwLock<VecDeque<T>>,
+    lock_policy: LockPolicy,
+}
+
+impl<T> Default for Deque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deque<T> {
+    /// Creates an empty `Deque`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::deque::Deque;
+    /// let deque: Deque<u64> = Deque::new();
+    /// ```
+    pub fn new() -> Self {
+        Deque {
+            elements: PriorityRwLock::new(VecDeque::new()),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    /// Appends `value` to the front of the deque.
+    pub fn push_front(&self, value: T) {
+        self.elements
+            .write(self.lock_policy.write)
+            .push_front(value);
+    }
+
+    /// Appends `value` to the back of the deque.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::deque::Deque;
+    ///
+    /// let deque = Deque::new();
+    /// deque.push_back(1);
+    /// deque.push_front(0);
+    /// assert_eq!(deque.pop_front(), Some(0));
+    /// ```
+    pub fn push_back(&self, value: T) {
+        self.elements.write(self.lock_policy.write).push_back(value);
+    }
+
+    /// Removes the element at the front of the deque, if any.
+    pub fn pop_front(&self) -> Option<T> {
+        self.elements.write(self.lock_policy.write).pop_front()
+    }
+
+    /// Removes the element at the back of the deque, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::deque::Deque;
+    ///
+    /// let deque = Deque::new();
+    /// deque.push_back(1);
+    /// deque.push_back(2);
+    /// assert_eq!(deque.pop_back(), Some(2));
+    /// ```
+    pub fn pop_back(&self) -> Option<T> {
+        self.elements.write(self.lock_policy.write).pop_back()
+    }
+
+    /// Attempts to take the element at the front of the deque on behalf of
+    /// another worker.
+    ///
+    /// Unlike [`Deque::pop_front`], stealing never waits for the lock: a
+    /// thief finding the deque busy moves on to another victim instead of
+    /// queueing behind the owner.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::deque::{Deque, Steal};
+    ///
+    /// let deque = Deque::new();
+    /// assert_eq!(deque.steal(), Steal::Empty);
+    ///
+    /// deque.push_back("job");
+    /// assert_eq!(deque.steal(), Steal::Success("job"));
+    /// ```
+    pub fn steal(&self) -> Steal<T> {
+        match self.elements.try_write() {
+            Some(mut elements) => match elements.pop_front() {
+                Some(value) => Steal::Success(value),
+                None => Steal::Empty,
+            },
+            None => Steal::Retry,
+        }
+    }
+
+    /// Takes up to half of the elements at the front of the deque and
+    /// appends them to the back of `dest`, returning the first one taken.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::deque::{Deque, Steal};
+    ///
+    /// let victim = Deque::new();
+    /// (0..4).for_each(|job| victim.push_back(job));
+    ///
+    /// let thief = Deque::new();
+    /// assert_eq!(victim.steal_batch_and_pop(&thief), Steal::Success(0));
+    /// assert_eq!(thief.pop_front(), Some(1));
+    /// assert_eq!(victim.len(), 2);
+    /// ```
+    pub fn steal_batch_and_pop(&self, dest: &Deque<T>) -> Steal<T> {
+        let mut batch = match self.elements.try_write() {
+            Some(mut elements) => {
+                let count = elements.len().div_ceil(2);
+                elements.drain(..count).collect::<VecDeque<T>>()
+            }
+            None => return Steal::Retry,
+        };
+        match batch.pop_front() {
+            Some(value) => {
+                if !batch.is_empty() {
+                    dest.elements.write(dest.lock_policy.write).extend(batch);
+                }
+                Steal::Success(value)
+            }
+            None => Steal::Empty,
+        }
+    }
+
+    /// Returns the number of elements in the deque.
+    pub fn len(&self) -> usize {
+        self.elements.read(self.lock_policy.read).len()
+    }
+
+    /// Returns `true` if the deque contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Outcome of a [`Deque::steal`] attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// An element was stolen.
+    Success(T),
+    /// The deque was locked by another thread; the steal may be retried.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    /// Returns the stolen element, if any.
+    pub fn success(self) -> Option<T> {
+        match self {
+            Steal::Success(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the steal should be retried.
+    pub fn is_retry(&self) -> bool {
+        matches!(self, Steal::Retry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deque, Steal};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_owner_and_thieves_take_every_job_once() {
+        let deque = Arc::new(Deque::new());
+        (0..10_000u64).for_each(|job| deque.push_back(job));
+
+        let thieves: Vec<_> = (0..3)
+            .map(|_| {
+                let deque = deque.clone();
+                thread::spawn(move || {
+                    let mut stolen = Vec::new();
+                    loop {
+                        match deque.steal() {
+                            Steal::Success(job) => stolen.push(job),
+                            Steal::Retry => thread::yield_now(),
+                            Steal::Empty => return stolen,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut taken = Vec::new();
+        while let Some(job) = deque.pop_back() {
+            taken.push(job);
+        }
+        for thief in thieves {
+            taken.extend(thief.join().unwrap());
+        }
+        taken.sort_unstable();
+
+        assert_eq!(taken, (0..10_000).collect::<Vec<u64>>());
+    }
+}
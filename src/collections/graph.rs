@@ -0,0 +1,449 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLockWriteGuard;
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Nodes of a bucket, each with its outgoing edges.
+type Adjacency<N, E> = Vec<(N, Vec<(N, E)>)>;
+
+/// Thread-Safe directed graph, storing every node with its adjacency list
+/// in a hash table.
+///
+/// Nodes are identified by value and edges carry a weight of type `E`,
+/// use `()` for unweighted graphs. Edges are added and removed in place
+/// under the write locks of the buckets of both endpoints, taken in bucket
+/// order, while [`Graph::neighbors`] and traversals only take read locks.
+pub struct Graph<N, E, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<PriorityRwLock<Adjacency<N, E>>>,
+    lock_policy: LockPolicy,
+    node_count: AtomicUsize,
+    edge_count: AtomicUsize,
+}
+
+impl<N, E> Default for Graph<N, E, RandomState>
+where
+    N: Hash + Eq + Clone,
+    E: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N, E> Graph<N, E, RandomState>
+where
+    N: Hash + Eq + Clone,
+    E: Clone,
+{
+    /// Creates an empty `Graph`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::graph::Graph;
+    /// let graph: Graph<&str, ()> = Graph::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `Graph` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::graph::Graph;
+    /// let graph: Graph<u64, f32> = Graph::with_bucket_count(64);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<N, E, H> Graph<N, E, H>
+where
+    N: Hash + Eq + Clone,
+    E: Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `Graph` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the nodes.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Vec::new()));
+
+        Graph {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+            node_count: AtomicUsize::new(0),
+            edge_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn bucket_index(&self, node: &N) -> usize {
+        self.hash_builder.hash_one(node) as usize % self.buckets.len()
+    }
+
+    /// Adds `node` to `bucket` unless present, returning its position.
+    fn insert_node(&self, bucket: &mut Adjacency<N, E>, node: &N) -> usize {
+        match bucket.iter().position(|(elem, _)| elem == node) {
+            Some(position) => position,
+            None => {
+                bucket.push((node.clone(), Vec::new()));
+                self.node_count.fetch_add(1, Ordering::SeqCst);
+                bucket.len() - 1
+            }
+        }
+    }
+
+    /// Adds `node` to the graph.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `node` was not in the graph before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph: Graph<&str, ()> = Graph::new();
+    /// assert!(graph.add_node(&"a"));
+    /// assert!(!graph.add_node(&"a"));
+    /// ```
+    pub fn add_node(&self, node: &N) -> bool {
+        let mut bucket = self.buckets[self.bucket_index(node)].write(self.lock_policy.write);
+        let node_count = bucket.len();
+        self.insert_node(&mut bucket, node);
+        bucket.len() > node_count
+    }
+
+    /// Returns `true` if `node` is in the graph.
+    pub fn contains_node(&self, node: &N) -> bool {
+        let bucket = self.buckets[self.bucket_index(node)].read(self.lock_policy.read);
+        bucket.iter().any(|(elem, _)| elem == node)
+    }
+
+    /// Write-locks the buckets of `from` and `to` in bucket order, returning
+    /// the guard of `from`'s bucket and, if it differs, of `to`'s bucket.
+    #[allow(clippy::type_complexity)]
+    fn lock_endpoints(
+        &self,
+        from: &N,
+        to: &N,
+    ) -> (
+        RwLockWriteGuard<'_, Adjacency<N, E>>,
+        Option<RwLockWriteGuard<'_, Adjacency<N, E>>>,
+    ) {
+        let (from, to) = (self.bucket_index(from), self.bucket_index(to));
+        let lock = |index: usize| self.buckets[index].write(self.lock_policy.write);
+        if from == to {
+            (lock(from), None)
+        } else if from < to {
+            let from = lock(from);
+            (from, Some(lock(to)))
+        } else {
+            let to = lock(to);
+            (lock(from), Some(to))
+        }
+    }
+
+    /// Adds an edge from `from` to `to` with `weight`, adding the nodes if
+    /// missing, and returns the weight of the edge it replaced, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph = Graph::new();
+    /// assert_eq!(graph.add_edge(&"a", &"b", 1), None);
+    /// assert_eq!(graph.add_edge(&"a", &"b", 2), Some(1));
+    /// assert!(graph.contains_node(&"b"));
+    /// ```
+    pub fn add_edge(&self, from: &N, to: &N, weight: E) -> Option<E> {
+        let (mut from_bucket, mut to_bucket) = self.lock_endpoints(from, to);
+        match to_bucket.as_mut() {
+            Some(to_bucket) => self.insert_node(to_bucket, to),
+            None => self.insert_node(&mut from_bucket, to),
+        };
+
+        let position = self.insert_node(&mut from_bucket, from);
+        let edges = &mut from_bucket[position].1;
+        match edges.iter_mut().find(|(target, _)| target == to) {
+            Some((_, existing)) => Some(std::mem::replace(existing, weight)),
+            None => {
+                edges.push((to.clone(), weight));
+                self.edge_count.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+
+    /// Removes the edge from `from` to `to`, returning its weight. The
+    /// nodes stay in the graph.
+    pub fn remove_edge(&self, from: &N, to: &N) -> Option<E> {
+        let mut bucket = self.buckets[self.bucket_index(from)].write(self.lock_policy.write);
+        let (_, edges) = bucket.iter_mut().find(|(elem, _)| elem == from)?;
+        let position = edges.iter().position(|(target, _)| target == to)?;
+        self.edge_count.fetch_sub(1, Ordering::SeqCst);
+        Some(edges.remove(position).1)
+    }
+
+    /// Removes `node` along with its outgoing and incoming edges.
+    ///
+    /// Finding the incoming edges takes the write lock of every bucket.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `node` was in the graph.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph = Graph::new();
+    /// graph.add_edge(&"a", &"b", ());
+    /// graph.add_edge(&"b", &"c", ());
+    ///
+    /// assert!(graph.remove_node(&"b"));
+    /// assert!(graph.neighbors(&"a").is_empty());
+    /// assert_eq!(graph.edge_count(), 0);
+    /// ```
+    pub fn remove_node(&self, node: &N) -> bool {
+        let mut buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.write(self.lock_policy.write))
+            .collect();
+
+        let bucket = &mut buckets[self.bucket_index(node)];
+        let position = match bucket.iter().position(|(elem, _)| elem == node) {
+            Some(position) => position,
+            None => return false,
+        };
+        let (_, outgoing) = bucket.swap_remove(position);
+        self.node_count.fetch_sub(1, Ordering::SeqCst);
+
+        let mut removed = outgoing.len();
+        for bucket in buckets.iter_mut() {
+            for (_, edges) in bucket.iter_mut() {
+                let count = edges.len();
+                edges.retain(|(target, _)| target != node);
+                removed += count - edges.len();
+            }
+        }
+        self.edge_count.fetch_sub(removed, Ordering::SeqCst);
+        true
+    }
+
+    /// Returns the targets and weights of the edges leaving `node`, in the
+    /// order they were added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph = Graph::new();
+    /// graph.add_edge(&1, &2, "depends");
+    /// graph.add_edge(&1, &3, "depends");
+    /// assert_eq!(graph.neighbors(&1), vec![(2, "depends"), (3, "depends")]);
+    /// assert!(graph.neighbors(&2).is_empty());
+    /// ```
+    pub fn neighbors(&self, node: &N) -> Vec<(N, E)> {
+        let bucket = self.buckets[self.bucket_index(node)].read(self.lock_policy.read);
+        bucket
+            .iter()
+            .find(|(elem, _)| elem == node)
+            .map(|(_, edges)| edges.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns an iterator over the nodes reachable from `start`, including
+    /// `start` itself, in breadth-first order.
+    ///
+    /// Adjacency lists are read as the traversal reaches their node, so
+    /// edges added or removed concurrently may or may not be followed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph = Graph::new();
+    /// graph.add_edge(&"a", &"b", ());
+    /// graph.add_edge(&"a", &"c", ());
+    /// graph.add_edge(&"b", &"d", ());
+    /// graph.add_edge(&"d", &"a", ());
+    ///
+    /// let order: Vec<_> = graph.bfs(&"a").collect();
+    /// assert_eq!(order, vec!["a", "b", "c", "d"]);
+    /// ```
+    pub fn bfs(&self, start: &N) -> Bfs<'_, N, E, H> {
+        let start = self.traversal_start(start);
+        Bfs {
+            graph: self,
+            queue: start.clone().into_iter().collect(),
+            visited: start.into_iter().collect(),
+        }
+    }
+
+    /// Returns an iterator over the nodes reachable from `start`, including
+    /// `start` itself, in depth-first order. See [`Graph::bfs`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::graph::Graph;
+    ///
+    /// let graph = Graph::new();
+    /// graph.add_edge(&"a", &"b", ());
+    /// graph.add_edge(&"a", &"c", ());
+    /// graph.add_edge(&"b", &"d", ());
+    ///
+    /// let order: Vec<_> = graph.dfs(&"a").collect();
+    /// assert_eq!(order, vec!["a", "b", "d", "c"]);
+    /// ```
+    pub fn dfs(&self, start: &N) -> Dfs<'_, N, E, H> {
+        Dfs {
+            graph: self,
+            stack: self.traversal_start(start).into_iter().collect(),
+            visited: HashSet::new(),
+        }
+    }
+
+    fn traversal_start(&self, start: &N) -> Option<N> {
+        self.contains_node(start).then(|| start.clone())
+    }
+
+    /// Returns the number of nodes in the graph.
+    pub fn node_count(&self) -> usize {
+        self.node_count.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of edges in the graph.
+    pub fn edge_count(&self) -> usize {
+        self.edge_count.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the graph contains no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.node_count() == 0
+    }
+}
+
+/// A breadth-first traversal of a [`Graph`], see [`Graph::bfs`].
+pub struct Bfs<'a, N, E, H> {
+    graph: &'a Graph<N, E, H>,
+    queue: VecDeque<N>,
+    visited: HashSet<N>,
+}
+
+impl<'a, N, E, H> Iterator for Bfs<'a, N, E, H>
+where
+    N: Hash + Eq + Clone,
+    E: Clone,
+    H: BuildHasher,
+{
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        let node = self.queue.pop_front()?;
+        for (target, _) in self.graph.neighbors(&node) {
+            if self.visited.insert(target.clone()) {
+                self.queue.push_back(target);
+            }
+        }
+        Some(node)
+    }
+}
+
+/// A depth-first traversal of a [`Graph`], see [`Graph::dfs`].
+pub struct Dfs<'a, N, E, H> {
+    graph: &'a Graph<N, E, H>,
+    stack: Vec<N>,
+    visited: HashSet<N>,
+}
+
+impl<'a, N, E, H> Iterator for Dfs<'a, N, E, H>
+where
+    N: Hash + Eq + Clone,
+    E: Clone,
+    H: BuildHasher,
+{
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        loop {
+            let node = self.stack.pop()?;
+            if !self.visited.insert(node.clone()) {
+                continue;
+            }
+            // push in reverse so that neighbors are visited in edge order
+            for (target, _) in self.graph.neighbors(&node).into_iter().rev() {
+                if !self.visited.contains(&target) {
+                    self.stack.push(target);
+                }
+            }
+            return Some(node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Graph;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_edges_form_connected_graph() {
+        let graph = Arc::new(Graph::with_bucket_count(8));
+
+        // every thread links a quarter of a chain and removes a shortcut
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let graph = graph.clone();
+                thread::spawn(move || {
+                    for node in (thread * 250)..(thread * 250 + 250) {
+                        graph.add_edge(&node, &(node + 1), node);
+                        graph.add_edge(&node, &0, node);
+                        graph.remove_edge(&node, &0);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(graph.node_count(), 1001);
+        assert_eq!(graph.edge_count(), 1000);
+        assert!(graph.bfs(&0).eq(0..=1000));
+        assert!(graph.dfs(&0).eq(0..=1000));
+
+        assert!(graph.remove_node(&500));
+        assert_eq!(graph.bfs(&0).count(), 500);
+        assert_eq!(graph.edge_count(), 998);
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type Chunk<T> = PriorityRwLock<VecDeque<T>>;
+
+/// Thread-Safe list with positional access, implemented as an unrolled
+/// linked list.
+///
+/// Elements are stored in chunks of up to [`List::CHUNK`] elements, each
+/// behind its own lock. Positional operations walk the chunks from the
+/// front, locking the next chunk before releasing the previous one, so that
+/// concurrent operations never observe each other half-way, while pushes
+/// and pops at either end only lock the chunk at that end. Chunks are only
+/// split, added or removed under an exclusive lock on the whole list.
+pub struct List<T> {
+    chunks: PriorityRwLock<Vec<Chunk<T>>>,
+    len: AtomicUsize,
+    lock_policy: LockPolicy,
+}
+
+impl<T> Default for List<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> List<T>
+where
+    T: Clone,
+{
+    /// Maximum number of elements of a chunk.
+    pub const CHUNK: usize = 64;
+
+    /// Creates an empty `List`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::list::List;
+    /// let list: List<u64> = List::new();
+    /// ```
+    pub fn new() -> Self {
+        List {
+            chunks: PriorityRwLock::new(vec![Self::chunk()]),
+            len: AtomicUsize::new(0),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    fn chunk() -> Chunk<T> {
+        PriorityRwLock::new(VecDeque::with_capacity(Self::CHUNK))
+    }
+
+    /// Removes the empty chunks, keeping at least one.
+    fn remove_empty(&self, chunks: &mut Vec<Chunk<T>>) {
+        chunks.retain(|chunk| !chunk.read(self.lock_policy.read).is_empty());
+        if chunks.is_empty() {
+            chunks.push(Self::chunk());
+        }
+    }
+
+    /// Appends `value` to the back of the list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// list.push_back('a');
+    /// list.push_back('b');
+    /// assert_eq!(list.range(..), vec!['a', 'b']);
+    /// ```
+    pub fn push_back(&self, value: T) {
+        {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            let mut last = chunks.last().unwrap().write(self.lock_policy.write);
+            if last.len() < Self::CHUNK {
+                last.push_back(value);
+                self.len.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+
+        let mut chunks = self.chunks.write(self.lock_policy.write);
+        if chunks.last().unwrap().read(self.lock_policy.read).len() >= Self::CHUNK {
+            chunks.push(Self::chunk());
+        }
+        chunks
+            .last()
+            .unwrap()
+            .write(self.lock_policy.write)
+            .push_back(value);
+        self.len.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Prepends `value` to the front of the list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// list.push_front('a');
+    /// list.push_front('b');
+    /// assert_eq!(list.range(..), vec!['b', 'a']);
+    /// ```
+    pub fn push_front(&self, value: T) {
+        {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            let mut first = chunks[0].write(self.lock_policy.write);
+            if first.len() < Self::CHUNK {
+                first.push_front(value);
+                self.len.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+
+        let mut chunks = self.chunks.write(self.lock_policy.write);
+        if chunks[0].read(self.lock_policy.read).len() >= Self::CHUNK {
+            chunks.insert(0, Self::chunk());
+        }
+        chunks[0].write(self.lock_policy.write).push_front(value);
+        self.len.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Removes the element at the back of the list, if any.
+    pub fn pop_back(&self) -> Option<T> {
+        self.pop_with(|chunks| chunks.last().unwrap(), VecDeque::pop_back)
+    }
+
+    /// Removes the element at the front of the list, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// list.push_back(1);
+    /// list.push_back(2);
+    /// assert_eq!(list.pop_front(), Some(1));
+    /// assert_eq!(list.pop_back(), Some(2));
+    /// assert_eq!(list.pop_front(), None);
+    /// ```
+    pub fn pop_front(&self) -> Option<T> {
+        self.pop_with(|chunks| &chunks[0], VecDeque::pop_front)
+    }
+
+    /// Pops from the chunk at one end of the list. An emptied chunk is
+    /// removed, and an empty chunk found at the end is removed before
+    /// popping from the next one.
+    fn pop_with<E, P>(&self, end: E, pop: P) -> Option<T>
+    where
+        E: for<'a> Fn(&'a [Chunk<T>]) -> &'a Chunk<T>,
+        P: Fn(&mut VecDeque<T>) -> Option<T>,
+    {
+        let popped = {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            let mut chunk = end(&chunks[..]).write(self.lock_policy.write);
+            let popped = pop(&mut chunk);
+            if popped.is_some() {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+            }
+            if !chunk.is_empty() || chunks.len() == 1 {
+                return popped;
+            }
+            popped
+        };
+
+        let mut chunks = self.chunks.write(self.lock_policy.write);
+        self.remove_empty(&mut chunks);
+        if popped.is_some() {
+            return popped;
+        }
+        let popped = pop(&mut end(&chunks[..]).write(self.lock_policy.write))?;
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        Some(popped)
+    }
+
+    /// Inserts `value` at position `index`, shifting the elements after it
+    /// towards the back.
+    ///
+    /// # Returns
+    ///
+    /// `Err(value)` if `index` is greater than the length of the list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// list.push_back('a');
+    /// list.push_back('c');
+    /// assert_eq!(list.insert_at(1, 'b'), Ok(()));
+    /// assert_eq!(list.insert_at(4, 'e'), Err('e'));
+    /// assert_eq!(list.range(..), vec!['a', 'b', 'c']);
+    /// ```
+    pub fn insert_at(&self, index: usize, value: T) -> Result<(), T> {
+        {
+            let chunks = self.chunks.read(self.lock_policy.read);
+            let mut offset = index;
+            let mut chunks_iter = chunks.iter();
+            let mut chunk = chunks_iter.next().unwrap().write(self.lock_policy.write);
+            loop {
+                if offset <= chunk.len() {
+                    if chunk.len() < Self::CHUNK {
+                        chunk.insert(offset, value);
+                        self.len.fetch_add(1, Ordering::SeqCst);
+                        return Ok(());
+                    }
+                    // the chunk has to be split
+                    break;
+                }
+                offset -= chunk.len();
+                match chunks_iter.next() {
+                    Some(next) => chunk = next.write(self.lock_policy.write),
+                    None => return Err(value),
+                }
+            }
+        }
+
+        let mut chunks = self.chunks.write(self.lock_policy.write);
+        let mut offset = index;
+        for position in 0..chunks.len() {
+            let mut chunk = chunks[position].write(self.lock_policy.write);
+            if offset > chunk.len() {
+                offset -= chunk.len();
+                continue;
+            }
+            if chunk.len() < Self::CHUNK {
+                chunk.insert(offset, value);
+            } else {
+                let mut tail = chunk.split_off(Self::CHUNK / 2);
+                if offset <= chunk.len() {
+                    chunk.insert(offset, value);
+                } else {
+                    tail.insert(offset - chunk.len(), value);
+                }
+                drop(chunk);
+                chunks.insert(position + 1, PriorityRwLock::new(tail));
+            }
+            self.len.fetch_add(1, Ordering::SeqCst);
+            return Ok(());
+        }
+        Err(value)
+    }
+
+    /// Returns the element at position `index`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// list.push_back('a');
+    /// assert_eq!(list.get(0), Some('a'));
+    /// assert_eq!(list.get(1), None);
+    /// ```
+    pub fn get(&self, index: usize) -> Option<T> {
+        let chunks = self.chunks.read(self.lock_policy.read);
+        let mut offset = index;
+        let mut chunks_iter = chunks.iter();
+        let mut chunk = chunks_iter.next().unwrap().read(self.lock_policy.read);
+        while offset >= chunk.len() {
+            offset -= chunk.len();
+            chunk = chunks_iter.next()?.read(self.lock_policy.read);
+        }
+        chunk.get(offset).cloned()
+    }
+
+    /// Returns the elements at the positions within `range`, which is
+    /// clamped to the length of the list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::list::List;
+    ///
+    /// let list = List::new();
+    /// for value in 0..10 {
+    ///     list.push_back(value);
+    /// }
+    /// assert_eq!(list.range(2..5), vec![2, 3, 4]);
+    /// assert_eq!(list.range(8..), vec![8, 9]);
+    /// assert_eq!(list.range(20..), vec![]);
+    /// ```
+    pub fn range<R: RangeBounds<usize>>(&self, range: R) -> Vec<T> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.saturating_add(1),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => usize::MAX,
+        };
+        let mut values = Vec::new();
+        if start >= end {
+            return values;
+        }
+
+        let chunks = self.chunks.read(self.lock_policy.read);
+        let mut position = 0;
+        let mut chunks_iter = chunks.iter();
+        let mut chunk = chunks_iter.next().unwrap().read(self.lock_policy.read);
+        loop {
+            let from = start.saturating_sub(position).min(chunk.len());
+            let to = (end - position).min(chunk.len());
+            values.extend(chunk.range(from..to.max(from)).cloned());
+            position += chunk.len();
+            if position >= end {
+                return values;
+            }
+            match chunks_iter.next() {
+                Some(next) => chunk = next.read(self.lock_policy.read),
+                None => return values,
+            }
+        }
+    }
+
+    /// Returns the number of elements in the list.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the list contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_pushes_and_inserts_keep_order() {
+        let list = Arc::new(List::new());
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        match thread {
+                            0 => list.push_back((thread, i)),
+                            1 => list.push_front((thread, i)),
+                            // insert in the middle, forcing chunk splits
+                            _ => list.insert_at(list.len() / 2, (thread, i)).unwrap(),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let values = list.range(..);
+        assert_eq!(values.len(), 4000);
+        assert_eq!(list.len(), 4000);
+        assert_eq!(list.get(3999), values.last().copied());
+
+        // pushes at either end keep their relative order
+        let back: Vec<_> = values.iter().filter(|(thread, _)| *thread == 0).collect();
+        assert!(back.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        let front: Vec<_> = values.iter().filter(|(thread, _)| *thread == 1).collect();
+        assert!(front.windows(2).all(|pair| pair[0].1 > pair[1].1));
+
+        while list.pop_front().is_some() {}
+        assert!(list.is_empty());
+        assert_eq!(list.range(..), vec![]);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type Segment<T> = Box<[OnceLock<T>]>;
+
+/// Segments of the log, `first` being the offset of the first entry of the
+/// front segment.
+struct Segments<T> {
+    first: u64,
+    segments: VecDeque<Segment<T>>,
+}
+
+/// Thread-Safe append-only log, addressing its entries by offset.
+///
+/// An append reserves the next offset with an atomic increment, then writes
+/// its entry into a preallocated slot of a segment of [`AppendLog::SEGMENT`]
+/// entries, under a shared lock on the segments. The exclusive lock is only
+/// taken to add a segment, or to drop segments on truncation, so concurrent
+/// appends do not wait on each other.
+///
+/// Appends may complete out of order: an entry is readable once its own
+/// append returns, even if an entry at a lower offset is still being
+/// written.
+pub struct AppendLog<T> {
+    segments: PriorityRwLock<Segments<T>>,
+    start: AtomicU64,
+    next: AtomicU64,
+    lock_policy: LockPolicy,
+}
+
+impl<T> Default for AppendLog<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> AppendLog<T>
+where
+    T: Clone,
+{
+    /// Number of entries of a segment.
+    pub const SEGMENT: usize = 1024;
+
+    /// Creates an empty `AppendLog`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::log::AppendLog;
+    /// let log: AppendLog<Vec<u8>> = AppendLog::new();
+    /// ```
+    pub fn new() -> Self {
+        AppendLog {
+            segments: PriorityRwLock::new(Segments {
+                first: 0,
+                segments: VecDeque::new(),
+            }),
+            start: AtomicU64::new(0),
+            next: AtomicU64::new(0),
+            lock_policy: LockPolicy::hybrid(),
+        }
+    }
+
+    fn segment() -> Segment<T> {
+        (0..Self::SEGMENT).map(|_| OnceLock::new()).collect()
+    }
+
+    /// Splits the position of `offset` relative to `first` into the index
+    /// of its segment and of its slot.
+    fn locate(first: u64, offset: u64) -> (usize, usize) {
+        let position = (offset - first) as usize;
+        (position / Self::SEGMENT, position % Self::SEGMENT)
+    }
+
+    /// Appends `value` to the log, returning its offset.
+    ///
+    /// Offsets start at 0 and increase by one with every append.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::log::AppendLog;
+    ///
+    /// let log = AppendLog::new();
+    /// assert_eq!(log.append("begin"), 0);
+    /// assert_eq!(log.append("commit"), 1);
+    /// ```
+    pub fn append(&self, value: T) -> u64 {
+        let offset = self.next.fetch_add(1, Ordering::SeqCst);
+        loop {
+            {
+                let segments = self.segments.read(self.lock_policy.read);
+                // truncated while being appended, the entry is discarded
+                if offset < segments.first {
+                    return offset;
+                }
+                let (segment, slot) = Self::locate(segments.first, offset);
+                if let Some(segment) = segments.segments.get(segment) {
+                    let _ = segment[slot].set(value);
+                    return offset;
+                }
+            }
+
+            let mut segments = self.segments.write(self.lock_policy.write);
+            let (segment, _) = Self::locate(segments.first, offset.max(segments.first));
+            while segments.segments.len() <= segment {
+                segments.segments.push_back(Self::segment());
+            }
+        }
+    }
+
+    /// Returns the entry at `offset`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the entry was truncated, or is not appended yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::log::AppendLog;
+    ///
+    /// let log = AppendLog::new();
+    /// let offset = log.append("begin");
+    /// assert_eq!(log.get(offset), Some("begin"));
+    /// assert_eq!(log.get(offset + 1), None);
+    /// ```
+    pub fn get(&self, offset: u64) -> Option<T> {
+        if offset < self.start.load(Ordering::SeqCst) {
+            return None;
+        }
+        let segments = self.segments.read(self.lock_policy.read);
+        if offset < segments.first {
+            return None;
+        }
+        let (segment, slot) = Self::locate(segments.first, offset);
+        segments.segments.get(segment)?[slot].get().cloned()
+    }
+
+    /// Returns an iterator over the entries from `offset` on, along with
+    /// their offsets.
+    ///
+    /// The iterator starts at the first entry not truncated if `offset` was,
+    /// and stops at the first offset not appended yet, so entries it yields
+    /// are contiguous.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::log::AppendLog;
+    ///
+    /// let log = AppendLog::new();
+    /// for entry in ["begin", "put", "commit"] {
+    ///     log.append(entry);
+    /// }
+    ///
+    /// let replayed: Vec<_> = log.iter_from(1).collect();
+    /// assert_eq!(replayed, vec![(1, "put"), (2, "commit")]);
+    /// ```
+    pub fn iter_from(&self, offset: u64) -> Iter<'_, T> {
+        Iter {
+            log: self,
+            offset: offset.max(self.start_offset()),
+        }
+    }
+
+    /// Discards every entry before `offset`, returning how many were
+    /// discarded. Offsets are never reused, the next append keeps the offset
+    /// it would have had.
+    ///
+    /// `offset` is capped to [`AppendLog::next_offset`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::log::AppendLog;
+    ///
+    /// let log = AppendLog::new();
+    /// for entry in 0..10 {
+    ///     log.append(entry);
+    /// }
+    ///
+    /// assert_eq!(log.truncate(4), 4);
+    /// assert_eq!(log.get(3), None);
+    /// assert_eq!(log.get(4), Some(4));
+    /// assert_eq!(log.append(10), 10);
+    /// ```
+    pub fn truncate(&self, offset: u64) -> u64 {
+        let mut segments = self.segments.write(self.lock_policy.write);
+        let offset = offset.min(self.next_offset());
+        let start = self.start.load(Ordering::SeqCst);
+        if offset <= start {
+            return 0;
+        }
+        self.start.store(offset, Ordering::SeqCst);
+
+        let (dropped, _) = Self::locate(segments.first, offset.max(segments.first));
+        let dropped = dropped.min(segments.segments.len());
+        segments.segments.drain(..dropped);
+        segments.first += (dropped * Self::SEGMENT) as u64;
+        offset - start
+    }
+
+    /// Returns the offset of the first entry not truncated.
+    pub fn start_offset(&self) -> u64 {
+        self.start.load(Ordering::SeqCst)
+    }
+
+    /// Returns the offset the next append will get.
+    pub fn next_offset(&self) -> u64 {
+        self.next.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of entries in the log, including those still
+    /// being appended.
+    pub fn len(&self) -> usize {
+        let start = self.start_offset();
+        self.next_offset().saturating_sub(start) as usize
+    }
+
+    /// Returns `true` if the log contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An iterator over the entries of an [`AppendLog`], see
+/// [`AppendLog::iter_from`].
+pub struct Iter<'a, T> {
+    log: &'a AppendLog<T>,
+    offset: u64,
+}
+
+impl<'a, T> Iterator for Iter<'a, T>
+where
+    T: Clone,
+{
+    type Item = (u64, T);
+
+    fn next(&mut self) -> Option<(u64, T)> {
+        let value = self.log.get(self.offset)?;
+        self.offset += 1;
+        Some((self.offset - 1, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppendLog;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_appends_get_distinct_offsets() {
+        let log = Arc::new(AppendLog::new());
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let log = log.clone();
+                thread::spawn(move || {
+                    (0..5000u64)
+                        .map(|i| (log.append(thread * 5000 + i), thread * 5000 + i))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let appended: Vec<_> = writers
+            .into_iter()
+            .flat_map(|writer| writer.join().unwrap())
+            .collect();
+
+        assert_eq!(log.len(), 20_000);
+        for (offset, value) in &appended {
+            assert_eq!(log.get(*offset), Some(*value));
+        }
+        assert_eq!(log.iter_from(0).count(), 20_000);
+
+        assert_eq!(log.truncate(15_000), 15_000);
+        assert_eq!(log.iter_from(0).count(), 5000);
+        assert_eq!(log.append(0), 20_000);
+    }
+}
@@ -1,28 +1,171 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::RwLock;
 
-struct BucketValue<K, V>(K, V);
+use super::filter::BucketFilter;
+use super::version::{Clock, Version, VersionChain};
+
+/// An entry of the bucket: the key, the hash of the key and the versions of
+/// its value.
+struct BucketValue<K, V>(K, u64, VersionChain<V>);
 type BucketData<K, V> = Vec<BucketValue<K, V>>;
 
+/// The committed version chains of a bucket's entries by key hash, sharing
+/// their versions with the entries themselves.
+type Published<K, V> = HashMap<u64, Vec<(K, VersionChain<V>)>>;
+
+/// A chain committed under a [`BucketGuard`], or `None` for an entry dropped
+/// from the bucket, waiting to be published.
+type Publication<K, V> = (u64, K, Option<VersionChain<V>>);
+
 /// Hash bucket within the hash table, for storing
 /// entries with clashing hash values.
+///
+/// Entries are never moved within the bucket while it is pinned by an
+/// iterator, so that iterators can release the lock between steps and resume
+/// from the slot they stopped at. Removed entries are left behind as dead
+/// slots and compacted away on the next write to an unpinned bucket.
+///
+/// Version chains committed under the lock are published into an index of
+/// their own once the write is done, from which [`Bucket::get_versioned`]
+/// and [`Bucket::get_as_of`] read without taking the bucket's lock. Writes
+/// hold the index's lock only for as long as it takes to publish them.
 pub struct Bucket<K, V> {
     // a multi-read, single-write wrapper
-    data: RwLock<BucketData<K, V>>,
+    data: PriorityRwLock<BucketData<K, V>>,
+    // number of iterators currently walking the bucket
+    pins: AtomicUsize,
+    // hashes of the keys in `data`, readable without taking the lock
+    filter: BucketFilter,
+    // chains committed to `data`, readable without taking its lock
+    published: RwLock<Published<K, V>>,
 }
 
-use super::utils::LockWrapper;
+use crate::collections::utils::{Acquire, LockWrapper, PriorityRwLock};
+
+/// A locked [`Bucket`], through which its entries can be accessed for as
+/// long as the guard is held.
+///
+/// Guards obtained through [`Bucket::read`] panic on any mutating operation.
+/// The chains committed under a guard are published when it is dropped.
+pub struct BucketGuard<'a, K, V>
+where
+    K: Eq,
+{
+    data: LockWrapper<'a, BucketData<K, V>>,
+    filter: &'a BucketFilter,
+    published: &'a RwLock<Published<K, V>>,
+    pending: Vec<Publication<K, V>>,
+}
 
 impl<K, V> Bucket<K, V>
 where
-    K: Eq + Copy,
+    K: Eq + Clone,
     V: Clone,
 {
-    pub fn new() -> Self {
+    /// Creates an empty bucket with a Bloom filter of `filter_bits` bits,
+    /// or without a filter if `filter_bits` is 0.
+    pub fn new(filter_bits: usize) -> Self {
         Bucket {
-            data: RwLock::new(Vec::new()),
+            data: PriorityRwLock::new(Vec::new()),
+            pins: AtomicUsize::new(0),
+            filter: BucketFilter::new(filter_bits),
+            published: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Acquires a shared lock on this bucket.
+    pub fn read(&self, acquire: Acquire) -> BucketGuard<'_, K, V> {
+        self.guard(LockWrapper::Read(self.data.read(acquire)))
+    }
+
+    /// Acquires an exclusive lock on this bucket, compacting away its dead
+    /// slots unless the bucket is pinned.
+    pub fn write(&self, acquire: Acquire) -> BucketGuard<'_, K, V> {
+        let mut guard = self.guard(LockWrapper::Write(self.data.write(acquire)));
+        if !self.is_pinned() {
+            guard.compact();
+        }
+        guard
+    }
+
+    /// Acquires an exclusive lock on this bucket for maintenance work,
+    /// yielding to any user operation queued on the bucket.
+    pub fn maintain(&self) -> BucketGuard<'_, K, V> {
+        self.guard(LockWrapper::Write(self.data.maintenance_write()))
+    }
+
+    fn guard<'a>(&'a self, data: LockWrapper<'a, BucketData<K, V>>) -> BucketGuard<'a, K, V> {
+        BucketGuard {
+            data,
+            filter: &self.filter,
+            published: &self.published,
+            pending: Vec::new(),
         }
     }
 
+    /// Returns the latest committed value for `key` along with its
+    /// [`Version`], without taking the bucket's lock.
+    pub fn get_versioned(&self, hash: u64, key: &K) -> Option<(V, Version)> {
+        self.published_chain(hash, key).and_then(|versions| {
+            versions
+                .latest()
+                .map(|(value, version)| (value.clone(), version))
+        })
+    }
+
+    /// Returns the newest value for `key` committed at or before `version`,
+    /// without taking the bucket's lock.
+    pub fn get_as_of(&self, hash: u64, key: &K, version: Version) -> Option<V> {
+        self.published_chain(hash, key)
+            .and_then(|versions| versions.as_of(version).cloned())
+    }
+
+    /// Returns the published chain of `key`, shared with its entry.
+    fn published_chain(&self, hash: u64, key: &K) -> Option<VersionChain<V>> {
+        let published = self.published.read().unwrap();
+        published
+            .get(&hash)?
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, versions)| versions.clone())
+    }
+}
+
+impl<K, V> Bucket<K, V> {
+    /// Prevents the entries of this bucket from being moved, until a matching
+    /// call to [`Bucket::unpin`].
+    pub fn pin(&self) {
+        self.pins.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn unpin(&self) {
+        self.pins.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if entries of this bucket may not be moved.
+    pub fn is_pinned(&self) -> bool {
+        self.pins.load(Ordering::SeqCst) > 0
+    }
+
+    /// Returns `false` if the bucket definitely holds no entry whose key has
+    /// the given `hash`, without taking the bucket's lock.
+    pub fn might_contain(&self, hash: u64) -> bool {
+        self.filter.might_contain(hash)
+    }
+
+    /// Returns the bytes allocated for the bucket's Bloom filter.
+    pub fn filter_bytes(&self) -> usize {
+        self.filter.allocated_bytes()
+    }
+}
+
+impl<'a, K, V> BucketGuard<'a, K, V>
+where
+    K: Eq + Clone,
+    V: Clone,
+{
     /// Searches and returns the first [`BucketValue`] within this bucket's
     /// data list that has the given `key`, along with an index of the
     /// returned [`BucketValue`].
@@ -31,44 +174,331 @@ where
     ///
     /// # Arguments
     ///
+    /// * `hash`    - hash of the key
     /// * `key`     - reference to the key
-    /// * `data`    - a [`LockWrapper`] to the data that contains the
-    ///               [`BucketValue`] to search in. The Lock can be both
-    ///               a read lock as well as a write lock.
     ///
     /// # Returns
     ///
     /// An [`Option`]al tuple of the form `(index, &BucketValue)` where
     /// `index` is the current index of the [`BucketValue`] returned.
-    fn find_entry_for<'gaurd>(
-        key: &K,
-        data: &'gaurd LockWrapper<Vec<BucketValue<K, V>>>,
-    ) -> Option<(usize, &'gaurd BucketValue<K, V>)> {
-        data.iter()
+    ///
+    /// Dead entries are returned as well, callers interested in the
+    /// latest value have to check for a tombstone themselves.
+    fn find_entry_for(&self, hash: u64, key: &K) -> Option<(usize, &BucketValue<K, V>)> {
+        self.find_entry_by(hash, |elem_key| elem_key == key)
+    }
+
+    /// Like [`BucketGuard::find_entry_for`], but matches keys using
+    /// `is_match` instead of comparing them for equality.
+    fn find_entry_by<F>(&self, hash: u64, mut is_match: F) -> Option<(usize, &BucketValue<K, V>)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.data
+            .iter()
             .enumerate()
-            .find(|(_, BucketValue(elem_key, _))| *elem_key == *key)
+            .find(|(_, BucketValue(elem_key, elem_hash, _))| {
+                *elem_hash == hash && is_match(elem_key)
+            })
+    }
+
+    /// Returns a reference to the latest committed value for `key`, valid for
+    /// as long as the guard is held.
+    pub fn latest(&self, hash: u64, key: &K) -> Option<&V> {
+        self.latest_by(hash, |elem_key| elem_key == key)
+            .map(|(value, _)| value)
+    }
+
+    /// Returns the latest committed value and [`Version`] of the first entry
+    /// with the given `hash` whose key satisfies `is_match`.
+    pub fn latest_by<F>(&self, hash: u64, is_match: F) -> Option<(&V, Version)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.find_entry_by(hash, is_match)
+            .and_then(|(_, BucketValue(_, _, versions))| versions.latest())
+    }
+
+    /// Returns the key and latest committed value of the first entry with
+    /// the given `hash` whose key satisfies `is_match`.
+    pub fn latest_entry_by<F>(&self, hash: u64, is_match: F) -> Option<(&K, &V)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.find_entry_by(hash, is_match)
+            .and_then(|(_, BucketValue(key, _, versions))| {
+                versions.latest().map(|(value, _)| (key, value))
+            })
+    }
+
+    /// Returns the latest committed value for `key` along with its [`Version`].
+    pub fn get_versioned(&self, hash: u64, key: &K) -> Option<(V, Version)> {
+        self.latest_by(hash, |elem_key| elem_key == key)
+            .map(|(value, version)| (value.clone(), version))
+    }
+
+    /// Commits `value` as the newest version of `key`. The version is drawn
+    /// from `clock` while the write lock is held, so that versions of the same
+    /// key are ordered the same way as the writes themselves.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `key` was not mapped before.
+    pub fn put(&mut self, hash: u64, key: &K, value: V, clock: &Clock) -> bool {
+        let version = clock.tick();
+        match self.find_entry_for(hash, key) {
+            None => {
+                self.filter.insert(hash);
+                let versions = VersionChain::new(version, value);
+                self.pending
+                    .push((hash, key.clone(), Some(versions.clone())));
+                self.data.push(BucketValue(key.clone(), hash, versions));
+                true
+            }
+            Some((index, _)) => {
+                let versions = &mut self.data[index].2;
+                let was_dead = versions.is_dead();
+                versions.push(version, value, clock.oldest_held());
+                let published = versions.clone();
+                self.pending.push((hash, key.clone(), Some(published)));
+                was_dead
+            }
+        }
+    }
+
+    /// Returns the first live entry at or after `slot`, along with the slot
+    /// it was found at.
+    pub fn next_live(&self, slot: usize) -> Option<(usize, K, V)> {
+        self.data.iter().enumerate().skip(slot).find_map(
+            |(index, BucketValue(key, _, versions))| {
+                versions
+                    .latest()
+                    .map(|(value, _)| (index, key.clone(), value.clone()))
+            },
+        )
+    }
+
+    /// Returns the hash, key and latest value of every live entry.
+    pub fn live_entries(&self) -> impl Iterator<Item = (u64, &K, &V)> + '_ {
+        self.data
+            .iter()
+            .filter_map(|BucketValue(key, hash, versions)| {
+                versions.latest().map(|(value, _)| (*hash, key, value))
+            })
+    }
+
+    /// Returns the hash, key and value of every entry that was live at
+    /// `version`.
+    pub fn entries_as_of(&self, version: Version) -> impl Iterator<Item = (u64, &K, &V)> + '_ {
+        self.data
+            .iter()
+            .filter_map(move |BucketValue(key, hash, versions)| {
+                versions.as_of(version).map(|value| (*hash, key, value))
+            })
+    }
+
+    /// Returns the key and latest value of every live entry whose latest
+    /// version is newer than `since`.
+    pub fn changed_entries(&self, since: Version) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.data.iter().filter_map(
+            move |BucketValue(key, _, versions)| match versions.latest() {
+                Some((value, version)) if version > since => Some((key, value)),
+                _ => None,
+            },
+        )
+    }
+
+    /// Returns the keys of the live entries.
+    pub fn live_keys(&self) -> Vec<K> {
+        self.data
+            .iter()
+            .filter(|BucketValue(_, _, versions)| versions.latest().is_some())
+            .map(|BucketValue(key, _, _)| key.clone())
+            .collect()
+    }
+
+    /// Commits a tombstone for the first entry with the given `hash` whose
+    /// key satisfies `is_match`, if it is mapped. The entry itself stays in
+    /// the bucket as a dead slot until it is compacted or garbage collected.
+    ///
+    /// # Returns
+    ///
+    /// The key of the entry and the version of its tombstone if a mapped
+    /// entry was found.
+    pub fn unmap_by<F>(&mut self, hash: u64, is_match: F, clock: &Clock) -> Option<(&K, Version)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        match self.find_entry_by(hash, is_match) {
+            Some((index, _)) if !self.data[index].2.is_dead() => {
+                let version = clock.tick();
+                let BucketValue(key, hash, versions) = &mut self.data[index];
+                versions.push_tombstone(version, clock.oldest_held());
+                self.pending
+                    .push((*hash, key.clone(), Some(versions.clone())));
+                Some((&self.data[index].0, version))
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops every dead slot from the bucket, and clears the hashes of the
+    /// dropped keys from the bucket's filter.
+    ///
+    /// # Returns
+    ///
+    /// The number of slots dropped.
+    pub fn compact(&mut self) -> usize {
+        let before = self.data.len();
+        let pending = &mut self.pending;
+        self.data.retain(|BucketValue(key, hash, versions)| {
+            let dead = versions.is_dead();
+            if dead {
+                pending.push((*hash, key.clone(), None));
+            }
+            !dead
+        });
+        let dropped = before - self.data.len();
+
+        if dropped > 0 {
+            let hashes = self.data.iter().map(|BucketValue(_, hash, _)| *hash);
+            self.filter.rebuild(hashes);
+        }
+        dropped
     }
 
-    pub fn get(&self, key: &K) -> Option<V> {
-        let gaurd = LockWrapper::Read(self.data.read().unwrap());
-        match Self::find_entry_for(key, &gaurd) {
-            Some((_, BucketValue(_, value))) => Some(value.clone()),
-            None => None,
+    /// Trims the version chains of live entries down to `retain_versions`
+    /// versions, short of those needed to read them as of `held`, and drops
+    /// every dead slot if `compact` is set.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the form `(entries, versions)` with the number of dead
+    /// entries and of superseded versions reclaimed.
+    pub fn collect_garbage(
+        &mut self,
+        retain_versions: usize,
+        compact: bool,
+        held: Option<Version>,
+    ) -> (usize, usize) {
+        let entries = if compact { self.compact() } else { 0 };
+
+        let mut versions = 0;
+        for BucketValue(key, hash, chain) in self.data.iter_mut() {
+            let trimmed = chain.trim(retain_versions, held);
+            if trimmed > 0 {
+                self.pending.push((*hash, key.clone(), Some(chain.clone())));
+            }
+            versions += trimmed;
         }
+
+        (entries, versions)
+    }
+
+    /// Returns the bytes allocated for the entries of this bucket and for
+    /// their published index, along with the sum of `measure` over the
+    /// values of every retained version.
+    pub fn memory_usage<F>(&self, measure: F) -> (usize, usize)
+    where
+        F: Fn(&V) -> usize,
+    {
+        let published = self.published.read().unwrap();
+        let index = published.capacity() * mem::size_of::<(u64, Vec<(K, VersionChain<V>)>)>()
+            + published
+                .values()
+                .map(|keys| keys.capacity() * mem::size_of::<(K, VersionChain<V>)>())
+                .sum::<usize>();
+        let slots = index + self.data.capacity() * mem::size_of::<BucketValue<K, V>>();
+        self.data.iter().fold(
+            (slots, 0),
+            |(entries, values), BucketValue(_, _, versions)| {
+                (
+                    entries + versions.allocated_bytes(),
+                    values + versions.values().map(&measure).sum::<usize>(),
+                )
+            },
+        )
     }
 
-    pub fn put(&self, key: &K, value: V) {
-        let mut gaurd = LockWrapper::Write(self.data.write().unwrap());
-        match Self::find_entry_for(key, &gaurd) {
-            None => gaurd.push(BucketValue(*key, value)),
-            Some((index, _)) => gaurd.get_mut(index).unwrap().1 = value,
+    /// Releases storage capacity not used by the entries of the bucket.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.published.write().unwrap().shrink_to_fit();
+    }
+}
+
+impl<K, V> Drop for BucketGuard<'_, K, V>
+where
+    K: Eq,
+{
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            publish(&mut self.published.write().unwrap(), &mut self.pending);
         }
     }
+}
+
+/// Publishes the chains committed under every guard at once, holding the
+/// index of each bucket locked until all are, so that reads observe either
+/// all or none of them.
+pub fn publish_together<K, V>(guards: &mut [(usize, BucketGuard<'_, K, V>)])
+where
+    K: Eq,
+{
+    let indexes: Vec<_> = guards.iter().map(|(_, guard)| guard.published).collect();
+    let mut indexes: Vec<_> = indexes
+        .into_iter()
+        .map(|published| published.write().unwrap())
+        .collect();
+    for ((_, guard), published) in guards.iter_mut().zip(&mut indexes) {
+        publish(published, &mut guard.pending);
+    }
+}
 
-    pub fn unmap(&self, key: &K) {
-        let mut gaurd = LockWrapper::Write(self.data.write().unwrap());
-        if let Some((index, _)) = Self::find_entry_for(key, &gaurd) {
-            gaurd.swap_remove(index);
+/// Applies `pending` to `published`, in the order the chains were committed.
+fn publish<K, V>(published: &mut Published<K, V>, pending: &mut Vec<Publication<K, V>>)
+where
+    K: Eq,
+{
+    for (hash, key, versions) in pending.drain(..) {
+        let keys = published.entry(hash).or_default();
+        let position = keys.iter().position(|(elem_key, _)| *elem_key == key);
+        match (position, versions) {
+            (Some(position), Some(versions)) => keys[position].1 = versions,
+            (None, Some(versions)) => keys.push((key, versions)),
+            (Some(position), None) => {
+                keys.swap_remove(position);
+            }
+            (None, None) => {}
+        }
+        if keys.is_empty() {
+            published.remove(&hash);
         }
     }
 }
+
+/// Write-locks the buckets at `indices` in ascending index order.
+///
+/// Duplicate indices are locked only once. Acquiring bucket locks in this
+/// canonical order is what keeps threads locking overlapping sets of buckets
+/// from deadlocking one another.
+///
+/// # Returns
+///
+/// The acquired guards paired with their bucket index, sorted by index.
+pub fn lock_buckets<K, V>(
+    buckets: &[Bucket<K, V>],
+    mut indices: Vec<usize>,
+    acquire: Acquire,
+) -> Vec<(usize, BucketGuard<'_, K, V>)>
+where
+    K: Eq + Clone,
+    V: Clone,
+{
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+        .into_iter()
+        .map(|index| (index, buckets[index].write(acquire)))
+        .collect()
+}
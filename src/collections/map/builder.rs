@@ -0,0 +1,265 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::AtomicUsize;
+
+use super::bucket::Bucket;
+use super::delta::ChangeLog;
+use super::gc::{GcCounters, GcPolicy};
+use super::memory::MeasureSize;
+use super::quota::{weigh_inline, Quota, QuotaPolicy};
+use super::version::Clock;
+use super::{Map, DEFAULT_BUCKET_COUNT};
+use crate::collections::cache::weigh_bytes;
+use crate::collections::utils::LockPolicy;
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+
+/// Configures and creates a [`Map`].
+///
+/// Options that are not set keep the same defaults as [`Map::new`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::collections::map::{LockPolicy, MapBuilder};
+/// use palladiumdb::Map;
+///
+/// let map: Map<&str, i32> = MapBuilder::new()
+///     .bucket_count(64)
+///     .lock_policy(LockPolicy::hybrid())
+///     .build();
+///
+/// map.put(&"One", 1);
+/// assert_eq!(map.get(&"One"), Some(1));
+/// ```
+#[derive(Clone)]
+pub struct MapBuilder<H = RandomState> {
+    hash_builder: H,
+    bucket_count: usize,
+    lock_policy: LockPolicy,
+    gc_policy: GcPolicy,
+    filter_bits: usize,
+    track_changes: bool,
+    compression: Compression,
+    encryption: Option<Encryption>,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    quota_policy: QuotaPolicy,
+}
+
+impl MapBuilder<RandomState> {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        MapBuilder {
+            hash_builder: RandomState::new(),
+            bucket_count: DEFAULT_BUCKET_COUNT,
+            lock_policy: LockPolicy::default(),
+            gc_policy: GcPolicy::default(),
+            filter_bits: 0,
+            track_changes: false,
+            compression: Compression::None,
+            encryption: None,
+            max_entries: None,
+            max_bytes: None,
+            quota_policy: QuotaPolicy::default(),
+        }
+    }
+}
+
+impl Default for MapBuilder<RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> MapBuilder<H> {
+    /// Sets the hash builder used to hash the keys of the map.
+    pub fn hasher<G: BuildHasher>(self, hash_builder: G) -> MapBuilder<G> {
+        MapBuilder {
+            hash_builder,
+            bucket_count: self.bucket_count,
+            lock_policy: self.lock_policy,
+            gc_policy: self.gc_policy,
+            filter_bits: self.filter_bits,
+            track_changes: self.track_changes,
+            compression: self.compression,
+            encryption: self.encryption,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            quota_policy: self.quota_policy,
+        }
+    }
+
+    /// Sets the number of buckets allocated for the map.
+    pub fn bucket_count(mut self, bucket_count: usize) -> Self {
+        self.bucket_count = bucket_count;
+        self
+    }
+
+    /// Sets how the map acquires its bucket locks on reads and writes.
+    pub fn lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.lock_policy = lock_policy;
+        self
+    }
+
+    /// Sets how aggressively garbage collection reclaims memory.
+    pub fn gc_policy(mut self, gc_policy: GcPolicy) -> Self {
+        self.gc_policy = gc_policy;
+        self
+    }
+
+    /// Gives every bucket a Bloom filter of `bits` bits over the hashes of
+    /// its keys. Disabled by default.
+    ///
+    /// Lookups of keys that are not in the map are answered from the filter
+    /// without taking any lock most of the time, at the cost of `bits / 8`
+    /// bytes per bucket and some extra work on every insert. The filter is
+    /// only effective while it is large compared to the number of keys per
+    /// bucket; about 10 bits per key give a false positive rate of 1%.
+    pub fn bucket_filter_bits(mut self, bits: usize) -> Self {
+        self.filter_bits = bits;
+        self
+    }
+
+    /// Makes the map track the keys removed since its last snapshot, so that
+    /// [`Map::save_incremental`] can write only what changed. Disabled by
+    /// default.
+    ///
+    /// Every removal then clones its key into a log, which is kept until the
+    /// next snapshot is saved.
+    pub fn track_changes(mut self, track_changes: bool) -> Self {
+        self.track_changes = track_changes;
+        self
+    }
+
+    /// Compresses the snapshots the map saves, see [`crate::compression`].
+    /// Snapshots are loaded whatever their compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypts the snapshots the map saves, see [`crate::encryption`], and
+    /// decrypts those loaded by [`MapBuilder::load_from`] and
+    /// [`MapBuilder::load_chain`].
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Limits the map to `max_entries` entries, a write taking it over the
+    /// limit being handled as set by [`MapBuilder::quota_policy`]. Unlimited
+    /// by default.
+    ///
+    /// Limits are checked on every put, without any lock held across
+    /// buckets, so concurrent puts into different buckets can take the map
+    /// over its limits by about one entry each.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Limits the map to entries of `max_bytes` bytes in total, a write
+    /// taking it over the limit being handled as set by
+    /// [`MapBuilder::quota_policy`]. Unlimited by default.
+    ///
+    /// Entries are charged the inline size of their key and value, or their
+    /// whole memory as reported by [`MeasureSize`] if the map is created by
+    /// [`MapBuilder::build_measured`]. Retained versions of entries are not
+    /// charged, see [`Map::memory_usage`] for the memory actually used.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets what the map does with a write taking it over its limits,
+    /// rejecting it by default. Maps limited in size report what enforcing
+    /// their limits did through [`Map::quota_stats`].
+    ///
+    /// Entries evicted are removed from the map itself, they are not written
+    /// to the log of an [`Aof`](crate::aof::Aof) or of a [`Durable`](crate::wal::Durable) map wrapping it.
+    pub fn quota_policy(mut self, quota_policy: QuotaPolicy) -> Self {
+        self.quota_policy = quota_policy;
+        self
+    }
+
+    /// Creates the configured [`Map`].
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the bucket count is 0.
+    pub fn build<K, V>(self) -> Map<K, V, H>
+    where
+        K: Hash + Eq + Clone,
+        V: Clone,
+        H: BuildHasher,
+    {
+        self.build_with_weigher(weigh_inline)
+    }
+
+    /// Creates the configured [`Map`], charging its entries their whole
+    /// memory as reported by [`MeasureSize`] against
+    /// [`MapBuilder::max_bytes`].
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the bucket count is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::Map;
+    ///
+    /// let map: Map<u64, String> = MapBuilder::new().max_bytes(1 << 10).build_measured();
+    ///
+    /// assert!(map.try_put(&1, "a".repeat(100)).is_ok());
+    /// assert!(map.try_put(&2, "a".repeat(1000)).is_err());
+    /// ```
+    pub fn build_measured<K, V>(self) -> Map<K, V, H>
+    where
+        K: Hash + Eq + Clone + MeasureSize,
+        V: Clone + MeasureSize,
+        H: BuildHasher,
+    {
+        self.build_with_weigher(weigh_bytes)
+    }
+
+    fn build_with_weigher<K, V>(self, weigh: fn(&K, &V) -> usize) -> Map<K, V, H>
+    where
+        K: Hash + Eq + Clone,
+        V: Clone,
+        H: BuildHasher,
+    {
+        if self.bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(self.bucket_count);
+        buckets.resize_with(self.bucket_count, || Bucket::new(self.filter_bits));
+
+        Map {
+            hash_builder: self.hash_builder,
+            buckets,
+            clock: Clock::new(),
+            len: AtomicUsize::new(0),
+            lock_policy: self.lock_policy,
+            gc_policy: self.gc_policy,
+            gc_counters: GcCounters::new(),
+            changes: if self.track_changes {
+                Some(ChangeLog::new(self.bucket_count))
+            } else {
+                None
+            },
+            compression: self.compression,
+            encryption: self.encryption,
+            quota: Quota::new(
+                self.max_entries,
+                self.max_bytes,
+                self.quota_policy,
+                self.bucket_count,
+            ),
+            weigh,
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::Map;
+use crate::codec::invalid_data;
+use crate::csv::{self, Cell, VALUE_COLUMN};
+
+impl<K, V, H> Map<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Writes the entries of the `Map` to `writer` as CSV, one row per
+    /// entry after a header naming the columns, so that it can be opened in
+    /// a spreadsheet or a data pipeline and read back by
+    /// [`Map::import_csv`].
+    ///
+    /// The key comes first, in a column named `key_column`, or in columns
+    /// prefixed by `key_column` and a dot if it is a struct. The value
+    /// follows, flattened to columns as described in [`crate::csv`]. The
+    /// first entry gives the header, which every other entry must flatten
+    /// to, so an `Option` of a struct must not be `None` in it. Nothing is
+    /// written for an empty map, whose columns are not known.
+    ///
+    /// Buckets are copied one at a time, each under its read lock, and
+    /// written before the next one is copied, so writers are only held off
+    /// one bucket at a time and the map is never copied whole in memory.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries written, or an error of kind `InvalidData` if
+    /// an entry cannot be flattened to columns or not to those of the
+    /// header, in which case the entries before it were written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Clone, Serialize)]
+    /// struct User {
+    ///     name: String,
+    ///     age: Option<u32>,
+    /// }
+    ///
+    /// let map = Map::new();
+    /// map.put(&7u64, User { name: "Smith, Alice".to_string(), age: None });
+    ///
+    /// let mut csv = Vec::new();
+    /// assert_eq!(map.export_csv(&mut csv, "id").unwrap(), 1);
+    /// assert_eq!(String::from_utf8(csv).unwrap(), "id,name,age\r\n7,\"Smith, Alice\",\r\n");
+    /// ```
+    pub fn export_csv<W: Write>(&self, mut writer: W, key_column: &str) -> io::Result<u64>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let key_prefix = format!("{}.", key_column);
+        let mut header: Option<Vec<String>> = None;
+        let mut columns = Vec::new();
+        let mut buf = String::new();
+        let mut count = 0u64;
+        for bucket in &self.buckets {
+            buf.clear();
+            {
+                let guard = bucket.read(self.lock_policy.read);
+                for (_, key, value) in guard.live_entries() {
+                    columns.clear();
+                    csv::flatten(key, key_column, &key_prefix, &mut columns)?;
+                    csv::flatten(value, VALUE_COLUMN, "", &mut columns)?;
+                    let header = match &header {
+                        Some(header) => header,
+                        None => {
+                            let names: Vec<Cell> =
+                                columns.iter().map(|(name, _)| Some(name.clone())).collect();
+                            csv::write_record(&names, &mut buf);
+                            header.insert(names.into_iter().flatten().collect())
+                        }
+                    };
+                    csv::write_record(csv::align(header, &columns)?, &mut buf);
+                    count += 1;
+                }
+            }
+            writer.write_all(buf.as_bytes())?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Puts the entries read from `reader`, CSV with a header naming the
+    /// columns as written by [`Map::export_csv`], replacing the values of
+    /// keys already present.
+    ///
+    /// The key is read from the column named `key_column`, or the columns
+    /// prefixed by `key_column` and a dot, and the value from the other
+    /// columns, which may come in any order. Columns that do not match a
+    /// field of the value are ignored, and missing ones are left to serde,
+    /// which reads a missing `Option` as `None`. Blank lines are skipped.
+    ///
+    /// Rows are parsed and put one at a time, so the input is never held
+    /// whole in memory.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries put, or an error of kind `InvalidData` naming
+    /// the faulty row if the input is not CSV or holds keys or values of
+    /// other types, in which case the entries before it were put.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Clone, Debug, PartialEq, Deserialize)]
+    /// struct User {
+    ///     name: String,
+    ///     age: Option<u32>,
+    /// }
+    ///
+    /// let map: Map<u64, User> = Map::new();
+    /// let csv = "age,id,name\n31,1,Alice\n,2,Bob\n";
+    /// assert_eq!(map.import_csv(csv.as_bytes(), "id").unwrap(), 2);
+    /// assert_eq!(map.get(&2), Some(User { name: "Bob".to_string(), age: None }));
+    ///
+    /// assert!(map.import_csv(&b"id,name\nthree,Carol\n"[..], "id").is_err());
+    /// ```
+    pub fn import_csv<R: Read>(&self, reader: R, key_column: &str) -> io::Result<u64>
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        let mut reader = BufReader::new(reader);
+        let header = match csv::read_record(&mut reader)? {
+            Some(header) => header
+                .into_iter()
+                .map(|name| name.ok_or_else(|| invalid_data("CSV header with an empty column")))
+                .collect::<io::Result<Vec<_>>>()?,
+            None => return Ok(0),
+        };
+        let key_prefix = format!("{}.", key_column);
+        if !header
+            .iter()
+            .any(|name| name == key_column || name.starts_with(&key_prefix))
+        {
+            return Err(invalid_data(&format!(
+                "CSV header without column {}",
+                key_column
+            )));
+        }
+
+        let mut count = 0u64;
+        let mut row = 1;
+        let (mut key_columns, mut value_columns) = (Vec::new(), Vec::new());
+        while let Some(cells) = csv::read_record(&mut reader)? {
+            row += 1;
+            if header.len() > 1 && cells == [None] {
+                continue;
+            }
+            let faulty = |err: io::Error| invalid_data(&format!("CSV row {}: {}", row, err));
+            if cells.len() != header.len() {
+                return Err(faulty(invalid_data(&format!(
+                    "{} cells for {} columns",
+                    cells.len(),
+                    header.len()
+                ))));
+            }
+
+            key_columns.clear();
+            value_columns.clear();
+            for (name, cell) in header.iter().zip(cells) {
+                if name == key_column || name.starts_with(&key_prefix) {
+                    key_columns.push((name.clone(), cell));
+                } else {
+                    value_columns.push((name.clone(), cell));
+                }
+            }
+            let key: K = csv::unflatten(key_column, &key_prefix, &key_columns).map_err(faulty)?;
+            let value: V = csv::unflatten(VALUE_COLUMN, "", &value_columns).map_err(faulty)?;
+            self.put(&key, value);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Map;
+    use serde::{Deserialize, Serialize};
+    use std::io::ErrorKind;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Status {
+        Active,
+        Suspended,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        city: String,
+        zip: Option<u32>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        owner: String,
+        balance: f64,
+        status: Status,
+        address: Option<Address>,
+        position: (i32, i32),
+    }
+
+    #[test]
+    fn test_flattens_nested_values_and_round_trips() {
+        // a single bucket keeps the entries in order, the first one giving
+        // the header
+        let map = Map::with_bucket_count(1);
+        for id in 0..100u64 {
+            let address = match id % 3 {
+                0 => Some(Address {
+                    city: format!("\"Port\", line\n{}", id),
+                    zip: Some(id as u32),
+                }),
+                1 => Some(Address {
+                    city: String::new(),
+                    zip: None,
+                }),
+                _ => None,
+            };
+            let account = Account {
+                owner: format!("owner {}", id),
+                balance: id as f64 - 0.5,
+                status: if id % 2 == 0 {
+                    Status::Active
+                } else {
+                    Status::Suspended
+                },
+                address,
+                position: (-(id as i32), id as i32),
+            };
+            map.put(&(id, format!("region-{}", id % 4)), account);
+        }
+
+        let mut csv = Vec::new();
+        assert_eq!(map.export_csv(&mut csv, "id").unwrap(), 100);
+        let text = String::from_utf8(csv.clone()).unwrap();
+        assert!(text.starts_with(
+            "id.0,id.1,owner,balance,status,address.city,address.zip,position.0,position.1\r\n"
+        ));
+
+        let imported: Map<(u64, String), Account> = Map::new();
+        assert_eq!(imported.import_csv(&csv[..], "id").unwrap(), 100);
+        for id in 0..100u64 {
+            let key = (id, format!("region-{}", id % 4));
+            assert_eq!(imported.get(&key), map.get(&key));
+        }
+
+        let bad_rows = [
+            "id,value\n1,2\n2\n",
+            "id,value\n1,x\"y\n",
+            "id,value\n1,\"2\"x\n",
+            "id,value\n1,256\n",
+            "name,value\n1,2\n",
+        ];
+        let small: Map<u8, u8> = Map::new();
+        for bad in &bad_rows {
+            let error = small.import_csv(bad.as_bytes(), "id").unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", bad);
+        }
+        let error = small
+            .import_csv(&b"id,value\n1,\"2\n"[..], "id")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            small
+                .import_csv(&b"id,value\r\n\r\n3,4\r\n\r\n"[..], "id")
+                .unwrap(),
+            1
+        );
+        assert_eq!(small.get(&3), Some(4));
+
+        let vectors: Map<u8, Vec<u8>> = Map::new();
+        vectors.put(&1, vec![1]);
+        let error = vectors.export_csv(Vec::new(), "id").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
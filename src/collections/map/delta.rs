@@ -0,0 +1,328 @@
+use std::any;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::version::Version;
+use super::{Map, MapBuilder};
+use crate::codec::{invalid_data, Decode, Encode};
+use crate::files;
+use crate::pdb::{PdbReader, PdbWriter, SECTION_SIZE};
+
+/// Kind of the PDB files holding the changes of a map since a snapshot, and
+/// version of their layout.
+const KIND: &str = "map-delta";
+const KIND_VERSION: u32 = 1;
+
+/// Keys removed from a map tracking its changes, kept until a snapshot
+/// newer than their removal is saved.
+///
+/// Removals cannot be told from the buckets alone, since the tombstones
+/// they leave are reclaimed by garbage collection.
+pub(crate) struct ChangeLog<K> {
+    removals: Vec<Mutex<Vec<(K, Version)>>>,
+    snapshot: Mutex<Option<Version>>,
+}
+
+impl<K: Clone> ChangeLog<K> {
+    pub(crate) fn new(bucket_count: usize) -> Self {
+        let mut removals = Vec::with_capacity(bucket_count);
+        removals.resize_with(bucket_count, || Mutex::new(Vec::new()));
+        ChangeLog {
+            removals,
+            snapshot: Mutex::new(None),
+        }
+    }
+
+    /// Records the removal of `key` from the bucket at `index`, called under
+    /// the bucket's write lock.
+    pub(crate) fn record_removal(&self, index: usize, key: &K, version: Version) {
+        self.removals[index]
+            .lock()
+            .unwrap()
+            .push((key.clone(), version));
+    }
+}
+
+impl<K, V, H> Map<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Saves to the file at `path` only the entries of the `Map` put or
+    /// removed since the last snapshot, whether saved by [`Map::save_to`] or
+    /// by this method, replacing the file.
+    ///
+    /// The delta is a [PDB file](crate::pdb) of kind `map-delta`, whose
+    /// metadata records the version of the snapshot it follows and its own.
+    /// Entries are picked by the version of their latest write, and removed
+    /// keys from the log kept by maps built with
+    /// [`MapBuilder::track_changes`](super::MapBuilder::track_changes). A
+    /// full snapshot followed by its deltas, in order, is loaded by
+    /// [`Map::load_chain`].
+    ///
+    /// Buckets are copied one at a time, each under its read lock, and the
+    /// delta is renamed over `path` once synced, as for [`Map::save_to`].
+    /// It is encrypted if the map was built with
+    /// [`MapBuilder::encryption`].
+    ///
+    /// # Returns
+    ///
+    /// The number of entries put or removed written, or an error of kind
+    /// `InvalidInput` if the map does not track its changes or no snapshot
+    /// was saved yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::Map;
+    ///
+    /// let full = std::env::temp_dir().join("palladiumdb-doc-full.snap");
+    /// let delta = std::env::temp_dir().join("palladiumdb-doc-delta.snap");
+    /// let map = MapBuilder::new().track_changes(true).build();
+    /// map.put(&1u64, "one".to_string());
+    /// map.put(&2u64, "two".to_string());
+    /// map.save_to(&full).unwrap();
+    ///
+    /// map.put(&3, "three".to_string());
+    /// map.remove(&1);
+    /// assert_eq!(map.save_incremental(&delta).unwrap(), 2);
+    ///
+    /// let loaded: Map<u64, String> = Map::load_chain(&[&full, &delta]).unwrap();
+    /// assert_eq!(loaded.get(&1), None);
+    /// assert_eq!(loaded.get(&3), Some("three".to_string()));
+    /// # std::fs::remove_file(&full).unwrap();
+    /// # std::fs::remove_file(&delta).unwrap();
+    /// ```
+    pub fn save_incremental<P: AsRef<Path>>(&self, path: P) -> io::Result<u64>
+    where
+        K: Encode,
+        V: Encode,
+    {
+        let changes = self.changes.as_ref().ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "map does not track its changes")
+        })?;
+        let base = changes.snapshot.lock().unwrap().ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "no snapshot to save changes since")
+        })?;
+
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+
+        let version = self.clock.now();
+        let metadata = [
+            ("base_version", base.as_u64().to_string()),
+            ("version", version.as_u64().to_string()),
+            ("key_type", any::type_name::<K>().to_string()),
+            ("value_type", any::type_name::<V>().to_string()),
+            ("palladiumdb_version", env!("CARGO_PKG_VERSION").to_string()),
+        ];
+        let out = BufWriter::new(File::create(&temp)?);
+        let mut out = PdbWriter::with_options(
+            out,
+            KIND,
+            KIND_VERSION,
+            &metadata,
+            self.compression,
+            self.encryption.clone(),
+        )?;
+        let mut buf = Vec::new();
+        let mut count = 0u64;
+        for (bucket, removals) in self.buckets.iter().zip(&changes.removals) {
+            {
+                // removals come first, a key removed and put again since
+                // the base being live
+                let guard = bucket.read(self.lock_policy.read);
+                for (key, _) in removals
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, removed)| *removed > base)
+                {
+                    key.encode(&mut buf);
+                    None::<&V>.encode(&mut buf);
+                    count += 1;
+                }
+                for (key, value) in guard.changed_entries(base) {
+                    key.encode(&mut buf);
+                    Some(value).encode(&mut buf);
+                    count += 1;
+                }
+            }
+            if buf.len() >= SECTION_SIZE {
+                out.write_section("changes", &buf)?;
+                buf.clear();
+            }
+        }
+        if !buf.is_empty() {
+            out.write_section("changes", &buf)?;
+        }
+
+        out.finish()?.into_inner()?.sync_all()?;
+        files::rename_durably(&temp, path)?;
+        self.snapshot_taken(version);
+        Ok(count)
+    }
+
+    /// Loads a `Map` from a snapshot written by [`Map::save_to`] followed by
+    /// the deltas written by [`Map::save_incremental`] since, with every
+    /// option but the bucket count at its default, see
+    /// [`MapBuilder::load_chain`].
+    pub fn load_chain<P: AsRef<Path>>(paths: &[P]) -> io::Result<Self>
+    where
+        K: Decode,
+        V: Decode,
+        H: Default,
+    {
+        MapBuilder::new().hasher(H::default()).load_chain(paths)
+    }
+
+    /// Makes the snapshot saved at `version` the base of the next delta,
+    /// dropping the removals it already reflects.
+    pub(super) fn snapshot_taken(&self, version: Version) {
+        if let Some(changes) = &self.changes {
+            let mut snapshot = changes.snapshot.lock().unwrap();
+            *snapshot = Some(version);
+            for removals in &changes.removals {
+                removals
+                    .lock()
+                    .unwrap()
+                    .retain(|(_, removed)| *removed > version);
+            }
+        }
+    }
+}
+
+impl<H: BuildHasher> MapBuilder<H> {
+    /// Loads a [`Map`] from a snapshot written by [`Map::save_to`] followed
+    /// by the deltas written by [`Map::save_incremental`] since, in the
+    /// order they were saved, with the options of the builder.
+    ///
+    /// The loaded map tracks its changes if the builder says so, but the
+    /// chain is not the base of its deltas until it saves a snapshot.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a file is not a snapshot or a
+    /// delta, is corrupted, or if a delta does not follow the file before
+    /// it in `paths`, and of kind `InvalidInput` if a file is encrypted but
+    /// the builder has no [`MapBuilder::encryption`].
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `paths` is empty.
+    pub fn load_chain<K, V, P>(self, paths: &[P]) -> io::Result<Map<K, V, H>>
+    where
+        K: Hash + Eq + Clone + Decode,
+        V: Clone + Decode,
+        P: AsRef<Path>,
+    {
+        let (full, deltas) = paths.split_first().expect("no snapshot to load");
+        let reader = PdbReader::new(BufReader::new(File::open(full)?))?;
+        let (map, version) = Map::load_pdb(self, reader)?;
+        let mut version =
+            version.ok_or_else(|| invalid_data("snapshot does not record its version"))?;
+
+        for path in deltas {
+            let mut reader = PdbReader::new(BufReader::new(File::open(path)?))?;
+            reader.expect_kind(KIND, KIND_VERSION)?;
+            if let Some(encryption) = &map.encryption {
+                reader.decrypt_with(encryption.clone());
+            }
+            let base: u64 = reader.parse_metadata("base_version")?;
+            if base != version {
+                return Err(invalid_data(&format!(
+                    "delta follows version {}, not {}",
+                    base, version
+                )));
+            }
+            version = reader.parse_metadata("version")?;
+
+            while let Some(section) = reader.next_section()? {
+                // sections added by later releases are skipped
+                if section.name != "changes" {
+                    continue;
+                }
+                let mut input = &section.payload[..];
+                while !input.is_empty() {
+                    let key = K::decode(&mut input)?;
+                    match Option::<V>::decode(&mut input)? {
+                        Some(value) => map.put(&key, value),
+                        None => map.unmap(&key),
+                    }
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::map::MapBuilder;
+    use crate::Map;
+    use std::fs;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_deltas_hold_only_changes_and_load_in_chain() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let paths: Vec<_> = (0..3)
+            .map(|index| dir.join(format!("palladiumdb-delta-{}-{}", id, index)))
+            .collect();
+
+        let map = MapBuilder::new()
+            .bucket_count(16)
+            .track_changes(true)
+            .build();
+        for key in 0..1000u64 {
+            map.put(&key, key);
+        }
+        let error = map.save_incremental(&paths[1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        map.save_to(&paths[0]).unwrap();
+        let full = fs::metadata(&paths[0]).unwrap().len();
+
+        for key in 0..10u64 {
+            map.put(&key, key * 10);
+        }
+        for key in 10..20u64 {
+            map.remove(&key);
+        }
+        assert_eq!(map.save_incremental(&paths[1]).unwrap(), 20);
+        assert!(fs::metadata(&paths[1]).unwrap().len() < full / 10);
+
+        // a key removed and put again since the last delta ends up live
+        map.remove(&0);
+        map.put(&0, 7);
+        map.remove(&1);
+        map.put(&10, 100);
+        map.collect_garbage();
+        assert_eq!(map.save_incremental(&paths[2]).unwrap(), 4);
+
+        let loaded: Map<u64, u64> = Map::load_chain(&paths).unwrap();
+        assert_eq!(loaded.len(), map.len());
+        for key in 0..1000u64 {
+            assert_eq!(loaded.get(&key), map.get(&key), "{}", key);
+        }
+
+        // deltas only load after the file they follow
+        let error = Map::<u64, u64>::load_chain(&[&paths[0], &paths[2]])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let untracked: Map<u64, u64> = Map::new();
+        let error = untracked.save_incremental(&paths[1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::collections::utils::mix;
+
+/// Bloom filter over the hashes of the keys stored in a bucket.
+///
+/// The filter is read and updated without holding the bucket's lock, which
+/// lets lookups of absent keys return early without contending for the lock
+/// at all. Bits are set before an entry becomes visible in the bucket, so a
+/// key present in the bucket is never reported as absent.
+///
+/// A filter without any bits is disabled and reports every key as possibly
+/// present.
+pub struct BucketFilter {
+    words: Box<[AtomicU64]>,
+}
+
+impl BucketFilter {
+    /// Number of bits set for every inserted hash.
+    const PROBES: u64 = 3;
+
+    /// Creates an empty filter of `bits` bits, rounded up to a multiple of
+    /// 64. A filter of 0 bits is disabled.
+    pub fn new(bits: usize) -> Self {
+        let words = (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect();
+        BucketFilter { words }
+    }
+
+    /// Returns the bytes allocated for the filter's bits.
+    pub fn allocated_bytes(&self) -> usize {
+        self.words.len() * 8
+    }
+
+    fn positions(&self, hash: u64) -> impl Iterator<Item = (usize, u64)> {
+        // the bucket index is derived from the low bits of `hash`, so they are
+        // all alike within a bucket; mix the hash before deriving bit positions
+        let mixed = mix(hash);
+        let (h1, h2) = (mixed & 0xffff_ffff, (mixed >> 32) | 1);
+        let bits = self.words.len() as u64 * 64;
+        (0..Self::PROBES).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+            ((bit / 64) as usize, 1u64 << (bit % 64))
+        })
+    }
+
+    /// Returns `false` if no key with the given `hash` was inserted since the
+    /// filter was last rebuilt.
+    pub fn might_contain(&self, hash: u64) -> bool {
+        self.words.is_empty()
+            || self
+                .positions(hash)
+                .all(|(word, mask)| self.words[word].load(Ordering::SeqCst) & mask != 0)
+    }
+
+    pub fn insert(&self, hash: u64) {
+        if self.words.is_empty() {
+            return;
+        }
+        for (word, mask) in self.positions(hash) {
+            self.words[word].fetch_or(mask, Ordering::SeqCst);
+        }
+    }
+
+    /// Resets the filter to only contain `hashes`, dropping removed keys.
+    ///
+    /// Must be called with the bucket's write lock held and `hashes` covering
+    /// every key of the bucket. Each word is replaced atomically and the bits
+    /// of the keys in `hashes` are set in both the old and the new word, so
+    /// concurrent lookups never miss one of those keys.
+    pub fn rebuild<I>(&self, hashes: I)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        if self.words.is_empty() {
+            return;
+        }
+        let mut words = vec![0u64; self.words.len()];
+        for hash in hashes {
+            for (word, mask) in self.positions(hash) {
+                words[word] |= mask;
+            }
+        }
+        for (word, bits) in self.words.iter().zip(words) {
+            word.store(bits, Ordering::SeqCst);
+        }
+    }
+}
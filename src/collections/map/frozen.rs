@@ -0,0 +1,79 @@
+use std::hash::{BuildHasher, Hash};
+
+/// An immutable copy of a [`Map`], see [`Map::freeze`].
+///
+/// Entries are stored in a single array sorted by hash, and looked up by
+/// binary search on the hash. No lock is ever taken, so any number of
+/// threads can read a `FrozenMap`, for instance shared behind an [`Arc`],
+/// without contending with each other or with writers of the `Map`.
+///
+/// [`Map`]: super::Map
+/// [`Map::freeze`]: super::Map::freeze
+/// [`Arc`]: std::sync::Arc
+pub struct FrozenMap<K, V, H> {
+    hash_builder: H,
+    entries: Box<[(u64, K, V)]>,
+}
+
+impl<K, V, H> FrozenMap<K, V, H>
+where
+    K: Hash + Eq,
+    H: BuildHasher,
+{
+    /// Creates a `FrozenMap` of `entries`, each with the hash of its key by
+    /// `hash_builder`.
+    pub(super) fn new(hash_builder: H, mut entries: Vec<(u64, K, V)>) -> Self {
+        entries.sort_unstable_by_key(|(hash, _, _)| *hash);
+        FrozenMap {
+            hash_builder,
+            entries: entries.into_boxed_slice(),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    ///
+    /// let frozen = map.freeze();
+    /// assert_eq!(frozen.get(&1), Some(&'a'));
+    /// assert_eq!(frozen.get(&2), None);
+    /// ```
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = self.hash_builder.hash_one(key);
+        let start = self
+            .entries
+            .partition_point(|(elem_hash, _, _)| *elem_hash < hash);
+        self.entries[start..]
+            .iter()
+            .take_while(|(elem_hash, _, _)| *elem_hash == hash)
+            .find(|(_, elem_key, _)| elem_key == key)
+            .map(|(_, _, value)| value)
+    }
+
+    /// Returns `true` if the `FrozenMap` contains a mapping for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns an iterator over the entries of the `FrozenMap`, in
+    /// arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.entries.iter().map(|(_, key, value)| (key, value))
+    }
+
+    /// Returns the number of entries in the `FrozenMap`.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the `FrozenMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,154 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::Map;
+
+/// Tunes how aggressively garbage collection reclaims memory from a [`Map`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Number of versions kept for every live entry, the rest are reclaimed.
+    /// Lower values free more memory but shorten how far back
+    /// [`Map::get_as_of`] can read. At least the latest version is always
+    /// kept.
+    pub retain_versions: usize,
+    /// Pause between two passes of a [`GarbageCollector`].
+    pub interval: Duration,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        GcPolicy {
+            retain_versions: 2,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Memory reclaimed by garbage collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Number of garbage collection passes over the whole map.
+    pub passes: u64,
+    /// Number of removed entries whose tombstones were reclaimed.
+    pub entries_reclaimed: u64,
+    /// Number of superseded versions reclaimed from live entries.
+    pub versions_reclaimed: u64,
+}
+
+/// Running totals of [`GcStats`] kept by a [`Map`].
+pub(crate) struct GcCounters {
+    passes: AtomicU64,
+    entries_reclaimed: AtomicU64,
+    versions_reclaimed: AtomicU64,
+}
+
+impl GcCounters {
+    pub(crate) fn new() -> Self {
+        GcCounters {
+            passes: AtomicU64::new(0),
+            entries_reclaimed: AtomicU64::new(0),
+            versions_reclaimed: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, stats: &GcStats) {
+        self.passes.fetch_add(stats.passes, Ordering::Relaxed);
+        self.entries_reclaimed
+            .fetch_add(stats.entries_reclaimed, Ordering::Relaxed);
+        self.versions_reclaimed
+            .fetch_add(stats.versions_reclaimed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn load(&self) -> GcStats {
+        GcStats {
+            passes: self.passes.load(Ordering::Relaxed),
+            entries_reclaimed: self.entries_reclaimed.load(Ordering::Relaxed),
+            versions_reclaimed: self.versions_reclaimed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Background thread running [`Map::collect_garbage`] on a map every
+/// [`GcPolicy::interval`].
+///
+/// The collector only holds a weak reference to the map and exits once the
+/// map is dropped. Dropping the collector stops the thread and waits for it
+/// to exit.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::collections::map::GarbageCollector;
+/// use palladiumdb::Map;
+/// use std::sync::Arc;
+///
+/// let map = Arc::new(Map::new());
+/// let collector = GarbageCollector::spawn(&map);
+///
+/// map.put(&1, "one");
+/// map.unmap(&1);
+///
+/// drop(collector);
+/// ```
+pub struct GarbageCollector {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl GarbageCollector {
+    /// Spawns a garbage collection thread for `map`.
+    pub fn spawn<K, V, H>(map: &Arc<Map<K, V, H>>) -> Self
+    where
+        K: Hash + Eq + Clone + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+        H: BuildHasher + Send + Sync + 'static,
+    {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let map = Arc::downgrade(map);
+        let thread_stopped = Arc::clone(&stopped);
+
+        let handle = thread::spawn(move || {
+            let (lock, condvar) = &*thread_stopped;
+            loop {
+                let interval = match map.upgrade() {
+                    Some(map) => map.gc_policy.interval,
+                    None => return,
+                };
+
+                let guard = lock.lock().unwrap();
+                let (guard, _) = condvar
+                    .wait_timeout_while(guard, interval, |stopped| !*stopped)
+                    .unwrap();
+                if *guard {
+                    return;
+                }
+                drop(guard);
+
+                match map.upgrade() {
+                    Some(map) => map.collect_garbage(),
+                    None => return,
+                };
+            }
+        });
+
+        GarbageCollector {
+            stopped,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for GarbageCollector {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        condvar.notify_all();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::Map;
+
+/// An iterator over the entries of a [`Map`], see [`Map::iter`].
+///
+/// The iterator only holds a bucket's read lock while fetching the next
+/// entry, so it never blocks writers for longer than a single [`Map::get`]
+/// would. The bucket it is walking is pinned, preventing entries from being
+/// moved around underneath it.
+///
+/// Every entry that is present for the whole duration of the iteration is
+/// yielded exactly once. Entries inserted or removed concurrently may or may
+/// not be yielded.
+pub struct Iter<'a, K, V, H> {
+    map: &'a Map<K, V, H>,
+    bucket: usize,
+    slot: usize,
+    pinned: bool,
+}
+
+impl<'a, K, V, H> Iter<'a, K, V, H> {
+    pub(super) fn new(map: &'a Map<K, V, H>) -> Self {
+        Iter {
+            map,
+            bucket: 0,
+            slot: 0,
+            pinned: false,
+        }
+    }
+}
+
+impl<'a, K, V, H> Iterator for Iter<'a, K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(bucket) = self.map.buckets.get(self.bucket) {
+            if !self.pinned {
+                bucket.pin();
+                self.pinned = true;
+                self.slot = 0;
+            }
+
+            let next = bucket.read(self.map.lock_policy.read).next_live(self.slot);
+            if let Some((slot, key, value)) = next {
+                self.slot = slot + 1;
+                return Some((key, value));
+            }
+
+            bucket.unpin();
+            self.pinned = false;
+            self.bucket += 1;
+        }
+        None
+    }
+}
+
+impl<'a, K, V, H> Drop for Iter<'a, K, V, H> {
+    fn drop(&mut self) {
+        if self.pinned {
+            self.map.buckets[self.bucket].unpin();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, Read, Write};
+
+use super::Map;
+use crate::codec::invalid_data;
+use crate::json::{FromJson, JsonReader, JsonValue, ToJson};
+
+impl<K, V, H> Map<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Writes the entries of the `Map` to `writer` as a JSON array of
+    /// `{"key": ..., "value": ...}` objects, one per line, so that it can
+    /// be inspected, diffed or edited with standard tooling and read back
+    /// by [`Map::import_json`].
+    ///
+    /// Buckets are copied one at a time, each under its read lock, and
+    /// written before the next one is copied, so writers are only held off
+    /// one bucket at a time and the map is never copied whole in memory.
+    /// Entries come in bucket order, sorting them is left to the tooling.
+    ///
+    /// Keys and values are written with [`ToJson`].
+    ///
+    /// # Returns
+    ///
+    /// The number of entries written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&"alice".to_string(), vec![31u64]);
+    ///
+    /// let mut json = Vec::new();
+    /// assert_eq!(map.export_json(&mut json).unwrap(), 1);
+    /// assert_eq!(String::from_utf8(json).unwrap(), "[\n{\"key\":\"alice\",\"value\":[31]}\n]\n");
+    /// ```
+    pub fn export_json<W: Write>(&self, mut writer: W) -> io::Result<u64>
+    where
+        K: ToJson,
+        V: ToJson,
+    {
+        writer.write_all(b"[")?;
+        let mut buf = String::new();
+        let mut count = 0u64;
+        for bucket in &self.buckets {
+            buf.clear();
+            {
+                let guard = bucket.read(self.lock_policy.read);
+                for (_, key, value) in guard.live_entries() {
+                    buf.push_str(if count == 0 { "\n" } else { ",\n" });
+                    buf.push_str("{\"key\":");
+                    key.to_json(&mut buf);
+                    buf.push_str(",\"value\":");
+                    value.to_json(&mut buf);
+                    buf.push('}');
+                    count += 1;
+                }
+            }
+            writer.write_all(buf.as_bytes())?;
+        }
+        writer.write_all(b"\n]\n")?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Puts the entries read from `reader`, a JSON array of
+    /// `{"key": ..., "value": ...}` objects as written by
+    /// [`Map::export_json`], replacing the values of keys already present.
+    ///
+    /// Entries are parsed and put one at a time, so the array is never held
+    /// whole in memory. Whitespace and the order of the members of an entry
+    /// are free.
+    ///
+    /// Keys and values are read with [`FromJson`].
+    ///
+    /// # Returns
+    ///
+    /// The number of entries put, or an error of kind `InvalidData` if the
+    /// input is not such an array or holds keys or values of other types,
+    /// in which case the entries before the faulty one were put.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map: Map<u64, String> = Map::new();
+    /// let json = r#"[{"value": "alice", "key": 1}, {"key": 2, "value": "bob"}]"#;
+    /// assert_eq!(map.import_json(json.as_bytes()).unwrap(), 2);
+    /// assert_eq!(map.get(&2), Some("bob".to_string()));
+    ///
+    /// assert!(map.import_json(&b"[{\"key\": \"3\", \"value\": \"carol\"}]"[..]).is_err());
+    /// ```
+    pub fn import_json<R: Read>(&self, reader: R) -> io::Result<u64>
+    where
+        K: FromJson,
+        V: FromJson,
+    {
+        let mut reader = JsonReader::new(BufReader::new(reader));
+        reader.begin_array()?;
+        let mut count = 0u64;
+        while reader.next_element(count == 0)? {
+            let (key, value) = decode_entry(reader.read_value()?)?;
+            self.put(&key, value);
+            count += 1;
+        }
+        reader.end()?;
+        Ok(count)
+    }
+}
+
+fn decode_entry<K: FromJson, V: FromJson>(entry: JsonValue) -> io::Result<(K, V)> {
+    let members = match entry {
+        JsonValue::Object(members) => members,
+        _ => return Err(invalid_data("expected an entry object")),
+    };
+    let (mut key, mut value) = (None, None);
+    for (name, member) in members {
+        let slot = match name.as_str() {
+            "key" => &mut key,
+            "value" => &mut value,
+            _ => return Err(invalid_data(&format!("unexpected entry member {}", name))),
+        };
+        if slot.replace(member).is_some() {
+            return Err(invalid_data(&format!("duplicate entry member {}", name)));
+        }
+    }
+    match (key, value) {
+        (Some(key), Some(value)) => Ok((K::from_json(key)?, V::from_json(value)?)),
+        _ => Err(invalid_data("entry without key or value")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Map;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_export_under_writes_imports_back_and_rejects_bad_entries() {
+        let map = Arc::new(Map::with_bucket_count(64));
+        for key in 0..1000i64 {
+            map.put(&key, (format!("line\n\"{}\"", key), vec![key as f64 / 4.0]));
+        }
+
+        let writer = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for key in 1000..2000i64 {
+                    map.put(&-key, (String::new(), Vec::new()));
+                }
+            })
+        };
+        let mut json = Vec::new();
+        let exported = map.export_json(&mut json).unwrap();
+        writer.join().unwrap();
+        assert!(exported >= 1000);
+        assert_eq!(
+            json.iter().filter(|&&byte| byte == b'\n').count() as u64,
+            exported + 2
+        );
+
+        let imported: Map<i64, (String, Vec<f64>)> = Map::new();
+        assert_eq!(imported.import_json(&json[..]).unwrap(), exported);
+        assert_eq!(imported.len() as u64, exported);
+        assert_eq!(
+            imported.get(&7),
+            Some(("line\n\"7\"".to_string(), vec![1.75]))
+        );
+
+        let empty: Map<u8, u8> = Map::new();
+        let mut json = Vec::new();
+        assert_eq!(empty.export_json(&mut json).unwrap(), 0);
+        assert_eq!(empty.import_json(&json[..]).unwrap(), 0);
+
+        let map: Map<u8, u8> = Map::new();
+        for bad in &[
+            "[{\"key\": 1}]",
+            "[{\"key\": 1, \"value\": 2, \"key\": 3}]",
+            "[{\"key\": 1, \"value\": 256}]",
+            "[{\"key\": 1, \"value\": 2, \"ttl\": 3}]",
+            "[[1, 2]]",
+            "[] {}",
+        ] {
+            let error = map.import_json(bad.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", bad);
+        }
+        let error = map
+            .import_json(&b"[{\"key\": 1, \"value\": 2},"[..])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(map.get(&1), Some(2));
+    }
+}
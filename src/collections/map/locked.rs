@@ -0,0 +1,107 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::bucket::{publish_together, BucketGuard};
+use super::{Map, Version};
+
+/// Access to a fixed set of keys of a [`Map`] whose buckets are all held
+/// locked at once, see [`Map::with_keys_locked`].
+///
+/// # Panics
+///
+/// Every method panics if it is called with a key that was not among the
+/// keys the buckets were locked for.
+pub struct LockedKeys<'a, K, V, H>
+where
+    K: Eq,
+{
+    map: &'a Map<K, V, H>,
+    /// The keys the buckets were locked for.
+    keys: &'a [K],
+    guards: Vec<(usize, BucketGuard<'a, K, V>)>,
+}
+
+impl<'a, K, V, H> LockedKeys<'a, K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    pub(super) fn new(
+        map: &'a Map<K, V, H>,
+        keys: &'a [K],
+        guards: Vec<(usize, BucketGuard<'a, K, V>)>,
+    ) -> Self {
+        LockedKeys { map, keys, guards }
+    }
+
+    /// Returns the hash of `key` along with the guard of its bucket.
+    fn guard_for(&mut self, key: &K) -> (u64, &mut BucketGuard<'a, K, V>) {
+        // keys sharing a bucket with a locked one are refused all the same
+        assert!(
+            self.keys.contains(key),
+            "key was not locked by with_keys_locked"
+        );
+        let hash = self.map.hash(key);
+        let index = self.map.bucket_index_for_hash(hash);
+        match self.guards.binary_search_by_key(&index, |(i, _)| *i) {
+            Ok(position) => (hash, &mut self.guards[position].1),
+            Err(_) => panic!("key was not locked by with_keys_locked"),
+        }
+    }
+
+    /// Returns the value corresponding to the key.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let (hash, guard) = self.guard_for(key);
+        guard.latest(hash, key).cloned()
+    }
+
+    /// Returns the value corresponding to the key along with its [`Version`].
+    pub fn get_versioned(&mut self, key: &K) -> Option<(V, Version)> {
+        let (hash, guard) = self.guard_for(key);
+        guard.get_versioned(hash, key)
+    }
+
+    /// Establishes a key value mapping for the key value pair.
+    ///
+    /// The put is dropped if it takes a map limited in size over its limits
+    /// and its [`QuotaPolicy`](super::QuotaPolicy) rejects writes.
+    pub fn put(&mut self, key: &K, value: V) {
+        let map = self.map;
+        let (hash, guard) = self.guard_for(key);
+        let _ = map.put_locked(guard, hash, key, value);
+    }
+
+    /// Erases the value associated with `key`, if present.
+    pub fn unmap(&mut self, key: &K) {
+        let map = self.map;
+        let (hash, guard) = self.guard_for(key);
+        map.unmap_locked(guard, hash, |k| k == key);
+    }
+}
+
+impl<K, V, H> Drop for LockedKeys<'_, K, V, H>
+where
+    K: Eq,
+{
+    fn drop(&mut self) {
+        // readers see the writes to every locked bucket at once, as they
+        // would have had they waited for the locks
+        publish_together(&mut self.guards);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Map;
+
+    #[test]
+    #[should_panic(expected = "key was not locked by with_keys_locked")]
+    fn test_keys_not_locked_are_refused_even_in_a_locked_bucket() {
+        let map = Map::with_bucket_count(1);
+        map.put(&"alice", 100);
+        map.with_keys_locked(&["alice"], |locked| {
+            assert_eq!(locked.get(&"alice"), Some(100));
+            locked.put(&"bob", 20);
+        });
+    }
+}
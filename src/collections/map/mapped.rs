@@ -0,0 +1,392 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::snapshot::{key_hash, ENTRIES_SECTION, INDEX_SECTION, KIND, KIND_VERSION};
+use super::Map;
+use crate::codec::{invalid_data, Decode, Encode};
+use crate::pdb::PdbReader;
+
+/// Size of a record of the index of a snapshot: the hash of the encoded key
+/// of an entry and its position, both as `u64`.
+const INDEX_RECORD_SIZE: usize = 16;
+
+impl<K, V> Map<K, V> {
+    /// Opens the snapshot at `path`, written by [`Map::save_to`], as a
+    /// read-only [`MappedSnapshot`] serving its entries straight from the
+    /// file mapped in memory.
+    ///
+    /// Only the entries read are decoded, and pages of the file are only read
+    /// from disk as entries on them are. The checksums of the sections are
+    /// not checked, which would read the whole file, as [`Map::load_from`]
+    /// does. The index of a snapshot written with [`SnapshotWriter::index`]
+    /// is looked up in place, so opening it takes about as long for a
+    /// snapshot of any size. Other snapshots, such as those [`Map::save_to`]
+    /// writes, are indexed once when opened, which decodes every key and
+    /// keeps 16 bytes an entry in memory.
+    ///
+    /// [`SnapshotWriter::index`]: super::SnapshotWriter::index
+    ///
+    /// The file must not be modified while mapped. [`Map::save_to`] never
+    /// does, as it replaces snapshots by renaming a new file over them.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the file is not a snapshot or is
+    /// corrupted, and of kind `InvalidInput` if its entries are compressed
+    /// or encrypted, which cannot be served in place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let path = std::env::temp_dir().join("palladiumdb-doc-mapped.snap");
+    /// let map = Map::new();
+    /// map.put(&"alice".to_string(), 100u64);
+    /// map.save_to(&path).unwrap();
+    ///
+    /// let replica = Map::<String, u64>::open_readonly_snapshot(&path).unwrap();
+    /// assert_eq!(replica.get(&"alice".to_string()).unwrap(), Some(100));
+    /// assert_eq!(replica.len(), 1);
+    /// # drop(replica);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open_readonly_snapshot<P: AsRef<Path>>(path: P) -> io::Result<MappedSnapshot<K, V>>
+    where
+        K: Decode,
+        V: Decode,
+    {
+        MappedSnapshot::open(path.as_ref())
+    }
+}
+
+/// Entries of a snapshot served from the file mapped in memory, see
+/// [`Map::open_readonly_snapshot`].
+///
+/// Lookups binary search the index of the snapshot for the hash of the
+/// encoded key, then compare encoded keys, so only the value found is
+/// decoded. No lock is ever taken, so any number of threads can read a
+/// `MappedSnapshot` at once.
+pub struct MappedSnapshot<K, V> {
+    mmap: Mmap,
+    // payloads of the entries sections, along with the position of their
+    // first byte among all of them
+    sections: Vec<(u64, Range<usize>)>,
+    index: Index,
+    entries: PhantomData<fn() -> (K, V)>,
+}
+
+/// Records of the index: mapped in place, or built when opening a snapshot
+/// written without one.
+enum Index {
+    Mapped(Range<usize>),
+    Built(Vec<(u64, u64)>),
+}
+
+impl<K, V> MappedSnapshot<K, V>
+where
+    K: Decode,
+    V: Decode,
+{
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: snapshots are replaced by renaming a new file over them,
+        // never modified in place, see `Map::open_readonly_snapshot`.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let reader = PdbReader::new(&mmap[..])?;
+        reader.expect_kind(KIND, KIND_VERSION)?;
+        if !reader.is_plain() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "snapshot entries are compressed or encrypted",
+            ));
+        }
+
+        let mut sections = Vec::new();
+        let mut position = 0;
+        let mut index = None;
+        for (name, payload) in reader.locate_sections(&mmap)? {
+            if name == ENTRIES_SECTION {
+                position += payload.len() as u64;
+                sections.push((position - payload.len() as u64, payload));
+            } else if name == INDEX_SECTION {
+                if payload.len() % INDEX_RECORD_SIZE != 0 {
+                    return Err(invalid_data("snapshot index corrupted"));
+                }
+                index = Some(Index::Mapped(payload));
+            }
+        }
+        let mut snapshot = MappedSnapshot {
+            mmap,
+            sections,
+            index: Index::Built(Vec::new()),
+            entries: PhantomData,
+        };
+        snapshot.index = match index {
+            Some(index) => index,
+            None => Index::Built(snapshot.build_index()?),
+        };
+        Ok(snapshot)
+    }
+
+    /// Indexes the entries of a snapshot written without an index.
+    fn build_index(&self) -> io::Result<Vec<(u64, u64)>> {
+        let mut index = Vec::new();
+        for (position, payload) in &self.sections {
+            let payload = &self.mmap[payload.clone()];
+            let mut input = payload;
+            while !input.is_empty() {
+                let start = payload.len() - input.len();
+                K::decode(&mut input)?;
+                let key = &payload[start..payload.len() - input.len()];
+                index.push((key_hash(key), position + start as u64));
+                V::decode(&mut input)?;
+            }
+        }
+        index.sort_unstable();
+        Ok(index)
+    }
+
+    /// Returns the hash and the position of the entry at `at` in the index.
+    fn record(&self, at: usize) -> (u64, u64) {
+        match &self.index {
+            Index::Mapped(range) => {
+                let start = range.start + at * INDEX_RECORD_SIZE;
+                let record = &self.mmap[start..start + INDEX_RECORD_SIZE];
+                let (hash, position) = record.split_at(8);
+                (
+                    u64::from_le_bytes(hash.try_into().unwrap()),
+                    u64::from_le_bytes(position.try_into().unwrap()),
+                )
+            }
+            Index::Built(records) => records[at],
+        }
+    }
+
+    /// Returns the bytes of the entries section holding `position`, from
+    /// the entry at `position` on.
+    fn entry_at(&self, position: u64) -> io::Result<&[u8]> {
+        let at = self
+            .sections
+            .partition_point(|(start, _)| *start <= position)
+            .checked_sub(1)
+            .ok_or_else(|| invalid_data("snapshot index corrupted"))?;
+        let (start, payload) = &self.sections[at];
+        let offset = (position - start) as usize;
+        self.mmap[payload.clone()]
+            .get(offset..)
+            .filter(|entry| !entry.is_empty())
+            .ok_or_else(|| invalid_data("snapshot index corrupted"))
+    }
+
+    /// Returns the value corresponding to the key, decoded from the
+    /// snapshot.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the entry found is corrupted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let path = std::env::temp_dir().join("palladiumdb-doc-mapped-get.snap");
+    /// let map = Map::new();
+    /// map.put(&1u64, "one".to_string());
+    /// map.save_to(&path).unwrap();
+    ///
+    /// let replica = Map::<u64, String>::open_readonly_snapshot(&path).unwrap();
+    /// assert_eq!(replica.get(&1).unwrap(), Some("one".to_string()));
+    /// assert_eq!(replica.get(&2).unwrap(), None);
+    /// # drop(replica);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn get(&self, key: &K) -> io::Result<Option<V>>
+    where
+        K: Encode,
+    {
+        let mut encoded = Vec::new();
+        key.encode(&mut encoded);
+        let hash = key_hash(&encoded);
+        let mut at = partition_point(self.len(), |at| self.record(at).0 < hash);
+        while at < self.len() {
+            let (elem_hash, position) = self.record(at);
+            if elem_hash != hash {
+                break;
+            }
+            // encodings are self-delimiting, so a key encoded the same as
+            // the start of the entry is its key
+            let entry = self.entry_at(position)?;
+            if entry.starts_with(&encoded) {
+                return V::decode(&mut &entry[encoded.len()..]).map(Some);
+            }
+            at += 1;
+        }
+        Ok(None)
+    }
+
+    /// Returns `true` if the snapshot contains a mapping for `key`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the entry found is corrupted.
+    pub fn contains_key(&self, key: &K) -> io::Result<bool>
+    where
+        K: Encode,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Returns an iterator over the entries of the snapshot, in the order
+    /// they were saved, decoding them as it goes.
+    ///
+    /// The iterator yields an error of kind `InvalidData` at the first
+    /// corrupted entry, then ends.
+    pub fn iter(&self) -> MappedIter<'_, K, V> {
+        MappedIter {
+            snapshot: self,
+            section: 0,
+            input: &[],
+        }
+    }
+
+    /// Returns the number of entries in the snapshot.
+    pub fn len(&self) -> usize {
+        match &self.index {
+            Index::Mapped(range) => range.len() / INDEX_RECORD_SIZE,
+            Index::Built(records) => records.len(),
+        }
+    }
+
+    /// Returns `true` if the snapshot contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Returns the index of the first of `len` items for which `pred` is false,
+/// all of those for which it is true coming first.
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if pred(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// Iterator over the entries of a [`MappedSnapshot`], see
+/// [`MappedSnapshot::iter`].
+pub struct MappedIter<'a, K, V> {
+    snapshot: &'a MappedSnapshot<K, V>,
+    section: usize,
+    input: &'a [u8],
+}
+
+impl<K, V> Iterator for MappedIter<'_, K, V>
+where
+    K: Decode,
+    V: Decode,
+{
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.input.is_empty() {
+            let (_, payload) = self.snapshot.sections.get(self.section)?;
+            self.input = &self.snapshot.mmap[payload.clone()];
+            self.section += 1;
+        }
+        let entry = K::decode(&mut self.input).and_then(|key| {
+            let value = V::decode(&mut self.input)?;
+            Ok((key, value))
+        });
+        if entry.is_err() {
+            self.input = &[];
+            self.section = self.snapshot.sections.len();
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Index;
+    #[cfg(feature = "encryption")]
+    use crate::collections::map::MapBuilder;
+    #[cfg(feature = "encryption")]
+    use crate::encryption::{Encryption, Key, KeyRing};
+    use crate::pdb::PdbWriter;
+    use crate::Map;
+    use std::fs;
+    #[cfg(feature = "encryption")]
+    use std::io::ErrorKind;
+    #[cfg(feature = "encryption")]
+    use std::sync::Arc;
+
+    #[test]
+    fn test_serves_entries_of_indexed_and_unindexed_snapshots() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-mapped-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("map.snap");
+
+        // several entries sections
+        let map = Map::with_bucket_count(64);
+        for key in 0..20_000u64 {
+            map.put(&key, format!("value {}", key));
+        }
+        map.snapshot_writer().index(true).save_to(&path).unwrap();
+        let replica = Map::<u64, String>::open_readonly_snapshot(&path).unwrap();
+        assert!(matches!(replica.index, Index::Mapped(_)));
+        assert!(replica.sections.len() > 1);
+        assert_eq!(replica.len(), 20_000);
+        for key in (0..20_000u64).step_by(97) {
+            assert_eq!(replica.get(&key).unwrap(), Some(format!("value {}", key)));
+        }
+        assert_eq!(replica.get(&20_000).unwrap(), None);
+        let mut keys: Vec<u64> = replica.iter().map(|entry| entry.unwrap().0).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..20_000).collect::<Vec<_>>());
+
+        // as written by releases before the index
+        let mut writer =
+            PdbWriter::new(Vec::new(), "map", 1, &[("bucket_count", "1".to_string())]).unwrap();
+        let mut entries = Vec::new();
+        for (key, value) in [("a", 1u32), ("b", 2)] {
+            crate::codec::Encode::encode(&key.to_string(), &mut entries);
+            crate::codec::Encode::encode(&value, &mut entries);
+        }
+        writer.write_section("entries", &entries).unwrap();
+        fs::write(&path, writer.finish().unwrap()).unwrap();
+        let replica = Map::<String, u32>::open_readonly_snapshot(&path).unwrap();
+        assert!(matches!(replica.index, Index::Built(_)));
+        assert_eq!(replica.get(&"b".to_string()).unwrap(), Some(2));
+        assert_eq!(replica.iter().count(), 2);
+
+        // encrypted snapshots are not served
+        #[cfg(feature = "encryption")]
+        {
+            let key = Key::new([7; 32]);
+            let encryption = Encryption::new(Arc::new(KeyRing::new(key))).unwrap();
+            let encrypted: Map<u64, u64> = MapBuilder::new().encryption(encryption).build();
+            encrypted.put(&1, 1);
+            encrypted.save_to(&path).unwrap();
+            let err = Map::<u64, u64>::open_readonly_snapshot(&path)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use std::mem;
+
+/// Approximate memory used by a [`Map`], in bytes.
+///
+/// [`Map`]: super::Map
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes taken by the bucket array itself, including the buckets' Bloom
+    /// filters.
+    pub buckets: usize,
+    /// Bytes allocated for entries within the buckets, including their keys,
+    /// retained versions and spare capacity. Memory owned by the values
+    /// themselves is not included.
+    pub entries: usize,
+    /// Heap bytes owned by the values of every retained version, as
+    /// reported by [`MeasureSize`]. Only measured by
+    /// [`Map::deep_memory_usage`].
+    ///
+    /// [`Map::deep_memory_usage`]: super::Map::deep_memory_usage
+    pub values: Option<usize>,
+}
+
+impl MemoryStats {
+    /// Returns the sum of all measured bytes.
+    pub fn total(&self) -> usize {
+        self.buckets + self.entries + self.values.unwrap_or(0)
+    }
+}
+
+/// Types that can report the heap memory they own.
+///
+/// Only memory owned through pointers needs to be reported, the inline size
+/// of a value (its [`mem::size_of`]) is accounted for by its container.
+pub trait MeasureSize {
+    /// Returns the number of heap bytes owned by `self`.
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! impl_measure_size_inline {
+    ($($t:ty),*) => {
+        $(impl MeasureSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+impl_measure_size_inline!(
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    ()
+);
+
+impl MeasureSize for &str {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl MeasureSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: MeasureSize> MeasureSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<T>() + self.iter().map(T::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: MeasureSize> MeasureSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        mem::size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T: MeasureSize> MeasureSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, T::heap_size)
+    }
+}
@@ -1,20 +1,90 @@
 mod bucket;
-mod utils;
+mod builder;
+#[cfg(feature = "serde")]
+mod csv;
+mod delta;
+mod filter;
+mod frozen;
+mod gc;
+mod iter;
+mod json;
+mod locked;
+#[cfg(feature = "mmap")]
+mod mapped;
+mod memory;
+mod quota;
+mod raw;
+mod snapshot;
+mod version;
 
 use std::collections::hash_map::RandomState;
-use std::hash::{BuildHasher, Hash, Hasher};
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use self::bucket::Bucket;
+use self::bucket::{lock_buckets, Bucket, BucketGuard};
+use self::delta::ChangeLog;
+use self::gc::GcCounters;
+use self::quota::Quota;
+use self::version::Clock;
+use crate::collections::utils::random;
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+
+pub use self::builder::MapBuilder;
+pub use self::frozen::FrozenMap;
+pub use self::gc::{GarbageCollector, GcPolicy, GcStats};
+pub use self::iter::Iter;
+pub use self::locked::LockedKeys;
+#[cfg(feature = "mmap")]
+pub use self::mapped::{MappedIter, MappedSnapshot};
+pub use self::memory::{MeasureSize, MemoryStats};
+pub use self::quota::{QuotaExceeded, QuotaPolicy, QuotaStats};
+pub use self::raw::RawEntry;
+pub use self::snapshot::SnapshotWriter;
+pub use self::version::Version;
+pub use crate::collections::utils::{Acquire, LockPolicy};
+
+pub(crate) const DEFAULT_BUCKET_COUNT: usize = 19;
 
 /// Thread-Safe map implemented as hash table.
+///
+/// Every entry keeps a short chain of its most recently committed versions,
+/// see [`Map::get_versioned`] and [`Map::get_as_of`]. Committed chains are
+/// published apart from the buckets, so those two reads skip the lock of the
+/// entry's bucket and never wait for a write in progress on it, at the cost
+/// of a copy of every key in the published index.
+///
+/// A `Map` can be limited in size, see [`MapBuilder::max_entries`] and
+/// [`MapBuilder::max_bytes`].
 pub struct Map<K, V, H = RandomState> {
     hash_builder: H,
     buckets: Vec<Bucket<K, V>>,
+    clock: Clock,
+    len: AtomicUsize,
+    lock_policy: LockPolicy,
+    gc_policy: GcPolicy,
+    gc_counters: GcCounters,
+    changes: Option<ChangeLog<K>>,
+    compression: Compression,
+    encryption: Option<Encryption>,
+    quota: Option<Quota<K>>,
+    weigh: fn(&K, &V) -> usize,
+}
+
+impl<K, V> Default for Map<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<K, V> Map<K, V, RandomState>
 where
-    K: Hash + Eq + Copy,
+    K: Hash + Eq + Clone,
     V: Clone,
 {
     /// Creates an empty `Map`
@@ -28,7 +98,7 @@ where
     /// let map: Map<&str, i32> = Map::new();
     /// ```
     pub fn new() -> Self {
-        Self::with_bucket_count(Self::DEFAULT_BUCKET_COUNT)
+        Self::with_bucket_count(DEFAULT_BUCKET_COUNT)
     }
 
     /// Creates an empty `Map` with a given bucket count.
@@ -56,12 +126,10 @@ where
 
 impl<K, V, H> Map<K, V, H>
 where
-    K: Hash + Eq + Copy,
+    K: Hash + Eq + Clone,
     V: Clone,
     H: BuildHasher,
 {
-    const DEFAULT_BUCKET_COUNT: usize = 19;
-
     /// Creates an empty `Map` with `bucket_count` buckets allocated, using
     /// `hash_builder` to hash the keys.
     ///
@@ -84,13 +152,10 @@ where
     /// map.put(&"Two", 2);
     /// ```
     pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
-        let mut buckets = Vec::with_capacity(bucket_count);
-        buckets.resize_with(bucket_count, || Bucket::new());
-
-        Map {
-            hash_builder,
-            buckets,
-        }
+        MapBuilder::new()
+            .hasher(hash_builder)
+            .bucket_count(bucket_count)
+            .build()
     }
 
     /// Creates an empty `Map` which will use the given hash builder to hash
@@ -113,16 +178,42 @@ where
     /// map.put(&"Two",2)
     /// ```
     pub fn with_hasher(hash_builder: H) -> Self {
-        Self::with_hasher_and_bucket_count(hash_builder, Self::DEFAULT_BUCKET_COUNT)
+        Self::with_hasher_and_bucket_count(hash_builder, DEFAULT_BUCKET_COUNT)
     }
 
-    fn get_bucket(&self, key: &K) -> &Bucket<K, V> {
-        let mut hasher = self.hash_builder.build_hasher();
-        key.hash(&mut hasher);
-        let hash = hasher.finish() as usize;
-        let bucket_index = hash % self.buckets.len();
+    pub(crate) fn hash(&self, key: &K) -> u64 {
+        self.hash_builder.hash_one(key)
+    }
+
+    fn bucket_index_for_hash(&self, hash: u64) -> usize {
+        hash as usize % self.buckets.len()
+    }
+
+    fn bucket_for_hash(&self, hash: u64) -> &Bucket<K, V> {
+        &self.buckets[self.bucket_index_for_hash(hash)]
+    }
+
+    /// Returns the hash of `key` along with the bucket the key belongs to.
+    fn locate(&self, key: &K) -> (u64, &Bucket<K, V>) {
+        let hash = self.hash(key);
+        (hash, self.bucket_for_hash(hash))
+    }
+
+    /// Returns the index of the bucket `key` belongs to, for collections
+    /// keeping per-bucket state alongside the `Map`.
+    pub(crate) fn bucket_index(&self, key: &K) -> usize {
+        self.bucket_index_for_hash(self.hash(key))
+    }
+
+    pub(crate) fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
 
-        &self.buckets[bucket_index]
+    /// Returns the keys mapped in bucket `index`, or `None` past the last
+    /// bucket, for collections walking the `Map` a bucket at a time.
+    pub(crate) fn bucket_keys(&self, index: usize) -> Option<Vec<K>> {
+        let bucket = self.buckets.get(index)?;
+        Some(bucket.read(self.lock_policy.read).live_keys())
     }
 
     /// Establishes a key value mapping for the key value pair.
@@ -145,8 +236,133 @@ where
     /// assert_eq!(map.get(&"Two"), Some(2));
     /// assert_eq!(map.get(&"First"), Some(0));
     /// ```
+    ///
+    /// A put taking a map limited in size over its limits is dropped if its
+    /// [`QuotaPolicy`] rejects writes, see [`Map::try_put`] for a put
+    /// telling whether it was.
     pub fn put(&self, key: &K, value: V) {
-        self.get_bucket(key).put(key, value)
+        let _ = self.try_put(key, value);
+    }
+
+    /// Establishes a key value mapping for the key value pair, as
+    /// [`Map::put`] does, unless it would take the `Map` over its limits
+    /// while its [`QuotaPolicy`] rejects such writes.
+    ///
+    /// # Returns
+    ///
+    /// [`QuotaExceeded`] if the put was rejected, in which case the `Map`
+    /// is left as it was.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::map::{MapBuilder, QuotaExceeded};
+    /// use palladiumdb::Map;
+    ///
+    /// let map: Map<&str, i32> = MapBuilder::new().max_entries(1).build();
+    ///
+    /// assert_eq!(map.try_put(&"First", 1), Ok(()));
+    /// assert_eq!(map.try_put(&"Two", 2), Err(QuotaExceeded));
+    /// assert_eq!(map.try_put(&"First", 0), Ok(()));
+    /// ```
+    pub fn try_put(&self, key: &K, value: V) -> Result<(), QuotaExceeded> {
+        let (hash, bucket) = self.locate(key);
+        self.put_locked(&mut bucket.write(self.lock_policy.write), hash, key, value)?;
+        self.enforce_quota(Some((hash, key)));
+        Ok(())
+    }
+
+    /// Establishes a key value mapping for the key value pair, only if the
+    /// key is not mapped yet.
+    ///
+    /// The key is first looked up under the bucket's read lock, so the write
+    /// lock is only taken when the key turns out to be absent. As locks
+    /// cannot be upgraded in place, the lookup is repeated under the write
+    /// lock before inserting.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was inserted, `false` if the key was mapped already
+    /// or the put was rejected as over the limits of the `Map`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    ///
+    /// assert!(map.put_if_absent(&"First", 1));
+    /// assert!(!map.put_if_absent(&"First", 2));
+    /// assert_eq!(map.get(&"First"), Some(1));
+    /// ```
+    pub fn put_if_absent(&self, key: &K, value: V) -> bool {
+        let (hash, bucket) = self.locate(key);
+        if bucket.might_contain(hash)
+            && bucket
+                .read(self.lock_policy.read)
+                .latest(hash, key)
+                .is_some()
+        {
+            return false;
+        }
+
+        let mut guard = bucket.write(self.lock_policy.write);
+        if guard.latest(hash, key).is_some()
+            || self.put_locked(&mut guard, hash, key, value).is_err()
+        {
+            return false;
+        }
+        drop(guard);
+        self.enforce_quota(Some((hash, key)));
+        true
+    }
+
+    /// Establishes a key value mapping for the key value pair, unless the
+    /// key is already mapped to an equal value.
+    ///
+    /// Overwriting a value with an equal one is detected under the bucket's
+    /// read lock, in which case neither the write lock is taken nor a new
+    /// [`Version`] committed.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was written, `false` if the key was mapped to an
+    /// equal value or the put was rejected as over the limits of the `Map`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&"First", 1);
+    /// let (_, version) = map.get_versioned(&"First").unwrap();
+    ///
+    /// assert!(!map.put_if_changed(&"First", 1));
+    /// assert_eq!(map.get_versioned(&"First"), Some((1, version)));
+    ///
+    /// assert!(map.put_if_changed(&"First", 2));
+    /// assert_eq!(map.get(&"First"), Some(2));
+    /// ```
+    pub fn put_if_changed(&self, key: &K, value: V) -> bool
+    where
+        V: PartialEq,
+    {
+        let (hash, bucket) = self.locate(key);
+        if bucket.read(self.lock_policy.read).latest(hash, key) == Some(&value) {
+            return false;
+        }
+
+        let mut guard = bucket.write(self.lock_policy.write);
+        if guard.latest(hash, key) == Some(&value)
+            || self.put_locked(&mut guard, hash, key, value).is_err()
+        {
+            return false;
+        }
+        drop(guard);
+        self.enforce_quota(Some((hash, key)));
+        true
     }
 
     /// Returns the value corresponding to the key.
@@ -162,12 +378,91 @@ where
     /// assert_eq!(map.get(&2), None);
     /// ```
     pub fn get(&self, key: &K) -> Option<V> {
-        self.get_bucket(key).get(key)
+        let (hash, bucket) = self.locate(key);
+        if !bucket.might_contain(hash) {
+            return None;
+        }
+        let value = bucket
+            .read(self.lock_policy.read)
+            .latest(hash, key)
+            .cloned();
+        if let (Some(quota), Some(_)) = (&self.quota, &value) {
+            quota.touch(self.bucket_index_for_hash(hash), key);
+        }
+        value
+    }
+
+    /// Returns the latest committed value corresponding to the key, along
+    /// with the [`Version`] at which it was committed.
+    ///
+    /// The value is read from the chains published by completed writes,
+    /// without taking the lock of the key's bucket: a write in progress on
+    /// the bucket is not waited for, and is not seen until it completes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    /// let (_, first) = map.get_versioned(&1).unwrap();
+    ///
+    /// map.put(&1, 'b');
+    /// let (value, second) = map.get_versioned(&1).unwrap();
+    ///
+    /// assert_eq!(value, 'b');
+    /// assert!(second > first);
+    /// ```
+    pub fn get_versioned(&self, key: &K) -> Option<(V, Version)> {
+        let (hash, bucket) = self.locate(key);
+        if !bucket.might_contain(hash) {
+            return None;
+        }
+        bucket.get_versioned(hash, key)
+    }
+
+    /// Returns the value corresponding to the key as it was at `version`.
+    ///
+    /// Only a few of the most recent versions of every entry are retained,
+    /// so reading far enough into the past returns `None`. The value is read
+    /// without taking the lock of the key's bucket, as [`Map::get_versioned`]
+    /// reads it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    /// let before = map.current_version();
+    /// map.put(&1, 'b');
+    ///
+    /// assert_eq!(map.get_as_of(&1, before), Some('a'));
+    /// assert_eq!(map.get_as_of(&1, map.current_version()), Some('b'));
+    /// ```
+    pub fn get_as_of(&self, key: &K, version: Version) -> Option<V> {
+        let (hash, bucket) = self.locate(key);
+        if !bucket.might_contain(hash) {
+            return None;
+        }
+        bucket.get_as_of(hash, key, version)
+    }
+
+    /// Returns the [`Version`] of the latest write committed to the `Map`.
+    pub fn current_version(&self) -> Version {
+        self.clock.now()
     }
 
     /// Erases the value associated with `key`, if present,
     /// from the `Map`.
     ///
+    /// The removal is committed as a tombstone version of the entry rather
+    /// than moving entries around, so concurrently running iterators are not
+    /// disturbed. The dead entry is compacted away on the next write to its
+    /// bucket once no iterator is walking it, or by [`Map::collect_garbage`].
+    ///
     /// # Examples
     ///
     /// ```
@@ -183,7 +478,531 @@ where
     /// assert_eq!(map.get(&"TheBestNumber"), None);
     /// ```
     pub fn unmap(&self, key: &K) {
-        self.get_bucket(key).unmap(key);
+        let (hash, bucket) = self.locate(key);
+        if !bucket.might_contain(hash) {
+            return;
+        }
+        self.unmap_locked(&mut bucket.write(self.lock_policy.write), hash, |k| {
+            k == key
+        });
+    }
+
+    /// Erases the value associated with `key` from the `Map`, returning it
+    /// if it was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&"MyNumber", 35642);
+    ///
+    /// assert_eq!(map.remove(&"MyNumber"), Some(35642));
+    /// assert_eq!(map.remove(&"MyNumber"), None);
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let (hash, bucket) = self.locate(key);
+        if !bucket.might_contain(hash) {
+            return None;
+        }
+        let mut guard = bucket.write(self.lock_policy.write);
+        let value = guard.latest(hash, key).cloned();
+        if value.is_some() {
+            self.unmap_locked(&mut guard, hash, |k| k == key);
+        }
+        value
+    }
+
+    /// Returns the number of entries in the `Map`.
+    ///
+    /// The count is kept up to date by every write, but is not synchronized
+    /// with writes still in progress on other threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    /// map.put(&2, 'b');
+    /// map.put(&2, 'c');
+    /// map.unmap(&1);
+    ///
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `Map` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Commits a put through an already locked bucket, keeping the entry
+    /// count and the usage of the quota of the `Map` up to date.
+    ///
+    /// # Returns
+    ///
+    /// [`QuotaExceeded`] if the put was rejected by the quota.
+    fn put_locked(
+        &self,
+        guard: &mut BucketGuard<'_, K, V>,
+        hash: u64,
+        key: &K,
+        value: V,
+    ) -> Result<(), QuotaExceeded> {
+        let quota = match &self.quota {
+            Some(quota) => quota,
+            None => {
+                if guard.put(hash, key, value, &self.clock) {
+                    self.len.fetch_add(1, Ordering::SeqCst);
+                }
+                return Ok(());
+            }
+        };
+        let old = guard.latest(hash, key).map(|old| (self.weigh)(key, old));
+        let new = (self.weigh)(key, &value);
+        if quota.policy() == QuotaPolicy::Reject && !quota.admit(self.len(), old, new) {
+            return Err(QuotaExceeded);
+        }
+        if guard.put(hash, key, value, &self.clock) {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        quota.put(self.bucket_index_for_hash(hash), key, old.unwrap_or(0), new);
+        Ok(())
+    }
+
+    /// Commits an unmap through an already locked bucket, keeping the entry
+    /// count up to date.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a mapped entry was found.
+    fn unmap_locked<F>(&self, guard: &mut BucketGuard<'_, K, V>, hash: u64, is_match: F) -> bool
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.remove_locked(guard, hash, is_match, false)
+    }
+
+    /// Commits an unmap through an already locked bucket, as
+    /// [`Map::unmap_locked`] does, counting it as an eviction if `evicted`.
+    fn remove_locked<F>(
+        &self,
+        guard: &mut BucketGuard<'_, K, V>,
+        hash: u64,
+        mut is_match: F,
+        evicted: bool,
+    ) -> bool
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let weight = match &self.quota {
+            Some(_) => guard
+                .latest_entry_by(hash, &mut is_match)
+                .map(|(key, value)| (self.weigh)(key, value)),
+            None => None,
+        };
+        match guard.unmap_by(hash, is_match, &self.clock) {
+            Some((key, version)) => {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                let index = self.bucket_index_for_hash(hash);
+                if let Some(quota) = &self.quota {
+                    quota.removed(index, key, weight.unwrap_or(0), evicted);
+                }
+                if let Some(changes) = &self.changes {
+                    changes.record_removal(index, key, version);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evicts entries until the `Map` is within its limits again, if its
+    /// [`QuotaPolicy`] evicts. The entry just written, given by its hash and
+    /// key, is only evicted once every other entry is.
+    ///
+    /// Must be called without any bucket locked.
+    fn enforce_quota(&self, written: Option<(u64, &K)>) {
+        let quota = match &self.quota {
+            Some(quota) if quota.policy() != QuotaPolicy::Reject => quota,
+            _ => return,
+        };
+        let start = match written {
+            Some((hash, _)) if quota.policy() == QuotaPolicy::EvictLru => {
+                self.bucket_index_for_hash(hash)
+            }
+            _ => random() as usize % self.buckets.len(),
+        };
+        let spared = written.map(|(_, key)| key);
+        while quota.is_exceeded(self.len()) {
+            if !self.evict_one(quota, start, spared)
+                && (spared.is_none() || !self.evict_one(quota, start, None))
+            {
+                break;
+            }
+        }
+    }
+
+    /// Evicts an entry other than `spared`, looking for one from bucket
+    /// `start` on.
+    ///
+    /// # Returns
+    ///
+    /// `true` if an entry was evicted.
+    fn evict_one(&self, quota: &Quota<K>, start: usize, spared: Option<&K>) -> bool {
+        for offset in 0..self.buckets.len() {
+            let index = (start + offset) % self.buckets.len();
+            let mut guard = self.buckets[index].write(self.lock_policy.write);
+            let victim = match quota.policy() {
+                QuotaPolicy::EvictLru => quota.least_recent(index, spared),
+                _ => {
+                    let mut keys = guard.live_keys();
+                    keys.retain(|key| Some(key) != spared);
+                    match keys.len() {
+                        0 => None,
+                        len => Some(keys.swap_remove(random() as usize % len)),
+                    }
+                }
+            };
+            if let Some(victim) = victim {
+                let hash = self.hash(&victim);
+                return self.remove_locked(&mut guard, hash, |key| *key == victim, true);
+            }
+        }
+        false
+    }
+
+    /// Returns the usage of the limits of the `Map` and what enforcing them
+    /// did so far, or `None` if it has no limits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::map::{MapBuilder, QuotaPolicy};
+    /// use palladiumdb::Map;
+    ///
+    /// let map: Map<u32, u32> = MapBuilder::new()
+    ///     .max_entries(2)
+    ///     .quota_policy(QuotaPolicy::EvictLru)
+    ///     .build();
+    /// for i in 0..3 {
+    ///     map.put(&i, i);
+    /// }
+    ///
+    /// let stats = map.quota_stats().unwrap();
+    /// assert_eq!((stats.entries, stats.evicted), (2, 1));
+    /// assert_eq!(Map::<u32, u32>::new().quota_stats(), None);
+    /// ```
+    pub fn quota_stats(&self) -> Option<QuotaStats> {
+        self.quota.as_ref().map(|quota| quota.stats(self.len()))
+    }
+
+    /// Returns a [`RawEntry`] for accessing entries by a precomputed hash.
+    ///
+    /// Callers that already hashed a key, for instance to route a request,
+    /// can reuse the hash instead of having the `Map` hash the key again.
+    /// Since keys are matched with a closure, entries can also be looked up
+    /// through a different type than `K` that hashes the same way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map: Map<Vec<u8>, i32> = Map::new();
+    /// map.put(&b"key".to_vec(), 1);
+    ///
+    /// // look the entry up by a byte slice, without allocating a `Vec<u8>`
+    /// let raw = map.raw_entry();
+    /// let key: &[u8] = b"key";
+    /// let hash = raw.hash(key);
+    ///
+    /// assert_eq!(raw.get(hash, |k| k.as_slice() == key), Some(1));
+    /// raw.unmap(hash, |k| k.as_slice() == key);
+    /// assert_eq!(map.get(&b"key".to_vec()), None);
+    /// ```
+    pub fn raw_entry(&self) -> RawEntry<'_, K, V, H> {
+        RawEntry::new(self)
+    }
+
+    /// Returns an iterator over the entries of the `Map`, in arbitrary order.
+    ///
+    /// The iterator does not hold any lock between calls to `next`, so the
+    /// `Map` may be freely modified while iterating, see [`Iter`] for what
+    /// is guaranteed to be observed in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    /// map.put(&2, 'b');
+    /// map.unmap(&1);
+    ///
+    /// let entries: Vec<_> = map.iter().collect();
+    /// assert_eq!(entries, vec![(2, 'b')]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V, H> {
+        Iter::new(self)
+    }
+
+    /// Returns an estimate of the memory used by the `Map`.
+    ///
+    /// Memory owned by the values on the heap is not measured, see
+    /// [`Map::deep_memory_usage`] for that. Buckets are read-locked one at a
+    /// time, so the estimate is not an atomic snapshot under concurrent
+    /// writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// let empty = map.memory_usage();
+    /// map.put(&1, 'a');
+    ///
+    /// assert!(map.memory_usage().entries > empty.entries);
+    /// assert_eq!(map.memory_usage().values, None);
+    /// ```
+    pub fn memory_usage(&self) -> MemoryStats {
+        self.measure_memory(|_| 0).0
+    }
+
+    /// Returns an estimate of the memory used by the `Map`, including the
+    /// heap memory owned by its values as reported by [`MeasureSize`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, String::with_capacity(1024));
+    ///
+    /// assert!(map.deep_memory_usage().values >= Some(1024));
+    /// ```
+    pub fn deep_memory_usage(&self) -> MemoryStats
+    where
+        V: MeasureSize,
+    {
+        let (stats, values) = self.measure_memory(V::heap_size);
+        MemoryStats {
+            values: Some(values),
+            ..stats
+        }
+    }
+
+    /// Measures the memory used by the `Map`, returning the sum of `measure`
+    /// over all retained values alongside.
+    fn measure_memory<F>(&self, measure: F) -> (MemoryStats, usize)
+    where
+        F: Fn(&V) -> usize,
+    {
+        let mut stats = MemoryStats {
+            buckets: self.buckets.capacity() * mem::size_of::<Bucket<K, V>>()
+                + self.buckets.iter().map(Bucket::filter_bytes).sum::<usize>(),
+            ..MemoryStats::default()
+        };
+        let mut values = 0;
+        for bucket in &self.buckets {
+            let (bucket_entries, bucket_values) =
+                bucket.read(self.lock_policy.read).memory_usage(&measure);
+            stats.entries += bucket_entries;
+            values += bucket_values;
+        }
+        (stats, values)
+    }
+
+    /// Releases memory held by buckets beyond what their entries need, for
+    /// instance after a large number of entries were unmapped.
+    ///
+    /// This is maintenance work: buckets are visited one at a time and the
+    /// operation backs off from any bucket that user operations are waiting
+    /// on, so it can be run alongside regular traffic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// for i in 0..100 {
+    ///     map.put(&i, i);
+    /// }
+    /// for i in 0..100 {
+    ///     map.unmap(&i);
+    /// }
+    ///
+    /// map.shrink_to_fit();
+    /// ```
+    pub fn shrink_to_fit(&self) {
+        for bucket in &self.buckets {
+            bucket.maintain().shrink_to_fit();
+        }
+    }
+
+    /// Reclaims the memory held by removed entries and by versions of live
+    /// entries beyond [`GcPolicy::retain_versions`].
+    ///
+    /// Buckets are collected one at a time at maintenance priority, so user
+    /// operations queued on a bucket are served first. A
+    /// [`GarbageCollector`] can run this periodically in the background.
+    ///
+    /// # Returns
+    ///
+    /// What this pass reclaimed. Totals over all passes are available through
+    /// [`Map::gc_stats`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&1, 'a');
+    /// map.put(&2, 'b');
+    /// map.unmap(&2);
+    ///
+    /// let stats = map.collect_garbage();
+    /// assert_eq!(stats.entries_reclaimed, 1);
+    /// assert_eq!(map.gc_stats().passes, 1);
+    /// ```
+    pub fn collect_garbage(&self) -> GcStats {
+        let mut stats = GcStats {
+            passes: 1,
+            ..GcStats::default()
+        };
+        for bucket in &self.buckets {
+            let mut guard = bucket.maintain();
+            let (entries, versions) = guard.collect_garbage(
+                self.gc_policy.retain_versions,
+                !bucket.is_pinned(),
+                self.clock.oldest_held(),
+            );
+            stats.entries_reclaimed += entries as u64;
+            stats.versions_reclaimed += versions as u64;
+        }
+        self.gc_counters.record(&stats);
+        stats
+    }
+
+    /// Returns the totals reclaimed by every garbage collection pass run on
+    /// the `Map` so far.
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_counters.load()
+    }
+
+    /// Locks the buckets of all of `keys` and calls `f` with a [`LockedKeys`]
+    /// handle through which those keys can be read and written atomically
+    /// with respect to every other operation on the `Map`.
+    ///
+    /// Buckets are always locked in the same canonical order, so concurrent
+    /// calls with overlapping keys cannot deadlock. The locks are released
+    /// once `f` returns, after which entries are evicted if the `Map` went
+    /// over its limits. Puts rejected by its [`QuotaPolicy`] are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Accessing a key through the handle that is not one of `keys` panics.
+    /// Calling back into this `Map` from `f` for any of the locked keys
+    /// deadlocks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&"alice", 100);
+    /// map.put(&"bob", 20);
+    ///
+    /// // transfer 30 from alice to bob
+    /// map.with_keys_locked(&["alice", "bob"], |locked| {
+    ///     let alice = locked.get(&"alice").unwrap();
+    ///     let bob = locked.get(&"bob").unwrap();
+    ///     locked.put(&"alice", alice - 30);
+    ///     locked.put(&"bob", bob + 30);
+    /// });
+    ///
+    /// assert_eq!(map.get(&"alice"), Some(70));
+    /// assert_eq!(map.get(&"bob"), Some(50));
+    /// ```
+    pub fn with_keys_locked<F, R>(&self, keys: &[K], f: F) -> R
+    where
+        F: FnOnce(&mut LockedKeys<'_, K, V, H>) -> R,
+    {
+        let mut locked = self.lock_keys(keys);
+        let result = f(&mut locked);
+        self.unlock_keys(locked);
+        result
+    }
+
+    /// Locks the buckets of `keys` as [`Map::with_keys_locked`] does, until
+    /// the handle is given back to [`Map::unlock_keys`], so that the keys of
+    /// several maps can be held locked at once. Maps locked together must
+    /// be locked in the same order by every caller.
+    pub(crate) fn lock_keys<'a>(&'a self, keys: &'a [K]) -> LockedKeys<'a, K, V, H> {
+        let indices = keys
+            .iter()
+            .map(|key| self.bucket_index_for_hash(self.hash(key)))
+            .collect();
+        let guards = lock_buckets(&self.buckets, indices, self.lock_policy.write);
+        LockedKeys::new(self, keys, guards)
+    }
+
+    /// Releases the buckets locked by [`Map::lock_keys`], then evicts
+    /// entries if the `Map` went over its limits.
+    pub(crate) fn unlock_keys(&self, locked: LockedKeys<'_, K, V, H>) {
+        drop(locked);
+        self.enforce_quota(None);
+    }
+
+    /// Copies the current entries of the `Map` into an immutable
+    /// [`FrozenMap`], optimized for lock-free reads.
+    ///
+    /// Every bucket is read-locked, in ascending index order, until all of
+    /// them are copied, so the copy is a consistent snapshot: it reflects
+    /// either all or none of the writes of every [`Map::with_keys_locked`]
+    /// call. Writers are held off for the duration of the copy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let map = Map::new();
+    /// map.put(&"alice", 100);
+    ///
+    /// let frozen = map.freeze();
+    /// map.put(&"alice", 70);
+    ///
+    /// assert_eq!(frozen.get(&"alice"), Some(&100));
+    /// assert_eq!(frozen.len(), 1);
+    /// ```
+    pub fn freeze(&self) -> FrozenMap<K, V, H>
+    where
+        H: Clone,
+    {
+        let guards: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.read(self.lock_policy.read))
+            .collect();
+        let entries = guards
+            .iter()
+            .flat_map(|guard| guard.live_entries())
+            .map(|(hash, key, value)| (hash, key.clone(), value.clone()))
+            .collect();
+        drop(guards);
+        FrozenMap::new(self.hash_builder.clone(), entries)
     }
 }
 
@@ -192,7 +1011,7 @@ mod tests {
 
     use std::sync::Arc;
 
-    use super::Map;
+    use super::{Map, MapBuilder};
 
     #[test]
     fn test_map_consistency() {
@@ -249,4 +1068,173 @@ mod tests {
         get_thread_1.join().unwrap();
         get_thread_2.join().unwrap();
     }
+
+    #[test]
+    fn test_with_keys_locked_is_atomic() {
+        let map = Arc::new(Map::with_bucket_count(4));
+        for key in 0..8 {
+            map.put(&key, 100);
+        }
+
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let m = Arc::clone(&map);
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let from = (worker + i) % 8;
+                        let to = (worker * 3 + i * 5 + 1) % 8;
+                        m.with_keys_locked(&[from, to], |locked| {
+                            let amount = locked.get(&from).unwrap();
+                            locked.put(&from, 0);
+                            let balance = locked.get(&to).unwrap();
+                            locked.put(&to, balance + amount);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let total: i32 = (0..8).map(|key| map.get(&key).unwrap()).sum();
+        assert_eq!(total, 800);
+    }
+
+    #[test]
+    fn test_unmap_keeps_history_until_collected() {
+        let map = Map::new();
+        map.put(&2, 'x');
+        map.put(&2, 'y');
+        map.put(&2, 'z');
+
+        map.put(&1, 'a');
+        map.put(&1, 'b');
+        map.put(&1, 'c');
+        let before_unmap = map.current_version();
+        map.unmap(&1);
+
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.get_as_of(&1, before_unmap), Some('c'));
+
+        let stats = map.collect_garbage();
+        assert_eq!(stats.entries_reclaimed, 1);
+        assert_eq!(stats.versions_reclaimed, 1);
+        assert_eq!(map.get_as_of(&1, before_unmap), None);
+        assert_eq!(map.get(&2), Some('z'));
+    }
+
+    #[test]
+    fn test_versioned_reads_skip_the_bucket_lock() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        // a single bucket, so that the locked key's bucket holds both keys
+        let map = Arc::new(Map::with_bucket_count(1));
+        map.put(&1, 'a');
+        map.put(&2, 'x');
+        let before = map.current_version();
+        map.put(&2, 'y');
+
+        map.with_keys_locked(&[1], |locked| {
+            locked.put(&1, 'b');
+
+            let (sender, receiver) = mpsc::channel();
+            let m = Arc::clone(&map);
+            let reader = std::thread::spawn(move || {
+                let latest = m.get_versioned(&1).map(|(value, _)| value);
+                sender.send((latest, m.get_as_of(&2, before))).unwrap();
+            });
+            // the write in progress is not seen until it completes
+            let read = receiver.recv_timeout(Duration::from_secs(10));
+            assert_eq!(read, Ok((Some('a'), Some('x'))));
+            reader.join().unwrap();
+        });
+
+        assert_eq!(map.get_versioned(&1).map(|(value, _)| value), Some('b'));
+    }
+
+    #[test]
+    fn test_iter_survives_concurrent_unmap() {
+        // a single bucket, so that every entry shares the iterator's bucket
+        let map = Map::with_bucket_count(1);
+        for key in 0..10 {
+            map.put(&key, key);
+        }
+
+        let mut iter = map.iter();
+        let mut seen: Vec<_> = iter.by_ref().take(3).map(|(key, _)| key).collect();
+
+        // remove already yielded entries, then write to trigger compaction
+        for &key in &seen {
+            map.unmap(&key);
+        }
+        map.put(&42, 42);
+
+        seen.extend(iter.map(|(key, _)| key));
+        for key in 0..10 {
+            assert!(seen.contains(&key));
+        }
+
+        // no longer pinned, so the next write compacts the dead slots
+        map.put(&43, 43);
+        assert_eq!(map.collect_garbage().entries_reclaimed, 0);
+    }
+
+    #[test]
+    fn test_bucket_filters_never_hide_present_keys() {
+        let map = MapBuilder::new()
+            .bucket_count(4)
+            .bucket_filter_bits(256)
+            .build();
+        for key in 0..200 {
+            map.put(&key, key * 2);
+        }
+        for key in (0..200).step_by(2) {
+            map.unmap(&key);
+        }
+        // compacts the dead slots, rebuilding the filters
+        map.put(&1000, 0);
+
+        for key in 0..200 {
+            let expected = if key % 2 == 0 { None } else { Some(key * 2) };
+            assert_eq!(map.get(&key), expected);
+        }
+    }
+
+    #[test]
+    fn test_freeze_is_consistent_under_transfers() {
+        let map = Arc::new(Map::with_bucket_count(4));
+        for key in 0..8 {
+            map.put(&key, 100);
+        }
+
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let m = Arc::clone(&map);
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let from = (worker + i) % 8;
+                        let to = (worker * 3 + i * 5 + 1) % 8;
+                        m.with_keys_locked(&[from, to], |locked| {
+                            let amount = locked.get(&from).unwrap();
+                            locked.put(&from, 0);
+                            let balance = locked.get(&to).unwrap();
+                            locked.put(&to, balance + amount);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..100 {
+            let frozen = map.freeze();
+            assert_eq!(frozen.len(), 8);
+            assert_eq!(frozen.iter().map(|(_, value)| value).sum::<i32>(), 800);
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
 }
@@ -0,0 +1,245 @@
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::collections::cache::recency::Recency;
+
+/// What a [`Map`] with size limits does with a write taking it over them,
+/// see [`MapBuilder::max_entries`] and [`MapBuilder::max_bytes`].
+///
+/// [`Map`]: super::Map
+/// [`MapBuilder::max_entries`]: super::MapBuilder::max_entries
+/// [`MapBuilder::max_bytes`]: super::MapBuilder::max_bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// Refuses the write, see [`Map::try_put`](super::Map::try_put).
+    #[default]
+    Reject,
+    /// Accepts the write, then evicts the least recently read or written
+    /// entries until the map is within its limits again. Recency is tracked
+    /// per bucket and the eviction starts from the bucket written to, so the
+    /// map-wide recency order is only followed approximately.
+    EvictLru,
+    /// Accepts the write, then evicts entries picked at random until the map
+    /// is within its limits again. Unlike [`QuotaPolicy::EvictLru`], reads
+    /// cost nothing extra.
+    EvictRandom,
+}
+
+/// Usage of a [`Map`] with size limits, along with what enforcing them did
+/// so far.
+///
+/// [`Map`]: super::Map
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaStats {
+    /// Number of entries in the map.
+    pub entries: usize,
+    /// Estimated bytes of the entries of the map, as counted against
+    /// [`MapBuilder::max_bytes`](super::MapBuilder::max_bytes).
+    pub bytes: usize,
+    /// Number of writes refused by [`QuotaPolicy::Reject`].
+    pub rejected: u64,
+    /// Number of entries evicted by the other policies.
+    pub evicted: u64,
+}
+
+/// Error of a write refused as it would take a [`Map`] over its limits.
+///
+/// [`Map`]: super::Map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded;
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("map quota exceeded")
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Limits of a [`Map`](super::Map), the estimated bytes of its entries and
+/// the counters of [`QuotaStats`].
+pub(crate) struct Quota<K> {
+    max_entries: usize,
+    max_bytes: usize,
+    policy: QuotaPolicy,
+    bytes: AtomicUsize,
+    rejected: AtomicU64,
+    evicted: AtomicU64,
+    // recency order of the keys of every bucket, only kept for
+    // `QuotaPolicy::EvictLru`
+    recency: Vec<Mutex<Recency<K>>>,
+}
+
+impl<K> Quota<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Returns the quota of a map of `bucket_count` buckets, or `None` if
+    /// neither limit is set.
+    pub(crate) fn new(
+        max_entries: Option<usize>,
+        max_bytes: Option<usize>,
+        policy: QuotaPolicy,
+        bucket_count: usize,
+    ) -> Option<Self> {
+        if max_entries.is_none() && max_bytes.is_none() {
+            return None;
+        }
+        let recency = match policy {
+            QuotaPolicy::EvictLru => (0..bucket_count)
+                .map(|_| Mutex::new(Recency::new()))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(Quota {
+            max_entries: max_entries.unwrap_or(usize::MAX),
+            max_bytes: max_bytes.unwrap_or(usize::MAX),
+            policy,
+            bytes: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            recency,
+        })
+    }
+
+    pub(crate) fn policy(&self) -> QuotaPolicy {
+        self.policy
+    }
+
+    /// Returns `true` if a map of `len` entries is over its limits.
+    pub(crate) fn is_exceeded(&self, len: usize) -> bool {
+        len > self.max_entries || self.bytes.load(Ordering::SeqCst) > self.max_bytes
+    }
+
+    /// Checks whether a put into a map of `len` entries, replacing an entry
+    /// of `old` bytes if any by one of `new` bytes, keeps it within its
+    /// limits, counting it as rejected otherwise. Puts not making the map
+    /// any larger are always accepted.
+    pub(crate) fn admit(&self, len: usize, old: Option<usize>, new: usize) -> bool {
+        let bytes = self.bytes.load(Ordering::SeqCst);
+        let over_entries = old.is_none() && len >= self.max_entries;
+        let over_bytes = new > old.unwrap_or(0) && bytes + new - old.unwrap_or(0) > self.max_bytes;
+        if over_entries || over_bytes {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Accounts for `key` being put into bucket `index` as an entry of `new`
+    /// bytes, replacing one of `old` bytes.
+    pub(crate) fn put(&self, index: usize, key: &K, old: usize, new: usize) {
+        self.bytes.fetch_add(new, Ordering::SeqCst);
+        self.bytes.fetch_sub(old, Ordering::SeqCst);
+        if let Some(recency) = self.recency.get(index) {
+            recency.lock().unwrap().insert(key.clone(), new);
+        }
+    }
+
+    /// Accounts for the entry of `key`, of `weight` bytes, being removed
+    /// from bucket `index`, counting it as evicted if `evicted`.
+    pub(crate) fn removed(&self, index: usize, key: &K, weight: usize, evicted: bool) {
+        self.bytes.fetch_sub(weight, Ordering::SeqCst);
+        if let Some(recency) = self.recency.get(index) {
+            recency.lock().unwrap().remove(key);
+        }
+        if evicted {
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks `key`, read from bucket `index`, as the most recently used.
+    pub(crate) fn touch(&self, index: usize, key: &K) {
+        if let Some(recency) = self.recency.get(index) {
+            recency.lock().unwrap().touch(key);
+        }
+    }
+
+    /// Returns the least recently used key of bucket `index`, unless it is
+    /// `spared`.
+    pub(crate) fn least_recent(&self, index: usize, spared: Option<&K>) -> Option<K> {
+        let recency = self.recency[index].lock().unwrap();
+        recency
+            .peek_lru()
+            .filter(|key| Some(*key) != spared)
+            .cloned()
+    }
+
+    pub(crate) fn stats(&self, entries: usize) -> QuotaStats {
+        QuotaStats {
+            entries,
+            bytes: self.bytes.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Charges an entry by the inline size of its key and value.
+pub(crate) fn weigh_inline<K, V>(_: &K, _: &V) -> usize {
+    mem::size_of::<K>() + mem::size_of::<V>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaExceeded, QuotaPolicy};
+    use crate::collections::map::{Map, MapBuilder};
+
+    #[test]
+    fn test_limits_are_enforced_by_every_policy() {
+        let map: Map<u64, String> = MapBuilder::new()
+            .max_entries(3)
+            .max_bytes(1000)
+            .build_measured();
+        for key in 0..3 {
+            map.try_put(&key, "a".to_string()).unwrap();
+        }
+        assert_eq!(map.try_put(&3, "a".to_string()), Err(QuotaExceeded));
+        // replacing an entry by one of the same size is always accepted
+        map.try_put(&0, "b".to_string()).unwrap();
+        assert_eq!(map.try_put(&1, "a".repeat(1000)), Err(QuotaExceeded));
+        map.put(&4, "a".to_string());
+        assert_eq!(map.get(&4), None);
+        map.remove(&2);
+        map.put(&4, "a".to_string());
+        let stats = map.quota_stats().unwrap();
+        assert_eq!((stats.entries, stats.rejected, stats.evicted), (3, 3, 0));
+        assert!(stats.bytes >= 3 && stats.bytes < 1000);
+
+        let lru: Map<u64, u64> = MapBuilder::new()
+            .bucket_count(1)
+            .max_entries(3)
+            .quota_policy(QuotaPolicy::EvictLru)
+            .build();
+        for key in 0..3 {
+            lru.put(&key, key);
+        }
+        lru.get(&0);
+        lru.put(&3, 3);
+        assert_eq!(lru.get(&1), None);
+        lru.get(&0);
+        lru.with_keys_locked(&[4, 5], |locked| {
+            locked.put(&4, 4);
+            locked.put(&5, 5);
+        });
+        assert_eq!(lru.len(), 3);
+        assert_eq!(lru.get(&0), Some(0));
+        assert_eq!(lru.quota_stats().unwrap().evicted, 3);
+
+        let random: Map<u64, u64> = MapBuilder::new()
+            .max_entries(100)
+            .quota_policy(QuotaPolicy::EvictRandom)
+            .build();
+        for key in 0..1000 {
+            random.put(&key, key);
+            assert_eq!(random.get(&key), Some(key));
+        }
+        assert_eq!(random.len(), 100);
+        assert_eq!(random.iter().count(), 100);
+        assert_eq!(random.quota_stats().unwrap().evicted, 900);
+        assert_eq!(MapBuilder::new().build::<u64, u64>().quota_stats(), None);
+    }
+}
@@ -0,0 +1,86 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::{Map, Version};
+
+/// Access to the entries of a [`Map`] by precomputed hash, see
+/// [`Map::raw_entry`].
+///
+/// Every hash passed to a `RawEntry` must be the hash of the entry's key as
+/// computed by the map's own hasher, which [`RawEntry::hash`] provides.
+/// Passing any other hash makes lookups miss and puts insert the entry into
+/// the wrong bucket, where it can only be found with the same wrong hash.
+///
+/// Lookups match keys with a caller supplied closure instead of [`Eq`], so
+/// a key can be looked up by any borrowed form that hashes the same way,
+/// for example a `&[u8]` against `Vec<u8>` keys.
+pub struct RawEntry<'a, K, V, H> {
+    map: &'a Map<K, V, H>,
+}
+
+impl<'a, K, V, H> RawEntry<'a, K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    pub(super) fn new(map: &'a Map<K, V, H>) -> Self {
+        RawEntry { map }
+    }
+
+    /// Hashes `key` with the map's hasher.
+    pub fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.map.hash_builder.hash_one(key)
+    }
+
+    /// Returns the value of the entry with the given `hash` whose key
+    /// satisfies `is_match`.
+    pub fn get<F>(&self, hash: u64, is_match: F) -> Option<V>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.get_versioned(hash, is_match).map(|(value, _)| value)
+    }
+
+    /// Returns the value and [`Version`] of the entry with the given `hash`
+    /// whose key satisfies `is_match`.
+    pub fn get_versioned<F>(&self, hash: u64, is_match: F) -> Option<(V, Version)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let bucket = self.map.bucket_for_hash(hash);
+        if !bucket.might_contain(hash) {
+            return None;
+        }
+        bucket
+            .read(self.map.lock_policy.read)
+            .latest_by(hash, is_match)
+            .map(|(value, version)| (value.clone(), version))
+    }
+
+    /// Establishes a mapping from `key`, whose hash is `hash`, to `value`,
+    /// as [`Map::put`] does.
+    pub fn put(&self, hash: u64, key: K, value: V) {
+        debug_assert_eq!(hash, self.hash(&key), "hash does not match the key");
+        let mut guard = self
+            .map
+            .bucket_for_hash(hash)
+            .write(self.map.lock_policy.write);
+        if self.map.put_locked(&mut guard, hash, &key, value).is_ok() {
+            drop(guard);
+            self.map.enforce_quota(Some((hash, &key)));
+        }
+    }
+
+    /// Erases the entry with the given `hash` whose key satisfies `is_match`,
+    /// if present.
+    pub fn unmap<F>(&self, hash: u64, is_match: F)
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let mut guard = self
+            .map
+            .bucket_for_hash(hash)
+            .write(self.map.lock_policy.write);
+        self.map.unmap_locked(&mut guard, hash, is_match);
+    }
+}
@@ -0,0 +1,602 @@
+use std::any;
+use std::cell::Cell;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::Path;
+
+use super::bucket::Bucket;
+use super::{Map, MapBuilder, Version};
+use crate::checksum::{crc32_update, crc64_update};
+use crate::codec::{decode_len, invalid_data, take, Decode, Encode};
+use crate::files;
+use crate::pdb::{self, PdbReader, PdbWriter, SECTION_SIZE};
+
+/// Kind of the PDB files holding a map, and version of their layout.
+pub(super) const KIND: &str = "map";
+pub(super) const KIND_VERSION: u32 = 1;
+
+/// Name of the sections holding entries, and of the one indexing them by
+/// the hash of their encoded key, to their position among the payloads of
+/// the entries sections.
+pub(super) const ENTRIES_SECTION: &str = "entries";
+pub(super) const INDEX_SECTION: &str = "index";
+
+/// Magic bytes of the snapshots written before PDB files, still loaded.
+const LEGACY_MAGIC: &[u8; 8] = b"PLDBSNAP";
+const LEGACY_VERSION: u32 = 1;
+
+impl<K, V, H> Map<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Saves the entries of the `Map` to the file at `path`, replacing it.
+    ///
+    /// The snapshot is a [PDB file](crate::pdb) of kind `map`, whose
+    /// metadata records the bucket count and the hasher, key and value types
+    /// of the map.
+    ///
+    /// Buckets are copied one at a time, each under its read lock, so writers
+    /// are only held off one bucket at a time. The snapshot is written next
+    /// to `path` first and renamed over it once synced, so a crash while
+    /// saving leaves the previous snapshot intact.
+    ///
+    /// Keys and values are written with [`Encode`]. With the `serde`
+    /// feature, serde types can be stored wrapped in `codec::Bincode`.
+    /// Entries are encrypted if the map was built with
+    /// [`MapBuilder::encryption`].
+    ///
+    /// Saving takes memory for about a section of entries whatever the size
+    /// of the map, as the snapshot is written without an index of the
+    /// entries by key.
+    ///
+    /// The snapshot becomes the base of the next [`Map::save_incremental`].
+    /// See [`Map::snapshot_writer`] to save a consistent cut of the map, or
+    /// to end it with an index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::Map;
+    ///
+    /// let path = std::env::temp_dir().join("palladiumdb-doc-save.snap");
+    /// let map = Map::new();
+    /// map.put(&"alice".to_string(), 100u64);
+    /// map.save_to(&path).unwrap();
+    ///
+    /// let loaded: Map<String, u64> = Map::load_from(&path).unwrap();
+    /// assert_eq!(loaded.get(&"alice".to_string()), Some(100));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    where
+        K: Encode,
+        V: Encode,
+    {
+        self.snapshot_writer().save_to(path).map(|_| ())
+    }
+
+    /// Returns a [`SnapshotWriter`] saving the entries of the `Map` with
+    /// other options than [`Map::save_to`].
+    pub fn snapshot_writer(&self) -> SnapshotWriter<'_, K, V, H> {
+        SnapshotWriter {
+            map: self,
+            consistent: false,
+            index: false,
+        }
+    }
+
+    /// Loads a `Map` from a snapshot written by [`Map::save_to`], with as
+    /// many buckets as the saved map had and every other option at its
+    /// default, see [`MapBuilder::load_from`].
+    pub fn load_from<P: AsRef<Path>>(path: P) -> io::Result<Self>
+    where
+        K: Decode,
+        V: Decode,
+        H: Default,
+    {
+        MapBuilder::new().hasher(H::default()).load_from(path)
+    }
+
+    /// Loads a `Map` configured by `builder` from the snapshot at `path`.
+    fn load_with(builder: MapBuilder<H>, path: &Path) -> io::Result<Self>
+    where
+        K: Decode,
+        V: Decode,
+    {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic == LEGACY_MAGIC {
+            let mut bytes = magic.to_vec();
+            input.read_to_end(&mut bytes)?;
+            return Self::load_legacy(builder, &bytes);
+        }
+        if &magic != pdb::MAGIC {
+            return Err(invalid_data("not a snapshot"));
+        }
+
+        Self::load_pdb(builder, PdbReader::after_magic(input)?).map(|(map, _)| map)
+    }
+
+    /// Loads a `Map` configured by `builder` from the PDB file read by
+    /// `reader`.
+    ///
+    /// # Returns
+    ///
+    /// The map along with the version the snapshot was saved at, unless it
+    /// was written by a release that did not record it.
+    pub(super) fn load_pdb<R: Read>(
+        builder: MapBuilder<H>,
+        mut reader: PdbReader<R>,
+    ) -> io::Result<(Self, Option<u64>)>
+    where
+        K: Decode,
+        V: Decode,
+    {
+        reader.expect_kind(KIND, KIND_VERSION)?;
+        let bucket_count: usize = reader.parse_metadata("bucket_count")?;
+        if bucket_count == 0 {
+            return Err(invalid_data("snapshot has no buckets"));
+        }
+        let map = builder.bucket_count(bucket_count).build();
+        if let Some(encryption) = &map.encryption {
+            reader.decrypt_with(encryption.clone());
+        }
+        while let Some(section) = reader.next_section()? {
+            // sections added by later releases are skipped
+            if section.name != ENTRIES_SECTION {
+                continue;
+            }
+            let mut input = &section.payload[..];
+            while !input.is_empty() {
+                let key = K::decode(&mut input)?;
+                map.put(&key, V::decode(&mut input)?);
+            }
+        }
+        let version = match reader.metadata("version") {
+            Some(_) => Some(reader.parse_metadata("version")?),
+            None => None,
+        };
+        Ok((map, version))
+    }
+
+    /// Loads a snapshot written before PDB files.
+    fn load_legacy(builder: MapBuilder<H>, bytes: &[u8]) -> io::Result<Self>
+    where
+        K: Decode,
+        V: Decode,
+    {
+        if bytes.len() < LEGACY_MAGIC.len() + 4 {
+            return Err(invalid_data("not a snapshot"));
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - 4);
+        if crc32_update(0, body).to_le_bytes() != crc {
+            return Err(invalid_data("snapshot checksum mismatch"));
+        }
+
+        let mut input = body;
+        take(&mut input, LEGACY_MAGIC.len())?;
+        if u32::decode(&mut input)? != LEGACY_VERSION {
+            return Err(invalid_data("unsupported snapshot version"));
+        }
+        let bucket_count = u64::decode(&mut input)? as usize;
+        if bucket_count == 0 {
+            return Err(invalid_data("snapshot has no buckets"));
+        }
+
+        let map = builder.bucket_count(bucket_count).build();
+        for _ in 0..bucket_count {
+            for _ in 0..decode_len(&mut input)? {
+                let key = K::decode(&mut input)?;
+                map.put(&key, V::decode(&mut input)?);
+            }
+        }
+        if !input.is_empty() {
+            return Err(invalid_data("trailing bytes after snapshot"));
+        }
+        Ok(map)
+    }
+}
+
+/// Writes a snapshot of a [`Map`] to disk, see [`Map::snapshot_writer`].
+///
+/// Buckets are walked one at a time, each under its read lock, and their
+/// entries streamed to the file a section at a time, so that neither writers
+/// nor memory are held up by more than a bucket at once. Only the index a
+/// [`SnapshotWriter::index`] snapshot ends with grows with the map.
+///
+/// By default every bucket is copied as it is when it is reached, so the
+/// snapshot may hold writes made while saving to some buckets and not to
+/// others. A [`SnapshotWriter::consistent`] snapshot instead holds every
+/// entry as it was when saving started, read through the versions of the
+/// entries.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::Map;
+///
+/// let path = std::env::temp_dir().join("palladiumdb-doc-snapshot-writer.snap");
+/// let map = Map::new();
+/// map.put(&"alice".to_string(), 100u64);
+/// let version = map
+///     .snapshot_writer()
+///     .consistent(true)
+///     .index(true)
+///     .save_to(&path)
+///     .unwrap();
+/// assert_eq!(version, map.current_version());
+///
+/// let loaded: Map<String, u64> = Map::load_from(&path).unwrap();
+/// assert_eq!(loaded.get(&"alice".to_string()), Some(100));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct SnapshotWriter<'a, K, V, H> {
+    map: &'a Map<K, V, H>,
+    consistent: bool,
+    index: bool,
+}
+
+impl<K, V, H> SnapshotWriter<'_, K, V, H>
+where
+    K: Hash + Eq + Clone + Encode,
+    V: Clone + Encode,
+    H: BuildHasher,
+{
+    /// Sets whether the snapshot holds every entry as of the version of the
+    /// map when saving starts, rather than as of when its bucket is copied.
+    ///
+    /// While saving, buckets are kept from compacting the slots of removed
+    /// entries, and entries keep the version the snapshot reads along with
+    /// every later one, past the few versions they otherwise keep and
+    /// [`GcPolicy::retain_versions`](super::GcPolicy::retain_versions).
+    /// Defaults to `false`.
+    pub fn consistent(mut self, consistent: bool) -> Self {
+        self.consistent = consistent;
+        self
+    }
+
+    /// Sets whether the snapshot ends with an index of its entries by key,
+    /// which [`Map::open_readonly_snapshot`] looks up in place rather than
+    /// building it when opening the snapshot.
+    ///
+    /// The index is kept in memory until every bucket is written, 16 bytes
+    /// an entry, so saving no longer takes bounded memory. Snapshots of maps
+    /// whose entries are compressed or encrypted are never indexed. Defaults
+    /// to `false`.
+    pub fn index(mut self, index: bool) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Saves the entries of the map to the file at `path`, replacing it,
+    /// like [`Map::save_to`].
+    ///
+    /// # Returns
+    ///
+    /// The version of the map the snapshot was taken at: entries written
+    /// after it may or may not be in the snapshot, unless it is consistent.
+    pub fn save_to<P: AsRef<Path>>(self, path: P) -> io::Result<Version> {
+        let map = self.map;
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+
+        // entries written from here on are newer than the snapshot, whether
+        // or not their bucket is copied before them
+        let hold = match self.consistent {
+            true => Some(Hold::new(map)),
+            false => None,
+        };
+        let version = match &hold {
+            Some(hold) => hold.version,
+            None => map.clock.now(),
+        };
+        let metadata = [
+            ("bucket_count", map.buckets.len().to_string()),
+            ("version", version.as_u64().to_string()),
+            ("hasher", any::type_name::<H>().to_string()),
+            ("key_type", any::type_name::<K>().to_string()),
+            ("value_type", any::type_name::<V>().to_string()),
+            ("palladiumdb_version", env!("CARGO_PKG_VERSION").to_string()),
+        ];
+        let out = BufWriter::new(File::create(&temp)?);
+        let mut out = PdbWriter::with_options(
+            out,
+            KIND,
+            KIND_VERSION,
+            &metadata,
+            map.compression,
+            map.encryption.clone(),
+        )?;
+        let indexed = self.index && !map.compression.is_enabled() && map.encryption.is_none();
+        let mut index = match indexed {
+            true => Some(Vec::new()),
+            false => None,
+        };
+        // bytes of the payloads of the entries sections written so far
+        let mut written = 0;
+        let mut buf = Vec::new();
+        for (at, bucket) in map.buckets.iter().enumerate() {
+            {
+                let guard = bucket.read(map.lock_policy.read);
+                let mut write = |key: &K, value: &V| {
+                    let start = buf.len();
+                    key.encode(&mut buf);
+                    if let Some(index) = &mut index {
+                        index.push((key_hash(&buf[start..]), written + start as u64));
+                    }
+                    value.encode(&mut buf);
+                };
+                match &hold {
+                    Some(_) => guard
+                        .entries_as_of(version)
+                        .for_each(|(_, key, value)| write(key, value)),
+                    None => guard
+                        .live_entries()
+                        .for_each(|(_, key, value)| write(key, value)),
+                }
+            }
+            if let Some(hold) = &hold {
+                hold.release_bucket(at);
+            }
+            if buf.len() >= SECTION_SIZE {
+                out.write_section(ENTRIES_SECTION, &buf)?;
+                written += buf.len() as u64;
+                buf.clear();
+            }
+        }
+        drop(hold);
+        if !buf.is_empty() {
+            out.write_section(ENTRIES_SECTION, &buf)?;
+        }
+        if let Some(mut index) = index {
+            index.sort_unstable();
+            buf.clear();
+            for (hash, position) in index {
+                buf.extend_from_slice(&hash.to_le_bytes());
+                buf.extend_from_slice(&position.to_le_bytes());
+            }
+            out.write_section(INDEX_SECTION, &buf)?;
+        }
+
+        out.finish()?.into_inner()?.sync_all()?;
+        files::rename_durably(&temp, path)?;
+        map.snapshot_taken(version);
+        Ok(version)
+    }
+}
+
+/// Version of a map held by a consistent snapshot, with the buckets not
+/// copied yet pinned so that removed entries stay in them. Released when
+/// dropped, whether or not saving succeeded.
+struct Hold<'a, K, V, H> {
+    map: &'a Map<K, V, H>,
+    version: Version,
+    /// Number of leading buckets copied and unpinned already.
+    released: Cell<usize>,
+}
+
+impl<'a, K, V, H> Hold<'a, K, V, H> {
+    fn new(map: &'a Map<K, V, H>) -> Self {
+        // dead slots compacted away before the pins are older than the held
+        // version, so entries removed by then are not missed
+        map.buckets.iter().for_each(Bucket::pin);
+        Hold {
+            map,
+            version: map.clock.hold(),
+            released: Cell::new(0),
+        }
+    }
+
+    /// Unpins the bucket at `at`, the next one to be released.
+    fn release_bucket(&self, at: usize) {
+        debug_assert_eq!(at, self.released.get());
+        self.map.buckets[at].unpin();
+        self.released.set(at + 1);
+    }
+}
+
+impl<K, V, H> Drop for Hold<'_, K, V, H> {
+    fn drop(&mut self) {
+        self.map.buckets[self.released.get()..]
+            .iter()
+            .for_each(Bucket::unpin);
+        self.map.clock.release(self.version);
+    }
+}
+
+/// Returns the hash an entry is indexed by in a snapshot, of its key as
+/// encoded, so that it does not depend on the hasher of the map.
+pub(super) fn key_hash(encoded: &[u8]) -> u64 {
+    crc64_update(0, encoded)
+}
+
+impl<H: BuildHasher> MapBuilder<H> {
+    /// Loads a [`Map`] from a snapshot written by [`Map::save_to`], with as
+    /// many buckets as the saved map had and the other options of the
+    /// builder. Snapshots written by earlier releases, before PDB files,
+    /// load as well.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the file is not a snapshot, is
+    /// corrupted, was saved with other key or value types, or by a newer
+    /// release with a layout this one does not know, and of kind
+    /// `InvalidInput` if it is encrypted but the builder has no
+    /// [`MapBuilder::encryption`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "encryption")]
+    /// # {
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::encryption::{Encryption, Key, KeyRing};
+    /// use palladiumdb::Map;
+    /// use std::sync::Arc;
+    ///
+    /// let path = std::env::temp_dir().join("palladiumdb-doc-encrypted.snap");
+    /// let encryption = Encryption::new(Arc::new(KeyRing::new(Key::new([7; 32])))).unwrap();
+    /// let map = MapBuilder::new().encryption(encryption.clone()).build();
+    /// map.put(&"alice".to_string(), 100u64);
+    /// map.save_to(&path).unwrap();
+    ///
+    /// assert!(Map::<String, u64>::load_from(&path).is_err());
+    /// let loaded: Map<String, u64> = MapBuilder::new().encryption(encryption).load_from(&path).unwrap();
+    /// assert_eq!(loaded.get(&"alice".to_string()), Some(100));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # }
+    /// ```
+    pub fn load_from<K, V, P>(self, path: P) -> io::Result<Map<K, V, H>>
+    where
+        K: Hash + Eq + Clone + Decode,
+        V: Clone + Decode,
+        P: AsRef<Path>,
+    {
+        Map::load_with(self, path.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::crc32_update;
+    use crate::codec::Encode;
+    use crate::collections::map::{GcPolicy, MapBuilder};
+    use crate::Map;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_snapshot_under_writes_loads_and_detects_corruption() {
+        let path =
+            std::env::temp_dir().join(format!("palladiumdb-snapshot-{}", std::process::id()));
+        let map = Arc::new(Map::with_bucket_count(64));
+        for key in 0..1000u64 {
+            map.put(&key, key.to_string());
+        }
+
+        let writer = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for key in 1000..2000u64 {
+                    map.put(&key, key.to_string());
+                }
+            })
+        };
+        map.save_to(&path).unwrap();
+        writer.join().unwrap();
+
+        let loaded: Map<u64, String> = Map::load_from(&path).unwrap();
+        assert!(loaded.len() >= 1000);
+        assert!((0..2000u64)
+            .filter_map(|key| loaded.get(&key).map(|value| (key, value)))
+            .all(|(key, value)| value == key.to_string()));
+
+        let mut bytes = fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let error = Map::<u64, String>::load_from(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // snapshots written before PDB files still load
+        let mut legacy = b"PLDBSNAP".to_vec();
+        1u32.encode(&mut legacy);
+        1u64.encode(&mut legacy);
+        vec![(7u64, "seven".to_string())].encode(&mut legacy);
+        let crc = crc32_update(0, &legacy);
+        legacy.extend_from_slice(&crc.to_le_bytes());
+        fs::write(&path, legacy).unwrap();
+        let loaded: Map<u64, String> = Map::load_from(&path).unwrap();
+        assert_eq!(loaded.get(&7), Some("seven".to_string()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_consistent_snapshot_is_a_cut_of_concurrent_writes() {
+        let path = std::env::temp_dir().join(format!(
+            "palladiumdb-consistent-snapshot-{}",
+            std::process::id()
+        ));
+        let gc_policy = GcPolicy {
+            retain_versions: 1,
+            ..GcPolicy::default()
+        };
+        let map = Arc::new(
+            MapBuilder::new()
+                .bucket_count(64)
+                .gc_policy(gc_policy)
+                .build(),
+        );
+        for key in 0..1000u64 {
+            map.put(&key, 0u64);
+        }
+
+        // rounds of writes over the keys in order, removing every tenth key
+        // on odd rounds, under garbage collection
+        let done = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..2)
+            .map(|writer| {
+                let (map, done) = (map.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut round = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        match writer {
+                            0 => {
+                                round += 1;
+                                for key in 0..1000 {
+                                    match key % 10 == 0 && round % 2 == 1 {
+                                        true => map.unmap(&key),
+                                        false => map.put(&key, round),
+                                    }
+                                }
+                            }
+                            _ => {
+                                map.collect_garbage();
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..5 {
+            map.snapshot_writer()
+                .consistent(true)
+                .index(false)
+                .save_to(&path)
+                .unwrap();
+            let loaded: Map<u64, u64> = Map::load_from(&path).unwrap();
+            let values: Vec<_> = (0..1000).map(|key| loaded.get(&key)).collect();
+            let rounds: Vec<_> = values.iter().flatten().copied().collect();
+            // keys before the cut are one round ahead of those after it
+            assert!(rounds.windows(2).all(|pair| pair[0] >= pair[1]));
+            assert!(rounds[0] - rounds[rounds.len() - 1] <= 1);
+            for (key, value) in values.iter().enumerate() {
+                match value {
+                    None => assert_eq!(key % 10, 0),
+                    Some(round) if key % 10 == 0 => assert_eq!(round % 2, 0),
+                    Some(_) => {}
+                }
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+
+        // nothing is held once the snapshots are done
+        assert_eq!(map.clock.oldest_held(), None);
+        assert!(map.buckets.iter().all(|bucket| !bucket.is_pinned()));
+        map.collect_garbage();
+        assert_eq!(map.collect_garbage().versions_reclaimed, 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Logical timestamp attached to every value written into a [`Map`].
+///
+/// Versions are handed out by the map's clock while the bucket's write
+/// lock is held, so the versions of a single key are strictly increasing
+/// in the order its writes were applied.
+///
+/// [`Map`]: super::Map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(pub(crate) u64);
+
+impl Version {
+    /// Returns the raw value of this version.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Source of [`Version`]s shared by every bucket of a map, keeping track of
+/// the versions held by consistent snapshots in progress.
+pub(crate) struct Clock {
+    latest: AtomicU64,
+    /// Oldest held version, `u64::MAX` if none is.
+    oldest_held: AtomicU64,
+    held: Mutex<Vec<Version>>,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Clock {
+            latest: AtomicU64::new(0),
+            oldest_held: AtomicU64::new(u64::MAX),
+            held: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the next version, greater than every version returned before.
+    pub(crate) fn tick(&self) -> Version {
+        Version(self.latest.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    /// Returns the latest version handed out so far.
+    pub(crate) fn now(&self) -> Version {
+        Version(self.latest.load(Ordering::SeqCst))
+    }
+
+    /// Holds the latest version, so that the values entries had at that
+    /// version are retained until a matching call to [`Clock::release`].
+    pub(crate) fn hold(&self) -> Version {
+        let mut held = self.held.lock().unwrap();
+        // every version is retained while the held one is picked, so that
+        // writes made meanwhile cannot trim it away
+        self.oldest_held.store(0, Ordering::SeqCst);
+        let version = self.now();
+        held.push(version);
+        self.oldest_held
+            .store(held.iter().min().unwrap().0, Ordering::SeqCst);
+        version
+    }
+
+    pub(crate) fn release(&self, version: Version) {
+        let mut held = self.held.lock().unwrap();
+        if let Some(at) = held.iter().position(|v| *v == version) {
+            held.swap_remove(at);
+        }
+        let oldest = held.iter().min().map_or(u64::MAX, |v| v.0);
+        self.oldest_held.store(oldest, Ordering::SeqCst);
+    }
+
+    /// Returns the oldest version held, if any.
+    pub(crate) fn oldest_held(&self) -> Option<Version> {
+        match self.oldest_held.load(Ordering::SeqCst) {
+            u64::MAX => None,
+            version => Some(Version(version)),
+        }
+    }
+}
+
+/// Committed versions of a single entry, oldest first.
+///
+/// Removing an entry commits a tombstone (a version without a value) rather
+/// than dropping the chain, so reads as of an earlier version still observe
+/// the value the entry had back then. Tombstoned chains and versions beyond
+/// those worth retaining are reclaimed by garbage collection.
+///
+/// At most [`VersionChain::MAX_VERSIONS`] versions are retained, older
+/// versions are dropped as new ones are pushed, unless they are still needed
+/// to read the entry as of a held version.
+///
+/// Clones share the versions, which are replaced rather than modified, so
+/// that a chain published for reads stays as it was committed.
+pub(crate) struct VersionChain<V> {
+    versions: Arc<[(Version, Option<Arc<V>>)]>,
+}
+
+impl<V> Clone for VersionChain<V> {
+    fn clone(&self) -> Self {
+        VersionChain {
+            versions: self.versions.clone(),
+        }
+    }
+}
+
+impl<V> VersionChain<V> {
+    pub(crate) const MAX_VERSIONS: usize = 4;
+
+    pub(crate) fn new(version: Version, value: V) -> Self {
+        VersionChain {
+            versions: Arc::new([(version, Some(Arc::new(value)))]),
+        }
+    }
+
+    fn push_version(&mut self, version: Version, value: Option<V>, held: Option<Version>) {
+        let excess = (self.versions.len() + 1).saturating_sub(Self::MAX_VERSIONS);
+        let excess = excess.min(self.unneeded(held));
+        let mut versions = self.versions[excess..].to_vec();
+        versions.push((version, value.map(Arc::new)));
+        self.versions = versions.into();
+    }
+
+    /// Returns the number of the oldest versions not needed to read the
+    /// entry as of `held`.
+    fn unneeded(&self, held: Option<Version>) -> usize {
+        match held {
+            Some(held) => self
+                .versions
+                .iter()
+                .filter(|(v, _)| *v <= held)
+                .count()
+                .saturating_sub(1),
+            None => self.versions.len(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, version: Version, value: V, held: Option<Version>) {
+        self.push_version(version, Some(value), held)
+    }
+
+    /// Commits a tombstone as the latest version.
+    pub(crate) fn push_tombstone(&mut self, version: Version, held: Option<Version>) {
+        self.push_version(version, None, held)
+    }
+
+    /// Returns the latest committed version, unless the entry is dead.
+    pub(crate) fn latest(&self) -> Option<(&V, Version)> {
+        let (version, value) = self.versions.last().unwrap();
+        value.as_deref().map(|value| (value, *version))
+    }
+
+    /// Returns the newest value that was committed at or before `version`.
+    pub(crate) fn as_of(&self, version: Version) -> Option<&V> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|(v, _)| *v <= version)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Returns `true` if the latest committed version is a tombstone.
+    pub(crate) fn is_dead(&self) -> bool {
+        self.versions.last().unwrap().1.is_none()
+    }
+
+    /// Returns the bytes allocated for the versions of this chain, along
+    /// with the values themselves but for their heap allocations.
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        let counts = 2 * mem::size_of::<usize>();
+        let versions = counts + self.versions.len() * mem::size_of::<(Version, Option<Arc<V>>)>();
+        versions + self.values().count() * (counts + mem::size_of::<V>())
+    }
+
+    /// Returns the values of every retained version, oldest first.
+    pub(crate) fn values(&self) -> impl Iterator<Item = &V> {
+        self.versions
+            .iter()
+            .filter_map(|(_, value)| value.as_deref())
+    }
+
+    /// Drops all but the newest `retain` versions, always keeping at least
+    /// the latest one and those needed to read the entry as of `held`.
+    ///
+    /// # Returns
+    ///
+    /// The number of versions dropped.
+    pub(crate) fn trim(&mut self, retain: usize, held: Option<Version>) -> usize {
+        let excess = self.versions.len().saturating_sub(retain.max(1));
+        let excess = excess.min(self.unneeded(held));
+        if excess > 0 {
+            self.versions = self.versions[excess..].into();
+        }
+        excess
+    }
+}
@@ -1 +1,31 @@
+pub mod art;
+pub mod bimap;
+pub mod bitset;
+pub mod bloom;
+pub mod btree;
+pub mod cache;
+pub mod counter;
+pub mod cow;
+pub mod deque;
+pub mod graph;
+pub mod list;
+pub mod log;
 pub mod map;
+pub mod multimap;
+pub mod ordered;
+pub mod persistent;
+pub mod pool;
+pub mod pqueue;
+pub mod queue;
+pub mod ring;
+pub mod set;
+pub mod sketch;
+pub mod sorted;
+pub mod stack;
+pub mod timeseries;
+pub mod trie;
+pub mod ttl;
+pub mod vector;
+pub mod weak;
+
+pub(crate) mod utils;
@@ -0,0 +1,295 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type MultiBucket<K, V> = PriorityRwLock<Vec<(K, Vec<V>)>>;
+
+/// Thread-Safe map implemented as hash table, associating every key with
+/// a list of values.
+///
+/// Values are appended to and removed from a key's list in place under the
+/// bucket's write lock, unlike a `Map<K, Vec<V>>` where every update has to
+/// clone and write back the whole list.
+pub struct MultiMap<K, V, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<MultiBucket<K, V>>,
+    lock_policy: LockPolicy,
+    len: AtomicUsize,
+}
+
+impl<K, V> Default for MultiMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> MultiMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `MultiMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::multimap::MultiMap;
+    /// let map: MultiMap<&str, i32> = MultiMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `MultiMap` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::multimap::MultiMap;
+    /// let map: MultiMap<&str, i32> = MultiMap::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<K, V, H> MultiMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `MultiMap` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Vec::new()));
+
+        MultiMap {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn get_bucket(&self, key: &K) -> &MultiBucket<K, V> {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        &self.buckets[hash % self.buckets.len()]
+    }
+
+    /// Appends `value` to the values associated with `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::multimap::MultiMap;
+    ///
+    /// let map = MultiMap::new();
+    /// map.put(&"fruits", "apple");
+    /// map.put(&"fruits", "pear");
+    ///
+    /// assert_eq!(map.get_all(&"fruits"), vec!["apple", "pear"]);
+    /// ```
+    pub fn put(&self, key: &K, value: V) {
+        let mut bucket = self.get_bucket(key).write(self.lock_policy.write);
+        match bucket.iter_mut().find(|(elem_key, _)| elem_key == key) {
+            Some((_, values)) => values.push(value),
+            None => bucket.push((key.clone(), vec![value])),
+        }
+        self.len.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns all values associated with `key`, in the order they were put.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::multimap::MultiMap;
+    ///
+    /// let map: MultiMap<i32, char> = MultiMap::new();
+    /// assert!(map.get_all(&1).is_empty());
+    /// ```
+    pub fn get_all(&self, key: &K) -> Vec<V> {
+        let bucket = self.get_bucket(key).read(self.lock_policy.read);
+        bucket
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, values)| values.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of values associated with `key`.
+    pub fn count(&self, key: &K) -> usize {
+        let bucket = self.get_bucket(key).read(self.lock_policy.read);
+        bucket
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map_or(0, |(_, values)| values.len())
+    }
+
+    /// Returns `true` if at least one value is associated with `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.count(key) > 0
+    }
+
+    /// Removes the first occurrence of `value` from the values associated
+    /// with `key`. The key is removed along with its last value.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was associated with `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::multimap::MultiMap;
+    ///
+    /// let map = MultiMap::new();
+    /// map.put(&1, 'a');
+    /// map.put(&1, 'b');
+    ///
+    /// assert!(map.remove_value(&1, &'a'));
+    /// assert!(!map.remove_value(&1, &'a'));
+    /// assert_eq!(map.get_all(&1), vec!['b']);
+    /// ```
+    pub fn remove_value(&self, key: &K, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let mut bucket = self.get_bucket(key).write(self.lock_policy.write);
+        let index = match bucket.iter().position(|(elem_key, _)| elem_key == key) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let values = &mut bucket[index].1;
+        let removed = match values.iter().position(|elem| elem == value) {
+            Some(position) => {
+                values.remove(position);
+                true
+            }
+            None => false,
+        };
+        if values.is_empty() {
+            bucket.swap_remove(index);
+        }
+        if removed {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    /// Removes `key` along with all of its values, returning the values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::multimap::MultiMap;
+    ///
+    /// let map = MultiMap::new();
+    /// map.put(&1, 'a');
+    /// map.put(&1, 'b');
+    ///
+    /// assert_eq!(map.remove_key(&1), vec!['a', 'b']);
+    /// assert!(!map.contains_key(&1));
+    /// ```
+    pub fn remove_key(&self, key: &K) -> Vec<V> {
+        let mut bucket = self.get_bucket(key).write(self.lock_policy.write);
+        match bucket.iter().position(|(elem_key, _)| elem_key == key) {
+            Some(index) => {
+                let (_, values) = bucket.swap_remove(index);
+                self.len.fetch_sub(values.len(), Ordering::SeqCst);
+                values
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the number of key value pairs in the `MultiMap`.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `MultiMap` contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_puts_and_removals_keep_len() {
+        let map = Arc::new(MultiMap::with_bucket_count(4));
+        let threads: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for key in 0..50u64 {
+                        for value in 0..4u64 {
+                            map.put(&key, thread * 10 + value);
+                        }
+                        // every thread removes two of its values of each key
+                        assert!(map.remove_value(&key, &(thread * 10)));
+                        assert!(map.remove_value(&key, &(thread * 10 + 3)));
+                        assert!(!map.remove_value(&key, &(thread * 10 + 3)));
+                    }
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+
+        assert_eq!(map.len(), 50 * 4 * 2);
+        for key in 0..50u64 {
+            let mut values = map.get_all(&key);
+            values.sort_unstable();
+            assert_eq!(values, vec![1, 2, 11, 12, 21, 22, 31, 32]);
+            assert_eq!(map.count(&key), 8);
+        }
+
+        let removers: Vec<_> = (0..2u64)
+            .map(|half| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    (0..50u64)
+                        .filter(|key| key % 2 == half)
+                        .map(|key| map.remove_key(&key).len())
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        let removed: usize = removers
+            .into_iter()
+            .map(|remover| remover.join().unwrap())
+            .sum();
+        assert_eq!(removed, 400);
+        assert!(map.is_empty() && !map.contains_key(&7));
+        assert!(map.remove_key(&7).is_empty());
+    }
+}
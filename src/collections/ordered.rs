@@ -0,0 +1,511 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Entries of a bucket, each with its node in the insertion order.
+type OrderedBucket<K, V> = PriorityRwLock<Vec<(K, V, Arc<Node<K>>)>>;
+
+/// A key's place in the insertion order, linked to the keys inserted right
+/// before and after it. The sentinels at both ends of the order have no key.
+struct Node<K> {
+    key: Option<K>,
+    links: Mutex<Links<K>>,
+}
+
+struct Links<K> {
+    prev: Weak<Node<K>>,
+    /// Kept once the node is unlinked, so that iterators standing on it
+    /// can move on.
+    next: Option<Arc<Node<K>>>,
+    linked: bool,
+}
+
+impl<K> Node<K> {
+    fn new(key: Option<K>) -> Arc<Self> {
+        Arc::new(Node {
+            key,
+            links: Mutex::new(Links {
+                prev: Weak::new(),
+                next: None,
+                linked: true,
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Links<K>> {
+        self.links.lock().unwrap()
+    }
+
+    /// Returns the node after this one, unless it is the last sentinel.
+    fn next(&self) -> Option<Arc<Node<K>>> {
+        self.lock().next.clone().filter(|next| next.key.is_some())
+    }
+}
+
+/// Insertion order of the keys across buckets, a doubly linked list
+/// threaded through the nodes of the entries.
+///
+/// Every node has a lock of its own. Nodes are only ever linked at the end
+/// and never move, so the order of two nodes never changes, and the locks
+/// of neighbouring nodes are always taken from the oldest to the newest:
+/// linking, unlinking and popping the oldest node are O(1), and only
+/// contend on the nodes they touch.
+struct Order<K> {
+    head: Arc<Node<K>>,
+    tail: Arc<Node<K>>,
+}
+
+impl<K> Order<K> {
+    fn new() -> Self {
+        let head = Node::new(None);
+        let tail = Node::new(None);
+        head.lock().next = Some(tail.clone());
+        tail.lock().prev = Arc::downgrade(&head);
+        Order { head, tail }
+    }
+
+    /// Links `node` after every other node.
+    fn push_back(&self, node: &Arc<Node<K>>) {
+        loop {
+            let last = match self.tail.lock().prev.upgrade() {
+                Some(last) => last,
+                // unlinked in between, look again
+                None => continue,
+            };
+            let mut last_links = last.lock();
+            let mut tail_links = self.tail.lock();
+            if !Weak::ptr_eq(&tail_links.prev, &Arc::downgrade(&last)) {
+                continue;
+            }
+            let mut links = node.lock();
+            links.prev = Arc::downgrade(&last);
+            links.next = Some(self.tail.clone());
+            last_links.next = Some(node.clone());
+            tail_links.prev = Arc::downgrade(node);
+            return;
+        }
+    }
+
+    /// Unlinks `node` from between `prev` and the node after it, whose
+    /// links are both held.
+    fn unlink_locked(prev: &Arc<Node<K>>, prev_links: &mut Links<K>, links: &mut Links<K>) {
+        let next = links.next.clone().unwrap();
+        next.lock().prev = Arc::downgrade(prev);
+        prev_links.next = Some(next);
+        links.linked = false;
+    }
+
+    /// Unlinks `node` from the order.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the node was unlinked already.
+    fn unlink(&self, node: &Arc<Node<K>>) -> bool {
+        loop {
+            let prev = {
+                let links = node.lock();
+                if !links.linked {
+                    return false;
+                }
+                links.prev.upgrade()
+            };
+            let prev = match prev {
+                Some(prev) => prev,
+                None => continue,
+            };
+            let mut prev_links = prev.lock();
+            let mut links = node.lock();
+            if !links.linked {
+                return false;
+            }
+            if !Weak::ptr_eq(&links.prev, &Arc::downgrade(&prev)) {
+                continue;
+            }
+            Self::unlink_locked(&prev, &mut prev_links, &mut links);
+            return true;
+        }
+    }
+
+    /// Unlinks the oldest node and returns it.
+    fn pop_front(&self) -> Option<Arc<Node<K>>> {
+        let mut head_links = self.head.lock();
+        let first = head_links.next.clone().unwrap();
+        first.key.as_ref()?;
+        let mut links = first.lock();
+        Self::unlink_locked(&self.head, &mut head_links, &mut links);
+        drop(links);
+        Some(first)
+    }
+}
+
+impl<K> Drop for Order<K> {
+    fn drop(&mut self) {
+        // unchained one node at a time, rather than by dropping nodes
+        // recursively from the head
+        let mut next = self.head.lock().next.take();
+        while let Some(node) = next {
+            next = node.lock().next.take();
+        }
+    }
+}
+
+/// Thread-Safe map implemented as hash table, remembering the order keys
+/// were inserted in.
+///
+/// Iteration yields entries from the oldest to the most recently inserted
+/// key, and [`OrderedMap::pop_oldest`] removes the oldest entry in constant
+/// time, enabling FIFO eviction and ordered replay. Updating the value of a
+/// key keeps its position.
+///
+/// Entries live in the buckets, behind the bucket locks, and are linked in
+/// insertion order through nodes with locks of their own, only ever taken
+/// after a bucket lock or with no bucket lock held. Writers contend on the
+/// order only around the nodes they link or unlink.
+pub struct OrderedMap<K, V, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<OrderedBucket<K, V>>,
+    order: Order<K>,
+    lock_policy: LockPolicy,
+    len: AtomicUsize,
+}
+
+impl<K, V> Default for OrderedMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> OrderedMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `OrderedMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    /// let map: OrderedMap<&str, i32> = OrderedMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `OrderedMap` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    /// let map: OrderedMap<&str, i32> = OrderedMap::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<K, V, H> OrderedMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `OrderedMap` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Vec::new()));
+
+        OrderedMap {
+            hash_builder,
+            buckets,
+            order: Order::new(),
+            lock_policy: LockPolicy::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn get_bucket(&self, key: &K) -> &OrderedBucket<K, V> {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        &self.buckets[hash % self.buckets.len()]
+    }
+
+    /// Maps `key` to `value`, returning the value previously mapped to it.
+    /// A new key is placed after every other key, an existing key keeps its
+    /// position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    ///
+    /// let map = OrderedMap::new();
+    /// assert_eq!(map.insert(&"a", 1), None);
+    /// assert_eq!(map.insert(&"a", 2), Some(1));
+    /// ```
+    pub fn insert(&self, key: &K, value: V) -> Option<V> {
+        let mut entries = self.get_bucket(key).write(self.lock_policy.write);
+        if let Some((_, existing, _)) = entries.iter_mut().find(|(elem_key, _, _)| elem_key == key)
+        {
+            return Some(std::mem::replace(existing, value));
+        }
+
+        let node = Node::new(Some(key.clone()));
+        self.order.push_back(&node);
+        entries.push((key.clone(), value, node));
+        self.len.fetch_add(1, Ordering::SeqCst);
+        None
+    }
+
+    /// Returns the value mapped to `key`.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.get_entry(key, None)
+    }
+
+    /// Returns the value of the entry of `key` if `node` is its node, or
+    /// whatever its node if `None`.
+    fn get_entry(&self, key: &K, node: Option<&Arc<Node<K>>>) -> Option<V> {
+        let entries = self.get_bucket(key).read(self.lock_policy.read);
+        entries
+            .iter()
+            .find(|(elem_key, _, elem_node)| {
+                elem_key == key && node.is_none_or(|node| Arc::ptr_eq(node, elem_node))
+            })
+            .map(|(_, value, _)| value.clone())
+    }
+
+    /// Returns `true` if `key` is mapped to a value.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes the entry of `key` if `node` is its node, or whatever its
+    /// node if `None`. The node is unlinked unless it was already.
+    fn remove_entry(&self, key: &K, node: Option<&Arc<Node<K>>>) -> Option<V> {
+        let mut entries = self.get_bucket(key).write(self.lock_policy.write);
+        let index = entries.iter().position(|(elem_key, _, elem_node)| {
+            elem_key == key && node.is_none_or(|node| Arc::ptr_eq(node, elem_node))
+        })?;
+        let (_, value, node) = entries.swap_remove(index);
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        self.order.unlink(&node);
+        Some(value)
+    }
+
+    /// Removes the mapping of `key`, returning its value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    ///
+    /// let map = OrderedMap::new();
+    /// map.insert(&"a", 1);
+    /// assert_eq!(map.remove(&"a"), Some(1));
+    /// assert_eq!(map.remove(&"a"), None);
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.remove_entry(key, None)
+    }
+
+    /// Returns the entry of the oldest key without removing it.
+    pub fn peek_oldest(&self) -> Option<(K, V)> {
+        loop {
+            let oldest = self.order.head.next()?;
+            let key = oldest.key.as_ref().unwrap();
+            // unless removed in between, then look again
+            if let Some(value) = self.get_entry(key, Some(&oldest)) {
+                return Some((key.clone(), value));
+            }
+        }
+    }
+
+    /// Removes the entry of the oldest key and returns it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    ///
+    /// let map = OrderedMap::new();
+    /// map.insert(&"a", 1);
+    /// map.insert(&"b", 2);
+    /// map.insert(&"a", 3);
+    ///
+    /// assert_eq!(map.pop_oldest(), Some(("a", 3)));
+    /// assert_eq!(map.pop_oldest(), Some(("b", 2)));
+    /// assert_eq!(map.pop_oldest(), None);
+    /// ```
+    pub fn pop_oldest(&self) -> Option<(K, V)> {
+        loop {
+            // unlinked before its bucket is locked, as bucket locks are
+            // taken before node locks
+            let oldest = self.order.pop_front()?;
+            let key = oldest.key.as_ref().unwrap();
+            // unless removed in between, then pop the next one
+            if let Some(value) = self.remove_entry(key, Some(&oldest)) {
+                return Some((key.clone(), value));
+            }
+        }
+    }
+
+    /// Returns an iterator over the entries, from the oldest to the most
+    /// recently inserted key.
+    ///
+    /// The iterator walks the order one key at a time, reading values as it
+    /// reaches their key: keys removed before it reaches them are skipped,
+    /// keys inserted meanwhile may be yielded last.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ordered::OrderedMap;
+    ///
+    /// let map = OrderedMap::new();
+    /// for key in ["c", "a", "b"] {
+    ///     map.insert(&key, ());
+    /// }
+    ///
+    /// let keys: Vec<_> = map.iter().map(|(key, _)| key).collect();
+    /// assert_eq!(keys, vec!["c", "a", "b"]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V, H> {
+        Iter {
+            map: self,
+            node: self.order.head.clone(),
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An iterator over the entries of an [`OrderedMap`] in insertion order,
+/// see [`OrderedMap::iter`].
+pub struct Iter<'a, K, V, H> {
+    map: &'a OrderedMap<K, V, H>,
+    /// The node of the last key yielded.
+    node: Arc<Node<K>>,
+}
+
+impl<'a, K, V, H> Iterator for Iter<'a, K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            // nodes unlinked meanwhile still lead to the nodes after them
+            self.node = self.node.next()?;
+            let key = self.node.key.as_ref().unwrap();
+            if let Some(value) = self.map.get_entry(key, Some(&self.node)) {
+                return Some((key.clone(), value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_inserts_pop_in_per_thread_order() {
+        let map = Arc::new(OrderedMap::with_bucket_count(8));
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..1000u64 {
+                        map.insert(&(thread, i), i);
+                        if i % 3 == 0 {
+                            map.remove(&(thread, i));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(map.len(), 4 * 666);
+        assert_eq!(map.iter().count(), map.len());
+
+        let mut last = [None; 4];
+        while let Some(((thread, i), value)) = map.pop_oldest() {
+            assert_eq!(i, value);
+            assert_ne!(i % 3, 0);
+            assert!(last[thread as usize] < Some(i));
+            last[thread as usize] = Some(i);
+        }
+        assert!(map.is_empty());
+        assert_eq!(last, [Some(998); 4]);
+    }
+
+    #[test]
+    fn test_concurrent_pops_and_removes_take_every_entry_once() {
+        let map = Arc::new(OrderedMap::with_bucket_count(8));
+        for i in 0..4000u64 {
+            map.insert(&i, i);
+        }
+
+        let takers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    let mut taken = Vec::new();
+                    for i in 0..1000u64 {
+                        let key = 4000 - 4 * i - thread - 1;
+                        taken.extend(map.remove(&key));
+                        taken.extend(map.pop_oldest().map(|(_, value)| value));
+                    }
+                    taken
+                })
+            })
+            .collect();
+        let mut taken: Vec<_> = takers
+            .into_iter()
+            .flat_map(|taker| taker.join().unwrap())
+            .collect();
+
+        taken.sort_unstable();
+        assert_eq!(taken, (0..4000).collect::<Vec<_>>());
+        assert!(map.is_empty());
+        assert_eq!(map.iter().count(), 0);
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+/// Number of hash bits consumed by every level of the trie.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// A node of the trie. Branches have a child for every bit set in
+/// `bitmap`, in bit order, and leaves hold the entries whose keys share the
+/// same full hash.
+enum Node<K, V> {
+    Branch {
+        bitmap: u32,
+        children: Vec<Arc<Node<K, V>>>,
+    },
+    Leaf {
+        hash: u64,
+        entries: Vec<(K, V)>,
+    },
+}
+
+/// Returns the bit of the branch at `shift` the hash belongs to.
+fn bit_for(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+/// Returns the position, among the children of a branch, of the child for
+/// `bit`.
+fn position(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Eq + Clone,
+    V: Clone,
+{
+    /// Returns a branch at `shift` holding both leaves, whose hashes differ.
+    fn pair(a: Arc<Self>, a_hash: u64, b: Arc<Self>, b_hash: u64, shift: u32) -> Self {
+        let (a_bit, b_bit) = (bit_for(a_hash, shift), bit_for(b_hash, shift));
+        if a_bit == b_bit {
+            return Node::Branch {
+                bitmap: a_bit,
+                children: vec![Arc::new(Self::pair(a, a_hash, b, b_hash, shift + BITS))],
+            };
+        }
+        let children = if a_bit < b_bit {
+            vec![a, b]
+        } else {
+            vec![b, a]
+        };
+        Node::Branch {
+            bitmap: a_bit | b_bit,
+            children,
+        }
+    }
+
+    fn get(&self, hash: u64, key: &K) -> Option<&V> {
+        let mut node = self;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = bit_for(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    node = &children[position(*bitmap, bit)];
+                    shift += BITS;
+                }
+                Node::Leaf {
+                    hash: leaf_hash,
+                    entries,
+                } => {
+                    if *leaf_hash != hash {
+                        return None;
+                    }
+                    return entries
+                        .iter()
+                        .find(|(elem_key, _)| elem_key == key)
+                        .map(|(_, value)| value);
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the subtree of `node` with the entry inserted, and
+    /// whether the key was new. Only the nodes on the path to the entry are
+    /// copied.
+    fn insert(node: &Arc<Self>, hash: u64, shift: u32, key: &K, value: V) -> (Arc<Self>, bool) {
+        match &**node {
+            Node::Leaf {
+                hash: leaf_hash,
+                entries,
+            } if *leaf_hash == hash => {
+                let mut entries = entries.clone();
+                let added = match entries.iter_mut().find(|(elem_key, _)| elem_key == key) {
+                    Some((_, existing)) => {
+                        *existing = value;
+                        false
+                    }
+                    None => {
+                        entries.push((key.clone(), value));
+                        true
+                    }
+                };
+                (Arc::new(Node::Leaf { hash, entries }), added)
+            }
+            Node::Leaf {
+                hash: leaf_hash, ..
+            } => {
+                let leaf = Arc::new(Node::Leaf {
+                    hash,
+                    entries: vec![(key.clone(), value)],
+                });
+                let branch = Self::pair(Arc::clone(node), *leaf_hash, leaf, hash, shift);
+                (Arc::new(branch), true)
+            }
+            Node::Branch { bitmap, children } => {
+                let bit = bit_for(hash, shift);
+                let index = position(*bitmap, bit);
+                let mut children = children.clone();
+                let added = if bitmap & bit == 0 {
+                    let leaf = Node::Leaf {
+                        hash,
+                        entries: vec![(key.clone(), value)],
+                    };
+                    children.insert(index, Arc::new(leaf));
+                    true
+                } else {
+                    let (child, added) =
+                        Self::insert(&children[index], hash, shift + BITS, key, value);
+                    children[index] = child;
+                    added
+                };
+                let branch = Node::Branch {
+                    bitmap: bitmap | bit,
+                    children,
+                };
+                (Arc::new(branch), added)
+            }
+        }
+    }
+
+    /// Returns a copy of the subtree of `node` without the entry of `key`,
+    /// `None` for an empty subtree, along with the removed value.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the subtree does not contain `key`.
+    fn remove(node: &Arc<Self>, hash: u64, shift: u32, key: &K) -> Option<(Option<Arc<Self>>, V)> {
+        match &**node {
+            Node::Leaf {
+                hash: leaf_hash,
+                entries,
+            } => {
+                if *leaf_hash != hash {
+                    return None;
+                }
+                let index = entries.iter().position(|(elem_key, _)| elem_key == key)?;
+                let mut entries = entries.clone();
+                let (_, value) = entries.swap_remove(index);
+                let leaf = if entries.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(Node::Leaf { hash, entries }))
+                };
+                Some((leaf, value))
+            }
+            Node::Branch { bitmap, children } => {
+                let bit = bit_for(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let index = position(*bitmap, bit);
+                let (child, value) = Self::remove(&children[index], hash, shift + BITS, key)?;
+
+                let mut children = children.clone();
+                let mut bitmap = *bitmap;
+                match child {
+                    Some(child) => children[index] = child,
+                    None => {
+                        children.remove(index);
+                        bitmap &= !bit;
+                    }
+                }
+                let branch = match children.as_slice() {
+                    [] => None,
+                    // a lone leaf needs no branch above it
+                    [child] if matches!(**child, Node::Leaf { .. }) => Some(Arc::clone(child)),
+                    _ => Some(Arc::new(Node::Branch { bitmap, children })),
+                };
+                Some((branch, value))
+            }
+        }
+    }
+}
+
+/// Thread-Safe persistent map implemented as a hash array mapped trie.
+///
+/// A `PMap` is an immutable value: [`PMap::insert`] and [`PMap::remove`]
+/// return a new version of the map, leaving the original untouched. Versions
+/// share every node not on the path to the modified entry, so a new version
+/// costs O(log n) memory, and keeping old versions around gives cheap
+/// snapshots and time travel. Cloning a `PMap` is O(1).
+///
+/// Nodes are immutable and reference counted, so versions can be read from
+/// any number of threads without locking.
+pub struct PMap<K, V, H = RandomState> {
+    hash_builder: H,
+    root: Option<Arc<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> Default for PMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> PMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `PMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::persistent::PMap;
+    /// let map: PMap<&str, i32> = PMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, H> PMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher + Clone,
+{
+    /// Creates an empty `PMap` using `hash_builder` to hash the keys.
+    pub fn with_hasher(hash_builder: H) -> Self {
+        PMap {
+            hash_builder,
+            root: None,
+            len: 0,
+        }
+    }
+
+    /// Returns a version of the map with `key` mapped to `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::persistent::PMap;
+    ///
+    /// let v1 = PMap::new().insert(&"a", 1);
+    /// let v2 = v1.insert(&"a", 2);
+    ///
+    /// assert_eq!(v1.get(&"a"), Some(&1));
+    /// assert_eq!(v2.get(&"a"), Some(&2));
+    /// ```
+    pub fn insert(&self, key: &K, value: V) -> Self {
+        let hash = self.hash_builder.hash_one(key);
+        let (root, added) = match &self.root {
+            Some(root) => Node::insert(root, hash, 0, key, value),
+            None => {
+                let leaf = Node::Leaf {
+                    hash,
+                    entries: vec![(key.clone(), value)],
+                };
+                (Arc::new(leaf), true)
+            }
+        };
+        PMap {
+            hash_builder: self.hash_builder.clone(),
+            root: Some(root),
+            len: self.len + added as usize,
+        }
+    }
+
+    /// Returns a version of the map without the mapping for `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::persistent::PMap;
+    ///
+    /// let v1 = PMap::new().insert(&"a", 1);
+    /// let v2 = v1.remove(&"a");
+    ///
+    /// assert!(v1.contains_key(&"a"));
+    /// assert!(v2.is_empty());
+    /// ```
+    pub fn remove(&self, key: &K) -> Self {
+        let hash = self.hash_builder.hash_one(key);
+        match self
+            .root
+            .as_ref()
+            .and_then(|root| Node::remove(root, hash, 0, key))
+        {
+            Some((root, _)) => PMap {
+                hash_builder: self.hash_builder.clone(),
+                root,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = self.hash_builder.hash_one(key);
+        self.root.as_ref()?.get(hash, key)
+    }
+
+    /// Returns `true` if the `PMap` contains a mapping for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns an iterator over the entries of the `PMap`, in arbitrary
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::persistent::PMap;
+    ///
+    /// let map = PMap::new().insert(&1, 'a').insert(&2, 'b');
+    ///
+    /// let mut entries: Vec<_> = map.iter().collect();
+    /// entries.sort();
+    /// assert_eq!(entries, vec![(&1, &'a'), (&2, &'b')]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: self.root.iter().map(|root| &**root).collect(),
+            entries: [].iter(),
+        }
+    }
+
+    /// Returns the number of entries in the `PMap`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the `PMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K, V, H> Clone for PMap<K, V, H>
+where
+    H: Clone,
+{
+    fn clone(&self) -> Self {
+        PMap {
+            hash_builder: self.hash_builder.clone(),
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+/// An iterator over the entries of a [`PMap`], see [`PMap::iter`].
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    entries: std::slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.entries.next() {
+                return Some((key, value));
+            }
+            match self.stack.pop()? {
+                Node::Branch { children, .. } => {
+                    self.stack.extend(children.iter().map(|child| &**child))
+                }
+                Node::Leaf { entries, .. } => self.entries = entries.iter(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PMap;
+    use std::thread;
+
+    #[test]
+    fn test_versions_are_independent() {
+        let mut versions = vec![PMap::new()];
+        for key in 0..2000u64 {
+            let next = versions.last().unwrap().insert(&key, key);
+            versions.push(next);
+        }
+        let full = versions.last().unwrap().clone();
+
+        let readers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let full = full.clone();
+                thread::spawn(move || {
+                    let mut map = full.clone();
+                    for key in (thread..2000).step_by(4) {
+                        map = map.remove(&key);
+                    }
+                    assert_eq!(map.len(), 1500);
+                    assert!(full.iter().all(|(key, value)| key == value));
+                    map
+                })
+            })
+            .collect();
+        for reader in readers {
+            let map = reader.join().unwrap();
+            assert_eq!(map.iter().count(), 1500);
+        }
+
+        for (len, version) in versions.iter().enumerate() {
+            assert_eq!(version.len(), len);
+            assert_eq!(version.iter().count(), len);
+        }
+        assert_eq!(versions[1000].get(&999), Some(&999));
+        assert_eq!(versions[1000].get(&1000), None);
+        let emptied = (0..2000u64).fold(full, |map, key| map.remove(&key));
+        assert!(emptied.is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Objects of a key, the idle ones with the instant they were returned.
+struct Slot<T> {
+    idle: Vec<(T, Instant)>,
+    checked_out: usize,
+}
+
+type PoolBucket<K, T> = PriorityRwLock<Vec<(K, Slot<T>)>>;
+
+/// Thread-Safe pool of reusable objects, such as connections or buffers,
+/// kept per key in a hash table.
+///
+/// Objects are checked out wrapped in a [`Pooled`] guard, which returns
+/// them to the pool when dropped. Every key holds at most `max_size`
+/// objects, idle or checked out, and idle objects unused for longer than
+/// the idle timeout are dropped instead of being reused.
+pub struct Pool<K, T, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<PoolBucket<K, T>>,
+    lock_policy: LockPolicy,
+    max_size: usize,
+    idle_timeout: Option<Duration>,
+}
+
+impl<K, T> Pool<K, T, RandomState>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates an empty `Pool` holding at most `max_size` objects per key,
+    /// and keeping idle objects forever.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::pool::Pool;
+    /// let pool: Pool<&str, Vec<u8>> = Pool::new(8);
+    /// ```
+    pub fn new(max_size: usize) -> Self {
+        Self::with_hasher(RandomState::new(), max_size, None)
+    }
+
+    /// Creates an empty `Pool` holding at most `max_size` objects per key,
+    /// and dropping objects idle for longer than `idle_timeout`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::pool::Pool;
+    /// use std::time::Duration;
+    ///
+    /// let pool: Pool<&str, Vec<u8>> = Pool::with_idle_timeout(8, Duration::from_secs(60));
+    /// ```
+    pub fn with_idle_timeout(max_size: usize, idle_timeout: Duration) -> Self {
+        Self::with_hasher(RandomState::new(), max_size, Some(idle_timeout))
+    }
+}
+
+impl<K, T, H> Pool<K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `Pool` like [`Pool::new`] or
+    /// [`Pool::with_idle_timeout`], using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_size` is 0.
+    pub fn with_hasher(hash_builder: H, max_size: usize, idle_timeout: Option<Duration>) -> Self {
+        if max_size == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(DEFAULT_BUCKET_COUNT);
+        buckets.resize_with(DEFAULT_BUCKET_COUNT, || PriorityRwLock::new(Vec::new()));
+
+        Pool {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+            max_size,
+            idle_timeout,
+        }
+    }
+
+    fn get_bucket(&self, key: &K) -> &PoolBucket<K, T> {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        &self.buckets[hash % self.buckets.len()]
+    }
+
+    fn is_expired(&self, returned: Instant) -> bool {
+        self.idle_timeout
+            .is_some_and(|idle_timeout| returned.elapsed() >= idle_timeout)
+    }
+
+    /// Checks out an object for `key`: the most recently returned idle
+    /// one, or a new one made by `create` if the key holds less than
+    /// `max_size` objects.
+    ///
+    /// `create` runs outside of the pool's locks, so it may block, for
+    /// instance to open a connection.
+    ///
+    /// # Returns
+    ///
+    /// `None` if every object of `key` is checked out and the key is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pool::Pool;
+    ///
+    /// let pool = Pool::new(1);
+    /// {
+    ///     let mut buffer = pool.checkout(&"buffers", Vec::new).unwrap();
+    ///     buffer.push(1u8);
+    ///     assert!(pool.checkout(&"buffers", Vec::new).is_none());
+    /// }
+    ///
+    /// // the buffer was returned on drop, and is reused
+    /// let buffer = pool.checkout(&"buffers", Vec::new).unwrap();
+    /// assert_eq!(*buffer, vec![1]);
+    /// ```
+    pub fn checkout<F>(&self, key: &K, create: F) -> Option<Pooled<'_, K, T, H>>
+    where
+        F: FnOnce() -> T,
+    {
+        let mut expired = Vec::new();
+        let reused = {
+            let mut bucket = self.get_bucket(key).write(self.lock_policy.write);
+            let index = match bucket.iter().position(|(elem_key, _)| elem_key == key) {
+                Some(index) => index,
+                None => {
+                    bucket.push((
+                        key.clone(),
+                        Slot {
+                            idle: Vec::new(),
+                            checked_out: 0,
+                        },
+                    ));
+                    bucket.len() - 1
+                }
+            };
+
+            let slot = &mut bucket[index].1;
+            // idle objects are sorted by return instant, oldest first
+            let live = slot
+                .idle
+                .iter()
+                .position(|(_, returned)| !self.is_expired(*returned))
+                .unwrap_or(slot.idle.len());
+            expired.extend(slot.idle.drain(..live).map(|(object, _)| object));
+
+            let reused = slot.idle.pop().map(|(object, _)| object);
+            if reused.is_none() && slot.checked_out + slot.idle.len() >= self.max_size {
+                return None;
+            }
+            slot.checked_out += 1;
+            reused
+        };
+        // expired objects may be costly to drop, do it outside of the lock
+        drop(expired);
+
+        // checked in without an object if `create` panics, freeing its place
+        let mut pooled = Pooled {
+            pool: self,
+            key: key.clone(),
+            object: reused,
+        };
+        if pooled.object.is_none() {
+            pooled.object = Some(create());
+        }
+        Some(pooled)
+    }
+
+    /// Takes a checked out object back, or forgets it if `object` is `None`.
+    fn check_in(&self, key: &K, object: Option<T>) {
+        let mut bucket = self.get_bucket(key).write(self.lock_policy.write);
+        let index = match bucket.iter().position(|(elem_key, _)| elem_key == key) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let slot = &mut bucket[index].1;
+        slot.checked_out -= 1;
+        match object {
+            Some(object) => slot.idle.push((object, Instant::now())),
+            None if slot.checked_out == 0 && slot.idle.is_empty() => {
+                bucket.swap_remove(index);
+            }
+            None => {}
+        }
+    }
+
+    /// Returns the number of idle objects of `key`, including expired ones
+    /// not dropped yet.
+    pub fn idle(&self, key: &K) -> usize {
+        let bucket = self.get_bucket(key).read(self.lock_policy.read);
+        bucket
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map_or(0, |(_, slot)| slot.idle.len())
+    }
+
+    /// Returns the number of checked out objects of `key`.
+    pub fn checked_out(&self, key: &K) -> usize {
+        let bucket = self.get_bucket(key).read(self.lock_policy.read);
+        bucket
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map_or(0, |(_, slot)| slot.checked_out)
+    }
+
+    /// Drops the idle objects of every key that exceeded the idle timeout,
+    /// returning how many were dropped.
+    ///
+    /// Expired objects are otherwise only dropped when their key is checked
+    /// out, this lets a background thread release them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pool::Pool;
+    /// use std::time::Duration;
+    ///
+    /// let pool = Pool::with_idle_timeout(4, Duration::from_millis(1));
+    /// drop(pool.checkout(&"buffers", Vec::<u8>::new));
+    /// std::thread::sleep(Duration::from_millis(5));
+    ///
+    /// assert_eq!(pool.evict_idle(), 1);
+    /// assert_eq!(pool.idle(&"buffers"), 0);
+    /// ```
+    pub fn evict_idle(&self) -> usize {
+        let mut evicted = 0;
+        for bucket in &self.buckets {
+            let mut expired = Vec::new();
+            {
+                let mut bucket = bucket.write(self.lock_policy.write);
+                for (_, slot) in bucket.iter_mut() {
+                    let live = slot
+                        .idle
+                        .iter()
+                        .position(|(_, returned)| !self.is_expired(*returned))
+                        .unwrap_or(slot.idle.len());
+                    expired.extend(slot.idle.drain(..live).map(|(object, _)| object));
+                }
+                bucket.retain(|(_, slot)| slot.checked_out > 0 || !slot.idle.is_empty());
+            }
+            evicted += expired.len();
+        }
+        evicted
+    }
+}
+
+/// An object checked out of a [`Pool`], returned to it on drop. See
+/// [`Pool::checkout`].
+pub struct Pooled<'a, K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    pool: &'a Pool<K, T, H>,
+    key: K,
+    object: Option<T>,
+}
+
+impl<'a, K, T, H> Pooled<'a, K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Takes the object out of the pool for good, freeing its place for a
+    /// new object. Useful to discard a broken connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pool::Pool;
+    ///
+    /// let pool = Pool::new(1);
+    /// let connection = pool.checkout(&"replica", || "connection").unwrap();
+    /// assert_eq!(connection.detach(), "connection");
+    /// assert_eq!(pool.checked_out(&"replica"), 0);
+    /// assert_eq!(pool.idle(&"replica"), 0);
+    /// ```
+    pub fn detach(mut self) -> T {
+        self.object.take().unwrap()
+    }
+}
+
+impl<'a, K, T, H> Deref for Pooled<'a, K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.as_ref().unwrap()
+    }
+}
+
+impl<'a, K, T, H> DerefMut for Pooled<'a, K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.object.as_mut().unwrap()
+    }
+}
+
+impl<'a, K, T, H> Drop for Pooled<'a, K, T, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    fn drop(&mut self) {
+        self.pool.check_in(&self.key, self.object.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pool;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_checkouts_never_exceed_max_size() {
+        let pool = Arc::new(Pool::new(3));
+        let created = Arc::new(AtomicUsize::new(0));
+        let in_use = Arc::new(AtomicUsize::new(0));
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let (pool, created, in_use) = (pool.clone(), created.clone(), in_use.clone());
+                thread::spawn(move || {
+                    let mut served = 0;
+                    while served < 200 {
+                        let object =
+                            pool.checkout(&"shard", || created.fetch_add(1, Ordering::SeqCst));
+                        if let Some(_object) = object {
+                            assert!(in_use.fetch_add(1, Ordering::SeqCst) < 3);
+                            in_use.fetch_sub(1, Ordering::SeqCst);
+                            served += 1;
+                        } else {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert!(created.load(Ordering::SeqCst) <= 3);
+        assert_eq!(pool.checked_out(&"shard"), 0);
+        assert_eq!(pool.idle(&"shard"), created.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_panicking_create_frees_its_place() {
+        let pool = Pool::new(1);
+        for _ in 0..3 {
+            let checkout = panic::catch_unwind(AssertUnwindSafe(|| {
+                pool.checkout(&"replica", || -> &str { panic!("unreachable replica") })
+                    .map(|_| ())
+            }));
+            assert!(checkout.is_err());
+            assert_eq!(pool.checked_out(&"replica"), 0);
+        }
+        let connection = pool.checkout(&"replica", || "connection").unwrap();
+        assert_eq!(*connection, "connection");
+        assert_eq!(pool.checked_out(&"replica"), 1);
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::collections::sorted::SortedMap;
+
+/// Thread-Safe max-priority queue.
+///
+/// The queue is a [`SortedMap`] keyed by element and insertion sequence, so
+/// pushes and pops of different elements proceed in parallel and only
+/// contend around the largest element. Equal elements are popped in the
+/// order they were pushed.
+pub struct PriorityQueue<T> {
+    elements: SortedMap<(T, Reverse<u64>), ()>,
+    sequence: AtomicU64,
+}
+
+impl<T> Default for PriorityQueue<T>
+where
+    T: Ord + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PriorityQueue<T>
+where
+    T: Ord + Clone,
+{
+    /// Creates an empty `PriorityQueue`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::pqueue::PriorityQueue;
+    /// let queue: PriorityQueue<u64> = PriorityQueue::new();
+    /// ```
+    pub fn new() -> Self {
+        PriorityQueue {
+            elements: SortedMap::new(),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds `value` to the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pqueue::PriorityQueue;
+    ///
+    /// let queue = PriorityQueue::new();
+    /// queue.push(3);
+    /// queue.push(3);
+    /// assert_eq!(queue.len(), 2);
+    /// ```
+    pub fn push(&self, value: T) {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.elements.put(&(value, Reverse(sequence)), ());
+    }
+
+    /// Returns the largest element without removing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pqueue::PriorityQueue;
+    ///
+    /// let queue = PriorityQueue::new();
+    /// assert_eq!(queue.peek(), None);
+    ///
+    /// queue.push(1);
+    /// queue.push(5);
+    /// assert_eq!(queue.peek(), Some(5));
+    /// ```
+    pub fn peek(&self) -> Option<T> {
+        self.elements.last().map(|((value, _), _)| value)
+    }
+
+    /// Removes and returns the largest element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::pqueue::PriorityQueue;
+    ///
+    /// let queue = PriorityQueue::new();
+    /// queue.push(1);
+    /// queue.push(5);
+    /// queue.push(3);
+    ///
+    /// assert_eq!(queue.pop_max(), Some(5));
+    /// assert_eq!(queue.pop_max(), Some(3));
+    /// assert_eq!(queue.pop_max(), Some(1));
+    /// assert_eq!(queue.pop_max(), None);
+    /// ```
+    pub fn pop_max(&self) -> Option<T> {
+        loop {
+            let (key, _) = self.elements.last()?;
+            // another thread may pop the same element first, in which case
+            // the next largest one is tried
+            if self.elements.remove(&key).is_some() {
+                return Some(key.0);
+            }
+        }
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Returns `true` if the queue contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityQueue;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_pops_take_every_element_once() {
+        let queue = Arc::new(PriorityQueue::new());
+        (0..2000u64).for_each(|value| queue.push(value % 500));
+
+        let poppers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    while let Some(value) = queue.pop_max() {
+                        popped.push(value);
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        let mut popped = Vec::new();
+        for popper in poppers {
+            let values = popper.join().unwrap();
+            // every popper observes the queue draining from the top
+            assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+            popped.extend(values);
+        }
+        popped.sort_unstable();
+
+        let mut expected: Vec<u64> = (0..2000).map(|value| value % 500).collect();
+        expected.sort_unstable();
+        assert_eq!(popped, expected);
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+/// Thread-Safe FIFO queue for any number of producers and consumers,
+/// either bounded or unbounded.
+///
+/// Elements are pushed and popped under a write lock acquired according to
+/// the queue's [`LockPolicy`]. Blocking [`Queue::push`] and [`Queue::pop`]
+/// park the calling thread until another thread makes room or pushes an
+/// element.
+pub struct Queue<T> {
+    elements: PriorityRwLock<VecDeque<T>>,
+    capacity: Option<usize>,
+    lock_policy: LockPolicy,
+    parked: Mutex<()>,
+    changed: Condvar,
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::unbounded()
+    }
+}
+
+impl<T> Queue<T> {
+    fn with_capacity(capacity: Option<usize>) -> Self {
+        Queue {
+            elements: PriorityRwLock::new(VecDeque::new()),
+            capacity,
+            lock_policy: LockPolicy::hybrid(),
+            parked: Mutex::new(()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Creates an empty `Queue` without a capacity limit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::queue::Queue;
+    /// let queue: Queue<u64> = Queue::unbounded();
+    /// ```
+    pub fn unbounded() -> Self {
+        Self::with_capacity(None)
+    }
+
+    /// Creates an empty `Queue` holding at most `capacity` elements.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `capacity` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::queue::Queue;
+    /// let queue: Queue<u64> = Queue::bounded(128);
+    /// assert_eq!(queue.capacity(), Some(128));
+    /// ```
+    pub fn bounded(capacity: usize) -> Self {
+        if capacity == 0usize {
+            panic!()
+        }
+        Self::with_capacity(Some(capacity))
+    }
+
+    /// Returns the maximum number of elements, or `None` if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    fn is_full(&self, elements: &VecDeque<T>) -> bool {
+        self.capacity
+            .is_some_and(|capacity| elements.len() >= capacity)
+    }
+
+    /// Wakes every parked thread. Taking `parked` orders the notification
+    /// after the condition checks of threads about to park.
+    fn notify(&self) {
+        drop(self.parked.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    /// Parks the calling thread until `ready` holds, re-checking it after
+    /// every change to the queue.
+    fn park_until<F>(&self, ready: F)
+    where
+        F: Fn(&VecDeque<T>) -> bool,
+    {
+        let mut parked = self.parked.lock().unwrap();
+        while !ready(&self.elements.read(self.lock_policy.read)) {
+            parked = self.changed.wait(parked).unwrap();
+        }
+    }
+
+    /// Appends `value` to the back of the queue, unless the queue is full.
+    ///
+    /// # Returns
+    ///
+    /// `Err(value)` if the queue is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::queue::Queue;
+    ///
+    /// let queue = Queue::bounded(1);
+    /// assert_eq!(queue.try_push(1), Ok(()));
+    /// assert_eq!(queue.try_push(2), Err(2));
+    /// ```
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        {
+            let mut elements = self.elements.write(self.lock_policy.write);
+            if self.is_full(&elements) {
+                return Err(value);
+            }
+            elements.push_back(value);
+        }
+        self.notify();
+        Ok(())
+    }
+
+    /// Appends `value` to the back of the queue, blocking while the queue
+    /// is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::queue::Queue;
+    ///
+    /// let queue = Queue::unbounded();
+    /// queue.push("job");
+    /// assert_eq!(queue.len(), 1);
+    /// ```
+    pub fn push(&self, mut value: T) {
+        loop {
+            match self.try_push(value) {
+                Ok(()) => return,
+                Err(rejected) => value = rejected,
+            }
+            self.park_until(|elements| !self.is_full(elements));
+        }
+    }
+
+    /// Removes the element at the front of the queue, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::queue::Queue;
+    ///
+    /// let queue = Queue::unbounded();
+    /// queue.push(1);
+    /// assert_eq!(queue.try_pop(), Some(1));
+    /// assert_eq!(queue.try_pop(), None);
+    /// ```
+    pub fn try_pop(&self) -> Option<T> {
+        let value = self.elements.write(self.lock_policy.write).pop_front()?;
+        if self.capacity.is_some() {
+            self.notify();
+        }
+        Some(value)
+    }
+
+    /// Removes the element at the front of the queue, blocking while the
+    /// queue is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::queue::Queue;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    ///
+    /// let queue = Arc::new(Queue::unbounded());
+    /// let producer = {
+    ///     let queue = queue.clone();
+    ///     thread::spawn(move || queue.push(42))
+    /// };
+    ///
+    /// assert_eq!(queue.pop(), 42);
+    /// producer.join().unwrap();
+    /// ```
+    pub fn pop(&self) -> T {
+        loop {
+            if let Some(value) = self.try_pop() {
+                return value;
+            }
+            self.park_until(|elements| !elements.is_empty());
+        }
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.elements.read(self.lock_policy.read).len()
+    }
+
+    /// Returns `true` if the queue contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Queue;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_bounded_queue_delivers_every_element_once() {
+        let queue = Arc::new(Queue::bounded(4));
+        let producers: Vec<_> = (0..4u64)
+            .map(|producer| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        queue.push(producer * 1000 + i);
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || (0..1000).map(|_| queue.pop()).collect::<Vec<u64>>())
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut popped: Vec<u64> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        popped.sort_unstable();
+
+        assert_eq!(popped, (0..4000).collect::<Vec<u64>>());
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::collections::utils::Acquire;
+
+/// Slot of a [`RingBuffer`]. `sequence` tells which position the slot is
+/// ready for: twice the position when it can be written, twice the position
+/// plus one once it holds the element written at that position. Doubling
+/// keeps both states apart even when the buffer holds a single slot.
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Thread-Safe bounded FIFO ring buffer for any number of producers and
+/// consumers.
+///
+/// Pushes and pops never lock: producers and consumers claim positions with
+/// compare-and-swap loops and hand elements over through per-slot sequence
+/// numbers. Only the blocking [`RingBuffer::push`] and [`RingBuffer::pop`]
+/// park, after spinning, when the buffer stays full or empty.
+pub struct RingBuffer<T> {
+    slots: Box<[Slot<T>]>,
+    /// Position of the next push.
+    tail: AtomicUsize,
+    /// Position of the next pop.
+    head: AtomicUsize,
+    acquire: Acquire,
+    waiting: AtomicUsize,
+    parked: Mutex<()>,
+    changed: Condvar,
+}
+
+// SAFETY: a slot's value is only accessed by the single thread that
+// claimed its position, and handed over through the slot's sequence.
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    /// Creates an empty `RingBuffer` holding at most `capacity` elements.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `capacity` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ring::RingBuffer;
+    /// let ring: RingBuffer<u64> = RingBuffer::new(1024);
+    /// assert_eq!(ring.capacity(), 1024);
+    /// ```
+    pub fn new(capacity: usize) -> Self {
+        if capacity == 0usize {
+            panic!()
+        }
+        let slots = (0..capacity)
+            .map(|position| Slot {
+                sequence: AtomicUsize::new(position * 2),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        RingBuffer {
+            slots,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            acquire: Acquire::Spin { spins: 64 },
+            waiting: AtomicUsize::new(0),
+            parked: Mutex::new(()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Returns the maximum number of elements.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Appends `value` to the back of the buffer, unless the buffer is
+    /// full.
+    ///
+    /// # Returns
+    ///
+    /// `Err(value)` if the buffer is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ring::RingBuffer;
+    ///
+    /// let ring = RingBuffer::new(1);
+    /// assert_eq!(ring.try_push(1), Ok(()));
+    /// assert_eq!(ring.try_push(2), Err(2));
+    /// ```
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut position = self.tail.load(Ordering::SeqCst);
+        loop {
+            let slot = &self.slots[position % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::SeqCst);
+            let free = position.wrapping_mul(2);
+            if sequence == free {
+                match self.tail.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: the position is claimed, no other thread
+                        // accesses the slot until its sequence is bumped.
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(free.wrapping_add(1), Ordering::SeqCst);
+                        self.notify();
+                        return Ok(());
+                    }
+                    Err(current) => position = current,
+                }
+            } else if (sequence.wrapping_sub(free) as isize) < 0 {
+                // the slot still holds the element pushed a lap before
+                return Err(value);
+            } else {
+                position = self.tail.load(Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Removes the element at the front of the buffer, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ring::RingBuffer;
+    ///
+    /// let ring = RingBuffer::new(8);
+    /// ring.try_push("record").unwrap();
+    /// assert_eq!(ring.try_pop(), Some("record"));
+    /// assert_eq!(ring.try_pop(), None);
+    /// ```
+    pub fn try_pop(&self) -> Option<T> {
+        let mut position = self.head.load(Ordering::SeqCst);
+        loop {
+            let slot = &self.slots[position % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::SeqCst);
+            let ready = position.wrapping_mul(2).wrapping_add(1);
+            if sequence == ready {
+                match self.head.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: the position is claimed and its element
+                        // was written, no other thread accesses the slot
+                        // until its sequence is bumped.
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        let next_lap = position.wrapping_add(self.slots.len());
+                        slot.sequence
+                            .store(next_lap.wrapping_mul(2), Ordering::SeqCst);
+                        self.notify();
+                        return Some(value);
+                    }
+                    Err(current) => position = current,
+                }
+            } else if (sequence.wrapping_sub(ready) as isize) < 0 {
+                // the slot is not written yet
+                return None;
+            } else {
+                position = self.head.load(Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Appends `value` to the back of the buffer, blocking while the buffer
+    /// is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ring::RingBuffer;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    ///
+    /// let ring = Arc::new(RingBuffer::new(2));
+    /// let producer = {
+    ///     let ring = ring.clone();
+    ///     thread::spawn(move || (0..100).for_each(|value| ring.push(value)))
+    /// };
+    ///
+    /// let received: Vec<_> = (0..100).map(|_| ring.pop()).collect();
+    /// producer.join().unwrap();
+    /// assert_eq!(received, (0..100).collect::<Vec<_>>());
+    /// ```
+    pub fn push(&self, mut value: T) {
+        loop {
+            match self.try_push(value) {
+                Ok(()) => return,
+                Err(rejected) => value = rejected,
+            }
+            self.wait_until(|| self.len() < self.capacity());
+        }
+    }
+
+    /// Removes the element at the front of the buffer, blocking while the
+    /// buffer is empty.
+    pub fn pop(&self) -> T {
+        loop {
+            if let Some(value) = self.try_pop() {
+                return value;
+            }
+            self.wait_until(|| !self.is_empty());
+        }
+    }
+
+    /// Spins, then parks the calling thread until `ready` holds.
+    fn wait_until<F>(&self, ready: F)
+    where
+        F: Fn() -> bool,
+    {
+        if let Acquire::Spin { spins } = self.acquire {
+            for _ in 0..spins {
+                if ready() {
+                    return;
+                }
+                std::hint::spin_loop();
+            }
+        }
+
+        let mut parked = self.parked.lock().unwrap();
+        // registering before re-checking orders this thread either before
+        // the next change or after its notification check
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        while !ready() {
+            parked = self.changed.wait(parked).unwrap();
+        }
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wakes the parked threads, if any.
+    fn notify(&self) {
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            drop(self.parked.lock().unwrap());
+            self.changed.notify_all();
+        }
+    }
+
+    /// Returns the number of elements in the buffer, counting the ones
+    /// being pushed or popped concurrently.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::SeqCst);
+        let tail = self.tail.load(Ordering::SeqCst);
+        tail.wrapping_sub(head).min(self.capacity())
+    }
+
+    /// Returns `true` if the buffer contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_producers_and_consumers_hand_over_every_element() {
+        let ring = Arc::new(RingBuffer::new(16));
+
+        let producers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        ring.push(thread * 10_000 + i);
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let ring = ring.clone();
+                thread::spawn(move || (0..10_000).map(|_| ring.pop()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut received: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        received.sort_unstable();
+        assert_eq!(received, (0..40_000).collect::<Vec<_>>());
+        assert!(ring.is_empty());
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::vec;
+
+use crate::collections::map::{self, Map, MapBuilder};
+
+/// Thread-Safe set implemented as hash table.
+///
+/// A `Set` is a [`Map`] without values, and shares its bucket machinery:
+/// bucket locks, tombstoned removals and iteration that does not block
+/// writers.
+pub struct Set<T, H = RandomState> {
+    map: Map<T, (), H>,
+}
+
+impl<T> Default for Set<T, RandomState>
+where
+    T: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Set<T, RandomState>
+where
+    T: Hash + Eq + Clone,
+{
+    /// Creates an empty `Set`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::set::Set;
+    /// let set: Set<&str> = Set::new();
+    /// ```
+    pub fn new() -> Self {
+        Set { map: Map::new() }
+    }
+
+    /// Creates an empty `Set` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::set::Set;
+    /// let set: Set<&str> = Set::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Set {
+            map: Map::with_bucket_count(bucket_count),
+        }
+    }
+}
+
+impl<T, H> Set<T, H>
+where
+    T: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `Set` configured by `builder`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::collections::set::Set;
+    ///
+    /// let set: Set<u64> = Set::with_builder(MapBuilder::new().bucket_filter_bits(1024));
+    /// ```
+    pub fn with_builder(builder: MapBuilder<H>) -> Self {
+        Set {
+            map: builder.build(),
+        }
+    }
+
+    /// Adds `value` to the `Set`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was not present before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::Set;
+    ///
+    /// let set = Set::new();
+    /// assert!(set.insert(&"alice"));
+    /// assert!(!set.insert(&"alice"));
+    /// ```
+    pub fn insert(&self, value: &T) -> bool {
+        self.map.put_if_absent(value, ())
+    }
+
+    /// Returns `true` if the `Set` contains `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::Set;
+    ///
+    /// let set = Set::new();
+    /// set.insert(&1);
+    /// assert!(set.contains(&1));
+    /// assert!(!set.contains(&2));
+    /// ```
+    pub fn contains(&self, value: &T) -> bool {
+        self.map.get(value).is_some()
+    }
+
+    /// Removes `value` from the `Set`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `value` was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::Set;
+    ///
+    /// let set = Set::new();
+    /// set.insert(&1);
+    /// assert!(set.remove(&1));
+    /// assert!(!set.remove(&1));
+    /// ```
+    pub fn remove(&self, value: &T) -> bool {
+        self.map.remove(value).is_some()
+    }
+
+    /// Returns the number of values in the `Set`, see [`Map::len`].
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the `Set` contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns an iterator over the values of the `Set`, in arbitrary order.
+    ///
+    /// See [`map::Iter`] for what is observed of concurrent modifications.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::Set;
+    ///
+    /// let set = Set::new();
+    /// set.insert(&1);
+    /// set.insert(&2);
+    ///
+    /// let mut values: Vec<_> = set.iter().collect();
+    /// values.sort();
+    /// assert_eq!(values, vec![1, 2]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T, H> {
+        Iter(self.map.iter())
+    }
+
+    /// Reclaims memory held by removed values, see [`Map::collect_garbage`].
+    pub fn collect_garbage(&self) {
+        self.map.collect_garbage();
+    }
+}
+
+/// An iterator over the values of a [`Set`], see [`Set::iter`].
+pub struct Iter<'a, T, H>(map::Iter<'a, T, (), H>);
+
+impl<'a, T, H> Iterator for Iter<'a, T, H>
+where
+    T: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(value, _)| value)
+    }
+}
+
+/// A collection of distinct keys which can be combined with another one,
+/// such as a [`Set`] or the keys of a [`Map`].
+///
+/// Combinations walk one collection a bucket at a time, copying out the
+/// keys of a single bucket under its read lock and probing the other
+/// collection for each of them. Neither collection is ever copied whole, and
+/// no lock is held between steps, so the combinations observe concurrent
+/// modifications the way [`map::Iter`] does.
+pub trait KeySet<T> {
+    /// Returns `true` if `key` is in the collection.
+    fn contains_key(&self, key: &T) -> bool;
+
+    /// Returns the number of keys in the collection.
+    fn key_count(&self) -> usize;
+
+    /// Returns the keys of bucket `index`, or `None` past the last bucket.
+    fn bucket_keys(&self, index: usize) -> Option<Vec<T>>;
+
+    /// Returns an iterator over the keys in `self` or `other`, without
+    /// duplicates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::{KeySet, Set};
+    ///
+    /// let (a, b) = (Set::new(), Set::new());
+    /// a.insert(&1);
+    /// a.insert(&2);
+    /// b.insert(&2);
+    /// b.insert(&3);
+    ///
+    /// let mut union: Vec<_> = a.union(&b).collect();
+    /// union.sort();
+    /// assert_eq!(union, vec![1, 2, 3]);
+    /// ```
+    fn union<'a>(&'a self, other: &'a dyn KeySet<T>) -> Union<'a, T>
+    where
+        Self: Sized,
+    {
+        Union {
+            walk: BucketWalk::new(self),
+            rest: Filter {
+                walk: BucketWalk::new(other),
+                probed: self,
+                present: false,
+            },
+        }
+    }
+
+    /// Returns an iterator over the keys in both `self` and `other`.
+    ///
+    /// The smaller collection is walked, and the larger probed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::{KeySet, Set};
+    /// use palladiumdb::Map;
+    ///
+    /// let tagged = Set::new();
+    /// tagged.insert(&"alice");
+    /// tagged.insert(&"bob");
+    ///
+    /// let users = Map::new();
+    /// users.put(&"bob", 31);
+    /// users.put(&"carol", 27);
+    ///
+    /// let both: Vec<_> = tagged.intersection(&users).collect();
+    /// assert_eq!(both, vec!["bob"]);
+    /// ```
+    fn intersection<'a>(&'a self, other: &'a dyn KeySet<T>) -> Intersection<'a, T>
+    where
+        Self: Sized,
+    {
+        let (walked, probed): (&dyn KeySet<T>, &dyn KeySet<T>) =
+            if self.key_count() <= other.key_count() {
+                (self, other)
+            } else {
+                (other, self)
+            };
+        Intersection(Filter {
+            walk: BucketWalk::new(walked),
+            probed,
+            present: true,
+        })
+    }
+
+    /// Returns an iterator over the keys in `self` but not in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::{KeySet, Set};
+    ///
+    /// let (a, b) = (Set::new(), Set::new());
+    /// a.insert(&1);
+    /// a.insert(&2);
+    /// b.insert(&2);
+    ///
+    /// let difference: Vec<_> = a.difference(&b).collect();
+    /// assert_eq!(difference, vec![1]);
+    /// ```
+    fn difference<'a>(&'a self, other: &'a dyn KeySet<T>) -> Difference<'a, T>
+    where
+        Self: Sized,
+    {
+        Difference(Filter {
+            walk: BucketWalk::new(self),
+            probed: other,
+            present: false,
+        })
+    }
+
+    /// Returns `true` if every key in `self` is in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::set::{KeySet, Set};
+    ///
+    /// let (a, b) = (Set::new(), Set::new());
+    /// a.insert(&1);
+    /// b.insert(&1);
+    /// b.insert(&2);
+    ///
+    /// assert!(a.is_subset(&b));
+    /// assert!(!b.is_subset(&a));
+    /// ```
+    fn is_subset(&self, other: &dyn KeySet<T>) -> bool
+    where
+        Self: Sized,
+    {
+        self.difference(other).next().is_none()
+    }
+}
+
+impl<T, H> KeySet<T> for Set<T, H>
+where
+    T: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    fn contains_key(&self, key: &T) -> bool {
+        self.contains(key)
+    }
+
+    fn key_count(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_keys(&self, index: usize) -> Option<Vec<T>> {
+        self.map.bucket_keys(index)
+    }
+}
+
+impl<K, V, H> KeySet<K> for Map<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn key_count(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_keys(&self, index: usize) -> Option<Vec<K>> {
+        Map::bucket_keys(self, index)
+    }
+}
+
+/// Walks the keys of a [`KeySet`] a bucket at a time.
+struct BucketWalk<'a, T> {
+    keys: &'a dyn KeySet<T>,
+    bucket: usize,
+    batch: vec::IntoIter<T>,
+}
+
+impl<'a, T> BucketWalk<'a, T> {
+    fn new(keys: &'a dyn KeySet<T>) -> Self {
+        BucketWalk {
+            keys,
+            bucket: 0,
+            batch: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for BucketWalk<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(key) = self.batch.next() {
+                return Some(key);
+            }
+            self.batch = self.keys.bucket_keys(self.bucket)?.into_iter();
+            self.bucket += 1;
+        }
+    }
+}
+
+/// Walks the keys of a [`KeySet`], keeping those `present` in `probed`, or
+/// those absent from it if `present` is `false`.
+struct Filter<'a, T> {
+    walk: BucketWalk<'a, T>,
+    probed: &'a dyn KeySet<T>,
+    present: bool,
+}
+
+impl<'a, T> Iterator for Filter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let (probed, present) = (self.probed, self.present);
+        self.walk.find(|key| probed.contains_key(key) == present)
+    }
+}
+
+/// An iterator over the union of two [`KeySet`]s, see [`KeySet::union`].
+pub struct Union<'a, T> {
+    walk: BucketWalk<'a, T>,
+    rest: Filter<'a, T>,
+}
+
+impl<'a, T> Iterator for Union<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.walk.next().or_else(|| self.rest.next())
+    }
+}
+
+/// An iterator over the intersection of two [`KeySet`]s, see
+/// [`KeySet::intersection`].
+pub struct Intersection<'a, T>(Filter<'a, T>);
+
+impl<'a, T> Iterator for Intersection<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next()
+    }
+}
+
+/// An iterator over the difference of two [`KeySet`]s, see
+/// [`KeySet::difference`].
+pub struct Difference<'a, T>(Filter<'a, T>);
+
+impl<'a, T> Iterator for Difference<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeySet, Set};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_algebra_sees_values_present_throughout() {
+        let (evens, threes) = (Arc::new(Set::new()), Arc::new(Set::new()));
+        for i in 0..1000u64 {
+            evens.insert(&(i * 2));
+            threes.insert(&(i * 3));
+        }
+
+        // churn values outside of both sets while combining them
+        let writer = {
+            let evens = evens.clone();
+            thread::spawn(move || {
+                for i in 0..10_000u64 {
+                    evens.insert(&(1_000_000 + i));
+                    evens.remove(&(1_000_000 + i));
+                }
+            })
+        };
+
+        let mut both: Vec<_> = evens.intersection(&*threes).collect();
+        both.sort_unstable();
+        assert_eq!(both, (0..334).map(|i| i * 6).collect::<Vec<_>>());
+
+        let union = evens.union(&*threes).filter(|v| *v < 1_000_000).count();
+        assert_eq!(union, 1000 + 1000 - 334);
+        assert!(!threes.is_subset(&*evens));
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::collections::utils::mix;
+
+/// Thread-Safe HyperLogLog sketch estimating the number of distinct items
+/// inserted.
+///
+/// A sketch of precision `p` takes `2^p` bytes and estimates cardinalities
+/// with a standard error of about `1.04 / sqrt(2^p)`, whatever the number of
+/// items. Inserts and estimates never lock.
+pub struct HyperLogLog<T, H = RandomState> {
+    hash_builder: H,
+    registers: Box<[AtomicU8]>,
+    precision: u32,
+    _items: PhantomData<fn(&T)>,
+}
+
+impl<T> HyperLogLog<T, RandomState>
+where
+    T: Hash,
+{
+    /// Creates an empty `HyperLogLog` of the given `precision`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `precision` is not within `4..=16`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::sketch::HyperLogLog;
+    /// let sketch: HyperLogLog<u64> = HyperLogLog::new(14);
+    /// ```
+    pub fn new(precision: u32) -> Self {
+        Self::with_hasher(RandomState::new(), precision)
+    }
+}
+
+impl<T, H> HyperLogLog<T, H>
+where
+    T: Hash,
+    H: BuildHasher,
+{
+    /// Creates an empty `HyperLogLog` of the given `precision`, using
+    /// `hash_builder` to hash the items.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `precision` is not within `4..=16`.
+    pub fn with_hasher(hash_builder: H, precision: u32) -> Self {
+        if !(4..=16).contains(&precision) {
+            panic!()
+        }
+
+        let registers = (0..1usize << precision).map(|_| AtomicU8::new(0)).collect();
+        HyperLogLog {
+            hash_builder,
+            registers,
+            precision,
+            _items: PhantomData,
+        }
+    }
+
+    /// Returns the precision of the sketch.
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    /// Adds `item` to the sketch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sketch::HyperLogLog;
+    ///
+    /// let sketch = HyperLogLog::new(12);
+    /// sketch.insert(&"alice");
+    /// sketch.insert(&"alice");
+    /// assert_eq!(sketch.estimate(), 1);
+    /// ```
+    pub fn insert(&self, item: &T) {
+        let hash = mix(self.hash_builder.hash_one(item));
+        let index = (hash >> (64 - self.precision)) as usize;
+        // a sentinel bit bounds the rank when the remaining bits are all 0
+        let remaining = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = remaining.leading_zeros() as u8 + 1;
+        self.registers[index].fetch_max(rank, Ordering::SeqCst);
+    }
+
+    /// Returns the estimated number of distinct items inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sketch::HyperLogLog;
+    ///
+    /// let sketch = HyperLogLog::new(14);
+    /// for item in 0..10_000 {
+    ///     sketch.insert(&item);
+    /// }
+    ///
+    /// let estimate = sketch.estimate();
+    /// assert!(estimate > 9_500 && estimate < 10_500);
+    /// ```
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let (sum, zeros) = self
+            .registers
+            .iter()
+            .map(|register| register.load(Ordering::SeqCst))
+            .fold((0.0, 0usize), |(sum, zeros), rank| {
+                (
+                    sum + 2f64.powi(-i32::from(rank)),
+                    zeros + (rank == 0) as usize,
+                )
+            });
+
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let raw = alpha * m * m / sum;
+        // small cardinalities are estimated far better by linear counting
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    /// Returns an empty sketch of the same precision and hasher, which can
+    /// be merged with this one.
+    pub fn empty_clone(&self) -> Self
+    where
+        H: Clone,
+    {
+        Self::with_hasher(self.hash_builder.clone(), self.precision)
+    }
+
+    /// Adds every item of `other` to this sketch.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the sketches differ in precision. The
+    /// sketches must also share their hasher, see
+    /// [`HyperLogLog::empty_clone`], which cannot be checked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sketch::HyperLogLog;
+    ///
+    /// let monday = HyperLogLog::new(12);
+    /// let tuesday = monday.empty_clone();
+    /// monday.insert(&"alice");
+    /// tuesday.insert(&"alice");
+    /// tuesday.insert(&"bob");
+    ///
+    /// monday.merge(&tuesday);
+    /// assert_eq!(monday.estimate(), 2);
+    /// ```
+    pub fn merge(&self, other: &HyperLogLog<T, H>) {
+        if self.precision != other.precision {
+            panic!()
+        }
+        for (register, other) in self.registers.iter().zip(other.registers.iter()) {
+            register.fetch_max(other.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+    }
+
+    /// Removes every item from the sketch.
+    pub fn clear(&self) {
+        for register in self.registers.iter() {
+            register.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HyperLogLog;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_inserts_estimate_within_error() {
+        let sketch = Arc::new(HyperLogLog::new(14));
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let sketch = sketch.clone();
+                thread::spawn(move || {
+                    // every item is inserted by two threads
+                    for item in 0..250_000u64 {
+                        sketch.insert(&((thread / 2) * 250_000 + item));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let estimate = sketch.estimate() as f64;
+        let error = (estimate - 500_000.0).abs() / 500_000.0;
+        assert!(error < 0.05, "estimated {}", estimate);
+    }
+}
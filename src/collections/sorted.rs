@@ -0,0 +1,536 @@
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+
+use crate::collections::utils::random;
+
+/// Highest level a node of the skip list can be linked at.
+const MAX_LEVEL: usize = 24;
+
+type Link<K, V> = RwLock<Option<Arc<Node<K, V>>>>;
+
+/// A node of the skip list. The head sentinel is the only node without an
+/// entry.
+struct Node<K, V> {
+    entry: Option<(K, RwLock<V>)>,
+    // `next[level]` is the successor of the node at `level`
+    next: Vec<Link<K, V>>,
+    // held while linking nodes after this one, or while marking it
+    lock: Mutex<()>,
+    // set once the node is logically removed
+    marked: AtomicBool,
+    // set once the node is linked at every one of its levels
+    fully_linked: AtomicBool,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(entry: Option<(K, V)>, next: Vec<Option<Arc<Node<K, V>>>>) -> Self {
+        Node {
+            entry: entry.map(|(key, value)| (key, RwLock::new(value))),
+            next: next.into_iter().map(RwLock::new).collect(),
+            lock: Mutex::new(()),
+            marked: AtomicBool::new(false),
+            fully_linked: AtomicBool::new(false),
+        }
+    }
+
+    fn key(&self) -> &K {
+        &self.entry.as_ref().unwrap().0
+    }
+
+    fn value(&self) -> V
+    where
+        V: Clone,
+    {
+        self.entry.as_ref().unwrap().1.read().unwrap().clone()
+    }
+
+    fn top_level(&self) -> usize {
+        self.next.len() - 1
+    }
+
+    fn next(&self, level: usize) -> Option<Arc<Node<K, V>>> {
+        self.next[level].read().unwrap().clone()
+    }
+
+    fn is_live(&self) -> bool {
+        self.fully_linked.load(Ordering::SeqCst) && !self.marked.load(Ordering::SeqCst)
+    }
+}
+
+/// Predecessors and successors of a key at every level of the skip list.
+struct Position<K, V> {
+    preds: Vec<Arc<Node<K, V>>>,
+    succs: Vec<Option<Arc<Node<K, V>>>>,
+    // highest level at which a node with the key was found
+    found: Option<usize>,
+}
+
+/// Thread-Safe ordered map implemented as a skip list.
+///
+/// The skip list follows the lazy synchronization scheme: lookups and
+/// iteration never lock nodes, while writers only lock the handful of nodes
+/// directly preceding the entry they modify. Removed entries are first
+/// marked, then unlinked, so readers racing with a removal either observe
+/// the entry or skip it, but never lose track of the rest of the list.
+///
+/// Unlike [`Map`], entries are kept in key order, enabling [`range`] scans
+/// and ordered iteration.
+///
+/// [`Map`]: crate::collections::map::Map
+/// [`range`]: SortedMap::range
+pub struct SortedMap<K, V> {
+    head: Arc<Node<K, V>>,
+    len: AtomicUsize,
+}
+
+impl<K, V> Default for SortedMap<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> SortedMap<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    /// Creates an empty `SortedMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::sorted::SortedMap;
+    /// let map: SortedMap<&str, i32> = SortedMap::new();
+    /// ```
+    pub fn new() -> Self {
+        let head = Node::new(None, vec![None; MAX_LEVEL + 1]);
+        head.fully_linked.store(true, Ordering::SeqCst);
+        SortedMap {
+            head: Arc::new(head),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Finds the predecessors and successors of `key` at every level.
+    fn find(&self, key: &K) -> Position<K, V> {
+        let mut preds = vec![Arc::clone(&self.head); MAX_LEVEL + 1];
+        let mut succs = vec![None; MAX_LEVEL + 1];
+        let mut found = None;
+
+        let mut pred = Arc::clone(&self.head);
+        for level in (0..=MAX_LEVEL).rev() {
+            let mut curr = pred.next(level);
+            while let Some(node) = curr.as_ref().filter(|node| node.key() < key) {
+                pred = Arc::clone(node);
+                curr = pred.next(level);
+            }
+            if found.is_none() && curr.as_ref().is_some_and(|node| node.key() == key) {
+                found = Some(level);
+            }
+            preds[level] = Arc::clone(&pred);
+            succs[level] = curr;
+        }
+
+        Position {
+            preds,
+            succs,
+            found,
+        }
+    }
+
+    /// Locks the distinct predecessors of levels `0..=top`, bottom-up.
+    ///
+    /// Predecessors at higher levels never come after those at lower levels,
+    /// so every writer locks nodes in descending key order and writers cannot
+    /// deadlock.
+    fn lock_preds(preds: &[Arc<Node<K, V>>], top: usize) -> Vec<MutexGuard<'_, ()>> {
+        let mut guards = Vec::new();
+        let mut last: Option<&Arc<Node<K, V>>> = None;
+        for pred in &preds[..=top] {
+            if !last.is_some_and(|last| Arc::ptr_eq(last, pred)) {
+                guards.push(pred.lock.lock().unwrap());
+                last = Some(pred);
+            }
+        }
+        guards
+    }
+
+    /// Establishes a key value mapping for the key value pair.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `key` was not mapped before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// assert!(map.put(&"b", 2));
+    /// assert!(!map.put(&"b", 3));
+    /// assert_eq!(map.get(&"b"), Some(3));
+    /// ```
+    pub fn put(&self, key: &K, value: V) -> bool {
+        let top = random_level();
+        loop {
+            let Position {
+                preds,
+                succs,
+                found,
+            } = self.find(key);
+
+            if let Some(level) = found {
+                let node = succs[level].as_ref().unwrap();
+                if !node.marked.load(Ordering::SeqCst) {
+                    while !node.fully_linked.load(Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                    *node.entry.as_ref().unwrap().1.write().unwrap() = value;
+                    return false;
+                }
+                // the node is being removed, retry once it is unlinked
+                thread::yield_now();
+                continue;
+            }
+
+            let _guards = Self::lock_preds(&preds, top);
+            let valid = (0..=top).all(|level| {
+                let pred = &preds[level];
+                let succ = &succs[level];
+                !pred.marked.load(Ordering::SeqCst)
+                    && succ
+                        .as_ref()
+                        .is_none_or(|succ| !succ.marked.load(Ordering::SeqCst))
+                    && same_node(&pred.next(level), succ)
+            });
+            if !valid {
+                continue;
+            }
+
+            let node = Arc::new(Node::new(
+                Some((key.clone(), value)),
+                succs[..=top].to_vec(),
+            ));
+            for (level, pred) in preds[..=top].iter().enumerate() {
+                *pred.next[level].write().unwrap() = Some(Arc::clone(&node));
+            }
+            node.fully_linked.store(true, Ordering::SeqCst);
+            self.len.fetch_add(1, Ordering::SeqCst);
+            return true;
+        }
+    }
+
+    /// Returns the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.get(&1), Some('a'));
+    /// assert_eq!(map.get(&2), None);
+    /// ```
+    pub fn get(&self, key: &K) -> Option<V> {
+        let Position { succs, found, .. } = self.find(key);
+        found
+            .and_then(|level| succs[level].clone())
+            .filter(|node| node.is_live())
+            .map(|node| node.value())
+    }
+
+    /// Returns `true` if the `SortedMap` contains a mapping for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        let Position { succs, found, .. } = self.find(key);
+        found
+            .and_then(|level| succs[level].as_ref().map(|node| node.is_live()))
+            .unwrap_or(false)
+    }
+
+    /// Removes the mapping for `key`, returning its value if it was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.remove(&1), Some('a'));
+    /// assert_eq!(map.remove(&1), None);
+    /// ```
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut victim: Option<Arc<Node<K, V>>> = None;
+        loop {
+            let Position {
+                preds,
+                succs,
+                found,
+            } = self.find(key);
+
+            let node = match &victim {
+                Some(node) => Arc::clone(node),
+                None => {
+                    let node = found.and_then(|level| succs[level].clone())?;
+                    if !node.fully_linked.load(Ordering::SeqCst)
+                        || node.top_level() != found.unwrap()
+                    {
+                        // still being linked in, so it was never observable
+                        return None;
+                    }
+                    {
+                        // marking under the node's lock waits out writers
+                        // linking new nodes right after it
+                        let _lock = node.lock.lock().unwrap();
+                        if node.marked.swap(true, Ordering::SeqCst) {
+                            return None;
+                        }
+                    }
+                    victim = Some(Arc::clone(&node));
+                    node
+                }
+            };
+
+            let top = node.top_level();
+            let _guards = Self::lock_preds(&preds, top);
+            let valid = (0..=top).all(|level| {
+                let pred = &preds[level];
+                !pred.marked.load(Ordering::SeqCst)
+                    && same_node(&pred.next(level), &Some(Arc::clone(&node)))
+            });
+            if !valid {
+                continue;
+            }
+
+            for level in (0..=top).rev() {
+                *preds[level].next[level].write().unwrap() = node.next(level);
+            }
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return Some(node.value());
+        }
+    }
+
+    /// Returns the entry with the smallest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// map.put(&2, 'b');
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.first(), Some((1, 'a')));
+    /// ```
+    pub fn first(&self) -> Option<(K, V)> {
+        self.iter().next()
+    }
+
+    /// Returns the entry with the largest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// map.put(&2, 'b');
+    /// map.put(&1, 'a');
+    /// assert_eq!(map.last(), Some((2, 'b')));
+    /// ```
+    pub fn last(&self) -> Option<(K, V)> {
+        loop {
+            let mut pred = Arc::clone(&self.head);
+            for level in (0..=MAX_LEVEL).rev() {
+                while let Some(next) = pred.next(level) {
+                    pred = next;
+                }
+            }
+            // only the head has no entry, in which case the map is empty
+            pred.entry.as_ref()?;
+            if pred.is_live() {
+                return Some((pred.key().clone(), pred.value()));
+            }
+            // the last node is being linked or unlinked, wait for it to settle
+            thread::yield_now();
+        }
+    }
+
+    /// Returns an iterator over the entries with keys within `range`, in
+    /// ascending key order.
+    ///
+    /// The iterator does not lock the `SortedMap`. Entries present for the
+    /// whole duration of the iteration are always yielded, entries inserted
+    /// or removed concurrently may or may not be.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::sorted::SortedMap;
+    ///
+    /// let map = SortedMap::new();
+    /// for i in 0..10 {
+    ///     map.put(&i, i * 10);
+    /// }
+    ///
+    /// let keys: Vec<_> = map.range(3..6).map(|(key, _)| key).collect();
+    /// assert_eq!(keys, vec![3, 4, 5]);
+    /// ```
+    pub fn range<R>(&self, range: R) -> Range<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let next = match range.start_bound() {
+            Bound::Unbounded => self.head.next(0),
+            Bound::Included(start) => {
+                let Position { succs, .. } = self.find(start);
+                succs[0].clone()
+            }
+            Bound::Excluded(start) => {
+                let Position { succs, .. } = self.find(start);
+                let mut next = succs[0].clone();
+                while let Some(node) = next.as_ref().filter(|node| node.key() <= start) {
+                    next = node.next(0);
+                }
+                next
+            }
+        };
+
+        Range {
+            next,
+            end: match range.end_bound() {
+                Bound::Included(end) => Bound::Included(end.clone()),
+                Bound::Excluded(end) => Bound::Excluded(end.clone()),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+            _map: self,
+        }
+    }
+
+    /// Returns an iterator over all entries, in ascending key order.
+    ///
+    /// See [`SortedMap::range`] for what is observed of concurrent
+    /// modifications.
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Returns the number of entries in the `SortedMap`.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `SortedMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Drop for SortedMap<K, V> {
+    fn drop(&mut self) {
+        // unlink nodes one by one, dropping the list recursively could
+        // overflow the stack
+        for level in 1..=MAX_LEVEL {
+            self.head.next[level].write().unwrap().take();
+        }
+        let mut next = self.head.next[0].write().unwrap().take();
+        while let Some(node) = next {
+            for level in 1..node.next.len() {
+                node.next[level].write().unwrap().take();
+            }
+            next = node.next[0].write().unwrap().take();
+        }
+    }
+}
+
+/// An iterator over a range of entries of a [`SortedMap`], see
+/// [`SortedMap::range`].
+pub struct Range<'a, K, V> {
+    next: Option<Arc<Node<K, V>>>,
+    end: Bound<K>,
+    _map: &'a SortedMap<K, V>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.next.take() {
+            let in_range = match &self.end {
+                Bound::Included(end) => node.key() <= end,
+                Bound::Excluded(end) => node.key() < end,
+                Bound::Unbounded => true,
+            };
+            if !in_range {
+                return None;
+            }
+            self.next = node.next(0);
+            if node.is_live() {
+                return Some((node.key().clone(), node.value()));
+            }
+        }
+        None
+    }
+}
+
+fn same_node<K, V>(a: &Option<Arc<Node<K, V>>>, b: &Option<Arc<Node<K, V>>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Picks the top level of a new node, level `l` with probability `2^-(l+1)`.
+fn random_level() -> usize {
+    (random().trailing_zeros() as usize).min(MAX_LEVEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::SortedMap;
+
+    #[test]
+    fn test_concurrent_put_and_remove_keep_order() {
+        let map = Arc::new(SortedMap::new());
+
+        let writers: Vec<_> = (0..4)
+            .map(|worker| {
+                let m = Arc::clone(&map);
+                std::thread::spawn(move || {
+                    for i in 0..500 {
+                        let key = i * 4 + worker;
+                        m.put(&key, key);
+                        if key % 3 == 0 {
+                            assert_eq!(m.remove(&key), Some(key));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let keys: Vec<_> = map.iter().map(|(key, _)| key).collect();
+        let expected: Vec<_> = (0..2000).filter(|key| key % 3 != 0).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(map.len(), expected.len());
+        assert_eq!(map.last(), Some((1999, 1999)));
+    }
+}
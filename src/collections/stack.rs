@@ -0,0 +1,140 @@
+use crate::collections::utils::{Acquire, LockPolicy, PriorityRwLock};
+
+/// Thread-Safe LIFO stack.
+///
+/// Elements are pushed and popped under a write lock and peeked at under a
+/// read lock, acquired according to the stack's [`LockPolicy`]. Critical
+/// sections are a single `Vec` operation, which makes a lock cheaper than a
+/// lock-free list needing deferred reclamation of its nodes.
+pub struct Stack<T> {
+    elements: PriorityRwLock<Vec<T>>,
+    lock_policy: LockPolicy,
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Stack<T> {
+    /// Creates an empty `Stack`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::stack::Stack;
+    /// let stack: Stack<u64> = Stack::new();
+    /// ```
+    pub fn new() -> Self {
+        Stack {
+            elements: PriorityRwLock::new(Vec::new()),
+            // every critical section is tiny, spin on both paths
+            lock_policy: LockPolicy {
+                read: Acquire::Spin { spins: 64 },
+                write: Acquire::Spin { spins: 64 },
+            },
+        }
+    }
+
+    /// Pushes `value` on top of the stack.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::stack::Stack;
+    ///
+    /// let stack = Stack::new();
+    /// stack.push("undo");
+    /// assert_eq!(stack.len(), 1);
+    /// ```
+    pub fn push(&self, value: T) {
+        self.elements.write(self.lock_policy.write).push(value);
+    }
+
+    /// Removes the element on top of the stack, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::stack::Stack;
+    ///
+    /// let stack = Stack::new();
+    /// stack.push(1);
+    /// stack.push(2);
+    /// assert_eq!(stack.pop(), Some(2));
+    /// assert_eq!(stack.pop(), Some(1));
+    /// assert_eq!(stack.pop(), None);
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        self.elements.write(self.lock_policy.write).pop()
+    }
+
+    /// Returns the element on top of the stack without removing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::stack::Stack;
+    ///
+    /// let stack = Stack::new();
+    /// stack.push(1);
+    /// assert_eq!(stack.peek(), Some(1));
+    /// assert_eq!(stack.len(), 1);
+    /// ```
+    pub fn peek(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.elements.read(self.lock_policy.read).last().cloned()
+    }
+
+    /// Returns the number of elements in the stack.
+    pub fn len(&self) -> usize {
+        self.elements.read(self.lock_policy.read).len()
+    }
+
+    /// Returns `true` if the stack contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stack;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_pushes_and_pops_lose_nothing() {
+        let stack = Arc::new(Stack::new());
+
+        let workers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    for i in 0..10_000 {
+                        stack.push(thread * 10_000 + i);
+                        if i % 2 == 0 {
+                            popped.extend(stack.pop());
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut values: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+
+        assert_eq!(stack.len(), 20_000);
+        while let Some(value) = stack.pop() {
+            values.push(value);
+        }
+        values.sort_unstable();
+        assert_eq!(values, (0..40_000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,267 @@
+use std::ops::{Bound, RangeBounds};
+
+use crate::collections::sorted::SortedMap;
+
+/// Thread-Safe store of time series, each a sequence of `(timestamp,
+/// value)` points under a series key.
+///
+/// Points of every series are kept in a single [`SortedMap`] keyed by
+/// series key and timestamp, so the points of a series are adjacent and in
+/// time order, and appends to different series or time windows proceed in
+/// parallel. Timestamps are plain `u64`s in whatever unit the caller picks.
+pub struct Series<V> {
+    points: SortedMap<(String, u64), V>,
+}
+
+impl<V> Default for Series<V>
+where
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts a bound on timestamps to a bound on the keys of `series`.
+fn key_bound(series: &str, bound: Bound<&u64>, unbounded: u64) -> Bound<(String, u64)> {
+    match bound {
+        Bound::Included(timestamp) => Bound::Included((series.to_string(), *timestamp)),
+        Bound::Excluded(timestamp) => Bound::Excluded((series.to_string(), *timestamp)),
+        Bound::Unbounded => Bound::Included((series.to_string(), unbounded)),
+    }
+}
+
+impl<V> Series<V>
+where
+    V: Clone,
+{
+    /// Creates an empty `Series` store.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::timeseries::Series;
+    /// let series: Series<f64> = Series::new();
+    /// ```
+    pub fn new() -> Self {
+        Series {
+            points: SortedMap::new(),
+        }
+    }
+
+    /// Appends the point `(timestamp, value)` to `series`, replacing the
+    /// point at the same timestamp if any.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `series` had no point at `timestamp`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::timeseries::Series;
+    ///
+    /// let series = Series::new();
+    /// assert!(series.append("cpu", 1_000, 0.5));
+    /// assert!(!series.append("cpu", 1_000, 0.7));
+    /// assert_eq!(series.len(), 1);
+    /// ```
+    pub fn append(&self, series: &str, timestamp: u64, value: V) -> bool {
+        self.points.put(&(series.to_string(), timestamp), value)
+    }
+
+    /// Returns the points of `series` with timestamps within `window`, in
+    /// time order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::timeseries::Series;
+    ///
+    /// let series = Series::new();
+    /// for timestamp in 0..10 {
+    ///     series.append("cpu", timestamp, timestamp * 10);
+    /// }
+    /// series.append("mem", 5, 0);
+    ///
+    /// assert_eq!(series.range("cpu", 3..5), vec![(3, 30), (4, 40)]);
+    /// assert_eq!(series.range("mem", ..), vec![(5, 0)]);
+    /// ```
+    pub fn range<R>(&self, series: &str, window: R) -> Vec<(u64, V)>
+    where
+        R: RangeBounds<u64>,
+    {
+        let start = key_bound(series, window.start_bound(), u64::MIN);
+        let end = key_bound(series, window.end_bound(), u64::MAX);
+        self.points
+            .range((start, end))
+            .map(|((_, timestamp), value)| (timestamp, value))
+            .collect()
+    }
+
+    /// Returns the points of `series` within `window` downsampled to one
+    /// point per `interval`.
+    ///
+    /// Points are grouped by the multiple of `interval` their timestamp
+    /// falls after, and every non-empty group is reduced by `aggregate` to
+    /// a point at that multiple.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `interval` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::timeseries::Series;
+    ///
+    /// let series = Series::new();
+    /// for timestamp in 0..25 {
+    ///     series.append("requests", timestamp, 1);
+    /// }
+    ///
+    /// let per_ten = series.downsample("requests", .., 10, |values| values.iter().sum::<u64>());
+    /// assert_eq!(per_ten, vec![(0, 10), (10, 10), (20, 5)]);
+    /// ```
+    pub fn downsample<R, A, F>(
+        &self,
+        series: &str,
+        window: R,
+        interval: u64,
+        aggregate: F,
+    ) -> Vec<(u64, A)>
+    where
+        R: RangeBounds<u64>,
+        F: Fn(&[V]) -> A,
+    {
+        if interval == 0 {
+            panic!()
+        }
+
+        let mut downsampled = Vec::new();
+        let mut group: Option<(u64, Vec<V>)> = None;
+        for (timestamp, value) in self.range(series, window) {
+            let start = timestamp - timestamp % interval;
+            match &mut group {
+                Some((group_start, values)) if *group_start == start => values.push(value),
+                _ => {
+                    if let Some((group_start, values)) = group.replace((start, vec![value])) {
+                        downsampled.push((group_start, aggregate(&values)));
+                    }
+                }
+            }
+        }
+        if let Some((group_start, values)) = group {
+            downsampled.push((group_start, aggregate(&values)));
+        }
+        downsampled
+    }
+
+    /// Removes the points of `series` with timestamps before `cutoff`,
+    /// returning how many were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::timeseries::Series;
+    ///
+    /// let series = Series::new();
+    /// for timestamp in 0..10 {
+    ///     series.append("cpu", timestamp, ());
+    /// }
+    ///
+    /// assert_eq!(series.truncate("cpu", 7), 7);
+    /// assert_eq!(series.len(), 3);
+    /// ```
+    pub fn truncate(&self, series: &str, cutoff: u64) -> usize {
+        let expired: Vec<_> = self
+            .points
+            .range((series.to_string(), u64::MIN)..(series.to_string(), cutoff))
+            .map(|(key, _)| key)
+            .collect();
+        expired
+            .iter()
+            .filter(|key| self.points.remove(key).is_some())
+            .count()
+    }
+
+    /// Removes the points of every series with timestamps before `cutoff`,
+    /// enforcing a retention period ending at `cutoff`. Returns how many
+    /// points were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::timeseries::Series;
+    ///
+    /// let series = Series::new();
+    /// series.append("cpu", 10, ());
+    /// series.append("cpu", 20, ());
+    /// series.append("mem", 15, ());
+    ///
+    /// assert_eq!(series.truncate_all(18), 2);
+    /// assert_eq!(series.range("cpu", ..), vec![(20, ())]);
+    /// ```
+    pub fn truncate_all(&self, cutoff: u64) -> usize {
+        let mut removed = 0;
+        let mut next = self.points.first();
+        while let Some(((series, _), _)) = next {
+            removed += self.truncate(&series, cutoff);
+            // skip to the first point of the next series
+            next = self
+                .points
+                .range((Bound::Excluded((series, u64::MAX)), Bound::Unbounded))
+                .next();
+        }
+        removed
+    }
+
+    /// Returns the number of points of all series.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Returns `true` if no series holds any point.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Series;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_appends_then_retention() {
+        let store = Arc::new(Series::new());
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    // interleaved timestamps, appended out of order
+                    for i in (0..500u64).rev() {
+                        store.append(&format!("host-{}", thread % 2), i * 4 + thread, i);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(store.len(), 2000);
+        let points = store.range("host-1", ..);
+        assert_eq!(points.len(), 1000);
+        assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let counts = store.downsample("host-0", 0..400, 100, |values| values.len());
+        assert_eq!(counts, vec![(0, 50), (100, 50), (200, 50), (300, 50)]);
+
+        assert_eq!(store.truncate_all(1000), 1000);
+        assert!(store.range("host-0", ..1000).is_empty());
+        assert_eq!(store.len(), 1000);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+struct Node<V> {
+    value: RwLock<Option<V>>,
+    children: RwLock<BTreeMap<u8, Arc<Node<V>>>>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Self {
+        Node {
+            value: RwLock::new(None),
+            children: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn child(&self, byte: u8) -> Option<Arc<Node<V>>> {
+        self.children.read().unwrap().get(&byte).cloned()
+    }
+
+    fn child_or_insert(&self, byte: u8) -> Arc<Node<V>> {
+        if let Some(child) = self.child(byte) {
+            return child;
+        }
+        let mut children = self.children.write().unwrap();
+        Arc::clone(
+            children
+                .entry(byte)
+                .or_insert_with(|| Arc::new(Node::new())),
+        )
+    }
+}
+
+/// Thread-Safe map keyed by byte strings, supporting prefix scans.
+///
+/// Keys are stored in a trie with one node per byte. Every node has its own
+/// locks, so operations on keys diverging early never contend, and readers
+/// only take shared locks. Nodes are never unlinked: removing a key clears
+/// its value but keeps the path to it, on the assumption that hierarchical
+/// keyspaces reuse their prefixes.
+pub struct PrefixMap<V> {
+    root: Arc<Node<V>>,
+    len: AtomicUsize,
+}
+
+impl<V> Default for PrefixMap<V>
+where
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> PrefixMap<V>
+where
+    V: Clone,
+{
+    /// Creates an empty `PrefixMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::trie::PrefixMap;
+    /// let map: PrefixMap<u64> = PrefixMap::new();
+    /// ```
+    pub fn new() -> Self {
+        PrefixMap {
+            root: Arc::new(Node::new()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn find(&self, key: &[u8]) -> Option<Arc<Node<V>>> {
+        key.iter()
+            .try_fold(Arc::clone(&self.root), |node, byte| node.child(*byte))
+    }
+
+    /// Maps `key` to `value`, returning the value previously mapped to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::trie::PrefixMap;
+    ///
+    /// let map = PrefixMap::new();
+    /// assert_eq!(map.insert("users/alice", 1), None);
+    /// assert_eq!(map.insert("users/alice", 2), Some(1));
+    /// ```
+    pub fn insert<K: AsRef<[u8]>>(&self, key: K, value: V) -> Option<V> {
+        let node = key
+            .as_ref()
+            .iter()
+            .fold(Arc::clone(&self.root), |node, byte| {
+                node.child_or_insert(*byte)
+            });
+        let previous = node.value.write().unwrap().replace(value);
+        if previous.is_none() {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        previous
+    }
+
+    /// Returns the value mapped to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::trie::PrefixMap;
+    ///
+    /// let map = PrefixMap::new();
+    /// map.insert("users/alice", 1);
+    /// assert_eq!(map.get("users/alice"), Some(1));
+    /// assert_eq!(map.get("users"), None);
+    /// ```
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<V> {
+        self.find(key.as_ref())?.value.read().unwrap().clone()
+    }
+
+    /// Returns `true` if `key` is mapped to a value.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.find(key.as_ref())
+            .is_some_and(|node| node.value.read().unwrap().is_some())
+    }
+
+    /// Removes the mapping of `key`, returning its value.
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Option<V> {
+        let removed = self.find(key.as_ref())?.value.write().unwrap().take();
+        if removed.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix`,
+    /// in ascending byte order of the keys.
+    ///
+    /// The iterator does not lock the `PrefixMap`. Entries present for the
+    /// whole duration of the iteration are always yielded, entries inserted
+    /// or removed concurrently may or may not be.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::trie::PrefixMap;
+    ///
+    /// let map = PrefixMap::new();
+    /// map.insert("topics/db/writes", 1);
+    /// map.insert("topics/db/reads", 2);
+    /// map.insert("topics/net", 3);
+    ///
+    /// let keys: Vec<_> = map
+    ///     .iter_prefix("topics/db/")
+    ///     .map(|(key, _)| String::from_utf8(key).unwrap())
+    ///     .collect();
+    /// assert_eq!(keys, vec!["topics/db/reads", "topics/db/writes"]);
+    /// ```
+    pub fn iter_prefix<K: AsRef<[u8]>>(&self, prefix: K) -> PrefixIter<V> {
+        let prefix = prefix.as_ref();
+        let stack = match self.find(prefix) {
+            Some(node) => vec![(prefix.to_vec(), node)],
+            None => Vec::new(),
+        };
+        PrefixIter { stack }
+    }
+
+    /// Returns an iterator over all entries, in ascending byte order of the
+    /// keys. See [`PrefixMap::iter_prefix`].
+    pub fn iter(&self) -> PrefixIter<V> {
+        self.iter_prefix([])
+    }
+
+    /// Returns the number of entries in the `PrefixMap`.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the `PrefixMap` contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An iterator over the entries of a [`PrefixMap`] under a prefix, see
+/// [`PrefixMap::iter_prefix`].
+pub struct PrefixIter<V> {
+    stack: Vec<(Vec<u8>, Arc<Node<V>>)>,
+}
+
+impl<V> Iterator for PrefixIter<V>
+where
+    V: Clone,
+{
+    type Item = (Vec<u8>, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            // push children in reverse so that the smallest is visited next
+            for (byte, child) in node.children.read().unwrap().iter().rev() {
+                let mut child_key = key.clone();
+                child_key.push(*byte);
+                self.stack.push((child_key, Arc::clone(child)));
+            }
+            if let Some(value) = node.value.read().unwrap().clone() {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+impl<V> Drop for PrefixMap<V> {
+    fn drop(&mut self) {
+        // unlink nodes iteratively, recursive drops of long keys could
+        // overflow the stack
+        let mut nodes = vec![Arc::clone(&self.root)];
+        while let Some(node) = nodes.pop() {
+            let children = std::mem::take(&mut *node.children.write().unwrap());
+            nodes.extend(children.into_values());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefixMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_inserts_under_shared_prefixes() {
+        let map = Arc::new(PrefixMap::new());
+
+        let writers: Vec<_> = (0..4)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        map.insert(format!("tenants/{}/keys/{:04}", thread, i), i);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(map.len(), 2000);
+        let keys: Vec<_> = map.iter_prefix("tenants/2/").map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), 500);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::collections::map::{Map, MapBuilder};
+
+/// Thread-Safe map implemented as hash table, where every entry expires
+/// after a time to live.
+///
+/// Expired entries are invisible to lookups right away. Their memory is
+/// reclaimed lazily, when a lookup comes across them, and by
+/// [`TtlMap::purge_expired`], which a [`Reaper`] runs periodically.
+pub struct TtlMap<K, V, H = RandomState> {
+    map: Map<K, (V, Instant), H>,
+}
+
+impl<K, V> Default for TtlMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> TtlMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Creates an empty `TtlMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ttl::TtlMap;
+    /// let map: TtlMap<String, u64> = TtlMap::new();
+    /// ```
+    pub fn new() -> Self {
+        TtlMap { map: Map::new() }
+    }
+}
+
+impl<K, V, H> TtlMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `TtlMap` configured by `builder`.
+    pub fn with_builder(builder: MapBuilder<H>) -> Self {
+        TtlMap {
+            map: builder.build(),
+        }
+    }
+
+    /// Establishes a key value mapping expiring after `ttl`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ttl::TtlMap;
+    /// use std::time::Duration;
+    ///
+    /// let map = TtlMap::new();
+    /// map.put(&"session", 42, Duration::from_secs(60));
+    /// assert_eq!(map.get(&"session"), Some(42));
+    ///
+    /// map.put(&"session", 42, Duration::ZERO);
+    /// assert_eq!(map.get(&"session"), None);
+    /// ```
+    pub fn put(&self, key: &K, value: V, ttl: Duration) {
+        self.map.put(key, (value, Instant::now() + ttl));
+    }
+
+    /// Resets the time to live of the entry of `key` to `ttl`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `key` was mapped to a value that had not expired yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ttl::TtlMap;
+    /// use std::time::Duration;
+    ///
+    /// let map = TtlMap::new();
+    /// map.put(&"session", 42, Duration::from_secs(60));
+    ///
+    /// assert!(map.expire(&"session", Duration::from_secs(3600)));
+    /// assert!(map.ttl(&"session").unwrap() > Duration::from_secs(60));
+    /// ```
+    pub fn expire(&self, key: &K, ttl: Duration) -> bool {
+        self.map
+            .with_keys_locked(std::slice::from_ref(key), |locked| match locked.get(key) {
+                Some((value, deadline)) if deadline > Instant::now() => {
+                    locked.put(key, (value, Instant::now() + ttl));
+                    true
+                }
+                _ => false,
+            })
+    }
+
+    /// Unmaps `key` if its entry has expired by `now`.
+    fn unmap_if_expired(&self, key: &K, now: Instant) -> bool {
+        self.map
+            .with_keys_locked(std::slice::from_ref(key), |locked| match locked.get(key) {
+                Some((_, deadline)) if deadline <= now => {
+                    locked.unmap(key);
+                    true
+                }
+                _ => false,
+            })
+    }
+
+    /// Returns the value mapped to `key`, unless it has expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let (value, deadline) = self.map.get(key)?;
+        let now = Instant::now();
+        if deadline <= now {
+            self.unmap_if_expired(key, now);
+            return None;
+        }
+        Some(value)
+    }
+
+    /// Returns the time left until the entry of `key` expires.
+    pub fn ttl(&self, key: &K) -> Option<Duration> {
+        let (_, deadline) = self.map.get(key)?;
+        deadline.checked_duration_since(Instant::now())
+    }
+
+    /// Removes the entry of `key`, returning its value unless it has
+    /// expired.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let (value, deadline) = self.map.remove(key)?;
+        if deadline <= Instant::now() {
+            return None;
+        }
+        Some(value)
+    }
+
+    /// Unmaps every expired entry.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries unmapped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ttl::TtlMap;
+    /// use std::time::Duration;
+    ///
+    /// let map = TtlMap::new();
+    /// map.put(&1, 'a', Duration::ZERO);
+    /// map.put(&2, 'b', Duration::from_secs(60));
+    ///
+    /// assert_eq!(map.purge_expired(), 1);
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<K> = self
+            .map
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(key, _)| key)
+            .collect();
+        expired
+            .iter()
+            .filter(|key| self.unmap_if_expired(key, now))
+            .count()
+    }
+
+    /// Returns the number of entries in the `TtlMap`, including expired
+    /// entries which have not been purged yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the `TtlMap` contains no entries, expired or not.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// Thread-Safe set implemented as hash table, where membership of every
+/// item lapses after a time to live.
+///
+/// A sliding set refreshes the time to live of an item whenever it is
+/// found by [`ExpiringSet::contains`] or inserted again, so the item only
+/// lapses after going unused for its time to live. A fixed set keeps the
+/// deadline set by the first insertion. The former suits idle timeouts, the
+/// latter dedup windows and rate-limiting keys.
+pub struct ExpiringSet<T, H = RandomState> {
+    items: TtlMap<T, Duration, H>,
+    sliding: bool,
+}
+
+impl<T> Default for ExpiringSet<T, RandomState>
+where
+    T: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ExpiringSet<T, RandomState>
+where
+    T: Hash + Eq + Clone,
+{
+    /// Creates an empty `ExpiringSet` with fixed deadlines.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ttl::ExpiringSet;
+    /// let set: ExpiringSet<String> = ExpiringSet::new();
+    /// ```
+    pub fn new() -> Self {
+        ExpiringSet {
+            items: TtlMap::new(),
+            sliding: false,
+        }
+    }
+
+    /// Creates an empty `ExpiringSet` refreshing the time to live of items
+    /// on access.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::ttl::ExpiringSet;
+    /// let set: ExpiringSet<String> = ExpiringSet::sliding();
+    /// ```
+    pub fn sliding() -> Self {
+        ExpiringSet {
+            items: TtlMap::new(),
+            sliding: true,
+        }
+    }
+}
+
+impl<T, H> ExpiringSet<T, H>
+where
+    T: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `ExpiringSet` configured by `builder`, sliding if
+    /// `sliding` is `true`.
+    pub fn with_builder(builder: MapBuilder<H>, sliding: bool) -> Self {
+        ExpiringSet {
+            items: TtlMap::with_builder(builder),
+            sliding,
+        }
+    }
+
+    /// Adds `item` to the set for `ttl`.
+    ///
+    /// An item already in the set keeps its time to live, refreshed if the
+    /// set is sliding.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `item` was not in the set, or had expired.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ttl::ExpiringSet;
+    /// use std::time::Duration;
+    ///
+    /// let seen = ExpiringSet::new();
+    /// assert!(seen.insert(&"request-1", Duration::from_secs(60)));
+    /// assert!(!seen.insert(&"request-1", Duration::from_secs(60)));
+    /// ```
+    pub fn insert(&self, item: &T, ttl: Duration) -> bool {
+        let now = Instant::now();
+        self.items
+            .map
+            .with_keys_locked(std::slice::from_ref(item), |locked| {
+                match locked.get(item) {
+                    Some((item_ttl, deadline)) if deadline > now => {
+                        if self.sliding {
+                            locked.put(item, (item_ttl, now + item_ttl));
+                        }
+                        false
+                    }
+                    _ => {
+                        locked.put(item, (ttl, now + ttl));
+                        true
+                    }
+                }
+            })
+    }
+
+    /// Returns `true` if `item` is in the set and has not expired,
+    /// refreshing its time to live if the set is sliding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::ttl::ExpiringSet;
+    /// use std::time::Duration;
+    ///
+    /// let set = ExpiringSet::sliding();
+    /// set.insert(&"client", Duration::from_millis(100));
+    /// for _ in 0..4 {
+    ///     std::thread::sleep(Duration::from_millis(40));
+    ///     assert!(set.contains(&"client"));
+    /// }
+    ///
+    /// std::thread::sleep(Duration::from_millis(150));
+    /// assert!(!set.contains(&"client"));
+    /// ```
+    pub fn contains(&self, item: &T) -> bool {
+        match self.items.get(item) {
+            Some(ttl) if self.sliding => self.items.expire(item, ttl),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the time left until `item` expires.
+    pub fn ttl(&self, item: &T) -> Option<Duration> {
+        self.items.ttl(item)
+    }
+
+    /// Removes `item` from the set.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `item` was in the set and had not expired.
+    pub fn remove(&self, item: &T) -> bool {
+        self.items.remove(item).is_some()
+    }
+
+    /// Removes every expired item.
+    ///
+    /// # Returns
+    ///
+    /// The number of items removed.
+    pub fn purge_expired(&self) -> usize {
+        self.items.purge_expired()
+    }
+
+    /// Returns the number of items in the set, including expired items
+    /// which have not been purged yet.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the set contains no items, expired or not.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Background thread running [`TtlMap::purge_expired`] on a map every
+/// `interval`.
+///
+/// The reaper only holds a weak reference to the map and exits once the map
+/// is dropped. Dropping the reaper stops the thread and waits for it to
+/// exit.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::collections::ttl::{Reaper, TtlMap};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let map = Arc::new(TtlMap::new());
+/// let reaper = Reaper::spawn(&map, Duration::from_millis(100));
+///
+/// map.put(&1, "one", Duration::from_secs(1));
+///
+/// drop(reaper);
+/// ```
+pub struct Reaper {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Reaper {
+    /// Spawns a thread purging expired entries of `map`.
+    pub fn spawn<K, V, H>(map: &Arc<TtlMap<K, V, H>>, interval: Duration) -> Self
+    where
+        K: Hash + Eq + Clone + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+        H: BuildHasher + Send + Sync + 'static,
+    {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let map = Arc::downgrade(map);
+        let thread_stopped = Arc::clone(&stopped);
+
+        let handle = thread::spawn(move || {
+            let (lock, condvar) = &*thread_stopped;
+            loop {
+                let guard = lock.lock().unwrap();
+                let (guard, _) = condvar
+                    .wait_timeout_while(guard, interval, |stopped| !*stopped)
+                    .unwrap();
+                if *guard {
+                    return;
+                }
+                drop(guard);
+
+                match map.upgrade() {
+                    Some(map) => map.purge_expired(),
+                    None => return,
+                };
+            }
+        });
+
+        Reaper {
+            stopped,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        condvar.notify_all();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExpiringSet, Reaper, TtlMap};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reaper_purges_expired_entries() {
+        let map = Arc::new(TtlMap::new());
+        let _reaper = Reaper::spawn(&map, Duration::from_millis(10));
+
+        for key in 0..100u64 {
+            let ttl = if key % 2 == 0 {
+                Duration::from_millis(20)
+            } else {
+                Duration::from_secs(60)
+            };
+            map.put(&key, key, ttl);
+        }
+
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(map.len(), 50);
+        assert!((0..100u64).all(|key| map.get(&key).is_some() == (key % 2 == 1)));
+    }
+
+    #[test]
+    fn test_expiring_set_admits_each_item_once_per_window() {
+        let set = Arc::new(ExpiringSet::new());
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let set = set.clone();
+                thread::spawn(move || {
+                    (0..1000u64)
+                        .filter(|item| set.insert(item, Duration::from_secs(60)))
+                        .count()
+                })
+            })
+            .collect();
+        let admitted: usize = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum();
+
+        assert_eq!(admitted, 1000);
+        assert_eq!(set.len(), 1000);
+        assert!(set.insert(&1000, Duration::ZERO));
+        assert_eq!(set.purge_expired(), 1);
+        assert!(!set.contains(&1000));
+    }
+}
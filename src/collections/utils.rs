@@ -0,0 +1,251 @@
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hint;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::thread;
+use std::time::Duration;
+
+pub enum LockWrapper<'a, T> {
+    Read(RwLockReadGuard<'a, T>),
+    Write(RwLockWriteGuard<'a, T>),
+}
+
+impl<'a, T> Deref for LockWrapper<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            LockWrapper::Read(read_guard) => read_guard.deref(),
+            LockWrapper::Write(write_guard) => write_guard.deref(),
+        }
+    }
+}
+
+impl<'a, T> DerefMut for LockWrapper<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            LockWrapper::Write(write_gaurd) => write_gaurd.deref_mut(),
+            LockWrapper::Read(_) => panic!(),
+        }
+    }
+}
+
+/// Strategy used to acquire a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acquire {
+    /// Park the thread until the lock becomes available.
+    Block,
+    /// Busy-wait for the lock, retrying up to `spins` times before falling
+    /// back to blocking. Worthwhile when the critical sections contending
+    /// for the lock are tiny.
+    Spin { spins: u32 },
+}
+
+/// Lock acquisition strategies used by a [`Map`], configured separately for
+/// the read path (`get` and friends) and the write path (`put`, `unmap`).
+///
+/// The default policy blocks on both paths.
+///
+/// [`Map`]: crate::collections::map::Map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockPolicy {
+    /// Strategy of `get` and the other operations reading a bucket.
+    pub read: Acquire,
+    /// Strategy of `put`, `unmap` and the other operations writing a bucket.
+    pub write: Acquire,
+}
+
+impl LockPolicy {
+    /// Spins on the read path and blocks on the write path.
+    ///
+    /// Reads only hold the lock long enough to clone a value, while writes
+    /// may have to grow the bucket, so spinning only pays off for reads.
+    pub fn hybrid() -> Self {
+        LockPolicy {
+            read: Acquire::Spin { spins: 64 },
+            write: Acquire::Block,
+        }
+    }
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        LockPolicy {
+            read: Acquire::Block,
+            write: Acquire::Block,
+        }
+    }
+}
+
+/// Acquires a shared lock on `lock` using the given strategy.
+pub fn read_lock<T>(lock: &RwLock<T>, acquire: Acquire) -> RwLockReadGuard<'_, T> {
+    if let Acquire::Spin { spins } = acquire {
+        for _ in 0..spins {
+            match lock.try_read() {
+                Ok(guard) => return guard,
+                Err(TryLockError::WouldBlock) => hint::spin_loop(),
+                Err(TryLockError::Poisoned(_)) => break,
+            }
+        }
+    }
+    lock.read().unwrap()
+}
+
+/// Acquires an exclusive lock on `lock` using the given strategy.
+pub fn write_lock<T>(lock: &RwLock<T>, acquire: Acquire) -> RwLockWriteGuard<'_, T> {
+    if let Acquire::Spin { spins } = acquire {
+        for _ in 0..spins {
+            match lock.try_write() {
+                Ok(guard) => return guard,
+                Err(TryLockError::WouldBlock) => hint::spin_loop(),
+                Err(TryLockError::Poisoned(_)) => break,
+            }
+        }
+    }
+    lock.write().unwrap()
+}
+
+/// A [`RwLock`] that keeps count of the foreground threads queued on it,
+/// so that maintenance work can step aside for user operations.
+pub struct PriorityRwLock<T> {
+    lock: RwLock<T>,
+    queued: AtomicUsize,
+}
+
+impl<T> PriorityRwLock<T> {
+    /// Number of times a maintenance acquisition backs off before it stops
+    /// yielding and queues up like any foreground operation.
+    const MAX_BACKOFFS: u32 = 10;
+
+    pub fn new(data: T) -> Self {
+        PriorityRwLock {
+            lock: RwLock::new(data),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquires a shared lock at foreground priority.
+    pub fn read(&self, acquire: Acquire) -> RwLockReadGuard<'_, T> {
+        if let Ok(guard) = self.lock.try_read() {
+            return guard;
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let guard = read_lock(&self.lock, acquire);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
+
+    /// Acquires an exclusive lock at foreground priority.
+    pub fn write(&self, acquire: Acquire) -> RwLockWriteGuard<'_, T> {
+        if let Ok(guard) = self.lock.try_write() {
+            return guard;
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let guard = write_lock(&self.lock, acquire);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
+
+    /// Acquires an exclusive lock only if it is immediately available.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.lock.try_write().ok()
+    }
+
+    /// Acquires an exclusive lock at maintenance priority.
+    ///
+    /// The lock is only taken while no foreground operation is queued on it.
+    /// Otherwise the caller backs off for exponentially growing intervals,
+    /// and after [`Self::MAX_BACKOFFS`] attempts blocks for the lock so that
+    /// maintenance cannot be starved forever under sustained traffic.
+    pub fn maintenance_write(&self) -> RwLockWriteGuard<'_, T> {
+        let mut backoff = Duration::from_micros(50);
+        for _ in 0..Self::MAX_BACKOFFS {
+            if self.queued.load(Ordering::SeqCst) == 0 {
+                if let Ok(guard) = self.lock.try_write() {
+                    return guard;
+                }
+            }
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+        self.lock.write().unwrap()
+    }
+}
+
+/// Finalizer of the SplitMix64 generator, spreading every input bit over the
+/// whole output.
+pub fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Returns a pseudo-random `u64` from a per-thread xorshift generator. Fast
+/// and good enough for randomized data structures, not for anything else.
+pub fn random() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(thread::current().id()) | 1);
+    }
+    STATE.with(|state| {
+        // xorshift64
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_lock, write_lock, Acquire, PriorityRwLock};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_spinning_acquires_locks_released_meanwhile() {
+        let lock = Arc::new(RwLock::new(0));
+        let spin = Acquire::Spin { spins: u32::MAX };
+        *write_lock(&lock, spin) += 1;
+        assert_eq!(*read_lock(&lock, spin), 1);
+
+        // spinning for as long as it takes, the lock is acquired without
+        // ever blocking on it
+        let held = Arc::new(AtomicBool::new(false));
+        let writer = thread::spawn({
+            let (lock, held) = (lock.clone(), held.clone());
+            move || {
+                let mut guard = lock.write().unwrap();
+                held.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                *guard += 1;
+            }
+        });
+        while !held.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        assert_eq!(*read_lock(&lock, spin), 2);
+        writer.join().unwrap();
+
+        // once out of spins, it blocks instead
+        let reader = read_lock(&lock, Acquire::Block);
+        let writer = thread::spawn({
+            let lock = lock.clone();
+            move || *write_lock(&lock, Acquire::Spin { spins: 4 }) += 1
+        });
+        thread::sleep(Duration::from_millis(20));
+        drop(reader);
+        writer.join().unwrap();
+        let priority = PriorityRwLock::new(*lock.read().unwrap());
+        assert_eq!(*priority.read(spin), 3);
+        assert_eq!(*priority.write(spin), 3);
+    }
+}
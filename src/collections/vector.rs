@@ -0,0 +1,503 @@
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::collections::utils::{random, LockPolicy, PriorityRwLock};
+
+/// Distance between vectors used by a [`VectorIndex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Euclidean distance.
+    Euclidean,
+    /// One minus the cosine of the angle between the vectors.
+    Cosine,
+}
+
+impl Metric {
+    fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Euclidean => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+                .sqrt(),
+            Metric::Cosine => {
+                let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+                for (a, b) in a.iter().zip(b) {
+                    dot += a * b;
+                    norm_a += a * a;
+                    norm_b += b * b;
+                }
+                let norms = (norm_a * norm_b).sqrt();
+                if norms == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot / norms
+                }
+            }
+        }
+    }
+}
+
+/// A node of the graph, linked at every layer up to its level.
+struct Node {
+    id: u64,
+    vector: Box<[f32]>,
+    /// Set once the ID is removed or mapped to another vector. Deleted
+    /// nodes keep routing searches but are never returned.
+    deleted: AtomicBool,
+    /// `layers[layer]` holds the positions of the node's neighbors.
+    layers: Vec<Mutex<Vec<usize>>>,
+}
+
+impl Node {
+    fn level(&self) -> usize {
+        self.layers.len() - 1
+    }
+
+    fn neighbors(&self, layer: usize) -> Vec<usize> {
+        self.layers[layer].lock().unwrap().clone()
+    }
+}
+
+struct Nodes {
+    nodes: Vec<Arc<Node>>,
+    /// Position of the live node of every ID.
+    ids: HashMap<u64, usize>,
+    /// Position of the node searches start from, on the highest layer.
+    entry: Option<usize>,
+}
+
+/// Candidate of a search, ordered by distance.
+#[derive(Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    position: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.position.cmp(&other.position))
+    }
+}
+
+/// Thread-Safe index of fixed-dimension `f32` vectors keyed by `u64` IDs,
+/// answering approximate nearest neighbor queries.
+///
+/// Vectors are linked into a hierarchical navigable small world (HNSW)
+/// graph: every vector is linked to its closest neighbors on a random number
+/// of layers, sparser towards the top, and searches descend greedily from
+/// the top layer before exploring the bottom one. Every node's neighbor
+/// lists have their own lock, so inserts and searches proceed in parallel,
+/// and the index is only locked exclusively to allocate a node.
+pub struct VectorIndex {
+    dimension: usize,
+    metric: Metric,
+    /// Maximum number of neighbors per node above the bottom layer, which
+    /// allows twice as many.
+    m: usize,
+    ef_construction: usize,
+    nodes: PriorityRwLock<Nodes>,
+    lock_policy: LockPolicy,
+}
+
+impl VectorIndex {
+    /// Number of candidates kept while searching, unless more results are
+    /// requested.
+    const EF_SEARCH: usize = 64;
+
+    /// Creates an empty `VectorIndex` of vectors with `dimension`
+    /// components, linking every vector to up to 16 neighbors per layer.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `dimension` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::vector::{Metric, VectorIndex};
+    /// let index = VectorIndex::new(384, Metric::Cosine);
+    /// ```
+    pub fn new(dimension: usize, metric: Metric) -> Self {
+        Self::with_parameters(dimension, metric, 16, 200)
+    }
+
+    /// Creates an empty `VectorIndex` linking every vector to up to `m`
+    /// neighbors per layer, chosen among `ef_construction` candidates.
+    /// Larger values improve recall at the cost of slower inserts.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `dimension` is 0, `m` is less than 2 or
+    /// `ef_construction` is less than `m`.
+    pub fn with_parameters(
+        dimension: usize,
+        metric: Metric,
+        m: usize,
+        ef_construction: usize,
+    ) -> Self {
+        if dimension == 0 || m < 2 || ef_construction < m {
+            panic!()
+        }
+        VectorIndex {
+            dimension,
+            metric,
+            m,
+            ef_construction,
+            nodes: PriorityRwLock::new(Nodes {
+                nodes: Vec::new(),
+                ids: HashMap::new(),
+                entry: None,
+            }),
+            lock_policy: LockPolicy::default(),
+        }
+    }
+
+    /// Returns the number of components of the vectors.
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn random_level(&self) -> usize {
+        // P(level >= l) = m^-l
+        let uniform = (random() >> 11) as f64 / (1u64 << 53) as f64;
+        (-(1.0 - uniform).ln() / (self.m as f64).ln()) as usize
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn candidate(&self, nodes: &[Arc<Node>], query: &[f32], position: usize) -> Candidate {
+        Candidate {
+            distance: self.metric.distance(query, &nodes[position].vector),
+            position,
+        }
+    }
+
+    /// Returns the `ef` nodes closest to `query` found on `layer` from the
+    /// `entries`, closest first.
+    fn search_layer(
+        &self,
+        nodes: &[Arc<Node>],
+        query: &[f32],
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entries.iter().map(|entry| entry.position).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> =
+            entries.iter().copied().map(Reverse).collect();
+        let mut found: BinaryHeap<Candidate> = entries.iter().copied().collect();
+
+        while let Some(Reverse(closest)) = candidates.pop() {
+            if found.len() >= ef && closest > *found.peek().unwrap() {
+                break;
+            }
+            for neighbor in nodes[closest.position].neighbors(layer) {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let candidate = self.candidate(nodes, query, neighbor);
+                if found.len() < ef || candidate < *found.peek().unwrap() {
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    /// Descends greedily from the entry point through the upper layers,
+    /// returning the closest node found to start the search of the bottom
+    /// layer from.
+    fn descend(&self, nodes: &Nodes, query: &[f32]) -> Option<Candidate> {
+        let entry = nodes.entry?;
+        let mut closest = self.candidate(&nodes.nodes, query, entry);
+        for upper in (1..=nodes.nodes[entry].level()).rev() {
+            closest = self.search_layer(&nodes.nodes, query, &[closest], 1, upper)[0];
+        }
+        Some(closest)
+    }
+
+    /// Maps `id` to `vector`, replacing the vector previously mapped to it.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `vector` does not have
+    /// [`VectorIndex::dimension`] components.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::vector::{Metric, VectorIndex};
+    ///
+    /// let index = VectorIndex::new(2, Metric::Euclidean);
+    /// index.insert(1, &[0.0, 1.0]);
+    /// index.insert(1, &[1.0, 0.0]);
+    /// assert_eq!(index.get(1), Some(vec![1.0, 0.0]));
+    /// assert_eq!(index.len(), 1);
+    /// ```
+    pub fn insert(&self, id: u64, vector: &[f32]) {
+        if vector.len() != self.dimension {
+            panic!()
+        }
+
+        let level = self.random_level();
+        let node = Arc::new(Node {
+            id,
+            vector: vector.into(),
+            deleted: AtomicBool::new(false),
+            layers: (0..=level).map(|_| Mutex::new(Vec::new())).collect(),
+        });
+        let (position, entry) = {
+            let mut nodes = self.nodes.write(self.lock_policy.write);
+            let position = nodes.nodes.len();
+            nodes.nodes.push(Arc::clone(&node));
+            if let Some(replaced) = nodes.ids.insert(id, position) {
+                nodes.nodes[replaced].deleted.store(true, Ordering::SeqCst);
+            }
+            let entry = nodes.entry;
+            if entry.is_none() {
+                nodes.entry = Some(position);
+            }
+            (position, entry)
+        };
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let nodes = self.nodes.read(self.lock_policy.read);
+        let entry_level = nodes.nodes[entry].level();
+        let mut closest = vec![self.candidate(&nodes.nodes, vector, entry)];
+        for layer in (level + 1..=entry_level).rev() {
+            closest = self.search_layer(&nodes.nodes, vector, &closest, 1, layer);
+        }
+        for layer in (0..=level.min(entry_level)).rev() {
+            closest =
+                self.search_layer(&nodes.nodes, vector, &closest, self.ef_construction, layer);
+            let neighbors: Vec<_> = closest
+                .iter()
+                .filter(|candidate| candidate.position != position)
+                .take(self.m)
+                .map(|candidate| candidate.position)
+                .collect();
+            *node.layers[layer].lock().unwrap() = neighbors.clone();
+
+            for neighbor in neighbors {
+                self.link(&nodes.nodes, neighbor, position, layer);
+            }
+        }
+        drop(nodes);
+
+        if level > entry_level {
+            let mut nodes = self.nodes.write(self.lock_policy.write);
+            let current = nodes.entry.map_or(0, |entry| nodes.nodes[entry].level());
+            if level > current {
+                nodes.entry = Some(position);
+            }
+        }
+    }
+
+    /// Links `from` to `to` on `layer`, dropping `from`'s farthest neighbor
+    /// if it has too many.
+    fn link(&self, nodes: &[Arc<Node>], from: usize, to: usize, layer: usize) {
+        let mut neighbors = nodes[from].layers[layer].lock().unwrap();
+        neighbors.push(to);
+        if neighbors.len() > self.max_neighbors(layer) {
+            let vector = &nodes[from].vector;
+            let mut candidates: Vec<_> = neighbors
+                .iter()
+                .map(|neighbor| self.candidate(nodes, vector, *neighbor))
+                .collect();
+            candidates.sort_unstable();
+            candidates.truncate(self.max_neighbors(layer));
+            *neighbors = candidates
+                .into_iter()
+                .map(|candidate| candidate.position)
+                .collect();
+        }
+    }
+
+    /// Returns the IDs and distances of the (approximately) `k` vectors
+    /// closest to `query`, closest first.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `query` does not have
+    /// [`VectorIndex::dimension`] components.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::vector::{Metric, VectorIndex};
+    ///
+    /// let index = VectorIndex::new(2, Metric::Euclidean);
+    /// index.insert(1, &[0.0, 0.0]);
+    /// index.insert(2, &[5.0, 5.0]);
+    /// index.insert(3, &[1.0, 0.0]);
+    ///
+    /// let ids: Vec<_> = index.search(&[0.9, 0.0], 2).into_iter().map(|(id, _)| id).collect();
+    /// assert_eq!(ids, vec![3, 1]);
+    /// ```
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(u64, f32)> {
+        self.search_with_ef(query, k, Self::EF_SEARCH)
+    }
+
+    /// Like [`VectorIndex::search`], keeping `ef` candidates while exploring
+    /// the graph. Larger values improve recall at the cost of slower
+    /// searches.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `query` does not have
+    /// [`VectorIndex::dimension`] components.
+    pub fn search_with_ef(&self, query: &[f32], k: usize, ef: usize) -> Vec<(u64, f32)> {
+        if query.len() != self.dimension {
+            panic!()
+        }
+
+        let nodes = self.nodes.read(self.lock_policy.read);
+        let closest = match self.descend(&nodes, query) {
+            Some(closest) => closest,
+            None => return Vec::new(),
+        };
+        self.search_layer(&nodes.nodes, query, &[closest], ef.max(k), 0)
+            .into_iter()
+            .filter_map(|candidate| {
+                let node = &nodes.nodes[candidate.position];
+                (!node.deleted.load(Ordering::SeqCst)).then_some((node.id, candidate.distance))
+            })
+            .take(k)
+            .collect()
+    }
+
+    /// Returns the vector mapped to `id`.
+    pub fn get(&self, id: u64) -> Option<Vec<f32>> {
+        let nodes = self.nodes.read(self.lock_policy.read);
+        let position = *nodes.ids.get(&id)?;
+        Some(nodes.nodes[position].vector.to_vec())
+    }
+
+    /// Removes the vector mapped to `id`.
+    ///
+    /// The vector stays in the graph to route searches, but is not returned
+    /// by them anymore.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `id` was mapped to a vector.
+    pub fn remove(&self, id: u64) -> bool {
+        let mut nodes = self.nodes.write(self.lock_policy.write);
+        match nodes.ids.remove(&id) {
+            Some(position) => {
+                nodes.nodes[position].deleted.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of vectors in the index.
+    pub fn len(&self) -> usize {
+        self.nodes.read(self.lock_policy.read).ids.len()
+    }
+
+    /// Returns `true` if the index contains no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metric, VectorIndex};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn vectors(count: u64, dimension: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..count)
+            .map(|_| {
+                (0..dimension)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6_364_136_223_846_793_005)
+                            .wrapping_add(1);
+                        (state >> 40) as f32 / (1u64 << 24) as f32
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_concurrent_inserts_have_high_recall() {
+        let index = Arc::new(VectorIndex::new(16, Metric::Euclidean));
+        let data = Arc::new(vectors(4000, 16));
+
+        let writers: Vec<_> = (0..4usize)
+            .map(|thread| {
+                let (index, data) = (index.clone(), data.clone());
+                thread::spawn(move || {
+                    for id in (thread..data.len()).step_by(4) {
+                        index.insert(id as u64, &data[id]);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(index.len(), 4000);
+
+        let mut hits = 0;
+        for query in vectors(100, 16)
+            .iter()
+            .map(|query| query.iter().map(|x| x * 0.9).collect::<Vec<_>>())
+        {
+            let mut exact: Vec<_> = (0..data.len())
+                .map(|id| (Metric::Euclidean.distance(&query, &data[id]), id as u64))
+                .collect();
+            exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let found = index.search(&query, 10);
+            hits += exact[..10]
+                .iter()
+                .filter(|(_, id)| found.iter().any(|(found, _)| found == id))
+                .count();
+        }
+        assert!(hits >= 900, "recall {}%", hits / 10);
+
+        let (nearest, _) = index.search(&data[42], 1)[0];
+        assert_eq!(nearest, 42);
+        assert!(index.remove(42));
+        assert!(index.search(&data[42], 10).iter().all(|(id, _)| *id != 42));
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+use crate::collections::map::DEFAULT_BUCKET_COUNT;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+
+type WeakBucket<K, V> = PriorityRwLock<Vec<(K, Weak<V>)>>;
+
+/// Thread-Safe map implemented as hash table, holding weak references to
+/// its values.
+///
+/// The map never keeps a value alive: an entry dies along with the last
+/// [`Arc`] to its value. Dead entries of a bucket are purged whenever the
+/// bucket is written to, or read and found to contain one, so a map of
+/// shared objects does not grow with objects long dropped.
+pub struct WeakMap<K, V, H = RandomState> {
+    hash_builder: H,
+    buckets: Vec<WeakBucket<K, V>>,
+    lock_policy: LockPolicy,
+    len: AtomicUsize,
+}
+
+impl<K, V> Default for WeakMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> WeakMap<K, V, RandomState>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates an empty `WeakMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::weak::WeakMap;
+    /// let map: WeakMap<&str, Vec<u8>> = WeakMap::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), DEFAULT_BUCKET_COUNT)
+    }
+
+    /// Creates an empty `WeakMap` with a given bucket count.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::weak::WeakMap;
+    /// let map: WeakMap<&str, Vec<u8>> = WeakMap::with_bucket_count(32);
+    /// ```
+    pub fn with_bucket_count(bucket_count: usize) -> Self {
+        Self::with_hasher_and_bucket_count(RandomState::new(), bucket_count)
+    }
+}
+
+impl<K, V, H> WeakMap<K, V, H>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Creates an empty `WeakMap` with `bucket_count` buckets allocated,
+    /// using `hash_builder` to hash the keys.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bucket_count` is 0.
+    pub fn with_hasher_and_bucket_count(hash_builder: H, bucket_count: usize) -> Self {
+        if bucket_count == 0usize {
+            panic!()
+        }
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        buckets.resize_with(bucket_count, || PriorityRwLock::new(Vec::new()));
+
+        WeakMap {
+            hash_builder,
+            buckets,
+            lock_policy: LockPolicy::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn get_bucket(&self, key: &K) -> &WeakBucket<K, V> {
+        let hash = self.hash_builder.hash_one(key) as usize;
+        &self.buckets[hash % self.buckets.len()]
+    }
+
+    /// Removes the dead entries of a write-locked bucket.
+    fn purge_locked(&self, entries: &mut Vec<(K, Weak<V>)>) -> usize {
+        let count = entries.len();
+        entries.retain(|(_, value)| value.strong_count() > 0);
+        let purged = count - entries.len();
+        self.len.fetch_sub(purged, Ordering::SeqCst);
+        purged
+    }
+
+    /// Maps `key` to a weak reference to `value`, returning the value
+    /// previously mapped to it if still alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::weak::WeakMap;
+    /// use std::sync::Arc;
+    ///
+    /// let map = WeakMap::new();
+    /// let schema = Arc::new("users(id, name)");
+    /// assert!(map.insert(&"users", &schema).is_none());
+    /// assert_eq!(map.get(&"users"), Some(schema));
+    /// ```
+    pub fn insert(&self, key: &K, value: &Arc<V>) -> Option<Arc<V>> {
+        let mut entries = self.get_bucket(key).write(self.lock_policy.write);
+        self.purge_locked(&mut entries);
+        match entries.iter_mut().find(|(elem_key, _)| elem_key == key) {
+            Some((_, existing)) => std::mem::replace(existing, Arc::downgrade(value)).upgrade(),
+            None => {
+                entries.push((key.clone(), Arc::downgrade(value)));
+                self.len.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+
+    /// Returns the value mapped to `key`, if still alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::weak::WeakMap;
+    /// use std::sync::Arc;
+    ///
+    /// let map = WeakMap::new();
+    /// let schema = Arc::new("users(id, name)");
+    /// map.insert(&"users", &schema);
+    ///
+    /// drop(schema);
+    /// assert_eq!(map.get(&"users"), None);
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        let bucket = self.get_bucket(key);
+        let (value, has_dead) = {
+            let entries = bucket.read(self.lock_policy.read);
+            let value = entries
+                .iter()
+                .find(|(elem_key, _)| elem_key == key)
+                .and_then(|(_, value)| value.upgrade());
+            let has_dead = entries.iter().any(|(_, value)| value.strong_count() == 0);
+            (value, has_dead)
+        };
+        if has_dead {
+            self.purge_locked(&mut bucket.write(self.lock_policy.write));
+        }
+        value
+    }
+
+    /// Returns the value mapped to `key` if still alive, or maps `key` to
+    /// the value made by `make` and returns it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::weak::WeakMap;
+    /// use std::sync::Arc;
+    ///
+    /// let map = WeakMap::new();
+    /// let first = map.get_or_insert_with(&"index", || Arc::new(vec![1, 2, 3]));
+    /// let second = map.get_or_insert_with(&"index", || Arc::new(vec![]));
+    /// assert!(Arc::ptr_eq(&first, &second));
+    /// ```
+    pub fn get_or_insert_with<F>(&self, key: &K, make: F) -> Arc<V>
+    where
+        F: FnOnce() -> Arc<V>,
+    {
+        let mut entries = self.get_bucket(key).write(self.lock_policy.write);
+        self.purge_locked(&mut entries);
+        let existing = entries.iter_mut().find(|(elem_key, _)| elem_key == key);
+        if let Some(value) = existing.as_ref().and_then(|(_, value)| value.upgrade()) {
+            return value;
+        }
+
+        let value = make();
+        match existing {
+            // the last strong reference was dropped since the purge
+            Some((_, dead)) => *dead = Arc::downgrade(&value),
+            None => {
+                entries.push((key.clone(), Arc::downgrade(&value)));
+                self.len.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        value
+    }
+
+    /// Removes the mapping of `key`, returning its value if still alive.
+    pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut entries = self.get_bucket(key).write(self.lock_policy.write);
+        self.purge_locked(&mut entries);
+        let index = entries.iter().position(|(elem_key, _)| elem_key == key)?;
+        let (_, value) = entries.swap_remove(index);
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        value.upgrade()
+    }
+
+    /// Removes the dead entries of every bucket, returning how many were
+    /// removed.
+    pub fn purge(&self) -> usize {
+        self.buckets
+            .iter()
+            .map(|bucket| self.purge_locked(&mut bucket.write(self.lock_policy.write)))
+            .sum()
+    }
+
+    /// Returns the number of entries in the map, counting dead entries not
+    /// purged yet.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the map contains no entries, dead or alive.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WeakMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_entries_vanish_with_their_last_strong_reference() {
+        let map = Arc::new(WeakMap::with_bucket_count(4));
+
+        let workers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    // every thread keeps only the values of even keys
+                    (0..1000u64)
+                        .map(|i| map.get_or_insert_with(&(thread * 1000 + i), || Arc::new(i)))
+                        .filter(|value| **value % 2 == 0)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let kept: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+
+        map.purge();
+        assert_eq!(map.len(), 2000);
+        assert_eq!(map.get(&2), Some(Arc::new(2)));
+        assert_eq!(map.get(&3), None);
+
+        drop(kept);
+        assert_eq!(map.get(&2), None);
+        map.purge();
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! The LZ4 block format, without the frame around it.
+//!
+//! Matches are searched for through chains of the previous positions
+//! sharing a hash, as deep as the compression level asks for, so that the
+//! lowest level compresses about as fast as reference LZ4 and the highest
+//! ones closer to LZ4 HC. Every level decompresses with any LZ4 decoder.
+
+use std::io;
+
+use crate::codec::invalid_data;
+
+const MIN_MATCH: usize = 4;
+/// Matches start at least this many bytes before the end of the block.
+const MF_LIMIT: usize = 12;
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+const MAX_DISTANCE: usize = 65535;
+const HASH_LOG: u32 = 16;
+
+/// Highest compression level, higher ones being clamped to it.
+pub(crate) const MAX_LEVEL: u32 = 9;
+
+fn read_u32(input: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([input[at], input[at + 1], input[at + 2], input[at + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+/// Makes `pos` the head of the chain of its hash.
+///
+/// # Returns
+///
+/// The previous head, plus one, 0 if there was none.
+fn insert(input: &[u8], pos: usize, heads: &mut [u32], chains: &mut [u32]) -> u32 {
+    let slot = &mut heads[hash(read_u32(input, pos))];
+    let previous = *slot;
+    if let Some(link) = chains.get_mut(pos) {
+        *link = previous;
+    }
+    *slot = pos as u32 + 1;
+    previous
+}
+
+fn write_len(mut len: usize, out: &mut Vec<u8>) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Appends a sequence of `literals` followed, unless it is the last one, by
+/// a match of `len` bytes at `offset` back.
+fn write_sequence(literals: &[u8], matched: Option<(usize, usize)>, out: &mut Vec<u8>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_len(literals.len() - 15, out);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_len(match_len - 15, out);
+        }
+    }
+}
+
+/// Appends `input` compressed at `level`, from 1 to [`MAX_LEVEL`], to
+/// `out`.
+pub(crate) fn compress(input: &[u8], level: u32, out: &mut Vec<u8>) {
+    let mut anchor = 0;
+    if input.len() > MF_LIMIT {
+        let depth = 1usize << (level.clamp(1, MAX_LEVEL) - 1);
+        let mut heads = vec![0u32; 1 << HASH_LOG];
+        // previous position with the same hash, plus one, for every position
+        let mut chains = vec![0u32; if depth > 1 { input.len() } else { 0 }];
+
+        let match_limit = input.len() - LAST_LITERALS;
+        let mut pos = 0;
+        while pos < input.len() - MF_LIMIT {
+            let mut candidate = insert(input, pos, &mut heads, &mut chains);
+            let mut best = (0, 0);
+            for _ in 0..depth {
+                if candidate == 0 || pos - (candidate as usize - 1) > MAX_DISTANCE {
+                    break;
+                }
+                let start = candidate as usize - 1;
+                if read_u32(input, start) == read_u32(input, pos) {
+                    let len = MIN_MATCH
+                        + input[start + MIN_MATCH..]
+                            .iter()
+                            .zip(&input[pos + MIN_MATCH..match_limit])
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                    if len > best.1 {
+                        best = (pos - start, len);
+                    }
+                }
+                candidate = chains.get(start).copied().unwrap_or(0);
+            }
+
+            if best.1 < MIN_MATCH {
+                pos += 1;
+                continue;
+            }
+            write_sequence(&input[anchor..pos], Some(best), out);
+            let end = pos + best.1;
+            for inner in pos + 1..end.min(input.len() - MF_LIMIT) {
+                insert(input, inner, &mut heads, &mut chains);
+            }
+            pos = end;
+            anchor = end;
+        }
+    }
+    write_sequence(&input[anchor..], None, out);
+}
+
+/// Decompresses `input` into the `len` bytes it was compressed from.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if `input` is not an LZ4 block or does
+/// not decompress to `len` bytes.
+pub(crate) fn decompress(mut input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    fn corrupted() -> io::Error {
+        invalid_data("corrupted LZ4 block")
+    }
+    fn next(input: &mut &[u8]) -> io::Result<u8> {
+        let (byte, rest) = input.split_first().ok_or_else(corrupted)?;
+        *input = rest;
+        Ok(*byte)
+    }
+    fn read_len(mut len: usize, input: &mut &[u8]) -> io::Result<usize> {
+        if len == 15 {
+            loop {
+                let byte = next(input)?;
+                len += usize::from(byte);
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    // a byte of input decompresses to at most 255 bytes, which bounds what
+    // a corrupted length can allocate
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(255)));
+    loop {
+        let token = next(&mut input)?;
+        let literals = read_len(usize::from(token >> 4), &mut input)?;
+        if literals > input.len() || out.len() + literals > len {
+            return Err(corrupted());
+        }
+        out.extend_from_slice(&input[..literals]);
+        input = &input[literals..];
+        if input.is_empty() {
+            break;
+        }
+
+        let offset = usize::from(u16::from_le_bytes([next(&mut input)?, next(&mut input)?]));
+        let match_len = read_len(usize::from(token & 15), &mut input)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return Err(corrupted());
+        }
+        // matches may overlap the bytes they produce, so are copied one
+        // byte at a time
+        let start = out.len() - offset;
+        for index in start..start + match_len {
+            out.push(out[index]);
+        }
+    }
+    if out.len() != len {
+        return Err(corrupted());
+    }
+    Ok(out)
+}
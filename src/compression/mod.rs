@@ -0,0 +1,222 @@
+//! Compression of the files written by the crate.
+//!
+//! Log records, table blocks and the sections of [PDB files](crate::pdb)
+//! are each compressed on their own, once configured with a
+//! [`Compression`] on the [`WalBuilder`](crate::wal::WalBuilder), the
+//! [`LsmBuilder`](crate::storage::LsmBuilder), the
+//! [`MapBuilder`](crate::collections::map::MapBuilder) or the
+//! [`DatabaseBuilder`](crate::db::DatabaseBuilder), and decompressed as they
+//! are read whatever the reader is configured with. A compressed block is
+//! laid out as:
+//!
+//! ```text
+//! codec: u8 | if not raw, uncompressed length: u32 | payload
+//! ```
+//!
+//! Blocks which compression would not shrink are kept raw. Compression
+//! comes before [encryption](crate::encryption), ciphertext not being
+//! compressible, and the checksum each block already carries covers the
+//! block as stored, so corruption is told apart before decompressing.
+//!
+//! The LZ4 codec is behind the `lz4` feature. Files compressed with it can
+//! only be read by builds with the feature on, others failing with an error
+//! of kind `InvalidData`.
+
+#[cfg(feature = "lz4")]
+mod lz4;
+
+use std::io;
+
+use crate::codec::invalid_data;
+
+/// Codec a block is stored with, in its first byte.
+const RAW: u8 = 0;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 1;
+
+/// Compression of the blocks of a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Blocks are stored as they are.
+    #[default]
+    None,
+    /// Blocks are compressed with LZ4, at a `level` from 1, the fastest, to
+    /// 9, the smallest, higher ones being clamped to 9.
+    ///
+    /// Every level decompresses equally fast.
+    #[cfg(feature = "lz4")]
+    Lz4 { level: u32 },
+}
+
+impl Compression {
+    /// Returns whether blocks are compressed.
+    pub fn is_enabled(&self) -> bool {
+        *self != Compression::None
+    }
+
+    /// Returns the name of the codec, as recorded by files describing their
+    /// compression as text.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 { .. } => "lz4",
+        }
+    }
+}
+
+/// Appends `data` compressed as a block to `out`.
+pub(crate) fn pack(compression: Compression, data: &[u8], out: &mut Vec<u8>) {
+    match compression {
+        Compression::None => {}
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 { level } => {
+            let start = out.len();
+            out.push(LZ4);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            lz4::compress(data, level, out);
+            if out.len() - start < data.len() + 1 {
+                return;
+            }
+            out.truncate(start);
+        }
+    }
+    out.push(RAW);
+    out.extend_from_slice(data);
+}
+
+/// Decompresses a block written by [`pack`].
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if the block is corrupted or compressed
+/// with a codec this build does not have.
+pub(crate) fn unpack(mut block: Vec<u8>) -> io::Result<Vec<u8>> {
+    match block.first() {
+        Some(&RAW) => {
+            block.drain(..1);
+            Ok(block)
+        }
+        #[cfg(feature = "lz4")]
+        Some(&LZ4) if block.len() >= 5 => {
+            let len = u32::from_le_bytes([block[1], block[2], block[3], block[4]]);
+            lz4::decompress(&block[5..], len as usize)
+        }
+        Some(codec) => Err(invalid_data(&format!(
+            "block compressed with unknown codec {}",
+            codec
+        ))),
+        None => Err(invalid_data("empty compressed block")),
+    }
+}
+
+#[cfg(all(test, feature = "encryption", feature = "lz4"))]
+mod tests {
+    use super::{pack, unpack, Compression};
+    use crate::collections::map::MapBuilder;
+    use crate::encryption::{Encryption, Key, KeyRing};
+    use crate::storage::{LsmBuilder, StorageEngine};
+    use crate::Map;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tables_logs_and_snapshots_read_whatever_their_compression() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-compression-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let lz4 = Compression::Lz4 { level: 5 };
+        let encryption = Encryption::new(Arc::new(KeyRing::new(Key::new([3; 32])))).unwrap();
+        let value = b"repetitive value ".repeat(100);
+
+        let engine = LsmBuilder::new().open(dir.join("lsm")).unwrap();
+        engine.put(b"plain", &value).unwrap();
+        engine.flush_memtable().unwrap();
+        drop(engine);
+        for builder in [
+            LsmBuilder::new().compression(lz4),
+            LsmBuilder::new()
+                .compression(lz4)
+                .encryption(encryption.clone()),
+        ] {
+            let engine = builder.open(dir.join("lsm")).unwrap();
+            engine.put(b"flushed", &value).unwrap();
+            engine.flush_memtable().unwrap();
+            engine.put(b"logged", &value).unwrap();
+            drop(engine);
+
+            let engine = LsmBuilder::new()
+                .encryption(encryption.clone())
+                .open(dir.join("lsm"))
+                .unwrap();
+            for key in [&b"plain"[..], b"flushed", b"logged"] {
+                assert_eq!(engine.get(key).unwrap(), Some(value.clone()));
+            }
+        }
+
+        let path = dir.join("snapshot");
+        let map = MapBuilder::new().compression(lz4).build();
+        for key in 0..1000u64 {
+            map.put(&key, format!("value of {}", key % 10));
+        }
+        map.save_to(&path).unwrap();
+        let compressed = fs::metadata(&path).unwrap().len();
+        let loaded: Map<u64, String> = Map::load_from(&path).unwrap();
+        assert_eq!(loaded.get(&999), Some("value of 9".to_string()));
+        let map: Map<u64, String> = Map::new();
+        for key in 0..1000u64 {
+            map.put(&key, format!("value of {}", key % 10));
+        }
+        map.save_to(&path).unwrap();
+        assert!(compressed < fs::metadata(&path).unwrap().len() / 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lz4_blocks_round_trip_and_decode_reference_output() {
+        let mut text = Vec::new();
+        for index in 0..2000u32 {
+            text.extend_from_slice(format!("key-{:05} value-{} ", index, index % 7).as_bytes());
+        }
+        // pseudo-random bytes, which do not compress
+        let noise: Vec<u8> = (0..4096u32)
+            .map(|index| (index.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+
+        for data in [&text[..], &noise[..], b"", b"short", &[7; 70000][..]] {
+            let mut previous = usize::MAX;
+            for level in [1, 3, 9, 20] {
+                let mut block = Vec::new();
+                pack(Compression::Lz4 { level }, data, &mut block);
+                assert!(block.len() <= data.len() + 1);
+                assert!(block.len() <= previous);
+                previous = block.len();
+                assert_eq!(unpack(block).unwrap(), data);
+            }
+        }
+        for (level, ratio) in [(1, 2), (9, 3)] {
+            let mut block = Vec::new();
+            pack(Compression::Lz4 { level }, &text, &mut block);
+            assert!(block.len() < text.len() / ratio);
+        }
+
+        // "abcabcabcabcabcabcabcabcabcabc", as compressed by the lz4 tool
+        let mut reference = vec![1, 30, 0, 0, 0];
+        reference.extend_from_slice(&[0x3f, b'a', b'b', b'c', 3, 0, 3, 0x50]);
+        reference.extend_from_slice(b"bcabc");
+        assert_eq!(unpack(reference).unwrap(), b"abc".repeat(10));
+
+        // corrupted blocks fail rather than panic or read out of bounds
+        let mut block = Vec::new();
+        pack(Compression::Lz4 { level: 9 }, &text, &mut block);
+        for len in [1, 5, 6, block.len() / 2, block.len() - 1] {
+            assert!(unpack(block[..len].to_vec()).is_err());
+        }
+        block[1] ^= 1;
+        assert!(unpack(block).is_err());
+        assert!(unpack(vec![9, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,764 @@
+//! CSV format, used to export collections of tabular values to spreadsheets
+//! and data pipelines and import them back, see
+//! [`Map::export_csv`](crate::Map::export_csv).
+//!
+//! A value is flattened to columns by serde: a struct gives a column per
+//! field, named after it, nested structs and tuples giving columns named
+//! after their path, such as `address.city` or `position.0`. A value that
+//! is not a struct is a single column named `value`. Unit variants of enums
+//! are written as their name. `None` is written as an empty cell, or as
+//! empty cells for the fields of a struct, and an empty string as a quoted
+//! cell, `""`, to tell them apart. Sequences, maps and enum variants
+//! holding data have no fixed columns, and are rejected.
+
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, ErrorKind};
+use std::str::FromStr;
+
+use serde::de::value::StrDeserializer;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize, SerializeStruct, SerializeTuple};
+
+use crate::codec::invalid_data;
+
+/// Name of the column of a value that is not a struct.
+pub const VALUE_COLUMN: &str = "value";
+
+/// Error of flattening a value to columns, or of reading it back from them.
+#[derive(Debug)]
+pub(crate) struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        invalid_data(&err.0)
+    }
+}
+
+/// A cell of a row, `None` if empty.
+pub(crate) type Cell = Option<String>;
+
+/// Appends the columns `value` flattens to, named `name` if it is not a
+/// struct or after its fields prefixed by `prefix` if it is.
+pub(crate) fn flatten<T: Serialize + ?Sized>(
+    value: &T,
+    name: &str,
+    prefix: &str,
+    columns: &mut Vec<(String, Cell)>,
+) -> io::Result<()> {
+    value.serialize(Flattener {
+        name,
+        prefix,
+        columns,
+    })?;
+    Ok(())
+}
+
+/// Reads a value back from the columns written by [`flatten`].
+pub(crate) fn unflatten<'de, T: de::Deserialize<'de>>(
+    name: &str,
+    prefix: &str,
+    columns: &'de [(String, Cell)],
+) -> io::Result<T> {
+    Ok(T::deserialize(Columns {
+        name: name.to_string(),
+        prefix: prefix.to_string(),
+        columns,
+    })?)
+}
+
+/// Returns the cells of `columns` in the order of the columns of `header`.
+///
+/// An empty column stands for empty columns nested in it, and empty nested
+/// columns for an empty column, so that an `Option` of a struct may be
+/// `None` in some rows only.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if a cell that is not empty has no column
+/// in `header`, or a column of `header` has no cell.
+pub(crate) fn align<'a>(
+    header: &[String],
+    columns: &'a [(String, Cell)],
+) -> io::Result<Vec<&'a Cell>> {
+    const EMPTY: &Cell = &None;
+    let nested = |outer: &str, name: &str| {
+        name.len() > outer.len() && name.starts_with(outer) && name[outer.len()..].starts_with('.')
+    };
+    let mut cells = Vec::with_capacity(header.len());
+    let mut matched = 0;
+    for column in header {
+        if let Some((_, cell)) = columns.iter().find(|(name, _)| name == column) {
+            cells.push(cell);
+            matched += cell.is_some() as usize;
+        } else if columns
+            .iter()
+            .any(|(name, cell)| cell.is_none() && (nested(name, column) || nested(column, name)))
+        {
+            cells.push(EMPTY);
+        } else {
+            return Err(invalid_data(&format!("no cell for column {}", column)));
+        }
+    }
+    if matched != columns.iter().filter(|(_, cell)| cell.is_some()).count() {
+        return Err(invalid_data("cells without a column in the header"));
+    }
+    Ok(cells)
+}
+
+/// Appends `cell` to `buf`, quoted if needed.
+fn write_cell(cell: &Cell, buf: &mut String) {
+    let text = match cell {
+        Some(text) => text,
+        None => return,
+    };
+    if text.is_empty() || text.contains([',', '"', '\r', '\n']) {
+        buf.push('"');
+        buf.push_str(&text.replace('"', "\"\""));
+        buf.push('"');
+    } else {
+        buf.push_str(text);
+    }
+}
+
+/// Appends a record made of `cells` to `buf`.
+pub(crate) fn write_record<'a, I>(cells: I, buf: &mut String)
+where
+    I: IntoIterator<Item = &'a Cell>,
+{
+    for (i, cell) in cells.into_iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        write_cell(cell, buf);
+    }
+    buf.push_str("\r\n");
+}
+
+/// Reads the next record of `input`, `None` at its end. Quoted cells may
+/// span lines, and records may end with `\n` or `\r\n`.
+pub(crate) fn read_record<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<Cell>>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut chars: Vec<char> = line.chars().collect();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    // whether the cell started with a quote, and whether it is still open
+    let (mut quoted, mut open) = (false, false);
+    let mut i = 0;
+    loop {
+        if i == chars.len() {
+            if !open {
+                break;
+            }
+            // the quoted cell goes on on the next line
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "CSV quoted cell not closed",
+                ));
+            }
+            chars = line.chars().collect();
+            i = 0;
+        }
+        let c = chars[i];
+        i += 1;
+        if open {
+            match c {
+                '"' if chars.get(i) == Some(&'"') => {
+                    cell.push('"');
+                    i += 1;
+                }
+                '"' => open = false,
+                c => cell.push(c),
+            }
+            continue;
+        }
+        match c {
+            ',' => cells.push(take_cell(&mut cell, &mut quoted)),
+            '\r' | '\n' => break,
+            _ if quoted => return Err(invalid_data("CSV text after closing quote")),
+            '"' if cell.is_empty() => (quoted, open) = (true, true),
+            '"' => return Err(invalid_data("CSV quote inside unquoted cell")),
+            c => cell.push(c),
+        }
+    }
+    cells.push(take_cell(&mut cell, &mut quoted));
+    Ok(Some(cells))
+}
+
+/// Returns the cell read so far, empty if it is neither quoted nor holds
+/// any text.
+fn take_cell(cell: &mut String, quoted: &mut bool) -> Cell {
+    let cell = std::mem::take(cell);
+    let quoted = std::mem::take(quoted);
+    Some(cell).filter(|cell| quoted || !cell.is_empty())
+}
+
+struct Flattener<'a> {
+    name: &'a str,
+    prefix: &'a str,
+    columns: &'a mut Vec<(String, Cell)>,
+}
+
+impl Flattener<'_> {
+    fn push(self, cell: Cell) -> Result<(), Error> {
+        self.columns.push((self.name.to_string(), cell));
+        Ok(())
+    }
+
+    fn unsupported(&self, what: &str) -> Error {
+        Error(format!(
+            "{} {} cannot be flattened to columns",
+            self.name, what
+        ))
+    }
+}
+
+macro_rules! serialize_display {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, value: $ty) -> Result<(), Error> {
+                self.push(Some(value.to_string()))
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for Flattener<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Fields<'a>;
+    type SerializeTupleStruct = Fields<'a>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Fields<'a>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    serialize_display!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str
+    );
+
+    fn serialize_bytes(self, _value: &[u8]) -> Result<(), Error> {
+        Err(self.unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.push(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.push(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.push(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.push(Some(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Err(self.unsupported("enum variant"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(self.unsupported("sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(self.unsupported("enum variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(self.unsupported("map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(self.unsupported("enum variant"))
+    }
+}
+
+/// Flattens the fields of a struct or tuple, named after the field or its
+/// index.
+struct Fields<'a> {
+    prefix: &'a str,
+    columns: &'a mut Vec<(String, Cell)>,
+    index: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(flattener: Flattener<'a>) -> Self {
+        Fields {
+            prefix: flattener.prefix,
+            columns: flattener.columns,
+            index: 0,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, field: &str, value: &T) -> Result<(), Error> {
+        let name = format!("{}{}", self.prefix, field);
+        value.serialize(Flattener {
+            name: &name,
+            prefix: &format!("{}.", name),
+            columns: self.columns,
+        })
+    }
+}
+
+impl SerializeTuple for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.index += 1;
+        self.field(&(self.index - 1).to_string(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.serialize_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl SerializeStruct for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Deserializer of a value from the columns of a row, named `name` if it is
+/// not a struct or after its fields prefixed by `prefix` if it is.
+struct Columns<'de> {
+    name: String,
+    prefix: String,
+    columns: &'de [(String, Cell)],
+}
+
+impl<'de> Columns<'de> {
+    fn cell(&self) -> Result<&'de Cell, Error> {
+        self.columns
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map(|(_, cell)| cell)
+            .ok_or_else(|| Error(format!("missing column {}", self.name)))
+    }
+
+    /// Returns the columns of the fields of the value.
+    fn nested(&self) -> impl Iterator<Item = &'de (String, Cell)> + '_ {
+        self.columns
+            .iter()
+            .filter(move |(name, _)| name.starts_with(&self.prefix))
+    }
+
+    fn field(&self, field: &str) -> Columns<'de> {
+        let name = format!("{}{}", self.prefix, field);
+        Columns {
+            prefix: format!("{}.", name),
+            name,
+            columns: self.columns,
+        }
+    }
+
+    /// Returns `true` if the value has a column or nested columns.
+    fn exists(&self) -> bool {
+        self.cell().is_ok() || self.nested().next().is_some()
+    }
+}
+
+macro_rules! deserialize_cell {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let cell = self.cell()?;
+                Text::new(self.name, cell).$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Columns<'de> {
+    type Error = Error;
+
+    deserialize_cell!(
+        deserialize_any,
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_unit,
+        deserialize_seq,
+        deserialize_map,
+        deserialize_identifier,
+        deserialize_ignored_any
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.cell() {
+            Ok(None) => visitor.visit_none(),
+            Ok(Some(_)) => visitor.visit_some(self),
+            // a struct is missing if all of its columns are empty
+            Err(_) if self.nested().all(|(_, cell)| cell.is_none()) => visitor.visit_none(),
+            Err(_) => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements {
+            columns: self,
+            index: 0,
+            len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // fields without columns are left out, for serde to report them
+        // missing or default them
+        let fields = fields
+            .iter()
+            .filter(|field| self.field(field).exists())
+            .collect::<Vec<_>>()
+            .into_iter();
+        visitor.visit_map(Members {
+            columns: self,
+            fields,
+            next: None,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let cell = self.cell()?;
+        Text::new(self.name, cell).deserialize_enum(name, variants, visitor)
+    }
+}
+
+struct Elements<'de> {
+    columns: Columns<'de>,
+    index: usize,
+    len: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.index == self.len {
+            return Ok(None);
+        }
+        self.index += 1;
+        let element = self.columns.field(&(self.index - 1).to_string());
+        seed.deserialize(element).map(Some)
+    }
+}
+
+struct Members<'de, I> {
+    columns: Columns<'de>,
+    fields: I,
+    next: Option<&'static str>,
+}
+
+impl<'de, I: Iterator<Item = &'static &'static str>> MapAccess<'de> for Members<'de, I> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.next() {
+            Some(field) => {
+                self.next = Some(field);
+                let field: StrDeserializer<'_, Error> = field.into_deserializer();
+                seed.deserialize(field).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let field = self.next.take().expect("value requested before its key");
+        seed.deserialize(self.columns.field(field))
+    }
+}
+
+/// Deserializer of a value from the text of a single cell.
+struct Text<'de> {
+    name: String,
+    cell: &'de Cell,
+}
+
+impl<'de> Text<'de> {
+    fn new(name: String, cell: &'de Cell) -> Self {
+        Text { name, cell }
+    }
+
+    fn text(&self) -> Result<&'de str, Error> {
+        self.cell
+            .as_deref()
+            .ok_or_else(|| Error(format!("column {} is empty", self.name)))
+    }
+
+    fn parse<T: FromStr>(&self, what: &str) -> Result<T, Error> {
+        let text = self.text()?;
+        text.parse().map_err(|_| {
+            Error(format!(
+                "column {}: {:?} is not a valid {}",
+                self.name, text, what
+            ))
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident: $ty:ty => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Text<'de> {
+    type Error = Error;
+
+    deserialize_parsed!(
+        deserialize_bool: bool => visit_bool,
+        deserialize_i8: i8 => visit_i8,
+        deserialize_i16: i16 => visit_i16,
+        deserialize_i32: i32 => visit_i32,
+        deserialize_i64: i64 => visit_i64,
+        deserialize_i128: i128 => visit_i128,
+        deserialize_u8: u8 => visit_u8,
+        deserialize_u16: u16 => visit_u16,
+        deserialize_u32: u32 => visit_u32,
+        deserialize_u64: u64 => visit_u64,
+        deserialize_u128: u128 => visit_u128,
+        deserialize_f32: f32 => visit_f32,
+        deserialize_f64: f64 => visit_f64,
+        deserialize_char: char => visit_char
+    );
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.cell {
+            Some(text) => visitor.visit_borrowed_str(text),
+            None => visitor.visit_unit(),
+        }
+    }
+
+    // spreadsheets write empty strings unquoted
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.cell.as_deref().unwrap_or(""))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.cell {
+            Some(_) => visitor.visit_some(self),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.cell {
+            Some(_) => Err(Error(format!("column {} is not empty", self.name))),
+            None => visitor.visit_unit(),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let variant: StrDeserializer<'de, Error> = self.text()?.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf seq map tuple tuple_struct struct identifier
+    }
+}
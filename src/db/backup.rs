@@ -0,0 +1,257 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use crate::codec::{decode_bytes, encode_bytes};
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+use crate::pdb::{PdbReader, PdbWriter, SECTION_SIZE};
+use crate::storage::{StorageEngine, StorageSnapshot};
+
+/// Name of the file of a backup holding the entries of the stores of the
+/// database, see [`Database::backup_to`](super::Database::backup_to). The
+/// other files of a backup are laid out as in the directory of a database.
+pub const STORES_FILE: &str = "STORES";
+
+/// Kind of the PDB file of the stores, and version of its layout.
+const KIND: &str = "stores";
+const KIND_VERSION: u32 = 1;
+
+/// Size of the chunks files are copied in, progress being reported after
+/// each one.
+const COPY_CHUNK: usize = 1 << 20;
+
+/// Progress of a backup or of a restore, as reported to its callback and
+/// once it completes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackupProgress {
+    /// Number of files copied so far.
+    pub files: u64,
+    /// Number of bytes copied so far.
+    pub bytes: u64,
+}
+
+/// Copies files, keeping track of the progress.
+pub(super) struct Copier<'a> {
+    progress: BackupProgress,
+    report: &'a mut dyn FnMut(&BackupProgress),
+}
+
+impl<'a> Copier<'a> {
+    pub(super) fn new(report: &'a mut dyn FnMut(&BackupProgress)) -> Self {
+        Copier {
+            progress: BackupProgress::default(),
+            report,
+        }
+    }
+
+    pub(super) fn progress(&self) -> BackupProgress {
+        self.progress
+    }
+
+    /// Copies the file `from` to `to`, syncing the copy.
+    pub(super) fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut input = File::open(from)?;
+        let mut output = File::create(to)?;
+        let mut buf = vec![0; COPY_CHUNK];
+        loop {
+            let read = input.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            output.write_all(&buf[..read])?;
+            self.progress.bytes += read as u64;
+            (self.report)(&self.progress);
+        }
+        output.sync_all()?;
+        self.file_done();
+        Ok(())
+    }
+
+    /// Writes the entries of `snapshot` to the file `path`.
+    pub(super) fn write_stores(
+        &mut self,
+        path: &Path,
+        snapshot: &dyn StorageSnapshot,
+        compression: Compression,
+        encryption: Option<Encryption>,
+    ) -> io::Result<()> {
+        let entries = snapshot.scan((Bound::Unbounded, Bound::Unbounded))?;
+        let metadata = [("entries", entries.len().to_string())];
+        let out = BufWriter::new(File::create(path)?);
+        let mut out =
+            PdbWriter::with_options(out, KIND, KIND_VERSION, &metadata, compression, encryption)?;
+        let mut buf = Vec::new();
+        for (key, value) in &entries {
+            encode_bytes(key, &mut buf);
+            encode_bytes(value, &mut buf);
+            if buf.len() >= SECTION_SIZE {
+                out.write_section("entries", &buf)?;
+                self.progress.bytes += buf.len() as u64;
+                (self.report)(&self.progress);
+                buf.clear();
+            }
+        }
+        if !buf.is_empty() {
+            out.write_section("entries", &buf)?;
+            self.progress.bytes += buf.len() as u64;
+        }
+        out.finish()?.into_inner()?.sync_all()?;
+        self.file_done();
+        Ok(())
+    }
+
+    /// Puts the entries of the file `path`, written by
+    /// [`Copier::write_stores`], into `engine`.
+    pub(super) fn read_stores(
+        &mut self,
+        path: &Path,
+        engine: &dyn StorageEngine,
+        encryption: Option<&Encryption>,
+    ) -> io::Result<()> {
+        let mut reader = PdbReader::new(BufReader::new(File::open(path)?))?;
+        reader.expect_kind(KIND, KIND_VERSION)?;
+        if let Some(encryption) = encryption {
+            reader.decrypt_with(encryption.clone());
+        }
+        while let Some(section) = reader.next_section()? {
+            if section.name != "entries" {
+                continue;
+            }
+            let mut input = &section.payload[..];
+            while !input.is_empty() {
+                let key = decode_bytes(&mut input)?;
+                engine.put(key, decode_bytes(&mut input)?)?;
+            }
+            self.progress.bytes += section.payload.len() as u64;
+            (self.report)(&self.progress);
+        }
+        self.file_done();
+        Ok(())
+    }
+
+    fn file_done(&mut self) {
+        self.progress.files += 1;
+        (self.report)(&self.progress);
+    }
+}
+
+/// Returns the path a backup to `dir` is written to before being renamed
+/// to `dir` once complete.
+pub(super) fn temp_path(dir: &Path) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    dir.with_file_name(name)
+}
+
+/// Removes the file or directory `path`, if it exists.
+pub(super) fn remove_if_exists(path: &Path) -> io::Result<()> {
+    let removed = match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    };
+    match removed {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseBuilder, Error};
+    use crate::storage::BTreeEngine;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_backup_is_consistent_while_writes_go_on() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let engine = BTreeEngine::open(dir.join("engine")).unwrap();
+        let db = Arc::new(
+            DatabaseBuilder::new()
+                .engine(engine)
+                .open(dir.join("db"))
+                .unwrap(),
+        );
+        db.open_store::<String, u64>("users")
+            .unwrap()
+            .put(&"alice".to_string(), &31)
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (db, stop) = (db.clone(), stop.clone());
+            thread::spawn(move || {
+                let counters = db.open_durable::<u64, u64>("counters").unwrap();
+                let mut key = 0;
+                while !stop.load(Ordering::SeqCst) || key < 1000 {
+                    counters.put(&key, key).unwrap();
+                    key += 1;
+                }
+            })
+        };
+        while !db.contains_map("counters") {
+            thread::yield_now();
+        }
+        let mut reported = Vec::new();
+        let copied = db
+            .backup_to_with_progress(dir.join("backup"), |progress| reported.push(*progress))
+            .unwrap();
+        stop.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+        assert_eq!(reported.last(), Some(&copied));
+        assert!(reported
+            .windows(2)
+            .all(|pair| pair[0].bytes <= pair[1].bytes));
+        assert!(matches!(
+            db.backup_to(dir.join("backup")),
+            Err(Error::Io(_))
+        ));
+
+        // the writes kept are those made before some point in time
+        let engine = BTreeEngine::open(dir.join("restored-engine")).unwrap();
+        let restored = DatabaseBuilder::new()
+            .engine(engine)
+            .restore_from(dir.join("backup"), dir.join("restored"))
+            .unwrap();
+        let counters = restored.open_durable::<u64, u64>("counters").unwrap();
+        let len = counters.len() as u64;
+        assert!((0..len).all(|key| counters.get(&key) == Some(key)));
+        let users = restored.open_store::<String, u64>("users").unwrap();
+        assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+
+        // the log goes on from where the backup ends
+        counters.put(&len, len).unwrap();
+        drop((counters, users, restored));
+        let reopened = Database::open(dir.join("restored")).unwrap();
+        let counters = reopened.open_durable::<u64, u64>("counters").unwrap();
+        assert_eq!(counters.get(&len), Some(len));
+        // even when the backup holds no record past its checkpoint
+        reopened.checkpoint_now().unwrap();
+        reopened.backup_to(dir.join("checkpointed")).unwrap();
+        let restored = Database::restore_from(dir.join("checkpointed"), dir.join("again")).unwrap();
+        restored
+            .open_durable::<u64, u64>("counters")
+            .unwrap()
+            .put(&0, 7)
+            .unwrap();
+        drop(restored);
+        let restored = Database::open(dir.join("again")).unwrap();
+        let restored_counters = restored.open_durable::<u64, u64>("counters").unwrap();
+        assert_eq!(restored_counters.get(&0), Some(7));
+        drop(restored_counters);
+        drop(restored);
+        assert!(matches!(
+            Database::restore_from(dir.join("restored"), dir.join("not-a-backup")),
+            Err(Error::Io(_))
+        ));
+        drop(counters);
+        drop(reopened);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{
+    BackupProgress, CheckpointPolicy, Database, Migrations, RecoveryTarget, Result, VacuumPolicy,
+};
+use crate::collections::map::MapBuilder;
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+use crate::storage::{MemoryEngine, StorageEngine};
+use crate::wal::{SyncPolicy, WalBuilder};
+
+/// Configures and opens a [`Database`] stored on disk.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::collections::map::MapBuilder;
+/// use palladiumdb::db::{CheckpointPolicy, DatabaseBuilder};
+/// use palladiumdb::wal::{SyncPolicy, WalBuilder};
+/// use std::time::Duration;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-db-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let db = DatabaseBuilder::new()
+///     .map_builder(MapBuilder::new().bucket_count(1024))
+///     .wal_builder(WalBuilder::new().sync_policy(SyncPolicy::EveryNMillis(10)))
+///     .checkpoint_policy(CheckpointPolicy::Interval(Duration::from_secs(60)))
+///     .open(&dir)
+///     .unwrap();
+/// # drop(db);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct DatabaseBuilder<H = RandomState, E = MemoryEngine> {
+    pub(super) map_builder: MapBuilder<H>,
+    pub(super) engine: Arc<E>,
+    pub(super) wal_builder: WalBuilder,
+    pub(super) checkpoint_policy: CheckpointPolicy,
+    pub(super) keyspace_checkpoint_policies: HashMap<String, CheckpointPolicy>,
+    pub(super) compression: Compression,
+    pub(super) encryption: Option<Encryption>,
+    pub(super) read_only: bool,
+    pub(super) migrations: Migrations,
+    pub(super) vacuum_policy: Option<VacuumPolicy>,
+}
+
+// not derived, which would need the engine to be `Clone`
+impl<H: Clone, E> Clone for DatabaseBuilder<H, E> {
+    fn clone(&self) -> Self {
+        DatabaseBuilder {
+            map_builder: self.map_builder.clone(),
+            engine: self.engine.clone(),
+            wal_builder: self.wal_builder.clone(),
+            checkpoint_policy: self.checkpoint_policy,
+            keyspace_checkpoint_policies: self.keyspace_checkpoint_policies.clone(),
+            compression: self.compression,
+            encryption: self.encryption.clone(),
+            read_only: self.read_only,
+            migrations: self.migrations.clone(),
+            vacuum_policy: self.vacuum_policy,
+        }
+    }
+}
+
+impl DatabaseBuilder<RandomState> {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        DatabaseBuilder {
+            map_builder: MapBuilder::new(),
+            engine: Arc::new(MemoryEngine::new()),
+            wal_builder: WalBuilder::new(),
+            checkpoint_policy: CheckpointPolicy::default(),
+            keyspace_checkpoint_policies: HashMap::new(),
+            compression: Compression::None,
+            encryption: None,
+            read_only: false,
+            migrations: Migrations::new(),
+            vacuum_policy: None,
+        }
+    }
+}
+
+impl Default for DatabaseBuilder<RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H, E> DatabaseBuilder<H, E> {
+    /// Sets how the keyspaces of the database are configured.
+    pub fn map_builder<G>(self, map_builder: MapBuilder<G>) -> DatabaseBuilder<G, E> {
+        DatabaseBuilder {
+            map_builder,
+            engine: self.engine,
+            wal_builder: self.wal_builder,
+            checkpoint_policy: self.checkpoint_policy,
+            keyspace_checkpoint_policies: self.keyspace_checkpoint_policies,
+            compression: self.compression,
+            encryption: self.encryption,
+            read_only: self.read_only,
+            migrations: self.migrations,
+            vacuum_policy: self.vacuum_policy,
+        }
+    }
+
+    /// Sets the engine the stores of the database keep their entries in,
+    /// see [`Database::open_store`]. Defaults to a
+    /// [`MemoryEngine`](crate::storage::MemoryEngine), whose entries do not
+    /// survive the database.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::DatabaseBuilder;
+    /// use palladiumdb::storage::BTreeEngine;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-engine");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let engine = BTreeEngine::open(dir.join("entries")).unwrap();
+    /// let db = DatabaseBuilder::new().engine(engine).open(&dir).unwrap();
+    /// let users = db.open_store::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), &31).unwrap();
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn engine<F>(self, engine: F) -> DatabaseBuilder<H, F> {
+        DatabaseBuilder {
+            map_builder: self.map_builder,
+            engine: Arc::new(engine),
+            wal_builder: self.wal_builder,
+            checkpoint_policy: self.checkpoint_policy,
+            keyspace_checkpoint_policies: self.keyspace_checkpoint_policies,
+            compression: self.compression,
+            encryption: self.encryption,
+            read_only: self.read_only,
+            migrations: self.migrations,
+            vacuum_policy: self.vacuum_policy,
+        }
+    }
+
+    /// Sets how the log of the database is configured.
+    pub fn wal_builder(mut self, wal_builder: WalBuilder) -> Self {
+        self.wal_builder = wal_builder;
+        self
+    }
+
+    /// Sets when the writes to the durable keyspaces are synced to stable
+    /// storage, overriding the sync policy of the
+    /// [`wal_builder`](DatabaseBuilder::wal_builder). Writes made with
+    /// [`Durable::put_durable`](crate::wal::Durable::put_durable) are synced
+    /// whatever the policy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::DatabaseBuilder;
+    /// use palladiumdb::wal::SyncPolicy;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-sync-policy");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = DatabaseBuilder::new()
+    ///     .sync_policy(SyncPolicy::OnCheckpoint)
+    ///     .open(&dir)
+    ///     .unwrap();
+    /// let orders = db.open_durable::<u64, String>("orders").unwrap();
+    /// orders.put(&1, "pending".to_string()).unwrap();
+    /// orders.put_durable(&2, "paid".to_string()).unwrap();
+    /// db.checkpoint_now().unwrap();
+    /// # drop(orders);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.wal_builder = self.wal_builder.sync_policy(sync_policy);
+        self
+    }
+
+    /// Sets when the database checkpoints itself in the background.
+    pub fn checkpoint_policy(mut self, checkpoint_policy: CheckpointPolicy) -> Self {
+        self.checkpoint_policy = checkpoint_policy;
+        self
+    }
+
+    /// Sets when the durable keyspace `name` is checkpointed in the
+    /// background, instead of along with the others by the
+    /// [`checkpoint_policy`](DatabaseBuilder::checkpoint_policy), see
+    /// [`Database::checkpoint_keyspace`].
+    ///
+    /// Every keyspace is checkpointed to a file of its own, so that a large
+    /// keyspace written to rarely can be checkpointed rarely without
+    /// holding back the others. The log is only removed up to the oldest
+    /// checkpoint of a keyspace though. [`CheckpointPolicy::WalBytes`]
+    /// counts the bytes appended to the log by every keyspace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{CheckpointPolicy, DatabaseBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-keyspace-policy");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = DatabaseBuilder::new()
+    ///     .checkpoint_policy(CheckpointPolicy::Interval(Duration::from_secs(60)))
+    ///     .keyspace_checkpoint_policy("archive", CheckpointPolicy::Interval(Duration::from_secs(3600)))
+    ///     .open(&dir)
+    ///     .unwrap();
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn keyspace_checkpoint_policy(mut self, name: &str, policy: CheckpointPolicy) -> Self {
+        self.keyspace_checkpoint_policies
+            .insert(name.to_string(), policy);
+        self
+    }
+
+    /// Makes the database vacuum itself in the background every
+    /// [`VacuumPolicy::interval`], the engine only rewriting the files with
+    /// at least [`VacuumPolicy::garbage_ratio`] of garbage, see
+    /// [`Database::vacuum`]. Databases are only vacuumed by
+    /// `Database::vacuum` otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{DatabaseBuilder, VacuumPolicy};
+    /// use std::time::Duration;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-vacuum-policy");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let policy = VacuumPolicy {
+    ///     garbage_ratio: 0.25,
+    ///     interval: Duration::from_secs(600),
+    /// };
+    /// let db = DatabaseBuilder::new().vacuum_policy(policy).open(&dir).unwrap();
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn vacuum_policy(mut self, vacuum_policy: VacuumPolicy) -> Self {
+        self.vacuum_policy = Some(vacuum_policy);
+        self
+    }
+
+    /// Compresses the log and the checkpoints of the database, see
+    /// [`crate::compression`]. Log records and checkpoints are read
+    /// whatever their compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypts the log and the checkpoints of the database, see
+    /// [`crate::encryption`]. Log records written unencrypted before are
+    /// still replayed.
+    ///
+    /// To rotate keys, rotate the key of the provider, then take a
+    /// checkpoint with [`Database::checkpoint_now`]: it is written with the
+    /// new key and removes the log written with the old one, which can be
+    /// retired afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "encryption")]
+    /// # {
+    /// use palladiumdb::db::DatabaseBuilder;
+    /// use palladiumdb::encryption::{Encryption, Key, KeyRing};
+    /// use std::sync::Arc;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-encryption");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let ring = Arc::new(KeyRing::new(Key::new([7; 32])));
+    /// let encryption = Encryption::new(ring.clone()).unwrap();
+    /// let db = DatabaseBuilder::new().encryption(encryption).open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 100).unwrap();
+    ///
+    /// ring.rotate(Key::new([8; 32]));
+    /// db.checkpoint_now().unwrap();
+    /// assert!(ring.retire(1));
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// # }
+    /// ```
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Sets whether the database is opened read-only, in which case its
+    /// files are left untouched: the keyspaces recovered can be read, and
+    /// written to in memory through [`Database::open_map`], but writes to
+    /// durable keyspaces fail, as do checkpoints and the creation and
+    /// removal of durable keyspaces, with [`Error::ReadOnly`](super::Error::ReadOnly).
+    /// No checkpoint is taken in the background.
+    ///
+    /// A database can be open either for writing by a single process, or
+    /// read-only by any number of them, see [`LOCK_FILE`](super::LOCK_FILE).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, DatabaseBuilder, Error};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-read-only");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// db.open_durable::<u64, String>("orders").unwrap().put(&1, "paid".to_string()).unwrap();
+    /// drop(db);
+    ///
+    /// let db = DatabaseBuilder::new().read_only(true).open(&dir).unwrap();
+    /// let orders = db.open_durable::<u64, String>("orders").unwrap();
+    /// assert_eq!(orders.get(&1), Some("paid".to_string()));
+    /// assert!(orders.put(&2, "pending".to_string()).is_err());
+    /// assert!(matches!(db.checkpoint_now(), Err(Error::ReadOnly)));
+    ///
+    /// let writer = Database::open(&dir);
+    /// assert!(matches!(writer, Err(Error::AlreadyLocked { pid: None })));
+    /// # drop(orders);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets the steps bringing the files of the database to the current
+    /// on-disk format when it is opened, see [`Migrations`]. Defaults to
+    /// [`Migrations::new`], upgrading from the formats of earlier releases.
+    pub fn migrations(mut self, migrations: Migrations) -> Self {
+        self.migrations = migrations;
+        self
+    }
+
+    /// Opens the database stored in `dir`, creating it if it does not
+    /// exist, unless opened [read-only](DatabaseBuilder::read_only).
+    ///
+    /// The log is replayed over the last checkpoint to recover the durable
+    /// keyspaces, once its files are brought to the current format, see
+    /// [`DatabaseBuilder::migrations`]. A record left partially written at
+    /// its end by a crash is cut off, and records failing their checksum are
+    /// skipped rather than failing the open, both being accounted for in the
+    /// [`RecoveryReport`](super::RecoveryReport). The entries of a keyspace
+    /// are only decoded once it is opened with its key and value types.
+    ///
+    /// The directory is locked until the database is dropped, even if it is
+    /// closed before.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::AlreadyLocked`](super::Error::AlreadyLocked) if the database
+    /// is open for writing elsewhere, or read-only elsewhere while opened
+    /// for writing, [`Error::Io`](super::Error::Io) if the log or the
+    /// checkpoint cannot be read.
+    pub fn open<P: AsRef<Path>>(self, dir: P) -> Result<Database<H, E>>
+    where
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        Database::open_with(self, dir.as_ref())
+    }
+
+    /// Recovers the database stored in `dir` to a point in time, then opens
+    /// it: its log is replayed up to `target` only, over the checkpoint if
+    /// it was taken by then, such as to undo an accidental mass delete.
+    ///
+    /// The records logged after `target` are removed for good, as is the
+    /// checkpoint if it was taken after it, so a copy of the database should
+    /// be set aside first, see [`Database::backup_to`]. Only the durable
+    /// keyspaces are recovered, the stores of the engine being left as they
+    /// are. Times are found from the time marks of the log, which databases
+    /// append every second unless the
+    /// [`wal_builder`](DatabaseBuilder::wal_builder) sets another interval,
+    /// see [`WalBuilder::time_marks`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::AlreadyLocked`](super::Error::AlreadyLocked) if the database
+    /// is open, [`Error::ReadOnly`](super::Error::ReadOnly) if the builder
+    /// opens databases read-only, [`Error::Io`](super::Error::Io) of kind
+    /// `InvalidInput` if the checkpoint was taken after `target` and the
+    /// log before it was removed, as checkpoints do unless the log is kept,
+    /// see [`WalBuilder::retention`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{DatabaseBuilder, RecoveryTarget};
+    /// use palladiumdb::wal::WalBuilder;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-recover-to");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// // marks every append, so that any time can be recovered to
+    /// let builder = DatabaseBuilder::new().wal_builder(WalBuilder::new().time_marks(Duration::ZERO));
+    /// let db = builder.clone().open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// std::thread::sleep(Duration::from_millis(5));
+    /// let before_delete = SystemTime::now();
+    /// std::thread::sleep(Duration::from_millis(5));
+    /// users.remove(&"alice".to_string()).unwrap();
+    /// drop(users);
+    /// drop(db);
+    ///
+    /// let db = builder
+    ///     .recover_to(&dir, RecoveryTarget::Time(before_delete))
+    ///     .unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()), Some(31));
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn recover_to<P: AsRef<Path>>(
+        self,
+        dir: P,
+        target: RecoveryTarget,
+    ) -> Result<Database<H, E>>
+    where
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        Database::recover_with(self, dir.as_ref(), target)
+    }
+
+    /// Restores the backup `backup`, taken with
+    /// [`Database::backup_to`], to `dir`, then opens the database there,
+    /// see [`DatabaseBuilder::restore_from_with_progress`].
+    pub fn restore_from<P, Q>(self, backup: P, dir: Q) -> Result<Database<H, E>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        self.restore_from_with_progress(backup, dir, |_| {})
+    }
+
+    /// Restores the backup `backup`, taken with
+    /// [`Database::backup_to`], to `dir`, created if it does not exist,
+    /// then opens the database there, calling `progress` as files are
+    /// copied.
+    ///
+    /// The log and the checkpoint of a database stored in `dir` are
+    /// replaced by those of the backup, and the stores of the engine by
+    /// those of the backup. The builder must be configured as the backed up
+    /// database was, with its encryption key in particular.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::AlreadyLocked`](super::Error::AlreadyLocked) if a database
+    /// is open in `dir`, [`Error::ReadOnly`](super::Error::ReadOnly) if the
+    /// builder opens databases read-only, [`Error::Io`](super::Error::Io) if
+    /// `backup` is not a backup or cannot be copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, DatabaseBuilder};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-restore");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(dir.join("db")).unwrap();
+    /// let users = db.open_store::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), &31).unwrap();
+    /// db.backup_to(dir.join("backup")).unwrap();
+    ///
+    /// let mut bytes = 0;
+    /// let restored = DatabaseBuilder::new()
+    ///     .restore_from_with_progress(dir.join("backup"), dir.join("restored"), |progress| {
+    ///         bytes = progress.bytes
+    ///     })
+    ///     .unwrap();
+    /// let users = restored.open_store::<String, u64>("users").unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+    /// assert!(bytes > 0);
+    /// # drop(users);
+    /// # drop(restored);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn restore_from_with_progress<P, Q, F>(
+        self,
+        backup: P,
+        dir: Q,
+        mut progress: F,
+    ) -> Result<Database<H, E>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        F: FnMut(&BackupProgress),
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        Database::restore_with(self, backup.as_ref(), dir.as_ref(), &mut progress)
+    }
+}
@@ -0,0 +1,788 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use super::recovery::Recovered;
+use crate::checksum::crc32_update;
+use crate::codec::{decode_bytes, decode_len, encode_bytes, invalid_data, Decode, Encode};
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+use crate::files;
+use crate::pdb::{self, PdbReader, PdbWriter, SECTION_SIZE};
+use crate::wal::{Durable, Lsn, Mutation, Wal};
+
+/// Name of the file within the directory of a database listing the
+/// checkpoint of every keyspace, renamed over the previous one once
+/// written, so that a checkpoint is completed by a single rename.
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// Name of the directory within the directory of a database holding the
+/// checkpoint of every keyspace listed in the manifest, each in a file of
+/// its own.
+pub const KEYSPACES_DIR: &str = "keyspaces";
+
+/// Name of the file within the directory of a database holding the
+/// checkpoint of every keyspace, as written before the manifest. It is
+/// still read, and replaced by the manifest on the next checkpoint.
+pub const CHECKPOINT_FILE: &str = "CHECKPOINT";
+
+/// Kind of the PDB files holding a checkpoint, and version of their layout.
+const KIND: &str = "checkpoint";
+const KIND_VERSION: u32 = 1;
+
+/// Kind of the PDB file of the manifest, and version of its layout.
+const MANIFEST_KIND: &str = "manifest";
+const MANIFEST_VERSION: u32 = 1;
+
+const EXTENSION: &str = "ckpt";
+
+/// Magic bytes of the checkpoints written before PDB files, still read.
+const LEGACY_MAGIC: &[u8; 8] = b"PLDBCKPT";
+const LEGACY_VERSION: u32 = 1;
+
+/// When a [`Database`](super::Database) stored on disk checkpoints itself
+/// in the background, see [`Database::checkpoint_now`](super::Database::checkpoint_now).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CheckpointPolicy {
+    /// Checkpoints are only taken by `checkpoint_now`.
+    #[default]
+    Manual,
+    /// A checkpoint is taken every time the interval elapses.
+    Interval(Duration),
+    /// A checkpoint is taken every time that many bytes were appended to the
+    /// log since the last one.
+    WalBytes(u64),
+}
+
+/// Outcome of the checkpoints of a [`Database`](super::Database), see
+/// [`Database::checkpoint_status`](super::Database::checkpoint_status).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckpointStatus {
+    /// Number of checkpoints completed since the database was opened.
+    pub checkpoints: u64,
+    /// LSN the log is replayed from on recovery, if a checkpoint was ever
+    /// taken.
+    pub lsn: Option<Lsn>,
+    /// When the last checkpoint completed.
+    pub completed_at: Option<SystemTime>,
+    /// How long the last checkpoint took.
+    pub duration: Duration,
+    /// Size in bytes of the last checkpoint.
+    pub size: u64,
+    /// Bytes of log removed by the last checkpoint.
+    pub wal_bytes_removed: u64,
+    /// Error of the last checkpoint, if it failed.
+    pub last_error: Option<String>,
+}
+
+/// How often the log is measured when checkpointing every so many bytes.
+const WAL_BYTES_POLL: Duration = Duration::from_millis(100);
+
+/// Thread taking checkpoints according to a [`CheckpointPolicy`], stopped
+/// and joined when dropped.
+pub(super) struct Checkpointer {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    /// Starts calling `checkpoint` according to `policy`, if it is not
+    /// `Manual`, `wal` being the log it truncates.
+    pub(super) fn spawn<F>(
+        policy: CheckpointPolicy,
+        wal: Arc<Wal>,
+        mut checkpoint: F,
+    ) -> io::Result<Option<Self>>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let poll = match policy {
+            CheckpointPolicy::Manual => return Ok(None),
+            CheckpointPolicy::Interval(interval) => interval,
+            CheckpointPolicy::WalBytes(_) => WAL_BYTES_POLL,
+        };
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stopped = stop.clone();
+        let handle = thread::Builder::new()
+            .name("palladiumdb-checkpoint".to_string())
+            .spawn(move || {
+                let (stopped, wakeup) = &*stopped;
+                let mut checkpointed_len = wal.appended_len();
+                loop {
+                    let guard = stopped.lock().unwrap();
+                    let (guard, _) = wakeup
+                        .wait_timeout_while(guard, poll, |stopped| !*stopped)
+                        .unwrap();
+                    if *guard {
+                        return;
+                    }
+                    drop(guard);
+
+                    let appended_len = wal.appended_len();
+                    let due = match policy {
+                        CheckpointPolicy::WalBytes(bytes) => {
+                            appended_len - checkpointed_len >= bytes
+                        }
+                        _ => true,
+                    };
+                    if due {
+                        checkpointed_len = appended_len;
+                        checkpoint();
+                    }
+                }
+            })?;
+        Ok(Some(Checkpointer {
+            stop,
+            handle: Some(handle),
+        }))
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wakeup.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A durable keyspace, with its key and value types erased.
+pub(super) trait Checkpointed: Send + Sync {
+    /// Calls `write` with a logged put for every entry of the keyspace.
+    fn write_entries(&self, write: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()>;
+
+    /// Applies a logged write without logging it, as replicated from a
+    /// primary.
+    #[cfg(feature = "server")]
+    fn apply(&self, mutation: &[u8]) -> io::Result<()>;
+}
+
+impl<K, V, H> Checkpointed for Durable<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode + Send + Sync,
+    V: Clone + Encode + Decode + Send + Sync,
+    H: BuildHasher + Send + Sync,
+{
+    fn write_entries(&self, write: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        let mut mutation = Vec::new();
+        for (key, value) in self.map().iter() {
+            mutation.clear();
+            Mutation::encode_put(&mut mutation, &key, &value);
+            write(&mutation)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "server")]
+    fn apply(&self, mutation: &[u8]) -> io::Result<()> {
+        crate::wal::apply_mutation(self.map(), mutation)
+    }
+}
+
+/// The entries of a keyspace to checkpoint.
+pub(super) enum Entries {
+    /// An opened keyspace, copied one bucket at a time.
+    Open(Arc<dyn Checkpointed>),
+    /// A keyspace not opened since it was recovered, whose logged writes are
+    /// copied as they are.
+    Pending(Vec<Vec<u8>>),
+}
+
+/// Writes the checkpoint of `keyspaces`, each with its id and name, taken
+/// at `lsn`, to `path`, next to it then renamed over it once synced.
+///
+/// The checkpoint is a [PDB file](crate::pdb) of kind `checkpoint`, with a
+/// `keyspace` section per keyspace followed by `mutations` sections holding
+/// its entries as logged puts, compressed with `compression` and encrypted
+/// if `encryption` is set.
+///
+/// # Returns
+///
+/// The size in bytes of the checkpoint.
+pub(super) fn write(
+    path: &Path,
+    lsn: Lsn,
+    next_id: u64,
+    keyspaces: &[(u64, String, Entries)],
+    compression: Compression,
+    encryption: Option<Encryption>,
+) -> io::Result<u64> {
+    let temp = path.with_extension("tmp");
+    let metadata = [
+        ("lsn", lsn.to_string()),
+        ("next_id", next_id.to_string()),
+        ("keyspaces", keyspaces.len().to_string()),
+        ("palladiumdb_version", env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    let out = BufWriter::new(File::create(&temp)?);
+    let mut out =
+        PdbWriter::with_options(out, KIND, KIND_VERSION, &metadata, compression, encryption)?;
+
+    let mut buf = Vec::new();
+    for (id, name, entries) in keyspaces {
+        buf.clear();
+        id.encode(&mut buf);
+        name.encode(&mut buf);
+        out.write_section("keyspace", &buf)?;
+
+        buf.clear();
+        let mut write = |mutation: &[u8]| {
+            encode_bytes(mutation, &mut buf);
+            if buf.len() >= SECTION_SIZE {
+                out.write_section("mutations", &buf)?;
+                buf.clear();
+            }
+            Ok(())
+        };
+        match entries {
+            Entries::Open(keyspace) => keyspace.write_entries(&mut write)?,
+            Entries::Pending(mutations) => {
+                for mutation in mutations {
+                    write(mutation)?;
+                }
+            }
+        }
+        if !buf.is_empty() {
+            out.write_section("mutations", &buf)?;
+        }
+    }
+
+    let file = out.finish()?.into_inner()?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+    files::rename_durably(&temp, path)?;
+    Ok(size)
+}
+
+/// Returns the path of the checkpoint of the keyspace `id` taken at `lsn`,
+/// within the directory of a database `dir`.
+pub(super) fn keyspace_path(dir: &Path, id: u64, lsn: Lsn) -> PathBuf {
+    dir.join(KEYSPACES_DIR)
+        .join(format!("{:020}-{:020}.{}", id, lsn, EXTENSION))
+}
+
+/// The id, name and checkpoint of the keyspaces listed in a manifest.
+type Listing = Vec<(u64, String, KeyspaceFile)>;
+
+/// The checkpoint of a keyspace listed in the manifest.
+#[derive(Clone, Debug)]
+pub(super) struct KeyspaceFile {
+    /// LSN the checkpoint was taken at, the writes to the keyspace logged
+    /// before it being reflected in it.
+    pub(super) lsn: Lsn,
+    pub(super) path: PathBuf,
+}
+
+/// The checkpoints of a database, as read back when opening it.
+pub(super) struct Checkpoint {
+    pub(super) recovered: Recovered,
+    /// LSN the manifest was written at, the creations and removals of
+    /// keyspaces logged before it being reflected in it.
+    pub(super) lsn: Lsn,
+    /// Checkpoints of the keyspaces listed in the manifest, empty if read
+    /// from a [`CHECKPOINT_FILE`].
+    pub(super) files: HashMap<u64, KeyspaceFile>,
+    /// LSN every keyspace was checkpointed at.
+    pub(super) keyspace_lsns: HashMap<u64, Lsn>,
+}
+
+impl Checkpoint {
+    /// Returns the LSN the log is replayed from, that of the oldest
+    /// checkpoint of a keyspace.
+    pub(super) fn replay_from(&self) -> Lsn {
+        self.keyspace_lsns
+            .values()
+            .fold(self.lsn, |min, lsn| min.min(*lsn))
+    }
+}
+
+/// Writes the manifest of `dir`, taken at `lsn`, listing the checkpoint of
+/// every keyspace of `keyspaces` with its id and name, replacing the
+/// previous one once synced.
+///
+/// The manifest is a [PDB file](crate::pdb) of kind `manifest`, with a
+/// `keyspace` section per keyspace holding its id, its name, the LSN of its
+/// checkpoint and the name of its file in [`KEYSPACES_DIR`], encrypted if
+/// `encryption` is set.
+///
+/// # Returns
+///
+/// The size in bytes of the manifest.
+pub(super) fn write_manifest(
+    dir: &Path,
+    lsn: Lsn,
+    next_id: u64,
+    keyspaces: &[(u64, String, KeyspaceFile)],
+    encryption: Option<Encryption>,
+) -> io::Result<u64> {
+    let path = dir.join(MANIFEST_FILE);
+    let temp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+    let metadata = [
+        ("lsn", lsn.to_string()),
+        ("next_id", next_id.to_string()),
+        ("keyspaces", keyspaces.len().to_string()),
+        ("palladiumdb_version", env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    let out = BufWriter::new(File::create(&temp)?);
+    let mut out = PdbWriter::with_options(
+        out,
+        MANIFEST_KIND,
+        MANIFEST_VERSION,
+        &metadata,
+        Compression::None,
+        encryption,
+    )?;
+    let mut buf = Vec::new();
+    for (id, name, file) in keyspaces {
+        let file_name = file
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid_data("keyspace checkpoint without a file name"))?;
+        buf.clear();
+        id.encode(&mut buf);
+        name.encode(&mut buf);
+        file.lsn.encode(&mut buf);
+        file_name.encode(&mut buf);
+        out.write_section("keyspace", &buf)?;
+    }
+    let file = out.finish()?.into_inner()?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+    files::rename_durably(&temp, &path)?;
+    Ok(size)
+}
+
+/// Reads the manifest of `dir`, decrypting it with `encryption`.
+///
+/// # Returns
+///
+/// The LSN it was written at, the id the next keyspace created gets, and
+/// the id, name and checkpoint of every keyspace.
+fn read_manifest(dir: &Path, encryption: Option<&Encryption>) -> io::Result<(Lsn, u64, Listing)> {
+    let mut reader = PdbReader::new(BufReader::new(File::open(dir.join(MANIFEST_FILE))?))?;
+    reader.expect_kind(MANIFEST_KIND, MANIFEST_VERSION)?;
+    if let Some(encryption) = encryption {
+        reader.decrypt_with(encryption.clone());
+    }
+    let lsn = reader.parse_metadata("lsn")?;
+    let next_id = reader.parse_metadata("next_id")?;
+    let mut keyspaces = Vec::new();
+    while let Some(section) = reader.next_section()? {
+        let mut input = &section.payload[..];
+        if section.name == "keyspace" {
+            let id = u64::decode(&mut input)?;
+            let name = String::decode(&mut input)?;
+            let lsn = Lsn::decode(&mut input)?;
+            let file_name = String::decode(&mut input)?;
+            let path = dir.join(KEYSPACES_DIR).join(file_name);
+            keyspaces.push((id, name, KeyspaceFile { lsn, path }));
+        }
+    }
+    Ok((lsn, next_id, keyspaces))
+}
+
+/// Reads the checkpoints of `dir`, if any, decrypting them with
+/// `encryption`: the manifest and the checkpoints of the keyspaces it
+/// lists, or else the [`CHECKPOINT_FILE`] of earlier releases.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if a checkpoint is corrupted or missing.
+pub(super) fn read(dir: &Path, encryption: Option<&Encryption>) -> io::Result<Option<Checkpoint>> {
+    if !dir.join(MANIFEST_FILE).exists() {
+        let (recovered, lsn) = match read_file(&dir.join(CHECKPOINT_FILE), encryption)? {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(None),
+        };
+        let keyspace_lsns = recovered.ids.values().map(|id| (*id, lsn)).collect();
+        return Ok(Some(Checkpoint {
+            recovered,
+            lsn,
+            files: HashMap::new(),
+            keyspace_lsns,
+        }));
+    }
+
+    let (lsn, next_id, keyspaces) = read_manifest(dir, encryption)?;
+    let mut checkpoint = Checkpoint {
+        recovered: Recovered {
+            next_id,
+            ..Recovered::default()
+        },
+        lsn,
+        files: HashMap::new(),
+        keyspace_lsns: HashMap::new(),
+    };
+    for (id, name, file) in keyspaces {
+        let (mut recovered, _) = read_file(&file.path, encryption)?
+            .ok_or_else(|| invalid_data("keyspace checkpoint missing"))?;
+        let pending = recovered.pending.remove(&id).unwrap_or_default();
+        checkpoint.recovered.ids.insert(name, id);
+        checkpoint.recovered.pending.insert(id, pending);
+        checkpoint.keyspace_lsns.insert(id, file.lsn);
+        checkpoint.files.insert(id, file);
+    }
+    Ok(Some(checkpoint))
+}
+
+/// Returns the paths of the checkpoint files of `dir` relative to it: the
+/// manifest, the checkpoints of the keyspaces, and the
+/// [`CHECKPOINT_FILE`] of earlier releases.
+pub(super) fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for name in &[MANIFEST_FILE, CHECKPOINT_FILE] {
+        if dir.join(name).is_file() {
+            files.push(PathBuf::from(name));
+        }
+    }
+    match fs::read_dir(dir.join(KEYSPACES_DIR)) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) == Some(EXTENSION) {
+                    files.push(Path::new(KEYSPACES_DIR).join(path.file_name().unwrap()));
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the checkpoint file `path` of `dir` as listed by [`files`],
+/// checking that it is not corrupted.
+pub(super) fn check_file(
+    dir: &Path,
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> io::Result<()> {
+    match path == Path::new(MANIFEST_FILE) {
+        true => read_manifest(dir, encryption).map(drop),
+        false => read_file(&dir.join(path), encryption).map(drop),
+    }
+}
+
+/// Removes the checkpoints of `dir`, the manifest first so that a crash in
+/// the middle leaves none listed.
+pub(super) fn remove(dir: &Path) -> io::Result<()> {
+    super::backup::remove_if_exists(&dir.join(MANIFEST_FILE))?;
+    super::backup::remove_if_exists(&dir.join(CHECKPOINT_FILE))?;
+    super::backup::remove_if_exists(&dir.join(KEYSPACES_DIR))
+}
+
+/// Reads the checkpoint file at `path`, if any, decrypting it with
+/// `encryption`. Checkpoints written by earlier releases, before PDB files,
+/// are read as well.
+///
+/// # Returns
+///
+/// The keyspaces of the checkpoint along with the LSN it was taken at, or
+/// an error of kind `InvalidData` if it is corrupted.
+pub(super) fn read_file(
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> io::Result<Option<(Recovered, Lsn)>> {
+    let mut input = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic == LEGACY_MAGIC {
+        let mut bytes = magic.to_vec();
+        input.read_to_end(&mut bytes)?;
+        return read_legacy(&bytes).map(Some);
+    }
+    if &magic != pdb::MAGIC {
+        return Err(invalid_data("not a checkpoint"));
+    }
+
+    let mut reader = PdbReader::after_magic(input)?;
+    reader.expect_kind(KIND, KIND_VERSION)?;
+    if let Some(encryption) = encryption {
+        reader.decrypt_with(encryption.clone());
+    }
+    let lsn = reader.parse_metadata("lsn")?;
+    let mut recovered = Recovered {
+        ids: HashMap::new(),
+        pending: HashMap::new(),
+        next_id: reader.parse_metadata("next_id")?,
+    };
+    let mut keyspace = None;
+    while let Some(section) = reader.next_section()? {
+        let mut input = &section.payload[..];
+        match section.name.as_str() {
+            "keyspace" => {
+                let id = u64::decode(&mut input)?;
+                recovered.ids.insert(String::decode(&mut input)?, id);
+                keyspace = Some(recovered.pending.entry(id).or_default());
+            }
+            "mutations" => {
+                let pending = keyspace
+                    .as_mut()
+                    .ok_or_else(|| invalid_data("checkpoint mutations without a keyspace"))?;
+                while !input.is_empty() {
+                    pending.push(decode_bytes(&mut input)?.to_vec());
+                }
+            }
+            // sections added by later releases are skipped
+            _ => {}
+        }
+    }
+    Ok(Some((recovered, lsn)))
+}
+
+/// Rewrites the checkpoint of `dir` as a PDB file if it was written before
+/// them, uncompressed and unencrypted as those were.
+///
+/// # Returns
+///
+/// `true` if the checkpoint was rewritten.
+pub(super) fn upgrade_legacy(dir: &Path) -> io::Result<bool> {
+    if !is_legacy(dir)? {
+        return Ok(false);
+    }
+    let path = dir.join(CHECKPOINT_FILE);
+    let (recovered, lsn) = match read_file(&path, None)? {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(false),
+    };
+    let mut pending = recovered.pending;
+    let mut keyspaces: Vec<_> = recovered
+        .ids
+        .into_iter()
+        .map(|(name, id)| {
+            let mutations = pending.remove(&id).unwrap_or_default();
+            (id, name, Entries::Pending(mutations))
+        })
+        .collect();
+    keyspaces.sort_by_key(|(id, _, _)| *id);
+    write(
+        &path,
+        lsn,
+        recovered.next_id,
+        &keyspaces,
+        Compression::None,
+        None,
+    )?;
+    Ok(true)
+}
+
+/// Returns `true` if the checkpoint of `dir` was written before PDB files.
+pub(super) fn is_legacy(dir: &Path) -> io::Result<bool> {
+    let mut magic = [0; 8];
+    match File::open(dir.join(CHECKPOINT_FILE)) {
+        Ok(mut file) => match file.read_exact(&mut magic) {
+            Ok(()) => Ok(&magic == LEGACY_MAGIC),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads a checkpoint written before PDB files.
+fn read_legacy(bytes: &[u8]) -> io::Result<(Recovered, Lsn)> {
+    if bytes.len() < LEGACY_MAGIC.len() + 4 {
+        return Err(invalid_data("not a checkpoint"));
+    }
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    if crc32_update(0, body) != u32::decode(&mut &crc[..])? {
+        return Err(invalid_data("checkpoint checksum mismatch"));
+    }
+
+    let mut input = &body[LEGACY_MAGIC.len()..];
+    if u32::decode(&mut input)? != LEGACY_VERSION {
+        return Err(invalid_data("unsupported checkpoint version"));
+    }
+    let lsn = Lsn::decode(&mut input)?;
+    let mut recovered = Recovered {
+        ids: HashMap::new(),
+        pending: HashMap::new(),
+        next_id: u64::decode(&mut input)?,
+    };
+    let count = decode_len(&mut input)?;
+    for _ in 0..count {
+        let id = u64::decode(&mut input)?;
+        let name = String::decode(&mut input)?;
+        let mut mutations = Vec::new();
+        loop {
+            let mutation = decode_bytes(&mut input)?;
+            if mutation.is_empty() {
+                break;
+            }
+            mutations.push(mutation.to_vec());
+        }
+        recovered.ids.insert(name, id);
+        recovered.pending.insert(id, mutations);
+    }
+    if !input.is_empty() {
+        return Err(invalid_data("trailing bytes in checkpoint"));
+    }
+    Ok((recovered, lsn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckpointPolicy, KEYSPACES_DIR};
+    use crate::db::{Database, DatabaseBuilder, WAL_DIR};
+    use crate::wal::WalBuilder;
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn wal_len(dir: &Path) -> u64 {
+        fs::read_dir(dir.join(WAL_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn test_checkpoints_truncate_log_and_survive_reopen() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let wal_builder = WalBuilder::new().segment_size(4096);
+
+        let db = Database::open(&dir).unwrap();
+        let archived = db.open_durable::<u64, u64>("archived").unwrap();
+        archived.put(&7, 49).unwrap();
+        drop((db, archived));
+
+        // the archived keyspace is carried through checkpoints unopened
+        let db = DatabaseBuilder::new()
+            .wal_builder(wal_builder.clone())
+            .open(&dir)
+            .unwrap();
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        for key in 0..1000 {
+            accounts.put(&key, key).unwrap();
+        }
+        let before = wal_len(&dir);
+        db.checkpoint_now().unwrap();
+        let status = db.checkpoint_status().unwrap();
+        assert_eq!(status.checkpoints, 1);
+        assert!(status.wal_bytes_removed > 0);
+        assert_eq!(status.last_error, None);
+        assert!(wal_len(&dir) < before);
+
+        for key in 0..500 {
+            accounts.remove(&key).unwrap();
+        }
+        accounts.put(&1000, 1000).unwrap();
+        let lsn = status.lsn;
+        drop((db, accounts));
+
+        let db = DatabaseBuilder::new()
+            .wal_builder(wal_builder.clone())
+            .checkpoint_policy(CheckpointPolicy::WalBytes(1024))
+            .open(&dir)
+            .unwrap();
+        let report = db.recovery_report().unwrap();
+        assert_eq!(report.checkpoint_lsn, lsn);
+        assert_eq!(report.records_replayed, 501);
+        assert_eq!(report.keyspaces, 2);
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        assert_eq!(accounts.len(), 501);
+        assert_eq!(accounts.get(&499), None);
+        assert_eq!(accounts.get(&500), Some(500));
+
+        // enough writes for the background checkpointer to kick in
+        for key in 0..200 {
+            accounts.put(&key, key).unwrap();
+        }
+        let started = Instant::now();
+        while db.checkpoint_status().unwrap().checkpoints == 0 {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        db.close().unwrap();
+        drop((db, accounts));
+
+        let db = Database::open(&dir).unwrap();
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        assert_eq!(accounts.len(), 701);
+        assert_eq!(accounts.get(&1000), Some(1000));
+        let archived = db.open_durable::<u64, u64>("archived").unwrap();
+        assert_eq!(archived.get(&7), Some(49));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keyspaces_are_checkpointed_independently() {
+        let dir = std::env::temp_dir().join(format!(
+            "palladiumdb-checkpoint-keyspaces-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let keyspace_files = || {
+            let mut files: Vec<_> = fs::read_dir(dir.join(KEYSPACES_DIR))
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            files.sort();
+            files
+        };
+
+        let db = Database::open(&dir).unwrap();
+        let archive = db.open_durable::<u64, u64>("archive").unwrap();
+        let recent = db.open_durable::<u64, u64>("recent").unwrap();
+        let dropped = db.open_durable::<u64, u64>("dropped").unwrap();
+        for key in 0..100 {
+            archive.put(&key, key).unwrap();
+            recent.put(&key, key).unwrap();
+        }
+        dropped.put(&1, 1).unwrap();
+        db.checkpoint_now().unwrap();
+        assert_eq!(keyspace_files().len(), 3);
+        let archive_file = db.checkpoint_keyspace("archive").unwrap().unwrap();
+
+        // the archive is left out of the checkpoints of the recent keyspace
+        archive.put(&100, 100).unwrap();
+        recent.put(&100, 100).unwrap();
+        assert!(db.drop_map("dropped").unwrap());
+        let recent_file = db.checkpoint_keyspace("recent").unwrap().unwrap();
+        assert_eq!(keyspace_files(), vec![archive_file.clone(), recent_file]);
+        recent.put(&101, 101).unwrap();
+        assert!(db.verify().unwrap().is_clean());
+        drop((db, archive, recent, dropped));
+
+        let db = Database::open(&dir).unwrap();
+        // the put to the archive and that to the recent keyspace after its
+        // checkpoint
+        assert_eq!(db.recovery_report().unwrap().records_replayed, 2);
+        assert_eq!(db.recovery_report().unwrap().keyspaces, 2);
+        let archive = db.open_durable::<u64, u64>("archive").unwrap();
+        let recent = db.open_durable::<u64, u64>("recent").unwrap();
+        assert_eq!((archive.len(), recent.len()), (101, 102));
+        db.backup_to(dir.join("backup")).unwrap();
+        drop((db, archive, recent));
+
+        let restored = Database::restore_from(dir.join("backup"), dir.join("restored")).unwrap();
+        let archive = restored.open_durable::<u64, u64>("archive").unwrap();
+        assert_eq!(archive.get(&100), Some(100));
+        assert!(restored
+            .open_durable::<u64, u64>("dropped")
+            .unwrap()
+            .is_empty());
+        drop((restored, archive));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
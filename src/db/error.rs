@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by a [`Database`](super::Database).
+#[derive(Debug)]
+pub enum Error {
+    /// A collection already exists under `name` with other key or value
+    /// types than requested.
+    TypeMismatch {
+        name: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The database was closed.
+    Closed,
+    /// The operation needs a database stored on disk, see
+    /// [`Database::open`](super::Database::open).
+    InMemory,
+    /// The directory of the database is locked by another database open for
+    /// writing, by the process `pid` if known, or by read-only ones, see
+    /// [`LOCK_FILE`](super::LOCK_FILE).
+    AlreadyLocked { pid: Option<u32> },
+    /// The operation writes to a database opened read-only, see
+    /// [`DatabaseBuilder::read_only`](super::DatabaseBuilder::read_only).
+    ReadOnly,
+    /// Reading or writing the files of the database failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TypeMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "collection {:?} holds {}, not {}", name, found, expected),
+            Error::Closed => write!(f, "database is closed"),
+            Error::InMemory => write!(f, "database is not stored on disk"),
+            Error::AlreadyLocked { pid: Some(pid) } => {
+                write!(f, "database is locked by process {}", pid)
+            }
+            Error::AlreadyLocked { pid: None } => write!(f, "database is locked"),
+            Error::ReadOnly => write!(f, "database is read-only"),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Result of the fallible operations of a [`Database`](super::Database).
+pub type Result<T> = std::result::Result<T, Error>;
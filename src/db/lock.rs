@@ -0,0 +1,147 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{Error, Result};
+
+/// Name of the file locked by the processes having a database open, within
+/// its directory. It holds the id of the process having it open for writing,
+/// if any.
+pub const LOCK_FILE: &str = "LOCK";
+
+/// Lock on the directory of a database, released when dropped, or when the
+/// process exits whatever the way.
+///
+/// Databases opened for writing hold it exclusively, read-only ones share
+/// it, so a database is open either for writing by a single process or
+/// read-only by any number of them. As the lock is tied to the open file
+/// rather than to the process, the same rule holds between the databases of
+/// a process.
+pub(super) struct DirLock {
+    file: File,
+    exclusive: bool,
+}
+
+impl DirLock {
+    /// Locks `dir`, exclusively unless `read_only`, creating the lock file
+    /// if it does not exist.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::AlreadyLocked`] if the lock is held by another database,
+    /// [`Error::Io`] if the lock file cannot be opened.
+    pub(super) fn acquire(dir: &Path, read_only: bool) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = match read_only {
+            true => match File::open(&path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    drop(OpenOptions::new().create(true).append(true).open(&path)?);
+                    File::open(&path)?
+                }
+                file => file?,
+            },
+            false => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?,
+        };
+        let locked = match read_only {
+            true => file.try_lock_shared(),
+            false => file.try_lock(),
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::AlreadyLocked {
+                    pid: read_pid(&mut file),
+                })
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        if !read_only {
+            file.set_len(0)?;
+            file.write_all(std::process::id().to_string().as_bytes())?;
+            file.sync_data()?;
+        }
+        Ok(DirLock {
+            file,
+            exclusive: !read_only,
+        })
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // cleared so the id of a process gone is not reported to the
+        // databases later contending with read-only ones
+        if self.exclusive {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
+
+/// Returns the id of the process holding the lock file for writing, `None`
+/// if it holds none, such as when read-only databases hold it.
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LOCK_FILE;
+    use crate::db::{Database, DatabaseBuilder, Error};
+    use std::fs;
+
+    #[test]
+    fn test_writer_excludes_every_other_open() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-lock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let read_only = || DatabaseBuilder::new().read_only(true).open(&dir);
+        assert!(read_only().is_err());
+
+        let db = Database::open(&dir).unwrap();
+        db.open_durable::<u64, u64>("counters")
+            .unwrap()
+            .put(&1, 1)
+            .unwrap();
+        let pid = Some(std::process::id());
+        assert!(matches!(Database::open(&dir), Err(Error::AlreadyLocked { pid: p }) if p == pid));
+        assert!(matches!(read_only(), Err(Error::AlreadyLocked { pid: p }) if p == pid));
+        db.close().unwrap();
+        assert!(matches!(read_only(), Err(Error::AlreadyLocked { .. })));
+        drop(db);
+        assert_eq!(fs::read(dir.join(LOCK_FILE)).unwrap(), b"");
+
+        let readers = [read_only().unwrap(), read_only().unwrap()];
+        assert!(matches!(
+            Database::open(&dir),
+            Err(Error::AlreadyLocked { pid: None })
+        ));
+        for reader in &readers {
+            assert!(reader.is_read_only());
+            let counters = reader.open_durable::<u64, u64>("counters").unwrap();
+            assert_eq!(counters.get(&1), Some(1));
+            assert!(counters.put(&2, 2).is_err());
+            assert!(matches!(reader.drop_map("counters"), Err(Error::ReadOnly)));
+            assert!(matches!(
+                reader.open_durable::<u64, u64>("other"),
+                Err(Error::ReadOnly)
+            ));
+        }
+        drop(readers);
+
+        let db = Database::open(&dir).unwrap();
+        assert!(!db.is_read_only());
+        // checkpointed as it was closed, read-only opens leaving it as is
+        let report = db.recovery_report().unwrap();
+        assert!(report.clean_shutdown);
+        assert_eq!(report.records_replayed, 0);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{checkpoint, CHECKPOINT_FILE, MANIFEST_FILE, WAL_DIR};
+use crate::codec::invalid_data;
+use crate::files;
+
+/// Name of the file recording the version of the on-disk format of a
+/// database, within its directory. Databases written before it count as
+/// version 1.
+pub const FORMAT_FILE: &str = "FORMAT";
+
+/// Version of the on-disk format written by this release.
+///
+/// - 1: checkpoints in their own format, before PDB files.
+/// - 2: checkpoints as [PDB files](crate::pdb).
+/// - 3: a manifest listing a checkpoint per keyspace, see
+///   [`MANIFEST_FILE`](super::MANIFEST_FILE).
+pub const FORMAT_VERSION: u32 = 3;
+
+type Step = Arc<dyn Fn(&Path) -> io::Result<()> + Send + Sync>;
+
+/// Outcome of bringing the files of a database to the current format, see
+/// [`Migrations::run`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Version of the format the files were in.
+    pub from: u32,
+    /// Version of the format the files are in now, or would be if not a
+    /// dry run.
+    pub to: u32,
+    /// Descriptions of the steps run, or to run if a dry run, in order.
+    pub steps: Vec<String>,
+    /// Whether the steps were only listed, the files being left untouched.
+    pub dry_run: bool,
+}
+
+/// Steps bringing the files of a database from one version of the on-disk
+/// format to the next, run when it is opened for writing so that upgrading
+/// the crate does not take dumping and reloading the data.
+///
+/// Every step is registered with the version it upgrades from, and records
+/// the next one once it completes, in [`FORMAT_FILE`], so that a database
+/// interrupted in the middle of a migration resumes from the last step
+/// completed. Steps must be idempotent all the same, as one interrupted is
+/// run again.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::{Database, DatabaseBuilder, Migrations, FORMAT_VERSION};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-db-migrations");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// drop(Database::open(&dir).unwrap());
+///
+/// // a later release of the application renames a file of its own
+/// fs::write(dir.join("settings.v1"), "theme=dark").unwrap();
+/// let migrations = Migrations::new().register(FORMAT_VERSION, "rename settings", |dir| {
+///     match dir.join("settings.v1").exists() {
+///         true => fs::rename(dir.join("settings.v1"), dir.join("settings")),
+///         false => Ok(()),
+///     }
+/// });
+/// let report = migrations.dry_run(&dir).unwrap();
+/// assert_eq!(report.steps, vec!["rename settings".to_string()]);
+/// assert!(dir.join("settings.v1").exists());
+///
+/// let db = DatabaseBuilder::new().migrations(migrations).open(&dir).unwrap();
+/// assert_eq!(db.migration_report().unwrap().to, FORMAT_VERSION + 1);
+/// assert!(dir.join("settings").exists());
+/// # drop(db);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Migrations {
+    steps: BTreeMap<u32, (String, Step)>,
+}
+
+impl Migrations {
+    /// Creates the migrations of the formats of this release.
+    pub fn new() -> Self {
+        Migrations {
+            steps: BTreeMap::new(),
+        }
+        .register(1, "rewrite the checkpoint as a PDB file", |dir| {
+            checkpoint::upgrade_legacy(dir).map(drop)
+        })
+        // the checkpoint is still read, until the next one writes the
+        // manifest, which earlier releases must not miss
+        .register(2, "checkpoint keyspaces to files of their own", |_| Ok(()))
+    }
+
+    /// Registers `step`, bringing the files of the database in the
+    /// directory it is given from version `from` of the format to the next,
+    /// replacing the step registered from `from` if any.
+    ///
+    /// Databases are brought to the version following the last step
+    /// registered, [`FORMAT_VERSION`] unless steps are registered from it
+    /// on, such as by applications keeping files of their own next to those
+    /// of the database.
+    pub fn register<D, F>(mut self, from: u32, description: D, step: F) -> Self
+    where
+        D: Into<String>,
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.steps
+            .insert(from, (description.into(), Arc::new(step)));
+        self
+    }
+
+    /// Returns the version of the format databases are brought to.
+    pub fn target_version(&self) -> u32 {
+        let last = self.steps.keys().next_back().map_or(0, |from| from + 1);
+        last.max(FORMAT_VERSION)
+    }
+
+    /// Brings the files of the database in `dir` to the
+    /// [target version](Migrations::target_version), recording it. The
+    /// database must not be open.
+    ///
+    /// A directory holding no database yet is recorded as being in the
+    /// target version, without running any step.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the files are in a version newer
+    /// than the target one, or if a step is missing on the way to it, the
+    /// error of the step failing otherwise.
+    pub fn run<P: AsRef<Path>>(&self, dir: P) -> io::Result<MigrationReport> {
+        self.migrate(dir.as_ref(), false)
+    }
+
+    /// Returns the steps [`run`](Migrations::run) would run on the database
+    /// in `dir`, leaving its files untouched.
+    pub fn dry_run<P: AsRef<Path>>(&self, dir: P) -> io::Result<MigrationReport> {
+        self.migrate(dir.as_ref(), true)
+    }
+
+    fn migrate(&self, dir: &Path, dry_run: bool) -> io::Result<MigrationReport> {
+        let to = self.target_version();
+        let from = match read_version(dir)? {
+            Some(version) => version,
+            None if is_empty(dir)? => to,
+            None => 1,
+        };
+        if from > to {
+            let message = format!(
+                "database format version {} is newer than the supported {}",
+                from, to
+            );
+            return Err(invalid_data(&message));
+        }
+
+        let mut report = MigrationReport {
+            from,
+            to,
+            steps: Vec::new(),
+            dry_run,
+        };
+        for version in from..to {
+            let (description, step) = self.steps.get(&version).ok_or_else(|| {
+                invalid_data(&format!("no migration from format version {}", version))
+            })?;
+            if !dry_run {
+                step(dir)?;
+                write_version(dir, version + 1)?;
+            }
+            report.steps.push(description.clone());
+        }
+        if !dry_run && read_version(dir)?.is_none() {
+            write_version(dir, to)?;
+        }
+        Ok(report)
+    }
+}
+
+impl Default for Migrations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps = self
+            .steps
+            .iter()
+            .map(|(from, (description, _))| (from, description));
+        f.debug_map().entries(steps).finish()
+    }
+}
+
+/// Returns the version of the format recorded in `dir`, `None` if none is.
+fn read_version(dir: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(dir.join(FORMAT_FILE)) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid_data("unreadable format version")),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Records `version` in `dir`, next to the previous one and renamed over it
+/// once synced.
+fn write_version(dir: &Path, version: u32) -> io::Result<()> {
+    let temp = dir.join(format!("{}.tmp", FORMAT_FILE));
+    let mut file = File::create(&temp)?;
+    writeln!(file, "{}", version)?;
+    file.sync_all()?;
+    files::rename_durably(&temp, dir.join(FORMAT_FILE))
+}
+
+/// Returns `true` if `dir` holds neither a checkpoint nor a log.
+fn is_empty(dir: &Path) -> io::Result<bool> {
+    if dir.join(MANIFEST_FILE).exists() || dir.join(CHECKPOINT_FILE).exists() {
+        return Ok(false);
+    }
+    match fs::read_dir(dir.join(WAL_DIR)) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_version, Migrations, FORMAT_FILE, FORMAT_VERSION};
+    use crate::checksum::crc32_update;
+    use crate::codec::{encode_bytes, encode_len, Encode};
+    use crate::db::{checkpoint, Database, Error, CHECKPOINT_FILE, MANIFEST_FILE};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    /// Rewrites the checkpoint of `dir` as written before PDB files.
+    fn downgrade_checkpoint(dir: &Path) {
+        let checkpoint = checkpoint::read(dir, None).unwrap().unwrap();
+        let (recovered, lsn) = (checkpoint.recovered, checkpoint.lsn);
+        checkpoint::remove(dir).unwrap();
+        let mut bytes = b"PLDBCKPT".to_vec();
+        1u32.encode(&mut bytes);
+        lsn.encode(&mut bytes);
+        recovered.next_id.encode(&mut bytes);
+        encode_len(recovered.ids.len(), &mut bytes);
+        for (name, id) in &recovered.ids {
+            id.encode(&mut bytes);
+            name.encode(&mut bytes);
+            for mutation in &recovered.pending[id] {
+                encode_bytes(mutation, &mut bytes);
+            }
+            encode_bytes(&[], &mut bytes);
+        }
+        crc32_update(0, &bytes).encode(&mut bytes);
+        fs::write(dir.join(CHECKPOINT_FILE), bytes).unwrap();
+    }
+
+    #[test]
+    fn test_migrations_upgrade_legacy_checkpoints() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-migrations-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.migration_report().unwrap().steps.len(), 0);
+        assert_eq!(read_version(&dir).unwrap(), Some(FORMAT_VERSION));
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        for key in 0..10 {
+            accounts.put(&key, key).unwrap();
+        }
+        db.checkpoint_now().unwrap();
+        accounts.put(&10, 10).unwrap();
+        drop((db, accounts));
+
+        // as left by an earlier release
+        downgrade_checkpoint(&dir);
+        fs::remove_file(dir.join(FORMAT_FILE)).unwrap();
+        let report = Migrations::new().dry_run(&dir).unwrap();
+        assert_eq!((report.from, report.to, report.steps.len()), (1, 3, 2));
+        assert!(report.dry_run);
+        assert!(checkpoint::is_legacy(&dir).unwrap());
+        assert_eq!(read_version(&dir).unwrap(), None);
+
+        let db = Database::open(&dir).unwrap();
+        let report = db.migration_report().unwrap().clone();
+        assert_eq!((report.from, report.to, report.steps.len()), (1, 3, 2));
+        assert!(!report.dry_run);
+        assert!(!checkpoint::is_legacy(&dir).unwrap());
+        assert_eq!(read_version(&dir).unwrap(), Some(FORMAT_VERSION));
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        assert_eq!(accounts.len(), 11);
+        db.checkpoint_now().unwrap();
+        assert!(!dir.join(CHECKPOINT_FILE).exists() && dir.join(MANIFEST_FILE).exists());
+        drop((db, accounts));
+
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.migration_report().unwrap().from, FORMAT_VERSION);
+        assert!(db.migration_report().unwrap().steps.is_empty());
+        drop(db);
+
+        // written by a later release
+        fs::write(dir.join(FORMAT_FILE), "9\n").unwrap();
+        match Database::open(&dir) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            _ => panic!("opened a newer format"),
+        }
+        let migrations =
+            Migrations::new().register(FORMAT_VERSION + 1, "skips a version", |_| Ok(()));
+        fs::write(dir.join(FORMAT_FILE), format!("{}\n", FORMAT_VERSION)).unwrap();
+        assert!(migrations.run(&dir).is_err());
+        assert_eq!(read_version(&dir).unwrap(), Some(FORMAT_VERSION));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
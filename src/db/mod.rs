@@ -0,0 +1,1702 @@
+mod backup;
+mod builder;
+mod checkpoint;
+mod error;
+mod lock;
+mod migrations;
+mod recovery;
+#[cfg(feature = "server")]
+mod replication;
+mod stats;
+mod store;
+mod tiered;
+mod ttl;
+mod vacuum;
+
+use std::any::{self, Any};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use self::backup::Copier;
+use self::checkpoint::{Checkpointed, Checkpointer, Entries, KeyspaceFile};
+use self::lock::DirLock;
+use self::recovery::{LogRecord, Recovered};
+use self::stats::Measured;
+use self::vacuum::Vacuumer;
+use crate::codec::{self, Decode, Encode};
+use crate::collections::map::{LockPolicy, Map, MapBuilder};
+use crate::collections::utils::PriorityRwLock;
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+use crate::files;
+use crate::storage::{MemoryEngine, StorageEngine, VacuumReport};
+use crate::verify::{self, VerifyReport};
+use crate::wal::{self, Durable, Tail, Wal};
+
+pub use self::backup::{BackupProgress, STORES_FILE};
+pub use self::builder::DatabaseBuilder;
+pub use self::checkpoint::{
+    CheckpointPolicy, CheckpointStatus, CHECKPOINT_FILE, KEYSPACES_DIR, MANIFEST_FILE,
+};
+pub use self::error::{Error, Result};
+pub use self::lock::LOCK_FILE;
+pub use self::migrations::{MigrationReport, Migrations, FORMAT_FILE, FORMAT_VERSION};
+pub use self::recovery::{RecoveryReport, RecoveryTarget, CLEAN_SHUTDOWN_FILE};
+#[cfg(feature = "server")]
+pub(crate) use self::replication::Role;
+#[cfg(feature = "server")]
+pub use self::replication::REPLICATION_FILE;
+pub use self::stats::{FollowerStats, KeyspaceStats, PersistenceStats, ReplicationStats, Stats};
+pub use self::store::Store;
+pub use self::tiered::{TierPolicy, TierStats, Tiered};
+pub use self::ttl::DurableTtl;
+pub use self::vacuum::VacuumPolicy;
+
+/// Name of the directory of the write-ahead log within the directory of a
+/// database.
+pub const WAL_DIR: &str = "wal";
+
+/// Interval of the time marks of the log of a database, unless its
+/// [`WalBuilder`](crate::wal::WalBuilder) sets one, see
+/// [`RecoveryTarget::Time`].
+const TIME_MARK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A named collection, along with the name of its type for error reports.
+struct Collection {
+    map: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+    /// The same collection, if it is a durable keyspace.
+    checkpointed: Option<Arc<dyn Checkpointed>>,
+    /// The same collection, measured for its statistics.
+    measured: Arc<dyn Measured>,
+}
+
+type Keyspaces = PriorityRwLock<HashMap<String, Collection>>;
+
+/// The files of a database stored on disk, along with what was read back
+/// from them.
+struct Persistence {
+    dir: PathBuf,
+    wal: Arc<Wal>,
+    report: RecoveryReport,
+    migration: MigrationReport,
+    /// Locked while the keyspaces are locked for writing.
+    recovered: Mutex<Recovered>,
+    /// Serializes checkpoints.
+    checkpointing: Mutex<()>,
+    /// Checkpoints of the keyspaces listed in the manifest.
+    files: Mutex<HashMap<u64, KeyspaceFile>>,
+    status: Mutex<CheckpointStatus>,
+    compression: Compression,
+    encryption: Option<Encryption>,
+    /// How replication is going, as long as the database is open.
+    #[cfg(feature = "server")]
+    replication: Mutex<replication::Progress>,
+    /// Held for as long as the database is open.
+    _lock: DirLock,
+}
+
+impl Persistence {
+    /// Writes a checkpoint of the durable keyspaces whose name is selected
+    /// by `select`, along with those not checkpointed yet, then removes the
+    /// log it makes obsolete, recording the outcome in the status.
+    fn checkpoint(
+        &self,
+        keyspaces: &Keyspaces,
+        lock_policy: LockPolicy,
+        select: &dyn Fn(&str) -> bool,
+    ) -> io::Result<()> {
+        let _checkpointing = self.checkpointing.lock().unwrap();
+        let started = Instant::now();
+        let result = self.write_checkpoint(keyspaces, lock_policy, select);
+
+        let mut status = self.status.lock().unwrap();
+        match &result {
+            Ok((lsn, size, wal_bytes_removed)) => {
+                *status = CheckpointStatus {
+                    checkpoints: status.checkpoints + 1,
+                    lsn: Some(*lsn),
+                    completed_at: Some(SystemTime::now()),
+                    duration: started.elapsed(),
+                    size: *size,
+                    wal_bytes_removed: *wal_bytes_removed,
+                    last_error: None,
+                };
+            }
+            Err(err) => status.last_error = Some(err.to_string()),
+        }
+        result.map(|_| ())
+    }
+
+    /// Checks the checkpoints and the log, holding checkpoints off so that
+    /// no file is removed in the meantime.
+    fn verify(&self) -> io::Result<VerifyReport> {
+        let _checkpointing = self.checkpointing.lock().unwrap();
+        let mut report = VerifyReport::default();
+        for file in checkpoint::files(&self.dir)? {
+            let path = self.dir.join(&file);
+            let len = fs::metadata(&path)?.len();
+            match checkpoint::check_file(&self.dir, &file, self.encryption.as_ref()) {
+                Err(err) if verify::is_corruption(&err) => report.corrupted(&path, 0, len, &err),
+                checked => checked?,
+            }
+            report.checked(len);
+        }
+        report.merge(self.wal.verify()?);
+        Ok(report)
+    }
+
+    /// Removes the files no checkpoint refers to anymore, left behind by a
+    /// crash, and the log written before every checkpoint of a keyspace,
+    /// holding checkpoints off meanwhile.
+    fn vacuum(&self) -> io::Result<VacuumReport> {
+        let _checkpointing = self.checkpointing.lock().unwrap();
+        let files = self.files.lock().unwrap();
+        let listed: HashSet<_> = files.values().map(|file| file.path.as_path()).collect();
+        let mut report = vacuum::remove_obsolete(&self.dir, &listed)?;
+        if let Some(replay_from) = files.values().map(|file| file.lsn).min() {
+            let segments = self.wal.segments()?.len();
+            report.bytes_reclaimed += self.wal.remove_before(replay_from)?;
+            report.files_removed += (segments - self.wal.segments()?.len()) as u64;
+        }
+        Ok(report)
+    }
+
+    /// Returns the LSN the log is replayed from once checkpointed, the size
+    /// of the files written and the bytes of log removed.
+    fn write_checkpoint(
+        &self,
+        keyspaces: &Keyspaces,
+        lock_policy: LockPolicy,
+        select: &dyn Fn(&str) -> bool,
+    ) -> io::Result<(wal::Lsn, u64, u64)> {
+        let mut files = self.files.lock().unwrap();
+        // the keyspaces are listed as of the LSN the log is replayed from,
+        // their entries are copied afterwards while writers keep going, the
+        // writes copied or not being replayed from the log either way
+        let (lsn, next_id, live, entries) = {
+            let keyspaces = keyspaces.read(lock_policy.read);
+            let recovered = self.recovered.lock().unwrap();
+            let lsn = self.wal.roll_over()?;
+            let mut live = Vec::with_capacity(recovered.ids.len());
+            let mut entries = Vec::new();
+            for (name, &id) in &recovered.ids {
+                live.push((id, name.clone()));
+                if files.contains_key(&id) && !select(name) {
+                    continue;
+                }
+                let keyspace = match recovered.pending.get(&id) {
+                    Some(pending) => Entries::Pending(pending.clone()),
+                    None => keyspaces
+                        .get(name)
+                        .and_then(|collection| collection.checkpointed.clone())
+                        .map(Entries::Open)
+                        .ok_or_else(|| {
+                            io::Error::other(format!("durable keyspace {} is not open", name))
+                        })?,
+                };
+                entries.push((id, name.clone(), keyspace));
+            }
+            (lsn, recovered.next_id, live, entries)
+        };
+
+        if !self.dir.join(KEYSPACES_DIR).exists() {
+            fs::create_dir_all(self.dir.join(KEYSPACES_DIR))?;
+            files::sync_dir(&self.dir)?;
+        }
+        let mut size = 0;
+        let mut written = files.clone();
+        let mut obsolete = Vec::new();
+        for entry in &entries {
+            let path = checkpoint::keyspace_path(&self.dir, entry.0, lsn);
+            size += checkpoint::write(
+                &path,
+                lsn,
+                next_id,
+                slice::from_ref(entry),
+                self.compression,
+                self.encryption.clone(),
+            )?;
+            let file = KeyspaceFile { lsn, path };
+            // rewritten in place if nothing was logged since
+            match written.insert(entry.0, file) {
+                Some(previous) if previous.path != written[&entry.0].path => {
+                    obsolete.push(previous.path)
+                }
+                _ => {}
+            }
+        }
+        written.retain(|id, file| {
+            let dropped = !live.iter().any(|(live, _)| live == id);
+            if dropped {
+                obsolete.push(file.path.clone());
+            }
+            !dropped
+        });
+        let listed: Vec<_> = live
+            .into_iter()
+            .map(|(id, name)| (id, name, written[&id].clone()))
+            .collect();
+        size +=
+            checkpoint::write_manifest(&self.dir, lsn, next_id, &listed, self.encryption.clone())?;
+        *files = written;
+
+        // the checkpoints replaced are no longer listed, and the renames of
+        // the checkpoints and manifest replacing them synced `keyspaces/` and
+        // the database directory, so no crash persists the removals below
+        // without them
+        for path in obsolete {
+            backup::remove_if_exists(&path)?;
+        }
+        backup::remove_if_exists(&self.dir.join(CHECKPOINT_FILE))?;
+        let replay_from = files.values().fold(lsn, |min, file| min.min(file.lsn));
+        let wal_bytes_removed = self.wal.remove_before(replay_from)?;
+        Ok((replay_from, size, wal_bytes_removed))
+    }
+}
+
+/// Thread-Safe container of named [`Map`]s, the keyspaces of a database.
+///
+/// Keyspaces play the part of column families: each one is an independent
+/// `Map` with its own key and value types, opened by name at runtime and
+/// shared by every caller opening the same name. All of them are created
+/// from the same [`MapBuilder`], and live and die with the `Database`,
+/// which gives them a single lifecycle to hang persistence on.
+///
+/// A database opened from a directory with [`Database::open`] can also hold
+/// durable keyspaces, opened with [`Database::open_durable`], whose writes
+/// are all logged to one write-ahead log. Checkpoints copy the durable
+/// keyspaces to disk so that the log can be truncated, and opening the
+/// database again replays the log over the last checkpoint, recovering
+/// every durable keyspace.
+///
+/// Keyspaces opened with [`Database::open_store`] rather keep their entries
+/// in the [`StorageEngine`] of the database, an in-memory
+/// [`MemoryEngine`] unless another one is given to
+/// [`Database::with_engine`] or [`DatabaseBuilder::engine`], so that
+/// engines can be swapped without changing the code using the keyspaces.
+pub struct Database<H = RandomState, E = MemoryEngine> {
+    builder: MapBuilder<H>,
+    engine: Arc<E>,
+    keyspaces: Arc<Keyspaces>,
+    persistence: Option<Arc<Persistence>>,
+    checkpointers: Mutex<Vec<Checkpointer>>,
+    vacuumer: Mutex<Option<Vacuumer>>,
+    closed: AtomicBool,
+    lock_policy: LockPolicy,
+    opened: Instant,
+}
+
+impl Default for Database<RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Database<RandomState> {
+    /// Creates an empty `Database`, whose keyspaces are configured like
+    /// [`Map::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::db::Database;
+    /// let db = Database::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_builder(MapBuilder::new())
+    }
+
+    /// Opens the database stored in `dir` with the default configuration,
+    /// creating it if it does not exist, see [`DatabaseBuilder::open`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-open");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// drop(users);
+    /// drop(db);
+    ///
+    /// let db = Database::open(&dir).unwrap();
+    /// let report = db.recovery_report().unwrap();
+    /// assert_eq!(report.records_replayed, 2);
+    /// assert_eq!(report.keyspaces, 1);
+    ///
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()), Some(31));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        DatabaseBuilder::new().open(dir)
+    }
+
+    /// Restores the backup `backup` to `dir` with the default
+    /// configuration, then opens it, see [`DatabaseBuilder::restore_from`].
+    pub fn restore_from<P: AsRef<Path>, Q: AsRef<Path>>(backup: P, dir: Q) -> Result<Self> {
+        DatabaseBuilder::new().restore_from(backup, dir)
+    }
+
+    /// Recovers the database stored in `dir` to `target` with the default
+    /// configuration, then opens it, see [`DatabaseBuilder::recover_to`].
+    pub fn recover_to<P: AsRef<Path>>(dir: P, target: RecoveryTarget) -> Result<Self> {
+        DatabaseBuilder::new().recover_to(dir, target)
+    }
+}
+
+impl<E> Database<RandomState, E>
+where
+    E: StorageEngine + 'static,
+{
+    /// Creates an empty `Database` whose stores keep their entries in
+    /// `engine`, see [`Database::open_store`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    /// use palladiumdb::storage::LsmEngine;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-with-engine");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::with_engine(LsmEngine::open(&dir).unwrap());
+    /// let users = db.open_store::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), &31).unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn with_engine(engine: E) -> Self {
+        Self::with_parts(MapBuilder::new(), Arc::new(engine))
+    }
+}
+
+impl<H> Database<H, MemoryEngine>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Creates an empty `Database`, whose keyspaces are all configured by
+    /// `builder`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::db::Database;
+    ///
+    /// let db = Database::with_builder(MapBuilder::new().bucket_count(1024));
+    /// ```
+    pub fn with_builder(builder: MapBuilder<H>) -> Self {
+        Self::with_parts(builder, Arc::new(MemoryEngine::new()))
+    }
+}
+
+impl<H, E> Database<H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    fn with_parts(builder: MapBuilder<H>, engine: Arc<E>) -> Self {
+        Database {
+            builder,
+            engine,
+            keyspaces: Arc::new(PriorityRwLock::new(HashMap::new())),
+            persistence: None,
+            checkpointers: Mutex::new(Vec::new()),
+            vacuumer: Mutex::new(None),
+            closed: AtomicBool::new(false),
+            lock_policy: LockPolicy::default(),
+            opened: Instant::now(),
+        }
+    }
+
+    fn open_with(builder: DatabaseBuilder<H, E>, dir: &Path) -> Result<Self> {
+        if !builder.read_only {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = DirLock::acquire(dir, builder.read_only)?;
+        Self::open_locked(builder, dir, lock, None)
+    }
+
+    /// Cuts the log of `dir` off after `target`, setting aside a checkpoint
+    /// taken after it, then opens the database, see
+    /// [`DatabaseBuilder::recover_to`].
+    fn recover_with(
+        builder: DatabaseBuilder<H, E>,
+        dir: &Path,
+        target: RecoveryTarget,
+    ) -> Result<Self> {
+        if builder.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let lock = DirLock::acquire(dir, false)?;
+        let wal = Self::open_wal(&builder, dir, None)?;
+        let end = match target {
+            RecoveryTarget::Lsn(lsn) => lsn.saturating_add(1),
+            RecoveryTarget::Time(time) => wal.lsn_at(time)?,
+        };
+        if let Some(checkpoint) = checkpoint::read(dir, builder.encryption.as_ref())? {
+            let lsn = checkpoint.lsn;
+            // replayed from the first record instead, if it is still logged
+            let from_start = wal
+                .segments()?
+                .first()
+                .is_some_and(|first| first.start == 1);
+            if lsn > end && !from_start {
+                let err = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "log removed before the checkpoint, taken after the recovery target",
+                );
+                return Err(err.into());
+            }
+            // removed before the log is cut off, so that a crash in between
+            // leaves the whole log to replay
+            if lsn > end {
+                checkpoint::remove(dir)?;
+            }
+        }
+        wal.truncate_from(end)?;
+        drop(wal);
+        Self::open_locked(builder, dir, lock, Some(end))
+    }
+
+    /// Replaces the log and the checkpoint of `dir` with those of `backup`,
+    /// and the stores of the engine with those of `backup`, then opens the
+    /// database, see [`DatabaseBuilder::restore_from`].
+    fn restore_with(
+        builder: DatabaseBuilder<H, E>,
+        backup: &Path,
+        dir: &Path,
+        progress: &mut dyn FnMut(&BackupProgress),
+    ) -> Result<Self> {
+        if builder.read_only {
+            return Err(Error::ReadOnly);
+        }
+        // every backup has one, even of a database without stores
+        if !backup.join(STORES_FILE).is_file() {
+            return Err(Error::Io(codec::invalid_data("not a backup")));
+        }
+        fs::create_dir_all(dir)?;
+        let lock = DirLock::acquire(dir, false)?;
+        let mut copier = Copier::new(progress);
+        checkpoint::remove(dir)?;
+        backup::remove_if_exists(&dir.join(CLEAN_SHUTDOWN_FILE))?;
+        backup::remove_if_exists(&dir.join(FORMAT_FILE))?;
+        backup::remove_if_exists(&dir.join(WAL_DIR))?;
+        fs::create_dir_all(dir.join(WAL_DIR))?;
+        fs::create_dir_all(dir.join(KEYSPACES_DIR))?;
+        for file in checkpoint::files(backup)? {
+            copier.copy(&backup.join(&file), &dir.join(&file))?;
+        }
+        // backups taken before the format was recorded are migrated on open
+        let format = backup.join(FORMAT_FILE);
+        if format.is_file() {
+            copier.copy(&format, &dir.join(FORMAT_FILE))?;
+        }
+        for entry in fs::read_dir(backup.join(WAL_DIR))? {
+            let path = entry?.path();
+            copier.copy(&path, &dir.join(WAL_DIR).join(path.file_name().unwrap()))?;
+        }
+        let engine = &*builder.engine;
+        for name in store::names(engine)? {
+            store::remove(engine, &name)?;
+        }
+        copier.read_stores(
+            &backup.join(STORES_FILE),
+            engine,
+            builder.encryption.as_ref(),
+        )?;
+        Self::open_locked(builder, dir, lock, None)
+    }
+
+    /// Opens the log of the database stored in `dir`, taken to end at
+    /// `tail` if it was closed cleanly.
+    fn open_wal(
+        builder: &DatabaseBuilder<H, E>,
+        dir: &Path,
+        tail: Option<Tail>,
+    ) -> io::Result<Wal> {
+        let mut wal_builder = builder
+            .wal_builder
+            .clone()
+            .skip_corrupted(true)
+            .read_only(builder.read_only)
+            .compression(builder.compression)
+            .tail(tail);
+        if wal_builder.time_marks_interval().is_none() {
+            wal_builder = wal_builder.time_marks(TIME_MARK_INTERVAL);
+        }
+        if let Some(encryption) = &builder.encryption {
+            wal_builder = wal_builder.encryption(encryption.clone());
+        }
+        wal_builder.open(dir.join(WAL_DIR))
+    }
+
+    /// Opens the database stored in `dir`, whose lock is `lock`, its log
+    /// having been cut off at `recovered_to` if recovered to a point in
+    /// time.
+    fn open_locked(
+        builder: DatabaseBuilder<H, E>,
+        dir: &Path,
+        lock: DirLock,
+        recovered_to: Option<wal::Lsn>,
+    ) -> Result<Self> {
+        let migration = match builder.read_only {
+            true => builder.migrations.dry_run(dir)?,
+            false => builder.migrations.run(dir)?,
+        };
+        let tail = recovery::read_clean_shutdown(dir)?;
+        // removed before the log is appended to, which a crash could leave
+        // otherwise than recorded
+        if !builder.read_only {
+            backup::remove_if_exists(&dir.join(CLEAN_SHUTDOWN_FILE))?;
+        }
+        let wal = Self::open_wal(&builder, dir, tail)?;
+        let clean_shutdown = tail == Some(wal.tail());
+        let checkpoint = checkpoint::read(dir, builder.encryption.as_ref())?;
+        let files = checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.files.clone())
+            .unwrap_or_default();
+        let (recovered, mut report) = recovery::replay(&wal, checkpoint)?;
+        report.recovered_to = recovered_to;
+        report.clean_shutdown = clean_shutdown;
+        let persistence = Arc::new(Persistence {
+            dir: dir.to_path_buf(),
+            wal: Arc::new(wal),
+            status: Mutex::new(CheckpointStatus {
+                lsn: report.checkpoint_lsn,
+                ..CheckpointStatus::default()
+            }),
+            report,
+            migration,
+            recovered: Mutex::new(recovered),
+            checkpointing: Mutex::new(()),
+            files: Mutex::new(files),
+            compression: builder.compression,
+            encryption: builder.encryption,
+            #[cfg(feature = "server")]
+            replication: Mutex::default(),
+            _lock: lock,
+        });
+
+        let mut db = Self::with_parts(builder.map_builder, builder.engine);
+        let mut checkpointers = Vec::new();
+        if !builder.read_only {
+            // keyspaces with a policy of their own are left to it
+            let own = Arc::new(builder.keyspace_checkpoint_policies.clone());
+            let mut policies = vec![(builder.checkpoint_policy, None)];
+            for (name, policy) in builder.keyspace_checkpoint_policies {
+                policies.push((policy, Some(name)));
+            }
+            for (policy, name) in policies {
+                let (keyspaces, lock_policy) = (db.keyspaces.clone(), db.lock_policy);
+                let (persistence, own) = (persistence.clone(), own.clone());
+                let select = move |keyspace: &str| match &name {
+                    Some(name) => keyspace == name,
+                    None => !own.contains_key(keyspace),
+                };
+                let checkpointer =
+                    Checkpointer::spawn(policy, persistence.wal.clone(), move || {
+                        // failures are reported by the checkpoint status
+                        let _ = persistence.checkpoint(&keyspaces, lock_policy, &select);
+                    })?;
+                checkpointers.extend(checkpointer);
+            }
+            if let Some(policy) = builder.vacuum_policy {
+                let (persistence, engine) = (persistence.clone(), db.engine.clone());
+                let vacuumer = Vacuumer::spawn(policy, move || {
+                    let _ = persistence.vacuum();
+                    let _ = engine.vacuum(policy.garbage_ratio);
+                })?;
+                db.vacuumer = Mutex::new(Some(vacuumer));
+            }
+        }
+        db.persistence = Some(persistence);
+        db.checkpointers = Mutex::new(checkpointers);
+        Ok(db)
+    }
+
+    /// Returns the engine the stores of the database keep their entries in.
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Returns what was recovered when opening the database, `None` if it
+    /// is not stored on disk.
+    pub fn recovery_report(&self) -> Option<&RecoveryReport> {
+        self.persistence
+            .as_ref()
+            .map(|persistence| &persistence.report)
+    }
+
+    /// Returns the steps run when opening the database to bring its files to
+    /// the current format, see [`DatabaseBuilder::migrations`], `None` if it
+    /// is not stored on disk. Databases opened read-only are left in their
+    /// format, the report listing the steps that would be run.
+    pub fn migration_report(&self) -> Option<&MigrationReport> {
+        self.persistence
+            .as_ref()
+            .map(|persistence| &persistence.migration)
+    }
+
+    /// Writes a checkpoint of every durable keyspace, then removes the part
+    /// of the log written before it, waiting for the checkpoint in progress
+    /// if any.
+    ///
+    /// Writers are only held off one bucket at a time while the entries are
+    /// copied, the checkpoint reflecting the keyspaces as of a point of the
+    /// log from which the log is replayed on recovery. Every keyspace is
+    /// written to a file of its own in [`KEYSPACES_DIR`], then the
+    /// [`MANIFEST_FILE`] listing them is written next to the previous one
+    /// and renamed over it once synced, so a crash while checkpointing
+    /// leaves the previous checkpoint intact.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::InMemory`] if the database is not stored on disk,
+    /// [`Error::ReadOnly`] if it was opened read-only, [`Error::Io`] if the
+    /// checkpoint cannot be written, [`Error::Closed`] if the database was
+    /// closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-checkpoint");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// db.checkpoint_now().unwrap();
+    /// assert_eq!(db.checkpoint_status().unwrap().checkpoints, 1);
+    /// drop(users);
+    /// drop(db);
+    ///
+    /// let db = Database::open(&dir).unwrap();
+    /// assert_eq!(db.recovery_report().unwrap().records_replayed, 0);
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()), Some(31));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn checkpoint_now(&self) -> Result<()> {
+        self.check_open()?;
+        let persistence = self.persistence.as_ref().ok_or(Error::InMemory)?;
+        if persistence.wal.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        persistence.checkpoint(&self.keyspaces, self.lock_policy, &|_| true)?;
+        Ok(())
+    }
+
+    /// Writes a checkpoint of the durable keyspace `name` alone, along with
+    /// the keyspaces not checkpointed yet, then removes the part of the log
+    /// written before the oldest checkpoint of a keyspace, see
+    /// [`Database::checkpoint_now`].
+    ///
+    /// The checkpoints of the other keyspaces are left as they are, so that
+    /// keyspaces can be checkpointed at their own pace, see
+    /// [`DatabaseBuilder::keyspace_checkpoint_policy`].
+    ///
+    /// # Returns
+    ///
+    /// The path of the checkpoint of the keyspace, a file holding it alone
+    /// as of the checkpoint, which can be copied aside as a backup of it,
+    /// `None` if it is not a durable keyspace. The errors of
+    /// [`Database::checkpoint_now`] otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-checkpoint-keyspace");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// let events = db.open_durable::<u64, String>("events").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// let first = db.checkpoint_keyspace("users").unwrap().unwrap();
+    ///
+    /// events.put(&1, "signed up".to_string()).unwrap();
+    /// let second = db.checkpoint_keyspace("events").unwrap().unwrap();
+    /// assert!(first.exists() && second.exists());
+    /// assert_eq!(db.checkpoint_keyspace("missing").unwrap(), None);
+    /// # drop((users, events));
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn checkpoint_keyspace(&self, name: &str) -> Result<Option<PathBuf>> {
+        self.check_open()?;
+        let persistence = self.persistence.as_ref().ok_or(Error::InMemory)?;
+        if persistence.wal.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        persistence.checkpoint(&self.keyspaces, self.lock_policy, &|keyspace| {
+            keyspace == name
+        })?;
+        let id = match persistence.recovered.lock().unwrap().ids.get(name) {
+            Some(&id) => id,
+            None => return Ok(None),
+        };
+        let files = persistence.files.lock().unwrap();
+        Ok(files.get(&id).map(|file| file.path.clone()))
+    }
+
+    /// Checks the checksums of every file of the database, its checkpoint,
+    /// its log and the files of its engine, reporting the regions failing
+    /// them rather than stopping at the first one, see [`crate::verify`].
+    ///
+    /// Reads check the checksums of what they read already, so corrupted
+    /// data is never returned, but a verification finds it before it is
+    /// needed. The corrupted regions can then be set aside with
+    /// [`VerifyReport::quarantine`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Io`] if a file cannot be read, [`Error::Closed`] if the
+    /// database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-verify");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// db.checkpoint_now().unwrap();
+    ///
+    /// let report = db.verify().unwrap();
+    /// assert!(report.is_clean());
+    /// assert_eq!(report.files, 3);
+    /// report.quarantine(dir.join("quarantine")).unwrap();
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn verify(&self) -> Result<VerifyReport> {
+        self.check_open()?;
+        let mut report = match &self.persistence {
+            Some(persistence) => persistence.verify()?,
+            None => VerifyReport::default(),
+        };
+        report.merge(self.engine.verify()?);
+        Ok(report)
+    }
+
+    /// Reclaims the disk space taken by data the database no longer needs:
+    /// the files of the database left behind by a crash, such as checkpoints
+    /// no manifest lists anymore, and the log written before every
+    /// checkpoint of a keyspace, are removed, and the engine rewrites every
+    /// file holding garbage, see [`StorageEngine::vacuum`]. Checkpoints are
+    /// held off meanwhile, and reads and writes wait for the files of the
+    /// engine they need while they are rewritten.
+    ///
+    /// The log is only made obsolete by checkpoints, take one first with
+    /// [`Database::checkpoint_now`] to reclaim it. A
+    /// [`DatabaseBuilder::vacuum_policy`] vacuums the database in the
+    /// background instead.
+    ///
+    /// # Returns
+    ///
+    /// The files and bytes reclaimed, [`Error::ReadOnly`] if the database
+    /// was opened read-only, [`Error::Io`] if a file cannot be removed or
+    /// rewritten, [`Error::Closed`] if the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "mmap")]
+    /// # {
+    /// use palladiumdb::db::DatabaseBuilder;
+    /// use palladiumdb::storage::MmapEngine;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-vacuum");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let engine = MmapEngine::open(dir.join("entries")).unwrap();
+    /// let db = DatabaseBuilder::new().engine(engine).open(&dir).unwrap();
+    /// let users = db.open_store::<String, u64>("users").unwrap();
+    /// for age in 0..100 {
+    ///     users.put(&"alice".to_string(), &age).unwrap();
+    /// }
+    ///
+    /// let report = db.vacuum().unwrap();
+    /// assert_eq!(report.files_rewritten, 1);
+    /// assert!(report.bytes_reclaimed > 0);
+    /// assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(99));
+    /// # drop(users);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// # }
+    /// ```
+    pub fn vacuum(&self) -> Result<VacuumReport> {
+        self.check_open()?;
+        let mut report = VacuumReport::default();
+        if let Some(persistence) = &self.persistence {
+            if persistence.wal.is_read_only() {
+                return Err(Error::ReadOnly);
+            }
+            report = persistence.vacuum()?;
+        }
+        report.merge(self.engine.vacuum(0.0)?);
+        Ok(report)
+    }
+
+    /// Copies the database to `dir`, which must not exist, while writes go
+    /// on, see [`Database::backup_to_with_progress`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, DatabaseBuilder};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-backup");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(dir.join("db")).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// db.backup_to(dir.join("backup")).unwrap();
+    /// users.put(&"bob".to_string(), 42).unwrap();
+    ///
+    /// let restored = Database::restore_from(dir.join("backup"), dir.join("restored")).unwrap();
+    /// let users = restored.open_durable::<String, u64>("users").unwrap();
+    /// assert_eq!(users.get(&"alice".to_string()), Some(31));
+    /// assert_eq!(users.get(&"bob".to_string()), None);
+    /// # drop(users);
+    /// # drop(restored);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn backup_to<P: AsRef<Path>>(&self, dir: P) -> Result<BackupProgress> {
+        self.backup_to_with_progress(dir, |_| {})
+    }
+
+    /// Copies the database to `dir`, which must not exist, while writes go
+    /// on, calling `progress` as files are copied.
+    ///
+    /// The backup is consistent as of the moment it starts: the log is
+    /// rolled over to a new segment, then the checkpoint and the segments
+    /// before the new one are copied as they are, checkpoints being held off
+    /// until they are, and the entries of the stores are read from a
+    /// [snapshot](StorageEngine::snapshot) of the engine. Files are
+    /// copied to a directory next to `dir` renamed to `dir` once complete,
+    /// so an interrupted backup is never taken for a complete one.
+    ///
+    /// The backup is laid out as the directory of a database, along with a
+    /// [`STORES_FILE`], and is encrypted and compressed as the database is.
+    /// It is restored with [`DatabaseBuilder::restore_from`].
+    ///
+    /// # Returns
+    ///
+    /// The number of files and bytes copied, [`Error::Io`] if `dir` exists
+    /// or the files cannot be copied, [`Error::Closed`] if the database was
+    /// closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-backup-progress");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(dir.join("db")).unwrap();
+    /// db.open_durable::<u64, u64>("counters").unwrap().put(&1, 1).unwrap();
+    ///
+    /// let mut files = 0;
+    /// let copied = db
+    ///     .backup_to_with_progress(dir.join("backup"), |progress| files = progress.files)
+    ///     .unwrap();
+    /// assert_eq!(files, copied.files);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn backup_to_with_progress<P, F>(&self, dir: P, mut progress: F) -> Result<BackupProgress>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&BackupProgress),
+    {
+        self.check_open()?;
+        let dir = dir.as_ref();
+        if dir.exists() {
+            let err = io::Error::new(io::ErrorKind::AlreadyExists, "backup directory exists");
+            return Err(err.into());
+        }
+        let temp = backup::temp_path(dir);
+        backup::remove_if_exists(&temp)?;
+        fs::create_dir_all(temp.join(WAL_DIR))?;
+
+        let mut copier = Copier::new(&mut progress);
+        let (mut compression, mut encryption) = (Compression::None, None);
+        if let Some(persistence) = &self.persistence {
+            // the checkpoint is not replaced, nor segments of the log
+            // removed, while they are copied
+            let _checkpointing = persistence.checkpointing.lock().unwrap();
+            let end = match persistence.wal.is_read_only() {
+                true => wal::Lsn::MAX,
+                false => persistence.wal.roll_over()?,
+            };
+            fs::create_dir_all(temp.join(KEYSPACES_DIR))?;
+            for file in checkpoint::files(&persistence.dir)? {
+                copier.copy(&persistence.dir.join(&file), &temp.join(&file))?;
+            }
+            let format = persistence.dir.join(FORMAT_FILE);
+            if format.is_file() {
+                copier.copy(&format, &temp.join(FORMAT_FILE))?;
+            }
+            for segment in persistence.wal.segments()? {
+                let copy = temp.join(WAL_DIR).join(segment.path.file_name().unwrap());
+                match segment.start < end {
+                    true => copier.copy(&segment.path, &copy)?,
+                    // the segment written to from now on, kept empty so
+                    // that the restored log goes on from there
+                    false => drop(fs::File::create(&copy)?),
+                }
+            }
+            compression = persistence.compression;
+            encryption = persistence.encryption.clone();
+        }
+        let snapshot = self.engine.snapshot()?;
+        copier.write_stores(&temp.join(STORES_FILE), &*snapshot, compression, encryption)?;
+        files::rename_durably(&temp, dir)?;
+        Ok(copier.progress())
+    }
+
+    /// Returns `true` if the database was opened read-only, see
+    /// [`DatabaseBuilder::read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.persistence
+            .as_ref()
+            .is_some_and(|persistence| persistence.wal.is_read_only())
+    }
+
+    /// Returns `true` if the database follows a primary, taking no writes
+    /// but those replicated from it, see `replication::Replica`, with the
+    /// `server` feature. Writes to its durable keyspaces fail with an error
+    /// of kind `PermissionDenied`, and creating or dropping one with
+    /// [`Error::ReadOnly`].
+    pub fn is_follower(&self) -> bool {
+        self.persistence
+            .as_ref()
+            .is_some_and(|persistence| persistence.wal.is_follower())
+    }
+
+    /// Returns the outcome of the checkpoints of the database, `None` if it
+    /// is not stored on disk.
+    pub fn checkpoint_status(&self) -> Option<CheckpointStatus> {
+        self.persistence
+            .as_ref()
+            .map(|persistence| persistence.status.lock().unwrap().clone())
+    }
+
+    /// Returns the statistics of the database: how long it has been open,
+    /// the keys and memory of its keyspaces, the state of its files and its
+    /// part in replication, see [`Stats`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Io`] if the log cannot be listed or the part the database
+    /// plays in replication read, [`Error::Closed`] if the database was
+    /// closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let db = Database::new();
+    /// db.open_map::<String, u64>("users")
+    ///     .unwrap()
+    ///     .put(&"alice".to_string(), 31);
+    ///
+    /// let stats = db.stats().unwrap();
+    /// assert_eq!(stats.keyspaces[0].keys, Some(1));
+    /// assert!(stats.used_memory() > 0);
+    /// assert!(stats.to_string().contains("keyspace_users:kind=map,keys=1,"));
+    /// ```
+    pub fn stats(&self) -> Result<Stats> {
+        self.check_open()?;
+        let mut keyspaces: Vec<_> = self
+            .keyspaces
+            .read(self.lock_policy.read)
+            .iter()
+            .map(|(name, collection)| KeyspaceStats {
+                name: name.clone(),
+                kind: collection.measured.kind(),
+                keys: collection.measured.keys(),
+                memory: collection.measured.memory(),
+            })
+            .collect();
+        keyspaces.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let persistence = match &self.persistence {
+            Some(persistence) => Some(PersistenceStats {
+                read_only: persistence.wal.is_read_only(),
+                checkpoint: persistence.status.lock().unwrap().clone(),
+                wal_next_lsn: persistence.wal.next_lsn(),
+                wal_bytes: persistence.wal.segments()?.iter().map(|s| s.len).sum(),
+                recovery: persistence.report.clone(),
+            }),
+            None => None,
+        };
+        Ok(Stats {
+            uptime: self.opened.elapsed(),
+            keyspaces,
+            persistence,
+            replication: self.replication_stats()?,
+        })
+    }
+
+    /// Returns the part the database plays in replication, as recorded,
+    /// and how it is going.
+    #[cfg(feature = "server")]
+    fn replication_stats(&self) -> Result<ReplicationStats> {
+        let persistence = match &self.persistence {
+            Some(persistence) => persistence,
+            None => return Ok(ReplicationStats::None),
+        };
+        let progress = persistence.replication.lock().unwrap();
+        let staleness = progress.caught_up.map(|caught_up| caught_up.elapsed());
+        Ok(match self.replication_role()? {
+            Some(Role::Primary { .. }) if !self.is_follower() => {
+                let next = persistence.wal.next_lsn();
+                let followers = progress.followers.values().map(|follower| FollowerStats {
+                    address: follower.address,
+                    offset: follower.offset,
+                    lag: follower.offset.map(|offset| next.saturating_sub(offset)),
+                    acknowledged: follower.acknowledged.elapsed(),
+                });
+                ReplicationStats::Primary {
+                    followers: followers.collect(),
+                }
+            }
+            Some(Role::Follower { offset, .. }) => ReplicationStats::Follower {
+                offset: Some(offset),
+                staleness,
+            },
+            _ if self.is_follower() => ReplicationStats::Follower {
+                offset: None,
+                staleness,
+            },
+            _ => ReplicationStats::None,
+        })
+    }
+
+    #[cfg(not(feature = "server"))]
+    fn replication_stats(&self) -> Result<ReplicationStats> {
+        Ok(ReplicationStats::None)
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+        Ok(())
+    }
+
+    fn downcast<T>(name: &str, collection: &Collection) -> Result<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        Arc::clone(&collection.map)
+            .downcast::<T>()
+            .map_err(|_| Error::TypeMismatch {
+                name: name.to_string(),
+                expected: any::type_name::<T>(),
+                found: collection.type_name,
+            })
+    }
+
+    /// Returns the keyspace `name`, creating it if it does not exist.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::TypeMismatch`] if the keyspace exists with other key or
+    /// value types, [`Error::Closed`] if the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, Error};
+    ///
+    /// let db = Database::new();
+    /// let users = db.open_map::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31);
+    ///
+    /// let again = db.open_map::<String, u64>("users").unwrap();
+    /// assert_eq!(again.get(&"alice".to_string()), Some(31));
+    ///
+    /// let wrong = db.open_map::<u64, u64>("users");
+    /// assert!(matches!(wrong, Err(Error::TypeMismatch { .. })));
+    /// ```
+    pub fn open_map<K, V>(&self, name: &str) -> Result<Arc<Map<K, V, H>>>
+    where
+        K: Hash + Eq + Clone + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        self.check_open()?;
+        if let Some(collection) = self.keyspaces.read(self.lock_policy.read).get(name) {
+            return Self::downcast::<Map<K, V, H>>(name, collection);
+        }
+
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        // checked again under the write lock, the database may have been
+        // closed or the keyspace created in between
+        self.check_open()?;
+        let collection = keyspaces.entry(name.to_string()).or_insert_with(|| {
+            let map = Arc::new(self.builder.clone().build::<K, V>());
+            Collection {
+                map: map.clone(),
+                type_name: any::type_name::<Map<K, V, H>>(),
+                checkpointed: None,
+                measured: map,
+            }
+        });
+        Self::downcast::<Map<K, V, H>>(name, collection)
+    }
+
+    /// Returns the durable keyspace `name`, creating it if it does not exist.
+    /// Its writes are logged, and recovered when the database is opened
+    /// again.
+    ///
+    /// Opening a keyspace recovered from the log applies its logged writes.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::TypeMismatch`] if the keyspace exists with other key or
+    /// value types, [`Error::InMemory`] if the database is not stored on
+    /// disk, [`Error::Io`] if the logged writes cannot be decoded as these
+    /// types or the creation of the keyspace cannot be logged,
+    /// [`Error::ReadOnly`] if the keyspace does not exist and the database
+    /// was opened read-only, [`Error::Closed`] if the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, Error};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-durable");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let balances = db.open_durable::<u64, i64>("balances").unwrap();
+    /// balances.put(&1, -20).unwrap();
+    ///
+    /// let in_memory = Database::new();
+    /// let wrong = in_memory.open_durable::<u64, i64>("balances");
+    /// assert!(matches!(wrong, Err(Error::InMemory)));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn open_durable<K, V>(&self, name: &str) -> Result<Arc<Durable<K, V, H>>>
+    where
+        K: Hash + Eq + Clone + Encode + Decode + Send + Sync + 'static,
+        V: Clone + Encode + Decode + Send + Sync + 'static,
+    {
+        self.open_logged(name, |mutations, wal, prefix| {
+            let map = self.builder.clone().build::<K, V>();
+            for mutation in mutations {
+                wal::apply_mutation(&map, mutation)?;
+            }
+            Ok(Durable::with_shared_wal(map, wal, prefix))
+        })
+    }
+
+    /// Returns the durable keyspace `name` whose entries expire after a time
+    /// to live, creating it if it does not exist, see [`DurableTtl`].
+    ///
+    /// # Returns
+    ///
+    /// The errors of [`Database::open_durable`], [`Error::Io`] in particular
+    /// if the keyspace was not created by this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    /// use std::time::Duration;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-durable-ttl");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let sessions = db.open_durable_ttl::<String, u64>("sessions").unwrap();
+    /// sessions.put(&"alice".to_string(), 1, Duration::from_secs(3600)).unwrap();
+    /// sessions.put(&"bob".to_string(), 2, Duration::ZERO).unwrap();
+    /// drop(sessions);
+    /// drop(db);
+    ///
+    /// let db = Database::open(&dir).unwrap();
+    /// assert_eq!(db.recovery_report().unwrap().expired, 1);
+    /// let sessions = db.open_durable_ttl::<String, u64>("sessions").unwrap();
+    /// assert_eq!(sessions.get(&"alice".to_string()), Some(1));
+    /// assert_eq!(sessions.len(), 1);
+    /// # drop(sessions);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn open_durable_ttl<K, V>(&self, name: &str) -> Result<Arc<DurableTtl<K, V, H>>>
+    where
+        K: Hash + Eq + Clone + Encode + Decode + Send + Sync + 'static,
+        V: Clone + Encode + Decode + Send + Sync + 'static,
+    {
+        self.open_logged(name, |mutations, wal, prefix| {
+            let map = self.builder.clone().build::<K, (V, u64)>();
+            for mutation in mutations {
+                ttl::apply(&map, mutation)?;
+            }
+            Ok(DurableTtl::new(map, wal, prefix))
+        })
+    }
+
+    /// Writes `writes` as a whole, each putting a value to a key of a
+    /// keyspace, or removing the key if `None`. The keys are all locked at
+    /// once, and their writes logged as a single record, so that they are
+    /// recovered together or not at all. Keyspaces are durable ones if the
+    /// database is stored on disk, plain ones otherwise.
+    ///
+    /// # Returns
+    ///
+    /// The errors of opening the keyspaces and of appending to the log, in
+    /// which case none of the writes is made.
+    #[cfg(feature = "server")]
+    pub(crate) fn write_atomically<K, V>(&self, writes: Vec<(String, K, Option<V>)>) -> Result<()>
+    where
+        K: Hash + Eq + Clone + Encode + Decode + Send + Sync + 'static,
+        V: Clone + Encode + Decode + Send + Sync + 'static,
+    {
+        // keyspaces locked in the order of their names, so that concurrent
+        // calls cannot deadlock
+        let mut by_keyspace = std::collections::BTreeMap::<_, Vec<_>>::new();
+        for (name, key, value) in writes {
+            by_keyspace.entry(name).or_default().push((key, value));
+        }
+        let keys: Vec<Vec<K>> = by_keyspace
+            .values()
+            .map(|writes| writes.iter().map(|(key, _)| key.clone()).collect())
+            .collect();
+        match &self.persistence {
+            Some(persistence) => {
+                let durables = by_keyspace
+                    .keys()
+                    .map(|name| self.open_durable::<K, V>(name))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut records = Vec::new();
+                for (durable, writes) in durables.iter().zip(by_keyspace.values()) {
+                    for (key, value) in writes {
+                        records.push(durable.record_of(key, value.as_ref()));
+                    }
+                }
+                let maps: Vec<_> = durables.iter().map(|durable| durable.map()).collect();
+                write_locked(&maps, &keys, by_keyspace.into_values(), || {
+                    let batch = LogRecord::encode_batch(&records);
+                    persistence.wal.append(&batch).map(drop)
+                })
+            }
+            None => {
+                let maps = by_keyspace
+                    .keys()
+                    .map(|name| self.open_map::<K, V>(name))
+                    .collect::<Result<Vec<_>>>()?;
+                let maps: Vec<_> = maps.iter().map(|map| &**map).collect();
+                write_locked(&maps, &keys, by_keyspace.into_values(), || Ok(()))
+            }
+        }
+    }
+
+    /// Returns the keyspace `name` logging its writes, creating it if it
+    /// does not exist, built by `open` from the writes recovered for it,
+    /// the log and the prefix of its records.
+    fn open_logged<T, F>(&self, name: &str, open: F) -> Result<Arc<T>>
+    where
+        T: Checkpointed + Measured + 'static,
+        F: FnOnce(&[Vec<u8>], Arc<Wal>, Vec<u8>) -> io::Result<T>,
+    {
+        self.check_open()?;
+        let persistence = self.persistence.as_ref().ok_or(Error::InMemory)?;
+        if let Some(collection) = self.keyspaces.read(self.lock_policy.read).get(name) {
+            return Self::downcast::<T>(name, collection);
+        }
+
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        // checked again under the write lock, the database may have been
+        // closed or the keyspace created in between
+        self.check_open()?;
+        if let Some(collection) = keyspaces.get(name) {
+            return Self::downcast::<T>(name, collection);
+        }
+
+        let mut recovered = persistence.recovered.lock().unwrap();
+        let keyspace = match recovered.ids.get(name) {
+            Some(&id) => {
+                let mutations = recovered.pending.get(&id).map_or(&[][..], Vec::as_slice);
+                let prefix = LogRecord::write_prefix(id);
+                let keyspace = open(mutations, persistence.wal.clone(), prefix)?;
+                recovered.pending.remove(&id);
+                keyspace
+            }
+            None if persistence.wal.is_read_only() || persistence.wal.is_follower() => {
+                return Err(Error::ReadOnly)
+            }
+            None => {
+                let id = recovered.next_id;
+                persistence
+                    .wal
+                    .append(&LogRecord::encode_create(id, name))?;
+                recovered.next_id += 1;
+                recovered.ids.insert(name.to_string(), id);
+                open(&[], persistence.wal.clone(), LogRecord::write_prefix(id))?
+            }
+        };
+
+        let keyspace = Arc::new(keyspace);
+        let collection = keyspaces.entry(name.to_string()).or_insert(Collection {
+            map: keyspace.clone(),
+            type_name: any::type_name::<T>(),
+            checkpointed: Some(keyspace.clone()),
+            measured: keyspace,
+        });
+        Self::downcast::<T>(name, collection)
+    }
+
+    /// Returns the store `name`, a keyspace whose entries live in the
+    /// engine of the database, creating it if it does not exist, see
+    /// [`Store`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::TypeMismatch`] if the keyspace is already open with other
+    /// key or value types, or is not a store, [`Error::Io`] if the engine
+    /// fails, [`Error::Closed`] if the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let db = Database::new();
+    /// let users = db.open_store::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), &31).unwrap();
+    ///
+    /// let again = db.open_store::<String, u64>("users").unwrap();
+    /// assert_eq!(again.entries().unwrap(), vec![("alice".to_string(), 31)]);
+    /// assert_eq!(db.map_names(), vec!["users".to_string()]);
+    /// ```
+    pub fn open_store<K, V>(&self, name: &str) -> Result<Arc<Store<K, V, E>>>
+    where
+        K: Encode + Decode + 'static,
+        V: Encode + Decode + 'static,
+    {
+        self.check_open()?;
+        if let Some(collection) = self.keyspaces.read(self.lock_policy.read).get(name) {
+            return Self::downcast::<Store<K, V, E>>(name, collection);
+        }
+
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        // checked again under the write lock, the database may have been
+        // closed or the keyspace created in between
+        self.check_open()?;
+        if let Some(collection) = keyspaces.get(name) {
+            return Self::downcast::<Store<K, V, E>>(name, collection);
+        }
+        let id = store::create(&*self.engine, name)?;
+        let store = Arc::new(Store::<K, V, E>::new(self.engine.clone(), id));
+        let collection = keyspaces.entry(name.to_string()).or_insert(Collection {
+            map: store.clone(),
+            type_name: any::type_name::<Store<K, V, E>>(),
+            checkpointed: None,
+            measured: store,
+        });
+        Self::downcast::<Store<K, V, E>>(name, collection)
+    }
+
+    /// Returns the tiered keyspace `name`, whose recently used entries are
+    /// kept in memory and the others in the engine of the database, creating
+    /// it if it does not exist, see [`Tiered`]. Its cold tier is the store
+    /// `name`, so that entries flushed to it are found again by
+    /// [`Database::open_store`] or by this method once the keyspace is
+    /// reopened.
+    ///
+    /// `policy` is ignored if the keyspace is already open.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::TypeMismatch`] if the keyspace is already open with other
+    /// key or value types, or is not tiered, [`Error::Io`] if the engine
+    /// fails, [`Error::Closed`] if the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, TierPolicy};
+    ///
+    /// let db = Database::new();
+    /// let users = db
+    ///     .open_tiered::<String, u64>("users", TierPolicy::new().max_hot_entries(1000))
+    ///     .unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    ///
+    /// assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+    /// assert_eq!(users.stats().hot_hits, 1);
+    /// ```
+    pub fn open_tiered<K, V>(&self, name: &str, policy: TierPolicy) -> Result<Arc<Tiered<K, V, E>>>
+    where
+        K: Hash + Eq + Clone + Encode + Decode + Send + Sync + 'static,
+        V: Clone + Encode + Decode + Send + Sync + 'static,
+    {
+        self.check_open()?;
+        if let Some(collection) = self.keyspaces.read(self.lock_policy.read).get(name) {
+            return Self::downcast::<Tiered<K, V, E>>(name, collection);
+        }
+
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        // checked again under the write lock, the database may have been
+        // closed or the keyspace created in between
+        self.check_open()?;
+        if let Some(collection) = keyspaces.get(name) {
+            return Self::downcast::<Tiered<K, V, E>>(name, collection);
+        }
+        let id = store::create(&*self.engine, name)?;
+        let cold = Store::<K, V, E>::new(self.engine.clone(), id);
+        let tiered = Arc::new(Tiered::new(cold, policy));
+        let collection = keyspaces.entry(name.to_string()).or_insert(Collection {
+            map: tiered.clone(),
+            type_name: any::type_name::<Tiered<K, V, E>>(),
+            checkpointed: None,
+            measured: tiered,
+        });
+        Self::downcast::<Tiered<K, V, E>>(name, collection)
+    }
+
+    /// Removes the keyspace `name` from the database. Handles to it opened
+    /// before keep working, but opening `name` again creates a new, empty
+    /// keyspace. Writes made through them to a durable keyspace are not
+    /// recovered.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the keyspace existed, [`Error::Io`] if the removal of a
+    /// durable keyspace cannot be logged or the entries of a store cannot be
+    /// removed from the engine, [`Error::ReadOnly`] if the keyspace is
+    /// durable and the database was opened read-only, [`Error::Closed`] if
+    /// the database was closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::Database;
+    ///
+    /// let db = Database::new();
+    /// db.open_map::<u64, u64>("sessions").unwrap().put(&1, 1);
+    ///
+    /// assert!(db.drop_map("sessions").unwrap());
+    /// assert!(db.open_map::<u64, u64>("sessions").unwrap().is_empty());
+    /// ```
+    pub fn drop_map(&self, name: &str) -> Result<bool> {
+        self.check_open()?;
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        let mut logged = false;
+        if let Some(persistence) = &self.persistence {
+            let mut recovered = persistence.recovered.lock().unwrap();
+            if let Some(&id) = recovered.ids.get(name) {
+                if persistence.wal.is_read_only() || persistence.wal.is_follower() {
+                    return Err(Error::ReadOnly);
+                }
+                persistence.wal.append(&LogRecord::encode_drop(id))?;
+                recovered.ids.remove(name);
+                recovered.pending.remove(&id);
+                logged = true;
+            }
+        }
+        let stored = store::remove(&*self.engine, name)?;
+        let removed = keyspaces.remove(name).is_some();
+        Ok(removed || logged || stored)
+    }
+
+    /// Returns `true` if the keyspace `name` exists, durable keyspaces
+    /// recovered from the log and stores recorded in the engine but not
+    /// opened yet included. Stores are left out if the engine fails.
+    pub fn contains_map(&self, name: &str) -> bool {
+        let keyspaces = self.keyspaces.read(self.lock_policy.read);
+        keyspaces.contains_key(name)
+            || self.persistence.as_ref().is_some_and(|persistence| {
+                persistence.recovered.lock().unwrap().ids.contains_key(name)
+            })
+            || matches!(store::lookup(&*self.engine, name), Ok(Some(_)))
+    }
+
+    /// Returns the names of the keyspaces, sorted, durable keyspaces
+    /// recovered from the log and stores recorded in the engine but not
+    /// opened yet included. Stores are left out if the engine fails.
+    pub fn map_names(&self) -> Vec<String> {
+        let keyspaces = self.keyspaces.read(self.lock_policy.read);
+        let mut names: Vec<_> = keyspaces.keys().cloned().collect();
+        if let Some(persistence) = &self.persistence {
+            let recovered = persistence.recovered.lock().unwrap();
+            names.extend(recovered.ids.keys().cloned());
+        }
+        names.extend(store::names(&*self.engine).unwrap_or_default());
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Closes the database, releasing its keyspaces. Every later operation
+    /// on the database returns [`Error::Closed`], while handles to keyspaces
+    /// opened before keep working on their own.
+    ///
+    /// Background checkpoints and vacuums are stopped first. A database
+    /// stored on disk then takes a last checkpoint of every durable
+    /// keyspace, syncs its log and records that it was closed cleanly in
+    /// [`CLEAN_SHUTDOWN_FILE`], so that opening it again replays nothing
+    /// and finds the end of the log without reading it through, see
+    /// [`RecoveryReport::clean_shutdown`]. The engine is closed as well,
+    /// see [`StorageEngine::close`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Closed`] if the database was already closed, [`Error::Io`]
+    /// if the checkpoint cannot be written, the log synced or the engine
+    /// closed, the database being left open.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::db::{Database, Error};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-db-close");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let db = Database::open(&dir).unwrap();
+    /// let users = db.open_durable::<String, u64>("users").unwrap();
+    /// users.put(&"alice".to_string(), 31).unwrap();
+    /// db.close().unwrap();
+    /// assert!(matches!(db.open_map::<u64, u64>("users"), Err(Error::Closed)));
+    /// drop((users, db));
+    ///
+    /// let db = Database::open(&dir).unwrap();
+    /// let report = db.recovery_report().unwrap();
+    /// assert!(report.clean_shutdown);
+    /// assert_eq!(report.records_replayed, 0);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn close(&self) -> Result<()> {
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        // stopped before locking the keyspaces, which a checkpoint in
+        // progress waits for
+        self.checkpointers.lock().unwrap().clear();
+        self.vacuumer.lock().unwrap().take();
+        let writable = self
+            .persistence
+            .as_ref()
+            .filter(|persistence| !persistence.wal.is_read_only());
+        if let Some(persistence) = writable {
+            persistence.checkpoint(&self.keyspaces, self.lock_policy, &|_| true)?;
+        }
+        let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+        if let Some(persistence) = writable {
+            persistence.wal.sync()?;
+        }
+        self.engine.close()?;
+        // written last, writes made through the handles to keyspaces from
+        // now on moving the end of the log away from the one recorded
+        if let Some(persistence) = writable {
+            recovery::write_clean_shutdown(&persistence.dir, persistence.wal.tail())?;
+        }
+        self.closed.store(true, Ordering::SeqCst);
+        keyspaces.clear();
+        Ok(())
+    }
+
+    /// Returns `true` if the database was closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// Locks `keys` in every map of `maps`, in order, then makes `writes` to
+/// them once `log` logged them, see [`Database::write_atomically`].
+#[cfg(feature = "server")]
+fn write_locked<K, V, H>(
+    maps: &[&Map<K, V, H>],
+    keys: &[Vec<K>],
+    writes: impl Iterator<Item = Vec<(K, Option<V>)>>,
+    log: impl FnOnce() -> io::Result<()>,
+) -> Result<()>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    H: BuildHasher,
+{
+    let mut locked: Vec<_> = maps
+        .iter()
+        .zip(keys)
+        .map(|(map, keys)| map.lock_keys(keys))
+        .collect();
+    let logged = log();
+    if logged.is_ok() {
+        for (locked, writes) in locked.iter_mut().zip(writes) {
+            for (key, value) in writes {
+                match value {
+                    Some(value) => locked.put(&key, value),
+                    None => locked.unmap(&key),
+                }
+            }
+        }
+    }
+    for (map, locked) in maps.iter().zip(locked) {
+        map.unlock_keys(locked);
+    }
+    logged.map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Database;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_opens_share_one_keyspace() {
+        let db = Arc::new(Database::new());
+
+        let workers: Vec<_> = (0..8u64)
+            .map(|thread| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let map = db.open_map::<u64, u64>("counters").unwrap();
+                    for key in 0..100 {
+                        map.put(&(thread * 100 + key), key);
+                    }
+                    map
+                })
+            })
+            .collect();
+        let maps: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect();
+
+        assert!(maps.iter().all(|map| Arc::ptr_eq(map, &maps[0])));
+        assert_eq!(maps[0].len(), 800);
+        assert_eq!(db.map_names(), vec!["counters".to_string()]);
+    }
+}
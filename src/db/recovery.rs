@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::checkpoint::Checkpoint;
+use super::ttl;
+use crate::codec::{decode_all, decode_bytes, decode_len, invalid_data, Decode, Encode};
+#[cfg(feature = "server")]
+use crate::codec::{encode_bytes, encode_len};
+use crate::files;
+use crate::wal::{unix_millis, Lsn, Tail, Wal};
+
+/// Name of the file within the directory of a database recording that it
+/// was closed cleanly, along with the end of its log, so that the log need
+/// not be read through when the database is opened again, see
+/// [`Database::close`](super::Database::close). It is removed as the
+/// database is opened.
+pub const CLEAN_SHUTDOWN_FILE: &str = "CLEAN_SHUTDOWN";
+
+/// Outcome of replaying the log of a [`Database`](super::Database) when
+/// opening it, see [`Database::recovery_report`](super::Database::recovery_report).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// LSN the log was replayed from, that of the oldest checkpoint of a
+    /// keyspace, if any.
+    pub checkpoint_lsn: Option<Lsn>,
+    /// Number of records read back from the log.
+    pub records_replayed: u64,
+    /// Number of records skipped for failing their checksum or not being
+    /// readable.
+    pub corrupted_skipped: u64,
+    /// Number of bytes of a record left partially written at the end of the
+    /// log by a crash, cut off when opening it.
+    pub torn_tail_bytes: u64,
+    /// Number of keyspaces recovered.
+    pub keyspaces: usize,
+    /// LSN the log was cut off at by a point-in-time recovery, see
+    /// [`DatabaseBuilder::recover_to`](super::DatabaseBuilder::recover_to).
+    pub recovered_to: Option<Lsn>,
+    /// Number of entries of TTL keyspaces dropped for having expired by the
+    /// time the database was opened, see
+    /// [`Database::open_durable_ttl`](super::Database::open_durable_ttl).
+    pub expired: u64,
+    /// Whether the database was closed cleanly, with
+    /// [`Database::close`](super::Database::close), and its log left as it
+    /// was then, so that it was not read through to find its end.
+    pub clean_shutdown: bool,
+}
+
+/// Point of the log a [`Database`](super::Database) is recovered to, see
+/// [`DatabaseBuilder::recover_to`](super::DatabaseBuilder::recover_to).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Every record up to this LSN included is kept.
+    Lsn(Lsn),
+    /// Every record appended by this time is kept, as found by
+    /// [`Wal::lsn_at`], up to the interval of its time marks early.
+    Time(SystemTime),
+}
+
+/// Records in `dir` that the database was closed cleanly, its log ending at
+/// `tail`, next to the previous record and renamed over it once synced.
+pub(super) fn write_clean_shutdown(dir: &Path, tail: Tail) -> io::Result<()> {
+    let temp = dir.join(format!("{}.tmp", CLEAN_SHUTDOWN_FILE));
+    let mut file = File::create(&temp)?;
+    writeln!(
+        file,
+        "{} {} {}",
+        tail.segment_start, tail.segment_len, tail.next_lsn
+    )?;
+    file.sync_all()?;
+    files::rename_durably(&temp, dir.join(CLEAN_SHUTDOWN_FILE))
+}
+
+/// Returns the end of the log recorded in `dir` when the database was
+/// closed cleanly, `None` if it was not or the record is unreadable.
+pub(super) fn read_clean_shutdown(dir: &Path) -> io::Result<Option<Tail>> {
+    let text = match fs::read_to_string(dir.join(CLEAN_SHUTDOWN_FILE)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let fields = text
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>();
+    Ok(match fields.as_deref() {
+        Ok(&[segment_start, segment_len, next_lsn]) => Some(Tail {
+            segment_start,
+            segment_len,
+            next_lsn,
+        }),
+        _ => None,
+    })
+}
+
+/// A record of the log of a database. Keyspaces are logged by an id
+/// assigned when they are created, so that writes made through a handle to
+/// a dropped keyspace are not recovered into a later one of the same name.
+/// The writes of a batch, to any number of keyspaces, are logged as a
+/// single record so that they are recovered together or not at all.
+pub(super) enum LogRecord<'a> {
+    Create { id: u64, name: String },
+    Write { id: u64, mutation: &'a [u8] },
+    Drop { id: u64 },
+    Batch { writes: Vec<(u64, &'a [u8])> },
+}
+
+impl LogRecord<'_> {
+    pub(super) fn encode_create(id: u64, name: &str) -> Vec<u8> {
+        let mut buf = vec![0];
+        id.encode(&mut buf);
+        name.encode(&mut buf);
+        buf
+    }
+
+    /// Returns the bytes the records of the writes to keyspace `id` start
+    /// with, followed by the mutation.
+    pub(super) fn write_prefix(id: u64) -> Vec<u8> {
+        let mut buf = vec![1];
+        id.encode(&mut buf);
+        buf
+    }
+
+    pub(super) fn encode_drop(id: u64) -> Vec<u8> {
+        let mut buf = vec![2];
+        id.encode(&mut buf);
+        buf
+    }
+
+    /// Returns the record of the writes `records`, each a record of a
+    /// write starting with the prefix of its keyspace, see
+    /// [`LogRecord::write_prefix`].
+    #[cfg(feature = "server")]
+    pub(super) fn encode_batch(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![3];
+        encode_len(records.len(), &mut buf);
+        for record in records {
+            encode_bytes(record, &mut buf);
+        }
+        buf
+    }
+
+    pub(super) fn decode(payload: &[u8]) -> io::Result<LogRecord<'_>> {
+        let (tag, mut input) = payload
+            .split_first()
+            .ok_or_else(|| invalid_data("empty log record"))?;
+        if *tag == 3 {
+            let mut writes = Vec::new();
+            for _ in 0..decode_len(&mut input)? {
+                match LogRecord::decode(decode_bytes(&mut input)?)? {
+                    LogRecord::Write { id, mutation } => writes.push((id, mutation)),
+                    _ => return Err(invalid_data("batch of records other than writes")),
+                }
+            }
+            return match input.is_empty() {
+                true => Ok(LogRecord::Batch { writes }),
+                false => Err(invalid_data("unknown log record")),
+            };
+        }
+        let id = u64::decode(&mut input)?;
+        match tag {
+            0 => Ok(LogRecord::Create {
+                id,
+                name: decode_all(input)?,
+            }),
+            1 => Ok(LogRecord::Write {
+                id,
+                mutation: input,
+            }),
+            2 if input.is_empty() => Ok(LogRecord::Drop { id }),
+            _ => Err(invalid_data("unknown log record")),
+        }
+    }
+}
+
+/// Keyspaces found in the log, whose writes are kept encoded until they are
+/// opened with their key and value types.
+#[derive(Default)]
+pub(super) struct Recovered {
+    /// Ids of the keyspaces, by name.
+    pub(super) ids: HashMap<String, u64>,
+    /// Logged writes of every keyspace not opened yet, oldest first.
+    pub(super) pending: HashMap<u64, Vec<Vec<u8>>>,
+    /// Id the next keyspace created gets.
+    pub(super) next_id: u64,
+}
+
+/// Reads back the records of `wal` over the keyspaces of `checkpoint`, if
+/// any. Creations and removals of keyspaces logged before the manifest, and
+/// writes logged before the checkpoint of their keyspace, are reflected in
+/// them already and left out.
+pub(super) fn replay(
+    wal: &Wal,
+    checkpoint: Option<Checkpoint>,
+) -> io::Result<(Recovered, RecoveryReport)> {
+    let checkpoint_lsn = checkpoint.as_ref().map(Checkpoint::replay_from);
+    let (mut recovered, manifest_lsn, keyspace_lsns) = match checkpoint {
+        Some(checkpoint) => (
+            checkpoint.recovered,
+            checkpoint.lsn,
+            checkpoint.keyspace_lsns,
+        ),
+        None => (Recovered::default(), 0, HashMap::new()),
+    };
+    let from = checkpoint_lsn.unwrap_or(0);
+    let mut report = RecoveryReport {
+        checkpoint_lsn,
+        torn_tail_bytes: wal.truncated_len(),
+        ..RecoveryReport::default()
+    };
+
+    let mut records = wal.iter_from(from)?;
+    for record in &mut records {
+        let (lsn, payload) = record?;
+        let record = match LogRecord::decode(&payload) {
+            Ok(record) => record,
+            Err(_) => {
+                report.corrupted_skipped += 1;
+                continue;
+            }
+        };
+        let before_checkpoint = |id: &u64| keyspace_lsns.get(id).is_some_and(|at| lsn < *at);
+        let checkpointed = match &record {
+            LogRecord::Write { id, .. } => before_checkpoint(id),
+            LogRecord::Batch { writes } => writes.iter().all(|(id, _)| before_checkpoint(id)),
+            _ => lsn < manifest_lsn,
+        };
+        if checkpointed {
+            continue;
+        }
+        report.records_replayed += 1;
+        match record {
+            LogRecord::Create { id, name } => {
+                if let Some(previous) = recovered.ids.insert(name, id) {
+                    recovered.pending.remove(&previous);
+                }
+                // the keyspace may have been created while checkpointing
+                recovered.pending.entry(id).or_default();
+                recovered.next_id = recovered.next_id.max(id + 1);
+            }
+            // writes to dropped keyspaces are left out
+            LogRecord::Write { id, mutation } => {
+                if let Some(pending) = recovered.pending.get_mut(&id) {
+                    pending.push(mutation.to_vec());
+                }
+            }
+            LogRecord::Drop { id } => {
+                recovered.ids.retain(|_, live| *live != id);
+                recovered.pending.remove(&id);
+            }
+            LogRecord::Batch { writes } => {
+                // checkpointed keyspace by keyspace
+                for (id, mutation) in writes {
+                    if before_checkpoint(&id) {
+                        continue;
+                    }
+                    if let Some(pending) = recovered.pending.get_mut(&id) {
+                        pending.push(mutation.to_vec());
+                    }
+                }
+            }
+        }
+    }
+    report.corrupted_skipped += records.corrupted();
+    let now = unix_millis(SystemTime::now());
+    for pending in recovered.pending.values_mut() {
+        report.expired += ttl::expire_pending(pending, now);
+    }
+    report.keyspaces = recovered.ids.len();
+    Ok((recovered, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Database, DatabaseBuilder, Error, RecoveryTarget, CLEAN_SHUTDOWN_FILE, WAL_DIR,
+    };
+    use crate::wal::WalBuilder;
+    use std::fs;
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_recovery_skips_corruption_and_cuts_torn_tail() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-recovery-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let value = |key: u64| format!("value-{:03}", key);
+
+        let db = Database::open(&dir).unwrap();
+        let dropped = db.open_durable::<u64, String>("dropped").unwrap();
+        dropped.put(&1, value(1)).unwrap();
+        assert!(db.drop_map("dropped").unwrap());
+        dropped.put(&2, value(2)).unwrap();
+        let accounts = db.open_durable::<u64, String>("accounts").unwrap();
+        for key in 0..100 {
+            accounts.put(&key, value(key)).unwrap();
+        }
+        drop((db, dropped, accounts));
+
+        // corrupt the record of key 50, then tear the last record
+        let mut segments: Vec<_> = fs::read_dir(dir.join(WAL_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        segments.sort();
+        let path = segments.last().unwrap();
+        let mut bytes = fs::read(path).unwrap();
+        let target = value(50).into_bytes();
+        let at = bytes
+            .windows(target.len())
+            .position(|w| w == target)
+            .unwrap();
+        bytes[at] ^= 0xff;
+        bytes.truncate(bytes.len() - 3);
+        fs::write(path, &bytes).unwrap();
+
+        let db = Database::open(&dir).unwrap();
+        let report = db.recovery_report().unwrap().clone();
+        assert!(!report.clean_shutdown);
+        assert_eq!(report.corrupted_skipped, 1);
+        assert!(report.torn_tail_bytes > 0);
+        assert_eq!(report.keyspaces, 1);
+        // create, put, drop and stale put of the dropped keyspace, then
+        // create and every put of accounts but the corrupted and torn ones
+        assert_eq!(report.records_replayed, 4 + 1 + 98);
+        assert_eq!(db.map_names(), vec!["accounts".to_string()]);
+
+        let accounts = db.open_durable::<u64, String>("accounts").unwrap();
+        assert_eq!(accounts.len(), 98);
+        assert_eq!(accounts.get(&50), None);
+        assert_eq!(accounts.get(&99), None);
+        assert_eq!(accounts.get(&49), Some(value(49)));
+        accounts.put(&99, value(99)).unwrap();
+        assert!(db
+            .open_durable::<u64, String>("dropped")
+            .unwrap()
+            .is_empty());
+        drop((db, accounts));
+
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.recovery_report().unwrap().torn_tail_bytes, 0);
+        let accounts = db.open_durable::<u64, String>("accounts").unwrap();
+        assert_eq!(accounts.len(), 99);
+        assert_eq!(accounts.get(&99), Some(value(99)));
+
+        // closed cleanly, the next open has nothing to replay nor read
+        // through, unless the log changed since
+        db.close().unwrap();
+        assert!(dir.join(CLEAN_SHUTDOWN_FILE).is_file());
+        drop((db, accounts));
+        let db = Database::open(&dir).unwrap();
+        let report = db.recovery_report().unwrap();
+        assert!(report.clean_shutdown);
+        assert_eq!(report.records_replayed, 0);
+        assert!(!dir.join(CLEAN_SHUTDOWN_FILE).exists());
+        let accounts = db.open_durable::<u64, String>("accounts").unwrap();
+        assert_eq!(accounts.len(), 99);
+        db.close().unwrap();
+        accounts.put(&100, value(100)).unwrap();
+        drop((db, accounts));
+        let db = Database::open(&dir).unwrap();
+        let report = db.recovery_report().unwrap();
+        assert!(!report.clean_shutdown);
+        assert_eq!(report.records_replayed, 1);
+        let accounts = db.open_durable::<u64, String>("accounts").unwrap();
+        assert_eq!(accounts.get(&100), Some(value(100)));
+        drop((db, accounts));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover_to_time_undoes_mass_delete() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-recover-to-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let builder =
+            DatabaseBuilder::new().wal_builder(WalBuilder::new().time_marks(Duration::ZERO));
+
+        let db = builder.clone().open(&dir).unwrap();
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        for key in 0..10 {
+            accounts.put(&key, key).unwrap();
+        }
+        db.checkpoint_now().unwrap();
+        for key in 10..20 {
+            accounts.put(&key, key).unwrap();
+        }
+        thread::sleep(Duration::from_millis(5));
+        let before_delete = SystemTime::now();
+        thread::sleep(Duration::from_millis(5));
+        for key in 0..20 {
+            accounts.remove(&key).unwrap();
+        }
+        drop((db, accounts));
+
+        let db = builder
+            .clone()
+            .recover_to(&dir, RecoveryTarget::Time(before_delete))
+            .unwrap();
+        let report = db.recovery_report().unwrap().clone();
+        assert!(report.checkpoint_lsn.is_some() && report.recovered_to.is_some());
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        assert_eq!(accounts.len(), 20);
+        accounts.put(&20, 20).unwrap();
+        drop((db, accounts));
+
+        // the records after the target are gone for good
+        let db = builder.clone().open(&dir).unwrap();
+        let accounts = db.open_durable::<u64, u64>("accounts").unwrap();
+        assert_eq!(accounts.len(), 21);
+        drop((db, accounts));
+
+        // the log before the checkpoint was removed along with it
+        match builder.recover_to(&dir, RecoveryTarget::Lsn(1)) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            _ => panic!("recovered to before the checkpoint"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
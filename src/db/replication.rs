@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use super::backup;
+use super::checkpoint::Entries;
+use super::recovery::LogRecord;
+use super::{Database, Error, Persistence, Result};
+use crate::codec::invalid_data;
+use crate::files;
+use crate::storage::StorageEngine;
+use crate::wal::Lsn;
+
+/// Name of the file recording the part a database plays in
+/// [replication](crate::replication), within its directory: `primary` and
+/// the id of the history of its log, or `follower`, the id of the history
+/// of the log of its primary and the LSN of the next record to replicate
+/// from it. Followers without one are synced in full.
+pub const REPLICATION_FILE: &str = "REPLICATION";
+
+/// The part a database plays in replication, as recorded in
+/// [`REPLICATION_FILE`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// The database is written to, its log having the history `id`.
+    Primary { id: u64 },
+    /// The database replicates the log of history `id` of a primary, from
+    /// `offset` on.
+    Follower { id: u64, offset: Lsn },
+}
+
+/// How replication is going, as the threads of
+/// [replication](crate::replication) report it, see
+/// [`ReplicationStats`](super::ReplicationStats).
+#[derive(Default)]
+pub(super) struct Progress {
+    /// The followers connected to the primary, by connection.
+    pub(super) followers: BTreeMap<u64, Follower>,
+    /// When the follower was last caught up with its primary.
+    pub(super) caught_up: Option<Instant>,
+}
+
+/// A follower connected to the primary.
+pub(super) struct Follower {
+    pub(super) address: SocketAddr,
+    /// The offset it last acknowledged, `None` until it is synced in full.
+    pub(super) offset: Option<Lsn>,
+    /// When it last acknowledged, or connected.
+    pub(super) acknowledged: Instant,
+}
+
+impl<H, E> Database<H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    fn replicated(&self) -> Result<&Persistence> {
+        self.check_open()?;
+        self.persistence.as_deref().ok_or(Error::InMemory)
+    }
+
+    /// Returns the part the database plays in replication, `None` if it
+    /// plays none yet, or a follower has not completed a full sync.
+    pub(crate) fn replication_role(&self) -> Result<Option<Role>> {
+        let persistence = self.replicated()?;
+        let text = match fs::read_to_string(persistence.dir.join(REPLICATION_FILE)) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let fields: Vec<_> = text.split_whitespace().collect();
+        let id = |field: &str| u64::from_str_radix(field, 16).ok();
+        let role = match fields[..] {
+            ["primary", field] => id(field).map(|id| Role::Primary { id }),
+            ["follower", field, offset] => id(field)
+                .zip(offset.parse().ok())
+                .map(|(id, offset)| Role::Follower { id, offset }),
+            _ => None,
+        };
+        match role {
+            Some(role) => Ok(Some(role)),
+            None => Err(invalid_data("unreadable replication file").into()),
+        }
+    }
+
+    /// Records the part the database plays in replication, `None` for none,
+    /// syncing the log first so that the records a follower replicated
+    /// before `offset` are durable.
+    pub(crate) fn set_replication_role(&self, role: Option<Role>) -> Result<()> {
+        let persistence = self.replicated()?;
+        let path = persistence.dir.join(REPLICATION_FILE);
+        let text = match role {
+            Some(Role::Primary { id }) => format!("primary {:016x}", id),
+            Some(Role::Follower { id, offset }) => {
+                persistence.wal.sync()?;
+                format!("follower {:016x} {}", id, offset)
+            }
+            None => return Ok(backup::remove_if_exists(&path)?),
+        };
+        let temp = persistence.dir.join(format!("{}.tmp", REPLICATION_FILE));
+        let mut file = File::create(&temp)?;
+        writeln!(file, "{}", text)?;
+        file.sync_all()?;
+        files::rename_durably(&temp, path)?;
+        Ok(())
+    }
+
+    /// Makes the database take only the writes replicated from a primary,
+    /// or writes of its own again, see [`Database::is_follower`].
+    pub(crate) fn set_follower(&self, follower: bool) -> Result<()> {
+        let persistence = self.replicated()?;
+        if persistence.wal.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        persistence.wal.set_follower(follower);
+        Ok(())
+    }
+
+    /// Records that the follower connected as `follower` from `address`
+    /// reached `offset`, `None` if it has yet to be synced in full.
+    pub(crate) fn follower_acknowledged(
+        &self,
+        follower: u64,
+        address: SocketAddr,
+        offset: Option<Lsn>,
+    ) -> Result<()> {
+        let persistence = self.replicated()?;
+        let acknowledged = Follower {
+            address,
+            offset,
+            acknowledged: Instant::now(),
+        };
+        let mut progress = persistence.replication.lock().unwrap();
+        progress.followers.insert(follower, acknowledged);
+        Ok(())
+    }
+
+    /// Forgets the follower connected as `follower`, once disconnected.
+    pub(crate) fn follower_disconnected(&self, follower: u64) {
+        if let Some(persistence) = &self.persistence {
+            let mut progress = persistence.replication.lock().unwrap();
+            progress.followers.remove(&follower);
+        }
+    }
+
+    /// Records that the database, following a primary, caught up with it.
+    pub(crate) fn caught_up(&self) -> Result<()> {
+        let persistence = self.replicated()?;
+        persistence.replication.lock().unwrap().caught_up = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Returns the LSN the next record of the log gets.
+    pub(crate) fn next_lsn(&self) -> Result<Lsn> {
+        Ok(self.replicated()?.wal.next_lsn())
+    }
+
+    /// Returns the LSN of the first record the log still holds.
+    pub(crate) fn first_lsn(&self) -> Result<Lsn> {
+        let wal = &self.replicated()?.wal;
+        let segments = wal.segments()?;
+        Ok(segments
+            .first()
+            .map_or_else(|| wal.next_lsn(), |first| first.start))
+    }
+
+    /// Calls `replicate` with every record of the log from `lsn` on.
+    ///
+    /// # Returns
+    ///
+    /// The LSN of the record following the last one, an error of kind
+    /// `NotFound` if the log no longer holds the records from `lsn`.
+    pub(crate) fn log_records(
+        &self,
+        lsn: Lsn,
+        replicate: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> Result<Lsn> {
+        let wal = &self.replicated()?.wal;
+        // every record before it is logged already, time marks included
+        let end = wal.next_lsn();
+        if self.first_lsn()? > lsn {
+            let err = io::Error::new(io::ErrorKind::NotFound, "log removed from the offset");
+            return Err(err.into());
+        }
+        let mut next = lsn;
+        for record in wal.iter_from(lsn)? {
+            let (lsn, payload) = record?;
+            replicate(&payload)?;
+            next = lsn + 1;
+        }
+        Ok(next.max(end))
+    }
+
+    /// Calls `replicate` with the records creating every durable keyspace
+    /// and putting its entries, the records of the log from the LSN
+    /// returned on bringing them up to date.
+    pub(crate) fn snapshot_records(
+        &self,
+        replicate: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> Result<Lsn> {
+        let persistence = self.replicated()?;
+        // listed as of the LSN returned, their entries being copied
+        // afterwards as a checkpoint does
+        let (lsn, entries) = {
+            let keyspaces = self.keyspaces.read(self.lock_policy.read);
+            let recovered = persistence.recovered.lock().unwrap();
+            let lsn = persistence.wal.next_lsn();
+            let mut entries = Vec::with_capacity(recovered.ids.len());
+            for (name, &id) in &recovered.ids {
+                let keyspace = match recovered.pending.get(&id) {
+                    Some(pending) => Entries::Pending(pending.clone()),
+                    None => keyspaces
+                        .get(name)
+                        .and_then(|collection| collection.checkpointed.clone())
+                        .map(Entries::Open)
+                        .ok_or_else(|| {
+                            io::Error::other(format!("durable keyspace {} is not open", name))
+                        })?,
+                };
+                entries.push((id, name.clone(), keyspace));
+            }
+            (lsn, entries)
+        };
+        let mut record = Vec::new();
+        for (id, name, keyspace) in entries {
+            replicate(&LogRecord::encode_create(id, &name))?;
+            let prefix = LogRecord::write_prefix(id);
+            let mut write = |mutation: &[u8]| {
+                record.clear();
+                record.extend_from_slice(&prefix);
+                record.extend_from_slice(mutation);
+                replicate(&record)
+            };
+            match keyspace {
+                Entries::Open(keyspace) => keyspace.write_entries(&mut write)?,
+                Entries::Pending(mutations) => {
+                    for mutation in &mutations {
+                        write(mutation)?;
+                    }
+                }
+            }
+        }
+        Ok(lsn)
+    }
+
+    /// Logs and applies `record`, replicated from the log of a primary.
+    /// Records applied already, as a follower resuming from an earlier
+    /// offset than it reached does, leave the keyspaces as they were.
+    pub(crate) fn apply_replicated(&self, record: &[u8]) -> Result<()> {
+        let persistence = self.replicated()?;
+        let wal = &persistence.wal;
+        match LogRecord::decode(record)? {
+            LogRecord::Create { id, name } => {
+                let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+                let mut recovered = persistence.recovered.lock().unwrap();
+                recovered.next_id = recovered.next_id.max(id + 1);
+                if recovered.ids.get(&name) == Some(&id) {
+                    return Ok(());
+                }
+                wal.append_replicated(record)?;
+                if let Some(previous) = recovered.ids.insert(name.clone(), id) {
+                    recovered.pending.remove(&previous);
+                }
+                recovered.pending.insert(id, Vec::new());
+                if keyspaces
+                    .get(&name)
+                    .is_some_and(|collection| collection.checkpointed.is_some())
+                {
+                    keyspaces.remove(&name);
+                }
+            }
+            LogRecord::Write { id, mutation } => {
+                self.apply_replicated_writes(persistence, record, &[(id, mutation)])?
+            }
+            LogRecord::Batch { writes } => {
+                self.apply_replicated_writes(persistence, record, &writes)?
+            }
+            LogRecord::Drop { id } => {
+                let mut keyspaces = self.keyspaces.write(self.lock_policy.write);
+                let mut recovered = persistence.recovered.lock().unwrap();
+                let name = match recovered.ids.iter().find(|(_, live)| **live == id) {
+                    Some((name, _)) => name.clone(),
+                    None => return Ok(()),
+                };
+                wal.append_replicated(record)?;
+                recovered.ids.remove(&name);
+                recovered.pending.remove(&id);
+                if keyspaces
+                    .get(&name)
+                    .is_some_and(|collection| collection.checkpointed.is_some())
+                {
+                    keyspaces.remove(&name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs `record`, replicated from the log of a primary, then applies
+    /// its `writes` to their keyspaces.
+    fn apply_replicated_writes(
+        &self,
+        persistence: &Persistence,
+        record: &[u8],
+        writes: &[(u64, &[u8])],
+    ) -> Result<()> {
+        let keyspaces = self.keyspaces.read(self.lock_policy.read);
+        let mut recovered = persistence.recovered.lock().unwrap();
+        // writes to dropped keyspaces are left out
+        let live: Vec<_> = writes
+            .iter()
+            .filter_map(|&(id, mutation)| {
+                let (name, _) = recovered.ids.iter().find(|(_, live)| **live == id)?;
+                Some((name.clone(), id, mutation))
+            })
+            .collect();
+        if live.is_empty() {
+            return Ok(());
+        }
+        persistence.wal.append_replicated(record)?;
+        for (name, id, mutation) in live {
+            match recovered.pending.get_mut(&id) {
+                Some(pending) => pending.push(mutation.to_vec()),
+                None => {
+                    let open = keyspaces.get(&name);
+                    if let Some(keyspace) = open.and_then(|open| open.checkpointed.as_ref()) {
+                        keyspace.apply(mutation)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops every durable keyspace, before a full sync from a primary.
+    pub(crate) fn clear_replicated(&self) -> Result<()> {
+        let persistence = self.replicated()?;
+        let ids: Vec<u64> = {
+            let recovered = persistence.recovered.lock().unwrap();
+            recovered.ids.values().copied().collect()
+        };
+        for id in ids {
+            self.apply_replicated(&LogRecord::encode_drop(id))?;
+        }
+        Ok(())
+    }
+}
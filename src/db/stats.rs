@@ -0,0 +1,363 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::net::SocketAddr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::checkpoint::CheckpointStatus;
+use super::recovery::RecoveryReport;
+use super::{DurableTtl, Store, Tiered};
+use crate::codec::{Decode, Encode};
+use crate::collections::map::Map;
+use crate::storage::StorageEngine;
+use crate::wal::{Durable, Lsn};
+
+/// Statistics of a [`Database`](super::Database), see
+/// [`Database::stats`](super::Database::stats).
+///
+/// They are displayed as `key:value` lines, in sections headed by a
+/// `# Name` line, as the `INFO` command of Redis reports them:
+///
+/// ```text
+/// # Database
+/// uptime_in_seconds:42
+/// keyspaces:1
+/// used_memory:1268
+/// # Persistence
+/// read_only:0
+/// ...
+/// # Keyspace
+/// keyspace_users:kind=durable,keys=3,memory=1268
+/// ```
+///
+/// The characters of keyspace names that would break the lines, `%`, `:`,
+/// `,`, `=`, whitespace and control characters, are displayed as their
+/// UTF-8 bytes, each as `%` followed by its two hexadecimal digits, as in
+/// `keyspace_a%3Ab` for the keyspace `a:b`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// How long the database has been open.
+    pub uptime: Duration,
+    /// The keyspaces open, sorted by name.
+    pub keyspaces: Vec<KeyspaceStats>,
+    /// The files of the database, `None` if it is not stored on disk.
+    pub persistence: Option<PersistenceStats>,
+    /// The part the database plays in [replication](crate::replication).
+    pub replication: ReplicationStats,
+}
+
+impl Stats {
+    /// Returns the estimate of the memory used by the keyspaces, in bytes.
+    pub fn used_memory(&self) -> usize {
+        self.keyspaces.iter().map(|keyspace| keyspace.memory).sum()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Database")?;
+        writeln!(f, "uptime_in_seconds:{}", self.uptime.as_secs())?;
+        writeln!(f, "keyspaces:{}", self.keyspaces.len())?;
+        writeln!(f, "used_memory:{}", self.used_memory())?;
+        if let Some(persistence) = &self.persistence {
+            write!(f, "{}", persistence)?;
+        }
+        writeln!(f, "# Replication")?;
+        match &self.replication {
+            ReplicationStats::None => writeln!(f, "role:none")?,
+            ReplicationStats::Primary { followers } => {
+                writeln!(f, "role:primary")?;
+                writeln!(f, "connected_followers:{}", followers.len())?;
+                for (index, follower) in followers.iter().enumerate() {
+                    write!(f, "follower{}:address={}", index, follower.address)?;
+                    if let (Some(offset), Some(lag)) = (follower.offset, follower.lag) {
+                        write!(f, ",offset={},lag={}", offset, lag)?;
+                    }
+                    let acknowledged = follower.acknowledged.as_millis();
+                    writeln!(f, ",last_ack_ms={}", acknowledged)?;
+                }
+            }
+            ReplicationStats::Follower { offset, staleness } => {
+                writeln!(f, "role:follower")?;
+                if let Some(offset) = offset {
+                    writeln!(f, "follower_offset:{}", offset)?;
+                }
+                if let Some(staleness) = staleness {
+                    writeln!(f, "follower_staleness_ms:{}", staleness.as_millis())?;
+                }
+            }
+        }
+        writeln!(f, "# Keyspace")?;
+        for keyspace in &self.keyspaces {
+            let name = escape(&keyspace.name);
+            write!(f, "keyspace_{}:kind={}", name, keyspace.kind)?;
+            if let Some(keys) = keyspace.keys {
+                write!(f, ",keys={}", keys)?;
+            }
+            writeln!(f, ",memory={}", keyspace.memory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `name` with the bytes breaking `key:value` lines escaped, see
+/// [`Stats`].
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '%' | ':' | ',' | '=' => {}
+            c if c.is_whitespace() || c.is_control() => {}
+            c => {
+                escaped.push(c);
+                continue;
+            }
+        }
+        for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Statistics of a keyspace of a [`Database`](super::Database), see
+/// [`Stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyspaceStats {
+    /// The name of the keyspace.
+    pub name: String,
+    /// What the keyspace was opened as: `map`, `durable`, `durable_ttl`,
+    /// `store` or `tiered`.
+    pub kind: &'static str,
+    /// The number of keys of the keyspace, `None` for those keeping their
+    /// entries in the engine, which are not counted.
+    pub keys: Option<usize>,
+    /// The estimate of the memory used by the keyspace in bytes, see
+    /// [`Map::memory_usage`], that of the hot tier of tiered keyspaces and
+    /// none for stores.
+    pub memory: usize,
+}
+
+/// Statistics of the files of a [`Database`](super::Database) stored on
+/// disk, see [`Stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PersistenceStats {
+    /// Whether the database was opened read-only.
+    pub read_only: bool,
+    /// The outcome of the checkpoints, see
+    /// [`Database::checkpoint_status`](super::Database::checkpoint_status).
+    pub checkpoint: CheckpointStatus,
+    /// LSN of the next record of the log.
+    pub wal_next_lsn: Lsn,
+    /// Bytes of log kept.
+    pub wal_bytes: u64,
+    /// What was recovered when opening the database, see
+    /// [`Database::recovery_report`](super::Database::recovery_report).
+    pub recovery: RecoveryReport,
+}
+
+impl fmt::Display for PersistenceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checkpoint = &self.checkpoint;
+        let completed_at = checkpoint
+            .completed_at
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        writeln!(f, "# Persistence")?;
+        writeln!(f, "read_only:{}", self.read_only as u8)?;
+        writeln!(f, "clean_shutdown:{}", self.recovery.clean_shutdown as u8)?;
+        writeln!(f, "records_replayed:{}", self.recovery.records_replayed)?;
+        writeln!(f, "checkpoints:{}", checkpoint.checkpoints)?;
+        writeln!(f, "last_checkpoint_lsn:{}", checkpoint.lsn.unwrap_or(0))?;
+        writeln!(f, "last_checkpoint_time:{}", completed_at)?;
+        writeln!(f, "last_checkpoint_bytes:{}", checkpoint.size)?;
+        let status = match checkpoint.last_error {
+            Some(_) => "err",
+            None => "ok",
+        };
+        writeln!(f, "last_checkpoint_status:{}", status)?;
+        writeln!(f, "wal_next_lsn:{}", self.wal_next_lsn)?;
+        writeln!(f, "wal_bytes:{}", self.wal_bytes)
+    }
+}
+
+/// The part a [`Database`](super::Database) plays in
+/// [replication](crate::replication), see [`Stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicationStats {
+    /// The database plays no part.
+    None,
+    /// The database serves its log to `followers`, those connected to its
+    /// `replication::Primary`, in the order they connected.
+    Primary { followers: Vec<FollowerStats> },
+    /// The database follows a primary, having replicated its log up to
+    /// `offset`, the LSN of the next record to replicate as last recorded,
+    /// `None` until a full sync completes. It was last caught up with the
+    /// primary `staleness` ago, `None` until it first is.
+    Follower {
+        offset: Option<Lsn>,
+        staleness: Option<Duration>,
+    },
+}
+
+/// A follower connected to a primary, see [`ReplicationStats::Primary`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FollowerStats {
+    /// The address the follower connected from.
+    pub address: SocketAddr,
+    /// The LSN of the next record to replicate, as the follower last
+    /// acknowledged, `None` until it completes a full sync.
+    pub offset: Option<Lsn>,
+    /// The records of the log the follower has yet to acknowledge, `None`
+    /// until it completes a full sync.
+    pub lag: Option<u64>,
+    /// How long ago the follower last acknowledged, or connected.
+    pub acknowledged: Duration,
+}
+
+/// A keyspace measured for [`Stats`].
+pub(super) trait Measured: Send + Sync {
+    /// Returns what the keyspace was opened as, see [`KeyspaceStats::kind`].
+    fn kind(&self) -> &'static str;
+
+    /// Returns the number of keys, if counted.
+    fn keys(&self) -> Option<usize>;
+
+    /// Returns the estimate of the memory used, in bytes.
+    fn memory(&self) -> usize;
+}
+
+impl<K, V, H> Measured for Map<K, V, H>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+    H: BuildHasher + Send + Sync,
+{
+    fn kind(&self) -> &'static str {
+        "map"
+    }
+
+    fn keys(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn memory(&self) -> usize {
+        self.memory_usage().total()
+    }
+}
+
+impl<K, V, H> Measured for Durable<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode + Send + Sync,
+    V: Clone + Encode + Decode + Send + Sync,
+    H: BuildHasher + Send + Sync,
+{
+    fn kind(&self) -> &'static str {
+        "durable"
+    }
+
+    fn keys(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn memory(&self) -> usize {
+        self.map().memory_usage().total()
+    }
+}
+
+impl<K, V, H> Measured for DurableTtl<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode + Send + Sync,
+    V: Clone + Encode + Decode + Send + Sync,
+    H: BuildHasher + Send + Sync,
+{
+    fn kind(&self) -> &'static str {
+        "durable_ttl"
+    }
+
+    fn keys(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn memory(&self) -> usize {
+        self.map().memory_usage().total()
+    }
+}
+
+impl<K, V, E> Measured for Store<K, V, E>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+    E: StorageEngine,
+{
+    fn kind(&self) -> &'static str {
+        "store"
+    }
+
+    fn keys(&self) -> Option<usize> {
+        None
+    }
+
+    fn memory(&self) -> usize {
+        0
+    }
+}
+
+impl<K, V, E> Measured for Tiered<K, V, E>
+where
+    K: Hash + Eq + Clone + Encode + Decode + Send + Sync,
+    V: Clone + Encode + Decode + Send + Sync,
+    E: StorageEngine,
+{
+    fn kind(&self) -> &'static str {
+        "tiered"
+    }
+
+    fn keys(&self) -> Option<usize> {
+        None
+    }
+
+    fn memory(&self) -> usize {
+        self.hot().memory_usage().total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+
+    #[test]
+    fn test_keyspace_names_are_escaped() {
+        let db = Database::new();
+        for name in &[
+            "plain",
+            "a:b,c=d",
+            "line\nbreak",
+            "100%",
+            "caf\u{e9} \u{2028}",
+        ] {
+            db.open_map::<u64, u64>(name).unwrap().put(&1, 1);
+        }
+        let report = db.stats().unwrap().to_string();
+        let lines: Vec<_> = report
+            .lines()
+            .skip_while(|line| *line != "# Keyspace")
+            .skip(1)
+            .collect();
+        assert_eq!(lines.len(), 5);
+        for line in &lines {
+            let (key, fields) = line.split_once(':').unwrap();
+            assert!(!key.contains(|c: char| c == ',' || c == '=' || c.is_whitespace()));
+            assert_eq!(fields.split(',').count(), 3);
+        }
+        for name in &[
+            "100%25",
+            "a%3Ab%2Cc%3Dd",
+            "caf\u{e9}%20%E2%80%A8",
+            "line%0Abreak",
+            "plain",
+        ] {
+            let key = format!("keyspace_{}:", name);
+            assert!(lines.iter().any(|line| line.starts_with(&key)), "{}", key);
+        }
+    }
+}
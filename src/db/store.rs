@@ -0,0 +1,325 @@
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::codec::{decode_all, invalid_data, Decode, Encode};
+use crate::storage::{MemoryEngine, StorageEngine};
+
+/// Id of the keyspace the names of the other ones are recorded in, keyed
+/// by name with their id as value.
+const CATALOG_ID: u32 = 0;
+
+/// Returns the prefix of the keys of the keyspace `id` in the engine.
+fn prefix(id: u32) -> [u8; 4] {
+    id.to_be_bytes()
+}
+
+/// Returns the bounds of the keys of the keyspace `id` in the engine.
+fn bounds(id: u32) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let end = match id.checked_add(1) {
+        Some(next) => Bound::Excluded(prefix(next).to_vec()),
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix(id).to_vec()), end)
+}
+
+/// Returns the entries of the keyspace `id`, their keys stripped of its
+/// prefix.
+fn scan_keyspace<E: StorageEngine>(engine: &E, id: u32) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let (start, end) = bounds(id);
+    let range = (
+        start.as_ref().map(Vec::as_slice),
+        end.as_ref().map(Vec::as_slice),
+    );
+    let mut entries = engine.scan(range)?;
+    for (key, _) in &mut entries {
+        key.drain(..4);
+    }
+    Ok(entries)
+}
+
+/// Returns the id of the keyspace `name`, if it exists.
+pub(super) fn lookup<E: StorageEngine>(engine: &E, name: &str) -> io::Result<Option<u32>> {
+    let mut key = prefix(CATALOG_ID).to_vec();
+    key.extend_from_slice(name.as_bytes());
+    engine.get(&key)?.map(|id| decode_all(&id)).transpose()
+}
+
+/// Returns the id of the keyspace `name`, recording it in the catalog of
+/// the engine with the next free id if it does not exist.
+pub(super) fn create<E: StorageEngine>(engine: &E, name: &str) -> io::Result<u32> {
+    if let Some(id) = lookup(engine, name)? {
+        return Ok(id);
+    }
+    let mut next = CATALOG_ID + 1;
+    for (_, id) in scan_keyspace(engine, CATALOG_ID)? {
+        next = next.max(decode_all::<u32>(&id)? + 1);
+    }
+    let mut key = prefix(CATALOG_ID).to_vec();
+    key.extend_from_slice(name.as_bytes());
+    let mut id = Vec::new();
+    next.encode(&mut id);
+    engine.put(&key, &id)?;
+    Ok(next)
+}
+
+/// Removes the keyspace `name` along with its entries from the engine.
+///
+/// # Returns
+///
+/// `true` if the keyspace existed.
+pub(super) fn remove<E: StorageEngine>(engine: &E, name: &str) -> io::Result<bool> {
+    let id = match lookup(engine, name)? {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+    for (key, _) in scan_keyspace(engine, id)? {
+        let mut full_key = prefix(id).to_vec();
+        full_key.extend_from_slice(&key);
+        engine.delete(&full_key)?;
+    }
+    let mut key = prefix(CATALOG_ID).to_vec();
+    key.extend_from_slice(name.as_bytes());
+    engine.delete(&key)
+}
+
+/// Returns the names of the keyspaces recorded in the catalog of the
+/// engine.
+pub(super) fn names<E: StorageEngine>(engine: &E) -> io::Result<Vec<String>> {
+    scan_keyspace(engine, CATALOG_ID)?
+        .into_iter()
+        .map(|(name, _)| String::from_utf8(name).map_err(|_| invalid_data("invalid keyspace name")))
+        .collect()
+}
+
+/// A keyspace of a [`Database`](super::Database) whose entries live in its
+/// [`StorageEngine`], opened with
+/// [`Database::open_store`](super::Database::open_store).
+///
+/// Keys and values are encoded with [`crate::codec`], keys behind the id
+/// of the keyspace, so that the keyspaces of a database share its engine
+/// without their entries mixing. Whether the entries survive the database
+/// is up to the engine.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::Database;
+/// use palladiumdb::storage::BTreeEngine;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-db-store");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let db = Database::with_engine(BTreeEngine::open(&dir).unwrap());
+/// let users = db.open_store::<String, u64>("users").unwrap();
+/// users.put(&"alice".to_string(), &31).unwrap();
+/// drop(users);
+/// drop(db);
+///
+/// let db = Database::with_engine(BTreeEngine::open(&dir).unwrap());
+/// let users = db.open_store::<String, u64>("users").unwrap();
+/// assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Store<K, V, E = MemoryEngine> {
+    engine: Arc<E>,
+    id: u32,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, E> Store<K, V, E>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+    E: StorageEngine,
+{
+    pub(super) fn new(engine: Arc<E>, id: u32) -> Self {
+        Store {
+            engine,
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    fn key(&self, key: &K) -> Vec<u8> {
+        let mut buf = prefix(self.id).to_vec();
+        key.encode(&mut buf);
+        buf
+    }
+
+    /// Returns the value corresponding to the key.
+    ///
+    /// # Returns
+    ///
+    /// An error if the engine fails or the value cannot be decoded as `V`.
+    pub fn get(&self, key: &K) -> io::Result<Option<V>> {
+        self.engine
+            .get(&self.key(key))?
+            .map(|value| decode_all(&value))
+            .transpose()
+    }
+
+    /// Returns `true` if the keyspace contains a value for the key.
+    pub fn contains_key(&self, key: &K) -> io::Result<bool> {
+        Ok(self.engine.get(&self.key(key))?.is_some())
+    }
+
+    /// Establishes a key value mapping for the key value pair.
+    pub fn put(&self, key: &K, value: &V) -> io::Result<()> {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        self.engine.put(&self.key(key), &buf)
+    }
+
+    /// Erases the value associated with `key`, returning it if it was
+    /// present.
+    pub fn remove(&self, key: &K) -> io::Result<Option<V>> {
+        let key = self.key(key);
+        let value = match self.engine.get(&key)? {
+            Some(value) => decode_all(&value)?,
+            None => return Ok(None),
+        };
+        self.engine.delete(&key)?;
+        Ok(Some(value))
+    }
+
+    /// Returns the entries of the keyspace, sorted by encoded key.
+    pub fn entries(&self) -> io::Result<Vec<(K, V)>> {
+        scan_keyspace(&*self.engine, self.id)?
+            .into_iter()
+            .map(|(key, value)| Ok((decode_all(&key)?, decode_all(&value)?)))
+            .collect()
+    }
+
+    /// Returns the engine the entries live in.
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use crate::storage::{BTreeBuilder, LsmBuilder, MemoryEngine, StorageEngine};
+    use std::fs;
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+    use std::path::Path;
+
+    /// Checks scans and snapshots of `engine`, then uses it through stores.
+    fn check_engine<E: StorageEngine + 'static>(engine: E) -> E {
+        for key in 0..2000u32 {
+            engine.put(&key.to_be_bytes(), &key.to_le_bytes()).unwrap();
+        }
+        let snapshot = engine.snapshot().unwrap();
+        // overwrite, delete and add enough for pages to be reused, tables
+        // compacted and memtables flushed
+        for round in 0..3u32 {
+            for key in 0..3000u32 {
+                match key % 3 {
+                    0 => assert_eq!(
+                        engine.delete(&key.to_be_bytes()).unwrap(),
+                        round == 0 && key < 2000
+                    ),
+                    _ => engine.put(&key.to_be_bytes(), &[round as u8; 40]).unwrap(),
+                }
+            }
+        }
+        engine.flush().unwrap();
+
+        let all = snapshot.scan((Unbounded, Unbounded)).unwrap();
+        assert_eq!(all.len(), 2000);
+        for (index, (key, value)) in all.iter().enumerate() {
+            assert_eq!(key[..], (index as u32).to_be_bytes());
+            assert_eq!(value[..], (index as u32).to_le_bytes());
+        }
+        assert_eq!(
+            snapshot.get(&3u32.to_be_bytes()).unwrap(),
+            Some(3u32.to_le_bytes().to_vec())
+        );
+        assert_eq!(snapshot.get(&2500u32.to_be_bytes()).unwrap(), None);
+        drop(snapshot);
+
+        let (start, end) = (10u32.to_be_bytes(), 20u32.to_be_bytes());
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<u8> {
+            entries.iter().map(|(key, _)| key[3]).collect()
+        };
+        let range = engine
+            .scan((Included(&start[..]), Excluded(&end[..])))
+            .unwrap();
+        assert_eq!(keys(range), vec![10, 11, 13, 14, 16, 17, 19]);
+        let range = engine
+            .scan((Excluded(&start[..]), Included(&end[..])))
+            .unwrap();
+        assert_eq!(keys(range), vec![11, 13, 14, 16, 17, 19, 20]);
+        assert_eq!(engine.scan((Unbounded, Unbounded)).unwrap().len(), 2000);
+        for key in 0..3000u32 {
+            engine.delete(&key.to_be_bytes()).unwrap();
+        }
+
+        let db = Database::with_engine(engine);
+        let users = db.open_store::<String, u64>("users").unwrap();
+        let sessions = db.open_store::<u64, String>("sessions").unwrap();
+        users.put(&"alice".to_string(), &31).unwrap();
+        users.put(&"bob".to_string(), &40).unwrap();
+        sessions.put(&7, &"alice".to_string()).unwrap();
+        assert_eq!(users.remove(&"bob".to_string()).unwrap(), Some(40));
+        assert_eq!(users.entries().unwrap(), vec![("alice".to_string(), 31)]);
+        assert!(db.open_store::<u64, u64>("users").is_err());
+        assert!(db.drop_map("sessions").unwrap());
+        assert_eq!(db.map_names(), vec!["users".to_string()]);
+        assert_eq!(db.engine().scan((Unbounded, Unbounded)).unwrap().len(), 2);
+        drop((users, sessions));
+        db.close().unwrap();
+        match std::sync::Arc::try_unwrap(db.engine) {
+            Ok(engine) => engine,
+            Err(_) => panic!("engine still shared"),
+        }
+    }
+
+    fn check_reopened<E: StorageEngine + 'static>(engine: E) {
+        let db = Database::with_engine(engine);
+        assert_eq!(db.map_names(), vec!["users".to_string()]);
+        let users = db.open_store::<String, u64>("users").unwrap();
+        assert_eq!(users.get(&"alice".to_string()).unwrap(), Some(31));
+        // the catalog hands out new ids after the recorded ones
+        let orders = db.open_store::<u64, u64>("orders").unwrap();
+        orders.put(&1, &1).unwrap();
+        assert_eq!(users.entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_engines_scan_snapshot_and_back_stores() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let btree = |dir: &Path| {
+            BTreeBuilder::new()
+                .page_size(1024)
+                .max_dirty_pages(16)
+                .open(dir.join("btree"))
+                .unwrap()
+        };
+        let lsm = |dir: &Path| {
+            LsmBuilder::new()
+                .memtable_size(16 << 10)
+                .table_size(16 << 10)
+                .open(dir.join("lsm"))
+                .unwrap()
+        };
+
+        check_engine(MemoryEngine::new());
+        drop(check_engine(btree(&dir)));
+        check_reopened(btree(&dir));
+        let engine = check_engine(lsm(&dir));
+        engine.wait_for_compactions().unwrap();
+        drop(engine);
+        check_reopened(lsm(&dir));
+        #[cfg(feature = "mmap")]
+        {
+            use crate::storage::MmapEngine;
+            drop(check_engine(MmapEngine::open(dir.join("mmap")).unwrap()));
+            check_reopened(MmapEngine::open(dir.join("mmap")).unwrap());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
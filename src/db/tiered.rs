@@ -0,0 +1,393 @@
+use std::hash::Hash;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::store::Store;
+use crate::codec::{Decode, Encode};
+use crate::collections::cache::recency::Recency;
+use crate::collections::map::{Map, DEFAULT_BUCKET_COUNT};
+use crate::storage::{MemoryEngine, StorageEngine};
+
+/// Number of locks the keys are spread over, see `Tiered::stripes`.
+const STRIPES: usize = 64;
+
+/// Thresholds of a [`Tiered`] keyspace, opened with
+/// [`Database::open_tiered`](super::Database::open_tiered).
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::TierPolicy;
+///
+/// // demote down to 8000 entries whenever more than 10000 are hot
+/// let policy = TierPolicy::new().max_hot_entries(10_000).low_watermark(8_000);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TierPolicy {
+    max_hot_entries: usize,
+    low_watermark: Option<usize>,
+}
+
+impl Default for TierPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TierPolicy {
+    /// Creates a policy with every threshold set to its default.
+    pub fn new() -> Self {
+        TierPolicy {
+            max_hot_entries: 10_000,
+            low_watermark: None,
+        }
+    }
+
+    /// Sets the number of entries the hot tier holds at most before the
+    /// least recently used ones are demoted to the cold tier.
+    pub fn max_hot_entries(mut self, max_hot_entries: usize) -> Self {
+        self.max_hot_entries = max_hot_entries;
+        self
+    }
+
+    /// Sets the number of entries demotions bring the hot tier down to, so
+    /// that they happen in batches rather than on every write. Defaults to,
+    /// and is capped by, [`TierPolicy::max_hot_entries`].
+    pub fn low_watermark(mut self, low_watermark: usize) -> Self {
+        self.low_watermark = Some(low_watermark);
+        self
+    }
+
+    fn low(&self) -> usize {
+        self.low_watermark
+            .map_or(self.max_hot_entries, |low| low.min(self.max_hot_entries))
+    }
+}
+
+/// What the tiers of a [`Tiered`] keyspace served so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TierStats {
+    /// Lookups which found their entry in the hot tier.
+    pub hot_hits: u64,
+    /// Lookups which found their entry in the cold tier, promoting it.
+    pub cold_hits: u64,
+    /// Lookups which found no entry.
+    pub misses: u64,
+    /// Entries demoted to the cold tier.
+    pub demotions: u64,
+    /// Number of entries in the hot tier.
+    pub hot_entries: usize,
+}
+
+impl TierStats {
+    /// Returns the share of lookups served by the hot tier, 0 if there was
+    /// none.
+    pub fn hot_hit_ratio(&self) -> f64 {
+        match self.hot_hits + self.cold_hits + self.misses {
+            0 => 0.0,
+            lookups => self.hot_hits as f64 / lookups as f64,
+        }
+    }
+
+    /// Returns the share of lookups served by the cold tier, 0 if there was
+    /// none.
+    pub fn cold_hit_ratio(&self) -> f64 {
+        match self.hot_hits + self.cold_hits + self.misses {
+            0 => 0.0,
+            lookups => self.cold_hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// A keyspace of a [`Database`](super::Database) keeping its recently used
+/// entries in an in-memory [`Map`], the hot tier, and the others in the
+/// [`StorageEngine`] of the database, the cold tier, as a [`Store`] does,
+/// opened with [`Database::open_tiered`](super::Database::open_tiered).
+///
+/// Once the hot tier holds more than [`TierPolicy::max_hot_entries`], its
+/// least recently read or written entries are demoted to the cold tier,
+/// and entries read from the cold tier are promoted back to the hot one.
+///
+/// Entries promoted by reads are kept in the cold tier as well, so that
+/// demoting them again costs nothing, but written entries only reach it
+/// once demoted or [flushed](Tiered::flush): whether they survive the
+/// database is up to the engine past that point only.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::{Database, TierPolicy};
+/// use palladiumdb::storage::BTreeEngine;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-db-tiered");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let db = Database::with_engine(BTreeEngine::open(&dir).unwrap());
+/// let policy = TierPolicy::new().max_hot_entries(2);
+/// let users = db.open_tiered::<u64, String>("users", policy).unwrap();
+/// for id in 0..4 {
+///     users.put(&id, format!("user {}", id)).unwrap();
+/// }
+/// assert_eq!(users.stats().demotions, 2);
+///
+/// assert_eq!(users.get(&0).unwrap(), Some("user 0".to_string()));
+/// assert_eq!(users.stats().cold_hits, 1);
+/// users.flush().unwrap();
+/// # drop(users);
+/// # drop(db);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Tiered<K, V, E = MemoryEngine> {
+    // the values along with whether they were written since last stored in
+    // the cold tier
+    hot: Map<K, (V, bool)>,
+    cold: Store<K, V, E>,
+    policy: TierPolicy,
+    // recency order of the keys of the hot tier, each charged 1
+    recency: Mutex<Recency<K>>,
+    // serialize the operations on a key, which move it between the tiers
+    stripes: Vec<Mutex<()>>,
+    hot_hits: AtomicU64,
+    cold_hits: AtomicU64,
+    misses: AtomicU64,
+    demotions: AtomicU64,
+}
+
+impl<K, V, E> Tiered<K, V, E>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+    E: StorageEngine,
+{
+    pub(super) fn new(cold: Store<K, V, E>, policy: TierPolicy) -> Self {
+        let bucket_count = (policy.max_hot_entries / 8).max(DEFAULT_BUCKET_COUNT);
+        Tiered {
+            hot: Map::with_bucket_count(bucket_count),
+            cold,
+            policy,
+            recency: Mutex::new(Recency::new()),
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+            hot_hits: AtomicU64::new(0),
+            cold_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            demotions: AtomicU64::new(0),
+        }
+    }
+
+    fn stripe(&self, key: &K) -> &Mutex<()> {
+        &self.stripes[self.hot.hash(key) as usize % STRIPES]
+    }
+
+    /// Returns the value corresponding to the key, promoting it to the hot
+    /// tier if it was cold.
+    ///
+    /// # Returns
+    ///
+    /// An error if the engine fails or the value cannot be decoded as `V`.
+    pub fn get(&self, key: &K) -> io::Result<Option<V>> {
+        let promoted = {
+            let _stripe = self.stripe(key).lock().unwrap();
+            if let Some((value, _)) = self.hot.get(key) {
+                self.recency.lock().unwrap().touch(key);
+                self.hot_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(value));
+            }
+            let value = match self.cold.get(key)? {
+                Some(value) => value,
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    return Ok(None);
+                }
+            };
+            self.hot.put(key, (value.clone(), false));
+            self.recency.lock().unwrap().insert(key.clone(), 1);
+            self.cold_hits.fetch_add(1, Ordering::Relaxed);
+            value
+        };
+        self.demote_excess()?;
+        Ok(Some(promoted))
+    }
+
+    /// Returns `true` if either tier contains a value for the key, without
+    /// promoting it.
+    pub fn contains_key(&self, key: &K) -> io::Result<bool> {
+        let _stripe = self.stripe(key).lock().unwrap();
+        Ok(self.hot.get(key).is_some() || self.cold.contains_key(key)?)
+    }
+
+    /// Establishes a key value mapping for the key value pair in the hot
+    /// tier, demoting the least recently used entries if it is full.
+    ///
+    /// # Returns
+    ///
+    /// An error if the engine fails to store the entries demoted, which are
+    /// kept hot.
+    pub fn put(&self, key: &K, value: V) -> io::Result<()> {
+        {
+            let _stripe = self.stripe(key).lock().unwrap();
+            self.hot.put(key, (value, true));
+            self.recency.lock().unwrap().insert(key.clone(), 1);
+        }
+        self.demote_excess()
+    }
+
+    /// Erases the value associated with `key` from both tiers, returning it
+    /// if it was present.
+    pub fn remove(&self, key: &K) -> io::Result<Option<V>> {
+        let _stripe = self.stripe(key).lock().unwrap();
+        let hot = self.hot.remove(key);
+        self.recency.lock().unwrap().remove(key);
+        let cold = self.cold.remove(key)?;
+        Ok(hot.map(|(value, _)| value).or(cold))
+    }
+
+    /// Stores every entry of the hot tier written since it was last stored
+    /// in the cold tier, keeping it hot, then flushes the engine.
+    pub fn flush(&self) -> io::Result<()> {
+        for (key, _) in self.hot.iter() {
+            let _stripe = self.stripe(&key).lock().unwrap();
+            // checked again under the lock of the key, which may have been
+            // demoted or written in between
+            if let Some((value, true)) = self.hot.get(&key) {
+                self.cold.put(&key, &value)?;
+                self.hot.put(&key, (value, false));
+            }
+        }
+        self.cold.engine().flush()
+    }
+
+    /// Returns what the tiers served so far.
+    pub fn stats(&self) -> TierStats {
+        TierStats {
+            hot_hits: self.hot_hits.load(Ordering::Relaxed),
+            cold_hits: self.cold_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            demotions: self.demotions.load(Ordering::Relaxed),
+            hot_entries: self.hot.len(),
+        }
+    }
+
+    /// Returns the hot tier, along with whether the values were written
+    /// since last stored in the cold tier.
+    pub(super) fn hot(&self) -> &Map<K, (V, bool)> {
+        &self.hot
+    }
+
+    /// Returns the policy the keyspace was opened with.
+    pub fn policy(&self) -> TierPolicy {
+        self.policy
+    }
+
+    /// Returns the store holding the cold tier.
+    pub fn cold(&self) -> &Store<K, V, E> {
+        &self.cold
+    }
+
+    /// Demotes the least recently used entries of the hot tier down to the
+    /// low watermark, if it holds more than its maximum.
+    fn demote_excess(&self) -> io::Result<()> {
+        if self.recency.lock().unwrap().weight() <= self.policy.max_hot_entries {
+            return Ok(());
+        }
+        loop {
+            // popped before locking the key, which a writer of the key
+            // holding it would wait on the recency for otherwise
+            let key = {
+                let mut recency = self.recency.lock().unwrap();
+                if recency.weight() <= self.policy.low() {
+                    return Ok(());
+                }
+                match recency.pop_lru() {
+                    Some((key, _)) => key,
+                    None => return Ok(()),
+                }
+            };
+            self.demote(key)?;
+        }
+    }
+
+    /// Moves the entry of `key` from the hot tier to the cold one, only
+    /// writing it if it is dirty.
+    fn demote(&self, key: K) -> io::Result<()> {
+        let _stripe = self.stripe(&key).lock().unwrap();
+        // tracked again if written since it was popped, and demoted anyway
+        self.recency.lock().unwrap().remove(&key);
+        let (value, dirty) = match self.hot.get(&key) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        if dirty {
+            if let Err(err) = self.cold.put(&key, &value) {
+                self.recency.lock().unwrap().insert(key, 1);
+                return Err(err);
+            }
+        }
+        self.hot.unmap(&key);
+        self.demotions.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TierPolicy;
+    use crate::db::Database;
+    use crate::storage::LsmBuilder;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn test_tiers_demote_promote_and_survive_concurrent_writes() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-db-tiered-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let policy = TierPolicy::new().max_hot_entries(100).low_watermark(80);
+        let db = Database::with_engine(LsmBuilder::new().open(&dir).unwrap());
+        let tiered = db.open_tiered::<u64, u64>("counts", policy).unwrap();
+
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let tiered = tiered.clone();
+                thread::spawn(move || {
+                    for key in 0..250 {
+                        tiered.put(&(thread * 1000 + key), key).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let stats = tiered.stats();
+        assert!(stats.hot_entries <= 100);
+        assert_eq!(stats.demotions as usize + stats.hot_entries, 1000);
+
+        // cold entries are promoted, and demoted again without being lost
+        for thread in 0..4u64 {
+            for key in 0..250 {
+                assert_eq!(tiered.get(&(thread * 1000 + key)).unwrap(), Some(key));
+            }
+        }
+        assert_eq!(tiered.get(&5000).unwrap(), None);
+        let stats = tiered.stats();
+        assert!(stats.cold_hits >= 900 && stats.misses == 1);
+        assert!(stats.hot_hit_ratio() + stats.cold_hit_ratio() < 1.0);
+
+        assert_eq!(tiered.remove(&3).unwrap(), Some(3));
+        assert_eq!(tiered.remove(&3).unwrap(), None);
+        tiered.put(&1, 100).unwrap();
+        tiered.flush().unwrap();
+        drop(tiered);
+        db.close().unwrap();
+        drop(db);
+
+        // only the cold tier survives, holding every entry once flushed
+        let db = Database::with_engine(LsmBuilder::new().open(&dir).unwrap());
+        let store = db.open_store::<u64, u64>("counts").unwrap();
+        assert_eq!(store.entries().unwrap().len(), 999);
+        assert_eq!(store.get(&1).unwrap(), Some(100));
+        drop(store);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
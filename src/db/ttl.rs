@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::slice;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::checkpoint::Checkpointed;
+use crate::codec::{decode_all, decode_bytes, encode_bytes, invalid_data, Decode, Encode};
+use crate::collections::map::Map;
+use crate::wal::{unix_millis, Wal};
+
+/// Tags of the records of the writes to a TTL keyspace, following those of
+/// the [`Mutation`](crate::wal::Mutation)s of the other durable keyspaces.
+/// Keys are written as byte strings, so that expired entries can be told
+/// apart on recovery before the keyspace is opened with its types.
+const PUT: u8 = 2;
+const REMOVE: u8 = 3;
+
+fn encode_put<K: Encode, V: Encode>(buf: &mut Vec<u8>, key: &K, value: &V, deadline: u64) {
+    buf.push(PUT);
+    let mut key_bytes = Vec::new();
+    key.encode(&mut key_bytes);
+    encode_bytes(&key_bytes, buf);
+    deadline.encode(buf);
+    value.encode(buf);
+}
+
+fn encode_remove<K: Encode>(buf: &mut Vec<u8>, key: &K) {
+    buf.push(REMOVE);
+    let mut key_bytes = Vec::new();
+    key.encode(&mut key_bytes);
+    encode_bytes(&key_bytes, buf);
+}
+
+/// Splits the encoded key off `record`, along with the deadline if it is a
+/// put, leaving the value in it.
+fn decode_header<'a>(record: &mut &'a [u8]) -> io::Result<(&'a [u8], Option<u64>)> {
+    let tag = u8::decode(record)?;
+    let key = decode_bytes(record)?;
+    match tag {
+        PUT => Ok((key, Some(u64::decode(record)?))),
+        REMOVE => Ok((key, None)),
+        _ => Err(invalid_data("unknown TTL keyspace record")),
+    }
+}
+
+/// Applies to `map` the write logged as `record`.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if the record was not written for these
+/// key and value types, or not by a TTL keyspace.
+pub(super) fn apply<K, V, H>(map: &Map<K, (V, u64), H>, record: &[u8]) -> io::Result<()>
+where
+    K: Hash + Eq + Clone + Decode,
+    V: Clone + Decode,
+    H: BuildHasher,
+{
+    let mut value = record;
+    let (key, deadline) = decode_header(&mut value)?;
+    let key = decode_all::<K>(key)?;
+    match deadline {
+        Some(deadline) => map.put(&key, (decode_all(value)?, deadline)),
+        None if value.is_empty() => map.unmap(&key),
+        _ => return Err(invalid_data("trailing bytes after value")),
+    }
+    Ok(())
+}
+
+/// Reduces the logged writes of a TTL keyspace to a put of every entry
+/// live at `now`, in Unix milliseconds, leaving the writes of other
+/// keyspaces untouched.
+///
+/// # Returns
+///
+/// The number of entries dropped for having expired.
+pub(super) fn expire_pending(mutations: &mut Vec<Vec<u8>>, now: u64) -> u64 {
+    let mut last = HashMap::new();
+    for (index, mutation) in mutations.iter().enumerate() {
+        match decode_header(&mut &mutation[..]) {
+            Ok((key, deadline)) => last.insert(key, (index, deadline)),
+            // not a TTL keyspace
+            Err(_) => return 0,
+        };
+    }
+    let mut expired = 0;
+    let mut live: Vec<_> = last
+        .into_values()
+        .filter_map(|(index, deadline)| match deadline {
+            Some(deadline) if deadline <= now => {
+                expired += 1;
+                None
+            }
+            Some(_) => Some(index),
+            None => None,
+        })
+        .collect();
+    live.sort_unstable();
+    let mut kept = live.into_iter().peekable();
+    let mut index = 0;
+    mutations.retain(|_| {
+        let keep = kept.next_if_eq(&index).is_some();
+        index += 1;
+        keep
+    });
+    expired
+}
+
+/// Durable keyspace of a [`Database`](super::Database) where every entry
+/// expires after a time to live, see
+/// [`Database::open_durable_ttl`](super::Database::open_durable_ttl).
+///
+/// Entries are logged along with the time they expire at, by the clock of
+/// the system, so that entries which expired while the database was closed
+/// are dropped when recovering it rather than coming back, and accounted
+/// for in [`RecoveryReport::expired`](super::RecoveryReport::expired).
+/// Expired entries are invisible to lookups right away, as in a
+/// [`TtlMap`](crate::collections::ttl::TtlMap), and reclaimed by
+/// [`DurableTtl::purge_expired`] or by the next recovery.
+pub struct DurableTtl<K, V, H> {
+    map: Map<K, (V, u64), H>,
+    wal: Arc<Wal>,
+    /// Bytes every record starts with, telling apart the keyspaces sharing
+    /// the log.
+    prefix: Vec<u8>,
+}
+
+impl<K, V, H> DurableTtl<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+    H: BuildHasher,
+{
+    /// Wraps `map`, logging its writes to `wal` in records starting with
+    /// `prefix`.
+    pub(super) fn new(map: Map<K, (V, u64), H>, wal: Arc<Wal>, prefix: Vec<u8>) -> Self {
+        DurableTtl { map, wal, prefix }
+    }
+
+    fn now() -> u64 {
+        unix_millis(SystemTime::now())
+    }
+
+    /// Logs then establishes a key value mapping expiring after `ttl`.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the log, in which case the keyspace is
+    /// left unchanged.
+    pub fn put(&self, key: &K, value: V, ttl: Duration) -> io::Result<()> {
+        let deadline = Self::now().saturating_add(ttl.as_millis() as u64);
+        let mut record = self.prefix.clone();
+        encode_put(&mut record, key, &value, deadline);
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            self.wal.append(&record)?;
+            locked.put(key, (value, deadline));
+            Ok(())
+        })
+    }
+
+    /// Logs then resets the time to live of the entry of `key` to `ttl`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `key` was mapped to a value that had not expired yet, the
+    /// error of appending to the log otherwise.
+    pub fn expire(&self, key: &K, ttl: Duration) -> io::Result<bool> {
+        self.map
+            .with_keys_locked(slice::from_ref(key), |locked| match locked.get(key) {
+                Some((value, deadline)) if deadline > Self::now() => {
+                    let deadline = Self::now().saturating_add(ttl.as_millis() as u64);
+                    let mut record = self.prefix.clone();
+                    encode_put(&mut record, key, &value, deadline);
+                    self.wal.append(&record)?;
+                    locked.put(key, (value, deadline));
+                    Ok(true)
+                }
+                _ => Ok(false),
+            })
+    }
+
+    /// Returns the value mapped to `key`, unless it has expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        match self.map.get(key)? {
+            (value, deadline) if deadline > Self::now() => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the time left until the entry of `key` expires.
+    pub fn ttl(&self, key: &K) -> Option<Duration> {
+        let (_, deadline) = self.map.get(key)?;
+        match deadline.checked_sub(Self::now()) {
+            Some(left) if left > 0 => Some(Duration::from_millis(left)),
+            _ => None,
+        }
+    }
+
+    /// Logs then removes the entry of `key`, returning its value unless it
+    /// had expired. Nothing is logged if the key is absent.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the log, in which case the keyspace is
+    /// left unchanged.
+    pub fn remove(&self, key: &K) -> io::Result<Option<V>> {
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            let entry = locked.get(key);
+            if entry.is_some() {
+                let mut record = self.prefix.clone();
+                encode_remove(&mut record, key);
+                self.wal.append(&record)?;
+                locked.unmap(key);
+            }
+            Ok(entry.and_then(|(value, deadline)| (deadline > Self::now()).then_some(value)))
+        })
+    }
+
+    /// Unmaps every expired entry. Nothing is logged, the entries being
+    /// dropped on recovery anyway.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries unmapped.
+    pub fn purge_expired(&self) -> usize {
+        let now = Self::now();
+        let expired: Vec<K> = self
+            .map
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(key, _)| key)
+            .collect();
+        expired
+            .iter()
+            .filter(|key| {
+                self.map
+                    .with_keys_locked(slice::from_ref(*key), |locked| match locked.get(key) {
+                        Some((_, deadline)) if deadline <= now => {
+                            locked.unmap(key);
+                            true
+                        }
+                        _ => false,
+                    })
+            })
+            .count()
+    }
+
+    /// Returns the number of entries in the keyspace, including expired
+    /// entries which have not been purged yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the keyspace contains no entries, expired or not.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Flushes every logged write to stable storage, regardless of the sync
+    /// policy of the log.
+    pub fn sync(&self) -> io::Result<()> {
+        self.wal.sync()
+    }
+
+    /// Returns the map itself, values paired with the Unix millisecond they
+    /// expire at, for reads bypassing the log.
+    pub(crate) fn map(&self) -> &Map<K, (V, u64), H> {
+        &self.map
+    }
+}
+
+impl<K, V, H> Checkpointed for DurableTtl<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode + Send + Sync,
+    V: Clone + Encode + Decode + Send + Sync,
+    H: BuildHasher + Send + Sync,
+{
+    fn write_entries(&self, write: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        let now = Self::now();
+        let mut record = Vec::new();
+        for (key, (value, deadline)) in self.map.iter() {
+            if deadline > now {
+                record.clear();
+                encode_put(&mut record, &key, &value, deadline);
+                write(&record)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "server")]
+    fn apply(&self, mutation: &[u8]) -> io::Result<()> {
+        apply(&self.map, mutation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_expired_entries_are_dropped_on_recovery() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-ttl-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (short, long) = (Duration::from_millis(50), Duration::from_secs(3600));
+
+        let db = Database::open(&dir).unwrap();
+        let sessions = db.open_durable_ttl::<u64, String>("sessions").unwrap();
+        sessions.put(&1, "kept".to_string(), long).unwrap();
+        sessions.put(&2, "expired".to_string(), short).unwrap();
+        sessions.put(&3, "removed".to_string(), long).unwrap();
+        assert_eq!(sessions.remove(&3).unwrap(), Some("removed".to_string()));
+        sessions.put(&4, "extended".to_string(), short).unwrap();
+        assert!(sessions.expire(&4, long).unwrap());
+        db.checkpoint_now().unwrap();
+        sessions.put(&5, "expired".to_string(), short).unwrap();
+        sessions.put(&1, "overwritten".to_string(), short).unwrap();
+        sessions.put(&1, "kept".to_string(), long).unwrap();
+        drop((db, sessions));
+
+        thread::sleep(Duration::from_millis(100));
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.recovery_report().unwrap().expired, 2);
+        // checkpointed without being opened
+        db.checkpoint_now().unwrap();
+        drop(db);
+
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.recovery_report().unwrap().expired, 0);
+        let sessions = db.open_durable_ttl::<u64, String>("sessions").unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.get(&1), Some("kept".to_string()));
+        assert_eq!(sessions.get(&4), Some("extended".to_string()));
+        assert!(sessions.ttl(&4).unwrap() > short);
+        sessions
+            .put(&6, "expired".to_string(), Duration::ZERO)
+            .unwrap();
+        assert_eq!(sessions.get(&6), None);
+        assert_eq!(sessions.purge_expired(), 1);
+        assert!(db.open_durable::<u64, String>("sessions").is_err());
+        drop((db, sessions));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::backup;
+use super::checkpoint::{CHECKPOINT_FILE, KEYSPACES_DIR, MANIFEST_FILE};
+use super::migrations::FORMAT_FILE;
+use crate::storage::VacuumReport;
+
+/// When a [`Database`](super::Database) vacuums itself in the background,
+/// see [`DatabaseBuilder::vacuum_policy`](super::DatabaseBuilder::vacuum_policy).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VacuumPolicy {
+    /// Share of garbage, from 0 to 1, from which a file of the engine is
+    /// rewritten, see [`StorageEngine::vacuum`](crate::storage::StorageEngine::vacuum).
+    pub garbage_ratio: f64,
+    /// Pause between two vacuums.
+    pub interval: Duration,
+}
+
+impl Default for VacuumPolicy {
+    fn default() -> Self {
+        VacuumPolicy {
+            garbage_ratio: 0.5,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Removes the files of `dir` no checkpoint refers to: checkpoints of
+/// keyspaces not in `listed` and files left half-written by a crash, along
+/// with the [`CHECKPOINT_FILE`] of earlier releases once a manifest
+/// replaced it. Must not run while a checkpoint is written.
+pub(super) fn remove_obsolete(dir: &Path, listed: &HashSet<&Path>) -> io::Result<VacuumReport> {
+    let mut obsolete: Vec<PathBuf> = [MANIFEST_FILE, CHECKPOINT_FILE, FORMAT_FILE]
+        .iter()
+        .map(|name| dir.join(format!("{}.tmp", name)))
+        .collect();
+    if dir.join(MANIFEST_FILE).is_file() {
+        obsolete.push(dir.join(CHECKPOINT_FILE));
+    }
+    match fs::read_dir(dir.join(KEYSPACES_DIR)) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if !listed.contains(path.as_path()) {
+                    obsolete.push(path);
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let mut report = VacuumReport::default();
+    for path in obsolete {
+        let len = match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        backup::remove_if_exists(&path)?;
+        report.files_removed += 1;
+        report.bytes_reclaimed += len;
+    }
+    Ok(report)
+}
+
+/// Thread vacuuming a database according to a [`VacuumPolicy`], stopped and
+/// joined when dropped.
+pub(super) struct Vacuumer {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Vacuumer {
+    /// Starts calling `vacuum` every [`VacuumPolicy::interval`].
+    pub(super) fn spawn<F>(policy: VacuumPolicy, mut vacuum: F) -> io::Result<Self>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stopped = stop.clone();
+        let handle = thread::Builder::new()
+            .name("palladiumdb-vacuum".to_string())
+            .spawn(move || {
+                let (stopped, wakeup) = &*stopped;
+                loop {
+                    let guard = stopped.lock().unwrap();
+                    let (guard, _) = wakeup
+                        .wait_timeout_while(guard, policy.interval, |stopped| !*stopped)
+                        .unwrap();
+                    if *guard {
+                        return;
+                    }
+                    drop(guard);
+                    vacuum();
+                }
+            })?;
+        Ok(Vacuumer {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Vacuumer {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wakeup.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "mmap")]
+    use super::VacuumPolicy;
+    #[cfg(feature = "mmap")]
+    use crate::db::DatabaseBuilder;
+    use crate::db::{Database, CHECKPOINT_FILE, KEYSPACES_DIR, MANIFEST_FILE};
+    #[cfg(feature = "mmap")]
+    use crate::storage::MmapEngine;
+    use std::fs;
+    #[cfg(feature = "mmap")]
+    use std::thread;
+    #[cfg(feature = "mmap")]
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_vacuum_removes_leftovers() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-vacuum-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let db = Database::open(&dir).unwrap();
+        let users = db.open_durable::<u64, String>("users").unwrap();
+        users.put(&1, "alice".to_string()).unwrap();
+        let listed = db.checkpoint_keyspace("users").unwrap().unwrap();
+        // what a crash in the middle of checkpoints would leave behind
+        let orphan = dir.join(KEYSPACES_DIR).join("orphan.ckpt");
+        fs::copy(&listed, &orphan).unwrap();
+        fs::write(dir.join(format!("{}.tmp", MANIFEST_FILE)), b"torn").unwrap();
+        fs::write(dir.join(CHECKPOINT_FILE), b"superseded").unwrap();
+
+        let report = db.vacuum().unwrap();
+        assert_eq!(report.files_removed, 3);
+        assert!(report.bytes_reclaimed > 0);
+        assert!(listed.exists() && !orphan.exists());
+        assert_eq!(db.vacuum().unwrap().files_removed, 0);
+        drop((db, users));
+
+        let db = Database::open(&dir).unwrap();
+        let users = db.open_durable::<u64, String>("users").unwrap();
+        assert_eq!(users.get(&1), Some("alice".to_string()));
+        drop((db, users));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_vacuum_rewrites_engine_once_garbage_piles_up() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-vacuum-engine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let engine = MmapEngine::open(dir.join("entries")).unwrap();
+        let policy = VacuumPolicy {
+            garbage_ratio: 0.5,
+            interval: Duration::from_millis(10),
+        };
+        let db = DatabaseBuilder::new()
+            .engine(engine)
+            .vacuum_policy(policy)
+            .open(&dir)
+            .unwrap();
+        let counters = db.open_store::<u64, u64>("counters").unwrap();
+        for count in 0..1000 {
+            counters.put(&1, &count).unwrap();
+        }
+        let started = Instant::now();
+        while db.engine().data_len() > 1000 {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(counters.get(&1).unwrap(), Some(999));
+        drop((db, counters));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! ChaCha20-Poly1305 as specified by RFC 8439, as implemented by `ring`,
+//! with the `encryption` feature.
+
+use std::io;
+
+#[cfg(feature = "encryption")]
+use ring::aead::{Aad, LessSafeKey, Nonce, Tag, UnboundKey, CHACHA20_POLY1305};
+#[cfg(feature = "encryption")]
+use std::convert::TryFrom;
+
+pub(super) const KEY_LEN: usize = 32;
+pub(super) const NONCE_LEN: usize = 12;
+pub(super) const TAG_LEN: usize = 16;
+
+/// Returns the error of sealing or opening records in builds without the
+/// `encryption` feature.
+#[cfg(not(feature = "encryption"))]
+pub(super) fn unsupported() -> io::Error {
+    let message = "built without the encryption feature";
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+#[cfg(feature = "encryption")]
+fn cipher(key: &[u8; KEY_LEN]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("keys are 32 bytes"))
+}
+
+/// Encrypts `data` in place, returning the tag authenticating it along with
+/// `aad`.
+///
+/// # Returns
+///
+/// An error of kind `InvalidInput` if `data` is too long to be encrypted
+/// under a single nonce, or `Unsupported` without the `encryption` feature.
+#[cfg(feature = "encryption")]
+pub(super) fn encrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    data: &mut [u8],
+) -> io::Result<[u8; TAG_LEN]> {
+    let nonce = Nonce::assume_unique_for_key(*nonce);
+    let tag = cipher(key)
+        .seal_in_place_separate_tag(nonce, Aad::from(aad), data)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too long to seal"))?;
+    let mut bytes = [0; TAG_LEN];
+    bytes.copy_from_slice(tag.as_ref());
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(super) fn encrypt(
+    _key: &[u8; KEY_LEN],
+    _nonce: &[u8; NONCE_LEN],
+    _aad: &[u8],
+    _data: &mut [u8],
+) -> io::Result<[u8; TAG_LEN]> {
+    Err(unsupported())
+}
+
+/// Decrypts `data` in place if `expected` authenticates it along with
+/// `aad`, returning whether it does. `data` is left garbled if it does not.
+///
+/// # Returns
+///
+/// An error of kind `Unsupported` without the `encryption` feature.
+#[cfg(feature = "encryption")]
+pub(super) fn decrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    data: &mut [u8],
+    expected: &[u8],
+) -> io::Result<bool> {
+    let tag = match Tag::try_from(expected) {
+        Ok(tag) => tag,
+        Err(_) => return Ok(false),
+    };
+    let nonce = Nonce::assume_unique_for_key(*nonce);
+    let opened = cipher(key).open_in_place_separate_tag(nonce, Aad::from(aad), tag, data, 0..);
+    Ok(opened.is_ok())
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(super) fn decrypt(
+    _key: &[u8; KEY_LEN],
+    _nonce: &[u8; NONCE_LEN],
+    _aad: &[u8],
+    _data: &mut [u8],
+    _expected: &[u8],
+) -> io::Result<bool> {
+    Err(unsupported())
+}
@@ -0,0 +1,427 @@
+//! Encryption at rest of the files written by the crate.
+//!
+//! Log records, table blocks and the sections of [PDB files](crate::pdb)
+//! are each sealed with ChaCha20-Poly1305 (RFC 8439) under a key handed out
+//! by a [`KeyProvider`], once configured with an [`Encryption`] on the
+//! [`WalBuilder`](crate::wal::WalBuilder), the
+//! [`LsmBuilder`](crate::storage::LsmBuilder), the
+//! [`MapBuilder`](crate::collections::map::MapBuilder) or the
+//! [`DatabaseBuilder`](crate::db::DatabaseBuilder). A sealed record is laid
+//! out as:
+//!
+//! ```text
+//! key id: u32 | nonce: [u8; 12] | ciphertext | tag: [u8; 16]
+//! ```
+//!
+//! The key id lets records sealed before a key rotation be opened with the
+//! key they were sealed with, so rotating a key only requires the provider
+//! to keep handing out the retired key until no file refers to it. Files
+//! are re-sealed under the current key as they are rewritten: snapshots as
+//! they are saved again, the log as it is truncated by checkpoints, and
+//! tables as they are compacted.
+//!
+//! The tag authenticates the record along with data telling where it
+//! belongs, such as its LSN, so records cannot be swapped around undetected.
+//! Nonces are made of 8 random bytes drawn from the OS, renewed every 2^32
+//! records, followed by a counter.
+//!
+//! Records are sealed by the ChaCha20-Poly1305 of the `ring` crate, behind
+//! the `encryption` feature, on by default. Builds without it fail to
+//! create an [`Encryption`], with an error of kind `Unsupported`, and so
+//! to open encrypted files.
+
+mod cipher;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex, RwLock};
+
+use self::cipher::{KEY_LEN, NONCE_LEN, TAG_LEN};
+use crate::codec::invalid_data;
+
+/// Secret key of [`KEY_LEN`](Key::LEN) bytes, hidden from `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    /// Length of a key in bytes.
+    pub const LEN: usize = KEY_LEN;
+
+    /// Creates a key from its bytes.
+    pub fn new(bytes: [u8; KEY_LEN]) -> Self {
+        Key(bytes)
+    }
+
+    /// Creates a key from random bytes drawn from the OS.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `Unsupported` on platforms without
+    /// `/dev/urandom`.
+    pub fn generate() -> io::Result<Self> {
+        let mut bytes = [0; KEY_LEN];
+        random_bytes(&mut bytes)?;
+        Ok(Key(bytes))
+    }
+
+    /// Returns the bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// Source of the keys records are sealed with, such as a key management
+/// service or a file readable only by the database.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the key new records are sealed with, along with its id.
+    fn current_key(&self) -> io::Result<(u32, Key)>;
+
+    /// Returns the key with the given `id`, to open records sealed with it,
+    /// possibly before it was rotated.
+    fn key(&self, id: u32) -> io::Result<Key>;
+}
+
+/// [`KeyProvider`] holding its keys in memory, rotated by
+/// [`KeyRing::rotate`].
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "encryption")]
+/// # {
+/// use palladiumdb::encryption::{Encryption, Key, KeyRing};
+/// use std::sync::Arc;
+///
+/// let ring = Arc::new(KeyRing::new(Key::new([7; 32])));
+/// let encryption = Encryption::new(ring.clone()).unwrap();
+/// let sealed = encryption.seal(b"secret", b"").unwrap();
+///
+/// // records sealed before a rotation still open
+/// assert_eq!(ring.rotate(Key::new([8; 32])), 2);
+/// assert_eq!(encryption.open(&sealed, b"").unwrap(), b"secret");
+///
+/// ring.retire(1);
+/// assert!(encryption.open(&sealed, b"").is_err());
+/// # }
+/// ```
+pub struct KeyRing {
+    keys: RwLock<(u32, BTreeMap<u32, Key>)>,
+}
+
+impl KeyRing {
+    /// Creates a ring holding `key` with id 1.
+    pub fn new(key: Key) -> Self {
+        Self::with_keys(1, vec![(1, key)])
+    }
+
+    /// Creates a ring holding the given keys by id, sealing new records with
+    /// the key with id `current`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if there is no key with id `current`.
+    pub fn with_keys<I>(current: u32, keys: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, Key)>,
+    {
+        let keys: BTreeMap<_, _> = keys.into_iter().collect();
+        assert!(keys.contains_key(&current), "no key with id {}", current);
+        KeyRing {
+            keys: RwLock::new((current, keys)),
+        }
+    }
+
+    /// Makes `key` the one new records are sealed with, keeping the others
+    /// to open the records sealed with them.
+    ///
+    /// # Returns
+    ///
+    /// The id given to `key`, above every id in the ring.
+    pub fn rotate(&self, key: Key) -> u32 {
+        let mut keys = self.keys.write().unwrap();
+        let id = keys.1.keys().next_back().map_or(1, |id| id + 1);
+        keys.1.insert(id, key);
+        keys.0 = id;
+        id
+    }
+
+    /// Drops the key with the given `id`, once no file refers to it anymore.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key was dropped, `false` if there was none with that
+    /// id or it is the current one.
+    pub fn retire(&self, id: u32) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        id != keys.0 && keys.1.remove(&id).is_some()
+    }
+
+    /// Returns the id of the key new records are sealed with.
+    pub fn current_id(&self) -> u32 {
+        self.keys.read().unwrap().0
+    }
+}
+
+impl KeyProvider for KeyRing {
+    fn current_key(&self) -> io::Result<(u32, Key)> {
+        let keys = self.keys.read().unwrap();
+        Ok((keys.0, keys.1[&keys.0].clone()))
+    }
+
+    fn key(&self, id: u32) -> io::Result<Key> {
+        self.keys
+            .read()
+            .unwrap()
+            .1
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("no key with id {}", id)))
+    }
+}
+
+/// Seals and opens records with the keys of a [`KeyProvider`], shared by
+/// every file configured with it. Cloning an `Encryption` is cheap.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "encryption")]
+/// # {
+/// use palladiumdb::encryption::{Encryption, Key, KeyRing};
+/// use std::sync::Arc;
+///
+/// let encryption = Encryption::new(Arc::new(KeyRing::new(Key::new([7; 32])))).unwrap();
+/// let sealed = encryption.seal(b"secret", b"lsn 1").unwrap();
+/// assert_eq!(sealed.len(), 6 + Encryption::OVERHEAD);
+///
+/// assert_eq!(encryption.open(&sealed, b"lsn 1").unwrap(), b"secret");
+/// assert!(encryption.open(&sealed, b"lsn 2").is_err());
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Encryption {
+    provider: Arc<dyn KeyProvider>,
+    nonces: Arc<Mutex<(u64, u32)>>,
+}
+
+impl Encryption {
+    /// Bytes a sealed record takes beyond its plaintext.
+    pub const OVERHEAD: usize = 4 + NONCE_LEN + TAG_LEN;
+
+    /// Creates an `Encryption` sealing records with the keys of `provider`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `Unsupported` on platforms without
+    /// `/dev/urandom`, which nonces are drawn from, or in builds without the
+    /// `encryption` feature.
+    pub fn new(provider: Arc<dyn KeyProvider>) -> io::Result<Self> {
+        #[cfg(feature = "encryption")]
+        {
+            let mut prefix = [0; 8];
+            random_bytes(&mut prefix)?;
+            Ok(Encryption {
+                provider,
+                nonces: Arc::new(Mutex::new((u64::from_le_bytes(prefix), 0))),
+            })
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = provider;
+            Err(cipher::unsupported())
+        }
+    }
+
+    /// Returns the provider of the keys.
+    pub fn provider(&self) -> &Arc<dyn KeyProvider> {
+        &self.provider
+    }
+
+    /// Encrypts `plaintext` with the current key, authenticating it along
+    /// with `aad`, which must be given again to open it.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let (id, key) = self.provider.current_key()?;
+        let nonce = self.next_nonce()?;
+
+        let mut sealed = Vec::with_capacity(plaintext.len() + Self::OVERHEAD);
+        sealed.extend_from_slice(&id.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(plaintext);
+        let tag = cipher::encrypt(&key.0, &nonce, aad, &mut sealed[4 + NONCE_LEN..])?;
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    /// Decrypts a record sealed by [`Encryption::seal`] with the key it was
+    /// sealed with.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the record was tampered with, was
+    /// sealed with another `aad` or with another key under the same id, or
+    /// the error of the provider if it has no key with that id.
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < Self::OVERHEAD {
+            return Err(invalid_data("truncated encrypted record"));
+        }
+        let (header, rest) = sealed.split_at(4 + NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let id = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&header[4..]);
+
+        let key = self.provider.key(id)?;
+        let mut plaintext = ciphertext.to_vec();
+        if !cipher::decrypt(&key.0, &nonce, aad, &mut plaintext, tag)? {
+            return Err(invalid_data("encrypted record failed authentication"));
+        }
+        Ok(plaintext)
+    }
+
+    /// Returns a nonce never returned before by this `Encryption`.
+    fn next_nonce(&self) -> io::Result<[u8; NONCE_LEN]> {
+        let mut nonces = self.nonces.lock().unwrap();
+        if nonces.1 == u32::MAX {
+            let mut prefix = [0; 8];
+            random_bytes(&mut prefix)?;
+            *nonces = (u64::from_le_bytes(prefix), 0);
+        }
+        let (prefix, counter) = *nonces;
+        nonces.1 += 1;
+
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..8].copy_from_slice(&prefix.to_le_bytes());
+        nonce[8..].copy_from_slice(&counter.to_le_bytes());
+        Ok(nonce)
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption").finish_non_exhaustive()
+    }
+}
+
+/// Fills `buf` with random bytes drawn from the OS.
+#[cfg(unix)]
+fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom")?.read_exact(buf)
+}
+
+#[cfg(not(unix))]
+fn random_bytes(_buf: &mut [u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "no source of random bytes",
+    ))
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::{cipher, Encryption, Key, KeyRing};
+    use crate::storage::{LsmBuilder, StorageEngine};
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// Returns `true` if a file under `dir` holds `bytes`.
+    fn found_in_files(dir: &Path, bytes: &[u8]) -> bool {
+        fs::read_dir(dir).unwrap().any(|entry| {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => found_in_files(&path, bytes),
+                false => fs::read(&path)
+                    .unwrap()
+                    .windows(bytes.len())
+                    .any(|window| window == bytes),
+            }
+        })
+    }
+
+    #[test]
+    fn test_tables_and_logs_are_encrypted_at_rest_across_rotations() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-encryption-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let ring = Arc::new(KeyRing::new(Key::generate().unwrap()));
+        let encryption = Encryption::new(ring.clone()).unwrap();
+        let open = || LsmBuilder::new().encryption(encryption.clone()).open(&dir);
+
+        let engine = open().unwrap();
+        engine.put(b"flushed", b"plaintext in a table").unwrap();
+        engine.flush_memtable().unwrap();
+        ring.rotate(Key::generate().unwrap());
+        engine.put(b"logged", b"plaintext in a log").unwrap();
+        drop(engine);
+        assert!(!found_in_files(&dir, b"plaintext in"));
+
+        let engine = open().unwrap();
+        assert_eq!(
+            engine.get(b"flushed").unwrap(),
+            Some(b"plaintext in a table".to_vec())
+        );
+        assert_eq!(
+            engine.get(b"logged").unwrap(),
+            Some(b"plaintext in a log".to_vec())
+        );
+        drop(engine);
+
+        let error = LsmBuilder::new().open(&dir).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        ring.retire(1);
+        assert_eq!(open().err().unwrap().kind(), ErrorKind::NotFound);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_matches_rfc_8439_test_vector() {
+        let key: Vec<u8> = (0x80..0xa0).collect();
+        let mut nonce = [0; 12];
+        nonce[0] = 7;
+        for (index, byte) in nonce[4..].iter_mut().enumerate() {
+            *byte = 0x40 + index as u8;
+        }
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let expected = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+            3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+            92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+            3ff4def08e4b7a9de576d26586cec64b6116";
+        let expected_tag = "1ae10b594f09e26a7e902ecbd0600691";
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+
+        let mut key_bytes = [0; 32];
+        key_bytes.copy_from_slice(&key);
+        let mut data = plaintext.to_vec();
+        let tag = cipher::encrypt(&key_bytes, &nonce, &aad, &mut data).unwrap();
+        assert_eq!(hex(&data), expected);
+        assert_eq!(hex(&tag), expected_tag);
+
+        assert!(cipher::decrypt(&key_bytes, &nonce, &aad, &mut data, &tag).unwrap());
+        assert_eq!(&data[..], &plaintext[..]);
+        data[0] ^= 1;
+        assert!(!cipher::decrypt(&key_bytes, &nonce, &aad, &mut data, &tag).unwrap());
+        let mut tampered = tag;
+        tampered[0] ^= 1;
+        assert!(!cipher::decrypt(&key_bytes, &nonce, &aad, &mut data, &tampered).unwrap());
+        assert!(!cipher::decrypt(&key_bytes, &nonce, &aad, &mut data, &tag[1..]).unwrap());
+    }
+}
@@ -0,0 +1,75 @@
+//! Durable replacement of files, for the files rewritten as a whole, such
+//! as manifests and snapshots, written to a temporary file first and renamed
+//! over the previous one.
+//!
+//! A rename, as a file created or removed, is an update of its directory,
+//! only durable once the directory is synced: until then a crash may undo
+//! it while persisting the removals that followed, leaving files referring
+//! to removed ones.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Renames `from` to `to`, replacing `to` if it exists, and syncs the
+/// directories of both so that the rename survives a crash. The content of
+/// `from` must be synced already.
+pub(crate) fn rename_durably<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    fs::rename(from, to)?;
+    let (from_dir, to_dir) = (parent(from), parent(to));
+    sync_dir(to_dir)?;
+    if from_dir != to_dir {
+        sync_dir(from_dir)?;
+    }
+    Ok(())
+}
+
+/// Syncs the entries of `dir`, the files created, renamed or removed in it,
+/// to disk. Does nothing off Unix, where directories cannot be opened.
+pub(crate) fn sync_dir<P: AsRef<Path>>(dir: P) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::File::open(dir.as_ref())?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(())
+    }
+}
+
+/// Returns the directory of `path`, the current one for bare file names.
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rename_durably, sync_dir};
+    use std::fs;
+
+    #[test]
+    fn test_renames_replace_files_across_directories() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("staging")).unwrap();
+        fs::write(dir.join("manifest"), b"old").unwrap();
+        fs::write(dir.join("manifest.tmp"), b"new").unwrap();
+
+        rename_durably(dir.join("manifest.tmp"), dir.join("manifest")).unwrap();
+        assert_eq!(fs::read(dir.join("manifest")).unwrap(), b"new");
+        assert!(!dir.join("manifest.tmp").exists());
+
+        fs::write(dir.join("staging").join("table"), b"rows").unwrap();
+        rename_durably(dir.join("staging").join("table"), dir.join("table")).unwrap();
+        assert_eq!(fs::read(dir.join("table")).unwrap(), b"rows");
+        sync_dir(&dir).unwrap();
+        assert!(rename_durably(dir.join("missing"), dir.join("table")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
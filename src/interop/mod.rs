@@ -0,0 +1,8 @@
+//! Moving datasets between palladiumdb and other data stores.
+//!
+//! * [`redis`]: import of the RDB snapshots of Redis.
+//! * `sqlite`: export of maps to SQLite tables, with the `sqlite` feature.
+
+pub mod redis;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
@@ -0,0 +1,902 @@
+//! Import of Redis datasets from RDB files, the snapshots Redis writes on
+//! `SAVE` and `BGSAVE` and sends to its replicas, see [`import_rdb`].
+//!
+//! Keys are imported by the type of their value into keyspaces of their
+//! own, one per type and per Redis database, named by [`Kind::keyspace`].
+//! Every keyspace maps the keys, as `Vec<u8>`, to values of the
+//! collections matching their type:
+//!
+//! | Redis type | Value type                    |
+//! |------------|-------------------------------|
+//! | string     | `Vec<u8>`                     |
+//! | hash       | `Arc<Map<Vec<u8>, Vec<u8>>>`  |
+//! | set        | `Arc<Set<Vec<u8>>>`           |
+//! | list       | `Arc<List<Vec<u8>>>`          |
+//!
+//! Integers Redis stores in a compact encoding are imported as their
+//! decimal representation, as Redis returns them. Keys already expired are
+//! skipped, the others are imported without their expiry. Sorted sets are
+//! skipped, while streams and the types of modules fail the import.
+//!
+//! Files of RDB versions 9 to 12 are read, as written by Redis 5 to 7.4.
+//!
+//! [`Map`]: crate::Map
+//! [`Set`]: crate::collections::set::Set
+//! [`List`]: crate::collections::list::List
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum::crc64_update;
+use crate::codec::{invalid_data, take};
+use crate::collections::list::List;
+use crate::collections::set::Set;
+use crate::db::{Database, Result};
+use crate::storage::StorageEngine;
+use crate::Map;
+
+const MAGIC: &[u8] = b"REDIS";
+const MIN_VERSION: u32 = 9;
+const MAX_VERSION: u32 = 12;
+
+const OPCODE_SLOT_INFO: u8 = 0xf4;
+const OPCODE_FUNCTION2: u8 = 0xf6;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+/// Container of a node of a quicklist: a single element, or a listpack.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+/// Kind of the values of a keyspace keys are imported into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Strings, imported as `Vec<u8>`.
+    String,
+    /// Hashes, imported as `Arc<Map<Vec<u8>, Vec<u8>>>`.
+    Hash,
+    /// Sets, imported as `Arc<Set<Vec<u8>>>`.
+    Set,
+    /// Lists, imported as `Arc<List<Vec<u8>>>`.
+    List,
+}
+
+impl Kind {
+    /// Returns the name of the keyspace the values of this kind of the Redis
+    /// database `db` are imported into, such as `redis:0:hashes`.
+    pub fn keyspace(self, db: u64) -> String {
+        let kind = match self {
+            Kind::String => "strings",
+            Kind::Hash => "hashes",
+            Kind::Set => "sets",
+            Kind::List => "lists",
+        };
+        format!("redis:{}:{}", db, kind)
+    }
+}
+
+/// Outcome of an import, see [`import_rdb`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Number of strings imported.
+    pub strings: u64,
+    /// Number of hashes imported.
+    pub hashes: u64,
+    /// Number of sets imported.
+    pub sets: u64,
+    /// Number of lists imported.
+    pub lists: u64,
+    /// Number of keys skipped as already expired.
+    pub expired: u64,
+    /// Number of keys skipped as holding sorted sets.
+    pub skipped: u64,
+}
+
+/// Imports the keys of the RDB file at `path` into `db`, see the
+/// [module documentation](self) for the keyspaces they go to. Keys already
+/// in those keyspaces are overwritten by the keys of the same name.
+///
+/// The file is read and its checksum verified in full before any key is
+/// put, the keys being held in memory meanwhile, so a file failing the
+/// import leaves `db` unchanged.
+///
+/// # Returns
+///
+/// What was imported, [`Error::Io`](crate::db::Error::Io) of kind
+/// `InvalidData` if the file is not an RDB file of a supported version,
+/// fails its checksum or holds values of an unsupported type,
+/// [`Error::TypeMismatch`](crate::db::Error::TypeMismatch) if a keyspace
+/// exists with other types.
+///
+/// # Examples
+///
+/// ```no_run
+/// use palladiumdb::db::Database;
+/// use palladiumdb::interop::redis::{import_rdb, Kind};
+///
+/// let db = Database::new();
+/// let report = import_rdb("dump.rdb", &db).unwrap();
+/// println!("imported {} strings", report.strings);
+///
+/// let strings = db
+///     .open_map::<Vec<u8>, Vec<u8>>(&Kind::String.keyspace(0))
+///     .unwrap();
+/// let name = strings.get(&b"name".to_vec());
+/// ```
+pub fn import_rdb<P, H, E>(path: P, db: &Database<H, E>) -> Result<ImportReport>
+where
+    P: AsRef<Path>,
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let mut reader = RdbReader {
+        input: BufReader::new(File::open(path)?),
+        crc: 0,
+    };
+    let mut header = [0; 9];
+    reader.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("not an RDB file").into());
+    }
+    let version = std::str::from_utf8(&header[MAGIC.len()..])
+        .ok()
+        .and_then(|version| version.parse::<u32>().ok())
+        .ok_or_else(|| invalid_data("invalid RDB version"))?;
+    if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+        let message = format!("unsupported RDB version {}", version);
+        return Err(invalid_data(&message).into());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut index = 0;
+    let mut expires_at = None;
+    let mut report = ImportReport::default();
+    let mut keys = Vec::new();
+    loop {
+        match reader.byte()? {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => index = reader.len()?,
+            OPCODE_EXPIRETIME => {
+                let mut secs = [0; 4];
+                reader.read_exact(&mut secs)?;
+                expires_at = Some(u64::from(u32::from_le_bytes(secs)) * 1000);
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let mut millis = [0; 8];
+                reader.read_exact(&mut millis)?;
+                expires_at = Some(u64::from_le_bytes(millis));
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.len()?;
+                }
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            // functions are not imported
+            OPCODE_FUNCTION2 => drop(reader.string()?),
+            OPCODE_FREQ => drop(reader.byte()?),
+            OPCODE_IDLE => drop(reader.len()?),
+            kind => {
+                let key = reader.string()?;
+                let value = reader.value(kind)?;
+                if expires_at.take().is_some_and(|at| at <= now) {
+                    report.expired += 1;
+                    continue;
+                }
+                keys.push((index, key, value));
+            }
+        }
+    }
+
+    // computed over every byte before the checksum itself, 0 if Redis was
+    // configured not to compute it
+    let crc = reader.crc;
+    let mut checksum = [0; 8];
+    reader.read_exact(&mut checksum)?;
+    let checksum = u64::from_le_bytes(checksum);
+    if checksum != 0 && checksum != crc {
+        return Err(invalid_data("RDB checksum mismatch").into());
+    }
+
+    // every keyspace opened before the first key is put, so a keyspace of
+    // other types fails the import before it changes anything
+    let mut targets = HashMap::new();
+    for (index, _, value) in &keys {
+        let target = targets
+            .entry(*index)
+            .or_insert_with(|| Target::new(db, *index));
+        target.open(value)?;
+    }
+    for (index, key, value) in keys {
+        targets
+            .get_mut(&index)
+            .unwrap()
+            .put(key, value, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// A value read from an RDB file.
+enum Value {
+    String(Vec<u8>),
+    Hash(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    List(Vec<Vec<u8>>),
+    /// A sorted set, which is not imported.
+    Skipped,
+}
+
+/// A keyspace of values of type `V`, not opened until a key goes to it.
+type Keyspace<V, H> = Option<Arc<Map<Vec<u8>, V, H>>>;
+
+/// The fields of a hash.
+type Fields = Map<Vec<u8>, Vec<u8>>;
+
+/// The keyspaces of a Redis database, opened as keys of their kind come.
+struct Target<'a, H, E> {
+    db: &'a Database<H, E>,
+    index: u64,
+    strings: Keyspace<Vec<u8>, H>,
+    hashes: Keyspace<Arc<Fields>, H>,
+    sets: Keyspace<Arc<Set<Vec<u8>>>, H>,
+    lists: Keyspace<Arc<List<Vec<u8>>>, H>,
+}
+
+impl<'a, H, E> Target<'a, H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    fn new(db: &'a Database<H, E>, index: u64) -> Self {
+        Target {
+            db,
+            index,
+            strings: None,
+            hashes: None,
+            sets: None,
+            lists: None,
+        }
+    }
+
+    /// Opens the keyspace `value` goes to if it was not yet.
+    fn open(&mut self, value: &Value) -> Result<()> {
+        let (db, index) = (self.db, self.index);
+        match value {
+            Value::String(_) => drop(open(&mut self.strings, db, Kind::String.keyspace(index))?),
+            Value::Hash(_) => drop(open(&mut self.hashes, db, Kind::Hash.keyspace(index))?),
+            Value::Set(_) => drop(open(&mut self.sets, db, Kind::Set.keyspace(index))?),
+            Value::List(_) => drop(open(&mut self.lists, db, Kind::List.keyspace(index))?),
+            Value::Skipped => {}
+        }
+        Ok(())
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Value, report: &mut ImportReport) -> Result<()> {
+        let (db, index) = (self.db, self.index);
+        match value {
+            Value::String(value) => {
+                let strings = open(&mut self.strings, db, Kind::String.keyspace(index))?;
+                strings.put(&key, value);
+                report.strings += 1;
+            }
+            Value::Hash(fields) => {
+                let hash = Map::new();
+                for pair in fields.chunks_exact(2) {
+                    hash.put(&pair[0], pair[1].clone());
+                }
+                let hashes = open(&mut self.hashes, db, Kind::Hash.keyspace(index))?;
+                hashes.put(&key, Arc::new(hash));
+                report.hashes += 1;
+            }
+            Value::Set(members) => {
+                let set = Set::new();
+                for member in &members {
+                    set.insert(member);
+                }
+                let sets = open(&mut self.sets, db, Kind::Set.keyspace(index))?;
+                sets.put(&key, Arc::new(set));
+                report.sets += 1;
+            }
+            Value::List(elements) => {
+                let list = List::new();
+                for element in elements {
+                    list.push_back(element);
+                }
+                let lists = open(&mut self.lists, db, Kind::List.keyspace(index))?;
+                lists.put(&key, Arc::new(list));
+                report.lists += 1;
+            }
+            Value::Skipped => report.skipped += 1,
+        }
+        Ok(())
+    }
+}
+
+/// Returns the keyspace `name` of `db`, opening it into `keyspace` first if
+/// it was not yet.
+fn open<'a, V, H, E>(
+    keyspace: &'a mut Keyspace<V, H>,
+    db: &Database<H, E>,
+    name: String,
+) -> Result<&'a Map<Vec<u8>, V, H>>
+where
+    V: Clone + Send + Sync + 'static,
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    if keyspace.is_none() {
+        *keyspace = Some(db.open_map(&name)?);
+    }
+    Ok(keyspace.as_ref().unwrap())
+}
+
+/// Length read from an RDB file, or the encoding of a string stored in a
+/// special format.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+/// Reads the items of an RDB file, computing the checksum of the bytes read.
+struct RdbReader<R> {
+    input: R,
+    crc: u64,
+}
+
+impl<R: Read> RdbReader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.input.read_exact(buf)?;
+        self.crc = crc64_update(self.crc, buf);
+        Ok(())
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        // read in steps rather than allocated up front, the length may be
+        // corrupted
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.crc = crc64_update(self.crc, &bytes);
+        Ok(bytes)
+    }
+
+    fn length(&mut self) -> io::Result<Length> {
+        let first = self.byte()?;
+        let len = match first >> 6 {
+            0 => u64::from(first & 0x3f),
+            1 => u64::from(first & 0x3f) << 8 | u64::from(self.byte()?),
+            2 if first == 0x80 => {
+                let mut len = [0; 4];
+                self.read_exact(&mut len)?;
+                u64::from(u32::from_be_bytes(len))
+            }
+            2 if first == 0x81 => {
+                let mut len = [0; 8];
+                self.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            2 => return Err(invalid_data("invalid RDB length")),
+            _ => return Ok(Length::Encoded(first & 0x3f)),
+        };
+        Ok(Length::Len(len))
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        match self.length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid_data("invalid RDB length")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        let int = match self.length()? {
+            Length::Len(len) => return self.bytes(len),
+            Length::Encoded(0) => i64::from(self.byte()? as i8),
+            Length::Encoded(1) => {
+                let mut int = [0; 2];
+                self.read_exact(&mut int)?;
+                i64::from(i16::from_le_bytes(int))
+            }
+            Length::Encoded(2) => {
+                let mut int = [0; 4];
+                self.read_exact(&mut int)?;
+                i64::from(i32::from_le_bytes(int))
+            }
+            Length::Encoded(3) => {
+                let compressed_len = self.len()?;
+                let len = self.len()?;
+                return lzf_decompress(&self.bytes(compressed_len)?, len);
+            }
+            Length::Encoded(_) => return Err(invalid_data("invalid RDB string encoding")),
+        };
+        Ok(int.to_string().into_bytes())
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.string()).collect()
+    }
+
+    /// Reads a value of the RDB type `kind`.
+    fn value(&mut self, kind: u8) -> io::Result<Value> {
+        let value = match kind {
+            TYPE_STRING => Value::String(self.string()?),
+            TYPE_LIST => Value::List(self.strings()?),
+            TYPE_SET => Value::Set(self.strings()?),
+            TYPE_HASH => {
+                let len = self.len()?;
+                let len = len
+                    .checked_mul(2)
+                    .ok_or_else(|| invalid_data("invalid RDB length"))?;
+                let mut fields = Vec::new();
+                for _ in 0..len {
+                    fields.push(self.string()?);
+                }
+                Value::Hash(fields)
+            }
+            TYPE_LIST_ZIPLIST => Value::List(ziplist(&self.string()?)?),
+            TYPE_SET_INTSET => Value::Set(intset(&self.string()?)?),
+            TYPE_HASH_ZIPLIST => Value::Hash(pairs(ziplist(&self.string()?)?)?),
+            TYPE_HASH_LISTPACK => Value::Hash(pairs(listpack(&self.string()?)?)?),
+            TYPE_SET_LISTPACK => Value::Set(listpack(&self.string()?)?),
+            TYPE_LIST_QUICKLIST => {
+                let mut elements = Vec::new();
+                for _ in 0..self.len()? {
+                    elements.append(&mut ziplist(&self.string()?)?);
+                }
+                Value::List(elements)
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                let mut elements = Vec::new();
+                for _ in 0..self.len()? {
+                    let container = self.len()?;
+                    let node = self.string()?;
+                    match container {
+                        QUICKLIST_NODE_PLAIN => elements.push(node),
+                        _ => elements.append(&mut listpack(&node)?),
+                    }
+                }
+                Value::List(elements)
+            }
+            TYPE_ZSET => {
+                for _ in 0..self.len()? {
+                    self.string()?;
+                    // scores as strings, their length being 253 to 255 for
+                    // NaN and infinities
+                    match self.byte()? {
+                        253..=255 => {}
+                        len => drop(self.bytes(u64::from(len))?),
+                    }
+                }
+                Value::Skipped
+            }
+            TYPE_ZSET_2 => {
+                for _ in 0..self.len()? {
+                    self.string()?;
+                    self.bytes(8)?;
+                }
+                Value::Skipped
+            }
+            TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+                self.string()?;
+                Value::Skipped
+            }
+            kind => {
+                let message = format!("unsupported RDB value type {}", kind);
+                return Err(invalid_data(&message));
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Returns the fields and values of a hash from its flattened `entries`.
+fn pairs(entries: Vec<Vec<u8>>) -> io::Result<Vec<Vec<u8>>> {
+    match entries.len() % 2 {
+        0 => Ok(entries),
+        _ => Err(invalid_data("hash with a field without value")),
+    }
+}
+
+fn int_entry(int: i64) -> Vec<u8> {
+    int.to_string().into_bytes()
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> io::Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(input, N)?);
+    Ok(array)
+}
+
+/// Returns the entries of a ziplist, the compact encoding of small lists
+/// and hashes of RDB versions before 10.
+fn ziplist(mut input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let input = &mut input;
+    // total length, offset of the last entry and number of entries
+    take(input, 10)?;
+    let mut entries = Vec::new();
+    loop {
+        // length of the previous entry, 1 or 5 bytes
+        match take(input, 1)?[0] {
+            0xff => break,
+            0xfe => drop(take(input, 4)?),
+            _ => {}
+        }
+        let encoding = take(input, 1)?[0];
+        let entry = match encoding >> 6 {
+            0 => take(input, usize::from(encoding & 0x3f))?.to_vec(),
+            1 => {
+                let len = usize::from(encoding & 0x3f) << 8 | usize::from(take(input, 1)?[0]);
+                take(input, len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(take_array(input)?) as usize;
+                take(input, len)?.to_vec()
+            }
+            _ => int_entry(match encoding {
+                0xc0 => i64::from(i16::from_le_bytes(take_array(input)?)),
+                0xd0 => i64::from(i32::from_le_bytes(take_array(input)?)),
+                0xe0 => i64::from_le_bytes(take_array(input)?),
+                0xf0 => {
+                    let [a, b, c] = take_array(input)?;
+                    i64::from(i32::from_le_bytes([0, a, b, c]) >> 8)
+                }
+                0xfe => i64::from(take(input, 1)?[0] as i8),
+                0xf1..=0xfd => i64::from(encoding & 0x0f) - 1,
+                _ => return Err(invalid_data("invalid ziplist entry encoding")),
+            }),
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Returns the entries of a listpack, the compact encoding of small lists,
+/// sets and hashes from RDB version 10 on.
+fn listpack(mut input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let input = &mut input;
+    // total length and number of entries
+    take(input, 6)?;
+    let mut entries = Vec::new();
+    loop {
+        let encoding = take(input, 1)?[0];
+        let (entry, len) = match encoding {
+            0xff => break,
+            _ if encoding & 0x80 == 0 => (int_entry(i64::from(encoding)), 1),
+            _ if encoding & 0xc0 == 0x80 => {
+                let len = usize::from(encoding & 0x3f);
+                (take(input, len)?.to_vec(), 1 + len)
+            }
+            _ if encoding & 0xe0 == 0xc0 => {
+                let int = i64::from(encoding & 0x1f) << 8 | i64::from(take(input, 1)?[0]);
+                // 13 bits, two's complement
+                let int = if int >= 1 << 12 { int - (1 << 13) } else { int };
+                (int_entry(int), 2)
+            }
+            _ if encoding & 0xf0 == 0xe0 => {
+                let len = usize::from(encoding & 0x0f) << 8 | usize::from(take(input, 1)?[0]);
+                (take(input, len)?.to_vec(), 2 + len)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(take_array(input)?) as usize;
+                (take(input, len)?.to_vec(), 5 + len)
+            }
+            0xf1 => (
+                int_entry(i64::from(i16::from_le_bytes(take_array(input)?))),
+                3,
+            ),
+            0xf2 => {
+                let [a, b, c] = take_array(input)?;
+                (
+                    int_entry(i64::from(i32::from_le_bytes([0, a, b, c]) >> 8)),
+                    4,
+                )
+            }
+            0xf3 => (
+                int_entry(i64::from(i32::from_le_bytes(take_array(input)?))),
+                5,
+            ),
+            0xf4 => (int_entry(i64::from_le_bytes(take_array(input)?)), 9),
+            _ => return Err(invalid_data("invalid listpack entry encoding")),
+        };
+        // the length of the entry, written backwards for reverse traversal
+        let backlen = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        take(input, backlen)?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Returns the members of an intset, the encoding of small sets of
+/// integers.
+fn intset(mut input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let input = &mut input;
+    let width = u32::from_le_bytes(take_array(input)?);
+    let len = u32::from_le_bytes(take_array(input)?);
+    (0..len)
+        .map(|_| {
+            let int = match width {
+                2 => i64::from(i16::from_le_bytes(take_array(input)?)),
+                4 => i64::from(i32::from_le_bytes(take_array(input)?)),
+                8 => i64::from_le_bytes(take_array(input)?),
+                _ => return Err(invalid_data("invalid intset encoding")),
+            };
+            Ok(int_entry(int))
+        })
+        .collect()
+}
+
+/// Decompresses the LZF-compressed `input` to the `len` bytes it holds.
+fn lzf_decompress(input: &[u8], len: u64) -> io::Result<Vec<u8>> {
+    let corrupted = || invalid_data("invalid LZF-compressed string");
+    let mut output = Vec::new();
+    let mut input = input.iter().copied();
+    while let Some(control) = input.next() {
+        let control = usize::from(control);
+        if control < 32 {
+            // a run of literals
+            for _ in 0..=control {
+                output.push(input.next().ok_or_else(corrupted)?);
+            }
+        } else {
+            // a back reference
+            let mut run = control >> 5;
+            if run == 7 {
+                run += usize::from(input.next().ok_or_else(corrupted)?);
+            }
+            let offset = (control & 0x1f) << 8 | usize::from(input.next().ok_or_else(corrupted)?);
+            let start = output.len().checked_sub(offset + 1).ok_or_else(corrupted)?;
+            // may overlap the bytes it produces
+            for index in start..start + run + 2 {
+                output.push(output[index]);
+            }
+        }
+        if output.len() as u64 > len {
+            return Err(corrupted());
+        }
+    }
+    match output.len() as u64 == len {
+        true => Ok(output),
+        false => Err(corrupted()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{import_rdb, ImportReport, Kind};
+    use crate::checksum::crc64_update;
+    use crate::collections::list::List;
+    use crate::collections::set::Set;
+    use crate::db::{Database, Error};
+    use crate::Map;
+    use std::fs;
+    use std::sync::Arc;
+
+    fn string(rdb: &mut Vec<u8>, bytes: &[u8]) {
+        assert!(bytes.len() < 64);
+        rdb.push(bytes.len() as u8);
+        rdb.extend_from_slice(bytes);
+    }
+
+    fn key(rdb: &mut Vec<u8>, kind: u8, name: &str) {
+        rdb.push(kind);
+        string(rdb, name.as_bytes());
+    }
+
+    /// Appends a listpack entry of `bytes`, or of its encoding if given.
+    fn listpack_entry(listpack: &mut Vec<u8>, bytes: &[u8], encoded: Option<&[u8]>) {
+        let entry = encoded.map(<[u8]>::to_vec).unwrap_or_else(|| {
+            let mut entry = vec![0x80 | bytes.len() as u8];
+            entry.extend_from_slice(bytes);
+            entry
+        });
+        listpack.extend_from_slice(&entry);
+        listpack.push(entry.len() as u8);
+    }
+
+    fn listpack(entries: &[(&[u8], Option<&[u8]>)]) -> Vec<u8> {
+        let mut listpack = vec![0; 6];
+        for (bytes, encoded) in entries {
+            listpack_entry(&mut listpack, bytes, *encoded);
+        }
+        listpack.push(0xff);
+        listpack
+    }
+
+    #[test]
+    fn test_imports_every_encoding_of_supported_types() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-rdb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.push(0xfa);
+        string(&mut rdb, b"redis-ver");
+        string(&mut rdb, b"7.2.0");
+        rdb.extend_from_slice(&[0xfe, 0, 0xfb, 8, 1]);
+        key(&mut rdb, 0, "name");
+        string(&mut rdb, b"alice");
+        key(&mut rdb, 0, "counter");
+        rdb.extend_from_slice(&[0xc1, 0xd2, 0x04]);
+        // 20 bytes compressed as a literal and a back reference
+        key(&mut rdb, 0, "compressed");
+        rdb.extend_from_slice(&[0xc3, 5, 20, 0x00, b'a', 0xe0, 10, 0x00]);
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&1000u64.to_le_bytes());
+        key(&mut rdb, 0, "expired");
+        string(&mut rdb, b"x");
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&u64::MAX.to_le_bytes());
+        key(&mut rdb, 0, "expiring");
+        string(&mut rdb, b"y");
+
+        key(&mut rdb, 16, "user");
+        let fields = listpack(&[
+            (b"name", None),
+            (b"bob", None),
+            (b"age", None),
+            (b"42", Some(&[42])),
+        ]);
+        string(&mut rdb, &fields);
+        key(&mut rdb, 11, "ids");
+        let mut intset = vec![2, 0, 0, 0, 3, 0, 0, 0];
+        for id in [1i16, 2, 300] {
+            intset.extend_from_slice(&id.to_le_bytes());
+        }
+        string(&mut rdb, &intset);
+        key(&mut rdb, 20, "tags");
+        string(&mut rdb, &listpack(&[(b"x", None), (b"y", None)]));
+        key(&mut rdb, 18, "queue");
+        rdb.extend_from_slice(&[2, 1]);
+        string(&mut rdb, b"big");
+        rdb.push(2);
+        string(
+            &mut rdb,
+            &listpack(&[(b"c", None), (b"-5", Some(&[0xdf, 0xfb]))]),
+        );
+        key(&mut rdb, 14, "letters");
+        rdb.push(1);
+        let mut ziplist = vec![0; 10];
+        ziplist.extend_from_slice(&[0, 0x01, b'a', 3, 0xf2, 2, 0xc0]);
+        ziplist.extend_from_slice(&(-300i16).to_le_bytes());
+        ziplist.push(0xff);
+        string(&mut rdb, &ziplist);
+
+        rdb.extend_from_slice(&[0xfe, 1]);
+        key(&mut rdb, 5, "board");
+        rdb.push(1);
+        string(&mut rdb, b"m");
+        rdb.extend_from_slice(&1.5f64.to_le_bytes());
+        key(&mut rdb, 2, "members");
+        rdb.push(1);
+        string(&mut rdb, b"m");
+        rdb.push(0xff);
+        let crc = crc64_update(0, &rdb);
+        rdb.extend_from_slice(&crc.to_le_bytes());
+        fs::write(dir.join("dump.rdb"), &rdb).unwrap();
+
+        let db = Database::new();
+        let report = import_rdb(dir.join("dump.rdb"), &db).unwrap();
+        let expected = ImportReport {
+            strings: 4,
+            hashes: 1,
+            sets: 3,
+            lists: 2,
+            expired: 1,
+            skipped: 1,
+        };
+        assert_eq!(report, expected);
+
+        let strings = db
+            .open_map::<Vec<u8>, Vec<u8>>(&Kind::String.keyspace(0))
+            .unwrap();
+        let string = |key: &str| strings.get(&key.as_bytes().to_vec());
+        assert_eq!(string("name"), Some(b"alice".to_vec()));
+        assert_eq!(string("counter"), Some(b"1234".to_vec()));
+        assert_eq!(string("compressed"), Some(vec![b'a'; 20]));
+        assert_eq!(string("expiring"), Some(b"y".to_vec()));
+        assert_eq!(string("expired"), None);
+
+        let hashes = db
+            .open_map::<Vec<u8>, Arc<Map<Vec<u8>, Vec<u8>>>>(&Kind::Hash.keyspace(0))
+            .unwrap();
+        let user = hashes.get(&b"user".to_vec()).unwrap();
+        assert_eq!(user.get(&b"age".to_vec()), Some(b"42".to_vec()));
+        let sets = db
+            .open_map::<Vec<u8>, Arc<Set<Vec<u8>>>>(&Kind::Set.keyspace(0))
+            .unwrap();
+        let ids = sets.get(&b"ids".to_vec()).unwrap();
+        assert!(ids.contains(&b"300".to_vec()) && ids.len() == 3);
+        let lists = db
+            .open_map::<Vec<u8>, Arc<List<Vec<u8>>>>(&Kind::List.keyspace(0))
+            .unwrap();
+        let range = |key: &str| lists.get(&key.as_bytes().to_vec()).unwrap().range(..);
+        let queue: Vec<&[u8]> = vec![b"big", b"c", b"-5"];
+        assert_eq!(range("queue"), queue);
+        let letters: Vec<&[u8]> = vec![b"a", b"1", b"-300"];
+        assert_eq!(range("letters"), letters);
+        assert!(db.contains_map(&Kind::Set.keyspace(1)));
+        assert!(!db.contains_map(&Kind::List.keyspace(1)));
+
+        // a byte flipped within a value, found out before any key is put
+        let mut corrupted = rdb.clone();
+        corrupted[30] ^= 1;
+        fs::write(dir.join("corrupted.rdb"), &corrupted).unwrap();
+        let target = Database::new();
+        let strings = target
+            .open_map::<Vec<u8>, Vec<u8>>(&Kind::String.keyspace(0))
+            .unwrap();
+        strings.put(&b"name".to_vec(), b"bob".to_vec());
+        assert!(matches!(
+            import_rdb(dir.join("corrupted.rdb"), &target),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(strings.len(), 1);
+        assert_eq!(strings.get(&b"name".to_vec()), Some(b"bob".to_vec()));
+        assert!(!target.contains_map(&Kind::Hash.keyspace(0)));
+        assert!(!target.contains_map(&Kind::Set.keyspace(1)));
+
+        // a hash of more fields than can be counted
+        let mut overflowing = b"REDIS0011".to_vec();
+        key(&mut overflowing, 4, "hash");
+        overflowing.push(0x81);
+        overflowing.extend_from_slice(&u64::MAX.to_be_bytes());
+        fs::write(dir.join("overflowing.rdb"), &overflowing).unwrap();
+        assert!(matches!(
+            import_rdb(dir.join("overflowing.rdb"), &target),
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::InvalidData
+        ));
+        assert!(!target.contains_map(&Kind::Hash.keyspace(0)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! Export of maps to SQLite databases, so that their entries can be queried
+//! with SQL for analyses and audits, see [`export`].
+//!
+//! Entries are flattened to columns by serde as for CSV, see [`crate::csv`]:
+//! the key goes to a column named `key`, or to columns prefixed by `key.`
+//! if it is a struct, and the fields of the value to columns named after
+//! them, or to a column named `value` if it is not a struct. Columns are
+//! declared without a type, and keep the SQLite type of what serde gives:
+//!
+//! | serde                              | SQLite                        |
+//! |------------------------------------|-------------------------------|
+//! | booleans, integers                 | `INTEGER`, `TEXT` if too large|
+//! | floats                             | `REAL`                        |
+//! | chars, strings, unit variants      | `TEXT`                        |
+//! | bytes                              | `BLOB`                        |
+//! | `None`, units                      | `NULL`                        |
+//!
+//! Sequences, maps and enum variants holding data are rejected, as they are
+//! for CSV.
+
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::Path;
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use serde::ser::{self, Impossible, Serialize, SerializeStruct, SerializeTuple};
+
+use crate::csv::{Error, VALUE_COLUMN};
+use crate::Map;
+
+/// Name of the column of a key that is not a struct, and prefix of the
+/// columns of one that is.
+pub const KEY_COLUMN: &str = "key";
+
+/// Writes the entries of `map` to the table `table` of the SQLite database
+/// at `path`, one row per entry, creating the database if it does not
+/// exist. The table is replaced if it exists, and is written in a single
+/// transaction, so readers of the database see either the former table or
+/// all of the entries.
+///
+/// Columns are added to the table as entries flatten to new ones, which
+/// happens when an `Option` of a struct is `None` in the first entries, and
+/// are `NULL` in the rows of entries not flattening to them. The table has
+/// the columns `key` and `value` if `map` is empty.
+///
+/// Entries are read by [`Map::iter`], so writers are not held off, see
+/// [`Iter`](crate::collections::map::Iter) for what is observed of
+/// concurrent writes.
+///
+/// # Returns
+///
+/// The number of entries written, an error of kind `InvalidData` if an
+/// entry cannot be flattened to columns, or an error of kind `Other` if
+/// SQLite fails, in which cases the database is left as it was.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::interop::sqlite;
+/// use palladiumdb::Map;
+/// use serde::Serialize;
+///
+/// #[derive(Clone, Serialize)]
+/// struct User {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let users = Map::new();
+/// users.put(&1u64, User { name: "alice".to_string(), age: 31 });
+/// users.put(&2u64, User { name: "bob".to_string(), age: 27 });
+///
+/// let path = std::env::temp_dir().join("palladiumdb-doc-sqlite-export.db");
+/// # let _ = std::fs::remove_file(&path);
+/// assert_eq!(sqlite::export(&users, &path, "users").unwrap(), 2);
+///
+/// let connection = rusqlite::Connection::open(&path).unwrap();
+/// let name: String = connection
+///     .query_row("SELECT name FROM users WHERE age > 30", [], |row| row.get(0))
+///     .unwrap();
+/// assert_eq!(name, "alice");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn export<K, V, H, P>(map: &Map<K, V, H>, path: P, table: &str) -> io::Result<u64>
+where
+    K: Hash + Eq + Clone + Serialize,
+    V: Clone + Serialize,
+    H: BuildHasher,
+    P: AsRef<Path>,
+{
+    let mut connection = Connection::open(path).map_err(sql_error)?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+    let table = quote(table);
+    transaction
+        .execute(&format!("DROP TABLE IF EXISTS {}", table), [])
+        .map_err(sql_error)?;
+
+    let key_prefix = format!("{}.", KEY_COLUMN);
+    let mut known: Option<Vec<String>> = None;
+    let mut columns = Vec::new();
+    let mut count = 0u64;
+    for (key, value) in map.iter() {
+        columns.clear();
+        flatten(&key, KEY_COLUMN, &key_prefix, &mut columns)?;
+        flatten(&value, VALUE_COLUMN, "", &mut columns)?;
+        match &mut known {
+            None => {
+                let names: Vec<String> = columns.iter().map(|(name, _)| name.clone()).collect();
+                create(&transaction, &table, &names)?;
+                known = Some(names);
+            }
+            Some(known) => {
+                for (name, _) in &columns {
+                    if !known.contains(name) {
+                        let sql = format!("ALTER TABLE {} ADD COLUMN {}", table, quote(name));
+                        transaction.execute(&sql, []).map_err(sql_error)?;
+                        known.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        let names: Vec<String> = columns.iter().map(|(name, _)| quote(name)).collect();
+        let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table,
+            names.join(", "),
+            placeholders.join(", ")
+        );
+        // the same statement serves every entry of the same columns
+        let mut insert = transaction.prepare_cached(&sql).map_err(sql_error)?;
+        insert
+            .execute(params_from_iter(columns.drain(..).map(|(_, value)| value)))
+            .map_err(sql_error)?;
+        count += 1;
+    }
+    if known.is_none() {
+        let names = [KEY_COLUMN.to_string(), VALUE_COLUMN.to_string()];
+        create(&transaction, &table, &names)?;
+    }
+    transaction.commit().map_err(sql_error)?;
+    Ok(count)
+}
+
+fn create(connection: &Connection, table: &str, columns: &[String]) -> io::Result<()> {
+    let columns: Vec<String> = columns.iter().map(|name| quote(name)).collect();
+    let sql = format!("CREATE TABLE {} ({})", table, columns.join(", "));
+    connection.execute(&sql, []).map_err(sql_error)?;
+    Ok(())
+}
+
+/// Returns `name` quoted as an SQL identifier, which column names with
+/// dots need.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+/// Appends the columns `value` flattens to, named `name` if it is not a
+/// struct or after its fields prefixed by `prefix` if it is.
+fn flatten<T: Serialize + ?Sized>(
+    value: &T,
+    name: &str,
+    prefix: &str,
+    columns: &mut Vec<(String, Value)>,
+) -> io::Result<()> {
+    value.serialize(Flattener {
+        name,
+        prefix,
+        columns,
+    })?;
+    Ok(())
+}
+
+struct Flattener<'a> {
+    name: &'a str,
+    prefix: &'a str,
+    columns: &'a mut Vec<(String, Value)>,
+}
+
+impl Flattener<'_> {
+    fn push(self, value: Value) -> Result<(), Error> {
+        self.columns.push((self.name.to_string(), value));
+        Ok(())
+    }
+
+    /// Pushes `value` as an `INTEGER`, or as `TEXT` if it does not fit.
+    fn push_integer<T: TryInto<i64> + ToString>(self, value: T) -> Result<(), Error> {
+        let text = value.to_string();
+        match value.try_into() {
+            Ok(value) => self.push(Value::Integer(value)),
+            Err(_) => self.push(Value::Text(text)),
+        }
+    }
+
+    fn unsupported(&self, what: &str) -> Error {
+        ser::Error::custom(format!(
+            "{} {} cannot be flattened to columns",
+            self.name, what
+        ))
+    }
+}
+
+macro_rules! serialize_integer {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, value: $ty) -> Result<(), Error> {
+                self.push_integer(value)
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for Flattener<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Fields<'a>;
+    type SerializeTupleStruct = Fields<'a>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Fields<'a>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    serialize_integer!(
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128
+    );
+
+    fn serialize_bool(self, value: bool) -> Result<(), Error> {
+        self.push(Value::Integer(value as i64))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<(), Error> {
+        self.push(Value::Real(f64::from(value)))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<(), Error> {
+        self.push(Value::Real(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<(), Error> {
+        self.push(Value::Text(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<(), Error> {
+        self.push(Value::Text(value.to_string()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<(), Error> {
+        self.push(Value::Blob(value.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.push(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.push(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.push(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.push(Value::Text(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Err(self.unsupported("enum variant"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(self.unsupported("sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(self.unsupported("enum variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(self.unsupported("map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Fields<'a>, Error> {
+        Ok(Fields::new(self))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(self.unsupported("enum variant"))
+    }
+}
+
+/// Flattens the fields of a struct or tuple, named after the field or its
+/// index.
+struct Fields<'a> {
+    prefix: &'a str,
+    columns: &'a mut Vec<(String, Value)>,
+    index: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(flattener: Flattener<'a>) -> Self {
+        Fields {
+            prefix: flattener.prefix,
+            columns: flattener.columns,
+            index: 0,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, field: &str, value: &T) -> Result<(), Error> {
+        let name = format!("{}{}", self.prefix, field);
+        value.serialize(Flattener {
+            name: &name,
+            prefix: &format!("{}.", name),
+            columns: self.columns,
+        })
+    }
+}
+
+impl SerializeTuple for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.index += 1;
+        self.field(&(self.index - 1).to_string(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.serialize_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl SerializeStruct for Fields<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export;
+    use crate::collections::map::MapBuilder;
+    use crate::Map;
+    use rusqlite::types::Value;
+    use rusqlite::Connection;
+    use serde::Serialize;
+    use std::fs;
+    use std::io::ErrorKind;
+
+    #[derive(Clone, Serialize)]
+    struct Address {
+        city: String,
+    }
+
+    #[derive(Clone, Serialize)]
+    struct Account {
+        balance: f64,
+        active: bool,
+        address: Option<Address>,
+        tags: Option<Vec<String>>,
+    }
+
+    #[test]
+    fn test_export_keeps_types_and_adds_columns() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-sqlite-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("export.db");
+
+        let map: Map<(String, u64), Account> = MapBuilder::new().bucket_count(1).build();
+        let account = |balance, address: Option<&str>| Account {
+            balance,
+            active: balance > 0.0,
+            address: address.map(|city| Address {
+                city: city.to_string(),
+            }),
+            tags: None,
+        };
+        map.put(&("eu".to_string(), u64::MAX), account(1.5, None));
+        map.put(&("us".to_string(), 7), account(-2.0, Some("Boston")));
+        // sequences have no columns, the export fails as a whole
+        let mut tagged = account(0.0, None);
+        tagged.tags = Some(vec!["vip".to_string()]);
+        map.put(&("us".to_string(), 8), tagged);
+        let err = export(&map, &path, "accounts").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        map.remove(&("us".to_string(), 8));
+
+        assert_eq!(export(&map, &path, "accounts").unwrap(), 2);
+        // exported again, the table is replaced
+        assert_eq!(export(&map, &path, "accounts").unwrap(), 2);
+        let connection = Connection::open(&path).unwrap();
+        let mut select = connection
+            .prepare(
+                "SELECT \"key.0\", \"key.1\", balance, active, \"address.city\" \
+                 FROM accounts ORDER BY balance",
+            )
+            .unwrap();
+        let rows: Vec<Vec<Value>> = select
+            .query_map([], |row| (0..5).map(|i| row.get(i)).collect())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    text("us"),
+                    Value::Integer(7),
+                    Value::Real(-2.0),
+                    Value::Integer(0),
+                    text("Boston"),
+                ],
+                vec![
+                    text("eu"),
+                    text(&u64::MAX.to_string()),
+                    Value::Real(1.5),
+                    Value::Integer(1),
+                    Value::Null,
+                ],
+            ]
+        );
+
+        let empty: Map<u64, u64> = Map::new();
+        assert_eq!(export(&empty, &path, "empty").unwrap(), 0);
+        let count: i64 = connection
+            .query_row("SELECT COUNT(key) + COUNT(value) FROM empty", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,676 @@
+//! JSON text format, used to export collections to standard tooling and
+//! import them back, see [`Map::export_json`](crate::Map::export_json).
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, ErrorKind};
+
+use crate::codec::invalid_data;
+
+/// Maximum nesting of arrays and objects read, deeper documents being
+/// rejected rather than overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON document, parsed but not converted to a Rust type yet.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::json::{self, JsonValue};
+///
+/// let text = r#"{"id": 18446744073709551615, "tags": ["a"]}"#;
+/// let value: JsonValue = json::from_str(text).unwrap();
+/// assert_eq!(
+///     value,
+///     JsonValue::Object(vec![
+///         ("id".to_string(), JsonValue::Number("18446744073709551615".to_string())),
+///         ("tags".to_string(), JsonValue::Array(vec![JsonValue::String("a".to_string())])),
+///     ])
+/// );
+/// assert_eq!(json::to_string(&value), r#"{"id":18446744073709551615,"tags":["a"]}"#);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// A number, kept as written so that no precision is lost.
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// An object, its members in the order they were written.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    fn kind(&self) -> &'static str {
+        match self {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "a bool",
+            JsonValue::Number(_) => "a number",
+            JsonValue::String(_) => "a string",
+            JsonValue::Array(_) => "an array",
+            JsonValue::Object(_) => "an object",
+        }
+    }
+}
+
+/// Types that can be written as JSON.
+///
+/// Numbers are written as JSON numbers, without losing precision, except
+/// for non-finite floats which are written as the strings `"NaN"`, `"inf"`
+/// and `"-inf"`. Characters are written as strings, sequences and tuples as
+/// arrays, and `None` as `null`.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::json::{self, ToJson};
+///
+/// let mut buf = String::new();
+/// (42u32, "answer", Some(1.5f64), None::<bool>).to_json(&mut buf);
+/// assert_eq!(buf, r#"[42,"answer",1.5,null]"#);
+///
+/// let decoded: (u32, String, Option<f64>, Option<bool>) = json::from_str(&buf).unwrap();
+/// assert_eq!(decoded, (42, "answer".to_string(), Some(1.5), None));
+/// ```
+pub trait ToJson {
+    /// Appends the JSON text of `self` to `buf`.
+    fn to_json(&self, buf: &mut String);
+}
+
+/// Types that can be read back from the JSON written by [`ToJson`].
+pub trait FromJson: Sized {
+    /// Converts a parsed JSON document.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the document does not hold a value
+    /// of this type.
+    fn from_json(value: JsonValue) -> io::Result<Self>;
+}
+
+/// Returns the JSON text of `value`.
+pub fn to_string<T: ToJson + ?Sized>(value: &T) -> String {
+    let mut buf = String::new();
+    value.to_json(&mut buf);
+    buf
+}
+
+/// Parses the JSON document `text`, which may be surrounded by whitespace.
+///
+/// # Returns
+///
+/// An error of kind `UnexpectedEof` if `text` ends before the document
+/// does, `InvalidData` if it is not valid JSON or does not hold a value of
+/// type `T`.
+pub fn from_str<T: FromJson>(text: &str) -> io::Result<T> {
+    let mut reader = JsonReader::new(text.as_bytes());
+    let value = reader.read_value()?;
+    reader.end()?;
+    T::from_json(value)
+}
+
+fn ended_early() -> io::Error {
+    io::Error::new(ErrorKind::UnexpectedEof, "JSON text ended early")
+}
+
+fn mismatch(expected: &str, value: &JsonValue) -> io::Error {
+    invalid_data(&format!("expected {}, found {}", expected, value.kind()))
+}
+
+/// Appends `text` to `buf` as a JSON string.
+pub(crate) fn write_string(text: &str, buf: &mut String) {
+    buf.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+/// Streaming parser of JSON text, reading one value at a time so that
+/// arbitrarily long arrays can be read without holding them in memory.
+pub(crate) struct JsonReader<R> {
+    input: R,
+    /// Number of bytes consumed, for error reports.
+    offset: u64,
+}
+
+impl<R: BufRead> JsonReader<R> {
+    pub(crate) fn new(input: R) -> Self {
+        JsonReader { input, offset: 0 }
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        invalid_data(&format!("{} at byte {}", message, self.offset))
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => return Ok(buf.first().copied()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn next(&mut self) -> io::Result<u8> {
+        match self.peek()? {
+            Some(byte) => {
+                self.input.consume(1);
+                self.offset += 1;
+                Ok(byte)
+            }
+            None => Err(ended_early()),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> io::Result<()> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek()? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: u8) -> io::Result<()> {
+        self.skip_whitespace()?;
+        if self.next()? != expected {
+            return Err(self.error(&format!("expected `{}`", expected as char)));
+        }
+        Ok(())
+    }
+
+    /// Checks that only whitespace is left.
+    pub(crate) fn end(&mut self) -> io::Result<()> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(_) => Err(self.error("trailing characters after JSON value")),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the opening of an array, whose elements are then read one at a
+    /// time with [`JsonReader::next_element`].
+    pub(crate) fn begin_array(&mut self) -> io::Result<()> {
+        self.expect(b'[')
+    }
+
+    /// Moves to the next element of the array being read, `first` telling
+    /// whether it is its first one.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the closing of the array was read.
+    pub(crate) fn next_element(&mut self, first: bool) -> io::Result<bool> {
+        self.skip_whitespace()?;
+        if self.peek()? == Some(b']') {
+            self.next()?;
+            return Ok(false);
+        }
+        if !first {
+            self.expect(b',')?;
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn read_value(&mut self) -> io::Result<JsonValue> {
+        self.read_nested(0)
+    }
+
+    fn read_nested(&mut self, depth: usize) -> io::Result<JsonValue> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("JSON nested too deeply"));
+        }
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(b'n') => self.read_literal("null", JsonValue::Null),
+            Some(b't') => self.read_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.read_literal("false", JsonValue::Bool(false)),
+            Some(b'"') => self.read_string().map(JsonValue::String),
+            Some(b'-' | b'0'..=b'9') => self.read_number().map(JsonValue::Number),
+            Some(b'[') => {
+                self.begin_array()?;
+                let mut items = Vec::new();
+                while self.next_element(items.is_empty())? {
+                    items.push(self.read_nested(depth + 1)?);
+                }
+                Ok(JsonValue::Array(items))
+            }
+            Some(b'{') => {
+                self.next()?;
+                let mut members = Vec::new();
+                loop {
+                    self.skip_whitespace()?;
+                    if self.peek()? == Some(b'}') {
+                        self.next()?;
+                        break;
+                    }
+                    if !members.is_empty() {
+                        self.expect(b',')?;
+                        self.skip_whitespace()?;
+                    }
+                    if self.peek()? != Some(b'"') {
+                        return Err(self.error("expected member name"));
+                    }
+                    let name = self.read_string()?;
+                    self.expect(b':')?;
+                    members.push((name, self.read_nested(depth + 1)?));
+                }
+                Ok(JsonValue::Object(members))
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(ended_early()),
+        }
+    }
+
+    fn read_literal(&mut self, literal: &str, value: JsonValue) -> io::Result<JsonValue> {
+        for expected in literal.bytes() {
+            if self.next()? != expected {
+                return Err(self.error(&format!("expected `{}`", literal)));
+            }
+        }
+        Ok(value)
+    }
+
+    /// Appends the digits ahead to `number`, returning how many there were.
+    fn read_digits(&mut self, number: &mut String) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(digit @ b'0'..=b'9') = self.peek()? {
+            self.next()?;
+            number.push(digit as char);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn read_number(&mut self) -> io::Result<String> {
+        let mut number = String::new();
+        if self.peek()? == Some(b'-') {
+            self.next()?;
+            number.push('-');
+        }
+        let integer = self.read_digits(&mut number)?;
+        if integer == 0 || (integer > 1 && number.trim_start_matches('-').starts_with('0')) {
+            return Err(self.error("invalid number"));
+        }
+        if self.peek()? == Some(b'.') {
+            self.next()?;
+            number.push('.');
+            if self.read_digits(&mut number)? == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek()? {
+            number.push(self.next()? as char);
+            if let Some(sign @ (b'+' | b'-')) = self.peek()? {
+                self.next()?;
+                number.push(sign as char);
+            }
+            if self.read_digits(&mut number)? == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        Ok(number)
+    }
+
+    fn read_hex4(&mut self) -> io::Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = (self.next()? as char)
+                .to_digit(16)
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        self.next()?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next()? {
+                b'"' => break,
+                b'\\' => {
+                    let c = match self.next()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.read_hex4()?;
+                            // characters outside the basic plane are escaped
+                            // as a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) {
+                                if self.next()? != b'\\' || self.next()? != b'u' {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                let low = self.read_hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            std::char::from_u32(code)
+                                .ok_or_else(|| self.error("unpaired surrogate"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte if byte < 0x20 => return Err(self.error("control character in string")),
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid utf-8 in string"))
+    }
+}
+
+macro_rules! impl_json_for_integers {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJson for $ty {
+                fn to_json(&self, buf: &mut String) {
+                    let _ = write!(buf, "{}", self);
+                }
+            }
+
+            impl FromJson for $ty {
+                fn from_json(value: JsonValue) -> io::Result<Self> {
+                    match value {
+                        JsonValue::Number(number) => number.parse().map_err(|_| {
+                            invalid_data(&format!(
+                                "{} is not a valid {}",
+                                number,
+                                stringify!($ty)
+                            ))
+                        }),
+                        value => Err(mismatch("a number", &value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_json_for_integers!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! impl_json_for_floats {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJson for $ty {
+                fn to_json(&self, buf: &mut String) {
+                    if self.is_finite() {
+                        let _ = write!(buf, "{}", self);
+                    } else {
+                        write_string(&self.to_string(), buf);
+                    }
+                }
+            }
+
+            impl FromJson for $ty {
+                fn from_json(value: JsonValue) -> io::Result<Self> {
+                    let parsed = match &value {
+                        JsonValue::Number(number) => number.parse().ok(),
+                        JsonValue::String(text) => {
+                            text.parse().ok().filter(|float: &$ty| !float.is_finite())
+                        }
+                        _ => return Err(mismatch("a number", &value)),
+                    };
+                    parsed.ok_or_else(|| mismatch("a number", &value))
+                }
+            }
+        )*
+    };
+}
+
+impl_json_for_floats!(f32, f64);
+
+impl ToJson for bool {
+    fn to_json(&self, buf: &mut String) {
+        buf.push_str(if *self { "true" } else { "false" })
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        match value {
+            JsonValue::Bool(value) => Ok(value),
+            value => Err(mismatch("a bool", &value)),
+        }
+    }
+}
+
+impl ToJson for char {
+    fn to_json(&self, buf: &mut String) {
+        write_string(self.encode_utf8(&mut [0; 4]), buf)
+    }
+}
+
+impl FromJson for char {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        if let JsonValue::String(text) = &value {
+            let mut chars = text.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                return Ok(c);
+            }
+        }
+        Err(mismatch("a single character string", &value))
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self, buf: &mut String) {
+        write_string(self, buf)
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self, buf: &mut String) {
+        write_string(self, buf)
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        match value {
+            JsonValue::String(text) => Ok(text),
+            value => Err(mismatch("a string", &value)),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self, buf: &mut String) {
+        buf.push('[');
+        for (i, item) in self.iter().enumerate() {
+            if i > 0 {
+                buf.push(',');
+            }
+            item.to_json(buf);
+        }
+        buf.push(']');
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self, buf: &mut String) {
+        self.as_slice().to_json(buf)
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        match value {
+            JsonValue::Array(items) => items.into_iter().map(T::from_json).collect(),
+            value => Err(mismatch("an array", &value)),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self, buf: &mut String) {
+        match self {
+            Some(value) => value.to_json(buf),
+            None => buf.push_str("null"),
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        match value {
+            JsonValue::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self, buf: &mut String) {
+        (**self).to_json(buf)
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Box<T> {
+    fn to_json(&self, buf: &mut String) {
+        (**self).to_json(buf)
+    }
+}
+
+impl<T: FromJson> FromJson for Box<T> {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        T::from_json(value).map(Box::new)
+    }
+}
+
+impl ToJson for () {
+    fn to_json(&self, buf: &mut String) {
+        buf.push_str("null")
+    }
+}
+
+impl FromJson for () {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        match value {
+            JsonValue::Null => Ok(()),
+            value => Err(mismatch("null", &value)),
+        }
+    }
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self, buf: &mut String) {
+        match self {
+            JsonValue::Null => buf.push_str("null"),
+            JsonValue::Bool(value) => value.to_json(buf),
+            JsonValue::Number(number) => buf.push_str(number),
+            JsonValue::String(text) => write_string(text, buf),
+            JsonValue::Array(items) => items.to_json(buf),
+            JsonValue::Object(members) => {
+                buf.push('{');
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        buf.push(',');
+                    }
+                    write_string(name, buf);
+                    buf.push(':');
+                    value.to_json(buf);
+                }
+                buf.push('}');
+            }
+        }
+    }
+}
+
+impl FromJson for JsonValue {
+    fn from_json(value: JsonValue) -> io::Result<Self> {
+        Ok(value)
+    }
+}
+
+macro_rules! impl_json_for_tuples {
+    ($(($len:expr; $($name:ident),+)),*) => {
+        $(
+            impl<$($name: ToJson),+> ToJson for ($($name,)+) {
+                #[allow(non_snake_case)]
+                fn to_json(&self, buf: &mut String) {
+                    let ($($name,)+) = self;
+                    let items: &[&dyn ToJson] = &[$($name),+];
+                    items.to_json(buf);
+                }
+            }
+
+            impl<$($name: FromJson),+> FromJson for ($($name,)+) {
+                fn from_json(value: JsonValue) -> io::Result<Self> {
+                    match value {
+                        JsonValue::Array(items) if items.len() == $len => {
+                            let mut items = items.into_iter();
+                            Ok(($($name::from_json(items.next().unwrap())?,)+))
+                        }
+                        value => Err(mismatch(concat!("an array of ", $len), &value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_json_for_tuples!((1; A), (2; A, B), (3; A, B, C), (4; A, B, C, D));
+
+#[cfg(test)]
+mod tests {
+    use super::{from_str, to_string, JsonValue};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_round_trips_edge_values_and_rejects_malformed_text() {
+        let text = "tab\t quote\" slash\\ nul\u{0} snowman\u{2603} crab\u{1f980}".to_string();
+        let value = (text.clone(), vec![u64::MAX, 0], (i128::MIN, -0.25f64));
+        let json = to_string(&value);
+        assert_eq!(
+            from_str::<(String, Vec<u64>, (i128, f64))>(&json).unwrap(),
+            value
+        );
+        assert_eq!(
+            from_str::<String>(r#""\ud83e\udd80 \u2603\/""#).unwrap(),
+            "\u{1f980} \u{2603}/"
+        );
+
+        let floats = vec![f64::INFINITY, f64::NEG_INFINITY, 1e300, f64::MIN_POSITIVE];
+        assert_eq!(from_str::<Vec<f64>>(&to_string(&floats)).unwrap(), floats);
+        assert!(from_str::<f64>(&to_string(&f64::NAN)).unwrap().is_nan());
+        assert!(from_str::<u8>("256").is_err());
+        assert!(from_str::<u32>("1.0").is_err());
+
+        let deep = "[".repeat(1000) + &"]".repeat(1000);
+        for malformed in &[
+            "01",
+            "1.",
+            "-",
+            "[1,]",
+            "{\"a\" 1}",
+            "\"\\ud800\"",
+            "nulx",
+            &deep,
+        ] {
+            let error = from_str::<JsonValue>(malformed).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", malformed);
+        }
+        let error = from_str::<JsonValue>("[1, 2").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(from_str::<JsonValue>("[] []").is_err());
+    }
+}
@@ -1,3 +1,32 @@
+pub mod aof;
+mod checksum;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+pub mod codec;
 pub mod collections;
+pub mod compression;
+#[cfg(feature = "serde")]
+pub mod csv;
+pub mod db;
+pub mod encryption;
+mod files;
+pub mod interop;
+pub mod json;
+pub mod pdb;
+#[cfg(feature = "server")]
+pub mod protocol;
+pub mod pubsub;
+#[cfg(feature = "server")]
+pub mod replication;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod storage;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod verify;
+pub mod wal;
 
-pub use crate::collections::map::Map;
+pub use crate::collections::map::{LockedKeys, Map, Version};
+pub use crate::db::Database;
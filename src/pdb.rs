@@ -0,0 +1,546 @@
+//! Container format of the snapshots and dumps of palladiumdb, PDB files.
+//!
+//! A PDB file starts with a header holding the version of the container
+//! format, the kind of dump it holds along with the version of its layout,
+//! and metadata such as the bucket count or hasher of a map, as text so
+//! that it can be inspected without knowing the dump. Named sections
+//! follow, each with its own checksum, then an end marker counting them so
+//! that a truncated file is told apart from a complete one.
+//!
+//! Readers accept every version up to their own, of the container as well
+//! as of the layout of the dumps they read, and skip sections they do not
+//! know, so a dump taken by an older release loads on a newer one.
+//!
+//! The payloads of the sections can be [compressed](crate::compression),
+//! then [encrypted](crate::encryption), in which case the header records
+//! the codec under the `compression` metadata key and the cipher under the
+//! `encryption` one. The header and the names of the sections are left
+//! readable.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::pdb::{PdbReader, PdbWriter};
+//!
+//! let metadata = [("entries", "2".to_string())];
+//! let mut writer = PdbWriter::new(Vec::new(), "example", 1, &metadata).unwrap();
+//! writer.write_section("entry", b"first").unwrap();
+//! writer.write_section("entry", b"second").unwrap();
+//! let bytes = writer.finish().unwrap();
+//!
+//! let mut reader = PdbReader::new(&bytes[..]).unwrap();
+//! assert_eq!(reader.kind(), "example");
+//! assert_eq!(reader.metadata("entries"), Some("2"));
+//! let mut payloads = Vec::new();
+//! while let Some(section) = reader.next_section().unwrap() {
+//!     payloads.push(section.payload);
+//! }
+//! assert_eq!(payloads, vec![b"first".to_vec(), b"second".to_vec()]);
+//! ```
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::checksum::crc32_update;
+use crate::codec::{decode_len, encode_len, invalid_data, Decode, Encode};
+use crate::compression::{self, Compression};
+use crate::encryption::Encryption;
+
+/// Bytes every PDB file starts with.
+pub const MAGIC: &[u8; 8] = b"PLDBPDB\0";
+
+/// Version of the container format written, readers accepting every
+/// version up to it.
+pub const FORMAT_VERSION: u32 = 1;
+
+const SECTION: u8 = 1;
+const END: u8 = 0;
+
+/// Metadata key recording the cipher of encrypted files, and its value.
+const ENCRYPTION_KEY: &str = "encryption";
+const CIPHER: &str = "chacha20-poly1305";
+
+/// Metadata key recording the codec of compressed files.
+const COMPRESSION_KEY: &str = "compression";
+
+/// Size payloads are batched up to by the writers of dumps made of many
+/// small records.
+pub(crate) const SECTION_SIZE: usize = 64 * 1024;
+
+/// Writes `bytes` to `out`, keeping track of their checksum.
+fn write_checked<W: Write>(out: &mut W, bytes: &[u8], crc: &mut u32) -> io::Result<()> {
+    *crc = crc32_update(*crc, bytes);
+    out.write_all(bytes)
+}
+
+/// Writer of a PDB file, see the [module](self) docs.
+pub struct PdbWriter<W: Write> {
+    out: W,
+    sections: u64,
+    buf: Vec<u8>,
+    compression: Compression,
+    encryption: Option<Encryption>,
+}
+
+impl<W: Write> PdbWriter<W> {
+    /// Writes the header of a dump of `kind`, whose layout is at version
+    /// `kind_version`, described by `metadata`.
+    pub fn new(
+        out: W,
+        kind: &str,
+        kind_version: u32,
+        metadata: &[(&str, String)],
+    ) -> io::Result<Self> {
+        Self::with_options(out, kind, kind_version, metadata, Compression::None, None)
+    }
+
+    /// Writes the header of a dump as [`PdbWriter::new`] does, the payloads
+    /// of its sections being compressed with `compression`, then encrypted
+    /// if `encryption` is set.
+    pub fn with_options(
+        mut out: W,
+        kind: &str,
+        kind_version: u32,
+        metadata: &[(&str, String)],
+        compression: Compression,
+        encryption: Option<Encryption>,
+    ) -> io::Result<Self> {
+        let codec = Some(compression)
+            .filter(Compression::is_enabled)
+            .map(|compression| (COMPRESSION_KEY, compression.name().to_string()));
+        let cipher = encryption
+            .as_ref()
+            .map(|_| (ENCRYPTION_KEY, CIPHER.to_string()));
+        let mut header = Vec::new();
+        kind.encode(&mut header);
+        kind_version.encode(&mut header);
+        encode_len(
+            metadata.len() + codec.iter().len() + cipher.iter().len(),
+            &mut header,
+        );
+        for (key, value) in metadata.iter().chain(codec.iter()).chain(cipher.iter()) {
+            key.encode(&mut header);
+            value.encode(&mut header);
+        }
+
+        let mut buf = MAGIC.to_vec();
+        FORMAT_VERSION.encode(&mut buf);
+        (header.len() as u32).encode(&mut buf);
+        buf.extend_from_slice(&header);
+        crc32_update(0, &header).encode(&mut buf);
+        out.write_all(&buf)?;
+        Ok(PdbWriter {
+            out,
+            sections: 0,
+            buf,
+            compression,
+            encryption,
+        })
+    }
+
+    /// Appends a section named `name` holding `payload`.
+    pub fn write_section(&mut self, name: &str, payload: &[u8]) -> io::Result<()> {
+        let mut packed = Vec::new();
+        let payload = match self.compression.is_enabled() {
+            true => {
+                compression::pack(self.compression, payload, &mut packed);
+                &packed[..]
+            }
+            false => payload,
+        };
+        let sealed;
+        let payload = match &self.encryption {
+            Some(encryption) => {
+                sealed = encryption.seal(payload, &section_aad(self.sections, name))?;
+                &sealed[..]
+            }
+            None => payload,
+        };
+        self.buf.clear();
+        self.buf.push(SECTION);
+        name.encode(&mut self.buf);
+        encode_len(payload.len(), &mut self.buf);
+        let mut crc = 0;
+        write_checked(&mut self.out, &self.buf, &mut crc)?;
+        write_checked(&mut self.out, payload, &mut crc)?;
+        self.out.write_all(&crc.to_le_bytes())?;
+        self.sections += 1;
+        Ok(())
+    }
+
+    /// Writes the end marker and flushes the file.
+    ///
+    /// # Returns
+    ///
+    /// The underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.buf.clear();
+        self.buf.push(END);
+        self.sections.encode(&mut self.buf);
+        let crc = crc32_update(0, &self.buf);
+        crc.encode(&mut self.buf);
+        self.out.write_all(&self.buf)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Returns the data the payload of the section at `index`, named `name`, is
+/// authenticated along with, so that sections cannot be swapped around.
+fn section_aad(index: u64, name: &str) -> Vec<u8> {
+    let mut aad = index.to_le_bytes().to_vec();
+    aad.extend_from_slice(name.as_bytes());
+    aad
+}
+
+/// A section of a PDB file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+/// Reader of a PDB file, see the [module](self) docs.
+pub struct PdbReader<R: Read> {
+    input: R,
+    format_version: u32,
+    kind: String,
+    kind_version: u32,
+    metadata: Vec<(String, String)>,
+    sections: u64,
+    ended: bool,
+    encryption: Option<Encryption>,
+}
+
+impl<R: Read> PdbReader<R> {
+    /// Reads the header of the PDB file `input`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if `input` is not a PDB file, its
+    /// header is corrupted, or it was written by a newer release with a
+    /// container format this one does not know.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a PDB file"));
+        }
+        Self::after_magic(input)
+    }
+
+    /// Reads the header of the PDB file `input`, whose magic bytes were
+    /// read already.
+    pub(crate) fn after_magic(mut input: R) -> io::Result<Self> {
+        let mut fixed = [0; 8];
+        input.read_exact(&mut fixed)?;
+        let mut fixed = &fixed[..];
+        let format_version = u32::decode(&mut fixed)?;
+        if format_version == 0 || format_version > FORMAT_VERSION {
+            return Err(invalid_data(&format!(
+                "PDB format version {} is not supported, {} at most",
+                format_version, FORMAT_VERSION
+            )));
+        }
+        let len = u64::from(u32::decode(&mut fixed)?) + 4;
+        let mut header = Vec::new();
+        (&mut input).take(len).read_to_end(&mut header)?;
+        if header.len() as u64 != len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let (header, crc) = header.split_at(header.len() - 4);
+        if crc32_update(0, header) != u32::decode(&mut &crc[..])? {
+            return Err(invalid_data("PDB header checksum mismatch"));
+        }
+
+        let mut header = header;
+        let kind = String::decode(&mut header)?;
+        let kind_version = u32::decode(&mut header)?;
+        let mut metadata = Vec::new();
+        for _ in 0..decode_len(&mut header)? {
+            metadata.push((String::decode(&mut header)?, String::decode(&mut header)?));
+        }
+        Ok(PdbReader {
+            input,
+            format_version,
+            kind,
+            kind_version,
+            metadata,
+            sections: 0,
+            ended: false,
+            encryption: None,
+        })
+    }
+
+    /// Sets the `Encryption` the payloads of the sections are decrypted
+    /// with, if the file is encrypted.
+    pub fn decrypt_with(&mut self, encryption: Encryption) {
+        self.encryption = Some(encryption);
+    }
+
+    /// Returns `true` if the payloads of the sections are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.metadata(ENCRYPTION_KEY).is_some()
+    }
+
+    /// Returns the version of the container format of the file.
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// Returns the kind of dump the file holds.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Returns the version of the layout of the dump.
+    pub fn kind_version(&self) -> u32 {
+        self.kind_version
+    }
+
+    /// Returns the metadata entry `key` of the file.
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every metadata entry of the file, in the order written.
+    pub fn metadata_entries(&self) -> &[(String, String)] {
+        &self.metadata
+    }
+
+    /// Checks that the file holds a dump of `kind` at a version up to
+    /// `kind_version`.
+    pub(crate) fn expect_kind(&self, kind: &str, kind_version: u32) -> io::Result<()> {
+        if self.kind != kind {
+            return Err(invalid_data(&format!(
+                "PDB file holds a {} dump, not a {} one",
+                self.kind, kind
+            )));
+        }
+        if self.kind_version == 0 || self.kind_version > kind_version {
+            return Err(invalid_data(&format!(
+                "{} dump version {} is not supported, {} at most",
+                kind, self.kind_version, kind_version
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata entry `key` parsed, as required by a dump.
+    pub(crate) fn parse_metadata<T: std::str::FromStr>(&self, key: &str) -> io::Result<T> {
+        self.metadata(key)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| invalid_data(&format!("PDB metadata {} missing or invalid", key)))
+    }
+
+    fn read_byte(&mut self, crc: &mut u32) -> io::Result<u8> {
+        let mut byte = [0];
+        self.read_checked(&mut byte, crc)?;
+        Ok(byte[0])
+    }
+
+    fn read_checked(&mut self, buf: &mut [u8], crc: &mut u32) -> io::Result<()> {
+        self.input.read_exact(buf).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => {
+                io::Error::new(ErrorKind::UnexpectedEof, "truncated PDB file")
+            }
+            _ => err,
+        })?;
+        *crc = crc32_update(*crc, buf);
+        Ok(())
+    }
+
+    fn read_len(&mut self, crc: &mut u32) -> io::Result<usize> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.read_byte(crc)?;
+            bytes.push(byte);
+            if byte & 0x80 == 0 || bytes.len() == 10 {
+                return decode_len(&mut &bytes[..]);
+            }
+        }
+    }
+
+    /// Reads `len` bytes, through `take` so that a corrupted length does
+    /// not allocate more than the file holds.
+    fn read_bytes(&mut self, len: usize, crc: &mut u32) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated PDB file",
+            ));
+        }
+        *crc = crc32_update(*crc, &bytes);
+        Ok(bytes)
+    }
+
+    fn read_crc(&mut self, crc: u32) -> io::Result<()> {
+        let mut expected = [0; 4];
+        self.read_checked(&mut expected, &mut 0)?;
+        if u32::from_le_bytes(expected) != crc {
+            return Err(invalid_data("PDB section checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Reads the next section of the file, checking its checksum.
+    ///
+    /// # Returns
+    ///
+    /// `None` once the end marker was read, an error of kind
+    /// `UnexpectedEof` if the file ends before it, `InvalidData` if the
+    /// section is corrupted, and `InvalidInput` if it is encrypted but
+    /// [`PdbReader::decrypt_with`] was not called.
+    pub fn next_section(&mut self) -> io::Result<Option<Section>> {
+        if self.ended {
+            return Ok(None);
+        }
+        let mut crc = 0;
+        match self.read_byte(&mut crc)? {
+            SECTION => {}
+            END => {
+                let mut count = [0; 8];
+                self.read_checked(&mut count, &mut crc)?;
+                self.read_crc(crc)?;
+                if u64::from_le_bytes(count) != self.sections {
+                    return Err(invalid_data("PDB section count mismatch"));
+                }
+                self.ended = true;
+                return Ok(None);
+            }
+            _ => return Err(invalid_data("PDB section tag unknown")),
+        }
+
+        let len = self.read_len(&mut crc)?;
+        let name = self.read_bytes(len, &mut crc)?;
+        let len = self.read_len(&mut crc)?;
+        let payload = self.read_bytes(len, &mut crc)?;
+        self.read_crc(crc)?;
+        let name = String::from_utf8(name).map_err(|_| invalid_data("invalid utf-8"))?;
+        let payload = match self.metadata(ENCRYPTION_KEY) {
+            None => payload,
+            Some(CIPHER) => {
+                let encryption = self.encryption.as_ref().ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "encrypted PDB file read without encryption configured",
+                    )
+                })?;
+                encryption.open(&payload, &section_aad(self.sections, &name))?
+            }
+            Some(cipher) => {
+                return Err(invalid_data(&format!("PDB cipher {} unknown", cipher)));
+            }
+        };
+        // the codec of every payload is told by its first byte
+        let payload = match self.metadata(COMPRESSION_KEY) {
+            None => payload,
+            Some(_) => compression::unpack(payload)?,
+        };
+        self.sections += 1;
+        Ok(Some(Section { name, payload }))
+    }
+
+    /// Returns `true` if the payloads of the sections are stored as given
+    /// to [`PdbWriter::write_section`], neither compressed nor encrypted.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn is_plain(&self) -> bool {
+        self.metadata(ENCRYPTION_KEY).is_none() && self.metadata(COMPRESSION_KEY).is_none()
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<'a> PdbReader<&'a [u8]> {
+    /// Locates the sections of `file`, a whole PDB file held in memory
+    /// whose header this reader read, without reading their payloads: their
+    /// checksums are not checked, nor are they decrypted or decompressed.
+    ///
+    /// # Returns
+    ///
+    /// The name and the range in `file` of the payload of every section, or
+    /// an error as [`PdbReader::next_section`] if the file is truncated or
+    /// its structure corrupted.
+    pub(crate) fn locate_sections(
+        mut self,
+        file: &'a [u8],
+    ) -> io::Result<Vec<(String, std::ops::Range<usize>)>> {
+        let truncated = || io::Error::new(ErrorKind::UnexpectedEof, "truncated PDB file");
+        let mut sections = Vec::new();
+        loop {
+            let input = &mut self.input;
+            match crate::codec::take(input, 1).map_err(|_| truncated())?[0] {
+                SECTION => {}
+                END => {
+                    let count = u64::decode(input).map_err(|_| truncated())?;
+                    if count != sections.len() as u64 {
+                        return Err(invalid_data("PDB section count mismatch"));
+                    }
+                    return Ok(sections);
+                }
+                _ => return Err(invalid_data("PDB section tag unknown")),
+            }
+            let name = String::decode(input).map_err(|_| truncated())?;
+            let len = decode_len(input)?;
+            let start = file.len() - input.len();
+            crate::codec::take(input, len + 4).map_err(|_| truncated())?;
+            sections.push((name, start..start + len));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PdbReader, PdbWriter, FORMAT_VERSION, MAGIC};
+    use std::io::ErrorKind;
+
+    fn dump() -> Vec<u8> {
+        let metadata = [("bucket_count", "16".to_string())];
+        let mut writer = PdbWriter::new(Vec::new(), "map", 2, &metadata).unwrap();
+        writer.write_section("entries", &[7; 300]).unwrap();
+        writer.write_section("unknown", b"from the future").unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn read_all(bytes: &[u8]) -> std::io::Result<usize> {
+        let mut reader = PdbReader::new(bytes)?;
+        reader.expect_kind("map", 2)?;
+        let mut sections = 0;
+        while reader.next_section()?.is_some() {
+            sections += 1;
+        }
+        Ok(sections)
+    }
+
+    #[test]
+    fn test_reads_back_and_detects_corruption_truncation_and_versions() {
+        let bytes = dump();
+        assert_eq!(read_all(&bytes).unwrap(), 2);
+        let reader = PdbReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.format_version(), FORMAT_VERSION);
+        assert_eq!(reader.kind_version(), 2);
+        assert_eq!(reader.parse_metadata::<usize>("bucket_count").unwrap(), 16);
+        assert!(reader.parse_metadata::<usize>("hasher").is_err());
+        assert!(reader.expect_kind("map", 1).is_err());
+        assert!(reader.expect_kind("checkpoint", 2).is_err());
+
+        // flipping any byte is detected
+        for at in (MAGIC.len()..bytes.len()).step_by(7) {
+            let mut corrupted = bytes.clone();
+            corrupted[at] ^= 0x10;
+            assert!(read_all(&corrupted).is_err(), "byte {}", at);
+        }
+        for len in (0..bytes.len()).step_by(11) {
+            assert!(read_all(&bytes[..len]).is_err(), "length {}", len);
+        }
+        let error = read_all(&bytes[..bytes.len() - 20]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+
+        let mut newer = bytes;
+        newer[MAGIC.len()] = FORMAT_VERSION as u8 + 1;
+        assert_eq!(read_all(&newer).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,572 @@
+//! Wire protocol of the [server](crate::server), with the `server` feature.
+//!
+//! Requests and responses travel in frames: the length of the payload as a
+//! 32-bit little endian integer, then the payload. The payload of a request
+//! is an opcode byte followed by its fields, that of a response a status
+//! byte followed by its body, fields being written with
+//! [`Encode`](crate::codec::Encode). Keys and values are byte strings.
+//!
+//! A connection carries any number of requests, each answered by one
+//! response in the order they were sent. Once a connection subscribes to
+//! channels with [`Request::Subscribe`], the messages published on them are
+//! sent as [`Response::Message`]s as well, in between responses, as are the
+//! [`Response::Invalidate`]s of the keys read by connections tracking them,
+//! see [`Request::Tracking`]. Requests may be pipelined, sent without
+//! waiting for the responses of those before, or batched in a single frame
+//! through [`Request::Batch`], or [`Request::Atomic`] for them to be
+//! carried out as a whole.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::codec::{decode_all, Encode};
+//! use palladiumdb::protocol::{self, Request};
+//!
+//! let request = Request::Get {
+//!     keyspace: "users".to_string(),
+//!     key: b"alice".to_vec(),
+//! };
+//! let mut payload = Vec::new();
+//! request.encode(&mut payload);
+//! let mut frame = Vec::new();
+//! protocol::write_frame(&mut frame, &payload).unwrap();
+//!
+//! let payload = protocol::read_frame(&mut &frame[..]).unwrap().unwrap();
+//! assert_eq!(decode_all::<Request>(&payload).unwrap(), request);
+//! ```
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::codec::{decode_bytes, decode_len, encode_bytes, invalid_data, Decode, Encode};
+
+pub mod resp;
+
+/// Largest payload of a frame, larger ones being refused before they are
+/// read, so that a corrupted length does not exhaust memory.
+pub const MAX_FRAME_LEN: usize = 64 << 20;
+
+const GET: u8 = 1;
+const PUT: u8 = 2;
+const DELETE: u8 = 3;
+const SCAN: u8 = 4;
+const AUTH: u8 = 5;
+const PUBLISH: u8 = 6;
+const SUBSCRIBE: u8 = 7;
+const UNSUBSCRIBE: u8 = 8;
+const BATCH: u8 = 9;
+const ATOMIC: u8 = 10;
+const EVAL: u8 = 11;
+const INFO: u8 = 12;
+const TRACKING: u8 = 13;
+
+const OK: u8 = 0;
+const VALUE: u8 = 1;
+const DELETED: u8 = 2;
+const ENTRIES: u8 = 3;
+const ERROR: u8 = 4;
+const PUBLISHED: u8 = 5;
+const MESSAGE: u8 = 6;
+const RESPONSES: u8 = 7;
+const INVALIDATE: u8 = 8;
+
+/// A request to the server, on the keyspace of the database it names, but
+/// for [`Request::Auth`], [`Request::Info`], [`Request::Tracking`], those of
+/// [channels](crate::pubsub), batches and scripts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Reads the value of `key`, answered by [`Response::Value`].
+    Get { keyspace: String, key: Vec<u8> },
+    /// Maps `key` to `value`, answered by [`Response::Ok`].
+    Put {
+        keyspace: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Removes the entry of `key`, answered by [`Response::Deleted`].
+    Delete { keyspace: String, key: Vec<u8> },
+    /// Lists the entries whose key starts with `prefix`, sorted by key, at
+    /// most `limit` of them, answered by [`Response::Entries`].
+    Scan {
+        keyspace: String,
+        prefix: Vec<u8>,
+        limit: u64,
+    },
+    /// Authenticates the connection as `user`, answered by [`Response::Ok`]
+    /// or [`Response::Error`], see [`Acl`](crate::server::acl::Acl).
+    Auth { user: String, password: String },
+    /// Publishes `payload` on `channel`, answered by
+    /// [`Response::Published`].
+    Publish { channel: String, payload: Vec<u8> },
+    /// Subscribes the connection to the channels matching `pattern`,
+    /// answered by [`Response::Ok`], then by a [`Response::Message`] for
+    /// every message published on them.
+    Subscribe { pattern: String },
+    /// Cancels the subscriptions of the connection to `pattern`, answered
+    /// by [`Response::Ok`].
+    Unsubscribe { pattern: String },
+    /// Carries out `requests`, on keyspaces only, in order, each on its
+    /// own, answered by [`Response::Batch`] of their responses, failures
+    /// included as [`Response::Error`].
+    Batch { requests: Vec<Request> },
+    /// Carries out `requests`, on keyspaces only, in order and as a whole:
+    /// the requests of the other native clients of the server are not
+    /// carried out in between, those of other servers of its database and
+    /// writes made on the database directly may be, and the writes are
+    /// held back until every request is carried out, then made at once,
+    /// logged as a single record, so that they survive a crash together or
+    /// not at all. None is made if one of the requests fails. Answered by
+    /// [`Response::Batch`] of their responses, or by the
+    /// [`Response::Error`] of the failure.
+    Atomic { requests: Vec<Request> },
+    /// Runs the Lua `script` with `args`, as a whole as
+    /// [`Request::Atomic`] does, answered by the [`Response::Value`] the
+    /// script returns, or the [`Response::Batch`] of those of the array it
+    /// returns, with the `scripting` feature, see `server::script`.
+    Eval { script: String, args: Vec<Vec<u8>> },
+    /// Reports the statistics of the server and its database, those of
+    /// `section` only if given, answered by the [`Response::Value`] of
+    /// their `key:value` lines, see [`Stats`](crate::db::Stats).
+    Info { section: Option<String> },
+    /// Makes the server track the keys the connection reads with
+    /// [`Request::Get`], or stop if not `enabled`, answered by
+    /// [`Response::Ok`], then by a [`Response::Invalidate`] the first time
+    /// each key read is written through the server after being read.
+    Tracking { enabled: bool },
+}
+
+/// The answer of the server to a [`Request`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// The request was carried out.
+    Ok,
+    /// The value read, if the key was mapped.
+    Value(Option<Vec<u8>>),
+    /// Whether the key was mapped before being removed.
+    Deleted(bool),
+    /// The entries scanned.
+    Entries(Vec<(Vec<u8>, Vec<u8>)>),
+    /// The request failed, for the reason given.
+    Error(String),
+    /// The number of subscriptions a message was handed to.
+    Published(u64),
+    /// A message published on a channel the connection subscribed to.
+    Message { channel: String, payload: Vec<u8> },
+    /// The responses to the requests of a batch, in their order.
+    Batch(Vec<Response>),
+    /// `key` of `keyspace`, read by the connection while tracking keys, was
+    /// written since, or is no longer tracked.
+    Invalidate { keyspace: String, key: Vec<u8> },
+}
+
+impl Encode for Request {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Request::Get { keyspace, key } => {
+                buf.push(GET);
+                keyspace.encode(buf);
+                encode_bytes(key, buf);
+            }
+            Request::Put {
+                keyspace,
+                key,
+                value,
+            } => {
+                buf.push(PUT);
+                keyspace.encode(buf);
+                encode_bytes(key, buf);
+                encode_bytes(value, buf);
+            }
+            Request::Delete { keyspace, key } => {
+                buf.push(DELETE);
+                keyspace.encode(buf);
+                encode_bytes(key, buf);
+            }
+            Request::Scan {
+                keyspace,
+                prefix,
+                limit,
+            } => {
+                buf.push(SCAN);
+                keyspace.encode(buf);
+                encode_bytes(prefix, buf);
+                limit.encode(buf);
+            }
+            Request::Auth { user, password } => {
+                buf.push(AUTH);
+                user.encode(buf);
+                password.encode(buf);
+            }
+            Request::Publish { channel, payload } => {
+                buf.push(PUBLISH);
+                channel.encode(buf);
+                encode_bytes(payload, buf);
+            }
+            Request::Subscribe { pattern } => {
+                buf.push(SUBSCRIBE);
+                pattern.encode(buf);
+            }
+            Request::Unsubscribe { pattern } => {
+                buf.push(UNSUBSCRIBE);
+                pattern.encode(buf);
+            }
+            Request::Batch { requests } => {
+                buf.push(BATCH);
+                requests.encode(buf);
+            }
+            Request::Atomic { requests } => {
+                buf.push(ATOMIC);
+                requests.encode(buf);
+            }
+            Request::Eval { script, args } => {
+                buf.push(EVAL);
+                script.encode(buf);
+                args.len().encode(buf);
+                for arg in args {
+                    encode_bytes(arg, buf);
+                }
+            }
+            Request::Info { section } => {
+                buf.push(INFO);
+                section.encode(buf);
+            }
+            Request::Tracking { enabled } => {
+                buf.push(TRACKING);
+                enabled.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Request {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let opcode = u8::decode(input)?;
+        let bytes = |input: &mut &[u8]| decode_bytes(input).map(<[u8]>::to_vec);
+        // requests naming no keyspace
+        match opcode {
+            AUTH => {
+                return Ok(Request::Auth {
+                    user: String::decode(input)?,
+                    password: String::decode(input)?,
+                })
+            }
+            PUBLISH => {
+                return Ok(Request::Publish {
+                    channel: String::decode(input)?,
+                    payload: bytes(input)?,
+                })
+            }
+            SUBSCRIBE => {
+                let pattern = String::decode(input)?;
+                return Ok(Request::Subscribe { pattern });
+            }
+            UNSUBSCRIBE => {
+                let pattern = String::decode(input)?;
+                return Ok(Request::Unsubscribe { pattern });
+            }
+            BATCH => {
+                let requests = decode_batch(input)?;
+                return Ok(Request::Batch { requests });
+            }
+            ATOMIC => {
+                let requests = decode_batch(input)?;
+                return Ok(Request::Atomic { requests });
+            }
+            EVAL => {
+                let script = String::decode(input)?;
+                let len = usize::decode(input)?;
+                let mut args = Vec::with_capacity(len.min(input.len()));
+                for _ in 0..len {
+                    args.push(bytes(input)?);
+                }
+                return Ok(Request::Eval { script, args });
+            }
+            INFO => {
+                let section = Option::<String>::decode(input)?;
+                return Ok(Request::Info { section });
+            }
+            TRACKING => {
+                let enabled = bool::decode(input)?;
+                return Ok(Request::Tracking { enabled });
+            }
+            _ => {}
+        }
+        let keyspace = String::decode(input)?;
+        match opcode {
+            GET => Ok(Request::Get {
+                keyspace,
+                key: bytes(input)?,
+            }),
+            PUT => Ok(Request::Put {
+                keyspace,
+                key: bytes(input)?,
+                value: bytes(input)?,
+            }),
+            DELETE => Ok(Request::Delete {
+                keyspace,
+                key: bytes(input)?,
+            }),
+            SCAN => Ok(Request::Scan {
+                keyspace,
+                prefix: bytes(input)?,
+                limit: u64::decode(input)?,
+            }),
+            _ => Err(invalid_data("unknown request opcode")),
+        }
+    }
+}
+
+/// Decodes the requests of a batch, refusing nested batches before they are
+/// decoded, so that deep nesting does not exhaust the stack.
+fn decode_batch(input: &mut &[u8]) -> io::Result<Vec<Request>> {
+    let len = decode_len(input)?;
+    let mut requests = Vec::with_capacity(len.min(input.len()));
+    for _ in 0..len {
+        if matches!(input.first(), Some(&BATCH) | Some(&ATOMIC)) {
+            return Err(invalid_data("batches may not be nested"));
+        }
+        requests.push(Request::decode(input)?);
+    }
+    Ok(requests)
+}
+
+impl Encode for Response {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Response::Ok => buf.push(OK),
+            Response::Value(value) => {
+                buf.push(VALUE);
+                value.is_some().encode(buf);
+                if let Some(value) = value {
+                    encode_bytes(value, buf);
+                }
+            }
+            Response::Deleted(deleted) => {
+                buf.push(DELETED);
+                deleted.encode(buf);
+            }
+            Response::Entries(entries) => {
+                buf.push(ENTRIES);
+                entries.len().encode(buf);
+                for (key, value) in entries {
+                    encode_bytes(key, buf);
+                    encode_bytes(value, buf);
+                }
+            }
+            Response::Error(message) => {
+                buf.push(ERROR);
+                message.encode(buf);
+            }
+            Response::Published(subscriptions) => {
+                buf.push(PUBLISHED);
+                subscriptions.encode(buf);
+            }
+            Response::Message { channel, payload } => {
+                buf.push(MESSAGE);
+                channel.encode(buf);
+                encode_bytes(payload, buf);
+            }
+            Response::Batch(responses) => {
+                buf.push(RESPONSES);
+                responses.encode(buf);
+            }
+            Response::Invalidate { keyspace, key } => {
+                buf.push(INVALIDATE);
+                keyspace.encode(buf);
+                encode_bytes(key, buf);
+            }
+        }
+    }
+}
+
+impl Decode for Response {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let bytes = |input: &mut &[u8]| decode_bytes(input).map(<[u8]>::to_vec);
+        match u8::decode(input)? {
+            OK => Ok(Response::Ok),
+            VALUE => match bool::decode(input)? {
+                true => Ok(Response::Value(Some(bytes(input)?))),
+                false => Ok(Response::Value(None)),
+            },
+            DELETED => Ok(Response::Deleted(bool::decode(input)?)),
+            ENTRIES => {
+                let len = usize::decode(input)?;
+                // bounded by the input, so that a corrupted length does not
+                // allocate more than it holds
+                let mut entries = Vec::with_capacity(len.min(input.len()));
+                for _ in 0..len {
+                    entries.push((bytes(input)?, bytes(input)?));
+                }
+                Ok(Response::Entries(entries))
+            }
+            ERROR => Ok(Response::Error(String::decode(input)?)),
+            PUBLISHED => Ok(Response::Published(u64::decode(input)?)),
+            MESSAGE => Ok(Response::Message {
+                channel: String::decode(input)?,
+                payload: bytes(input)?,
+            }),
+            RESPONSES => Ok(Response::Batch(Vec::decode(input)?)),
+            INVALIDATE => Ok(Response::Invalidate {
+                keyspace: String::decode(input)?,
+                key: bytes(input)?,
+            }),
+            _ => Err(invalid_data("unknown response status")),
+        }
+    }
+}
+
+/// Reads the payload of the next frame of `input`.
+///
+/// # Returns
+///
+/// `None` if `input` ended before the frame, an error of kind
+/// `UnexpectedEof` if it ended within it, of kind `InvalidData` if the
+/// frame is longer than [`MAX_FRAME_LEN`].
+pub fn read_frame<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match input.read(&mut len[..1])? {
+        0 => return Ok(None),
+        _ => input.read_exact(&mut len[1..])?,
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("frame too long"));
+    }
+    let mut payload = vec![0; len];
+    input.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Writes `payload` to `out` as a frame.
+///
+/// # Returns
+///
+/// An error of kind `InvalidInput` if the payload is longer than
+/// [`MAX_FRAME_LEN`].
+pub fn write_frame<W: Write>(out: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "frame too long"));
+    }
+    out.write_all(&(payload.len() as u32).to_le_bytes())?;
+    out.write_all(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_frame, write_frame, Request, Response, MAX_FRAME_LEN};
+    use crate::codec::{decode_all, Encode};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_messages_round_trip_through_frames() {
+        let keyspace = "users".to_string();
+        let requests = vec![
+            Request::Get {
+                keyspace: keyspace.clone(),
+                key: b"alice".to_vec(),
+            },
+            Request::Put {
+                keyspace: keyspace.clone(),
+                key: b"alice".to_vec(),
+                value: vec![0, 1, 2],
+            },
+            Request::Delete {
+                keyspace: keyspace.clone(),
+                key: Vec::new(),
+            },
+            Request::Scan {
+                keyspace,
+                prefix: b"al".to_vec(),
+                limit: u64::MAX,
+            },
+            Request::Auth {
+                user: "alice".to_string(),
+                password: "s3cret".to_string(),
+            },
+            Request::Publish {
+                channel: "orders".to_string(),
+                payload: b"42".to_vec(),
+            },
+            Request::Subscribe {
+                pattern: "orders.*".to_string(),
+            },
+            Request::Unsubscribe {
+                pattern: "orders.*".to_string(),
+            },
+            Request::Batch {
+                requests: vec![Request::Delete {
+                    keyspace: "users".to_string(),
+                    key: b"bob".to_vec(),
+                }],
+            },
+            Request::Atomic {
+                requests: Vec::new(),
+            },
+            Request::Eval {
+                script: "return ARGV[1]".to_string(),
+                args: vec![b"42".to_vec()],
+            },
+            Request::Info { section: None },
+            Request::Info {
+                section: Some("keyspace".to_string()),
+            },
+            Request::Tracking { enabled: true },
+        ];
+        let responses = vec![
+            Response::Ok,
+            Response::Value(None),
+            Response::Value(Some(Vec::new())),
+            Response::Deleted(true),
+            Response::Entries(vec![(b"a".to_vec(), b"1".to_vec())]),
+            Response::Error("keyspace has other types".to_string()),
+            Response::Published(2),
+            Response::Message {
+                channel: "orders".to_string(),
+                payload: b"42".to_vec(),
+            },
+            Response::Batch(vec![Response::Deleted(false), Response::Value(None)]),
+            Response::Invalidate {
+                keyspace: "users".to_string(),
+                key: b"alice".to_vec(),
+            },
+        ];
+
+        let mut stream = Vec::new();
+        for request in &requests {
+            let mut payload = Vec::new();
+            request.encode(&mut payload);
+            write_frame(&mut stream, &payload).unwrap();
+        }
+        for response in &responses {
+            let mut payload = Vec::new();
+            response.encode(&mut payload);
+            write_frame(&mut stream, &payload).unwrap();
+        }
+        let mut input = &stream[..];
+        for request in requests {
+            let payload = read_frame(&mut input).unwrap().unwrap();
+            assert_eq!(decode_all::<Request>(&payload).unwrap(), request);
+        }
+        for response in responses {
+            let payload = read_frame(&mut input).unwrap().unwrap();
+            assert_eq!(decode_all::<Response>(&payload).unwrap(), response);
+        }
+        assert_eq!(read_frame(&mut input).unwrap(), None);
+
+        // torn, oversized and unknown frames
+        let torn = &stream[..6];
+        let err = read_frame(&mut &torn[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let oversized = ((MAX_FRAME_LEN + 1) as u32).to_le_bytes();
+        let err = read_frame(&mut &oversized[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(decode_all::<Request>(&[14, 0]).is_err());
+        let mut nested = Vec::new();
+        Request::Batch {
+            requests: vec![Request::Atomic {
+                requests: Vec::new(),
+            }],
+        }
+        .encode(&mut nested);
+        assert!(decode_all::<Request>(&nested).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! The Redis serialization protocol, RESP2 and RESP3, which the
+//! [server](crate::server) speaks to Redis clients when built with
+//! [`Protocol::Resp`](crate::server::Protocol::Resp).
+//!
+//! Clients send commands as arrays of bulk strings, or inline as a line of
+//! words separated by spaces, and are answered with a [`Value`]. Values
+//! RESP2 lacks, nulls, maps and pushes, are written as their RESP2
+//! counterparts until a client switches to RESP3 with `HELLO 3`.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::protocol::resp::{self, ProtocolVersion, Value};
+//!
+//! let mut command = &b"*2\r\n$3\r\nGET\r\n$5\r\nalice\r\n"[..];
+//! let args = resp::read_command(&mut command).unwrap().unwrap();
+//! assert_eq!(args, vec![b"GET".to_vec(), b"alice".to_vec()]);
+//!
+//! let mut reply = Vec::new();
+//! resp::write_value(&mut reply, &Value::Null, ProtocolVersion::Resp2).unwrap();
+//! assert_eq!(reply, b"$-1\r\n");
+//! assert_eq!(resp::read_value(&mut &reply[..]).unwrap(), Some(Value::Null));
+//! ```
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+use super::MAX_FRAME_LEN;
+use crate::codec::invalid_data;
+
+/// Longest line read, bulk strings aside, so that a client never sending a
+/// line break does not exhaust memory.
+pub const MAX_LINE_LEN: usize = 64 << 10;
+
+/// Most elements of an array or map allocated for before they are read.
+const PREALLOCATED: usize = 1024;
+
+/// The version of the protocol spoken on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// RESP2, which connections start with.
+    Resp2,
+    /// RESP3, negotiated with `HELLO 3`.
+    Resp3,
+}
+
+/// A value of the protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A short string, without line breaks.
+    Simple(String),
+    /// An error, whose message starts with its code, as in `ERR unknown`.
+    Error(String),
+    Integer(i64),
+    /// A binary safe string.
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    /// A missing value, written as a null bulk string in RESP2.
+    Null,
+    /// Key value pairs, written as a flat array in RESP2.
+    Map(Vec<(Value, Value)>),
+    /// Data sent without being asked for, such as the messages of a
+    /// subscription, written as an array in RESP2.
+    Push(Vec<Value>),
+}
+
+/// Reads a line ending with `\r\n`, or `\n` alone, without the line break.
+///
+/// # Returns
+///
+/// `None` if `input` ended before the line.
+pub(crate) fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    input
+        .take(MAX_LINE_LEN as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+    match line.last() {
+        None => return Ok(None),
+        Some(b'\n') => {}
+        Some(_) if line.len() > MAX_LINE_LEN => return Err(invalid_data("line too long")),
+        Some(_) => return Err(ErrorKind::UnexpectedEof.into()),
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse<T: std::str::FromStr>(bytes: &[u8]) -> io::Result<T> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| invalid_data("invalid number"))
+}
+
+fn text(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|_| invalid_data("invalid UTF-8 in simple string"))
+}
+
+/// Reads the body of a bulk string of `len` bytes, followed by a line break.
+fn read_bulk<R: BufRead>(input: &mut R, len: i64) -> io::Result<Vec<u8>> {
+    let len = usize::try_from(len).map_err(|_| invalid_data("invalid bulk length"))?;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("bulk string too long"));
+    }
+    let mut bulk = vec![0; len + 2];
+    input.read_exact(&mut bulk)?;
+    if !bulk.ends_with(b"\r\n") {
+        return Err(invalid_data("bulk string not followed by a line break"));
+    }
+    bulk.truncate(len);
+    Ok(bulk)
+}
+
+/// Reads `len` elements, as counted by the header of an aggregate.
+fn read_values<R: BufRead>(input: &mut R, len: usize) -> io::Result<Vec<Value>> {
+    let mut values = Vec::with_capacity(len.min(PREALLOCATED));
+    for _ in 0..len {
+        values.push(read_value(input)?.ok_or(ErrorKind::UnexpectedEof)?);
+    }
+    Ok(values)
+}
+
+/// Reads the next value of `input`, in either version of the protocol.
+///
+/// # Returns
+///
+/// `None` if `input` ended before the value, an error of kind
+/// `UnexpectedEof` if it ended within it, of kind `InvalidData` if the value
+/// is malformed.
+pub fn read_value<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut line = match read_line(input)? {
+        Some(line) if !line.is_empty() => line,
+        Some(_) => return Err(invalid_data("empty line")),
+        None => return Ok(None),
+    };
+    let body = line.split_off(1);
+    let value = match line[0] {
+        b'+' => Value::Simple(text(body)?),
+        b'-' => Value::Error(text(body)?),
+        b':' => Value::Integer(parse(&body)?),
+        b'$' => match parse(&body)? {
+            -1 => Value::Null,
+            len => Value::Bulk(read_bulk(input, len)?),
+        },
+        b'*' => match parse::<i64>(&body)? {
+            -1 => Value::Null,
+            len => {
+                let len = usize::try_from(len).map_err(|_| invalid_data("invalid length"))?;
+                Value::Array(read_values(input, len)?)
+            }
+        },
+        b'>' => Value::Push(read_values(input, parse(&body)?)?),
+        b'_' if body.is_empty() => Value::Null,
+        b'%' => {
+            let len = parse::<usize>(&body)?;
+            let mut values = read_values(input, len.checked_mul(2).ok_or(ErrorKind::InvalidData)?)?;
+            let mut pairs = Vec::with_capacity(len.min(PREALLOCATED));
+            while !values.is_empty() {
+                let mut pair = values.drain(..2);
+                pairs.push((pair.next().unwrap(), pair.next().unwrap()));
+            }
+            Value::Map(pairs)
+        }
+        _ => return Err(invalid_data("unknown value type")),
+    };
+    Ok(Some(value))
+}
+
+/// Reads the next command of `input`, as the arguments it is made of, the
+/// name of the command first. Inline commands are split on whitespace;
+/// blank lines make commands without arguments.
+///
+/// # Returns
+///
+/// `None` if `input` ended before the command, errors as
+/// [`read_value`] does.
+pub fn read_command<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    match input.fill_buf()?.first() {
+        None => return Ok(None),
+        Some(b'*') => {}
+        Some(_) => {
+            let line = read_line(input)?.ok_or(ErrorKind::UnexpectedEof)?;
+            let args = line
+                .split(u8::is_ascii_whitespace)
+                .filter(|arg| !arg.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect();
+            return Ok(Some(args));
+        }
+    }
+    let line = read_line(input)?.ok_or(ErrorKind::UnexpectedEof)?;
+    let len = parse::<usize>(&line[1..])?;
+    let mut args = Vec::with_capacity(len.min(PREALLOCATED));
+    for _ in 0..len {
+        let line = read_line(input)?.ok_or(ErrorKind::UnexpectedEof)?;
+        match line.split_first() {
+            Some((b'$', len)) => args.push(read_bulk(input, parse(len)?)?),
+            _ => return Err(invalid_data("command arguments must be bulk strings")),
+        }
+    }
+    Ok(Some(args))
+}
+
+/// Writes a simple string or error, replacing line breaks by spaces.
+fn write_line<W: Write>(out: &mut W, kind: u8, line: &str) -> io::Result<()> {
+    out.write_all(&[kind])?;
+    for part in line.split(['\r', '\n']).enumerate() {
+        match part {
+            (0, part) => out.write_all(part.as_bytes())?,
+            (_, part) => write!(out, " {}", part)?,
+        }
+    }
+    out.write_all(b"\r\n")
+}
+
+/// Writes `value` to `out`, in the `version` of the protocol.
+pub fn write_value<W: Write>(
+    out: &mut W,
+    value: &Value,
+    version: ProtocolVersion,
+) -> io::Result<()> {
+    match value {
+        Value::Simple(line) => write_line(out, b'+', line),
+        Value::Error(message) => write_line(out, b'-', message),
+        Value::Integer(n) => write!(out, ":{}\r\n", n),
+        Value::Bulk(bulk) => {
+            write!(out, "${}\r\n", bulk.len())?;
+            out.write_all(bulk)?;
+            out.write_all(b"\r\n")
+        }
+        Value::Array(values) => {
+            write!(out, "*{}\r\n", values.len())?;
+            values
+                .iter()
+                .try_for_each(|value| write_value(out, value, version))
+        }
+        Value::Push(values) => {
+            match version {
+                ProtocolVersion::Resp2 => write!(out, "*{}\r\n", values.len())?,
+                ProtocolVersion::Resp3 => write!(out, ">{}\r\n", values.len())?,
+            }
+            values
+                .iter()
+                .try_for_each(|value| write_value(out, value, version))
+        }
+        Value::Null => match version {
+            ProtocolVersion::Resp2 => out.write_all(b"$-1\r\n"),
+            ProtocolVersion::Resp3 => out.write_all(b"_\r\n"),
+        },
+        Value::Map(pairs) => {
+            match version {
+                ProtocolVersion::Resp2 => write!(out, "*{}\r\n", pairs.len() * 2)?,
+                ProtocolVersion::Resp3 => write!(out, "%{}\r\n", pairs.len())?,
+            }
+            pairs.iter().try_for_each(|(key, value)| {
+                write_value(out, key, version)?;
+                write_value(out, value, version)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_command, read_value, write_value, ProtocolVersion, Value};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_values_and_commands_round_trip() {
+        let value = Value::Array(vec![
+            Value::Simple("OK".to_string()),
+            Value::Error("ERR no\r\nsuch key".to_string()),
+            Value::Integer(-42),
+            Value::Bulk(b"line\r\nbreak".to_vec()),
+            Value::Null,
+            Value::Map(vec![(Value::Bulk(b"proto".to_vec()), Value::Integer(3))]),
+            Value::Array(Vec::new()),
+            Value::Push(vec![Value::Bulk(b"message".to_vec())]),
+        ]);
+        let mut resp3 = Vec::new();
+        write_value(&mut resp3, &value, ProtocolVersion::Resp3).unwrap();
+        let mut input = &resp3[..];
+        let mut read = match read_value(&mut input).unwrap().unwrap() {
+            Value::Array(read) => read,
+            read => panic!("not an array: {:?}", read),
+        };
+        // line breaks cannot be escaped in errors
+        assert_eq!(read[1], Value::Error("ERR no  such key".to_string()));
+        read[1] = Value::Error("ERR no\r\nsuch key".to_string());
+        assert_eq!(Value::Array(read), value);
+        assert_eq!(read_value(&mut input).unwrap(), None);
+
+        // RESP2 has neither nulls, maps nor pushes of its own
+        let mut resp2 = Vec::new();
+        let map = Value::Map(vec![(Value::Integer(1), Value::Null)]);
+        write_value(&mut resp2, &map, ProtocolVersion::Resp2).unwrap();
+        assert_eq!(resp2, b"*2\r\n:1\r\n$-1\r\n");
+        resp2.clear();
+        let push = Value::Push(vec![Value::Integer(1)]);
+        write_value(&mut resp2, &push, ProtocolVersion::Resp2).unwrap();
+        assert_eq!(resp2, b"*1\r\n:1\r\n");
+
+        let mut input = &b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$0\r\n\r\n  PING  hello\r\n\n"[..];
+        let set = read_command(&mut input).unwrap().unwrap();
+        assert_eq!(set, vec![b"SET".to_vec(), b"k".to_vec(), Vec::new()]);
+        let ping = read_command(&mut input).unwrap().unwrap();
+        assert_eq!(ping, vec![b"PING".to_vec(), b"hello".to_vec()]);
+        assert!(read_command(&mut input).unwrap().unwrap().is_empty());
+        assert_eq!(read_command(&mut input).unwrap(), None);
+
+        // torn and malformed input
+        let err = read_command(&mut &b"*2\r\n$3\r\nGET\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = read_command(&mut &b"*1\r\n:1\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = read_value(&mut &b"$3\r\nabcd\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
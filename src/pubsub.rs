@@ -0,0 +1,302 @@
+//! Messages published on channels, delivered to the subscribers of patterns
+//! matching them.
+//!
+//! A [`Broker`] hands every message published to the [`Subscription`]s
+//! whose pattern matches its channel, patterns being globs where `*`
+//! matches any bytes, `?` any byte, `[...]` any byte of a set, negated by a
+//! leading `^`, and `\` escapes the byte following it. Messages are kept
+//! until received, in the order they were published, and are lost if no
+//! subscription matches their channel: subscribers only see the messages
+//! published after they subscribed.
+//!
+//! Servers publish and subscribe on behalf of their clients through a
+//! broker shared with the application, see `ServerBuilder::broker`, with
+//! the `server` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::pubsub::Broker;
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! let broker = Arc::new(Broker::new());
+//! let orders = broker.subscribe("orders.*");
+//!
+//! let publisher = broker.clone();
+//! thread::spawn(move || {
+//!     publisher.publish("orders.created", b"42");
+//!     publisher.publish("users.created", b"alice");
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! let message = orders.recv().unwrap();
+//! assert_eq!(message.channel, "orders.created");
+//! assert_eq!(message.payload, b"42");
+//! assert!(orders.try_recv().is_none());
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// A message published on a channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The channel the message was published on.
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+/// A subscription of the broker, through which it hands messages.
+#[derive(Debug)]
+struct Subscriber {
+    id: u64,
+    pattern: Vec<u8>,
+    sender: Sender<Message>,
+}
+
+/// Hands the messages published on channels to the subscriptions whose
+/// pattern matches them, see [`crate::pubsub`].
+#[derive(Debug, Default)]
+pub struct Broker {
+    subscribers: Mutex<Vec<Subscriber>>,
+    next_id: AtomicU64,
+}
+
+impl Broker {
+    /// Creates a broker without subscriptions.
+    pub fn new() -> Self {
+        Broker::default()
+    }
+
+    /// Publishes `payload` on `channel`, to the subscriptions whose pattern
+    /// matches it, those dropped being forgotten.
+    ///
+    /// # Returns
+    ///
+    /// The number of subscriptions the message was handed to.
+    pub fn publish(&self, channel: &str, payload: &[u8]) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut delivered = 0;
+        subscribers.retain(|subscriber| {
+            if !matches(&subscriber.pattern, channel.as_bytes()) {
+                return true;
+            }
+            let message = Message {
+                channel: channel.to_string(),
+                payload: payload.to_vec(),
+            };
+            let open = subscriber.sender.send(message).is_ok();
+            delivered += open as usize;
+            open
+        });
+        delivered
+    }
+
+    /// Subscribes to the channels matching `pattern`, until the
+    /// subscription returned is dropped.
+    pub fn subscribe(self: &Arc<Self>, pattern: &str) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            id,
+            pattern: pattern.as_bytes().to_vec(),
+            sender,
+        });
+        Subscription {
+            broker: Arc::downgrade(self),
+            id,
+            pattern: pattern.to_string(),
+            receiver,
+        }
+    }
+
+    /// Returns the number of subscriptions.
+    pub fn subscriptions(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+/// Messages published on the channels matching a pattern, received in the
+/// order they were published, see [`Broker::subscribe`]. Iterating over a
+/// subscription blocks for each message.
+#[derive(Debug)]
+pub struct Subscription {
+    broker: Weak<Broker>,
+    id: u64,
+    pattern: String,
+    receiver: Receiver<Message>,
+}
+
+impl Subscription {
+    /// Returns the pattern subscribed to.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Waits for the next message.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the broker was dropped.
+    pub fn recv(&self) -> Option<Message> {
+        self.receiver.recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next message.
+    ///
+    /// # Returns
+    ///
+    /// `None` if no message was published in time.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Message> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => Some(message),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// Returns the next message, if one was published and not received yet.
+    pub fn try_recv(&self) -> Option<Message> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        self.recv()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(broker) = self.broker.upgrade() {
+            let mut subscribers = broker.subscribers.lock().unwrap();
+            subscribers.retain(|subscriber| subscriber.id != self.id);
+        }
+    }
+}
+
+/// Returns `true` if `key` matches the glob-style `pattern` of a
+/// subscription, or of a `SCAN` of the server, where `*` matches any bytes, `?` any byte, `[...]` any byte of a set,
+/// negated by a leading `^`, and `\` escapes the byte following it.
+pub(crate) fn matches(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // where to resume after the last star, matching one more byte with it
+    let mut backtrack = None;
+    while k < key.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, k));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_set(pattern, p, key[k]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == key[k]).then_some(p + 2),
+            Some(&byte) => (byte == key[k]).then_some(p + 1),
+            None => None,
+        };
+        match (next, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                k += 1;
+            }
+            (None, Some((star, from))) => {
+                p = star;
+                k = from + 1;
+                backtrack = Some((star, from + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Matches `byte` against the set opening at `pattern[open]`.
+///
+/// # Returns
+///
+/// The position following the set if `byte` is in it. Sets left open match
+/// their bracket literally.
+fn match_set(pattern: &[u8], open: usize, byte: u8) -> Option<usize> {
+    let mut i = open + 1;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut found = false;
+    loop {
+        match pattern.get(i..) {
+            Some([b']', ..]) => break,
+            Some([b'\\', escaped, ..]) => {
+                found |= *escaped == byte;
+                i += 2;
+            }
+            Some([low, b'-', high, ..]) if *high != b']' => {
+                let (low, high) = (*low.min(high), *low.max(high));
+                found |= (low..=high).contains(&byte);
+                i += 3;
+            }
+            Some([member, ..]) => {
+                found |= *member == byte;
+                i += 1;
+            }
+            _ => return (byte == b'[').then_some(open + 1),
+        }
+    }
+    (found != negated).then_some(i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Broker, Message};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_messages_reach_the_subscriptions_matching_their_channel() {
+        let broker = Arc::new(Broker::new());
+        assert_eq!(broker.publish("orders.created", b"lost"), 0);
+        let mut orders = broker.subscribe("orders.*");
+        let created = broker.subscribe("*.created");
+        let exact = broker.subscribe("users\\*");
+        assert_eq!(broker.subscriptions(), 3);
+
+        let publisher = broker.clone();
+        let publishing = thread::spawn(move || {
+            (0..100u8)
+                .map(|i| publisher.publish("orders.created", &[i]))
+                .sum::<usize>()
+        });
+        let payloads: Vec<_> = orders.by_ref().take(100).map(|m| m.payload).collect();
+        assert_eq!(payloads, (0..100u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(publishing.join().unwrap(), 200);
+        assert_eq!(std::iter::from_fn(|| created.try_recv()).count(), 100);
+
+        assert_eq!(broker.publish("users*", b"alice"), 1);
+        assert_eq!(broker.publish("users.created", b"bob"), 1);
+        assert_eq!(
+            exact.recv_timeout(Duration::from_secs(1)),
+            Some(Message {
+                channel: "users*".to_string(),
+                payload: b"alice".to_vec(),
+            })
+        );
+        assert_eq!(exact.recv_timeout(Duration::from_millis(1)), None);
+
+        // dropped subscriptions receive nothing more, nor do those of a
+        // broker dropped
+        drop(orders);
+        assert_eq!(broker.subscriptions(), 2);
+        assert_eq!(broker.publish("orders.created", b"x"), 1);
+        drop(broker);
+        let payloads: Vec<_> = created.map(|message| message.payload).collect();
+        assert_eq!(payloads, vec![b"bob".to_vec(), b"x".to_vec()]);
+    }
+}
@@ -0,0 +1,789 @@
+//! Asynchronous replication of a [`Database`] stored on disk to followers,
+//! with the `server` feature.
+//!
+//! A [`Primary`] serves the log of its database over TCP to followers, each
+//! one replicating it into a database of its own through a [`Replica`].
+//! Followers new to the primary, or so far behind that the records they
+//! need were removed by a checkpoint, are first synced in full: they drop
+//! their durable keyspaces and are sent the entries of those of the
+//! primary, copied as a checkpoint copies them, then the records logged
+//! since. Followers are otherwise caught up from the offset they reached,
+//! the LSN of the next record of the primary to replicate, recorded in
+//! their [`REPLICATION_FILE`] so that they resume from there once
+//! restarted. Records are sent as they are logged, the primary waiting for
+//! no follower.
+//!
+//! Followers log and apply the records replicated, taking no writes of
+//! their own, see [`Database::is_follower`], while serving reads, such as
+//! through a [server](crate::server). [`Replica::promote`] makes one a
+//! primary: it stops following and takes writes again, its log starting a
+//! history of its own, so that followers moving over to it are synced in
+//! full.
+//!
+//! Followers acknowledge the offset they reach, the primary reporting that
+//! of each one and how many records it lags behind in the statistics of its
+//! database, see [`ReplicationStats`](crate::db::ReplicationStats). Followers
+//! report how long ago they were last caught up with the primary, which is
+//! at least every [`HEARTBEAT`] while connected to it, so that clients may
+//! bound how stale their reads are, see `client::ReadPreference`, with the
+//! `client` feature.
+//!
+//! Only durable keyspaces are replicated, neither those kept in memory nor
+//! the stores of the engine.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::db::Database;
+//! use palladiumdb::replication::{Primary, Replica};
+//! use std::sync::Arc;
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let dir = std::env::temp_dir().join("palladiumdb-doc-replication");
+//! # let _ = std::fs::remove_dir_all(&dir);
+//! let db = Arc::new(Database::open(dir.join("primary")).unwrap());
+//! db.open_durable::<String, u64>("users")
+//!     .unwrap()
+//!     .put(&"alice".to_string(), 31)
+//!     .unwrap();
+//! let primary = Primary::start(db, "127.0.0.1:0").unwrap();
+//!
+//! let follower = Arc::new(Database::open(dir.join("follower")).unwrap());
+//! let address = primary.local_addr().to_string();
+//! let replica = Replica::start(follower.clone(), &address).unwrap();
+//! while !follower.contains_map("users") {
+//!     thread::sleep(Duration::from_millis(10));
+//! }
+//! let users = follower.open_durable::<String, u64>("users").unwrap();
+//! # while users.is_empty() {
+//! #     thread::sleep(Duration::from_millis(10));
+//! # }
+//! assert_eq!(users.get(&"alice".to_string()), Some(31));
+//! assert!(users.put(&"bob".to_string(), 20).is_err());
+//!
+//! replica.promote().unwrap();
+//! users.put(&"bob".to_string(), 20).unwrap();
+//! # drop((primary, users, follower));
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::codec::{decode_all, decode_bytes, encode_bytes, invalid_data, Decode, Encode};
+use crate::collections::utils::random;
+use crate::db::{Database, Error, Result, Role};
+use crate::protocol;
+use crate::storage::StorageEngine;
+use crate::wal::Lsn;
+
+pub use crate::db::REPLICATION_FILE;
+
+/// How often the primary checks for a follower connecting, and its log
+/// for records to send.
+const POLL: Duration = Duration::from_millis(10);
+
+/// How often an idle primary tells its followers it is still there, those
+/// hearing nothing for five times as long connecting again.
+pub const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// How long a follower waits before connecting again to its primary.
+const RECONNECT: Duration = Duration::from_millis(200);
+
+/// How often a follower records the offset it reached, as it is caught up.
+const RECORD_OFFSET: Duration = Duration::from_secs(1);
+
+/// Records are sent in frames of about that many bytes.
+const BATCH_BYTES: usize = 1 << 20;
+
+const FOLLOW: u8 = 0;
+const RESET: u8 = 1;
+const RECORDS: u8 = 2;
+const ACK: u8 = 3;
+
+/// A message between a primary and a follower.
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    /// Sent by a follower once connected, with the history of the log it
+    /// follows and the offset it reached, both 0 if it has none.
+    Follow { id: u64, offset: Lsn },
+    /// Starts a full sync of the follower to the log of history `id`.
+    Reset { id: u64 },
+    /// Records of the log to apply in order, after which the follower
+    /// reached `offset`, 0 if they are not all sent yet.
+    Records { offset: Lsn, records: Vec<Vec<u8>> },
+    /// Sent by a follower once it applied the records up to `offset`.
+    Ack { offset: Lsn },
+}
+
+impl Encode for Message {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Message::Follow { id, offset } => {
+                buf.push(FOLLOW);
+                id.encode(buf);
+                offset.encode(buf);
+            }
+            Message::Reset { id } => {
+                buf.push(RESET);
+                id.encode(buf);
+            }
+            Message::Records { offset, records } => {
+                buf.push(RECORDS);
+                offset.encode(buf);
+                records.len().encode(buf);
+                for record in records {
+                    encode_bytes(record, buf);
+                }
+            }
+            Message::Ack { offset } => {
+                buf.push(ACK);
+                offset.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(input)? {
+            FOLLOW => Ok(Message::Follow {
+                id: u64::decode(input)?,
+                offset: Lsn::decode(input)?,
+            }),
+            RESET => Ok(Message::Reset {
+                id: u64::decode(input)?,
+            }),
+            RECORDS => {
+                let offset = Lsn::decode(input)?;
+                let len = usize::decode(input)?;
+                let mut records = Vec::with_capacity(len.min(input.len()));
+                for _ in 0..len {
+                    records.push(decode_bytes(input)?.to_vec());
+                }
+                Ok(Message::Records { offset, records })
+            }
+            ACK => Ok(Message::Ack {
+                offset: Lsn::decode(input)?,
+            }),
+            _ => Err(invalid_data("unknown replication message")),
+        }
+    }
+}
+
+fn send(out: &mut impl Write, message: &Message) -> io::Result<()> {
+    let mut payload = Vec::new();
+    message.encode(&mut payload);
+    protocol::write_frame(out, &payload)
+}
+
+/// Returns the id of a new history of the log.
+fn new_history() -> u64 {
+    random().max(1)
+}
+
+/// Sends records to a follower, in frames of about [`BATCH_BYTES`].
+struct Batch<'a> {
+    out: BufWriter<&'a TcpStream>,
+    records: Vec<Vec<u8>>,
+    len: usize,
+}
+
+impl Batch<'_> {
+    fn push(&mut self, record: &[u8]) -> io::Result<()> {
+        self.records.push(record.to_vec());
+        self.len += record.len();
+        match self.len >= BATCH_BYTES {
+            true => self.send(0),
+            false => Ok(()),
+        }
+    }
+
+    /// Sends the records pushed, after which the follower reached `offset`,
+    /// if not 0.
+    fn send(&mut self, offset: Lsn) -> io::Result<()> {
+        let records = mem::take(&mut self.records);
+        self.len = 0;
+        send(&mut self.out, &Message::Records { offset, records })?;
+        match offset {
+            0 => Ok(()),
+            _ => self.out.flush(),
+        }
+    }
+}
+
+/// State shared by the threads of a [`Primary`].
+#[derive(Default)]
+struct PrimaryShared {
+    stopped: AtomicBool,
+    /// The connections of the followers, by id, shut down when stopping.
+    followers: Mutex<HashMap<u64, TcpStream>>,
+    next_follower: AtomicU64,
+}
+
+/// Serves the log of a database to its followers, until dropped.
+pub struct Primary {
+    address: SocketAddr,
+    shared: Arc<PrimaryShared>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl Primary {
+    /// Serves the log of `db` to the followers connecting to `address`,
+    /// port 0 picking a free port, on threads of its own. The database
+    /// becomes a primary, see [`REPLICATION_FILE`].
+    ///
+    /// # Returns
+    ///
+    /// [`Error::InMemory`] if the database is not stored on disk,
+    /// [`Error::ReadOnly`] if it follows another primary, [`Error::Io`] if
+    /// binding to the address fails.
+    pub fn start<H, E>(db: Arc<Database<H, E>>, address: &str) -> Result<Primary>
+    where
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        if db.is_follower() {
+            return Err(Error::ReadOnly);
+        }
+        let id = match db.replication_role()? {
+            Some(Role::Primary { id }) => id,
+            // followers promoted by being reopened start a history as well
+            _ => {
+                let id = new_history();
+                db.set_replication_role(Some(Role::Primary { id }))?;
+                id
+            }
+        };
+        let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+        let listener = TcpListener::bind(&addresses[..])?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(PrimaryShared::default());
+        let accepting = shared.clone();
+        let listener = thread::Builder::new()
+            .name("palladiumdb-primary".to_string())
+            .spawn(move || accept(db, id, &accepting, listener))?;
+        Ok(Primary {
+            address,
+            shared,
+            listener: Some(listener),
+        })
+    }
+
+    /// Returns the address followers connect to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the number of followers connected.
+    pub fn followers(&self) -> usize {
+        self.shared.followers.lock().unwrap().len()
+    }
+}
+
+impl Drop for Primary {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+/// Serves the followers connecting to `listener` until stopped.
+fn accept<H, E>(
+    db: Arc<Database<H, E>>,
+    id: u64,
+    shared: &Arc<PrimaryShared>,
+    listener: TcpListener,
+) where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let mut followers = Vec::new();
+    while !shared.stopped.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(POLL);
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let follower = shared.next_follower.fetch_add(1, Ordering::SeqCst);
+        let registered = stream.try_clone().map(|clone| {
+            shared.followers.lock().unwrap().insert(follower, clone);
+        });
+        let (db, serving) = (db.clone(), shared.clone());
+        let spawned = registered.and_then(|_| {
+            thread::Builder::new()
+                .name(format!("palladiumdb-primary-{}", follower))
+                .spawn(move || {
+                    // failures only end the connection
+                    let _ = serve(&db, id, follower, &serving, &stream);
+                    serving.followers.lock().unwrap().remove(&follower);
+                    db.follower_disconnected(follower);
+                })
+        });
+        match spawned {
+            Ok(handle) => followers.push(handle),
+            Err(_) => {
+                shared.followers.lock().unwrap().remove(&follower);
+            }
+        }
+        followers.retain(|follower| !follower.is_finished());
+    }
+    for stream in shared.followers.lock().unwrap().values() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for follower in followers {
+        let _ = follower.join();
+    }
+}
+
+/// Replicates the log of history `id` of `db` to the follower of `stream`,
+/// connected as `follower`, until it disconnects or the primary stops.
+fn serve<H, E>(
+    db: &Database<H, E>,
+    id: u64,
+    follower: u64,
+    shared: &PrimaryShared,
+    stream: &TcpStream,
+) -> Result<()>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let payload = protocol::read_frame(&mut &*stream)?
+        .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+    let offset = match decode_all::<Message>(&payload)? {
+        Message::Follow {
+            id: followed,
+            offset,
+        } if followed == id => Some(offset),
+        Message::Follow { .. } => None,
+        _ => return Err(invalid_data("follower did not follow").into()),
+    };
+    let mut batch = Batch {
+        out: BufWriter::new(stream),
+        records: Vec::new(),
+        len: 0,
+    };
+    let full_sync = |batch: &mut Batch| -> Result<Lsn> {
+        send(&mut batch.out, &Message::Reset { id })?;
+        let offset = db.snapshot_records(&mut |record| batch.push(record))?;
+        batch.send(offset)?;
+        Ok(offset)
+    };
+    // followers ahead of the log were following another primary
+    let logged = db.first_lsn()?..=db.next_lsn()?;
+    let offset = offset.filter(|offset| logged.contains(offset));
+    let address = stream.peer_addr()?;
+    db.follower_acknowledged(follower, address, offset)?;
+    let mut next = match offset {
+        Some(offset) => offset,
+        None => full_sync(&mut batch)?,
+    };
+    let mut input = BufReader::new(stream);
+    let mut sent = Instant::now();
+    while !shared.stopped.load(Ordering::SeqCst) {
+        // acknowledgements are read as they come, without waiting for them
+        while pending(&mut input)? {
+            let payload = match protocol::read_frame(&mut input)? {
+                Some(payload) => payload,
+                None => return Ok(()),
+            };
+            match decode_all::<Message>(&payload)? {
+                Message::Ack { offset } => {
+                    db.follower_acknowledged(follower, address, Some(offset))?
+                }
+                _ => return Err(invalid_data("follower did not acknowledge").into()),
+            }
+        }
+        if db.next_lsn()? <= next {
+            if sent.elapsed() >= HEARTBEAT {
+                batch.send(next)?;
+                sent = Instant::now();
+            }
+            thread::sleep(POLL);
+            continue;
+        }
+        next = match db.log_records(next, &mut |record| batch.push(record)) {
+            Ok(offset) => {
+                batch.send(offset)?;
+                offset
+            }
+            // removed by a checkpoint in the meantime
+            Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => full_sync(&mut batch)?,
+            Err(err) => return Err(err),
+        };
+        sent = Instant::now();
+    }
+    Ok(())
+}
+
+/// Returns whether bytes wait to be read from `input`, or its end, without
+/// waiting for any.
+fn pending(input: &mut BufReader<&TcpStream>) -> io::Result<bool> {
+    if !input.buffer().is_empty() {
+        return Ok(true);
+    }
+    let stream = *input.get_ref();
+    stream.set_nonblocking(true)?;
+    let filled = input.fill_buf().map(|_| true);
+    stream.set_nonblocking(false)?;
+    match filled {
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+        filled => filled,
+    }
+}
+
+/// How a [`Replica`] is doing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplicaStatus {
+    /// Whether the follower is connected to its primary.
+    pub connected: bool,
+    /// The LSN of the next record of the primary to replicate, `None` until
+    /// a full sync completes.
+    pub offset: Option<Lsn>,
+    /// Number of full syncs made since the replica started.
+    pub full_syncs: u64,
+    /// The error the last connection to the primary ended with, if any.
+    pub last_error: Option<String>,
+}
+
+/// State shared by a [`Replica`] and its thread.
+#[derive(Default)]
+struct ReplicaShared {
+    stopped: AtomicBool,
+    /// The connection to the primary, shut down when stopping.
+    stream: Mutex<Option<TcpStream>>,
+    status: Mutex<ReplicaStatus>,
+}
+
+/// Replicates the log of a primary into a follower database, until
+/// dropped or promoted.
+pub struct Replica<H, E> {
+    db: Arc<Database<H, E>>,
+    shared: Arc<ReplicaShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<H, E> Replica<H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    /// Makes `db` follow the primary at `primary`, replicating its log on a
+    /// thread of its own, connecting again whenever the connection is lost.
+    /// The database keeps following once the replica is dropped, until it
+    /// is promoted or opened again.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::InMemory`] if the database is not stored on disk,
+    /// [`Error::ReadOnly`] if it was opened read-only, [`Error::Io`] if its
+    /// [`REPLICATION_FILE`] cannot be read.
+    pub fn start(db: Arc<Database<H, E>>, primary: &str) -> Result<Self> {
+        let position = match db.replication_role()? {
+            Some(Role::Follower { id, offset }) => Some((id, offset)),
+            _ => None,
+        };
+        db.set_follower(true)?;
+        let shared = Arc::new(ReplicaShared::default());
+        shared.status.lock().unwrap().offset = position.map(|(_, offset)| offset);
+        let (following, replicating, primary) = (db.clone(), shared.clone(), primary.to_string());
+        let handle = thread::Builder::new()
+            .name("palladiumdb-replica".to_string())
+            .spawn(move || replicate(&following, &replicating, &primary, position))?;
+        Ok(Replica {
+            db,
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns how the replica is doing.
+    pub fn status(&self) -> ReplicaStatus {
+        self.shared.status.lock().unwrap().clone()
+    }
+
+    /// Stops following the primary, the database taking writes again as a
+    /// primary of a new history.
+    ///
+    /// # Returns
+    ///
+    /// [`Error::Io`] if the role of the database cannot be recorded.
+    pub fn promote(mut self) -> Result<()> {
+        self.stop();
+        let id = new_history();
+        self.db.set_replication_role(Some(Role::Primary { id }))?;
+        self.db.set_follower(false)
+    }
+}
+
+impl<H, E> Replica<H, E> {
+    fn stop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        if let Some(stream) = &*self.shared.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<H, E> Drop for Replica<H, E> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Replicates the log of `primary` into `db` until stopped, from
+/// `position`, the history followed and the offset reached in it, if any.
+fn replicate<H, E>(
+    db: &Database<H, E>,
+    shared: &ReplicaShared,
+    primary: &str,
+    mut position: Option<(u64, Lsn)>,
+) where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    while !shared.stopped.load(Ordering::SeqCst) {
+        let result = follow(db, shared, primary, &mut position);
+        let mut status = shared.status.lock().unwrap();
+        status.connected = false;
+        if let Err(err) = result {
+            status.last_error = Some(err.to_string());
+        }
+        drop(status);
+        let disconnected = Instant::now();
+        while disconnected.elapsed() < RECONNECT && !shared.stopped.load(Ordering::SeqCst) {
+            thread::sleep(POLL);
+        }
+    }
+    if let Some((id, offset)) = position {
+        let _ = db.set_replication_role(Some(Role::Follower { id, offset }));
+    }
+}
+
+/// Replicates the log of `primary` into `db` until the connection ends,
+/// keeping `position` up to date.
+fn follow<H, E>(
+    db: &Database<H, E>,
+    shared: &ReplicaShared,
+    primary: &str,
+    position: &mut Option<(u64, Lsn)>,
+) -> Result<()>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let addresses: Vec<_> = primary.to_socket_addrs()?.collect();
+    let stream = TcpStream::connect(&addresses[..])?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HEARTBEAT * 5))?;
+    *shared.stream.lock().unwrap() = Some(stream.try_clone()?);
+    // stopped while connecting, the stream was not shut down
+    if shared.stopped.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let (mut id, offset) = position.unwrap_or((0, 0));
+    send(&mut &stream, &Message::Follow { id, offset })?;
+    shared.status.lock().unwrap().connected = true;
+
+    let mut input = BufReader::new(&stream);
+    let mut recorded = Some(Instant::now());
+    while let Some(payload) = protocol::read_frame(&mut input)? {
+        match decode_all::<Message>(&payload)? {
+            Message::Reset { id: reset } => {
+                // synced in full again if interrupted
+                db.set_replication_role(None)?;
+                *position = None;
+                recorded = None;
+                id = reset;
+                db.clear_replicated()?;
+                let mut status = shared.status.lock().unwrap();
+                status.offset = None;
+                status.full_syncs += 1;
+            }
+            Message::Records { offset, records } => {
+                for record in &records {
+                    db.apply_replicated(record)?;
+                }
+                if offset == 0 {
+                    continue;
+                }
+                *position = Some((id, offset));
+                shared.status.lock().unwrap().offset = Some(offset);
+                db.caught_up()?;
+                send(&mut &stream, &Message::Ack { offset })?;
+                if recorded.is_none_or(|recorded| recorded.elapsed() >= RECORD_OFFSET) {
+                    db.set_replication_role(Some(Role::Follower { id, offset }))?;
+                    recorded = Some(Instant::now());
+                }
+            }
+            Message::Follow { .. } | Message::Ack { .. } => {
+                return Err(invalid_data("primary followed").into())
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Primary, Replica, HEARTBEAT};
+    use crate::codec::{decode_all, Encode};
+    use crate::db::{Database, Error, ReplicationStats};
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < Duration::from_secs(10), "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_followers_sync_in_full_then_catch_up_until_promoted() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-replication-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let message = Message::Records {
+            offset: 7,
+            records: vec![b"a".to_vec(), Vec::new()],
+        };
+        let mut payload = Vec::new();
+        message.encode(&mut payload);
+        assert_eq!(decode_all::<Message>(&payload).unwrap(), message);
+
+        let db = Arc::new(Database::open(dir.join("primary")).unwrap());
+        let users = db.open_durable::<u64, String>("users").unwrap();
+        let sessions = db.open_durable_ttl::<u64, u64>("sessions").unwrap();
+        for key in 0..100 {
+            users.put(&key, format!("user-{}", key)).unwrap();
+        }
+        sessions.put(&1, 1, Duration::from_secs(3600)).unwrap();
+        db.open_durable::<u64, u64>("dropped").unwrap();
+        assert!(db.drop_map("dropped").unwrap());
+        let primary = Primary::start(db.clone(), "127.0.0.1:0").unwrap();
+        let address = primary.local_addr().to_string();
+
+        // synced in full, then caught up as the primary is written to
+        let follower_dir = dir.join("follower");
+        let follower = Arc::new(Database::open(&follower_dir).unwrap());
+        follower.open_durable::<u64, u64>("stale").unwrap();
+        let replica = Replica::start(follower.clone(), &address).unwrap();
+        assert!(follower.is_follower());
+        wait_for(|| replica.status().offset.is_some());
+        assert_eq!(replica.status().full_syncs, 1);
+        assert_eq!(
+            follower.map_names(),
+            vec!["sessions".to_string(), "users".to_string()]
+        );
+        let replicated = follower.open_durable::<u64, String>("users").unwrap();
+        assert_eq!(replicated.len(), 100);
+        users.put(&100, "user-100".to_string()).unwrap();
+        users.remove(&0).unwrap();
+        db.open_durable::<u64, u64>("orders")
+            .unwrap()
+            .put(&1, 2)
+            .unwrap();
+        wait_for(|| replicated.len() == 100 && replicated.get(&100).is_some());
+        assert_eq!(replicated.get(&0), None);
+        let replicated_sessions = follower.open_durable_ttl::<u64, u64>("sessions").unwrap();
+        assert_eq!(replicated_sessions.get(&1), Some(1));
+        // followers take no writes of their own
+        let err = replicated.put(&1, "mallory".to_string()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(matches!(
+            follower.open_durable::<u64, u64>("local"),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(follower.drop_map("users"), Err(Error::ReadOnly)));
+        wait_for(|| primary.followers() == 1);
+
+        // the primary reports how far behind its followers are, followers
+        // how stale their reads are
+        wait_for(|| match db.stats().unwrap().replication {
+            ReplicationStats::Primary { followers } => {
+                followers.iter().all(|follower| follower.lag == Some(0)) && followers.len() == 1
+            }
+            stats => panic!("unexpected stats {:?}", stats),
+        });
+        match follower.stats().unwrap().replication {
+            ReplicationStats::Follower {
+                offset: Some(_),
+                staleness: Some(staleness),
+            } => assert!(staleness <= HEARTBEAT * 2, "{:?}", staleness),
+            stats => panic!("unexpected stats {:?}", stats),
+        }
+        drop((replica, replicated, replicated_sessions));
+
+        // resumed from the offset reached, once reopened
+        users.put(&101, "user-101".to_string()).unwrap();
+        drop(follower);
+        let follower = Arc::new(Database::open(&follower_dir).unwrap());
+        let replica = Replica::start(follower.clone(), &address).unwrap();
+        let replicated = follower.open_durable::<u64, String>("users").unwrap();
+        wait_for(|| replicated.get(&101).is_some());
+        assert_eq!(replica.status().full_syncs, 0);
+        assert_eq!(replicated.len(), 101);
+        let orders = follower.open_durable::<u64, u64>("orders").unwrap();
+        assert_eq!(orders.get(&1), Some(2));
+        drop((replica, replicated, orders));
+
+        // synced in full again once the log it needs was checkpointed away
+        users.put(&102, "user-102".to_string()).unwrap();
+        db.checkpoint_now().unwrap();
+        let replica = Replica::start(follower.clone(), &address).unwrap();
+        let replicated = follower.open_durable::<u64, String>("users").unwrap();
+        wait_for(|| replica.status().full_syncs == 1 && replica.status().offset.is_some());
+        let replicated = match replicated.get(&102) {
+            Some(_) => replicated,
+            // dropped by the full sync, the handle opened before is stale
+            None => follower.open_durable::<u64, String>("users").unwrap(),
+        };
+        assert_eq!(replicated.get(&102), Some("user-102".to_string()));
+        assert_eq!(replicated.len(), 102);
+
+        // promoted, the follower takes writes and starts a history
+        replica.promote().unwrap();
+        assert!(!follower.is_follower());
+        let replicated = follower.open_durable::<u64, String>("users").unwrap();
+        replicated.put(&103, "user-103".to_string()).unwrap();
+        follower.open_durable::<u64, u64>("local").unwrap();
+        let promoted = Primary::start(follower.clone(), "127.0.0.1:0").unwrap();
+        let other = Arc::new(Database::open(dir.join("other")).unwrap());
+        let replica = Replica::start(other.clone(), &promoted.local_addr().to_string()).unwrap();
+        wait_for(|| replica.status().offset.is_some());
+        let names = other.map_names();
+        assert_eq!(names, vec!["local", "orders", "sessions", "users"]);
+        assert_eq!(
+            other.open_durable::<u64, String>("users").unwrap().len(),
+            103
+        );
+
+        drop((replica, promoted, primary, users, sessions, replicated));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+//! gRPC service of servers built with [`Protocol::Grpc`](super::Protocol::Grpc),
+//! with the `grpc` feature.
+//!
+//! The service is generated by tonic from `proto/palladiumdb.proto`, which
+//! clients in other languages are generated from as well. Rust clients are
+//! in [`proto::palladium_client`], created from a tonic `Channel`.
+//!
+//! Servers given an [`Acl`] authenticate every call by its `authorization`
+//! metadata, holding Basic credentials as HTTP headers do, failing it with
+//! `UNAUTHENTICATED` or `PERMISSION_DENIED`.
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::vec;
+
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::Status;
+
+use self::proto::operation::Operation;
+use self::proto::operation_result::Result as OperationResult;
+use self::proto::palladium_server::{Palladium, PalladiumServer};
+use self::proto::{
+    BatchRequest, BatchResponse, DeleteRequest, DeleteResponse, Entry, GetRequest, GetResponse,
+    PutRequest, PutResponse, ScanRequest,
+};
+use super::acl::{basic_credentials, Acl, Refused, Session};
+use super::socket::Listener;
+use super::{Served, Server, ACCEPT_POLL};
+use crate::db::Error;
+use crate::protocol::{Request, Response};
+
+/// Messages and services generated from `proto/palladiumdb.proto`.
+#[allow(clippy::all, missing_docs)]
+pub mod proto {
+    tonic::include_proto!("palladiumdb.v1");
+}
+
+/// Returns the status a failure of the database is reported with.
+fn status(err: Error) -> Status {
+    let message = err.to_string();
+    match err {
+        Error::TypeMismatch { .. } | Error::InMemory | Error::ReadOnly => {
+            Status::failed_precondition(message)
+        }
+        Error::Closed | Error::AlreadyLocked { .. } => Status::unavailable(message),
+        Error::Io(_) => Status::internal(message),
+    }
+}
+
+/// Returns the status a refused call is failed with.
+fn refused(refused: Refused) -> Status {
+    match refused {
+        Refused::Unauthenticated | Refused::WrongPassword => {
+            Status::unauthenticated(refused.to_string())
+        }
+        Refused::Forbidden(message) => Status::permission_denied(message),
+    }
+}
+
+/// Returns the user and password of the `authorization` metadata of
+/// `request`.
+fn credentials<T>(request: &tonic::Request<T>) -> Credentials {
+    let value = request.metadata().get("authorization")?;
+    basic_credentials(value.to_str().ok()?)
+}
+
+type Credentials = Option<(String, String)>;
+
+fn unexpected(response: Response) -> Status {
+    Status::internal(format!("unexpected response {:?}", response))
+}
+
+/// The service, carrying out requests as the native protocol does.
+struct Service {
+    db: Arc<dyn Served>,
+    acl: Option<Arc<Acl>>,
+}
+
+impl Service {
+    /// Carries out `request` on behalf of the user of `credentials`, on a
+    /// thread allowed to block, writes waiting for the log.
+    async fn execute(
+        &self,
+        credentials: &Credentials,
+        mut request: Request,
+    ) -> Result<Response, Status> {
+        let (db, acl, credentials) = (self.db.clone(), self.acl.clone(), credentials.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut session = Session::new(acl.as_deref());
+            if let Some((user, password)) = credentials {
+                session.authenticate(&user, &password).map_err(refused)?;
+            }
+            let grant = session.authorize(&mut request).map_err(refused)?;
+            Ok(grant.apply(db.execute(request).map_err(status)?))
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+    }
+
+    async fn put(
+        &self,
+        credentials: &Credentials,
+        request: PutRequest,
+    ) -> Result<PutResponse, Status> {
+        let PutRequest {
+            keyspace,
+            key,
+            value,
+        } = request;
+        match self
+            .execute(
+                credentials,
+                Request::Put {
+                    keyspace,
+                    key,
+                    value,
+                },
+            )
+            .await?
+        {
+            Response::Ok => Ok(PutResponse {}),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn delete(
+        &self,
+        credentials: &Credentials,
+        request: DeleteRequest,
+    ) -> Result<DeleteResponse, Status> {
+        let DeleteRequest { keyspace, key } = request;
+        let delete = Request::Delete { keyspace, key };
+        match self.execute(credentials, delete).await? {
+            Response::Deleted(deleted) => Ok(DeleteResponse { deleted }),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Palladium for Service {
+    async fn get(
+        &self,
+        request: tonic::Request<GetRequest>,
+    ) -> Result<tonic::Response<GetResponse>, Status> {
+        let credentials = credentials(&request);
+        let GetRequest { keyspace, key } = request.into_inner();
+        match self
+            .execute(&credentials, Request::Get { keyspace, key })
+            .await?
+        {
+            Response::Value(value) => Ok(tonic::Response::new(GetResponse { value })),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn put(
+        &self,
+        request: tonic::Request<PutRequest>,
+    ) -> Result<tonic::Response<PutResponse>, Status> {
+        Service::put(self, &credentials(&request), request.into_inner())
+            .await
+            .map(tonic::Response::new)
+    }
+
+    async fn delete(
+        &self,
+        request: tonic::Request<DeleteRequest>,
+    ) -> Result<tonic::Response<DeleteResponse>, Status> {
+        Service::delete(self, &credentials(&request), request.into_inner())
+            .await
+            .map(tonic::Response::new)
+    }
+
+    type ScanStream = tokio_stream::Iter<vec::IntoIter<Result<Entry, Status>>>;
+
+    async fn scan(
+        &self,
+        request: tonic::Request<ScanRequest>,
+    ) -> Result<tonic::Response<Self::ScanStream>, Status> {
+        let credentials = credentials(&request);
+        let ScanRequest {
+            keyspace,
+            prefix,
+            limit,
+        } = request.into_inner();
+        let limit = if limit == 0 { u64::MAX } else { limit };
+        let entries = match self
+            .execute(
+                &credentials,
+                Request::Scan {
+                    keyspace,
+                    prefix,
+                    limit,
+                },
+            )
+            .await?
+        {
+            Response::Entries(entries) => entries,
+            response => return Err(unexpected(response)),
+        };
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(key, value)| Ok(Entry { key, value }))
+            .collect();
+        Ok(tonic::Response::new(tokio_stream::iter(entries)))
+    }
+
+    async fn batch(
+        &self,
+        request: tonic::Request<BatchRequest>,
+    ) -> Result<tonic::Response<BatchResponse>, Status> {
+        let credentials = credentials(&request);
+        let mut results = Vec::new();
+        for (index, operation) in request.into_inner().operations.into_iter().enumerate() {
+            let result = match operation.operation {
+                Some(Operation::Put(put)) => Service::put(self, &credentials, put)
+                    .await
+                    .map(OperationResult::Put),
+                Some(Operation::Delete(delete)) => Service::delete(self, &credentials, delete)
+                    .await
+                    .map(OperationResult::Delete),
+                None => Err(Status::invalid_argument("empty operation")),
+            };
+            let result = result.map_err(|status| {
+                let message = format!("operation {}: {}", index, status.message());
+                Status::new(status.code(), message)
+            })?;
+            results.push(proto::OperationResult {
+                result: Some(result),
+            });
+        }
+        Ok(tonic::Response::new(BatchResponse { results }))
+    }
+}
+
+/// Serves the clients of `server` on a runtime with a thread per worker,
+/// until it is shut down.
+pub(super) fn run(server: Server) -> io::Result<()> {
+    let Server {
+        listener,
+        db,
+        workers,
+        shared,
+        ..
+    } = server;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(workers)
+        .thread_name("palladiumdb-grpc")
+        .enable_all()
+        .build()?;
+    let listener = match listener {
+        Listener::Tcp(listener) => listener,
+        #[cfg(unix)]
+        Listener::Unix(_) => unreachable!("gRPC servers are refused Unix sockets"),
+    };
+    runtime.block_on(async move {
+        let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+        let shutdown = async {
+            while !shared.shutting_down.load(Ordering::SeqCst) {
+                tokio::time::sleep(ACCEPT_POLL).await;
+            }
+        };
+        tonic::transport::Server::builder()
+            .add_service(PalladiumServer::new(Service {
+                db,
+                acl: shared.acl.clone(),
+            }))
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::operation::Operation;
+    use super::proto::palladium_client::PalladiumClient;
+    use super::proto::{self, BatchRequest, DeleteRequest, GetRequest, PutRequest, ScanRequest};
+    use crate::db::Database;
+    use crate::server::{Protocol, ServerBuilder};
+    use std::sync::Arc;
+    use std::thread;
+    use tonic::transport::Endpoint;
+    use tonic::Code;
+
+    #[test]
+    fn test_generated_client_gets_puts_scans_and_batches() {
+        let db = Arc::new(Database::new());
+        db.open_map::<u64, u64>("typed").unwrap();
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(2)
+            .protocol(Protocol::Grpc)
+            .bind(db)
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let channel = Endpoint::from_shared(format!("http://{}", address))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            let mut client = PalladiumClient::new(channel);
+            let put = |key: &[u8], value: &[u8]| PutRequest {
+                keyspace: "users".to_string(),
+                key: key.to_vec(),
+                value: value.to_vec(),
+            };
+            let get = |keyspace: &str, key: &[u8]| GetRequest {
+                keyspace: keyspace.to_string(),
+                key: key.to_vec(),
+            };
+
+            client.put(put(b"alice", b"1")).await.unwrap();
+            let value = client.get(get("users", b"alice")).await.unwrap();
+            assert_eq!(value.into_inner().value, Some(b"1".to_vec()));
+            let value = client.get(get("users", b"bob")).await.unwrap();
+            assert_eq!(value.into_inner().value, None);
+            let err = client.get(get("typed", b"bob")).await.unwrap_err();
+            assert_eq!(err.code(), Code::FailedPrecondition);
+
+            let operations = vec![
+                Operation::Put(put(b"bob", b"2")),
+                Operation::Put(put(b"carol", b"3")),
+                Operation::Delete(DeleteRequest {
+                    keyspace: "users".to_string(),
+                    key: b"alice".to_vec(),
+                }),
+            ];
+            let operations = operations
+                .into_iter()
+                .map(|operation| proto::Operation {
+                    operation: Some(operation),
+                })
+                .collect();
+            let batch = client.batch(BatchRequest { operations }).await.unwrap();
+            assert_eq!(batch.into_inner().results.len(), 3);
+
+            let scan = ScanRequest {
+                keyspace: "users".to_string(),
+                prefix: Vec::new(),
+                limit: 0,
+            };
+            let mut entries = client.scan(scan).await.unwrap().into_inner();
+            let mut keys = Vec::new();
+            while let Some(entry) = entries.message().await.unwrap() {
+                keys.push(entry.key);
+            }
+            assert_eq!(keys, vec![b"bob".to_vec(), b"carol".to_vec()]);
+        });
+
+        // the shutdown waits for the connection of the client to close
+        drop(runtime);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,566 @@
+//! HTTP/1.1 front-end of servers built with
+//! [`Protocol::Http`](super::Protocol::Http), taking and answering JSON.
+//!
+//! Keys are taken from the path, percent-decoded, and the keyspace from the
+//! `keyspace` query parameter, [`DEFAULT_KEYSPACE`] if absent. Keys and
+//! values are written as JSON strings if they are valid UTF-8, as arrays
+//! of bytes otherwise, and read back as either.
+//!
+//! Servers given an [`Acl`] authenticate every request by its Basic
+//! `Authorization` header, answering `401 Unauthorized` without valid
+//! credentials and `403 Forbidden` if the user may not carry it out.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use super::acl::{basic_credentials, Acl, Refused, Session};
+use super::{Served, Stream};
+use crate::db::Error;
+use crate::json::{self, FromJson, JsonValue};
+use crate::protocol::resp::read_line;
+use crate::protocol::{Request, Response, MAX_FRAME_LEN};
+
+/// Keyspace of the requests without a `keyspace` query parameter.
+pub const DEFAULT_KEYSPACE: &str = "default";
+
+/// Most header lines read, so that a client sending them endlessly does not
+/// exhaust memory.
+const MAX_HEADERS: usize = 128;
+
+/// A request whose head was read.
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    /// The user and password of the `Authorization` header.
+    credentials: Option<(String, String)>,
+    /// Whether the client asked for the connection to be closed after the
+    /// response.
+    close: bool,
+}
+
+/// A request that cannot be carried out, answered with a status and an
+/// error message.
+struct Failure(u16, String);
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Failure(status, message.into())
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        let status = match err {
+            Error::TypeMismatch { .. } | Error::InMemory | Error::ReadOnly => 409,
+            Error::Closed | Error::AlreadyLocked { .. } => 503,
+            Error::Io(_) => 500,
+        };
+        Failure(status, err.to_string())
+    }
+}
+
+impl From<Refused> for Failure {
+    fn from(refused: Refused) -> Self {
+        let status = match refused {
+            Refused::Unauthenticated | Refused::WrongPassword => 401,
+            Refused::Forbidden(_) => 403,
+        };
+        Failure(status, refused.to_string())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Decodes the `%XX` escapes of `text`, and its `+` into spaces if it is
+/// part of a query.
+fn percent_decode(text: &str, query: bool) -> Result<Vec<u8>, Failure> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        decoded.push(match byte {
+            b'%' => {
+                let hex = [bytes.next(), bytes.next()];
+                let hex = match hex {
+                    [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                hex.ok_or_else(|| Failure::new(400, "invalid percent-encoding"))?
+            }
+            b'+' if query => b' ',
+            byte => byte,
+        });
+    }
+    Ok(decoded)
+}
+
+/// Reads the next request of `input`.
+///
+/// # Returns
+///
+/// `None` if `input` ended before the request, a failure if the request is
+/// malformed, after which the connection is closed.
+fn read_request<R: BufRead>(input: &mut R) -> io::Result<Option<Result<HttpRequest, Failure>>> {
+    let line = match read_line(input)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let line = String::from_utf8_lossy(&line).into_owned();
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method, target, version)
+        }
+        _ => return Ok(Some(Err(Failure::new(400, "malformed request line")))),
+    };
+    let mut close = version == "HTTP/1.0";
+    let (mut len, mut credentials) = (None, None);
+    for count in 0.. {
+        let header = read_line(input)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Ok(Some(Err(Failure::new(400, "too many headers"))));
+        }
+        let header = String::from_utf8_lossy(&header).into_owned();
+        let (name, value) = header.split_once(':').unwrap_or((&header, ""));
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => match value.parse::<usize>() {
+                Ok(value) if value <= MAX_FRAME_LEN => len = Some(value),
+                Ok(_) => return Ok(Some(Err(Failure::new(413, "body too long")))),
+                Err(_) => return Ok(Some(Err(Failure::new(400, "invalid Content-Length")))),
+            },
+            "transfer-encoding" => {
+                return Ok(Some(Err(Failure::new(
+                    411,
+                    "chunked bodies are not supported",
+                ))))
+            }
+            "connection" => close = value.eq_ignore_ascii_case("close"),
+            "authorization" => credentials = basic_credentials(value),
+            _ => {}
+        }
+    }
+    let mut body = vec![0; len.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let name = String::from_utf8_lossy(&percent_decode(name, true)?).into_owned();
+            Ok((name, percent_decode(value, true)?))
+        })
+        .collect::<Result<_, Failure>>();
+    Ok(Some(query.map(|query| HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        body,
+        credentials,
+        close,
+    })))
+}
+
+/// Writes `bytes` as a string if they are valid UTF-8, as bytes otherwise.
+fn bytes_to_json(bytes: Vec<u8>) -> JsonValue {
+    match String::from_utf8(bytes) {
+        Ok(text) => JsonValue::String(text),
+        Err(err) => JsonValue::Array(
+            err.into_bytes()
+                .into_iter()
+                .map(|byte| JsonValue::Number(byte.to_string()))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_bytes(value: JsonValue) -> Result<Vec<u8>, Failure> {
+    match value {
+        JsonValue::String(text) => Ok(text.into_bytes()),
+        value => Vec::from_json(value).map_err(|err| Failure::new(400, err.to_string())),
+    }
+}
+
+/// Removes the member `name` of `object`.
+fn take(object: &mut Vec<(String, JsonValue)>, name: &str) -> Result<JsonValue, Failure> {
+    match object.iter().position(|(member, _)| member == name) {
+        Some(index) => Ok(object.remove(index).1),
+        None => Err(Failure::new(400, format!("missing member {:?}", name))),
+    }
+}
+
+fn object(members: Vec<(&str, JsonValue)>) -> JsonValue {
+    let members = members
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    JsonValue::Object(members)
+}
+
+impl HttpRequest {
+    fn param(&self, name: &str) -> Option<&[u8]> {
+        let (_, value) = self.query.iter().find(|(param, _)| param == name)?;
+        Some(value)
+    }
+
+    fn keyspace(&self) -> String {
+        self.param("keyspace")
+            .map_or(DEFAULT_KEYSPACE.to_string(), |keyspace| {
+                String::from_utf8_lossy(keyspace).into_owned()
+            })
+    }
+
+    /// Parses the body as a JSON object.
+    fn object(&self) -> Result<Vec<(String, JsonValue)>, Failure> {
+        let body = std::str::from_utf8(&self.body)
+            .map_err(|_| Failure::new(400, "body is not valid UTF-8"))?;
+        match json::from_str(body) {
+            Ok(JsonValue::Object(members)) => Ok(members),
+            Ok(_) => Err(Failure::new(400, "body is not a JSON object")),
+            Err(err) => Err(Failure::new(400, err.to_string())),
+        }
+    }
+}
+
+/// Carries out `request` on behalf of the user of `session`.
+fn execute(db: &dyn Served, session: &Session, mut request: Request) -> Result<Response, Failure> {
+    let grant = session.authorize(&mut request)?;
+    Ok(grant.apply(db.execute(request)?))
+}
+
+/// Carries out `request`, answering with a status and a JSON body, if any.
+fn route(
+    db: &dyn Served,
+    session: &Session,
+    request: &HttpRequest,
+) -> Result<(u16, Option<JsonValue>), Failure> {
+    let execute = |request| execute(db, session, request);
+    if let Some(key) = request.path.strip_prefix("/keys/") {
+        let key = percent_decode(key, false)?;
+        let keyspace = request.keyspace();
+        return match request.method.as_str() {
+            "GET" => match execute(Request::Get {
+                keyspace,
+                key: key.clone(),
+            })? {
+                Response::Value(Some(value)) => Ok((
+                    200,
+                    Some(object(vec![
+                        ("key", bytes_to_json(key)),
+                        ("value", bytes_to_json(value)),
+                    ])),
+                )),
+                _ => Err(Failure::new(404, "key not found")),
+            },
+            "PUT" => {
+                let value = json_to_bytes(take(&mut request.object()?, "value")?)?;
+                execute(Request::Put {
+                    keyspace,
+                    key,
+                    value,
+                })?;
+                Ok((204, None))
+            }
+            "DELETE" => match execute(Request::Delete { keyspace, key })? {
+                Response::Deleted(deleted) => Ok((
+                    200,
+                    Some(object(vec![("deleted", JsonValue::Bool(deleted))])),
+                )),
+                response => Err(Failure::new(500, format!("unexpected {:?}", response))),
+            },
+            _ => Err(Failure::new(
+                405,
+                "keys are read with GET, written with PUT and DELETE",
+            )),
+        };
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/scan") => {
+            let limit = match request.param("limit") {
+                Some(limit) => std::str::from_utf8(limit)
+                    .ok()
+                    .and_then(|limit| limit.parse().ok())
+                    .ok_or_else(|| Failure::new(400, "invalid limit"))?,
+                None => u64::MAX,
+            };
+            let scan = Request::Scan {
+                keyspace: request.keyspace(),
+                prefix: request.param("prefix").unwrap_or_default().to_vec(),
+                limit,
+            };
+            match execute(scan)? {
+                Response::Entries(entries) => {
+                    let entries = entries
+                        .into_iter()
+                        .map(|(key, value)| {
+                            object(vec![
+                                ("key", bytes_to_json(key)),
+                                ("value", bytes_to_json(value)),
+                            ])
+                        })
+                        .collect();
+                    Ok((
+                        200,
+                        Some(object(vec![("entries", JsonValue::Array(entries))])),
+                    ))
+                }
+                response => Err(Failure::new(500, format!("unexpected {:?}", response))),
+            }
+        }
+        ("POST", "/batch") => batch(db, session, request),
+        (_, "/scan") | (_, "/batch") => Err(Failure::new(405, "method not allowed")),
+        _ => Err(Failure::new(404, "no such route")),
+    }
+}
+
+/// `POST /batch`, carrying out puts and deletes in order, each one on its
+/// own: those preceding a failed operation stay applied.
+fn batch(
+    db: &dyn Served,
+    session: &Session,
+    request: &HttpRequest,
+) -> Result<(u16, Option<JsonValue>), Failure> {
+    let operations = match take(&mut request.object()?, "operations")? {
+        JsonValue::Array(operations) => operations,
+        _ => return Err(Failure::new(400, "operations must be an array")),
+    };
+    let mut results = Vec::with_capacity(operations.len());
+    for (index, operation) in operations.into_iter().enumerate() {
+        let result =
+            batch_operation(db, session, request, operation).map_err(|Failure(status, err)| {
+                Failure(status, format!("operation {}: {}", index, err))
+            })?;
+        results.push(result);
+    }
+    Ok((
+        200,
+        Some(object(vec![("results", JsonValue::Array(results))])),
+    ))
+}
+
+fn batch_operation(
+    db: &dyn Served,
+    session: &Session,
+    request: &HttpRequest,
+    operation: JsonValue,
+) -> Result<JsonValue, Failure> {
+    let mut operation = match operation {
+        JsonValue::Object(members) => members,
+        _ => return Err(Failure::new(400, "not an object")),
+    };
+    let keyspace = match take(&mut operation, "keyspace") {
+        Ok(JsonValue::String(keyspace)) => keyspace,
+        Ok(_) => return Err(Failure::new(400, "keyspace must be a string")),
+        Err(_) => request.keyspace(),
+    };
+    let key = json_to_bytes(take(&mut operation, "key")?)?;
+    match take(&mut operation, "op")? {
+        JsonValue::String(op) if op == "put" => {
+            let value = json_to_bytes(take(&mut operation, "value")?)?;
+            let put = Request::Put {
+                keyspace,
+                key,
+                value,
+            };
+            execute(db, session, put)?;
+            Ok(object(vec![("op", JsonValue::String(op))]))
+        }
+        JsonValue::String(op) if op == "delete" => {
+            let deleted = matches!(
+                execute(db, session, Request::Delete { keyspace, key })?,
+                Response::Deleted(true)
+            );
+            Ok(object(vec![
+                ("op", JsonValue::String(op)),
+                ("deleted", JsonValue::Bool(deleted)),
+            ]))
+        }
+        _ => Err(Failure::new(400, "op must be \"put\" or \"delete\"")),
+    }
+}
+
+fn write_response<W: Write>(
+    out: &mut W,
+    status: u16,
+    body: Option<JsonValue>,
+    close: bool,
+) -> io::Result<()> {
+    let body = body.map(|body| json::to_string(&body)).unwrap_or_default();
+    write!(out, "HTTP/1.1 {} {}\r\n", status, reason(status))?;
+    if status == 401 {
+        write!(out, "WWW-Authenticate: Basic realm=\"palladiumdb\"\r\n")?;
+    }
+    if !body.is_empty() {
+        write!(out, "Content-Type: application/json\r\n")?;
+    }
+    if close {
+        write!(out, "Connection: close\r\n")?;
+    }
+    write!(out, "Content-Length: {}\r\n\r\n", body.len())?;
+    out.write_all(body.as_bytes())
+}
+
+/// Answers the requests of `stream` until the client asks for the
+/// connection to be closed or disconnects.
+pub(super) fn answer(db: &dyn Served, acl: Option<&Acl>, stream: &Stream) -> io::Result<()> {
+    let mut input = BufReader::new(stream);
+    let mut out = BufWriter::new(stream);
+    while let Some(request) = read_request(&mut input)? {
+        let (result, close) = match request {
+            Ok(request) => {
+                let mut session = Session::new(acl);
+                let authenticated = match &request.credentials {
+                    Some((user, password)) => session.authenticate(user, password),
+                    None => Ok(()),
+                };
+                let result = authenticated.map_err(Failure::from);
+                (
+                    result.and_then(|()| route(db, &session, &request)),
+                    request.close,
+                )
+            }
+            // the rest of a malformed request cannot be told apart from the
+            // next one
+            Err(failure) => (Err(failure), true),
+        };
+        let (status, body) = result.unwrap_or_else(|Failure(status, message)| {
+            (
+                status,
+                Some(object(vec![("error", JsonValue::String(message))])),
+            )
+        });
+        write_response(&mut out, status, body, close)?;
+        out.flush()?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use crate::json::{self, JsonValue};
+    use crate::server::{Protocol, ServerBuilder};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Sends a request, returning the status and body of the response.
+    fn call(stream: &TcpStream, method: &str, target: &str, body: &str) -> (u16, String) {
+        let mut out = stream;
+        write!(
+            out,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut input = BufReader::new(stream);
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut len = 0;
+        loop {
+            line.clear();
+            input.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some(("Content-Length", value)) => len = value.parse().unwrap(),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let mut body = vec![0; len];
+        input.read_exact(&mut body).unwrap();
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn test_rest_api_reads_writes_scans_and_batches() {
+        let db = Arc::new(Database::new());
+        db.open_map::<u64, u64>("typed").unwrap();
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .protocol(Protocol::Http)
+            .bind(db)
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        let stream = TcpStream::connect(address).unwrap();
+
+        assert_eq!(call(&stream, "GET", "/keys/alice", "").0, 404);
+        let (status, _) = call(&stream, "PUT", "/keys/alice", r#"{"value": "1"}"#);
+        assert_eq!(status, 204);
+        let (status, body) = call(&stream, "GET", "/keys/alice", "");
+        assert_eq!(
+            (status, body.as_str()),
+            (200, r#"{"key":"alice","value":"1"}"#)
+        );
+        // escaped keys, byte values
+        call(&stream, "PUT", "/keys/a%2Fb", r#"{"value": [255, 0]}"#);
+        let (_, body) = call(&stream, "GET", "/keys/a%2Fb", "");
+        assert_eq!(body, r#"{"key":"a/b","value":[255,0]}"#);
+
+        let batch = r#"{"operations": [
+            {"op": "put", "key": "bob", "value": "2"},
+            {"op": "delete", "key": "a/b"},
+            {"op": "put", "key": "x", "value": "y", "keyspace": "other"}
+        ]}"#;
+        let (status, body) = call(&stream, "POST", "/batch", batch);
+        assert_eq!(status, 200);
+        let results: JsonValue = json::from_str(&body).unwrap();
+        match results {
+            JsonValue::Object(members) => assert_eq!(members[0].0, "results"),
+            results => panic!("not an object: {:?}", results),
+        }
+        let (status, body) = call(&stream, "GET", "/scan?prefix=", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"entries":[{"key":"alice","value":"1"},{"key":"bob","value":"2"}]}"#
+        );
+        let (_, body) = call(&stream, "GET", "/scan?keyspace=other&limit=1", "");
+        assert_eq!(body, r#"{"entries":[{"key":"x","value":"y"}]}"#);
+
+        let (status, body) = call(&stream, "DELETE", "/keys/alice", "");
+        assert_eq!((status, body.as_str()), (200, r#"{"deleted":true}"#));
+        assert_eq!(
+            call(&stream, "GET", "/keys/alice?keyspace=typed", "").0,
+            409
+        );
+        assert_eq!(call(&stream, "PUT", "/keys/alice", "{").0, 400);
+        assert_eq!(call(&stream, "POST", "/keys/alice", "").0, 405);
+        assert_eq!(call(&stream, "GET", "/nowhere", "").0, 404);
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Statistics of a server, answered to [`Request::Info`] and the `INFO`
+//! command of Redis clients.
+//!
+//! The report holds the `# Server`, `# Stats` and `# Commandstats` sections
+//! of the server, followed by those of the [`Stats`] of its database, or
+//! the section named only, `all` and `default` naming them all:
+//!
+//! ```text
+//! # Server
+//! palladiumdb_version:0.1.0
+//! uptime_in_seconds:42
+//! connected_clients:2
+//! total_connections_received:7
+//! # Stats
+//! total_commands_processed:1024
+//! keyspace_hits:600
+//! keyspace_misses:24
+//! # Commandstats
+//! cmdstat_get:calls=624,avg_ops_per_sec=14.86
+//! cmdstat_set:calls=400,avg_ops_per_sec=9.52
+//! # Database
+//! ...
+//! ```
+//!
+//! Commands are named as the [ACL](super::acl) names them, the requests of
+//! batches and scripts being counted one by one as well.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::Served;
+use crate::db::Result;
+
+#[cfg(doc)]
+use crate::{db::Stats, protocol::Request};
+
+/// Counts of what the clients of a server did.
+pub(super) struct ServerStats {
+    started: Instant,
+    connections: AtomicU64,
+    /// Calls by command.
+    calls: Mutex<BTreeMap<String, u64>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        ServerStats {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            calls: Mutex::new(BTreeMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ServerStats {
+    /// Counts a connection received.
+    pub(super) fn connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a call of `command`.
+    pub(super) fn count(&self, command: &str) {
+        let mut calls = self.calls.lock().unwrap();
+        match calls.get_mut(command) {
+            Some(count) => *count += 1,
+            None => {
+                calls.insert(command.to_string(), 1);
+            }
+        }
+    }
+
+    /// Counts a read of a key, `found` or not.
+    pub(super) fn lookup(&self, found: bool) {
+        let counter = match found {
+            true => &self.hits,
+            false => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the report of the server serving `db` to `connected`
+    /// clients, its `section` only if set.
+    pub(super) fn report(
+        &self,
+        db: &dyn Served,
+        connected: usize,
+        section: Option<&str>,
+    ) -> Result<String> {
+        let snapshot = Snapshot {
+            uptime: self.started.elapsed(),
+            connected,
+            connections: self.connections.load(Ordering::Relaxed),
+            calls: self.calls.lock().unwrap().clone(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        };
+        let report = format!("{}{}", snapshot, db.stats()?);
+        Ok(self::section(&report, section))
+    }
+}
+
+/// The counts of a [`ServerStats`] at a time.
+struct Snapshot {
+    uptime: Duration,
+    connected: usize,
+    connections: u64,
+    calls: BTreeMap<String, u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Server")?;
+        writeln!(f, "palladiumdb_version:{}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "uptime_in_seconds:{}", self.uptime.as_secs())?;
+        writeln!(f, "connected_clients:{}", self.connected)?;
+        writeln!(f, "total_connections_received:{}", self.connections)?;
+        writeln!(f, "# Stats")?;
+        let processed: u64 = self.calls.values().sum();
+        writeln!(f, "total_commands_processed:{}", processed)?;
+        writeln!(f, "keyspace_hits:{}", self.hits)?;
+        writeln!(f, "keyspace_misses:{}", self.misses)?;
+        writeln!(f, "# Commandstats")?;
+        // average rates since the server started, those of servers up for
+        // less than a second being per second anyway
+        let seconds = self.uptime.as_secs_f64().max(1.0);
+        for (command, calls) in &self.calls {
+            let rate = *calls as f64 / seconds;
+            writeln!(
+                f,
+                "cmdstat_{}:calls={},avg_ops_per_sec={:.2}",
+                command, calls, rate
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the section of `report` named `section`, ignoring case, or the
+/// whole report if `None`, `all` or `default`.
+pub(super) fn section(report: &str, section: Option<&str>) -> String {
+    let name = match section {
+        Some(name)
+            if !["all", "default"]
+                .iter()
+                .any(|all| all.eq_ignore_ascii_case(name)) =>
+        {
+            name
+        }
+        _ => return report.to_string(),
+    };
+    let mut selected = false;
+    let mut lines = String::new();
+    for line in report.lines() {
+        if let Some(heading) = line.strip_prefix("# ") {
+            selected = heading.eq_ignore_ascii_case(name);
+        }
+        if selected {
+            lines.push_str(line);
+            lines.push('\n');
+        }
+    }
+    lines
+}
@@ -0,0 +1,517 @@
+//! Rate limits of the requests of clients, see
+//! [`ServerBuilder::rate_limits`](super::ServerBuilder::rate_limits), so
+//! that a runaway client cannot starve the others.
+//!
+//! Limits are token buckets, holding a second of their [`Rate`] at most,
+//! of operations and of bytes per second, applying to the whole server, to
+//! each connection, to each user of the [ACL](super::acl), or to the keys
+//! starting with a prefix across clients:
+//!
+//! ```
+//! use palladiumdb::server::limit::{Rate, RateLimits};
+//!
+//! let limits = RateLimits::new()
+//!     .global(Rate::new().ops_per_sec(100_000))
+//!     .per_user(Rate::new().ops_per_sec(10_000).bytes_per_sec(64 << 20))
+//!     .user("batch", Rate::new().ops_per_sec(1_000))
+//!     .prefix(b"logs:", Rate::new().bytes_per_sec(1 << 20));
+//! ```
+//!
+//! The gets, puts, deletes and scans of native clients are limited, those
+//! of batches and scripts included, as are the commands of Redis clients
+//! reading or writing keys. A request goes through if every limit it falls
+//! under has an operation left, and no bytes owed: the bytes of its keys
+//! and values are counted once it is answered, those of large values
+//! delaying the requests after it. Requests going over a limit are refused
+//! with an error naming the limit and when to retry, as in
+//! `throttled: user batch is limited to 1000 ops/sec, retry in 1 ms`.
+//! Scans fall under the limits of the prefixes of the keys they may return.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{Request, Response};
+
+/// Operations and bytes per second, unlimited unless set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rate {
+    ops_per_sec: Option<u64>,
+    bytes_per_sec: Option<u64>,
+}
+
+impl Rate {
+    /// Creates an unlimited rate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the rate to `ops_per_sec` operations per second, 1 at least.
+    pub fn ops_per_sec(mut self, ops_per_sec: u64) -> Self {
+        self.ops_per_sec = Some(ops_per_sec.max(1));
+        self
+    }
+
+    /// Limits the rate to `bytes_per_sec` bytes of keys and values per
+    /// second, 1 at least.
+    pub fn bytes_per_sec(mut self, bytes_per_sec: u64) -> Self {
+        self.bytes_per_sec = Some(bytes_per_sec.max(1));
+        self
+    }
+}
+
+/// The limits of the requests of the clients of a server, see
+/// [`crate::server::limit`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    global: Rate,
+    per_connection: Rate,
+    per_user: Rate,
+    users: HashMap<String, Rate>,
+    prefixes: Vec<(Vec<u8>, Rate)>,
+}
+
+impl RateLimits {
+    /// Creates limits letting every request through.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the requests of all clients together to `rate`.
+    pub fn global(mut self, rate: Rate) -> Self {
+        self.global = rate;
+        self
+    }
+
+    /// Limits the requests of each connection to `rate`.
+    pub fn per_connection(mut self, rate: Rate) -> Self {
+        self.per_connection = rate;
+        self
+    }
+
+    /// Limits the requests of each user not given a rate of its own, those
+    /// of all its connections together, to `rate`.
+    pub fn per_user(mut self, rate: Rate) -> Self {
+        self.per_user = rate;
+        self
+    }
+
+    /// Limits the requests of the user `name`, those of all its connections
+    /// together, to `rate`.
+    pub fn user(mut self, name: &str, rate: Rate) -> Self {
+        self.users.insert(name.to_string(), rate);
+        self
+    }
+
+    /// Limits the requests of all clients on the keys starting with
+    /// `prefix`, of every keyspace, to `rate`.
+    pub fn prefix(mut self, prefix: &[u8], rate: Rate) -> Self {
+        self.prefixes.push((prefix.to_vec(), rate));
+        self
+    }
+}
+
+/// The keys a request reads or writes, as its limits see it.
+#[derive(Default)]
+pub(super) struct Access {
+    /// The keys named.
+    pub(super) keys: Vec<Vec<u8>>,
+    /// The prefix of the keys scanned, for scans.
+    pub(super) scanned: Option<Vec<u8>>,
+    /// The bytes of the keys and values sent.
+    pub(super) bytes: u64,
+}
+
+impl Access {
+    /// Returns the access of `request`, `None` for those not limited.
+    pub(super) fn of(request: &Request) -> Option<Access> {
+        let (key, bytes) = match request {
+            Request::Get { key, .. } | Request::Delete { key, .. } => (key, key.len()),
+            Request::Put { key, value, .. } => (key, key.len() + value.len()),
+            Request::Scan { prefix, .. } => {
+                return Some(Access {
+                    scanned: Some(prefix.clone()),
+                    bytes: prefix.len() as u64,
+                    ..Access::default()
+                })
+            }
+            _ => return None,
+        };
+        Some(Access {
+            keys: vec![key.clone()],
+            scanned: None,
+            bytes: bytes as u64,
+        })
+    }
+
+    /// Returns whether the access may touch a key starting with `prefix`.
+    fn touches(&self, prefix: &[u8]) -> bool {
+        self.keys.iter().any(|key| key.starts_with(prefix))
+            || self
+                .scanned
+                .as_ref()
+                .is_some_and(|scanned| scanned.starts_with(prefix) || prefix.starts_with(scanned))
+    }
+}
+
+/// What a limit applies to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Scope {
+    Server,
+    Connection(u64),
+    User(String),
+    /// The keys starting with the prefix of that index.
+    Prefix(usize),
+}
+
+/// A token bucket, of operations or bytes, refilled at `rate` per second
+/// up to a second of it.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+    }
+
+    /// Returns how long until the bucket holds `tokens`.
+    fn wait(&self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(((tokens - self.tokens) / self.rate).max(0.0))
+    }
+}
+
+/// The buckets of the operations and bytes of a scope.
+struct Buckets {
+    ops: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+/// Enforces [`RateLimits`] on the requests of the clients of a server.
+pub(super) struct Limiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<Scope, Buckets>>,
+}
+
+impl Limiter {
+    pub(super) fn new(limits: RateLimits) -> Self {
+        Limiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the limits `access` by the connection `id` of `user` falls
+    /// under.
+    fn scopes(&self, id: u64, user: &str, access: &Access) -> Vec<(Scope, Rate)> {
+        let limits = &self.limits;
+        let user_rate = limits.users.get(user).copied();
+        let mut scopes = vec![
+            (Scope::Server, limits.global),
+            (Scope::Connection(id), limits.per_connection),
+            (
+                Scope::User(user.to_string()),
+                user_rate.unwrap_or(limits.per_user),
+            ),
+        ];
+        for (index, (prefix, rate)) in limits.prefixes.iter().enumerate() {
+            if access.touches(prefix) {
+                scopes.push((Scope::Prefix(index), *rate));
+            }
+        }
+        scopes.retain(|(_, rate)| *rate != Rate::default());
+        scopes
+    }
+
+    /// Lets `access` by the connection `id` of `user` through, taking an
+    /// operation from every limit it falls under.
+    ///
+    /// # Returns
+    ///
+    /// The limit it went over, taking nothing then.
+    pub(super) fn admit(&self, id: u64, user: &str, access: &Access) -> Result<(), Throttled> {
+        let scopes = self.scopes(id, user, access);
+        if scopes.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        for (scope, rate) in &scopes {
+            let buckets = buckets.entry(scope.clone()).or_insert_with(|| Buckets {
+                ops: rate.ops_per_sec.map(|rate| Bucket::new(rate, now)),
+                bytes: rate.bytes_per_sec.map(|rate| Bucket::new(rate, now)),
+            });
+            let throttled = |unit, rate, retry_after| Throttled {
+                scope: self.describe(scope),
+                unit,
+                rate,
+                retry_after,
+            };
+            if let (Some(bucket), Some(rate)) = (&mut buckets.ops, rate.ops_per_sec) {
+                bucket.refill(now);
+                if bucket.tokens < 1.0 {
+                    return Err(throttled("ops", rate, bucket.wait(1.0)));
+                }
+            }
+            if let (Some(bucket), Some(rate)) = (&mut buckets.bytes, rate.bytes_per_sec) {
+                bucket.refill(now);
+                if bucket.tokens < 0.0 {
+                    return Err(throttled("bytes", rate, bucket.wait(0.0)));
+                }
+            }
+        }
+        for (scope, _) in &scopes {
+            if let Some(bucket) = buckets.get_mut(scope).and_then(|b| b.ops.as_mut()) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the bytes of `access` by the connection `id` of `user`, and
+    /// the `answered` bytes of its response, against the limits it falls
+    /// under.
+    pub(super) fn charge(&self, id: u64, user: &str, access: &Access, answered: u64) {
+        let scopes = self.scopes(id, user, access);
+        let mut buckets = self.buckets.lock().unwrap();
+        for (scope, _) in scopes {
+            if let Some(bucket) = buckets.get_mut(&scope).and_then(|b| b.bytes.as_mut()) {
+                bucket.tokens -= (access.bytes + answered) as f64;
+            }
+        }
+    }
+
+    /// Forgets the limit of the connection `id`, closed.
+    pub(super) fn disconnect(&self, id: u64) {
+        self.buckets.lock().unwrap().remove(&Scope::Connection(id));
+    }
+
+    fn describe(&self, scope: &Scope) -> String {
+        match scope {
+            Scope::Server => "the server".to_string(),
+            Scope::Connection(_) => "the connection".to_string(),
+            Scope::User(name) => format!("user {}", name),
+            Scope::Prefix(index) => {
+                let prefix = &self.limits.prefixes[*index].0;
+                format!("keys prefixed {:?}", String::from_utf8_lossy(prefix))
+            }
+        }
+    }
+}
+
+/// A request refused for going over a limit, see [`Limiter::admit`].
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Throttled {
+    /// What the limit applies to, as in `user alice`.
+    scope: String,
+    /// `ops` or `bytes`.
+    unit: &'static str,
+    rate: u64,
+    /// How long until a request may go through.
+    retry_after: Duration,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // rounded up, for clients not to retry too early
+        let millis = self.retry_after.as_micros().div_ceil(1000);
+        write!(
+            f,
+            "throttled: {} is limited to {} {}/sec, retry in {} ms",
+            self.scope, self.rate, self.unit, millis
+        )
+    }
+}
+
+/// Returns the bytes of the keys and values of `response`.
+pub(super) fn answered(response: &Response) -> u64 {
+    match response {
+        Response::Value(Some(value)) => value.len() as u64,
+        Response::Entries(entries) => entries
+            .iter()
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rate, RateLimits};
+    use crate::codec::{decode_all, Encode};
+    use crate::db::Database;
+    use crate::protocol::resp::{self, ProtocolVersion, Value};
+    use crate::protocol::{self, Request, Response};
+    use crate::server::acl::Acl;
+    use crate::server::{Protocol, ServerBuilder};
+    use std::io::BufReader;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn native(stream: &TcpStream, request: Request) -> Response {
+        let mut payload = Vec::new();
+        request.encode(&mut payload);
+        let mut out = stream;
+        protocol::write_frame(&mut out, &payload).unwrap();
+        let payload = protocol::read_frame(&mut BufReader::new(stream))
+            .unwrap()
+            .unwrap();
+        decode_all(&payload).unwrap()
+    }
+
+    fn get(key: &[u8]) -> Request {
+        Request::Get {
+            keyspace: "limited".to_string(),
+            key: key.to_vec(),
+        }
+    }
+
+    fn put(key: &[u8], value: &[u8]) -> Request {
+        Request::Put {
+            keyspace: "limited".to_string(),
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Sends `request` on `stream` until it is throttled, `admitted` times
+    /// at least, returning the error.
+    fn throttle(stream: &TcpStream, request: Request, admitted: usize) -> String {
+        for sent in 0..admitted + 10 {
+            match native(stream, request.clone()) {
+                Response::Error(message) if sent >= admitted => return message,
+                Response::Error(message) => panic!("throttled early: {}", message),
+                _ => {}
+            }
+        }
+        panic!("never throttled")
+    }
+
+    #[test]
+    fn test_requests_are_throttled_by_connection_user_prefix_and_server() {
+        let acl = "user default on nopass allkeys +@all\nuser slow on >pw allkeys +@all";
+        let limits = RateLimits::new()
+            .per_connection(Rate::new().ops_per_sec(5))
+            .user("slow", Rate::new().ops_per_sec(3))
+            .prefix(b"big:", Rate::new().bytes_per_sec(100));
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(4)
+            .acl(Arc::new(Acl::parse(acl).unwrap()))
+            .rate_limits(limits)
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        // each connection has its own limit, refilled over time
+        let first = TcpStream::connect(address).unwrap();
+        let message = throttle(&first, get(b"a"), 5);
+        assert!(
+            message.starts_with("throttled: the connection is limited to 5 ops/sec, retry in "),
+            "{}",
+            message
+        );
+        let second = TcpStream::connect(address).unwrap();
+        assert_eq!(native(&second, get(b"a")), Response::Value(None));
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(native(&first, get(b"a")), Response::Value(None));
+        drop((first, second));
+
+        // users are limited across their connections
+        let slow = TcpStream::connect(address).unwrap();
+        let auth = Request::Auth {
+            user: "slow".to_string(),
+            password: "pw".to_string(),
+        };
+        assert_eq!(native(&slow, auth), Response::Ok);
+        let message = throttle(&slow, get(b"a"), 3);
+        assert!(
+            message.contains("user slow is limited to 3 ops/sec"),
+            "{}",
+            message
+        );
+
+        // large values are let through, then delay the requests on their
+        // prefix
+        let writer = TcpStream::connect(address).unwrap();
+        assert_eq!(native(&writer, put(b"big:a", &[0; 1000])), Response::Ok);
+        let message = match native(&writer, put(b"big:b", b"1")) {
+            Response::Error(message) => message,
+            response => panic!("not throttled: {:?}", response),
+        };
+        assert!(
+            message.contains("keys prefixed \"big:\" is limited to 100 bytes/sec"),
+            "{}",
+            message
+        );
+        assert_eq!(native(&writer, put(b"small", b"1")), Response::Ok);
+        let scan = Request::Scan {
+            keyspace: "limited".to_string(),
+            prefix: b"b".to_vec(),
+            limit: 10,
+        };
+        assert!(matches!(native(&writer, scan), Response::Error(_)));
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+
+        // as are the commands of Redis clients reading or writing keys
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(2)
+            .protocol(Protocol::Resp)
+            .rate_limits(RateLimits::new().global(Rate::new().ops_per_sec(2)))
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        let stream = TcpStream::connect(address).unwrap();
+        let redis = |command: &[&str]| {
+            let args = command
+                .iter()
+                .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+                .collect();
+            let mut out = &stream;
+            resp::write_value(&mut out, &Value::Array(args), ProtocolVersion::Resp2).unwrap();
+            resp::read_value(&mut BufReader::new(&stream))
+                .unwrap()
+                .unwrap()
+        };
+        let mut replies = Vec::new();
+        for _ in 0..12 {
+            replies.push(redis(&["SET", "a", "1"]));
+        }
+        assert_eq!(replies[0], Value::Simple("OK".to_string()));
+        let throttled = replies.iter().find_map(|reply| match reply {
+            Value::Error(message) => Some(message.clone()),
+            _ => None,
+        });
+        let throttled = throttled.expect("never throttled");
+        assert!(
+            throttled.starts_with("ERR throttled: the server is limited to 2 ops/sec"),
+            "{}",
+            throttled
+        );
+        assert_eq!(redis(&["PING"]), Value::Simple("PONG".to_string()));
+        drop(stream);
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
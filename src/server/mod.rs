@@ -0,0 +1,1366 @@
+//! Standalone server sharing a [`Database`] across processes over TCP, or
+//! a Unix domain socket, see [`UNIX_SCHEME`], with the `server` feature, see
+//! the `palladiumdb-server` binary.
+//!
+//! Clients speak the binary [protocol](crate::protocol): every request
+//! names the keyspace it reads or writes, whose keys and values are byte
+//! strings. Keyspaces are opened as durable keyspaces if the database is
+//! stored on disk, as plain keyspaces otherwise, when first requested.
+//! Batches of requests are carried out in order, atomic ones isolated from
+//! the requests of the other native clients of the server and their writes
+//! made at once, crashes included, see [`Request::Atomic`], as are Lua
+//! scripts with the `scripting` feature, see `script`. Writes made on the database directly, or through other
+//! servers of it, are not isolated from them.
+//! Servers built with [`Protocol::Resp`] speak to Redis clients instead,
+//! those built with [`Protocol::Http`] take JSON over HTTP, see [`http`],
+//! and those built with `Protocol::Grpc` serve gRPC clients, see `grpc`,
+//! with the `grpc` feature. With the `tls` feature, connections other than
+//! gRPC ones may be encrypted, see `ServerBuilder::tls`. Servers given an
+//! [`Acl`] authenticate their clients, see [`acl`]. Native and Redis clients
+//! publish and subscribe to [channels](crate::pubsub) as well, through the
+//! [`Broker`] of the server, on which servers may publish the writes of
+//! their clients as well, see [`notify`]. Native clients may have the keys
+//! they read tracked, to cache their values until told they were written,
+//! see [`tracking`]. The requests of native and Redis clients may be rate
+//! limited, see [`limit`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::iter;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::codec::{decode_all, Encode};
+use crate::db::{Database, Error, Result, Stats};
+use crate::protocol::{self, Request, Response};
+use crate::pubsub::{Broker, Subscription};
+use crate::storage::StorageEngine;
+#[cfg(feature = "tls")]
+use crate::tls::ServerTls;
+use acl::{Acl, Refused, Session};
+use info::ServerStats;
+use limit::{Access, Limiter, RateLimits};
+use notify::{KeyspaceEvents, Notifying};
+use socket::{Listener, Socket};
+use tracking::Tracking;
+
+pub mod acl;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+mod info;
+pub mod limit;
+pub mod notify;
+mod resp;
+#[cfg(feature = "scripting")]
+pub mod script;
+mod socket;
+pub mod tracking;
+
+/// Address servers bind to, unless [`ServerBuilder::bind_address`] sets
+/// another one.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7380";
+
+/// The prefix of the addresses naming the path of a Unix domain socket, as
+/// in `unix:///run/palladiumdb.sock`, see [`ServerBuilder::bind_address`].
+pub const UNIX_SCHEME: &str = "unix://";
+
+/// How often the listener checks for a shutdown while no client connects.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// How often connections subscribed to channels check for messages while
+/// no request comes.
+const SUBSCRIBED_POLL: Duration = Duration::from_millis(10);
+
+/// Bytes written by a key value pair of a scan.
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// A key along with the name of its keyspace.
+type KeyspaceKey = (String, Vec<u8>);
+
+/// Writes made as a whole, each the value a key of a keyspace is put to, or
+/// `None` for the key to be removed.
+type Writes = Vec<(String, Vec<u8>, Option<Vec<u8>>)>;
+
+/// A database served, with its key and value types erased.
+trait Served: Send + Sync {
+    /// Carries out `request` on the keyspace it names.
+    fn execute(&self, request: Request) -> Result<Response>;
+
+    /// Returns the keyspace `name` as Redis clients see it.
+    fn resp_keyspace(&self, name: &str) -> Result<Arc<dyn resp::Keyspace>>;
+
+    /// Returns the statistics of the database.
+    fn stats(&self) -> Result<Stats>;
+
+    /// Makes `writes` as a whole, logged as a single record if the database
+    /// is stored on disk, so that they survive a crash together or not at
+    /// all.
+    fn write_atomically(&self, writes: Writes) -> Result<()>;
+}
+
+impl<H, E> Served for Database<H, E>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    fn execute(&self, request: Request) -> Result<Response> {
+        execute(self, request)
+    }
+
+    fn resp_keyspace(&self, name: &str) -> Result<Arc<dyn resp::Keyspace>> {
+        Ok(match self.recovery_report() {
+            Some(_) => self.open_durable_ttl::<Vec<u8>, Vec<u8>>(name)?,
+            None => self.open_map::<Vec<u8>, (Vec<u8>, u64)>(name)?,
+        })
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        Database::stats(self)
+    }
+
+    fn write_atomically(&self, writes: Writes) -> Result<()> {
+        Database::write_atomically(self, writes)
+    }
+}
+
+/// Carries out `request` on the keyspace of `db` it names, as a server
+/// would, for tools working on a database without serving it.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::Database;
+/// use palladiumdb::protocol::{Request, Response};
+/// use palladiumdb::server;
+///
+/// let db = Database::new();
+/// let get = Request::Get {
+///     keyspace: "users".to_string(),
+///     key: b"alice".to_vec(),
+/// };
+/// assert_eq!(server::execute(&db, get).unwrap(), Response::Value(None));
+/// ```
+pub fn execute<H, E>(db: &Database<H, E>, request: Request) -> Result<Response>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    E: StorageEngine + 'static,
+{
+    let durable = db.recovery_report().is_some();
+    // followers create no keyspace, the primary having written to none
+    let missing = |keyspace: &str| db.is_follower() && !db.contains_map(keyspace);
+    Ok(match request {
+        Request::Get { keyspace, .. } if missing(&keyspace) => Response::Value(None),
+        Request::Scan { keyspace, .. } if missing(&keyspace) => Response::Entries(Vec::new()),
+        Request::Get { keyspace, key } => Response::Value(match durable {
+            true => db.open_durable(&keyspace)?.get(&key),
+            false => db.open_map(&keyspace)?.get(&key),
+        }),
+        Request::Put {
+            keyspace,
+            key,
+            value,
+        } => {
+            match durable {
+                true => db.open_durable(&keyspace)?.put(&key, value)?,
+                false => db.open_map(&keyspace)?.put(&key, value),
+            }
+            Response::Ok
+        }
+        Request::Delete { keyspace, key } => Response::Deleted(
+            match durable {
+                true => db
+                    .open_durable::<Vec<u8>, Vec<u8>>(&keyspace)?
+                    .remove(&key)?,
+                false => db.open_map::<Vec<u8>, Vec<u8>>(&keyspace)?.remove(&key),
+            }
+            .is_some(),
+        ),
+        // connections authenticate with the server, not the database, and
+        // no connection subscribes to a database not served
+        Request::Auth { .. } => Response::Ok,
+        Request::Publish { .. } => Response::Published(0),
+        Request::Subscribe { .. } | Request::Unsubscribe { .. } => {
+            Response::Error("subscriptions are served by servers only".to_string())
+        }
+        Request::Tracking { .. } => Response::Error("keys are tracked by servers only".to_string()),
+        // requests are carried out one at a time here, atomic batches and
+        // scripts only need their writes made as a whole
+        Request::Batch { requests } => execute_batch(db, requests, false, |served, request| {
+            served
+                .execute(request)
+                .unwrap_or_else(|err| Response::Error(err.to_string()))
+        }),
+        Request::Atomic { requests } => execute_batch(db, requests, true, |served, request| {
+            served
+                .execute(request)
+                .unwrap_or_else(|err| Response::Error(err.to_string()))
+        }),
+        Request::Eval { script, args } => eval(db, &script, args, |served, request| {
+            served
+                .execute(request)
+                .unwrap_or_else(|err| Response::Error(err.to_string()))
+        }),
+        Request::Info { section } => {
+            let stats = db.stats()?.to_string();
+            Response::Value(Some(info::section(&stats, section.as_deref()).into_bytes()))
+        }
+        Request::Scan {
+            keyspace,
+            prefix,
+            limit,
+        } => {
+            let matches = |(key, _): &Entry| key.starts_with(&prefix);
+            let mut entries: Vec<Entry> = match durable {
+                true => db
+                    .open_durable(&keyspace)?
+                    .map()
+                    .iter()
+                    .filter(matches)
+                    .collect(),
+                false => db.open_map(&keyspace)?.iter().filter(matches).collect(),
+            };
+            entries.sort_unstable();
+            entries.truncate(usize::try_from(limit).unwrap_or(usize::MAX));
+            Response::Entries(entries)
+        }
+    })
+}
+
+/// Carries out the requests of a batch through `execute`, on `db`, or on
+/// the writes staged by an atomic one, made once every request is carried
+/// out, see [`Staged`].
+fn execute_batch(
+    db: &dyn Served,
+    requests: Vec<Request>,
+    atomic: bool,
+    mut execute: impl FnMut(&dyn Served, Request) -> Response,
+) -> Response {
+    let staged = Staged::new(db);
+    let served: &dyn Served = match atomic {
+        true => &staged,
+        false => db,
+    };
+    let mut responses = Vec::with_capacity(requests.len());
+    for (i, request) in requests.into_iter().enumerate() {
+        let response = match request {
+            Request::Get { .. }
+            | Request::Put { .. }
+            | Request::Delete { .. }
+            | Request::Scan { .. } => execute(served, request),
+            _ => Response::Error("only requests of keyspaces are batched".to_string()),
+        };
+        match response {
+            Response::Error(message) if atomic => {
+                let message = format!("request {} failed, nothing was written: {}", i, message);
+                return Response::Error(message);
+            }
+            response => responses.push(response),
+        }
+    }
+    match staged.commit() {
+        Ok(()) => Response::Batch(responses),
+        Err(err) => Response::Error(format!("writing the batch failed: {}", err)),
+    }
+}
+
+/// Runs `script` as a whole, carrying out its requests through `execute`
+/// on the writes it stages, made once it succeeds.
+fn eval(
+    db: &dyn Served,
+    script: &str,
+    args: Vec<Vec<u8>>,
+    mut execute: impl FnMut(&dyn Served, Request) -> Response,
+) -> Response {
+    #[cfg(feature = "scripting")]
+    {
+        let staged = Staged::new(db);
+        let response = script::eval(script, args, &mut |request| execute(&staged, request));
+        match response {
+            Response::Error(message) => Response::Error(message),
+            response => match staged.commit() {
+                Ok(()) => response,
+                Err(err) => Response::Error(format!("writing the script failed: {}", err)),
+            },
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    {
+        let _ = (db, script, args, &mut execute);
+        Response::Error("built without the scripting feature".to_string())
+    }
+}
+
+/// A database whose writes are staged, for an atomic batch or script, to
+/// be made as a whole once it succeeds. Reads see the writes staged.
+struct Staged<'a> {
+    db: &'a dyn Served,
+    /// The values the keys written are to map to, `None` for those removed.
+    writes: Mutex<BTreeMap<KeyspaceKey, Option<Vec<u8>>>>,
+}
+
+impl<'a> Staged<'a> {
+    fn new(db: &'a dyn Served) -> Self {
+        Staged {
+            db,
+            writes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the value of `key` in `keyspace`, staged or not.
+    fn get(&self, keyspace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let staged = (keyspace.to_string(), key.to_vec());
+        if let Some(value) = self.writes.lock().unwrap().get(&staged) {
+            return Ok(value.clone());
+        }
+        let get = Request::Get {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        match self.db.execute(get)? {
+            Response::Value(value) => Ok(value),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Makes the writes staged, if any.
+    fn commit(self) -> Result<()> {
+        let writes = self.writes.into_inner().unwrap();
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let writes = writes
+            .into_iter()
+            .map(|((keyspace, key), value)| (keyspace, key, value))
+            .collect();
+        self.db.write_atomically(writes)
+    }
+}
+
+impl Served for Staged<'_> {
+    fn execute(&self, request: Request) -> Result<Response> {
+        Ok(match request {
+            Request::Get { keyspace, key } => Response::Value(self.get(&keyspace, &key)?),
+            // read first, for the keyspace to be checked
+            Request::Put {
+                keyspace,
+                key,
+                value,
+            } => {
+                self.get(&keyspace, &key)?;
+                let mut writes = self.writes.lock().unwrap();
+                writes.insert((keyspace, key), Some(value));
+                Response::Ok
+            }
+            Request::Delete { keyspace, key } => {
+                let deleted = self.get(&keyspace, &key)?.is_some();
+                let mut writes = self.writes.lock().unwrap();
+                writes.insert((keyspace, key), None);
+                Response::Deleted(deleted)
+            }
+            Request::Scan {
+                keyspace,
+                prefix,
+                limit,
+            } => {
+                let scan = Request::Scan {
+                    keyspace: keyspace.clone(),
+                    prefix: prefix.clone(),
+                    limit: u64::MAX,
+                };
+                let mut entries: BTreeMap<_, _> = match self.db.execute(scan)? {
+                    Response::Entries(entries) => entries.into_iter().collect(),
+                    response => return Err(unexpected(response)),
+                };
+                let writes = self.writes.lock().unwrap();
+                let staged = writes
+                    .range((keyspace.clone(), prefix.clone())..)
+                    .take_while(|((name, key), _)| *name == keyspace && key.starts_with(&prefix));
+                for ((_, key), value) in staged {
+                    match value {
+                        Some(value) => entries.insert(key.clone(), value.clone()),
+                        None => entries.remove(key),
+                    };
+                }
+                let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+                Response::Entries(entries.into_iter().take(limit).collect())
+            }
+            request => self.db.execute(request)?,
+        })
+    }
+
+    fn resp_keyspace(&self, name: &str) -> Result<Arc<dyn resp::Keyspace>> {
+        self.db.resp_keyspace(name)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.db.stats()
+    }
+
+    fn write_atomically(&self, writes: Writes) -> Result<()> {
+        self.db.write_atomically(writes)
+    }
+}
+
+/// Returns the error of a response not answering the request it was
+/// given.
+fn unexpected(response: Response) -> Error {
+    let message = format!("unexpected response {:?}", response);
+    io::Error::new(ErrorKind::InvalidData, message).into()
+}
+
+/// The protocol clients of a [`Server`] speak.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// The binary [protocol](crate::protocol) of palladiumdb.
+    Native,
+    /// The Redis serialization [protocol](crate::protocol::resp), for
+    /// `GET`, `SET`, `DEL`, `EXPIRE`, `INCR` and `SCAN` among others.
+    Resp,
+    /// HTTP/1.1 with JSON bodies, for `GET`, `PUT` and `DELETE` on
+    /// `/keys/{key}`, `GET /scan?prefix=` and `POST /batch`, see [`http`].
+    Http,
+    /// gRPC, over HTTP/2, see [`grpc`].
+    #[cfg(feature = "grpc")]
+    Grpc,
+}
+
+/// Configures and starts a [`Server`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::db::Database;
+/// use palladiumdb::server::ServerBuilder;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let server = ServerBuilder::new()
+///     .bind_address("127.0.0.1:0")
+///     .workers(4)
+///     .bind(Arc::new(Database::new()))
+///     .unwrap();
+/// let shutdown = server.shutdown_handle();
+/// let running = thread::spawn(move || server.run());
+///
+/// shutdown.shutdown();
+/// running.join().unwrap().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServerBuilder {
+    bind_address: String,
+    unix_socket_mode: Option<u32>,
+    workers: usize,
+    protocol: Protocol,
+    acl: Option<Arc<Acl>>,
+    broker: Option<Arc<Broker>>,
+    keyspace_events: Option<KeyspaceEvents>,
+    rate_limits: Option<RateLimits>,
+    #[cfg(feature = "tls")]
+    tls: Option<ServerTls>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    /// Creates a builder binding to [`DEFAULT_ADDRESS`], with a worker
+    /// thread per CPU.
+    pub fn new() -> Self {
+        ServerBuilder {
+            bind_address: DEFAULT_ADDRESS.to_string(),
+            unix_socket_mode: None,
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            protocol: Protocol::Native,
+            acl: None,
+            broker: None,
+            keyspace_events: None,
+            rate_limits: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Sets the address the server listens on, port 0 picking a free port,
+    /// or the path of the Unix domain socket it listens on after
+    /// [`UNIX_SCHEME`], on Unix. The file of the socket is removed once the
+    /// server stops, and taken over if no server listens on it.
+    pub fn bind_address(mut self, bind_address: &str) -> Self {
+        self.bind_address = bind_address.to_string();
+        self
+    }
+
+    /// Gives the file of the Unix domain socket the server listens on the
+    /// permissions `mode`, as in `0o660` for its owner and group only to
+    /// connect, rather than those the umask of the process leaves.
+    #[cfg(unix)]
+    pub fn unix_socket_mode(mut self, mode: u32) -> Self {
+        self.unix_socket_mode = Some(mode);
+        self
+    }
+
+    /// Sets the number of threads serving connections, each one serving a
+    /// connection at a time, so that as many clients are served at once.
+    /// Clients connecting beyond that wait for a connection to close.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets the protocol clients speak, [`Protocol::Native`] by default.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Authenticates clients as the users of `acl`, limiting them to what
+    /// their rules allow, see [`acl`]. Changes to `acl` apply at once.
+    pub fn acl(mut self, acl: Arc<Acl>) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Publishes and subscribes to channels on behalf of clients through
+    /// `broker`, shared with the application, rather than through a broker
+    /// of the server's own.
+    pub fn broker(mut self, broker: Arc<Broker>) -> Self {
+        self.broker = Some(broker);
+        self
+    }
+
+    /// Publishes the writes of clients on the channels of the broker,
+    /// carrying what `events` says, see [`notify`]. Writes are not notified
+    /// by default.
+    pub fn keyspace_events(mut self, events: KeyspaceEvents) -> Self {
+        self.keyspace_events = Some(events);
+        self
+    }
+
+    /// Limits the requests of native and Redis clients to `limits`, see
+    /// [`limit`]. Requests are not limited by default.
+    pub fn rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limits = Some(limits);
+        self
+    }
+
+    /// Encrypts connections with `tls`, with the `tls` feature, clients
+    /// speaking plaintext being refused. gRPC servers do not support it.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Binds the server to its address, serving `db` once
+    /// [`Server::run`] is called.
+    ///
+    /// # Returns
+    ///
+    /// The error of binding to the address, or one of kind `Unsupported` if
+    /// a gRPC server is given TLS or a Unix socket.
+    pub fn bind<H, E>(self, db: Arc<Database<H, E>>) -> io::Result<Server>
+    where
+        H: BuildHasher + Clone + Send + Sync + 'static,
+        E: StorageEngine + 'static,
+    {
+        #[cfg(all(feature = "grpc", feature = "tls"))]
+        if self.protocol == Protocol::Grpc && self.tls.is_some() {
+            let message = "gRPC servers do not support TLS";
+            return Err(io::Error::new(ErrorKind::Unsupported, message));
+        }
+        #[cfg(feature = "grpc")]
+        if self.protocol == Protocol::Grpc && self.bind_address.starts_with(UNIX_SCHEME) {
+            let message = "gRPC servers do not support Unix sockets";
+            return Err(io::Error::new(ErrorKind::Unsupported, message));
+        }
+        let listener = Listener::bind(&self.bind_address, self.unix_socket_mode)?;
+        let broker = self.broker.unwrap_or_default();
+        let db: Arc<dyn Served> = match self.keyspace_events {
+            Some(events) => Arc::new(Notifying::new(db, broker.clone(), events)),
+            None => db,
+        };
+        Ok(Server {
+            listener,
+            db,
+            workers: self.workers,
+            protocol: self.protocol,
+            shared: Arc::new(Shared {
+                acl: self.acl,
+                broker,
+                limiter: self.rate_limits.map(Limiter::new),
+                #[cfg(feature = "tls")]
+                tls: self.tls,
+                ..Shared::default()
+            }),
+        })
+    }
+}
+
+/// State shared by the threads of a server and its [`ShutdownHandle`]s.
+#[derive(Default)]
+struct Shared {
+    shutting_down: AtomicBool,
+    /// The connections being served, by id, shut down for reading when the
+    /// server shuts down.
+    connections: Mutex<HashMap<u64, Socket>>,
+    next_connection: AtomicU64,
+    /// Serializes the writes of Redis clients.
+    resp_writes: Mutex<()>,
+    /// Held exclusively by atomic batches and scripts, shared by the other
+    /// requests of native clients, so that none is carried out in between.
+    atomic: RwLock<()>,
+    /// Authenticates the clients, if set.
+    acl: Option<Arc<Acl>>,
+    /// Publishes and subscribes on behalf of clients.
+    broker: Arc<Broker>,
+    /// Encrypts the connections, if set.
+    #[cfg(feature = "tls")]
+    tls: Option<ServerTls>,
+    /// Counts what the clients did, see `info`.
+    stats: ServerStats,
+    /// The keys read by the native clients tracking them.
+    tracking: Tracking,
+    /// Limits the requests of the clients, if set.
+    limiter: Option<Limiter>,
+}
+
+/// Server sharing a [`Database`] over TCP or a Unix socket, see
+/// [`crate::server`].
+pub struct Server {
+    listener: Listener,
+    db: Arc<dyn Served>,
+    workers: usize,
+    protocol: Protocol,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// Returns the address the server listens on.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `Unsupported` for servers listening on a Unix
+    /// socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a handle shutting the server down.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Serves clients until the server is shut down through a
+    /// [`ShutdownHandle`], then waits for the requests in flight to be
+    /// answered, after which no request reaches the database anymore and it
+    /// can be closed, see [`Database::close`].
+    ///
+    /// # Returns
+    ///
+    /// The error of accepting connections, which shuts the server down as
+    /// well.
+    pub fn run(self) -> io::Result<()> {
+        #[cfg(feature = "grpc")]
+        if self.protocol == Protocol::Grpc {
+            return grpc::run(self);
+        }
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..self.workers)
+            .map(|worker| self.spawn_worker(worker, receiver.clone()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let result = loop {
+            if self.shared.shutting_down.load(Ordering::SeqCst) {
+                break Ok(());
+            }
+            match self.listener.accept() {
+                Ok(stream) => {
+                    // waits for a worker, requests being answered in order
+                    let _ = sender.send(stream);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+        // connections accepted but not served yet are closed
+        drop(sender);
+        self.shared.shut_down();
+        for worker in workers {
+            let _ = worker.join();
+        }
+        result
+    }
+
+    fn spawn_worker(
+        &self,
+        worker: usize,
+        receiver: Arc<Mutex<Receiver<Socket>>>,
+    ) -> io::Result<JoinHandle<()>> {
+        let (db, shared, protocol) = (self.db.clone(), self.shared.clone(), self.protocol);
+        thread::Builder::new()
+            .name(format!("palladiumdb-server-{}", worker))
+            .spawn(move || loop {
+                let stream = match receiver.lock().unwrap().recv() {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                // failures only end the connection
+                let _ = serve(&*db, &shared, protocol, stream);
+            })
+    }
+}
+
+/// Answers the requests of `stream` until the client disconnects or the
+/// server shuts down.
+fn serve(db: &dyn Served, shared: &Shared, protocol: Protocol, stream: Socket) -> io::Result<()> {
+    stream.set_blocking()?;
+    let id = shared.next_connection.fetch_add(1, Ordering::SeqCst);
+    shared.stats.connection();
+    shared
+        .connections
+        .lock()
+        .unwrap()
+        .insert(id, stream.try_clone()?);
+    #[cfg(feature = "tls")]
+    let stream = match &shared.tls {
+        Some(tls) => {
+            let connection = rustls::ServerConnection::new(tls.config().clone());
+            let connection = connection.map_err(io::Error::other)?;
+            Stream::Tls(Box::new(Mutex::new(rustls::StreamOwned::new(
+                connection, stream,
+            ))))
+        }
+        None => Stream::Plain(stream),
+    };
+    #[cfg(not(feature = "tls"))]
+    let stream = Stream::Plain(stream);
+    // registered before checking, so that the shutdown either sees the
+    // connection or is seen here
+    let result = match (shared.shutting_down.load(Ordering::SeqCst), protocol) {
+        (true, _) => Ok(()),
+        (false, Protocol::Native) => answer(db, shared, id, &stream),
+        (false, Protocol::Resp) => resp::answer(db, shared, id, &stream),
+        (false, Protocol::Http) => http::answer(db, shared.acl.as_deref(), &stream),
+        #[cfg(feature = "grpc")]
+        (false, Protocol::Grpc) => unreachable!("gRPC connections are served by tonic"),
+    };
+    shared.connections.lock().unwrap().remove(&id);
+    shared.tracking.disable(id);
+    if let Some(limiter) = &shared.limiter {
+        limiter.disconnect(id);
+    }
+    stream.close();
+    result
+}
+
+/// State of the connection of a native client.
+struct Connection<'a> {
+    id: u64,
+    session: Session<'a>,
+    subscriptions: Vec<Subscription>,
+    /// The keys read invalidated, while tracking them.
+    invalidations: Option<Receiver<(String, Vec<u8>)>>,
+}
+
+fn answer(db: &dyn Served, shared: &Shared, id: u64, stream: &Stream) -> io::Result<()> {
+    let mut connection = Connection {
+        id,
+        session: Session::new(shared.acl.as_deref()),
+        subscriptions: Vec::new(),
+        invalidations: None,
+    };
+    let mut input = BufReader::new(stream);
+    let mut out = BufWriter::new(stream);
+    let mut buf = Vec::new();
+    let mut send = |out: &mut BufWriter<&Stream>, response: &Response| {
+        buf.clear();
+        response.encode(&mut buf);
+        protocol::write_frame(out, &buf)
+    };
+    loop {
+        // messages and invalidations are sent while no request waits
+        while !connection.subscriptions.is_empty() || connection.invalidations.is_some() {
+            let messages = connection
+                .subscriptions
+                .iter()
+                .flat_map(|subscription: &Subscription| {
+                    iter::from_fn(move || subscription.try_recv())
+                })
+                .map(|message| Response::Message {
+                    channel: message.channel,
+                    payload: message.payload,
+                });
+            let invalidations = connection
+                .invalidations
+                .iter()
+                .flat_map(|invalidations| invalidations.try_iter())
+                .map(|(keyspace, key)| Response::Invalidate { keyspace, key });
+            for pushed in messages.chain(invalidations) {
+                send(&mut out, &pushed)?;
+            }
+            out.flush()?;
+            if readable(&mut input, SUBSCRIBED_POLL)? {
+                break;
+            }
+        }
+        let payload = match protocol::read_frame(&mut input)? {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let response = match decode_all::<Request>(&payload) {
+            Ok(request) => respond(db, shared, &mut connection, request),
+            Err(err) => Response::Error(err.to_string()),
+        };
+        send(&mut out, &response)?;
+        // the responses of pipelined requests are written at once
+        if input.buffer().is_empty() {
+            out.flush()?;
+        }
+    }
+}
+
+/// Answers `request` on behalf of the user of `connection`, publishing and
+/// subscribing through the broker of the server.
+fn respond(
+    db: &dyn Served,
+    shared: &Shared,
+    connection: &mut Connection,
+    request: Request,
+) -> Response {
+    let refused = |refused: Refused| Response::Error(refused.to_string());
+    shared.stats.count(acl::command_of(&request));
+    match request {
+        Request::Publish { channel, payload } => match connection.session.check("publish", &[]) {
+            Ok(_) => Response::Published(shared.broker.publish(&channel, &payload) as u64),
+            Err(err) => refused(err),
+        },
+        Request::Subscribe { pattern } => match connection.session.check("subscribe", &[]) {
+            Ok(_) => {
+                connection
+                    .subscriptions
+                    .push(shared.broker.subscribe(&pattern));
+                Response::Ok
+            }
+            Err(err) => refused(err),
+        },
+        Request::Unsubscribe { pattern } => match connection.session.check("subscribe", &[]) {
+            Ok(_) => {
+                connection
+                    .subscriptions
+                    .retain(|subscription| subscription.pattern() != pattern);
+                Response::Ok
+            }
+            Err(err) => refused(err),
+        },
+        Request::Batch { requests } => {
+            let _shared = shared.atomic.read().unwrap();
+            execute_batch(db, requests, false, |served, request| {
+                shared.stats.count(acl::command_of(&request));
+                carry_out(served, shared, connection, request)
+            })
+        }
+        Request::Atomic { requests } => {
+            let _exclusive = shared.atomic.write().unwrap();
+            execute_batch(db, requests, true, |served, request| {
+                shared.stats.count(acl::command_of(&request));
+                carry_out(served, shared, connection, request)
+            })
+        }
+        Request::Eval { script, args } => match connection.session.check("eval", &[]) {
+            Ok(_) => {
+                let _exclusive = shared.atomic.write().unwrap();
+                eval(db, &script, args, |served, request| {
+                    shared.stats.count(acl::command_of(&request));
+                    carry_out(served, shared, connection, request)
+                })
+            }
+            Err(err) => refused(err),
+        },
+        Request::Info { section } => match connection.session.check("info", &[]) {
+            Ok(_) => {
+                let connected = shared.connections.lock().unwrap().len();
+                match shared.stats.report(db, connected, section.as_deref()) {
+                    Ok(report) => Response::Value(Some(report.into_bytes())),
+                    Err(err) => Response::Error(err.to_string()),
+                }
+            }
+            Err(err) => refused(err),
+        },
+        Request::Tracking { enabled } => {
+            connection.invalidations = match enabled {
+                true => Some(shared.tracking.enable(connection.id)),
+                false => {
+                    shared.tracking.disable(connection.id);
+                    None
+                }
+            };
+            Response::Ok
+        }
+        request => {
+            let _shared = shared.atomic.read().unwrap();
+            carry_out(db, shared, connection, request)
+        }
+    }
+}
+
+/// Carries out `request` on `db` on behalf of the user of `connection`
+/// within its rate limits, counting the keys read, and tracking them if it
+/// does, see `tracking`.
+fn carry_out(
+    db: &dyn Served,
+    shared: &Shared,
+    connection: &mut Connection,
+    request: Request,
+) -> Response {
+    let limited = shared
+        .limiter
+        .as_ref()
+        .and_then(|limiter| Some((limiter, Access::of(&request)?)));
+    if let Some((limiter, access)) = &limited {
+        let user = connection.session.user_name();
+        if let Err(throttled) = limiter.admit(connection.id, user, access) {
+            return Response::Error(throttled.to_string());
+        }
+    }
+    let mut written = None;
+    match &request {
+        // tracked before being read, for no write in between to be missed
+        Request::Get { keyspace, key } if connection.invalidations.is_some() => {
+            shared.tracking.read(connection.id, keyspace, key)
+        }
+        Request::Put { keyspace, key, .. } | Request::Delete { keyspace, key } => {
+            written = Some((keyspace.clone(), key.clone()))
+        }
+        _ => {}
+    }
+    let get = matches!(request, Request::Get { .. });
+    let response = match connection.session.execute(db, request) {
+        Ok(result) => result.unwrap_or_else(|err| Response::Error(err.to_string())),
+        Err(err) => Response::Error(err.to_string()),
+    };
+    match (&response, written) {
+        (Response::Value(value), _) if get => shared.stats.lookup(value.is_some()),
+        (Response::Error(_), _) => {}
+        (_, Some((keyspace, key))) => shared.tracking.write(&keyspace, &key),
+        _ => {}
+    }
+    if let Some((limiter, access)) = &limited {
+        let user = connection.session.user_name();
+        limiter.charge(connection.id, user, access, limit::answered(&response));
+    }
+    response
+}
+
+/// Waits up to `timeout` for bytes to read from `input`, or for its end.
+///
+/// # Returns
+///
+/// `false` if none came in time.
+fn readable(input: &mut BufReader<&Stream>, timeout: Duration) -> io::Result<bool> {
+    if !input.buffer().is_empty() {
+        return Ok(true);
+    }
+    let stream = *input.get_ref();
+    stream.set_read_timeout(Some(timeout))?;
+    let filled = input.fill_buf().map(|_| true);
+    stream.set_read_timeout(None)?;
+    match filled {
+        Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+        filled => filled,
+    }
+}
+
+/// A connection being served, read and written through shared references
+/// as its requests are answered one after the other.
+enum Stream {
+    Plain(Socket),
+    #[cfg(feature = "tls")]
+    Tls(Box<Mutex<rustls::StreamOwned<rustls::ServerConnection, Socket>>>),
+}
+
+impl Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.lock().unwrap().sock.set_read_timeout(timeout),
+        }
+    }
+
+    /// Tells the client the connection ends, TLS clients otherwise taking
+    /// its end for a truncation.
+    fn close(&self) {
+        match self {
+            Stream::Plain(_) => {}
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                let mut stream = stream.lock().unwrap();
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => (&*stream).read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.lock().unwrap().read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => (&*stream).write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => (&*stream).flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.lock().unwrap().flush(),
+        }
+    }
+}
+
+impl Shared {
+    /// Stops taking requests on every connection, the requests in flight
+    /// still being answered.
+    fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        for stream in self.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+/// Handle shutting a [`Server`] down, see [`Server::shutdown_handle`].
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+}
+
+impl ShutdownHandle {
+    /// Makes the server stop accepting connections and reading requests,
+    /// then return from [`Server::run`] once the requests in flight are
+    /// answered.
+    pub fn shutdown(&self) {
+        self.shared.shut_down();
+    }
+
+    /// Returns `true` if the server was shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shared.shutting_down.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, ServerBuilder};
+    use crate::codec::{decode_all, Encode};
+    use crate::db::{Database, WAL_DIR};
+    use crate::protocol::{self, Request, Response};
+    use std::fs::{self, OpenOptions};
+    use std::io::{BufReader, BufWriter, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn call(stream: &TcpStream, request: Request) -> Response {
+        let mut payload = Vec::new();
+        request.encode(&mut payload);
+        let mut out = BufWriter::new(stream);
+        protocol::write_frame(&mut out, &payload).unwrap();
+        out.flush().unwrap();
+        let payload = protocol::read_frame(&mut BufReader::new(stream))
+            .unwrap()
+            .unwrap();
+        decode_all(&payload).unwrap()
+    }
+
+    #[test]
+    fn test_clients_share_a_durable_database() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-server-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(&dir).unwrap());
+        db.open_durable::<u64, u64>("typed").unwrap();
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(4)
+            .bind(db.clone())
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let clients: Vec<_> = (0..4u8)
+            .map(|client| {
+                thread::spawn(move || {
+                    let stream = TcpStream::connect(address).unwrap();
+                    for key in 0..50u8 {
+                        let request = Request::Put {
+                            keyspace: "users".to_string(),
+                            key: vec![client, key],
+                            value: vec![key],
+                        };
+                        assert_eq!(call(&stream, request), Response::Ok);
+                    }
+                    let request = Request::Delete {
+                        keyspace: "users".to_string(),
+                        key: vec![client, 0],
+                    };
+                    assert_eq!(call(&stream, request), Response::Deleted(true));
+                })
+            })
+            .collect();
+        clients
+            .into_iter()
+            .for_each(|client| client.join().unwrap());
+
+        let stream = TcpStream::connect(address).unwrap();
+        let get = |keyspace: &str, key: Vec<u8>| Request::Get {
+            keyspace: keyspace.to_string(),
+            key,
+        };
+        let response = call(&stream, get("users", vec![2, 7]));
+        assert_eq!(response, Response::Value(Some(vec![7])));
+        assert_eq!(
+            call(&stream, get("users", vec![2, 0])),
+            Response::Value(None)
+        );
+        let scan = Request::Scan {
+            keyspace: "users".to_string(),
+            prefix: vec![3],
+            limit: 3,
+        };
+        let expected = (1..4u8).map(|key| (vec![3, key], vec![key])).collect();
+        assert_eq!(call(&stream, scan), Response::Entries(expected));
+        // keyspaces opened with other types are refused
+        let response = call(&stream, get("typed", Vec::new()));
+        assert!(matches!(response, Response::Error(_)));
+
+        // the server reports what its clients did, along with the database
+        let info = |section: &str| {
+            let section = Some(section.to_string());
+            match call(&stream, Request::Info { section }) {
+                Response::Value(Some(report)) => String::from_utf8(report).unwrap(),
+                response => panic!("unexpected response {:?}", response),
+            }
+        };
+        let report = info("all");
+        for line in &[
+            "total_connections_received:5",
+            "keyspace_hits:1",
+            "keyspace_misses:1",
+            "cmdstat_set:calls=200,",
+            "cmdstat_del:calls=4,",
+            "cmdstat_get:calls=3,",
+            "cmdstat_info:calls=1,",
+            "keyspace_users:kind=durable,keys=196,",
+            "keyspace_typed:kind=durable,keys=0,",
+        ] {
+            assert!(report.contains(line), "{} missing from {}", line, report);
+        }
+        let report = info("keyspace");
+        assert!(report.starts_with("# Keyspace\n") && !report.contains("# Server"));
+
+        // idle connections are closed, and writes kept
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        assert!(protocol::read_frame(&mut BufReader::new(&stream))
+            .unwrap()
+            .is_none());
+        db.close().unwrap();
+        drop(db);
+        let db = Database::open(&dir).unwrap();
+        assert!(db.recovery_report().unwrap().clean_shutdown);
+        let users = db.open_durable::<Vec<u8>, Vec<u8>>("users").unwrap();
+        assert_eq!(users.len(), 4 * 49);
+        drop((db, users));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_batches_write_as_a_whole_and_isolated() {
+        let db = Arc::new(Database::new());
+        db.open_map::<u64, u64>("typed").unwrap();
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(2)
+            .bind(db)
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        let put = |key: &[u8], value: Vec<u8>| Request::Put {
+            keyspace: "accounts".to_string(),
+            key: key.to_vec(),
+            value,
+        };
+        let get = |keyspace: &str, key: &[u8]| Request::Get {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+
+        // the writes before a failure are not made
+        let stream = TcpStream::connect(address).unwrap();
+        assert_eq!(call(&stream, put(b"a", vec![1])), Response::Ok);
+        let requests = vec![put(b"a", vec![2]), put(b"b", vec![2]), get("typed", b"a")];
+        match call(&stream, Request::Atomic { requests }) {
+            Response::Error(message) => {
+                assert!(message.starts_with("request 2 failed, nothing was written"))
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert_eq!(
+            call(&stream, get("accounts", b"a")),
+            Response::Value(Some(vec![1]))
+        );
+        assert_eq!(call(&stream, get("accounts", b"b")), Response::Value(None));
+
+        // while the requests after a write see it
+        let scan = Request::Scan {
+            keyspace: "accounts".to_string(),
+            prefix: Vec::new(),
+            limit: 10,
+        };
+        let delete = Request::Delete {
+            keyspace: "accounts".to_string(),
+            key: b"a".to_vec(),
+        };
+        let requests = vec![put(b"b", vec![3]), get("accounts", b"b"), delete, scan];
+        let responses = vec![
+            Response::Ok,
+            Response::Value(Some(vec![3])),
+            Response::Deleted(true),
+            Response::Entries(vec![(b"b".to_vec(), vec![3])]),
+        ];
+        assert_eq!(
+            call(&stream, Request::Atomic { requests }),
+            Response::Batch(responses)
+        );
+        assert_eq!(call(&stream, get("accounts", b"a")), Response::Value(None));
+
+        // the writes of another client, going on throughout, are not
+        // carried out in between
+        let writing = Arc::new(AtomicBool::new(true));
+        let writer = thread::spawn({
+            let writing = writing.clone();
+            move || {
+                let stream = TcpStream::connect(address).unwrap();
+                let mut count = 0u32;
+                while writing.load(Ordering::SeqCst) {
+                    let value = count.to_be_bytes().to_vec();
+                    assert_eq!(call(&stream, put(b"c", value)), Response::Ok);
+                    count += 1;
+                }
+            }
+        });
+        while call(&stream, get("accounts", b"c")) == Response::Value(None) {
+            thread::yield_now();
+        }
+        for _ in 0..5 {
+            let requests = (0..2000).map(|_| get("accounts", b"c")).collect();
+            match call(&stream, Request::Atomic { requests }) {
+                Response::Batch(responses) => {
+                    assert!(responses.windows(2).all(|pair| pair[0] == pair[1]))
+                }
+                response => panic!("unexpected response {:?}", response),
+            }
+        }
+        writing.store(false, Ordering::SeqCst);
+        writer.join().unwrap();
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_atomic_batches_are_recovered_as_a_whole() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-server-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let put = |keyspace: &str, key: &[u8]| Request::Put {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+            value: b"1".to_vec(),
+        };
+        let get = |keyspace: &str, key: &[u8]| Request::Get {
+            keyspace: keyspace.to_string(),
+            key: key.to_vec(),
+        };
+        let transfer = || Request::Atomic {
+            requests: vec![
+                put("from", b"alice"),
+                put("to", b"bob"),
+                Request::Delete {
+                    keyspace: "from".to_string(),
+                    key: b"carol".to_vec(),
+                },
+            ],
+        };
+        let value =
+            |db: &Database, keyspace: &str, key: &[u8]| match execute(db, get(keyspace, key)) {
+                Ok(Response::Value(value)) => value,
+                response => panic!("unexpected response {:?}", response),
+            };
+
+        let db = Database::open(&dir).unwrap();
+        execute(&db, put("from", b"carol")).unwrap();
+        execute(&db, put("to", b"dave")).unwrap();
+        let done = Response::Batch(vec![Response::Ok, Response::Ok, Response::Deleted(true)]);
+        assert_eq!(execute(&db, transfer()).unwrap(), done);
+        drop(db);
+
+        // a crash in the middle of logging the batch loses all of it
+        let mut segments: Vec<_> = fs::read_dir(dir.join(WAL_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        segments.sort();
+        let last = OpenOptions::new()
+            .write(true)
+            .open(segments.last().unwrap())
+            .unwrap();
+        last.set_len(last.metadata().unwrap().len() - 3).unwrap();
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(value(&db, "from", b"alice"), None);
+        assert_eq!(value(&db, "to", b"bob"), None);
+        assert_eq!(value(&db, "from", b"carol"), Some(b"1".to_vec()));
+
+        // while one logged in full is recovered in full, over the checkpoint
+        // of one of its keyspaces
+        assert_eq!(execute(&db, transfer()).unwrap(), done);
+        db.checkpoint_keyspace("from").unwrap();
+        execute(&db, put("to", b"erin")).unwrap();
+        drop(db);
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(value(&db, "from", b"alice"), Some(b"1".to_vec()));
+        assert_eq!(value(&db, "to", b"bob"), Some(b"1".to_vec()));
+        assert_eq!(value(&db, "from", b"carol"), None);
+        assert_eq!(value(&db, "to", b"erin"), Some(b"1".to_vec()));
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
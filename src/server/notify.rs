@@ -0,0 +1,385 @@
+//! Keyspace notifications, published by servers built with
+//! [`ServerBuilder::keyspace_events`](super::ServerBuilder::keyspace_events)
+//! on the [channels](crate::pubsub) of their broker whenever a client
+//! writes a key, so that caches may drop the keys written without polling.
+//!
+//! A write of `key` in `keyspace` is published, in the manner of Redis, on:
+//!
+//! | Channel                       | Payload                                |
+//! |-------------------------------|----------------------------------------|
+//! | `__keyspace@keyspace__:key`   | the name of the [`KeyEvent`]           |
+//! | `__keyevent@keyspace__:event` | the key                                |
+//! | `__keyvalue@keyspace__:key`   | the value, with [`KeyspaceEvents::Values`], on inserts and updates |
+//!
+//! Keys are written in channels as UTF-8, invalid sequences being replaced.
+//! Redis clients see the databases they `SELECT` as keyspaces `db0`, `db1`
+//! and so on. Keys expiring are not notified, nor writes made through the
+//! [`Database`](crate::db::Database) rather than the server.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::db::Database;
+//! use palladiumdb::pubsub::Broker;
+//! use palladiumdb::server::notify::{self, KeyEvent, KeyspaceEvents};
+//! use palladiumdb::server::ServerBuilder;
+//! use std::sync::Arc;
+//!
+//! let broker = Arc::new(Broker::new());
+//! let server = ServerBuilder::new()
+//!     .bind_address("127.0.0.1:0")
+//!     .broker(broker.clone())
+//!     .keyspace_events(KeyspaceEvents::Keys)
+//!     .bind(Arc::new(Database::new()))
+//!     .unwrap();
+//! let users = broker.subscribe(&notify::keyspace_channel("users", "*"));
+//! // once clients write to `users`
+//! if let Some(message) = users.try_recv() {
+//!     let event = KeyEvent::from_name(&String::from_utf8_lossy(&message.payload));
+//!     assert!(event.is_some());
+//! }
+//! ```
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::resp::Keyspace;
+use super::{Served, Writes};
+use crate::db::{Result, Stats};
+use crate::protocol::{Request, Response};
+use crate::pubsub::Broker;
+
+/// A write of a key, as notified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// The key was mapped while absent.
+    Insert,
+    /// The key was mapped while mapped already.
+    Update,
+    /// The key was removed.
+    Delete,
+}
+
+impl KeyEvent {
+    /// Returns the name of the event, `insert`, `update` or `delete`.
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyEvent::Insert => "insert",
+            KeyEvent::Update => "update",
+            KeyEvent::Delete => "delete",
+        }
+    }
+
+    /// Returns the event named `name`, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "insert" => Some(KeyEvent::Insert),
+            "update" => Some(KeyEvent::Update),
+            "delete" => Some(KeyEvent::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// What the keyspace notifications of a server carry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyspaceEvents {
+    /// The keys written and the events, on the `__keyspace@` and
+    /// `__keyevent@` channels.
+    Keys,
+    /// The values written as well, on the `__keyvalue@` channels.
+    Values,
+}
+
+/// Returns the channel the events of `key` in `keyspace` are published on,
+/// `key` being a pattern of keys to subscribe to.
+pub fn keyspace_channel(keyspace: &str, key: &str) -> String {
+    format!("__keyspace@{}__:{}", keyspace, key)
+}
+
+/// Returns the channel the keys `event` happens to in `keyspace` are
+/// published on.
+pub fn keyevent_channel(keyspace: &str, event: KeyEvent) -> String {
+    format!("__keyevent@{}__:{}", keyspace, event.name())
+}
+
+/// Returns the channel the values written to `key` in `keyspace` are
+/// published on, with [`KeyspaceEvents::Values`].
+pub fn keyvalue_channel(keyspace: &str, key: &str) -> String {
+    format!("__keyvalue@{}__:{}", keyspace, key)
+}
+
+/// Publishes the notifications of the writes of a server.
+struct Notifier {
+    broker: Arc<Broker>,
+    events: KeyspaceEvents,
+}
+
+impl Notifier {
+    fn notify(&self, keyspace: &str, key: &[u8], event: KeyEvent, value: Option<&[u8]>) {
+        let name = String::from_utf8_lossy(key);
+        let broker = &self.broker;
+        broker.publish(&keyspace_channel(keyspace, &name), event.name().as_bytes());
+        broker.publish(&keyevent_channel(keyspace, event), key);
+        if let (KeyspaceEvents::Values, Some(value)) = (self.events, value) {
+            broker.publish(&keyvalue_channel(keyspace, &name), value);
+        }
+    }
+}
+
+/// A database served whose writes are notified.
+pub(super) struct Notifying {
+    db: Arc<dyn Served>,
+    notifier: Arc<Notifier>,
+    /// Serializes the writes of native clients, so that the event of a
+    /// write tells whether the key was mapped right before.
+    writes: Mutex<()>,
+}
+
+impl Notifying {
+    pub(super) fn new(db: Arc<dyn Served>, broker: Arc<Broker>, events: KeyspaceEvents) -> Self {
+        Notifying {
+            db,
+            notifier: Arc::new(Notifier { broker, events }),
+            writes: Mutex::new(()),
+        }
+    }
+}
+
+impl Served for Notifying {
+    fn execute(&self, request: Request) -> Result<Response> {
+        match request {
+            Request::Put {
+                keyspace,
+                key,
+                value,
+            } => {
+                let _writes = self.writes.lock().unwrap();
+                let get = Request::Get {
+                    keyspace: keyspace.clone(),
+                    key: key.clone(),
+                };
+                let event = match self.db.execute(get)? {
+                    Response::Value(Some(_)) => KeyEvent::Update,
+                    _ => KeyEvent::Insert,
+                };
+                let put = Request::Put {
+                    keyspace: keyspace.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                };
+                let response = self.db.execute(put)?;
+                self.notifier.notify(&keyspace, &key, event, Some(&value));
+                Ok(response)
+            }
+            Request::Delete { keyspace, key } => {
+                let _writes = self.writes.lock().unwrap();
+                let delete = Request::Delete {
+                    keyspace: keyspace.clone(),
+                    key: key.clone(),
+                };
+                let response = self.db.execute(delete)?;
+                if response == Response::Deleted(true) {
+                    self.notifier
+                        .notify(&keyspace, &key, KeyEvent::Delete, None);
+                }
+                Ok(response)
+            }
+            request => self.db.execute(request),
+        }
+    }
+
+    fn resp_keyspace(&self, name: &str) -> Result<Arc<dyn Keyspace>> {
+        Ok(Arc::new(NotifyingKeyspace {
+            keyspace: self.db.resp_keyspace(name)?,
+            name: name.to_string(),
+            notifier: self.notifier.clone(),
+        }))
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.db.stats()
+    }
+
+    fn write_atomically(&self, writes: Writes) -> Result<()> {
+        let _writes = self.writes.lock().unwrap();
+        let mut events = Vec::with_capacity(writes.len());
+        for (keyspace, key, value) in &writes {
+            let get = Request::Get {
+                keyspace: keyspace.clone(),
+                key: key.clone(),
+            };
+            let mapped = matches!(self.db.execute(get)?, Response::Value(Some(_)));
+            events.push(match (value, mapped) {
+                (Some(_), true) => Some(KeyEvent::Update),
+                (Some(_), false) => Some(KeyEvent::Insert),
+                (None, true) => Some(KeyEvent::Delete),
+                (None, false) => None,
+            });
+        }
+        self.db.write_atomically(writes.clone())?;
+        for ((keyspace, key, value), event) in writes.iter().zip(events) {
+            if let Some(event) = event {
+                self.notifier.notify(keyspace, key, event, value.as_deref());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A keyspace of Redis clients whose writes are notified, the writes of
+/// Redis clients being serialized already.
+struct NotifyingKeyspace {
+    keyspace: Arc<dyn Keyspace>,
+    name: String,
+    notifier: Arc<Notifier>,
+}
+
+impl Keyspace for NotifyingKeyspace {
+    fn get(&self, key: &[u8]) -> Option<(Vec<u8>, u64)> {
+        self.keyspace.get(key)
+    }
+
+    fn put(&self, key: &[u8], value: Vec<u8>, deadline: u64) -> io::Result<()> {
+        let event = match self.keyspace.get(key) {
+            Some(_) => KeyEvent::Update,
+            None => KeyEvent::Insert,
+        };
+        self.keyspace.put(key, value.clone(), deadline)?;
+        self.notifier.notify(&self.name, key, event, Some(&value));
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> io::Result<bool> {
+        let removed = self.keyspace.remove(key)?;
+        if removed {
+            self.notifier
+                .notify(&self.name, key, KeyEvent::Delete, None);
+        }
+        Ok(removed)
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        self.keyspace.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keyevent_channel, keyspace_channel, keyvalue_channel, KeyEvent, KeyspaceEvents};
+    use crate::codec::{decode_all, Encode};
+    use crate::db::Database;
+    use crate::protocol::resp::{self, ProtocolVersion, Value};
+    use crate::protocol::{self, Request, Response};
+    use crate::pubsub::{Broker, Subscription};
+    use crate::server::{Protocol, ServerBuilder};
+    use std::io::BufReader;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_writes_of_native_and_redis_clients_are_notified() {
+        assert_eq!(
+            KeyEvent::from_name(KeyEvent::Update.name()),
+            Some(KeyEvent::Update)
+        );
+        assert_eq!(KeyEvent::from_name("expire"), None);
+        let (broker, db) = (Arc::new(Broker::new()), Arc::new(Database::new()));
+        let serve = |protocol, events| {
+            let server = ServerBuilder::new()
+                .bind_address("127.0.0.1:0")
+                .workers(1)
+                .protocol(protocol)
+                .broker(broker.clone())
+                .keyspace_events(events)
+                .bind(db.clone())
+                .unwrap();
+            let address = server.local_addr().unwrap();
+            let shutdown = server.shutdown_handle();
+            (address, shutdown, thread::spawn(move || server.run()))
+        };
+        let (native_address, native_shutdown, native_running) =
+            serve(Protocol::Native, KeyspaceEvents::Values);
+        let (resp_address, resp_shutdown, resp_running) =
+            serve(Protocol::Resp, KeyspaceEvents::Keys);
+        let received = |subscription: &Subscription| {
+            let message = subscription.recv_timeout(Duration::from_secs(5)).unwrap();
+            (message.channel, String::from_utf8(message.payload).unwrap())
+        };
+
+        let users = broker.subscribe(&keyspace_channel("users", "*"));
+        let values = broker.subscribe(&keyvalue_channel("users", "*"));
+        let deletes = broker.subscribe(&keyevent_channel("users", KeyEvent::Delete));
+        let stream = TcpStream::connect(native_address).unwrap();
+        let request = |request: Request| {
+            let mut payload = Vec::new();
+            request.encode(&mut payload);
+            protocol::write_frame(&mut &stream, &payload).unwrap();
+            let payload = protocol::read_frame(&mut &stream).unwrap().unwrap();
+            decode_all::<Response>(&payload).unwrap()
+        };
+        let put = |value: &str| Request::Put {
+            keyspace: "users".to_string(),
+            key: b"alice".to_vec(),
+            value: value.as_bytes().to_vec(),
+        };
+        let delete = || Request::Delete {
+            keyspace: "users".to_string(),
+            key: b"alice".to_vec(),
+        };
+        assert_eq!(request(put("1")), Response::Ok);
+        assert_eq!(request(put("2")), Response::Ok);
+        assert_eq!(request(delete()), Response::Deleted(true));
+        // deleting an absent key writes nothing
+        assert_eq!(request(delete()), Response::Deleted(false));
+        let channel = "__keyspace@users__:alice".to_string();
+        for event in ["insert", "update", "delete"] {
+            assert_eq!(received(&users), (channel.clone(), event.to_string()));
+        }
+        assert!(users.try_recv().is_none());
+        let channel = "__keyvalue@users__:alice".to_string();
+        assert_eq!(received(&values), (channel.clone(), "1".to_string()));
+        assert_eq!(received(&values), (channel, "2".to_string()));
+        assert!(values.try_recv().is_none());
+        let channel = "__keyevent@users__:delete".to_string();
+        assert_eq!(received(&deletes), (channel, "alice".to_string()));
+        assert!(deletes.try_recv().is_none());
+
+        // Redis clients write to the keyspaces of their databases
+        let events = broker.subscribe("__key*@db0__:*");
+        let stream = TcpStream::connect(resp_address).unwrap();
+        let mut replies = BufReader::new(&stream);
+        let mut call = |command: &[&str]| {
+            let bulk = |arg: &&str| Value::Bulk(arg.as_bytes().to_vec());
+            let command = Value::Array(command.iter().map(bulk).collect());
+            resp::write_value(&mut &stream, &command, ProtocolVersion::Resp2).unwrap();
+            resp::read_value(&mut replies).unwrap().unwrap()
+        };
+        assert_eq!(call(&["SET", "bob", "1"]), Value::Simple("OK".to_string()));
+        assert_eq!(call(&["INCR", "bob"]), Value::Integer(2));
+        assert_eq!(call(&["DEL", "bob", "carol"]), Value::Integer(1));
+        let expected = [
+            ("__keyspace@db0__:bob", "insert"),
+            ("__keyevent@db0__:insert", "bob"),
+            ("__keyspace@db0__:bob", "update"),
+            ("__keyevent@db0__:update", "bob"),
+            ("__keyspace@db0__:bob", "delete"),
+            ("__keyevent@db0__:delete", "bob"),
+        ];
+        for (channel, payload) in expected {
+            assert_eq!(
+                received(&events),
+                (channel.to_string(), payload.to_string())
+            );
+        }
+        // values are notified by the native server only
+        assert!(events.try_recv().is_none());
+
+        native_shutdown.shutdown();
+        resp_shutdown.shutdown();
+        native_running.join().unwrap().unwrap();
+        resp_running.join().unwrap().unwrap();
+    }
+}
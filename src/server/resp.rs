@@ -0,0 +1,993 @@
+//! Commands of the Redis clients of a server, spoken in
+//! [RESP](crate::protocol::resp).
+//!
+//! Redis databases, which `SELECT` switches between, are keyspaces named
+//! `db0`, `db1` and so on, holding values along with the time they expire
+//! at: durable TTL keyspaces if the database is stored on disk, plain
+//! keyspaces otherwise.
+//!
+//! `INFO [section]` reports the statistics of the server, see
+//! [`super::info`].
+//!
+//! Servers given an [`Acl`] take `AUTH` and `ACL SETUSER`, `DELUSER`,
+//! `LIST`, `USERS` and `WHOAMI`, see [`super::acl`].
+//!
+//! Clients publish on [channels](crate::pubsub) with `PUBLISH`, and
+//! subscribe to them with `SUBSCRIBE` and `PSUBSCRIBE`, for patterns, after
+//! which RESP2 connections only take `(P)SUBSCRIBE`, `(P)UNSUBSCRIBE`,
+//! `PING` and `QUIT` until they unsubscribe from every channel.
+
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::acl::{Acl, Refused, Session, User, DEFAULT_USER};
+use super::limit::Access;
+use super::{readable, Served, Shared, Stream, SUBSCRIBED_POLL};
+use crate::collections::map::Map;
+use crate::db::DurableTtl;
+use crate::protocol::resp::{self, ProtocolVersion, Value};
+use crate::pubsub::{matches, Broker, Subscription};
+use crate::wal::unix_millis;
+
+/// Deadline of the values which never expire.
+const PERSISTENT: u64 = u64::MAX;
+
+/// Keys a `SCAN` goes through unless its `COUNT` says otherwise.
+const SCAN_COUNT: usize = 10;
+
+/// Commands carried out, refused with an error naming them when given the
+/// wrong number of arguments.
+const COMMANDS: &[&str] = &[
+    "PING", "ECHO", "HELLO", "AUTH", "SELECT", "COMMAND", "CLIENT", "QUIT", "GET", "SET", "DEL",
+    "EXPIRE", "INCR", "INCRBY", "DECR", "DECRBY", "SCAN", "ACL", "PUBLISH", "INFO",
+];
+
+/// Commands carried out before the connection authenticates.
+const UNAUTHENTICATED: &[&str] = &["HELLO", "AUTH", "QUIT"];
+
+/// Commands of subscriptions, answered with a reply per channel, all
+/// checked as `subscribe` by the ACL.
+const SUBSCRIPTIONS: &[&str] = &["SUBSCRIBE", "PSUBSCRIBE", "UNSUBSCRIBE", "PUNSUBSCRIBE"];
+
+fn now() -> u64 {
+    unix_millis(SystemTime::now())
+}
+
+/// A keyspace as Redis clients see it, whose values expire at a deadline,
+/// in Unix milliseconds.
+pub(super) trait Keyspace: Send + Sync {
+    /// Returns the value of `key` and its deadline, unless it expired.
+    fn get(&self, key: &[u8]) -> Option<(Vec<u8>, u64)>;
+
+    /// Maps `key` to `value` until `deadline`.
+    fn put(&self, key: &[u8], value: Vec<u8>, deadline: u64) -> io::Result<()>;
+
+    /// Removes the entry of `key`, returning `true` unless it was absent or
+    /// expired.
+    fn remove(&self, key: &[u8]) -> io::Result<bool>;
+
+    /// Returns the keys whose values have not expired.
+    fn keys(&self) -> Vec<Vec<u8>>;
+}
+
+impl<H> Keyspace for DurableTtl<Vec<u8>, Vec<u8>, H>
+where
+    H: BuildHasher + Send + Sync,
+{
+    fn get(&self, key: &[u8]) -> Option<(Vec<u8>, u64)> {
+        self.map()
+            .get(&key.to_vec())
+            .filter(|(_, deadline)| *deadline > now())
+    }
+
+    fn put(&self, key: &[u8], value: Vec<u8>, deadline: u64) -> io::Result<()> {
+        let ttl = Duration::from_millis(deadline.saturating_sub(now()));
+        DurableTtl::put(self, &key.to_vec(), value, ttl)
+    }
+
+    fn remove(&self, key: &[u8]) -> io::Result<bool> {
+        Ok(DurableTtl::remove(self, &key.to_vec())?.is_some())
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        let now = now();
+        self.map()
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline > now)
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+impl<H> Keyspace for Map<Vec<u8>, (Vec<u8>, u64), H>
+where
+    H: BuildHasher + Send + Sync,
+{
+    fn get(&self, key: &[u8]) -> Option<(Vec<u8>, u64)> {
+        Map::get(self, &key.to_vec()).filter(|(_, deadline)| *deadline > now())
+    }
+
+    fn put(&self, key: &[u8], value: Vec<u8>, deadline: u64) -> io::Result<()> {
+        Map::put(self, &key.to_vec(), (value, deadline));
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> io::Result<bool> {
+        let removed = Map::remove(self, &key.to_vec());
+        Ok(removed.is_some_and(|(_, deadline)| deadline > now()))
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        let now = now();
+        self.iter()
+            .filter(|(_, (_, deadline))| *deadline > now)
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+fn text(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+fn integer(arg: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".to_string())
+}
+
+fn syntax_error<T>() -> Result<T, String> {
+    Err("ERR syntax error".to_string())
+}
+
+/// Returns the pattern matching `channel` alone.
+fn escape(channel: &str) -> String {
+    let mut pattern = String::with_capacity(channel.len());
+    for c in channel.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Returns the keys and bytes of the command `name` with `args`, as rate
+/// limits see them, `None` for commands not limited.
+fn access(name: &str, args: &[Vec<u8>]) -> Option<Access> {
+    let (keys, bytes) = match (name, args) {
+        ("GET" | "EXPIRE" | "INCR" | "INCRBY" | "DECR" | "DECRBY", [key, ..]) => {
+            (vec![key.clone()], key.len())
+        }
+        ("SET", [key, value, ..]) => (vec![key.clone()], key.len() + value.len()),
+        ("DEL", keys) if !keys.is_empty() => (keys.to_vec(), keys.iter().map(Vec::len).sum()),
+        // keys are scanned by pattern, whatever their prefix
+        ("SCAN", [_, ..]) => {
+            return Some(Access {
+                scanned: Some(Vec::new()),
+                ..Access::default()
+            })
+        }
+        _ => return None,
+    };
+    Some(Access {
+        keys,
+        scanned: None,
+        bytes: bytes as u64,
+    })
+}
+
+/// Returns the bytes of the strings of `value`.
+fn answered(value: &Value) -> u64 {
+    match value {
+        Value::Bulk(bytes) => bytes.len() as u64,
+        Value::Array(values) | Value::Push(values) => values.iter().map(answered).sum(),
+        _ => 0,
+    }
+}
+
+/// Returns the error Redis clients expect for `refused`.
+fn refused(refused: Refused) -> String {
+    match refused {
+        Refused::Unauthenticated => "NOAUTH Authentication required.".to_string(),
+        Refused::WrongPassword => format!("WRONGPASS {}.", refused),
+        Refused::Forbidden(message) => format!("NOPERM {}", message),
+    }
+}
+
+/// A subscription of a Redis client, to a channel or to a pattern.
+struct Subscribed {
+    /// Whether it was made by `PSUBSCRIBE`.
+    pattern: bool,
+    /// The channel or pattern, as the client named it.
+    name: String,
+    subscription: Subscription,
+}
+
+/// State of the connection of a Redis client.
+struct Connection<'a> {
+    db: &'a dyn Served,
+    /// Counts the commands, and the connections reported by `INFO`.
+    shared: &'a Shared,
+    /// Serializes the writes of every connection, so that commands reading
+    /// then writing a key are atomic.
+    writes: &'a Mutex<()>,
+    id: u64,
+    session: Session<'a>,
+    acl: Option<&'a Acl>,
+    broker: &'a Arc<Broker>,
+    subscriptions: Vec<Subscribed>,
+    version: ProtocolVersion,
+    selected: u64,
+    keyspace: Option<Arc<dyn Keyspace>>,
+    quit: bool,
+}
+
+impl Connection<'_> {
+    /// Returns the keyspace of the database selected.
+    fn keyspace(&mut self) -> Result<Arc<dyn Keyspace>, String> {
+        if let Some(keyspace) = &self.keyspace {
+            return Ok(keyspace.clone());
+        }
+        let name = format!("db{}", self.selected);
+        let keyspace = self
+            .db
+            .resp_keyspace(&name)
+            .map_err(|err| format!("ERR {}", err))?;
+        self.keyspace = Some(keyspace.clone());
+        Ok(keyspace)
+    }
+
+    /// Carries out the command made of `args`, answering failures with an
+    /// error value.
+    ///
+    /// # Returns
+    ///
+    /// The replies, one per channel or pattern for the commands of
+    /// subscriptions, one otherwise.
+    fn execute(&mut self, args: &[Vec<u8>]) -> Vec<Value> {
+        let name = text(&args[0]).to_ascii_uppercase();
+        if COMMANDS.contains(&name.as_str()) || SUBSCRIPTIONS.contains(&name.as_str()) {
+            self.shared.stats.count(&name.to_ascii_lowercase());
+        }
+        let replies = match name.as_str() {
+            name if SUBSCRIPTIONS.contains(&name) => self
+                .authorize(name, &args[1..])
+                .and_then(|_| self.subscribe(name, &args[1..])),
+            _ => self.limit(&name, args).map(|value| vec![value]),
+        };
+        replies.unwrap_or_else(|message| vec![Value::Error(message)])
+    }
+
+    /// Returns the messages published on the channels subscribed to since
+    /// they were last returned.
+    fn messages(&self) -> Vec<Value> {
+        let bulk = |text: &str| Value::Bulk(text.as_bytes().to_vec());
+        let mut messages = Vec::new();
+        for subscribed in &self.subscriptions {
+            let received = iter::from_fn(|| subscribed.subscription.try_recv());
+            messages.extend(received.map(|message| {
+                let mut push = match subscribed.pattern {
+                    true => vec![bulk("pmessage"), bulk(&subscribed.name)],
+                    false => vec![bulk("message")],
+                };
+                push.extend([bulk(&message.channel), Value::Bulk(message.payload)]);
+                Value::Push(push)
+            }));
+        }
+        messages
+    }
+
+    /// `SUBSCRIBE channel [channel ...]`, `PSUBSCRIBE pattern [pattern ...]`,
+    /// `UNSUBSCRIBE [channel ...]` and `PUNSUBSCRIBE [pattern ...]`, the
+    /// latter two cancelling every subscription of their kind without
+    /// arguments.
+    fn subscribe(&mut self, name: &str, args: &[Vec<u8>]) -> Result<Vec<Value>, String> {
+        let pattern = name.starts_with('P');
+        let reply = |name: &str, channel: Value, count: usize| {
+            let kind = Value::Bulk(name.to_ascii_lowercase().into_bytes());
+            Value::Push(vec![kind, channel, Value::Integer(count as i64)])
+        };
+        let mut names: Vec<String> = args.iter().map(|arg| text(arg)).collect();
+        let mut replies = Vec::new();
+        if name.ends_with("UNSUBSCRIBE") {
+            if names.is_empty() {
+                let subscriptions = self.subscriptions.iter();
+                let subscribed = subscriptions.filter(|subscribed| subscribed.pattern == pattern);
+                names = subscribed
+                    .map(|subscribed| subscribed.name.clone())
+                    .collect();
+            }
+            if names.is_empty() {
+                return Ok(vec![reply(name, Value::Null, self.subscriptions.len())]);
+            }
+            for channel in names {
+                self.subscriptions.retain(|subscribed| {
+                    subscribed.pattern != pattern || subscribed.name != channel
+                });
+                let count = self.subscriptions.len();
+                replies.push(reply(name, Value::Bulk(channel.into_bytes()), count));
+            }
+            return Ok(replies);
+        }
+        if names.is_empty() {
+            return Err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ));
+        }
+        for channel in names {
+            let same = |subscribed: &Subscribed| {
+                subscribed.pattern == pattern && subscribed.name == channel
+            };
+            if !self.subscriptions.iter().any(same) {
+                let subscription = match pattern {
+                    true => self.broker.subscribe(&channel),
+                    false => self.broker.subscribe(&escape(&channel)),
+                };
+                self.subscriptions.push(Subscribed {
+                    pattern,
+                    name: channel.clone(),
+                    subscription,
+                });
+            }
+            let count = self.subscriptions.len();
+            replies.push(reply(name, Value::Bulk(channel.into_bytes()), count));
+        }
+        Ok(replies)
+    }
+
+    /// Checks that the user may run the command made of `name` and `args`,
+    /// on the keys it names.
+    ///
+    /// # Returns
+    ///
+    /// The user, to filter the keys of a `SCAN`, if the server has an ACL.
+    fn authorize(&self, name: &str, args: &[Vec<u8>]) -> Result<Option<User>, String> {
+        let keys: Vec<&[u8]> = match (name, args) {
+            ("GET" | "SET" | "EXPIRE" | "INCR" | "INCRBY" | "DECR" | "DECRBY", [key, ..]) => {
+                vec![key]
+            }
+            ("DEL", keys) => keys.iter().map(Vec::as_slice).collect(),
+            _ => Vec::new(),
+        };
+        let command = match (name, args) {
+            (name, _) if UNAUTHENTICATED.contains(&name) => return Ok(None),
+            (name, _) if SUBSCRIPTIONS.contains(&name) => "subscribe",
+            // everyone may ask who they are
+            ("ACL", [subcommand, ..]) if subcommand.eq_ignore_ascii_case(b"WHOAMI") => "",
+            (name, _) => name,
+        };
+        self.session.check(command, &keys).map_err(refused)
+    }
+
+    /// Dispatches the command `name` made of `args` within the rate limits
+    /// of the server, see [`super::limit`].
+    fn limit(&mut self, name: &str, args: &[Vec<u8>]) -> Result<Value, String> {
+        let limiter = self.shared.limiter.as_ref();
+        let limited = limiter.and_then(|limiter| Some((limiter, access(name, &args[1..])?)));
+        if let Some((limiter, access)) = &limited {
+            let user = self.session.user_name();
+            let admitted = limiter.admit(self.id, user, access);
+            admitted.map_err(|throttled| format!("ERR {}", throttled))?;
+        }
+        let reply = self.dispatch(args);
+        if let Some((limiter, access)) = &limited {
+            let user = self.session.user_name();
+            limiter.charge(self.id, user, access, reply.as_ref().map_or(0, answered));
+        }
+        reply
+    }
+
+    fn dispatch(&mut self, args: &[Vec<u8>]) -> Result<Value, String> {
+        let name = text(&args[0]).to_ascii_uppercase();
+        let user = self.authorize(&name, &args[1..])?;
+        let ok = || Ok(Value::Simple("OK".to_string()));
+        let io = |err: io::Error| format!("ERR {}", err);
+        let subscribed = !self.subscriptions.is_empty() && self.version == ProtocolVersion::Resp2;
+        match (name.as_str(), &args[1..]) {
+            ("PING", message) if subscribed && message.len() <= 1 => {
+                let message = message.first().cloned().unwrap_or_default();
+                Ok(Value::Array(vec![
+                    Value::Bulk(b"pong".to_vec()),
+                    Value::Bulk(message),
+                ]))
+            }
+            (name, _) if subscribed && name != "QUIT" => Err(format!(
+                "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are \
+                 allowed in this context",
+                name.to_ascii_lowercase()
+            )),
+            ("PING", []) => Ok(Value::Simple("PONG".to_string())),
+            ("PING", [message]) | ("ECHO", [message]) => Ok(Value::Bulk(message.clone())),
+            ("HELLO", options) => self.hello(options),
+            ("AUTH", [password]) => self.authenticate(DEFAULT_USER, password),
+            ("AUTH", [user, password]) => self.authenticate(&text(user), password),
+            ("ACL", [subcommand, args @ ..]) => self.acl(subcommand, args),
+            ("SELECT", [index]) => {
+                self.selected = u64::try_from(integer(index)?)
+                    .map_err(|_| "ERR DB index is out of range".to_string())?;
+                self.keyspace = None;
+                ok()
+            }
+            // no command documentation, which clients do without
+            ("COMMAND", _) => Ok(Value::Array(Vec::new())),
+            ("CLIENT", [subcommand, _, ..])
+                if [&b"SETNAME"[..], b"SETINFO"]
+                    .iter()
+                    .any(|name| subcommand.eq_ignore_ascii_case(name)) =>
+            {
+                ok()
+            }
+            ("QUIT", []) => {
+                self.quit = true;
+                ok()
+            }
+            ("GET", [key]) => {
+                let value = self.keyspace()?.get(key);
+                self.shared.stats.lookup(value.is_some());
+                Ok(value.map_or(Value::Null, |(value, _)| Value::Bulk(value)))
+            }
+            ("SET", [key, value, options @ ..]) => self.set(key, value, options),
+            ("DEL", keys) if !keys.is_empty() => {
+                let keyspace = self.keyspace()?;
+                let _writes = self.writes.lock().unwrap();
+                let mut removed = 0;
+                for key in keys {
+                    removed += keyspace.remove(key).map_err(io)? as i64;
+                }
+                Ok(Value::Integer(removed))
+            }
+            ("EXPIRE", [key, seconds]) => {
+                let seconds = integer(seconds)?;
+                let keyspace = self.keyspace()?;
+                let _writes = self.writes.lock().unwrap();
+                if seconds <= 0 {
+                    return Ok(Value::Integer(keyspace.remove(key).map_err(io)? as i64));
+                }
+                Ok(Value::Integer(match keyspace.get(key) {
+                    Some((value, _)) => {
+                        let deadline = now().saturating_add((seconds as u64).saturating_mul(1000));
+                        keyspace.put(key, value, deadline).map_err(io)?;
+                        1
+                    }
+                    None => 0,
+                }))
+            }
+            ("INCR", [key]) => self.increment(key, 1),
+            ("DECR", [key]) => self.increment(key, -1),
+            ("INCRBY", [key, by]) => self.increment(key, integer(by)?),
+            ("DECRBY", [key, by]) => match integer(by)?.checked_neg() {
+                Some(by) => self.increment(key, by),
+                None => Err("ERR decrement would overflow".to_string()),
+            },
+            ("SCAN", [cursor, options @ ..]) => self.scan(cursor, options, user),
+            ("PUBLISH", [channel, message]) => {
+                let delivered = self.broker.publish(&text(channel), message);
+                Ok(Value::Integer(delivered as i64))
+            }
+            ("INFO", []) => self.info(None),
+            ("INFO", [section]) => self.info(Some(&text(section))),
+            (name, _) if COMMANDS.contains(&name) => Err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            )),
+            _ => Err(format!("ERR unknown command '{}'", text(&args[0]))),
+        }
+    }
+
+    /// `INFO [section]`, answered with the lines of the report of the
+    /// server ending with CRLF, see [`super::info`].
+    fn info(&self, section: Option<&str>) -> Result<Value, String> {
+        let connected = self.shared.connections.lock().unwrap().len();
+        let report = self.shared.stats.report(self.db, connected, section);
+        let report = report.map_err(|err| format!("ERR {}", err))?;
+        Ok(Value::Bulk(report.replace('\n', "\r\n").into_bytes()))
+    }
+
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`,
+    /// the name of the client being accepted and ignored.
+    fn hello(&mut self, options: &[Vec<u8>]) -> Result<Value, String> {
+        let version = match options.first().map(|version| integer(version)) {
+            Some(Ok(2)) => Some(ProtocolVersion::Resp2),
+            Some(Ok(3)) => Some(ProtocolVersion::Resp3),
+            Some(_) => return Err("NOPROTO unsupported protocol version".to_string()),
+            None => None,
+        };
+        let mut options = options.iter().skip(1);
+        while let Some(option) = options.next() {
+            match (text(option).to_ascii_uppercase().as_str(), options.next()) {
+                ("AUTH", Some(user)) => {
+                    let password = options.next().ok_or("ERR syntax error")?;
+                    self.authenticate(&text(user), password)?;
+                }
+                ("SETNAME", Some(_)) => {}
+                _ => return syntax_error(),
+            }
+        }
+        if let Some(version) = version {
+            self.version = version;
+        }
+        let field = |name: &str, value| (Value::Bulk(name.as_bytes().to_vec()), value);
+        let bulk = |value: &str| Value::Bulk(value.as_bytes().to_vec());
+        let version = match self.version {
+            ProtocolVersion::Resp2 => 2,
+            ProtocolVersion::Resp3 => 3,
+        };
+        Ok(Value::Map(vec![
+            field("server", bulk("palladiumdb")),
+            field("version", bulk(env!("CARGO_PKG_VERSION"))),
+            field("proto", Value::Integer(version)),
+            field("id", Value::Integer(self.id as i64)),
+            field("mode", bulk("standalone")),
+            field("role", bulk("master")),
+            field("modules", Value::Array(Vec::new())),
+        ]))
+    }
+
+    fn authenticate(&mut self, user: &str, password: &[u8]) -> Result<Value, String> {
+        self.session
+            .authenticate(user, &text(password))
+            .map_err(refused)?;
+        Ok(Value::Simple("OK".to_string()))
+    }
+
+    /// `ACL SETUSER name [rule ...]`, `ACL DELUSER name [name ...]`,
+    /// `ACL LIST`, `ACL USERS` and `ACL WHOAMI`.
+    fn acl(&mut self, subcommand: &[u8], args: &[Vec<u8>]) -> Result<Value, String> {
+        let subcommand = text(subcommand).to_ascii_uppercase();
+        if subcommand == "WHOAMI" && args.is_empty() {
+            let user = self.session.user_name();
+            return Ok(Value::Bulk(user.as_bytes().to_vec()));
+        }
+        let acl = self.acl.ok_or("ERR the server has no ACL")?;
+        let lines = |lines: Vec<String>| {
+            let lines = lines.into_iter().map(|line| Value::Bulk(line.into_bytes()));
+            Ok(Value::Array(lines.collect()))
+        };
+        match (subcommand.as_str(), args) {
+            ("SETUSER", [name, rules @ ..]) => {
+                let rules: Vec<String> = rules.iter().map(|rule| text(rule)).collect();
+                let rules: Vec<&str> = rules.iter().map(String::as_str).collect();
+                acl.set_user(&text(name), &rules)
+                    .map_err(|err| format!("ERR {}", err))?;
+                Ok(Value::Simple("OK".to_string()))
+            }
+            ("DELUSER", names) if !names.is_empty() => {
+                let names = names.iter();
+                let deleted = names.filter(|name| acl.delete_user(&text(name))).count();
+                Ok(Value::Integer(deleted as i64))
+            }
+            ("LIST", []) => lines(acl.list()),
+            ("USERS", []) => lines(acl.users()),
+            _ => Err(format!(
+                "ERR unknown subcommand or wrong number of arguments for 'acl|{}'",
+                subcommand.to_ascii_lowercase()
+            )),
+        }
+    }
+
+    /// `SET key value [NX | XX] [EX seconds | PX milliseconds | KEEPTTL]`
+    fn set(&mut self, key: &[u8], value: &[u8], options: &[Vec<u8>]) -> Result<Value, String> {
+        let (mut condition, mut deadline, mut keep_ttl) = (None, PERSISTENT, false);
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let option = text(option).to_ascii_uppercase();
+            match option.as_str() {
+                "NX" | "XX" if condition.is_none() => condition = Some(option == "XX"),
+                "EX" | "PX" if deadline == PERSISTENT && !keep_ttl => {
+                    let ttl = integer(options.next().ok_or("ERR syntax error")?)?;
+                    if ttl <= 0 {
+                        return Err("ERR invalid expire time in 'set' command".to_string());
+                    }
+                    let millis = match option.as_str() {
+                        "EX" => (ttl as u64).saturating_mul(1000),
+                        _ => ttl as u64,
+                    };
+                    deadline = now().saturating_add(millis);
+                }
+                "KEEPTTL" if deadline == PERSISTENT => keep_ttl = true,
+                _ => return syntax_error(),
+            }
+        }
+
+        let keyspace = self.keyspace()?;
+        let _writes = self.writes.lock().unwrap();
+        let current = keyspace.get(key);
+        if condition.is_some_and(|exists| exists != current.is_some()) {
+            return Ok(Value::Null);
+        }
+        if keep_ttl {
+            deadline = current.map_or(PERSISTENT, |(_, deadline)| deadline);
+        }
+        keyspace
+            .put(key, value.to_vec(), deadline)
+            .map_err(|err| format!("ERR {}", err))?;
+        Ok(Value::Simple("OK".to_string()))
+    }
+
+    /// Adds `by` to the integer value of `key`, missing keys counting as 0,
+    /// keeping the time to live of the key.
+    fn increment(&mut self, key: &[u8], by: i64) -> Result<Value, String> {
+        let keyspace = self.keyspace()?;
+        let _writes = self.writes.lock().unwrap();
+        let (value, deadline) = match keyspace.get(key) {
+            Some((value, deadline)) => (integer(&value)?, deadline),
+            None => (0, PERSISTENT),
+        };
+        let value = value
+            .checked_add(by)
+            .ok_or("ERR increment or decrement would overflow")?;
+        keyspace
+            .put(key, value.to_string().into_bytes(), deadline)
+            .map_err(|err| format!("ERR {}", err))?;
+        Ok(Value::Integer(value))
+    }
+
+    /// `SCAN cursor [MATCH pattern] [COUNT count]`, the cursor being the
+    /// position in the sorted keys to resume from: keys written in between
+    /// calls may be skipped or returned twice.
+    /// Keys the user may not access are left out, pages possibly coming out
+    /// empty.
+    fn scan(
+        &mut self,
+        cursor: &[u8],
+        options: &[Vec<u8>],
+        user: Option<User>,
+    ) -> Result<Value, String> {
+        let cursor = usize::try_from(integer(cursor)?).map_err(|_| "ERR invalid cursor")?;
+        let (mut pattern, mut count) = (None, SCAN_COUNT);
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let value = options.next().ok_or("ERR syntax error")?;
+            match text(option).to_ascii_uppercase().as_str() {
+                "MATCH" => pattern = Some(value),
+                "COUNT" => match usize::try_from(integer(value)?) {
+                    Ok(value) if value > 0 => count = value,
+                    _ => return syntax_error(),
+                },
+                _ => return syntax_error(),
+            }
+        }
+
+        let mut keys = self.keyspace()?.keys();
+        keys.sort_unstable();
+        let end = cursor.saturating_add(count);
+        let next = if end < keys.len() { end } else { 0 };
+        let page = keys
+            .into_iter()
+            .skip(cursor)
+            .take(count)
+            .filter(|key| pattern.is_none_or(|pattern| matches(pattern, key)))
+            .filter(|key| user.as_ref().is_none_or(|user| user.may_access(key)))
+            .map(Value::Bulk)
+            .collect();
+        Ok(Value::Array(vec![
+            Value::Bulk(next.to_string().into_bytes()),
+            Value::Array(page),
+        ]))
+    }
+}
+
+/// Answers the commands of `stream`, one of a connection numbered `id`,
+/// until the client quits or disconnects.
+pub(super) fn answer(db: &dyn Served, shared: &Shared, id: u64, stream: &Stream) -> io::Result<()> {
+    let mut input = BufReader::new(stream);
+    let mut out = BufWriter::new(stream);
+    let acl = shared.acl.as_deref();
+    let mut connection = Connection {
+        db,
+        shared,
+        writes: &shared.resp_writes,
+        id,
+        session: Session::new(acl),
+        acl,
+        broker: &shared.broker,
+        subscriptions: Vec::new(),
+        version: ProtocolVersion::Resp2,
+        selected: 0,
+        keyspace: None,
+        quit: false,
+    };
+    loop {
+        // messages are sent while no command waits
+        while !connection.subscriptions.is_empty() {
+            for message in connection.messages() {
+                resp::write_value(&mut out, &message, connection.version)?;
+            }
+            out.flush()?;
+            if readable(&mut input, SUBSCRIBED_POLL)? {
+                break;
+            }
+        }
+        let args = match resp::read_command(&mut input)? {
+            Some(args) if args.is_empty() => continue,
+            Some(args) => args,
+            None => break,
+        };
+        for reply in connection.execute(&args) {
+            resp::write_value(&mut out, &reply, connection.version)?;
+        }
+        if connection.quit {
+            break;
+        }
+        // commands pipelined are answered together
+        if input.buffer().is_empty() {
+            out.flush()?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+    use crate::codec::{decode_all, Encode};
+    use crate::db::Database;
+    use crate::protocol::resp::{self, ProtocolVersion, Value};
+    use crate::protocol::{self, Request, Response};
+    use crate::pubsub::Broker;
+    use crate::server::{Protocol, ServerBuilder};
+    use std::io::{BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn call(stream: &TcpStream, command: &[&str]) -> Value {
+        let command = command
+            .iter()
+            .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+            .collect();
+        let mut out = stream;
+        resp::write_value(&mut out, &Value::Array(command), ProtocolVersion::Resp2).unwrap();
+        out.flush().unwrap();
+        resp::read_value(&mut BufReader::new(stream))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn bulk(value: &str) -> Value {
+        Value::Bulk(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_redis_clients_get_set_expire_incr_and_scan() {
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .protocol(Protocol::Resp)
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        let stream = TcpStream::connect(address).unwrap();
+        let ok = Value::Simple("OK".to_string());
+
+        assert_eq!(call(&stream, &["PING"]), Value::Simple("PONG".to_string()));
+        assert_eq!(call(&stream, &["GET", "alice"]), Value::Null);
+        assert_eq!(call(&stream, &["SET", "alice", "1"]), ok);
+        assert_eq!(call(&stream, &["set", "alice", "2", "NX"]), Value::Null);
+        assert_eq!(call(&stream, &["GET", "alice"]), bulk("1"));
+        assert_eq!(
+            call(&stream, &["INCRBY", "alice", "41"]),
+            Value::Integer(42)
+        );
+        assert_eq!(call(&stream, &["INCR", "counter"]), Value::Integer(1));
+        let not_integer = call(&stream, &["SET", "bob", "x", "EX", "ten"]);
+        assert!(matches!(not_integer, Value::Error(message) if message.starts_with("ERR")));
+        assert_eq!(call(&stream, &["SET", "bob", "x", "PX", "1"]), ok);
+        assert!(matches!(
+            call(&stream, &["INCR", "alice", "bob"]),
+            Value::Error(_)
+        ));
+        assert!(matches!(call(&stream, &["FLUSHALL"]), Value::Error(_)));
+
+        // expired keys vanish, deadlines survive increments
+        thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(call(&stream, &["GET", "bob"]), Value::Null);
+        assert_eq!(call(&stream, &["EXPIRE", "bob", "10"]), Value::Integer(0));
+        assert_eq!(
+            call(&stream, &["EXPIRE", "counter", "0"]),
+            Value::Integer(1)
+        );
+        assert_eq!(call(&stream, &["INCR", "counter"]), Value::Integer(1));
+        assert_eq!(
+            call(&stream, &["DEL", "alice", "bob", "counter"]),
+            Value::Integer(2)
+        );
+
+        for key in 0..25 {
+            call(&stream, &["SET", &format!("user:{:02}", key), "x"]);
+        }
+        call(&stream, &["SET", "order:1", "x"]);
+        let (mut cursor, mut scanned) = ("0".to_string(), Vec::new());
+        loop {
+            let page = call(&stream, &["SCAN", &cursor, "MATCH", "user:*", "COUNT", "7"]);
+            match page {
+                Value::Array(mut page) => match (page.pop(), page.pop()) {
+                    (Some(Value::Array(keys)), Some(Value::Bulk(next))) => {
+                        scanned.extend(keys);
+                        cursor = String::from_utf8(next).unwrap();
+                    }
+                    page => panic!("malformed page: {:?}", page),
+                },
+                page => panic!("malformed page: {:?}", page),
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        let users: Vec<_> = (0..25)
+            .map(|key| bulk(&format!("user:{:02}", key)))
+            .collect();
+        assert_eq!(scanned, users);
+        match call(&stream, &["INFO", "stats"]) {
+            Value::Bulk(report) => {
+                let report = String::from_utf8(report).unwrap();
+                let expected = "# Stats\r\ntotal_commands_processed:46\r\nkeyspace_hits:1\r\n\
+                                keyspace_misses:2\r\n";
+                assert_eq!(report, expected);
+            }
+            report => panic!("unexpected report {:?}", report),
+        }
+
+        // RESP3 once negotiated, databases apart
+        match call(&stream, &["HELLO", "3"]) {
+            Value::Map(fields) => assert!(fields.contains(&(bulk("proto"), Value::Integer(3)))),
+            hello => panic!("not a map: {:?}", hello),
+        }
+        assert_eq!(call(&stream, &["SELECT", "1"]), ok);
+        assert_eq!(call(&stream, &["GET", "order:1"]), Value::Null);
+        assert_eq!(call(&stream, &["QUIT"]), ok);
+        assert_eq!(
+            resp::read_value(&mut BufReader::new(&stream)).unwrap(),
+            None
+        );
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_channels_are_shared_by_redis_native_and_library_clients() {
+        let (broker, db) = (Arc::new(Broker::new()), Arc::new(Database::new()));
+        let serve = |protocol| {
+            let server = ServerBuilder::new()
+                .bind_address("127.0.0.1:0")
+                .workers(2)
+                .protocol(protocol)
+                .broker(broker.clone())
+                .bind(db.clone())
+                .unwrap();
+            let address = server.local_addr().unwrap();
+            let shutdown = server.shutdown_handle();
+            (address, shutdown, thread::spawn(move || server.run()))
+        };
+        let (resp_address, resp_shutdown, resp_running) = serve(Protocol::Resp);
+        let (native_address, native_shutdown, native_running) = serve(Protocol::Native);
+        let library = broker.subscribe("orders.*");
+        let push = |values: &[Value]| Value::Array(values.to_vec());
+
+        let subscriber = TcpStream::connect(resp_address).unwrap();
+        let mut replies = BufReader::new(&subscriber);
+        let send = |command: &[&str]| {
+            let command = command.iter().map(|arg| bulk(arg)).collect();
+            let mut out = &subscriber;
+            resp::write_value(&mut out, &Value::Array(command), ProtocolVersion::Resp2).unwrap();
+        };
+        let mut reply = || resp::read_value(&mut replies).unwrap().unwrap();
+        send(&["SUBSCRIBE", "orders.created", "orders.created"]);
+        send(&["PSUBSCRIBE", "orders.*"]);
+        send(&["GET", "alice"]);
+        send(&["PING"]);
+        let subscribed = push(&[bulk("subscribe"), bulk("orders.created"), Value::Integer(1)]);
+        assert_eq!(reply(), subscribed);
+        assert_eq!(reply(), subscribed);
+        assert_eq!(
+            reply(),
+            push(&[bulk("psubscribe"), bulk("orders.*"), Value::Integer(2)])
+        );
+        assert!(matches!(reply(), Value::Error(message) if message.contains("context")));
+        assert_eq!(reply(), push(&[bulk("pong"), bulk("")]));
+
+        // published by a native client
+        let native = |stream: &TcpStream, request: Request| {
+            let mut payload = Vec::new();
+            request.encode(&mut payload);
+            let mut out = stream;
+            protocol::write_frame(&mut out, &payload).unwrap();
+        };
+        let publisher = TcpStream::connect(native_address).unwrap();
+        let publish = Request::Publish {
+            channel: "orders.created".to_string(),
+            payload: b"42".to_vec(),
+        };
+        native(&publisher, publish);
+        let payload = protocol::read_frame(&mut &publisher).unwrap().unwrap();
+        assert_eq!(
+            decode_all::<Response>(&payload).unwrap(),
+            Response::Published(3)
+        );
+        assert_eq!(
+            reply(),
+            push(&[bulk("message"), bulk("orders.created"), bulk("42")])
+        );
+        assert_eq!(
+            reply(),
+            push(&[
+                bulk("pmessage"),
+                bulk("orders.*"),
+                bulk("orders.created"),
+                bulk("42")
+            ])
+        );
+        let message = library.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message.payload, b"42");
+
+        // to a native client, by a Redis client
+        let native_subscriber = TcpStream::connect(native_address).unwrap();
+        let mut responses = BufReader::new(&native_subscriber);
+        let mut response = || {
+            let payload = protocol::read_frame(&mut responses).unwrap().unwrap();
+            decode_all::<Response>(&payload).unwrap()
+        };
+        let subscribe = Request::Subscribe {
+            pattern: "users.*".to_string(),
+        };
+        native(&native_subscriber, subscribe);
+        assert_eq!(response(), Response::Ok);
+        let stream = TcpStream::connect(resp_address).unwrap();
+        let published = call(&stream, &["PUBLISH", "users.created", "alice"]);
+        assert_eq!(published, Value::Integer(1));
+        assert_eq!(
+            response(),
+            Response::Message {
+                channel: "users.created".to_string(),
+                payload: b"alice".to_vec(),
+            }
+        );
+
+        // connections unsubscribed from everything take any command
+        send(&["UNSUBSCRIBE"]);
+        send(&["PUNSUBSCRIBE"]);
+        send(&["GET", "alice"]);
+        assert_eq!(
+            reply(),
+            push(&[
+                bulk("unsubscribe"),
+                bulk("orders.created"),
+                Value::Integer(1)
+            ])
+        );
+        assert_eq!(
+            reply(),
+            push(&[bulk("punsubscribe"), bulk("orders.*"), Value::Integer(0)])
+        );
+        assert_eq!(reply(), Value::Null);
+
+        resp_shutdown.shutdown();
+        native_shutdown.shutdown();
+        resp_running.join().unwrap().unwrap();
+        native_running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_scan_patterns() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"user:*", b"user:42"));
+        assert!(matches(b"*:4?", b"user:42"));
+        assert!(!matches(b"*:4?", b"user:4"));
+        assert!(matches(b"u*r*2", b"user:42"));
+        assert!(matches(b"[a-c]at", b"bat"));
+        assert!(!matches(b"[^a-c]at", b"bat"));
+        assert!(matches(b"\\*", b"*"));
+        assert!(!matches(b"\\*", b"x"));
+        assert!(matches(b"[ab", b"[ab"));
+    }
+}
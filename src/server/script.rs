@@ -0,0 +1,296 @@
+//! Lua scripts run by servers, with the `scripting` feature, see
+//! [`Request::Eval`].
+//!
+//! A script runs as a whole: the requests of the other native clients of
+//! the server wait for it to end, and its writes are made once it
+//! succeeds, at once as those of a [`Request::Atomic`] are, none being made
+//! if it fails.
+//! It reads and writes the keyspaces of the database through the functions
+//! of the `db` table, keys and values being Lua strings, and finds its
+//! arguments in the `ARGV` array:
+//!
+//! | Function                            | Returns                           |
+//! |-------------------------------------|-----------------------------------|
+//! | `db.get(keyspace, key)`             | the value of `key`, or `nil`      |
+//! | `db.put(keyspace, key, value)`      | nothing                           |
+//! | `db.delete(keyspace, key)`          | whether `key` was mapped          |
+//! | `db.scan(keyspace, prefix[, limit])`| an array of `{key, value}` arrays |
+//!
+//! Calls are checked against the [ACL](super::acl) of the server as the
+//! requests of the client would be, a call failing the script as a Lua
+//! error does. Scripts return `nil` or `false`, answered as a
+//! [`Response::Value`] of none, `true`, answered as `1`, a number, written
+//! in decimal, or a string, or an array of them, answered as a
+//! [`Response::Batch`] of values.
+//!
+//! Scripts are given the `string`, `table`, `math` and `utf8` libraries,
+//! but neither files nor `math.random`, so that they write the same on
+//! every node of a [Raft](crate::cluster) cluster, and are stopped past
+//! [`MAX_INSTRUCTIONS`] instructions or [`MAX_MEMORY`] bytes.
+//!
+//! # Examples
+//!
+//! ```
+//! use palladiumdb::db::Database;
+//! use palladiumdb::protocol::{Request, Response};
+//! use palladiumdb::server;
+//!
+//! let db = Database::new();
+//! let incr = Request::Eval {
+//!     script: r#"
+//!         local visits = tonumber(db.get("counters", ARGV[1]) or "0") + 1
+//!         db.put("counters", ARGV[1], tostring(visits))
+//!         return visits
+//!     "#
+//!     .to_string(),
+//!     args: vec![b"home".to_vec()],
+//! };
+//! let response = server::execute(&db, incr.clone()).unwrap();
+//! assert_eq!(response, Response::Value(Some(b"1".to_vec())));
+//! let response = server::execute(&db, incr).unwrap();
+//! assert_eq!(response, Response::Value(Some(b"2".to_vec())));
+//! ```
+
+use std::cell::{Cell, RefCell};
+
+use mlua::{Error, HookTriggers, Lua, LuaOptions, StdLib, Value};
+
+use crate::protocol::{Request, Response};
+
+/// Instructions past which scripts are stopped.
+pub const MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+/// Bytes of memory past which scripts are stopped.
+pub const MAX_MEMORY: usize = 64 << 20;
+
+/// Instructions run between two checks of [`MAX_INSTRUCTIONS`].
+const CHECK_INSTRUCTIONS: u32 = 10_000;
+
+type Execute<'a> = RefCell<&'a mut dyn FnMut(Request) -> Response>;
+
+/// Runs `script` with `args`, carrying out the requests of its calls
+/// through `execute`.
+///
+/// # Returns
+///
+/// The response the script answers with, [`Response::Error`] if it failed.
+pub(super) fn eval(
+    script: &str,
+    args: Vec<Vec<u8>>,
+    execute: &mut dyn FnMut(Request) -> Response,
+) -> Response {
+    match run(script, args, &RefCell::new(execute)) {
+        Ok(response) => response,
+        Err(err) => Response::Error(format!("script failed: {}", message(&err))),
+    }
+}
+
+fn run(script: &str, args: Vec<Vec<u8>>, execute: &Execute) -> mlua::Result<Response> {
+    let libs = StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8;
+    let lua = Lua::new_with(libs, LuaOptions::new())?;
+    lua.set_memory_limit(MAX_MEMORY)?;
+    let instructions = Cell::new(0);
+    let triggers = HookTriggers::new().every_nth_instruction(CHECK_INSTRUCTIONS);
+    lua.set_hook(triggers, move |_, _| {
+        instructions.set(instructions.get() + u64::from(CHECK_INSTRUCTIONS));
+        match instructions.get() > MAX_INSTRUCTIONS {
+            true => Err(Error::RuntimeError("too many instructions".to_string())),
+            false => Ok(()),
+        }
+    });
+    let globals = lua.globals();
+    for name in &["dofile", "loadfile", "print"] {
+        globals.set(*name, Value::Nil)?;
+    }
+    let math: mlua::Table = globals.get("math")?;
+    math.set("random", Value::Nil)?;
+    math.set("randomseed", Value::Nil)?;
+    let args = args.iter().map(|arg| lua.create_string(arg));
+    globals.set(
+        "ARGV",
+        lua.create_sequence_from(args.collect::<mlua::Result<Vec<_>>>()?)?,
+    )?;
+
+    lua.scope(|scope| {
+        let db = lua.create_table()?;
+        let get = scope.create_function(|lua, (keyspace, key): (String, mlua::String)| {
+            let get = Request::Get {
+                keyspace,
+                key: key.as_bytes().to_vec(),
+            };
+            match call(execute, get)? {
+                Response::Value(Some(value)) => Ok(Value::String(lua.create_string(&value)?)),
+                Response::Value(None) => Ok(Value::Nil),
+                response => Err(unexpected(response)),
+            }
+        })?;
+        db.set("get", get)?;
+        let put = scope.create_function(
+            |_, (keyspace, key, value): (String, mlua::String, mlua::String)| {
+                let put = Request::Put {
+                    keyspace,
+                    key: key.as_bytes().to_vec(),
+                    value: value.as_bytes().to_vec(),
+                };
+                match call(execute, put)? {
+                    Response::Ok => Ok(()),
+                    response => Err(unexpected(response)),
+                }
+            },
+        )?;
+        db.set("put", put)?;
+        let delete = scope.create_function(|_, (keyspace, key): (String, mlua::String)| {
+            let delete = Request::Delete {
+                keyspace,
+                key: key.as_bytes().to_vec(),
+            };
+            match call(execute, delete)? {
+                Response::Deleted(deleted) => Ok(deleted),
+                response => Err(unexpected(response)),
+            }
+        })?;
+        db.set("delete", delete)?;
+        let scan = scope.create_function(
+            |lua, (keyspace, prefix, limit): (String, mlua::String, Option<u64>)| {
+                let scan = Request::Scan {
+                    keyspace,
+                    prefix: prefix.as_bytes().to_vec(),
+                    limit: limit.unwrap_or(u64::MAX),
+                };
+                let entries = match call(execute, scan)? {
+                    Response::Entries(entries) => entries,
+                    response => return Err(unexpected(response)),
+                };
+                let entries = entries.iter().map(|(key, value)| {
+                    lua.create_sequence_from(vec![
+                        lua.create_string(key)?,
+                        lua.create_string(value)?,
+                    ])
+                });
+                lua.create_sequence_from(entries.collect::<mlua::Result<Vec<_>>>()?)
+            },
+        )?;
+        db.set("scan", scan)?;
+        globals.set("db", db)?;
+
+        match lua.load(script).set_name("script").eval()? {
+            Value::Table(values) => {
+                let values = values.sequence_values().map(|value| value.and_then(scalar));
+                let values = values.collect::<mlua::Result<Vec<_>>>()?;
+                Ok(Response::Batch(
+                    values.into_iter().map(Response::Value).collect(),
+                ))
+            }
+            value => scalar(value).map(Response::Value),
+        }
+    })
+}
+
+/// Carries out `request` through `execute`, failures being Lua errors.
+fn call(execute: &Execute, request: Request) -> mlua::Result<Response> {
+    match (execute.borrow_mut())(request) {
+        Response::Error(message) => Err(Error::RuntimeError(message)),
+        response => Ok(response),
+    }
+}
+
+fn unexpected(response: Response) -> Error {
+    Error::RuntimeError(format!("unexpected response {:?}", response))
+}
+
+/// Returns the value a script answers with for `value`.
+fn scalar(value: Value) -> mlua::Result<Option<Vec<u8>>> {
+    match value {
+        Value::Nil | Value::Boolean(false) => Ok(None),
+        Value::Boolean(true) => Ok(Some(b"1".to_vec())),
+        Value::Integer(integer) => Ok(Some(integer.to_string().into_bytes())),
+        Value::Number(number) => Ok(Some(number.to_string().into_bytes())),
+        Value::String(string) => Ok(Some(string.as_bytes().to_vec())),
+        value => {
+            let message = format!("scripts may not return a {}", value.type_name());
+            Err(Error::RuntimeError(message))
+        }
+    }
+}
+
+/// Returns the message of `err`, without the stack traceback of Lua.
+fn message(err: &Error) -> String {
+    match err {
+        Error::CallbackError { cause, .. } => message(cause),
+        Error::RuntimeError(message) => message.lines().next().unwrap_or_default().to_string(),
+        Error::SyntaxError { message, .. } => {
+            let message = message.lines().next().unwrap_or_default();
+            format!("syntax error: {}", message)
+        }
+        err => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use crate::protocol::{Request, Response};
+    use crate::server::execute;
+
+    fn eval(db: &Database, script: &str, args: &[&[u8]]) -> Response {
+        let eval = Request::Eval {
+            script: script.to_string(),
+            args: args.iter().map(|arg| arg.to_vec()).collect(),
+        };
+        execute(db, eval).unwrap()
+    }
+
+    fn error(response: Response) -> String {
+        match response {
+            Response::Error(message) => message,
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[test]
+    fn test_scripts_read_compute_and_write_as_a_whole() {
+        let db = Database::new();
+        let transfer = r#"
+            local from = tonumber(db.get("accounts", ARGV[1]) or "0")
+            local to = tonumber(db.get("accounts", ARGV[2]) or "0")
+            local amount = tonumber(ARGV[3])
+            db.put("accounts", ARGV[1], tostring(from - amount))
+            db.put("accounts", ARGV[2], tostring(to + amount))
+            if from < amount then
+                error("insufficient funds")
+            end
+            return {from - amount, to + amount}
+        "#;
+        eval(&db, r#"db.put("accounts", "alice", "100")"#, &[]);
+        let response = eval(&db, transfer, &[b"alice", b"bob", b"30"]);
+        let balances = vec![
+            Response::Value(Some(b"70".to_vec())),
+            Response::Value(Some(b"30".to_vec())),
+        ];
+        assert_eq!(response, Response::Batch(balances));
+
+        // failed scripts leave the keyspaces as they found them
+        let message = error(eval(&db, transfer, &[b"alice", b"bob", b"500"]));
+        assert!(message.contains("insufficient funds"), "{}", message);
+        let balances = r#"
+            local entries = db.scan("accounts", "")
+            assert(#entries == 2 and entries[1][1] == "alice")
+            return entries[1][2] .. "," .. entries[2][2]
+        "#;
+        let response = eval(&db, balances, &[]);
+        assert_eq!(response, Response::Value(Some(b"70,30".to_vec())));
+        let deleted = eval(&db, r#"return db.delete("accounts", "carol")"#, &[]);
+        assert_eq!(deleted, Response::Value(None));
+
+        // the failures of requests fail scripts, which are sandboxed and
+        // bounded
+        db.open_map::<u64, u64>("typed").unwrap();
+        let message = error(eval(&db, r#"db.put("typed", "a", "1")"#, &[]));
+        assert!(message.contains("\"typed\""), "{}", message);
+        assert!(error(eval(&db, "while true do end", &[])).contains("too many instructions"));
+        assert!(error(eval(&db, "return math.random()", &[])).contains("nil"));
+        assert!(error(eval(&db, r#"dofile("/etc/passwd")"#, &[])).contains("nil"));
+        assert!(error(eval(&db, "return {{}}", &[])).contains("table"));
+        assert!(error(eval(&db, "return (", &[])).contains("syntax"));
+    }
+}
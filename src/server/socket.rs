@@ -0,0 +1,210 @@
+//! The sockets servers listen on and serve connections through: TCP ones,
+//! or Unix domain sockets on Unix, bound to addresses starting with
+//! [`UNIX_SCHEME`], see [`ServerBuilder::bind_address`].
+//!
+//! Clients on the same host connecting through a Unix socket skip the TCP
+//! stack, and are let in by the permissions of its file, see
+//! `ServerBuilder::unix_socket_mode`.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use super::UNIX_SCHEME;
+
+#[cfg(doc)]
+use super::ServerBuilder;
+
+/// A socket a server listens on.
+pub(super) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(BoundUnix),
+}
+
+/// A Unix socket listened on, whose file is removed once it is dropped.
+#[cfg(unix)]
+pub(super) struct BoundUnix {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for BoundUnix {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Listener {
+    /// Binds to `address`, a Unix socket if it starts with [`UNIX_SCHEME`],
+    /// whose file is given the permissions `mode` if set, accepting
+    /// connections without blocking.
+    ///
+    /// # Returns
+    ///
+    /// The error of binding, of kind `AddrInUse` if a server listens on the
+    /// Unix socket already, or `Unsupported` for Unix sockets off Unix.
+    pub(super) fn bind(address: &str, mode: Option<u32>) -> io::Result<Listener> {
+        if let Some(path) = address.strip_prefix(UNIX_SCHEME) {
+            return bind_unix(path, mode);
+        }
+        let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+        let listener = TcpListener::bind(&addresses[..])?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Accepts a connection, failing with `WouldBlock` if none waits.
+    pub(super) fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => Ok(Socket::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(bound) => Ok(Socket::Unix(bound.listener.accept()?.0)),
+        }
+    }
+
+    /// Returns the address listened on.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `Unsupported` for Unix sockets.
+    pub(super) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            Listener::Unix(_) => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "the server listens on a Unix socket",
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, mode: Option<u32>) -> io::Result<Listener> {
+    let listener = match UnixListener::bind(path) {
+        // the file of a server gone without removing it is taken over
+        Err(err) if err.kind() == ErrorKind::AddrInUse => match UnixStream::connect(path) {
+            Err(refused) if refused.kind() == ErrorKind::ConnectionRefused => {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            _ => return Err(err),
+        },
+        listener => listener?,
+    };
+    let bound = BoundUnix {
+        listener,
+        path: PathBuf::from(path),
+    };
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    bound.listener.set_nonblocking(true)?;
+    Ok(Listener::Unix(bound))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_: &str, _: Option<u32>) -> io::Result<Listener> {
+    let message = "Unix sockets are supported on Unix only";
+    Err(io::Error::new(ErrorKind::Unsupported, message))
+}
+
+/// A connection accepted by a [`Listener`].
+pub(super) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Makes reads and writes block, sending small writes right away.
+    pub(super) fn set_blocking(&self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => {
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)
+            }
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_nonblocking(false),
+        }
+    }
+
+    pub(super) fn try_clone(&self) -> io::Result<Socket> {
+        match self {
+            Socket::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.try_clone().map(Socket::Unix),
+        }
+    }
+
+    pub(super) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub(super) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for &Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
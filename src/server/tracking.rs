@@ -0,0 +1,141 @@
+//! Keys read by the native clients of a server tracking them, see
+//! [`Request::Tracking`], for clients to cache their values.
+//!
+//! A key read with [`Request::Get`] by a connection tracking keys is
+//! remembered until it is written through the server, by any client, the
+//! connection being sent a [`Response::Invalidate`] then. Keys are
+//! remembered before they are read, so that a write racing with the read
+//! is never missed, at the cost of an invalidation of a value that may be
+//! the latest already. Past [`MAX_TRACKED_KEYS`], the keys tracked the
+//! longest are invalidated to make room.
+//!
+//! Writes made to the database other than through the native clients of
+//! the server, by Redis clients or the database itself, are not seen.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+#[cfg(doc)]
+use crate::protocol::{Request, Response};
+
+/// Keys tracked by a server at most.
+pub const MAX_TRACKED_KEYS: usize = 1 << 20;
+
+/// A key of a keyspace.
+type Key = (String, Vec<u8>);
+
+/// The keys read by the connections tracking them.
+#[derive(Default)]
+pub(super) struct Tracking {
+    table: Mutex<Table>,
+    /// The number of connections tracking keys, writes being left alone
+    /// while there are none.
+    tracking: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Table {
+    /// The connections tracking keys, by id, sent the keys invalidated.
+    connections: HashMap<u64, Sender<Key>>,
+    /// The connections each key was read by, and the number it was tracked
+    /// as.
+    keys: HashMap<Key, (u64, HashSet<u64>)>,
+    /// The keys by the number they were tracked as, in order, some of them
+    /// since invalidated.
+    order: VecDeque<(u64, Key)>,
+    next: u64,
+}
+
+impl Table {
+    /// Sends the invalidation of `key` to the connections that read it,
+    /// forgetting it.
+    fn invalidate(&mut self, key: Key) {
+        if let Some((_, readers)) = self.keys.remove(&key) {
+            for reader in readers {
+                if let Some(connection) = self.connections.get(&reader) {
+                    let _ = connection.send(key.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Tracking {
+    /// Makes the connection `id` track the keys it reads.
+    ///
+    /// # Returns
+    ///
+    /// The receiver of the keys it read invalidated, disconnected once it
+    /// stops tracking.
+    pub(super) fn enable(&self, id: u64) -> Receiver<(String, Vec<u8>)> {
+        let (sender, receiver) = mpsc::channel();
+        let mut table = self.table.lock().unwrap();
+        if table.connections.insert(id, sender).is_none() {
+            self.tracking.fetch_add(1, Ordering::SeqCst);
+        }
+        receiver
+    }
+
+    /// Makes the connection `id` stop tracking keys, the keys it read being
+    /// forgotten as they are invalidated.
+    pub(super) fn disable(&self, id: u64) {
+        if self.table.lock().unwrap().connections.remove(&id).is_some() {
+            self.tracking.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Remembers that the connection `id` reads `key` of `keyspace`, if it
+    /// tracks keys.
+    pub(super) fn read(&self, id: u64, keyspace: &str, key: &[u8]) {
+        let mut table = self.table.lock().unwrap();
+        if !table.connections.contains_key(&id) {
+            return;
+        }
+        let key = (keyspace.to_string(), key.to_vec());
+        if let Some((_, readers)) = table.keys.get_mut(&key) {
+            readers.insert(id);
+            return;
+        }
+        let table = &mut *table;
+        while table.keys.len() >= MAX_TRACKED_KEYS {
+            match table.order.pop_front() {
+                Some((number, oldest)) if tracked(&table.keys, number, &oldest) => {
+                    table.invalidate(oldest)
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let number = table.next;
+        table.next += 1;
+        table
+            .keys
+            .insert(key.clone(), (number, HashSet::from([id])));
+        table.order.push_back((number, key));
+        // keys since invalidated are dropped from the order as it grows
+        if table.order.len() > 2 * table.keys.len() {
+            let keys = &table.keys;
+            table
+                .order
+                .retain(|(number, key)| tracked(keys, *number, key));
+        }
+    }
+
+    /// Invalidates `key` of `keyspace`, written, for the connections that
+    /// read it.
+    pub(super) fn write(&self, keyspace: &str, key: &[u8]) {
+        if self.tracking.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let mut table = self.table.lock().unwrap();
+        table.invalidate((keyspace.to_string(), key.to_vec()));
+    }
+}
+
+/// Returns whether `key` is tracked as `number`, not having been
+/// invalidated since.
+fn tracked(keys: &HashMap<Key, (u64, HashSet<u64>)>, number: u64, key: &Key) -> bool {
+    keys.get(key).is_some_and(|(tracked, _)| *tracked == number)
+}
@@ -0,0 +1,689 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{read_at, KeyRange, StorageEngine, StorageSnapshot, VacuumReport};
+use crate::checksum::crc32_update;
+use crate::codec::invalid_data;
+use crate::verify::VerifyReport;
+
+/// Tags of the values stored in the wrapped engine: the value itself, or a
+/// pointer to the blob holding it.
+const INLINE: u8 = 0;
+const POINTER: u8 = 1;
+
+/// Length of a stored pointer: its tag, then the segment, offset and length
+/// of the record of the blob.
+const POINTER_LEN: usize = 25;
+
+/// Length of the header of a record: the lengths of its key and value.
+const HEADER_LEN: usize = 12;
+
+/// Number of locks the keys are spread over, see `BlobEngine::stripes`.
+const STRIPES: usize = 64;
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:06}.vlog", id))
+}
+
+/// Configures and opens a [`BlobEngine`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{BlobBuilder, MemoryEngine, StorageEngine};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-blob-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = BlobBuilder::new()
+///     .threshold(1024)
+///     .segment_size(16 << 20)
+///     .open(&dir, MemoryEngine::new())
+///     .unwrap();
+///
+/// engine.put(b"small", b"inline").unwrap();
+/// engine.put(b"large", &[7; 4096]).unwrap();
+/// assert_eq!(engine.get(b"large").unwrap(), Some(vec![7; 4096]));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct BlobBuilder {
+    threshold: usize,
+    segment_size: u64,
+    gc_ratio: f64,
+    sync_writes: bool,
+}
+
+impl Default for BlobBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlobBuilder {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        BlobBuilder {
+            threshold: 32 << 10,
+            segment_size: 64 << 20,
+            gc_ratio: 0.5,
+            sync_writes: false,
+        }
+    }
+
+    /// Sets the size in bytes from which values are stored as blobs, out of
+    /// the wrapped engine.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the size in bytes past which blobs go to a new segment of the
+    /// value log.
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Sets the share of garbage, from 0 to 1, from which
+    /// [`BlobEngine::collect_garbage`] rewrites a segment.
+    pub fn gc_ratio(mut self, gc_ratio: f64) -> Self {
+        self.gc_ratio = gc_ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Makes every write of a blob synced to disk before its pointer is
+    /// stored, for wrapped engines syncing their own writes, which could
+    /// otherwise keep the pointer to a blob lost in a crash.
+    pub fn sync_writes(mut self, sync_writes: bool) -> Self {
+        self.sync_writes = sync_writes;
+        self
+    }
+
+    /// Opens the value log stored in `dir`, creating the directory if
+    /// needed, in front of `engine`, which must have been wrapped by a
+    /// `BlobEngine` with the same `dir` since it was created.
+    ///
+    /// Blobs go to a new segment, the tail of the last one possibly being
+    /// torn by a crash.
+    pub fn open<E: StorageEngine, P: AsRef<Path>>(
+        self,
+        dir: P,
+        engine: E,
+    ) -> io::Result<BlobEngine<E>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut segments = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".vlog")?.parse::<u64>().ok());
+            if let Some(id) = id {
+                segments.insert(id, Arc::new(File::open(&path)?));
+            }
+        }
+        let id = segments.keys().next_back().map_or(0, |id| id + 1);
+        let head = Head::create(&dir, id)?;
+        segments.insert(id, head.reader.clone());
+        Ok(BlobEngine {
+            inner: engine,
+            dir,
+            options: self,
+            head: Mutex::new(head),
+            segments: RwLock::new(segments),
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+            snapshots: AtomicUsize::new(0),
+            collecting: Mutex::new(()),
+        })
+    }
+}
+
+/// What a call to [`BlobEngine::collect_garbage`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlobGcStats {
+    /// Number of segments checked for garbage.
+    pub segments_checked: u64,
+    /// Number of segments removed.
+    pub segments_removed: u64,
+    /// Number of live blobs moved out of the segments removed.
+    pub blobs_moved: u64,
+    /// Bytes of orphaned blobs freed.
+    pub bytes_reclaimed: u64,
+}
+
+/// Storage engine keeping large values out of a wrapped engine, in a value
+/// log, as WiscKey does.
+///
+/// Values of at least [`BlobBuilder::threshold`] bytes are appended to the
+/// current segment of the log as blobs, along with their key, and the
+/// wrapped engine only stores a pointer to them, so that its own writes,
+/// logs and compactions only ever handle small values. Smaller values are
+/// stored in the wrapped engine.
+///
+/// Overwriting or deleting a key orphans its blob, which
+/// [`BlobEngine::collect_garbage`] reclaims: segments with enough garbage
+/// have their live blobs moved to the current segment, then are removed.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{BlobBuilder, MemoryEngine, StorageEngine};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-blob-engine");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = BlobBuilder::new()
+///     .threshold(1024)
+///     .segment_size(4096)
+///     .open(&dir, MemoryEngine::new())
+///     .unwrap();
+///
+/// for version in 0..4u8 {
+///     engine.put(b"video", &[version; 3000]).unwrap();
+/// }
+/// let stats = engine.collect_garbage().unwrap();
+/// assert_eq!(stats.segments_removed, 3);
+/// assert_eq!(engine.get(b"video").unwrap(), Some(vec![3; 3000]));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct BlobEngine<E> {
+    inner: E,
+    dir: PathBuf,
+    options: BlobBuilder,
+    head: Mutex<Head>,
+    // every segment, opened for reading, write-locked to remove them so
+    // that readers never follow a pointer to a removed segment
+    segments: RwLock<BTreeMap<u64, Arc<File>>>,
+    // serialize the writes of a key with the collection moving its blob,
+    // which would otherwise overwrite them with the moved pointer
+    stripes: Vec<Mutex<()>>,
+    // snapshots open, whose pointers keep every segment alive
+    snapshots: AtomicUsize,
+    collecting: Mutex<()>,
+}
+
+/// The segment blobs are appended to.
+struct Head {
+    id: u64,
+    file: File,
+    reader: Arc<File>,
+    len: u64,
+}
+
+impl Head {
+    fn create(dir: &Path, id: u64) -> io::Result<Self> {
+        let path = segment_path(dir, id);
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        Ok(Head {
+            id,
+            len: file.metadata()?.len(),
+            file,
+            reader: Arc::new(File::open(&path)?),
+        })
+    }
+}
+
+/// Location of the record of a blob in the value log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Pointer {
+    segment: u64,
+    offset: u64,
+    len: u64,
+}
+
+impl Pointer {
+    fn encode(&self) -> Vec<u8> {
+        let mut stored = Vec::with_capacity(POINTER_LEN);
+        stored.push(POINTER);
+        stored.extend_from_slice(&self.segment.to_le_bytes());
+        stored.extend_from_slice(&self.offset.to_le_bytes());
+        stored.extend_from_slice(&self.len.to_le_bytes());
+        stored
+    }
+
+    fn decode(stored: &[u8]) -> io::Result<Self> {
+        if stored.len() != POINTER_LEN {
+            return Err(invalid_data("blob pointer corrupted"));
+        }
+        let field = |at: usize| u64::from_le_bytes(stored[at..at + 8].try_into().unwrap());
+        Ok(Pointer {
+            segment: field(1),
+            offset: field(9),
+            len: field(17),
+        })
+    }
+}
+
+/// Returns the record of the blob `value` of `key`: the lengths of the key
+/// and value, the key, the value, then a checksum of all of them.
+fn encode_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + key.len() + value.len() + 4);
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    record.extend_from_slice(key);
+    record.extend_from_slice(value);
+    let crc = crc32_update(0, &record);
+    record.extend_from_slice(&crc.to_le_bytes());
+    record
+}
+
+/// Checks the checksum of `record`, and splits it into its key and value.
+fn decode_record(record: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let corrupted = || invalid_data("blob record corrupted");
+    if record.len() < HEADER_LEN + 4 {
+        return Err(corrupted());
+    }
+    let (body, crc) = record.split_at(record.len() - 4);
+    if crc32_update(0, body).to_le_bytes() != crc {
+        return Err(invalid_data("blob record checksum mismatch"));
+    }
+    let key_len = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+    let value_len = u64::from_le_bytes(body[4..HEADER_LEN].try_into().unwrap());
+    if (HEADER_LEN + key_len) as u64 + value_len != body.len() as u64 {
+        return Err(corrupted());
+    }
+    Ok(body[HEADER_LEN..].split_at(key_len))
+}
+
+/// Reads the next record of a segment, `None` at its end.
+///
+/// # Returns
+///
+/// The record, an error of kind `UnexpectedEof` if it is torn, or of kind
+/// `InvalidData` if it is corrupted.
+fn read_record<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut record = vec![0; HEADER_LEN];
+    match input.read(&mut record[..1])? {
+        0 => return Ok(None),
+        _ => input.read_exact(&mut record[1..])?,
+    }
+    let key_len = u64::from(u32::from_le_bytes(record[..4].try_into().unwrap()));
+    let value_len = u64::from_le_bytes(record[4..].try_into().unwrap());
+    let len = key_len.saturating_add(value_len).saturating_add(4);
+    // through `take` so that a corrupted length does not allocate more than
+    // the segment holds
+    input.take(len).read_to_end(&mut record)?;
+    if ((record.len() - HEADER_LEN) as u64) < len {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "torn blob record"));
+    }
+    decode_record(&record)?;
+    Ok(Some(record))
+}
+
+impl<E: StorageEngine> BlobEngine<E> {
+    /// Returns the wrapped engine, which stores the pointers to the blobs.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Returns the directory of the value log.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the ids of the segments of the value log, in the order they
+    /// were written.
+    pub fn segments(&self) -> Vec<u64> {
+        self.segments.read().unwrap().keys().copied().collect()
+    }
+
+    fn stripe(&self, key: &[u8]) -> &Mutex<()> {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[hasher.finish() as usize % STRIPES]
+    }
+
+    /// Appends the blob `value` of `key` to the value log.
+    fn append(&self, key: &[u8], value: &[u8]) -> io::Result<Pointer> {
+        let record = encode_record(key, value);
+        let mut head = self.head.lock().unwrap();
+        if head.len > 0 && head.len + record.len() as u64 > self.options.segment_size {
+            head.file.sync_data()?;
+            let next = Head::create(&self.dir, head.id + 1)?;
+            let mut segments = self.segments.write().unwrap();
+            segments.insert(next.id, next.reader.clone());
+            *head = next;
+        }
+        head.file.write_all(&record)?;
+        if self.options.sync_writes {
+            head.file.sync_data()?;
+        }
+        let pointer = Pointer {
+            segment: head.id,
+            offset: head.len,
+            len: record.len() as u64,
+        };
+        head.len += record.len() as u64;
+        Ok(pointer)
+    }
+
+    /// Returns the value `stored` in the wrapped engine for `key`, read from
+    /// the value log if it is a pointer, among `segments`.
+    fn resolve(
+        &self,
+        segments: &BTreeMap<u64, Arc<File>>,
+        key: &[u8],
+        mut stored: Vec<u8>,
+    ) -> io::Result<Vec<u8>> {
+        match stored.first() {
+            Some(&INLINE) => {
+                stored.remove(0);
+                Ok(stored)
+            }
+            Some(&POINTER) => {
+                let pointer = Pointer::decode(&stored)?;
+                let file = segments
+                    .get(&pointer.segment)
+                    .ok_or_else(|| invalid_data("blob pointer to a removed segment"))?;
+                let mut record = vec![0; pointer.len as usize];
+                read_at(file, &mut record, pointer.offset)?;
+                let (record_key, value) = decode_record(&record)?;
+                if record_key != key {
+                    return Err(invalid_data("blob pointer to another key"));
+                }
+                Ok(value.to_vec())
+            }
+            _ => Err(invalid_data("stored value tag unknown")),
+        }
+    }
+
+    /// Reclaims the blobs orphaned by overwrites and deletes: every segment
+    /// but the current one whose share of orphaned blobs is at least
+    /// [`BlobBuilder::gc_ratio`] has its live blobs moved to the current
+    /// segment, then is removed once they are flushed.
+    ///
+    /// Writes go on during a collection, only waiting for the moves of the
+    /// blobs of their keys. Segments are not removed while snapshots of the
+    /// engine are open, which may point to them, but are left for the next
+    /// collection.
+    ///
+    /// # Returns
+    ///
+    /// What was collected, or an error of kind `InvalidData` if a segment is
+    /// corrupted, in which case it is left as it is.
+    pub fn collect_garbage(&self) -> io::Result<BlobGcStats> {
+        self.collect_segments(self.options.gc_ratio)
+    }
+
+    /// Collects the segments whose share of orphaned blobs is at least
+    /// `gc_ratio`, see [`BlobEngine::collect_garbage`].
+    fn collect_segments(&self, gc_ratio: f64) -> io::Result<BlobGcStats> {
+        let _collecting = self.collecting.lock().unwrap();
+        let current = self.head.lock().unwrap().id;
+        let mut stats = BlobGcStats::default();
+        for id in self.segments().into_iter().filter(|id| *id < current) {
+            stats.segments_checked += 1;
+            let path = segment_path(&self.dir, id);
+            let len = fs::metadata(&path)?.len();
+            let mut input = BufReader::new(File::open(&path)?);
+            let mut live = Vec::new();
+            let mut offset = 0;
+            let mut live_bytes = 0;
+            while let Some(record) = read_record(&mut input)? {
+                let (key, _) = decode_record(&record)?;
+                let pointer = Pointer {
+                    segment: id,
+                    offset,
+                    len: record.len() as u64,
+                };
+                if self.inner.get(key)? == Some(pointer.encode()) {
+                    live_bytes += pointer.len;
+                    live.push((key.to_vec(), pointer));
+                }
+                offset += record.len() as u64;
+            }
+            let garbage = len - live_bytes;
+            if len > 0 && (garbage as f64) < gc_ratio * len as f64 {
+                continue;
+            }
+
+            for (key, pointer) in live {
+                let _stripe = self.stripe(&key).lock().unwrap();
+                // written again since it was found live
+                if self.inner.get(&key)? != Some(pointer.encode()) {
+                    continue;
+                }
+                let file = self.segments.read().unwrap()[&id].clone();
+                let mut record = vec![0; pointer.len as usize];
+                read_at(&file, &mut record, pointer.offset)?;
+                let (_, value) = decode_record(&record)?;
+                let moved = self.append(&key, value)?;
+                self.inner.put(&key, &moved.encode())?;
+                stats.blobs_moved += 1;
+            }
+            // the moved blobs must not be lost with the segment in a crash
+            self.flush()?;
+
+            let mut segments = self.segments.write().unwrap();
+            if self.snapshots.load(Ordering::SeqCst) > 0 {
+                continue;
+            }
+            segments.remove(&id);
+            fs::remove_file(&path)?;
+            stats.segments_removed += 1;
+            stats.bytes_reclaimed += garbage;
+        }
+        Ok(stats)
+    }
+}
+
+impl<E: StorageEngine> StorageEngine for BlobEngine<E> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let segments = self.segments.read().unwrap();
+        match self.inner.get(key)? {
+            Some(stored) => self.resolve(&segments, key, stored).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let _stripe = self.stripe(key).lock().unwrap();
+        if value.len() >= self.options.threshold {
+            let pointer = self.append(key, value)?;
+            return self.inner.put(key, &pointer.encode());
+        }
+        let mut stored = Vec::with_capacity(value.len() + 1);
+        stored.push(INLINE);
+        stored.extend_from_slice(value);
+        self.inner.put(key, &stored)
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        let _stripe = self.stripe(key).lock().unwrap();
+        self.inner.delete(key)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let segments = self.segments.read().unwrap();
+        self.inner
+            .scan(range)?
+            .into_iter()
+            .map(|(key, stored)| {
+                let value = self.resolve(&segments, &key, stored)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// Syncs the current segment of the value log, then flushes the wrapped
+    /// engine, so that no pointer reaches disk before its blob.
+    fn flush(&self) -> io::Result<()> {
+        self.head.lock().unwrap().file.sync_data()?;
+        self.inner.flush()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.head.lock().unwrap().file.sync_data()?;
+        self.inner.close()
+    }
+
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        // under the lock removing segments, which checks for snapshots
+        let _segments = self.segments.read().unwrap();
+        let inner = self.inner.snapshot()?;
+        self.snapshots.fetch_add(1, Ordering::SeqCst);
+        Ok(Box::new(BlobSnapshot {
+            engine: self,
+            inner,
+        }))
+    }
+
+    /// Vacuums the wrapped engine, then collects the segments of the value
+    /// log with at least `garbage_ratio` of orphaned blobs, see
+    /// [`BlobEngine::collect_garbage`].
+    fn vacuum(&self, garbage_ratio: f64) -> io::Result<VacuumReport> {
+        let mut report = self.inner.vacuum(garbage_ratio)?;
+        // a ratio of 0 would move the blobs of segments without garbage
+        let stats = self.collect_segments(garbage_ratio.max(f64::MIN_POSITIVE))?;
+        report.merge(VacuumReport {
+            files_rewritten: 0,
+            files_removed: stats.segments_removed,
+            bytes_reclaimed: stats.bytes_reclaimed,
+        });
+        Ok(report)
+    }
+
+    fn verify(&self) -> io::Result<VerifyReport> {
+        let mut report = self.inner.verify()?;
+        for id in self.segments() {
+            let path = segment_path(&self.dir, id);
+            let len = fs::metadata(&path)?.len();
+            let mut input = BufReader::new(File::open(&path)?);
+            let mut offset = 0;
+            loop {
+                match read_record(&mut input) {
+                    Ok(Some(record)) => offset += record.len() as u64,
+                    Ok(None) => break,
+                    Err(err) => {
+                        report.corrupted(&path, offset, len - offset, &err);
+                        break;
+                    }
+                }
+            }
+            report.checked(len);
+        }
+        Ok(report)
+    }
+}
+
+/// Snapshot of a [`BlobEngine`], resolving the pointers of a snapshot of the
+/// wrapped engine, whose segments are kept while it is open.
+struct BlobSnapshot<'a, E> {
+    engine: &'a BlobEngine<E>,
+    inner: Box<dyn StorageSnapshot + 'a>,
+}
+
+impl<E: StorageEngine> StorageSnapshot for BlobSnapshot<'_, E> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let segments = self.engine.segments.read().unwrap();
+        match self.inner.get(key)? {
+            Some(stored) => self.engine.resolve(&segments, key, stored).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let segments = self.engine.segments.read().unwrap();
+        self.inner
+            .scan(range)?
+            .into_iter()
+            .map(|(key, stored)| {
+                let value = self.engine.resolve(&segments, &key, stored)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+impl<E> Drop for BlobSnapshot<'_, E> {
+    fn drop(&mut self) {
+        self.engine.snapshots.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{segment_path, BlobBuilder};
+    use crate::storage::{LsmBuilder, StorageEngine};
+    use std::fs;
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_blobs_survive_reopen_collection_and_concurrent_writes() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-blob-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let blobs = BlobBuilder::new().threshold(100).segment_size(10_000);
+        let engine = blobs
+            .clone()
+            .open(
+                dir.join("vlog"),
+                LsmBuilder::new().open(dir.join("lsm")).unwrap(),
+            )
+            .unwrap();
+        engine.put(b"small", b"inline").unwrap();
+        for key in 0..20u8 {
+            engine.put(&[key], &vec![key; 1000]).unwrap();
+        }
+        assert!(engine.segments().len() > 1);
+        // the wrapped engine only holds pointers
+        assert_eq!(engine.inner().get(&[3]).unwrap().unwrap().len(), 25);
+        engine.flush().unwrap();
+        drop(engine);
+
+        let engine = Arc::new(
+            blobs
+                .open(
+                    dir.join("vlog"),
+                    LsmBuilder::new().open(dir.join("lsm")).unwrap(),
+                )
+                .unwrap(),
+        );
+        assert_eq!(engine.get(b"small").unwrap(), Some(b"inline".to_vec()));
+        assert_eq!(engine.get(&[7]).unwrap(), Some(vec![7; 1000]));
+
+        // orphan most blobs, while collecting in parallel
+        let snapshot = engine.snapshot().unwrap();
+        let writer = {
+            let engine = engine.clone();
+            thread::spawn(move || {
+                for key in 0..15u8 {
+                    match key % 2 {
+                        0 => assert!(engine.delete(&[key]).unwrap()),
+                        _ => engine.put(&[key], &vec![key + 100; 1000]).unwrap(),
+                    }
+                }
+            })
+        };
+        let first = engine.segments()[0];
+        let kept = engine.collect_garbage().unwrap();
+        assert_eq!(kept.segments_removed, 0);
+        assert!(segment_path(engine.dir(), first).exists());
+        assert_eq!(snapshot.get(&[2]).unwrap(), Some(vec![2; 1000]));
+        drop(snapshot);
+        writer.join().unwrap();
+
+        let stats = engine.collect_garbage().unwrap();
+        assert!(stats.segments_removed > 0 && stats.bytes_reclaimed > 0);
+        assert!(!segment_path(engine.dir(), first).exists());
+        let entries = engine.scan((Bound::Unbounded, Bound::Unbounded)).unwrap();
+        assert_eq!(entries.len(), 13);
+        for (key, value) in entries.iter().filter(|(key, _)| key.len() == 1) {
+            let byte = if key[0] < 15 { key[0] + 100 } else { key[0] };
+            assert_eq!(value, &vec![byte; 1000]);
+        }
+        assert!(engine.verify().unwrap().is_clean());
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,973 @@
+mod page;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use self::page::{Meta, Node, PageId, Value, META_SLOTS, PAGE_OVERHEAD};
+use super::{BlockCache, KeyRange, StorageEngine, StorageSnapshot};
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+use crate::verify::{self, VerifyReport};
+
+/// Name of the data file within the directory of the engine.
+pub const DATA_FILE: &str = "tree.btree";
+
+/// Configures and opens a [`BTreeEngine`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::BTreeBuilder;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-btree-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = BTreeBuilder::new()
+///     .page_size(8192)
+///     .max_dirty_pages(256)
+///     .open(&dir)
+///     .unwrap();
+/// # drop(engine);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct BTreeBuilder {
+    page_size: usize,
+    max_dirty_pages: usize,
+    block_cache: Arc<BlockCache>,
+}
+
+impl Default for BTreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BTreeBuilder {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        BTreeBuilder {
+            page_size: 4096,
+            max_dirty_pages: 1024,
+            block_cache: Arc::new(BlockCache::default()),
+        }
+    }
+
+    /// Sets the size in bytes of the pages of a new tree. Existing trees
+    /// keep the page size they were created with.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `page_size` is not a power of two
+    /// between 1024 and 65536.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        if !page_size.is_power_of_two() || !(1024..=65536).contains(&page_size) {
+            panic!()
+        }
+        self.page_size = page_size;
+        self
+    }
+
+    /// Sets the number of pages written since the last commit past which
+    /// the engine commits on its own, bounding the memory they take.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_dirty_pages` is 0.
+    pub fn max_dirty_pages(mut self, max_dirty_pages: usize) -> Self {
+        if max_dirty_pages == 0 {
+            panic!()
+        }
+        self.max_dirty_pages = max_dirty_pages;
+        self
+    }
+
+    /// Sets the cache the pages read are kept in, which may be shared with
+    /// other engines. Defaults to a cache of 8 MiB shared by the engines
+    /// opened from this builder.
+    pub fn block_cache(mut self, block_cache: Arc<BlockCache>) -> Self {
+        self.block_cache = block_cache;
+        self
+    }
+
+    /// Opens the engine stored in `dir`, creating the directory and the data
+    /// file if needed.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if the data file is not a B-tree.
+    pub fn open<P: AsRef<Path>>(self, dir: P) -> io::Result<BTreeEngine> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(DATA_FILE))?;
+
+        let meta = match file.metadata()?.len() {
+            0 => {
+                let meta = Meta {
+                    page_size: self.page_size as u32,
+                    txn: 0,
+                    root: 0,
+                    page_count: 1,
+                    entries: 0,
+                    freelist: 0,
+                };
+                file.set_len(self.page_size as u64)?;
+                meta.write(&file)?;
+                file.sync_all()?;
+                meta
+            }
+            len if len < META_SLOTS[1] + 512 => {
+                return Err(io::Error::new(ErrorKind::InvalidData, "truncated B-tree"))
+            }
+            _ => Meta::read(&file)?
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not a B-tree"))?,
+        };
+
+        let page_size = meta.page_size as usize;
+        let (mut free, mut freelist_pages) = (Vec::new(), Vec::new());
+        let mut next = meta.freelist;
+        while next != 0 {
+            freelist_pages.push(next);
+            let (following, ids) = page::read_freelist(&file, next, page_size)?;
+            free.extend(ids);
+            next = following;
+        }
+
+        Ok(BTreeEngine {
+            dir,
+            block_cache: self.block_cache.clone(),
+            tree: PriorityRwLock::new(Tree {
+                file,
+                page_size,
+                max_dirty_pages: self.max_dirty_pages,
+                cache_file: self.block_cache.file_id(),
+                cache: self.block_cache.clone(),
+                meta,
+                root: meta.root,
+                entries: meta.entries,
+                page_count: meta.page_count,
+                dirty: HashMap::new(),
+                free,
+                pending_free: Vec::new(),
+                freelist_pages,
+                pins: BTreeMap::new(),
+                retained: Vec::new(),
+                changed: false,
+            }),
+            lock_policy: LockPolicy::default(),
+        })
+    }
+}
+
+/// The tree, along with the pages written since the last commit.
+struct Tree {
+    file: File,
+    page_size: usize,
+    max_dirty_pages: usize,
+    cache: Arc<BlockCache>,
+    /// Id of the data file in the cache.
+    cache_file: u64,
+    /// State of the tree as of the last commit.
+    meta: Meta,
+    root: PageId,
+    entries: u64,
+    page_count: u64,
+    /// Nodes written since the last commit, which no committed page refers
+    /// to, so they are updated in place.
+    dirty: HashMap<PageId, Node>,
+    /// Pages no longer referred to, committed or not.
+    free: Vec<PageId>,
+    /// Pages freed since the last commit, still referred to by the tree as
+    /// of the last commit, so only reused once the next one completes.
+    pending_free: Vec<PageId>,
+    /// Pages holding the free list of the last commit.
+    freelist_pages: Vec<PageId>,
+    /// Number of snapshots alive per commit they read the tree of.
+    pins: BTreeMap<u64, usize>,
+    /// Pages freed by each commit while snapshots of earlier ones were
+    /// alive, only reused once they are all dropped.
+    retained: Vec<(u64, Vec<PageId>)>,
+    changed: bool,
+}
+
+impl Tree {
+    /// Keys and values longer than this, together, have their value stored
+    /// in overflow pages, so that leaves hold at least a few entries.
+    fn inline_limit(&self) -> usize {
+        self.page_size / 4 - PAGE_OVERHEAD
+    }
+
+    /// Returns the page `id` as of the last commit, through the cache.
+    fn page(&self, id: PageId) -> io::Result<Arc<[u8]>> {
+        let key = (self.cache_file, id);
+        if let Some(page) = self.cache.get(key) {
+            return Ok(page);
+        }
+        let page: Arc<[u8]> = page::read_page(&self.file, id, self.page_size)?.into();
+        // every read goes down the branches
+        self.cache.insert(key, page.clone(), page::is_branch(&page));
+        Ok(page)
+    }
+
+    /// Writes `node` to page `id` of the file, dropping the page from the
+    /// cache.
+    fn write_page(&self, id: PageId, node: &Node) -> io::Result<()> {
+        self.cache.remove((self.cache_file, id));
+        page::write_node(&self.file, id, node, self.page_size)
+    }
+
+    fn node(&self, id: PageId) -> io::Result<Cow<'_, Node>> {
+        match self.dirty.get(&id) {
+            Some(node) => Ok(Cow::Borrowed(node)),
+            None => page::node_of(&self.page(id)?).map(Cow::Owned),
+        }
+    }
+
+    fn allocate(&mut self) -> PageId {
+        self.free.pop().unwrap_or_else(|| {
+            self.page_count += 1;
+            self.page_count - 1
+        })
+    }
+
+    fn free_page(&mut self, id: PageId) {
+        match self.dirty.remove(&id) {
+            Some(_) => self.free.push(id),
+            None => self.pending_free.push(id),
+        }
+    }
+
+    /// Writes `node` in place of the page `old` if it was written since the
+    /// last commit, to a new page otherwise.
+    fn write_node(&mut self, old: Option<PageId>, node: Node) -> PageId {
+        let id = match old {
+            Some(old) if self.dirty.contains_key(&old) => old,
+            Some(old) => {
+                self.pending_free.push(old);
+                self.allocate()
+            }
+            None => self.allocate(),
+        };
+        self.dirty.insert(id, node);
+        id
+    }
+
+    /// Writes `node` as [`Tree::write_node`] does, splitting it in two if it
+    /// does not fit in a page.
+    ///
+    /// # Returns
+    ///
+    /// The page of the node, or of its left half along with the smallest key
+    /// and the page of the right half.
+    fn store(&mut self, old: PageId, node: Node) -> (PageId, Option<(Vec<u8>, PageId)>) {
+        if node.encoded_len() <= self.page_size {
+            return (self.write_node(Some(old), node), None);
+        }
+        let (left, separator, right) = node.split();
+        let left = self.write_node(Some(old), left);
+        let right = self.write_node(None, right);
+        (left, Some((separator, right)))
+    }
+
+    fn read_value(&self, value: &Value) -> io::Result<Vec<u8>> {
+        let (mut next, len) = match value {
+            Value::Inline(value) => return Ok(value.clone()),
+            Value::Outlined { first, len } => (*first, *len as usize),
+        };
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            if next == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "truncated B-tree value",
+                ));
+            }
+            let (following, chunk) = page::overflow_of(&self.page(next)?)?;
+            data.extend_from_slice(&chunk);
+            next = following;
+        }
+        Ok(data)
+    }
+
+    /// Stores `value` in overflow pages, written right away since no
+    /// committed page refers to them.
+    fn write_value(&mut self, value: &[u8]) -> io::Result<Value> {
+        let chunks: Vec<_> = value.chunks(self.page_size - PAGE_OVERHEAD).collect();
+        let ids: Vec<_> = chunks.iter().map(|_| self.allocate()).collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let next = ids.get(index + 1).copied().unwrap_or(0);
+            self.cache.remove((self.cache_file, ids[index]));
+            page::write_overflow(&self.file, ids[index], next, chunk, self.page_size)?;
+        }
+        Ok(Value::Outlined {
+            first: ids[0],
+            len: value.len() as u64,
+        })
+    }
+
+    fn free_value(&mut self, value: Value) -> io::Result<()> {
+        if let Value::Outlined { mut first, .. } = value {
+            while first != 0 {
+                self.pending_free.push(first);
+                first = page::overflow_of(&self.page(first)?)?.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks `key` up in the tree rooted at `root`.
+    fn get(&self, root: PageId, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut id = root;
+        while id != 0 {
+            match &*self.node(id)? {
+                Node::Branch { keys, children } => {
+                    id = children[keys.partition_point(|separator| &separator[..] <= key)];
+                }
+                Node::Leaf(entries) => {
+                    return match entries.binary_search_by(|(entry, _)| entry[..].cmp(key)) {
+                        Ok(index) => self.read_value(&entries[index].1).map(Some),
+                        Err(_) => Ok(None),
+                    };
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Appends the entries of the subtree at `id` within `range` to `out`,
+    /// in key order.
+    fn collect_range<R>(
+        &self,
+        id: PageId,
+        range: &R,
+        out: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> io::Result<()>
+    where
+        R: RangeBounds<[u8]>,
+    {
+        match &*self.node(id)? {
+            Node::Branch { keys, children } => {
+                // children whose keys all lie outside of the range are skipped
+                let first = match range.start_bound() {
+                    Bound::Included(start) | Bound::Excluded(start) => {
+                        keys.partition_point(|separator| &separator[..] <= start)
+                    }
+                    Bound::Unbounded => 0,
+                };
+                let last = match range.end_bound() {
+                    Bound::Included(end) => keys.partition_point(|separator| &separator[..] <= end),
+                    Bound::Excluded(end) => keys.partition_point(|separator| &separator[..] < end),
+                    Bound::Unbounded => keys.len(),
+                };
+                for child in &children[first..=last.max(first)] {
+                    self.collect_range(*child, range, out)?;
+                }
+            }
+            Node::Leaf(entries) => {
+                for (key, value) in entries {
+                    if range.contains(&key[..]) {
+                        out.push((key.clone(), self.read_value(value)?));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the checksum of every page of the tree as of the last commit,
+    /// overflow pages and pages of the free list included, along with the
+    /// meta slots, reading them from `path` rather than through the cache.
+    /// The pages below a corrupted one cannot be located, so they are not
+    /// checked.
+    fn verify(&self, path: &Path, report: &mut VerifyReport) -> io::Result<()> {
+        for offset in Meta::corrupted_slots(&self.file)? {
+            let err = io::Error::new(ErrorKind::InvalidData, "B-tree meta checksum mismatch");
+            report.corrupted(path, offset, page::META_LEN as u64, &err);
+        }
+        let mut checker = PageChecker {
+            tree: self,
+            path,
+            report,
+            visited: HashSet::new(),
+        };
+        let mut nodes = vec![self.meta.root];
+        nodes.retain(|root| *root != 0);
+        while let Some(id) = nodes.pop() {
+            match checker.check(id, page::node_of)? {
+                Some(Node::Branch { children, .. }) => nodes.extend(children),
+                Some(Node::Leaf(entries)) => {
+                    for (_, value) in entries {
+                        if let Value::Outlined { first, .. } = value {
+                            checker.check_chain(first, page::overflow_of)?;
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        checker.check_chain(self.meta.freelist, page::freelist_of)?;
+        report.checked(self.file.metadata()?.len());
+        Ok(())
+    }
+
+    /// Returns the entries of the tree rooted at `root` within `range`, in
+    /// key order.
+    fn range(&self, root: PageId, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        if root != 0 {
+            self.collect_range(root, &range, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        if key.len() > self.inline_limit() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "key too large"));
+        }
+        let value = match key.len() + value.len() > self.inline_limit() {
+            true => self.write_value(value)?,
+            false => Value::Inline(value.to_vec()),
+        };
+        let (root, split, replaced) = match self.root {
+            0 => {
+                let leaf = Node::Leaf(vec![(key.to_vec(), value)]);
+                (self.write_node(None, leaf), None, None)
+            }
+            root => self.insert(root, key, value)?,
+        };
+        self.root = match split {
+            Some((separator, right)) => self.write_node(
+                None,
+                Node::Branch {
+                    keys: vec![separator],
+                    children: vec![root, right],
+                },
+            ),
+            None => root,
+        };
+        match replaced {
+            Some(replaced) => self.free_value(replaced)?,
+            None => self.entries += 1,
+        }
+        self.changed = true;
+        self.commit_if_full()
+    }
+
+    /// Inserts `key` in the subtree at `id`, copying every node on the way
+    /// down unless written since the last commit.
+    ///
+    /// # Returns
+    ///
+    /// The new page of the subtree, the split of its root if any, and the
+    /// value `key` had.
+    #[allow(clippy::type_complexity)]
+    fn insert(
+        &mut self,
+        id: PageId,
+        key: &[u8],
+        value: Value,
+    ) -> io::Result<(PageId, Option<(Vec<u8>, PageId)>, Option<Value>)> {
+        let mut node = self.node(id)?.into_owned();
+        let replaced = match &mut node {
+            Node::Leaf(entries) => {
+                match entries.binary_search_by(|(entry, _)| entry[..].cmp(key)) {
+                    Ok(index) => Some(mem::replace(&mut entries[index].1, value)),
+                    Err(index) => {
+                        entries.insert(index, (key.to_vec(), value));
+                        None
+                    }
+                }
+            }
+            Node::Branch { keys, children } => {
+                let index = keys.partition_point(|separator| &separator[..] <= key);
+                let (child, split, replaced) = self.insert(children[index], key, value)?;
+                children[index] = child;
+                if let Some((separator, right)) = split {
+                    keys.insert(index, separator);
+                    children.insert(index + 1, right);
+                }
+                replaced
+            }
+        };
+        let (id, split) = self.store(id, node);
+        Ok((id, split, replaced))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> io::Result<bool> {
+        if self.root == 0 {
+            return Ok(false);
+        }
+        let (root, removed) = match self.remove(self.root, key)? {
+            Some(removed) => removed,
+            None => return Ok(false),
+        };
+        self.root = root.unwrap_or(0);
+        // branches left with a single child are collapsed into it
+        while self.root != 0 {
+            let child = match &*self.node(self.root)? {
+                Node::Branch { keys, children } if keys.is_empty() => children[0],
+                _ => break,
+            };
+            self.free_page(self.root);
+            self.root = child;
+        }
+        self.free_value(removed)?;
+        self.entries -= 1;
+        self.changed = true;
+        self.commit_if_full()?;
+        Ok(true)
+    }
+
+    /// Removes `key` from the subtree at `id`. Nodes left empty are freed,
+    /// but nodes left underfull are not merged with their siblings.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the subtree does not hold `key`, otherwise the new page of
+    /// the subtree, `None` if it is now empty, along with the value removed.
+    fn remove(&mut self, id: PageId, key: &[u8]) -> io::Result<Option<(Option<PageId>, Value)>> {
+        let mut node = self.node(id)?.into_owned();
+        let removed = match &mut node {
+            Node::Leaf(entries) => {
+                match entries.binary_search_by(|(entry, _)| entry[..].cmp(key)) {
+                    Ok(index) => entries.remove(index).1,
+                    Err(_) => return Ok(None),
+                }
+            }
+            Node::Branch { keys, children } => {
+                let index = keys.partition_point(|separator| &separator[..] <= key);
+                let (child, removed) = match self.remove(children[index], key)? {
+                    Some(removed) => removed,
+                    None => return Ok(None),
+                };
+                match child {
+                    Some(child) => children[index] = child,
+                    None => {
+                        children.remove(index);
+                        if !keys.is_empty() {
+                            keys.remove(index.saturating_sub(1));
+                        }
+                    }
+                }
+                removed
+            }
+        };
+        let empty = match &node {
+            Node::Leaf(entries) => entries.is_empty(),
+            Node::Branch { children, .. } => children.is_empty(),
+        };
+        if empty {
+            self.free_page(id);
+            return Ok(Some((None, removed)));
+        }
+        Ok(Some((Some(self.write_node(Some(id), node)), removed)))
+    }
+
+    fn commit_if_full(&mut self) -> io::Result<()> {
+        match self.dirty.len() >= self.max_dirty_pages {
+            true => self.commit(),
+            false => Ok(()),
+        }
+    }
+
+    /// Writes the pages changed since the last commit, then the meta slot
+    /// pointing to them, syncing the file before and after so that the
+    /// slot never points to pages not written yet.
+    fn commit(&mut self) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        for (id, node) in &self.dirty {
+            self.write_page(*id, node)?;
+        }
+
+        // the pages of the free list are taken off the free pages first,
+        // so the list may be a page longer than needed
+        let per_page = (self.page_size - PAGE_OVERHEAD) / 8;
+        let retained = self.retained.iter().flat_map(|(_, pages)| pages);
+        let mut free = self.free.clone();
+        free.extend(
+            retained
+                .chain(&self.pending_free)
+                .chain(&self.freelist_pages),
+        );
+        let count = free.len().div_ceil(per_page);
+        let pages: Vec<_> = (0..count).map(|_| self.allocate()).collect();
+        // the free list is written with every page reusable once reopened,
+        // no snapshot surviving that
+        free.retain(|id| !pages.contains(id));
+        let chunks: Vec<_> = free.chunks(per_page).collect();
+        for (index, id) in pages.iter().enumerate() {
+            let next = pages.get(index + 1).copied().unwrap_or(0);
+            let ids = chunks.get(index).copied().unwrap_or_default();
+            page::write_freelist(&self.file, *id, next, ids, self.page_size)?;
+        }
+        self.file.sync_data()?;
+
+        let meta = Meta {
+            page_size: self.page_size as u32,
+            txn: self.meta.txn + 1,
+            root: self.root,
+            page_count: self.page_count,
+            entries: self.entries,
+            freelist: pages.first().copied().unwrap_or(0),
+        };
+        meta.write(&self.file)?;
+        self.file.sync_data()?;
+
+        self.meta = meta;
+        self.dirty.clear();
+        match self.pins.is_empty() {
+            true => self.free.append(&mut self.pending_free),
+            false => {
+                let freed = mem::take(&mut self.pending_free);
+                self.retained.push((self.meta.txn, freed));
+            }
+        }
+        self.free.append(&mut self.freelist_pages);
+        self.freelist_pages = pages;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Makes the pages retained for snapshots no longer alive reusable.
+    fn release(&mut self) {
+        let oldest = self.pins.keys().next().copied().unwrap_or(u64::MAX);
+        // pages freed by a commit are only read by snapshots of earlier ones
+        let (released, retained) = mem::take(&mut self.retained)
+            .into_iter()
+            .partition(|(txn, _)| *txn <= oldest);
+        self.retained = retained;
+        for (_, mut pages) in released {
+            self.free.append(&mut pages);
+        }
+    }
+}
+
+/// Reads pages of a tree for [`Tree::verify`], each once, reporting those
+/// corrupted.
+struct PageChecker<'a> {
+    tree: &'a Tree,
+    path: &'a Path,
+    report: &'a mut VerifyReport,
+    visited: HashSet<PageId>,
+}
+
+impl PageChecker<'_> {
+    /// Reads page `id` and decodes it with `decode`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the page was read before, or is corrupted.
+    fn check<T>(
+        &mut self,
+        id: PageId,
+        decode: fn(&[u8]) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        if !self.visited.insert(id) {
+            return Ok(None);
+        }
+        let page_size = self.tree.page_size;
+        match page::read_page(&self.tree.file, id, page_size).and_then(|page| decode(&page)) {
+            Ok(decoded) => Ok(Some(decoded)),
+            Err(err) if verify::is_corruption(&err) => {
+                let page_size = page_size as u64;
+                self.report
+                    .corrupted(self.path, id * page_size, page_size, &err);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads the chain of pages starting at `first`, 0 for none.
+    fn check_chain<T>(
+        &mut self,
+        mut first: PageId,
+        decode: fn(&[u8]) -> io::Result<(PageId, T)>,
+    ) -> io::Result<()> {
+        while first != 0 {
+            first = match self.check(first, decode)? {
+                Some((next, _)) => next,
+                None => 0,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Storage engine whose entries live in a copy-on-write B-tree of fixed
+/// size pages in a single file, read straight from disk.
+///
+/// Unlike the [`LsmEngine`](super::LsmEngine), there is no compaction:
+/// a write copies the pages on the path from the root to its leaf, pages
+/// it frees being reused by later writes, and reads, point or range, go
+/// down a single tree. Values too large for a leaf are stored in chains of
+/// overflow pages.
+///
+/// Writes are committed by [`StorageEngine::flush`], once
+/// [`BTreeBuilder::max_dirty_pages`] pages were written since the last
+/// commit, and when the engine is dropped. A commit writes the new pages,
+/// syncs them, then points one of the two meta slots of the file to the
+/// new root, so the tree as of the last commit is never overwritten, and a
+/// crash leaves the engine at its last commit rather than corrupted, as
+/// with LMDB. Every page carries a checksum.
+///
+/// Writes take the tree exclusively, reads share it.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{BTreeEngine, StorageEngine};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-btree");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = BTreeEngine::open(&dir).unwrap();
+/// engine.put(b"alice", b"100").unwrap();
+/// engine.put(b"bob", b"50").unwrap();
+/// engine.put(b"carol", b"70").unwrap();
+/// drop(engine);
+///
+/// let engine = BTreeEngine::open(&dir).unwrap();
+/// assert_eq!(engine.get(b"alice").unwrap(), Some(b"100".to_vec()));
+/// let range = engine.range(&b"b"[..]..).unwrap();
+/// assert_eq!(range.len(), 2);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct BTreeEngine {
+    dir: PathBuf,
+    block_cache: Arc<BlockCache>,
+    tree: PriorityRwLock<Tree>,
+    lock_policy: LockPolicy,
+}
+
+impl BTreeEngine {
+    /// Opens the engine stored in `dir` with the default configuration, see
+    /// [`BTreeBuilder::open`].
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        BTreeBuilder::new().open(dir)
+    }
+
+    /// Returns the directory the engine is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of entries in the engine.
+    pub fn len(&self) -> usize {
+        self.tree.read(self.lock_policy.read).entries as usize
+    }
+
+    /// Returns `true` if the engine contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the size in bytes of the pages of the tree.
+    pub fn page_size(&self) -> usize {
+        self.tree.read(self.lock_policy.read).page_size
+    }
+
+    /// Returns the cache the pages read are kept in.
+    pub fn block_cache(&self) -> &Arc<BlockCache> {
+        &self.block_cache
+    }
+
+    /// Returns the number of pages of the data file, in use or free.
+    pub fn page_count(&self) -> u64 {
+        self.tree.read(self.lock_policy.read).page_count
+    }
+
+    /// Returns the entries whose key is within `range`, in key order.
+    pub fn range<'a, R>(&self, range: R) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let range = (
+            range.start_bound().map(|start| *start),
+            range.end_bound().map(|end| *end),
+        );
+        let tree = self.tree.read(self.lock_policy.read);
+        tree.range(tree.root, range)
+    }
+}
+
+impl StorageEngine for BTreeEngine {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let tree = self.tree.read(self.lock_policy.read);
+        tree.get(tree.root, key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.tree.write(self.lock_policy.write).put(key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        self.tree.write(self.lock_policy.write).delete(key)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let tree = self.tree.read(self.lock_policy.read);
+        tree.range(tree.root, range)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.tree.write(self.lock_policy.write).commit()
+    }
+
+    /// Commits the writes made so far, then returns a snapshot reading the
+    /// tree of that commit, whose pages are not reused while it is alive.
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        let mut tree = self.tree.write(self.lock_policy.write);
+        tree.commit()?;
+        let txn = tree.meta.txn;
+        *tree.pins.entry(txn).or_insert(0) += 1;
+        Ok(Box::new(BTreeSnapshot {
+            engine: self,
+            root: tree.root,
+            txn,
+        }))
+    }
+
+    /// Checks the tree as of the last commit, writes made since being
+    /// committed first.
+    fn verify(&self) -> io::Result<VerifyReport> {
+        let mut tree = self.tree.write(self.lock_policy.write);
+        tree.commit()?;
+        let mut report = VerifyReport::default();
+        tree.verify(&self.dir.join(DATA_FILE), &mut report)?;
+        Ok(report)
+    }
+}
+
+/// Snapshot of a [`BTreeEngine`], reading the tree of a commit.
+struct BTreeSnapshot<'a> {
+    engine: &'a BTreeEngine,
+    root: PageId,
+    txn: u64,
+}
+
+impl StorageSnapshot for BTreeSnapshot<'_> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let engine = self.engine;
+        engine
+            .tree
+            .read(engine.lock_policy.read)
+            .get(self.root, key)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let engine = self.engine;
+        engine
+            .tree
+            .read(engine.lock_policy.read)
+            .range(self.root, range)
+    }
+}
+
+impl Drop for BTreeSnapshot<'_> {
+    fn drop(&mut self) {
+        let engine = self.engine;
+        let mut tree = engine.tree.write(engine.lock_policy.write);
+        if let Some(count) = tree.pins.get_mut(&self.txn) {
+            *count -= 1;
+            if *count == 0 {
+                tree.pins.remove(&self.txn);
+            }
+        }
+        tree.release();
+    }
+}
+
+impl Drop for BTreeEngine {
+    fn drop(&mut self) {
+        let _ = self.tree.write(self.lock_policy.write).commit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BTreeBuilder, DATA_FILE};
+    use crate::storage::StorageEngine;
+    use std::fs;
+
+    #[test]
+    fn test_reuses_pages_and_survives_reopen_and_torn_commits() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-btree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let open = || {
+            BTreeBuilder::new()
+                .page_size(1024)
+                .max_dirty_pages(64)
+                .open(&dir)
+        };
+        let key = |index: u32| format!("key-{:06}", index).into_bytes();
+        let large = vec![9u8; 5000];
+
+        let engine = open().unwrap();
+        for index in 0..5000u32 {
+            engine
+                .put(&key(index * 7 % 5000), &index.to_le_bytes())
+                .unwrap();
+        }
+        engine.put(b"large", &large).unwrap();
+        for index in (0..5000u32).filter(|index| index % 3 != 0) {
+            assert!(engine.delete(&key(index)).unwrap());
+        }
+        assert!(!engine.delete(&key(1)).unwrap());
+        engine.flush().unwrap();
+        let pages = engine.page_count();
+
+        // rewriting the same entries reuses the pages freed meanwhile
+        for round in 0..3u32 {
+            for index in (0..5000u32).step_by(3) {
+                engine.put(&key(index), &round.to_le_bytes()).unwrap();
+            }
+            engine.flush().unwrap();
+        }
+        assert!(engine.page_count() < pages * 2);
+        assert_eq!(engine.len(), 1668);
+        let range = engine.range(&key(30)[..]..&key(60)[..]).unwrap();
+        let keys: Vec<_> = range.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, (30..60).step_by(3).map(key).collect::<Vec<_>>());
+        drop(engine);
+
+        // a crash before the next commit leaves the engine as of the last one
+        let engine = open().unwrap();
+        assert_eq!(engine.len(), 1668);
+        assert_eq!(engine.get(b"large").unwrap(), Some(large.clone()));
+        assert_eq!(
+            engine.get(&key(3)).unwrap(),
+            Some(2u32.to_le_bytes().to_vec())
+        );
+        assert_eq!(engine.get(&key(4)).unwrap(), None);
+        engine.put(&key(3), b"uncommitted").unwrap();
+        std::mem::forget(engine);
+
+        let engine = open().unwrap();
+        assert_eq!(
+            engine.get(&key(3)).unwrap(),
+            Some(2u32.to_le_bytes().to_vec())
+        );
+        for index in 0..5000u32 {
+            engine.delete(&key(index)).unwrap();
+        }
+        assert!(engine.delete(b"large").unwrap());
+        assert!(engine.is_empty());
+        assert_eq!(engine.range(..).unwrap(), Vec::new());
+        drop(engine);
+
+        fs::write(dir.join(DATA_FILE), b"not a tree").unwrap();
+        assert!(open().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+//! Layout of the pages of a B-tree file.
+//!
+//! Page 0 holds two meta slots, written alternately by commits. Every other
+//! page starts with its CRC-32, computed along with its id so that a page
+//! written at the wrong place is caught, followed by its kind.
+
+use std::fs::File;
+use std::io;
+
+use crate::checksum::crc32_update;
+use crate::codec::{decode_bytes, decode_len, encode_bytes, encode_len, invalid_data};
+use crate::codec::{Decode, Encode};
+use crate::storage::read_at;
+
+pub(super) type PageId = u64;
+
+const LEAF: u8 = 1;
+const BRANCH: u8 = 2;
+const OVERFLOW: u8 = 3;
+const FREELIST: u8 = 4;
+
+const INLINE: u8 = 0;
+const OUTLINED: u8 = 1;
+
+/// Bytes of a page before its body: CRC-32 and kind.
+const HEADER_LEN: usize = 5;
+
+/// Bytes a page holds besides its entries at most: header, pointer to the
+/// next page and a length.
+pub(super) const PAGE_OVERHEAD: usize = 32;
+
+const META_MAGIC: &[u8; 8] = b"PLDBBTR1";
+pub(super) const META_LEN: usize = 56;
+
+/// Offsets of the two meta slots within page 0, each in its own sector so
+/// that tearing one leaves the other intact.
+pub(super) const META_SLOTS: [u64; 2] = [0, 512];
+
+/// Value of a leaf entry, stored inline or in a chain of overflow pages.
+#[derive(Clone, Debug)]
+pub(super) enum Value {
+    Inline(Vec<u8>),
+    Outlined { first: PageId, len: u64 },
+}
+
+/// A node of the tree, as decoded from its page.
+#[derive(Clone, Debug)]
+pub(super) enum Node {
+    /// Entries sorted by key.
+    Leaf(Vec<(Vec<u8>, Value)>),
+    /// `children[i + 1]` holds the keys from `keys[i]` up to `keys[i + 1]`,
+    /// excluded, and `children[0]` those below `keys[0]`.
+    Branch {
+        keys: Vec<Vec<u8>>,
+        children: Vec<PageId>,
+    },
+}
+
+fn encode_entry(key: &[u8], value: &Value, buf: &mut Vec<u8>) {
+    encode_bytes(key, buf);
+    match value {
+        Value::Inline(value) => {
+            buf.push(INLINE);
+            encode_bytes(value, buf);
+        }
+        Value::Outlined { first, len } => {
+            buf.push(OUTLINED);
+            first.encode(buf);
+            len.encode(buf);
+        }
+    }
+}
+
+impl Node {
+    /// Appends the body of the page of the node to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Node::Leaf(entries) => {
+                buf.push(LEAF);
+                encode_len(entries.len(), buf);
+                for (key, value) in entries {
+                    encode_entry(key, value, buf);
+                }
+            }
+            Node::Branch { keys, children } => {
+                buf.push(BRANCH);
+                encode_len(keys.len(), buf);
+                children[0].encode(buf);
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    encode_bytes(key, buf);
+                    child.encode(buf);
+                }
+            }
+        }
+    }
+
+    fn decode(kind: u8, mut input: &[u8]) -> io::Result<Self> {
+        let input = &mut input;
+        let count = decode_len(input)?;
+        match kind {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count.min(input.len()));
+                for _ in 0..count {
+                    let key = decode_bytes(input)?.to_vec();
+                    let value = match u8::decode(input)? {
+                        INLINE => Value::Inline(decode_bytes(input)?.to_vec()),
+                        OUTLINED => Value::Outlined {
+                            first: u64::decode(input)?,
+                            len: u64::decode(input)?,
+                        },
+                        _ => return Err(invalid_data("invalid B-tree value")),
+                    };
+                    entries.push((key, value));
+                }
+                Ok(Node::Leaf(entries))
+            }
+            _ => {
+                let mut keys = Vec::with_capacity(count.min(input.len()));
+                let mut children = vec![u64::decode(input)?];
+                for _ in 0..count {
+                    keys.push(decode_bytes(input)?.to_vec());
+                    children.push(u64::decode(input)?);
+                }
+                Ok(Node::Branch { keys, children })
+            }
+        }
+    }
+
+    /// Returns the number of bytes of the page of the node, header included.
+    pub(super) fn encoded_len(&self) -> usize {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        HEADER_LEN - 1 + buf.len()
+    }
+
+    /// Splits an oversized node into two halves of about the same size.
+    ///
+    /// # Returns
+    ///
+    /// The left half, the smallest key of the right half and the right half.
+    pub(super) fn split(self) -> (Node, Vec<u8>, Node) {
+        let mut buf = Vec::new();
+        match self {
+            Node::Leaf(mut entries) => {
+                let sizes: Vec<usize> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        buf.clear();
+                        encode_entry(key, value, &mut buf);
+                        buf.len()
+                    })
+                    .collect();
+                let at = middle(&sizes);
+                let right = entries.split_off(at);
+                let separator = right[0].0.clone();
+                (Node::Leaf(entries), separator, Node::Leaf(right))
+            }
+            Node::Branch {
+                mut keys,
+                mut children,
+            } => {
+                let sizes: Vec<usize> = keys.iter().map(|key| key.len() + 12).collect();
+                let at = middle(&sizes);
+                let right_keys = keys.split_off(at + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(at + 1);
+                (
+                    Node::Branch { keys, children },
+                    separator,
+                    Node::Branch {
+                        keys: right_keys,
+                        children: right_children,
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// Returns the index splitting items of the given sizes into two halves of
+/// about the same total size, both of them non-empty.
+fn middle(sizes: &[usize]) -> usize {
+    let total: usize = sizes.iter().sum();
+    let mut sum = 0;
+    let at = sizes
+        .iter()
+        .position(|size| {
+            sum += size;
+            sum * 2 >= total
+        })
+        .unwrap_or(0);
+    at.clamp(1, sizes.len().saturating_sub(1).max(1))
+}
+
+/// Returns the page `id` made of the `kind` and `body` given, padded to
+/// `page_size` and sealed with its checksum.
+fn seal(id: PageId, kind: u8, body: &[u8], page_size: usize) -> Vec<u8> {
+    let mut page = vec![0; page_size];
+    page[4] = kind;
+    page[HEADER_LEN..HEADER_LEN + body.len()].copy_from_slice(body);
+    let crc = crc32_update(crc32_update(0, &id.to_le_bytes()), &page[4..]);
+    page[..4].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// Checks the checksum of page `id`, returning its kind and body.
+fn open(id: PageId, page: &[u8]) -> io::Result<(u8, &[u8])> {
+    let crc = crc32_update(crc32_update(0, &id.to_le_bytes()), &page[4..]);
+    if crc.to_le_bytes() != page[..4] {
+        return Err(invalid_data("B-tree page checksum mismatch"));
+    }
+    Ok((page[4], &page[HEADER_LEN..]))
+}
+
+/// Reads page `id` of `file`, checking its checksum.
+pub(super) fn read_page(file: &File, id: PageId, page_size: usize) -> io::Result<Vec<u8>> {
+    let mut page = vec![0; page_size];
+    read_at(file, &mut page, id * page_size as u64)?;
+    open(id, &page)?;
+    Ok(page)
+}
+
+/// Returns whether `page`, as read by [`read_page`], is a branch.
+pub(super) fn is_branch(page: &[u8]) -> bool {
+    page[4] == BRANCH
+}
+
+pub(super) fn write_node(file: &File, id: PageId, node: &Node, page_size: usize) -> io::Result<()> {
+    let mut body = Vec::new();
+    node.encode(&mut body);
+    write_at(
+        file,
+        &seal(id, body[0], &body[1..], page_size),
+        id * page_size as u64,
+    )
+}
+
+/// Decodes the node of `page`, as read by [`read_page`].
+pub(super) fn node_of(page: &[u8]) -> io::Result<Node> {
+    match (page[4], &page[HEADER_LEN..]) {
+        (kind @ (LEAF | BRANCH), body) => Node::decode(kind, body),
+        _ => Err(invalid_data("B-tree page is not a node")),
+    }
+}
+
+/// Writes a page of a chain, of overflow pages or of the free list, linked
+/// to `next`, 0 ending the chain.
+fn write_link(
+    file: &File,
+    id: PageId,
+    kind: u8,
+    next: PageId,
+    data: &[u8],
+    page_size: usize,
+) -> io::Result<()> {
+    let mut body = Vec::with_capacity(data.len() + 16);
+    next.encode(&mut body);
+    encode_bytes(data, &mut body);
+    write_at(
+        file,
+        &seal(id, kind, &body, page_size),
+        id * page_size as u64,
+    )
+}
+
+/// Decodes a page of a chain, as read by [`read_page`], returning the next
+/// page along with the data.
+fn link_of(page: &[u8], kind: u8) -> io::Result<(PageId, Vec<u8>)> {
+    if page[4] != kind {
+        return Err(invalid_data("B-tree page of unexpected kind"));
+    }
+    let mut body = &page[HEADER_LEN..];
+    let next = u64::decode(&mut body)?;
+    Ok((next, decode_bytes(&mut body)?.to_vec()))
+}
+
+pub(super) fn write_overflow(
+    file: &File,
+    id: PageId,
+    next: PageId,
+    data: &[u8],
+    page_size: usize,
+) -> io::Result<()> {
+    write_link(file, id, OVERFLOW, next, data, page_size)
+}
+
+/// Decodes the overflow `page`, as read by [`read_page`], returning the
+/// next page of the chain along with the data.
+pub(super) fn overflow_of(page: &[u8]) -> io::Result<(PageId, Vec<u8>)> {
+    link_of(page, OVERFLOW)
+}
+
+pub(super) fn write_freelist(
+    file: &File,
+    id: PageId,
+    next: PageId,
+    ids: &[PageId],
+    page_size: usize,
+) -> io::Result<()> {
+    let mut data = Vec::with_capacity(ids.len() * 8);
+    for id in ids {
+        id.encode(&mut data);
+    }
+    write_link(file, id, FREELIST, next, &data, page_size)
+}
+
+pub(super) fn read_freelist(
+    file: &File,
+    id: PageId,
+    page_size: usize,
+) -> io::Result<(PageId, Vec<PageId>)> {
+    freelist_of(&read_page(file, id, page_size)?)
+}
+
+/// Decodes the free list `page`, as read by [`read_page`], returning the
+/// next page of the list along with the free pages it holds.
+pub(super) fn freelist_of(page: &[u8]) -> io::Result<(PageId, Vec<PageId>)> {
+    let (next, data) = link_of(page, FREELIST)?;
+    let mut input = &data[..];
+    let mut ids = Vec::with_capacity(data.len() / 8);
+    while !input.is_empty() {
+        ids.push(u64::decode(&mut input)?);
+    }
+    Ok((next, ids))
+}
+
+/// State of the tree as of a commit.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Meta {
+    pub(super) page_size: u32,
+    pub(super) txn: u64,
+    /// 0 if the tree is empty.
+    pub(super) root: PageId,
+    /// Number of pages the file was grown to.
+    pub(super) page_count: u64,
+    pub(super) entries: u64,
+    /// First page of the free list, 0 if no page is free.
+    pub(super) freelist: PageId,
+}
+
+impl Meta {
+    pub(super) fn write(&self, file: &File) -> io::Result<()> {
+        let mut buf = META_MAGIC.to_vec();
+        buf.extend_from_slice(&[0; 4]);
+        self.page_size.encode(&mut buf);
+        for field in [
+            self.txn,
+            self.root,
+            self.page_count,
+            self.entries,
+            self.freelist,
+        ] {
+            field.encode(&mut buf);
+        }
+        let crc = crc32_update(0, &buf[12..]);
+        buf[8..12].copy_from_slice(&crc.to_le_bytes());
+        write_at(file, &buf, META_SLOTS[(self.txn % 2) as usize])
+    }
+
+    /// Reads the meta slots of `file`.
+    ///
+    /// # Returns
+    ///
+    /// The valid slot of the latest commit, `None` if neither is valid.
+    pub(super) fn read(file: &File) -> io::Result<Option<Self>> {
+        let mut latest: Option<Meta> = None;
+        for offset in META_SLOTS {
+            let mut buf = [0; META_LEN];
+            read_at(file, &mut buf, offset)?;
+            if &buf[..8] != META_MAGIC || crc32_update(0, &buf[12..]).to_le_bytes() != buf[8..12] {
+                continue;
+            }
+            let mut input = &buf[12..];
+            let page_size = u32::decode(&mut input)?;
+            let mut field = || u64::decode(&mut input);
+            let meta = Meta {
+                page_size,
+                txn: field()?,
+                root: field()?,
+                page_count: field()?,
+                entries: field()?,
+                freelist: field()?,
+            };
+            if latest.is_none_or(|latest| latest.txn < meta.txn) {
+                latest = Some(meta);
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Returns the offsets of the meta slots of `file` failing their
+    /// checksum, slots never written aside.
+    pub(super) fn corrupted_slots(file: &File) -> io::Result<Vec<u64>> {
+        let mut corrupted = Vec::new();
+        for offset in META_SLOTS {
+            let mut buf = [0; META_LEN];
+            read_at(file, &mut buf, offset)?;
+            let written = buf.iter().any(|byte| *byte != 0);
+            if written && crc32_update(0, &buf[12..]).to_le_bytes() != buf[8..12] {
+                corrupted.push(offset);
+            }
+        }
+        Ok(corrupted)
+    }
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        let written = file.seek_write(buf, offset)?;
+        buf = &buf[written..];
+        offset += written as u64;
+    }
+    Ok(())
+}
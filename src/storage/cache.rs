@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Capacity of the cache the disk engines get unless given one.
+const DEFAULT_CAPACITY: usize = 8 << 20;
+
+/// Number of independently locked parts of a cache.
+const SHARDS: usize = 16;
+
+/// A block, identified by the file it was read from and its offset or
+/// page number within it.
+type BlockKey = (u64, u64);
+
+/// Statistics of a [`BlockCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups which found their block.
+    pub hits: u64,
+    /// Lookups which had to read their block from disk.
+    pub misses: u64,
+    /// Blocks evicted to stay within the capacity.
+    pub evictions: u64,
+    /// Bytes of blocks cached.
+    pub size: usize,
+    /// Bytes of pinned blocks cached.
+    pub pinned_size: usize,
+}
+
+impl CacheStats {
+    /// Returns the share of lookups which found their block, 0 if there was
+    /// none.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+struct Slot {
+    key: BlockKey,
+    block: Arc<[u8]>,
+    /// Set when the block is hit, cleared as the clock hand passes it.
+    referenced: bool,
+    pinned: bool,
+}
+
+/// Blocks of a part of the cache, evicted in CLOCK order.
+#[derive(Default)]
+struct Shard {
+    slots: Vec<Slot>,
+    index: HashMap<BlockKey, usize>,
+    hand: usize,
+    size: usize,
+    pinned_size: usize,
+}
+
+impl Shard {
+    fn remove_at(&mut self, position: usize) -> Slot {
+        let slot = self.slots.swap_remove(position);
+        self.index.remove(&slot.key);
+        if let Some(moved) = self.slots.get(position) {
+            self.index.insert(moved.key, position);
+        }
+        self.size -= slot.block.len();
+        if slot.pinned {
+            self.pinned_size -= slot.block.len();
+        }
+        slot
+    }
+
+    /// Evicts unpinned blocks until `len` more bytes fit within
+    /// `capacity`, sparing the blocks hit since the hand last passed them.
+    ///
+    /// # Returns
+    ///
+    /// The number of blocks evicted, `None` if the pinned blocks leave no
+    /// room.
+    fn make_room(&mut self, len: usize, capacity: usize) -> Option<u64> {
+        if self.pinned_size + len > capacity {
+            return None;
+        }
+        let mut evicted = 0;
+        while self.size + len > capacity {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let slot = &mut self.slots[self.hand];
+            if slot.pinned {
+                self.hand += 1;
+            } else if slot.referenced {
+                slot.referenced = false;
+                self.hand += 1;
+            } else {
+                self.remove_at(self.hand);
+                evicted += 1;
+            }
+        }
+        Some(evicted)
+    }
+}
+
+/// Cache of the blocks the disk engines read, within a budget of bytes,
+/// shared by any number of engines.
+///
+/// The blocks cached are the table blocks of the
+/// [`LsmEngine`](super::LsmEngine) and the pages of the
+/// [`BTreeEngine`](super::BTreeEngine), checksummed, decrypted and
+/// decompressed, so a hit skips all of that along with the read. Blocks are
+/// evicted in CLOCK order, an approximation of least recently used order
+/// where hits only flag their block rather than reorder a list, and the
+/// cache is split in independently locked shards.
+///
+/// Engines pin the blocks every read goes through, such as the branch pages
+/// of the B-tree, which are then never evicted. Pinned blocks take at most
+/// half of the capacity, others being cached unpinned.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{BTreeBuilder, BlockCache, LsmBuilder, StorageEngine};
+/// use std::sync::Arc;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-block-cache");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let cache = Arc::new(BlockCache::new(64 << 20));
+/// let lsm = LsmBuilder::new().block_cache(cache.clone()).open(dir.join("lsm")).unwrap();
+/// let btree = BTreeBuilder::new().block_cache(cache.clone()).open(dir.join("btree")).unwrap();
+///
+/// btree.put(b"alice", b"100").unwrap();
+/// btree.flush().unwrap();
+/// for _ in 0..10 {
+///     assert_eq!(btree.get(b"alice").unwrap(), Some(b"100".to_vec()));
+/// }
+/// assert_eq!(cache.stats().misses, 1);
+/// assert_eq!(cache.stats().hits, 9);
+/// # drop(lsm);
+/// # drop(btree);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct BlockCache {
+    shards: Box<[Mutex<Shard>]>,
+    capacity: usize,
+    next_file: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl BlockCache {
+    /// Creates an empty cache holding up to `capacity` bytes of blocks, 0
+    /// disabling caching.
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            capacity,
+            next_file: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of bytes of blocks the cache holds up to.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ..CacheStats::default()
+        };
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            stats.size += shard.size;
+            stats.pinned_size += shard.pinned_size;
+        }
+        stats
+    }
+
+    /// Drops every block.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            *shard.lock().unwrap() = Shard::default();
+        }
+    }
+
+    /// Returns a new id for a file whose blocks go through the cache.
+    pub(crate) fn file_id(&self) -> u64 {
+        self.next_file.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn shard(&self, key: BlockKey) -> &Mutex<Shard> {
+        let hash = key.0.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ key.1;
+        &self.shards[(hash.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % SHARDS]
+    }
+
+    /// Returns the block `key`, counting a hit or a miss.
+    pub(crate) fn get(&self, key: BlockKey) -> Option<Arc<[u8]>> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let block = shard.index.get(&key).copied().map(|position| {
+            let slot = &mut shard.slots[position];
+            slot.referenced = true;
+            slot.block.clone()
+        });
+        match &block {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        block
+    }
+
+    /// Caches `block` as `key`, evicting other blocks to make room. The
+    /// block is not cached if pinned blocks leave no room for it.
+    pub(crate) fn insert(&self, key: BlockKey, block: Arc<[u8]>, pinned: bool) {
+        let capacity = self.capacity / SHARDS;
+        let mut shard = self.shard(key).lock().unwrap();
+        if let Some(position) = shard.index.get(&key).copied() {
+            shard.remove_at(position);
+        }
+        let evicted = match shard.make_room(block.len(), capacity) {
+            Some(evicted) => evicted,
+            None => return,
+        };
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+        let pinned = pinned && shard.pinned_size + block.len() <= capacity / 2;
+        shard.size += block.len();
+        if pinned {
+            shard.pinned_size += block.len();
+        }
+        let position = shard.slots.len();
+        shard.index.insert(key, position);
+        shard.slots.push(Slot {
+            key,
+            block,
+            referenced: false,
+            pinned,
+        });
+    }
+
+    /// Drops the block `key`, if cached.
+    pub(crate) fn remove(&self, key: BlockKey) {
+        let mut shard = self.shard(key).lock().unwrap();
+        if let Some(position) = shard.index.get(&key).copied() {
+            shard.remove_at(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockCache, SHARDS};
+    use crate::storage::{LsmBuilder, StorageEngine};
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_stays_within_budget_sparing_hit_and_pinned_blocks() {
+        // blocks of file 0 with these numbers all land in one shard
+        let cache = BlockCache::new(SHARDS * 1000);
+        let shard = |block: u64| cache.shard((0, block)) as *const _;
+        let blocks: Vec<u64> = (0..10_000)
+            .filter(|block| shard(*block) == shard(0))
+            .collect();
+        let block = |len: usize| -> Arc<[u8]> { vec![7; len].into() };
+
+        cache.insert((0, blocks[0]), block(300), true);
+        cache.insert((0, blocks[1]), block(300), false);
+        cache.insert((0, blocks[2]), block(300), false);
+        assert!(cache.get((0, blocks[1])).is_some());
+        // evicts the block not hit, sparing the pinned one
+        cache.insert((0, blocks[3]), block(300), false);
+        assert!(cache.get((0, blocks[2])).is_none());
+        for index in [0, 1, 3] {
+            assert!(cache.get((0, blocks[index])).is_some());
+        }
+        for number in &blocks[4..20] {
+            cache.insert((0, *number), block(300), false);
+        }
+        assert!(cache.get((0, blocks[0])).is_some());
+        // pinned blocks past half of the shard are cached unpinned, and
+        // blocks larger than what is left unpinned are not cached
+        cache.insert((0, blocks[20]), block(300), true);
+        cache.insert((0, blocks[21]), block(800), false);
+        assert!(cache.get((0, blocks[21])).is_none());
+
+        let stats = cache.stats();
+        assert!(stats.size <= 1000);
+        assert_eq!(stats.pinned_size, 300);
+        assert_eq!(stats.evictions, 18);
+        cache.remove((0, blocks[0]));
+        assert_eq!(cache.stats().pinned_size, 0);
+        cache.clear();
+        assert_eq!(cache.stats().size, 0);
+
+        let dir = std::env::temp_dir().join(format!("palladiumdb-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let engine = LsmBuilder::new()
+            .block_cache(cache.clone())
+            .open(&dir)
+            .unwrap();
+        for key in 0..1000u32 {
+            engine.put(&key.to_be_bytes(), &[1; 100]).unwrap();
+        }
+        engine.flush_memtable().unwrap();
+        engine.wait_for_compactions().unwrap();
+        for _ in 0..3 {
+            for key in 0..1000u32 {
+                assert!(engine.get(&key.to_be_bytes()).unwrap().is_some());
+            }
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 3000);
+        assert!(stats.hit_ratio() > 0.9);
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::sstable::{Entry, Table, TableWriter};
+use super::LsmBuilder;
+
+/// A sorted run of entries, as merged by [`MergeIter`].
+pub(super) type Source<'a> = Box<dyn Iterator<Item = io::Result<Entry>> + 'a>;
+
+/// Compacted bytes are accounted to the rate limiter by chunks of this size.
+const RATE_LIMIT_CHUNK: u64 = 64 << 10;
+
+/// How an [`LsmEngine`](super::LsmEngine) merges its tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStrategy {
+    /// Tables are merged into levels of disjoint tables, each ten times
+    /// larger than the one above it. Reads check at most one table per level
+    /// past 0, at the cost of rewriting data more often.
+    #[default]
+    Leveled,
+    /// Tables are kept in level 0, and runs of at least `min_tables`
+    /// consecutive tables of similar sizes are merged into one. Data is
+    /// rewritten less often, at the cost of reads checking more tables.
+    SizeTiered { min_tables: usize },
+}
+
+/// Activity of the flushes and compactions of an
+/// [`LsmEngine`](super::LsmEngine), see
+/// [`LsmEngine::compaction_stats`](super::LsmEngine::compaction_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of memtables flushed to tables.
+    pub flushes: u64,
+    /// Number of compactions completed.
+    pub compactions: u64,
+    /// Bytes of keys and values written to the engine.
+    pub bytes_ingested: u64,
+    /// Bytes of tables written by flushes.
+    pub bytes_flushed: u64,
+    /// Bytes of tables read by compactions.
+    pub bytes_compacted_in: u64,
+    /// Bytes of tables written by compactions.
+    pub bytes_compacted_out: u64,
+}
+
+impl CompactionStats {
+    /// Returns the number of bytes written to tables for every byte written
+    /// to the engine, 0 if nothing was written yet.
+    pub fn write_amplification(&self) -> f64 {
+        if self.bytes_ingested == 0 {
+            return 0.0;
+        }
+        (self.bytes_flushed + self.bytes_compacted_out) as f64 / self.bytes_ingested as f64
+    }
+}
+
+/// Counters behind [`CompactionStats`].
+#[derive(Default)]
+pub(super) struct Counters {
+    pub(super) flushes: AtomicU64,
+    pub(super) compactions: AtomicU64,
+    pub(super) bytes_ingested: AtomicU64,
+    pub(super) bytes_flushed: AtomicU64,
+    pub(super) bytes_compacted_in: AtomicU64,
+    pub(super) bytes_compacted_out: AtomicU64,
+}
+
+impl Counters {
+    pub(super) fn snapshot(&self) -> CompactionStats {
+        CompactionStats {
+            flushes: self.flushes.load(Ordering::SeqCst),
+            compactions: self.compactions.load(Ordering::SeqCst),
+            bytes_ingested: self.bytes_ingested.load(Ordering::SeqCst),
+            bytes_flushed: self.bytes_flushed.load(Ordering::SeqCst),
+            bytes_compacted_in: self.bytes_compacted_in.load(Ordering::SeqCst),
+            bytes_compacted_out: self.bytes_compacted_out.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Token bucket capping the bytes written per second by compactions, shared
+/// by every compaction thread.
+pub(super) struct RateLimiter {
+    bytes_per_sec: u64,
+    /// Bytes that can be written without waiting, negative when in debt,
+    /// along with when they were last refilled.
+    budget: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub(super) fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec,
+            budget: Mutex::new((bytes_per_sec as f64, Instant::now())),
+        }
+    }
+
+    /// Accounts for `bytes` written, sleeping long enough to stay within
+    /// the rate.
+    pub(super) fn acquire(&self, bytes: u64) {
+        let rate = self.bytes_per_sec as f64;
+        let debt = {
+            let mut budget = self.budget.lock().unwrap();
+            let (available, refilled) = &mut *budget;
+            // at most a second worth of bytes is saved up for bursts
+            *available = (*available + refilled.elapsed().as_secs_f64() * rate).min(rate);
+            *refilled = Instant::now();
+            *available -= bytes as f64;
+            -*available
+        };
+        if debt > 0.0 {
+            thread::sleep(Duration::from_secs_f64(debt / rate));
+        }
+    }
+}
+
+/// Merges sorted runs of entries into one, keeping the entry of the first
+/// run for keys found in several.
+pub(super) struct MergeIter<'a> {
+    sources: Vec<Source<'a>>,
+    heads: Vec<Option<Entry>>,
+    started: bool,
+}
+
+impl<'a> MergeIter<'a> {
+    /// Creates an iterator merging `sources`, newest first.
+    pub(super) fn new(sources: Vec<Source<'a>>) -> Self {
+        MergeIter {
+            heads: sources.iter().map(|_| None).collect(),
+            sources,
+            started: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        if !self.started {
+            self.started = true;
+            for (head, source) in self.heads.iter_mut().zip(&mut self.sources) {
+                *head = source.next().transpose()?;
+            }
+        }
+
+        let mut first: Option<usize> = None;
+        for (index, head) in self.heads.iter().enumerate() {
+            if let Some((key, _)) = head {
+                if first.is_none_or(|first| *key < self.heads[first].as_ref().unwrap().0) {
+                    first = Some(index);
+                }
+            }
+        }
+        let entry = match first {
+            Some(first) => self.heads[first].take().unwrap(),
+            None => return Ok(None),
+        };
+
+        // advance every run past the key, older entries for it are dropped
+        for (head, source) in self.heads.iter_mut().zip(&mut self.sources) {
+            if head.as_ref().is_none_or(|(key, _)| *key == entry.0) {
+                *head = source.next().transpose()?;
+            }
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// Tables to merge together.
+pub(super) struct Task {
+    /// Tables of the compacted level, newest first.
+    pub(super) inputs: Vec<Arc<Table>>,
+    /// Tables of the output level overlapping the inputs.
+    pub(super) overlapping: Vec<Arc<Table>>,
+    /// Level the merged tables go to. Tables merged within level 0 take the
+    /// place of the inputs in the order of the level.
+    pub(super) output_level: usize,
+    /// Whether no older entry is left below the output for deletions to
+    /// shadow, so that they can be dropped.
+    pub(super) bottom: bool,
+}
+
+impl Task {
+    /// Returns the tables the task merges.
+    pub(super) fn tables(&self) -> impl Iterator<Item = &Arc<Table>> {
+        self.inputs.iter().chain(&self.overlapping)
+    }
+}
+
+/// Returns the number of bytes past which `level` is compacted, for levels
+/// past the first.
+fn max_level_bytes(options: &LsmBuilder, level: usize) -> u64 {
+    (1..level).fold(options.level_size_base, |size, _| {
+        size.saturating_mul(options.level_multiplier)
+    })
+}
+
+/// Picks the next compaction to run on `levels`, if any is due, among the
+/// tables not `busy` with another compaction.
+pub(super) fn pick(
+    levels: &[Vec<Arc<Table>>],
+    options: &LsmBuilder,
+    pointers: &mut Vec<Vec<u8>>,
+    busy: &HashSet<u64>,
+) -> Option<Task> {
+    match options.compaction_strategy {
+        CompactionStrategy::Leveled => pick_leveled(levels, options, pointers, busy),
+        CompactionStrategy::SizeTiered { min_tables } => pick_tiered(levels, min_tables, busy),
+    }
+}
+
+/// Level 0 is compacted whole once it holds enough tables, as its tables
+/// overlap each other. Deeper levels are compacted a table at a time once
+/// they grow past their size, `pointers` recording the largest key of the
+/// last table compacted in each level, so that successive compactions cycle
+/// through the key space.
+fn pick_leveled(
+    levels: &[Vec<Arc<Table>>],
+    options: &LsmBuilder,
+    pointers: &mut Vec<Vec<u8>>,
+    busy: &HashSet<u64>,
+) -> Option<Task> {
+    pointers.resize(levels.len(), Vec::new());
+    let is_busy = |table: &Arc<Table>| busy.contains(&table.id());
+    let overlapping = |level: usize, smallest: &[u8], largest: &[u8]| -> Vec<Arc<Table>> {
+        levels[level]
+            .iter()
+            .filter(|table| table.overlaps(smallest, largest))
+            .cloned()
+            .collect()
+    };
+    let bottom = |output_level: usize| levels[output_level + 1..].iter().all(Vec::is_empty);
+
+    if levels[0].len() >= options.level0_tables && !levels[0].iter().any(is_busy) {
+        let inputs: Vec<_> = levels[0].iter().rev().cloned().collect();
+        let smallest = inputs.iter().map(|table| table.smallest()).min()?;
+        let largest = inputs.iter().map(|table| table.largest()).max()?;
+        let overlapping = overlapping(1, smallest, largest);
+        if !overlapping.iter().any(is_busy) {
+            return Some(Task {
+                inputs,
+                overlapping,
+                output_level: 1,
+                bottom: bottom(1),
+            });
+        }
+    }
+
+    for level in 1..levels.len() - 1 {
+        let size: u64 = levels[level].iter().map(|table| table.size()).sum();
+        if size <= max_level_bytes(options, level) {
+            continue;
+        }
+        let tables = &levels[level];
+        let start = tables.partition_point(|table| table.smallest() <= &pointers[level][..]);
+        for table in tables[start..].iter().chain(&tables[..start]) {
+            let overlapping = overlapping(level + 1, table.smallest(), table.largest());
+            if is_busy(table) || overlapping.iter().any(is_busy) {
+                continue;
+            }
+            pointers[level] = table.largest().to_vec();
+            return Some(Task {
+                inputs: vec![table.clone()],
+                overlapping,
+                output_level: level + 1,
+                bottom: bottom(level + 1),
+            });
+        }
+    }
+    None
+}
+
+/// Runs of consecutive tables of level 0 whose sizes are within half and
+/// one and a half times the average size of the run are merged, once they
+/// count `min_tables` tables. Only consecutive tables can be merged, as
+/// their order tells which entry of a key is the latest.
+fn pick_tiered(levels: &[Vec<Arc<Table>>], min_tables: usize, busy: &HashSet<u64>) -> Option<Task> {
+    let tables = &levels[0];
+    let mut start = 0;
+    while start < tables.len() {
+        let mut end = start;
+        let mut total = 0u64;
+        while end < tables.len() && !busy.contains(&tables[end].id()) {
+            let size = tables[end].size();
+            let average = match end - start {
+                0 => size,
+                count => total / count as u64,
+            };
+            if size < average / 2 || size > average + average / 2 {
+                break;
+            }
+            total += size;
+            end += 1;
+        }
+
+        if end - start >= min_tables.max(2) {
+            return Some(Task {
+                inputs: tables[start..end].iter().rev().cloned().collect(),
+                overlapping: Vec::new(),
+                output_level: 0,
+                bottom: start == 0 && levels[1..].iter().all(Vec::is_empty),
+            });
+        }
+        start = end.max(start + 1);
+    }
+    None
+}
+
+/// Merges the tables of `task` into new tables of at most about
+/// `table_size` bytes each, created along with their id by `new_table`,
+/// writing no faster than `limiter` allows.
+pub(super) fn run<F>(
+    task: &Task,
+    table_size: u64,
+    limiter: Option<&RateLimiter>,
+    mut new_table: F,
+) -> io::Result<Vec<Arc<Table>>>
+where
+    F: FnMut() -> io::Result<(u64, TableWriter)>,
+{
+    let sources = task
+        .tables()
+        .map(|table| Box::new(table.iter()) as Source<'_>)
+        .collect();
+
+    let mut outputs = Vec::new();
+    let mut writer: Option<(u64, TableWriter)> = None;
+    let mut unaccounted = 0u64;
+    for entry in MergeIter::new(sources) {
+        let (key, value) = entry?;
+        if task.bottom && value.is_none() {
+            continue;
+        }
+        let (_, table) = match &mut writer {
+            Some(writer) => writer,
+            None => writer.insert(new_table()?),
+        };
+        table.add(&key, value.as_deref())?;
+
+        unaccounted += (key.len() + value.map_or(0, |value| value.len())) as u64;
+        if let Some(limiter) = limiter.filter(|_| unaccounted >= RATE_LIMIT_CHUNK) {
+            limiter.acquire(unaccounted);
+            unaccounted = 0;
+        }
+        if table.estimated_size() >= table_size {
+            let (id, table) = writer.take().unwrap();
+            outputs.push(Arc::new(table.finish(id)?));
+        }
+    }
+    if let Some((id, table)) = writer {
+        outputs.push(Arc::new(table.finish(id)?));
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompactionStrategy;
+    use crate::storage::{LsmBuilder, StorageEngine};
+    use crate::wal::SyncPolicy;
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn test_size_tiered_merges_runs_within_rate_limit() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-tiered-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let engine = LsmBuilder::new()
+            .memtable_size(16 << 10)
+            .compaction_strategy(CompactionStrategy::SizeTiered { min_tables: 4 })
+            .compaction_rate_limit(256 << 10)
+            .sync_policy(SyncPolicy::Never)
+            .open(&dir)
+            .unwrap();
+
+        let start = Instant::now();
+        for round in 0..4u32 {
+            for key in 0..2000u32 {
+                let value = format!("{}-{}", key, round);
+                engine.put(&key.to_be_bytes(), value.as_bytes()).unwrap();
+            }
+        }
+        engine.wait_for_compactions().unwrap();
+
+        let stats = engine.compaction_stats();
+        assert!(stats.compactions > 0);
+        assert!(stats.write_amplification() > 1.0);
+        // compactions were throttled to the configured rate
+        let limit = (256 << 10) as f64 * (start.elapsed().as_secs_f64() + 1.0);
+        assert!((stats.bytes_compacted_out as f64) < limit * 1.5);
+        assert!(engine.table_counts()[0] < stats.flushes as usize);
+        assert!(engine.table_counts()[1..].iter().all(|count| *count == 0));
+        for key in 0..2000u32 {
+            let expected = format!("{}-3", key).into_bytes();
+            assert_eq!(engine.get(&key.to_be_bytes()).unwrap(), Some(expected));
+        }
+
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::sstable::Table;
+use crate::checksum::crc32_update;
+use crate::codec::{decode_all, invalid_data, take, Decode, Encode};
+use crate::files;
+
+/// Name of the manifest file within the directory of the engine.
+pub(super) const MANIFEST: &str = "MANIFEST";
+
+const MAGIC: &[u8; 8] = b"PLDBMANI";
+
+/// The tables of every level, as recorded by the manifest, along with the
+/// next free file id.
+pub(super) struct Manifest {
+    pub(super) next_id: u64,
+    pub(super) levels: Vec<Vec<u64>>,
+}
+
+/// Replaces the manifest of `dir` with the tables of `levels`, atomically.
+pub(super) fn write(dir: &Path, next_id: u64, levels: &[Vec<Arc<Table>>]) -> io::Result<()> {
+    let mut buf = MAGIC.to_vec();
+    next_id.encode(&mut buf);
+    let ids: Vec<Vec<u64>> = levels
+        .iter()
+        .map(|tables| tables.iter().map(|table| table.id()).collect())
+        .collect();
+    ids.encode(&mut buf);
+    let crc = crc32_update(0, &buf);
+    crc.encode(&mut buf);
+
+    let temp = dir.join(format!("{}.tmp", MANIFEST));
+    let mut file = File::create(&temp)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    files::rename_durably(&temp, dir.join(MANIFEST))
+}
+
+/// Reads the manifest of `dir`, if it has one.
+pub(super) fn read(dir: &Path) -> io::Result<Option<Manifest>> {
+    let bytes = match fs::read(dir.join(MANIFEST)) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    if bytes.len() < MAGIC.len() + 4 {
+        return Err(invalid_data("truncated manifest"));
+    }
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    if crc32_update(0, body).to_le_bytes() != crc {
+        return Err(invalid_data("manifest checksum mismatch"));
+    }
+    let mut input = body;
+    if take(&mut input, MAGIC.len())? != MAGIC {
+        return Err(invalid_data("not a manifest"));
+    }
+    let next_id = u64::decode(&mut input)?;
+    let levels = decode_all(input)?;
+    Ok(Some(Manifest { next_id, levels }))
+}
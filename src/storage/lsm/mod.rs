@@ -0,0 +1,1117 @@
+mod compaction;
+mod manifest;
+mod sstable;
+
+pub use self::compaction::{CompactionStats, CompactionStrategy};
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use self::compaction::{Counters, MergeIter, RateLimiter, Source, Task};
+use self::sstable::{Entry, Table, TableWriter};
+use super::{BlockCache, KeyRange, StorageEngine, StorageSnapshot};
+use crate::collections::map::MapBuilder;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+use crate::verify::{self, VerifyReport};
+use crate::wal::{Durable, SyncPolicy, WalBuilder};
+
+/// Bytes accounted for every write to a memtable on top of its key and
+/// value, for the memory of the entry itself.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Number of full memtables waiting for their flush past which writes wait.
+const MAX_FROZEN: usize = 2;
+
+fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:06}.sst", id))
+}
+
+fn memtable_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("wal-{:06}", id))
+}
+
+/// Configures and opens an [`LsmEngine`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::LsmBuilder;
+/// use palladiumdb::wal::SyncPolicy;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-lsm-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = LsmBuilder::new()
+///     .memtable_size(64 << 20)
+///     .compaction_threads(4)
+///     .sync_policy(SyncPolicy::EveryNMillis(5))
+///     .open(&dir)
+///     .unwrap();
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct LsmBuilder {
+    memtable_size: usize,
+    table_size: u64,
+    level0_tables: usize,
+    level_size_base: u64,
+    level_multiplier: u64,
+    levels: usize,
+    sync_policy: SyncPolicy,
+    compaction_strategy: CompactionStrategy,
+    compaction_threads: usize,
+    compaction_rate_limit: Option<u64>,
+    encryption: Option<Encryption>,
+    compression: Compression,
+    block_cache: Arc<BlockCache>,
+}
+
+impl Default for LsmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LsmBuilder {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        LsmBuilder {
+            memtable_size: 4 << 20,
+            table_size: 2 << 20,
+            level0_tables: 4,
+            level_size_base: 10 << 20,
+            level_multiplier: 10,
+            levels: 7,
+            sync_policy: SyncPolicy::default(),
+            compaction_strategy: CompactionStrategy::default(),
+            compaction_threads: 2,
+            compaction_rate_limit: None,
+            encryption: None,
+            compression: Compression::None,
+            block_cache: Arc::new(BlockCache::default()),
+        }
+    }
+
+    /// Sets the size in bytes past which the memtable is flushed to a table.
+    pub fn memtable_size(mut self, memtable_size: usize) -> Self {
+        self.memtable_size = memtable_size;
+        self
+    }
+
+    /// Sets the size in bytes past which compactions start a new table.
+    pub fn table_size(mut self, table_size: u64) -> Self {
+        self.table_size = table_size;
+        self
+    }
+
+    /// Sets the number of tables of level 0 that triggers its compaction.
+    pub fn level0_tables(mut self, level0_tables: usize) -> Self {
+        self.level0_tables = level0_tables;
+        self
+    }
+
+    /// Sets the size in bytes of level 1, past which it is compacted.
+    pub fn level_size_base(mut self, level_size_base: u64) -> Self {
+        self.level_size_base = level_size_base;
+        self
+    }
+
+    /// Sets how many times larger every level is than the one above it.
+    pub fn level_multiplier(mut self, level_multiplier: u64) -> Self {
+        self.level_multiplier = level_multiplier;
+        self
+    }
+
+    /// Sets the number of levels, level 0 included.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `levels` is less than 2.
+    pub fn levels(mut self, levels: usize) -> Self {
+        if levels < 2 {
+            panic!()
+        }
+        self.levels = levels;
+        self
+    }
+
+    /// Sets when the writes logged for the memtable are synced to stable
+    /// storage. With [`SyncPolicy::OnCheckpoint`], they are only made
+    /// durable by the flush of the memtable to a table.
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Sets how tables are merged together.
+    pub fn compaction_strategy(mut self, compaction_strategy: CompactionStrategy) -> Self {
+        self.compaction_strategy = compaction_strategy;
+        self
+    }
+
+    /// Sets the number of background threads flushing memtables and
+    /// compacting tables.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `compaction_threads` is 0.
+    pub fn compaction_threads(mut self, compaction_threads: usize) -> Self {
+        if compaction_threads == 0 {
+            panic!()
+        }
+        self.compaction_threads = compaction_threads;
+        self
+    }
+
+    /// Caps the bytes written per second by compactions, across every
+    /// thread, so that they leave disk bandwidth to flushes and reads.
+    /// Compactions are not limited by default.
+    pub fn compaction_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.compaction_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Encrypts the tables and memtable logs written from now on, see
+    /// [`crate::encryption`]. Files written unencrypted before are still
+    /// read, and rewritten encrypted as they are compacted.
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Compresses the blocks of the tables and the records of the memtable
+    /// logs written from now on, see [`crate::compression`]. Files are read
+    /// whatever their compression, and rewritten with the current one as
+    /// they are compacted.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the cache the data blocks of the tables read are kept in,
+    /// which may be shared with other engines. Defaults to a cache of 8 MiB
+    /// shared by the engines opened from this builder.
+    pub fn block_cache(mut self, block_cache: Arc<BlockCache>) -> Self {
+        self.block_cache = block_cache;
+        self
+    }
+
+    /// Opens the engine stored in `dir`, creating the directory if needed.
+    ///
+    /// Memtables left unflushed by the previous process are recovered from
+    /// their logs and flushed in the background, and files of unfinished
+    /// compactions are deleted.
+    pub fn open<P: AsRef<Path>>(self, dir: P) -> io::Result<LsmEngine> {
+        LsmEngine::with_builder(self, dir.as_ref())
+    }
+
+    /// Returns the builder of the memtable logs.
+    fn wal_builder(&self, sync_policy: SyncPolicy) -> WalBuilder {
+        let mut wal_builder = WalBuilder::new()
+            .sync_policy(sync_policy)
+            .compression(self.compression);
+        if let Some(encryption) = &self.encryption {
+            wal_builder = wal_builder.encryption(encryption.clone());
+        }
+        wal_builder
+    }
+
+    /// Creates the table at `path` with the options of the builder.
+    fn create_table(&self, path: PathBuf) -> io::Result<TableWriter> {
+        TableWriter::create(
+            path,
+            self.encryption.clone(),
+            self.compression,
+            self.block_cache.clone(),
+        )
+    }
+}
+
+/// Writes not flushed to a table yet, logged to their own write-ahead log.
+struct Memtable {
+    id: u64,
+    dir: PathBuf,
+    entries: Durable<Vec<u8>, Option<Vec<u8>>>,
+    size: AtomicUsize,
+    obsolete: AtomicBool,
+}
+
+impl Memtable {
+    fn open(dir: &Path, id: u64, wal_builder: WalBuilder) -> io::Result<Self> {
+        let dir = memtable_path(dir, id);
+        let entries: Durable<Vec<u8>, Option<Vec<u8>>> =
+            Durable::with_builders(MapBuilder::new(), wal_builder, &dir)?;
+        let size = entries
+            .map()
+            .iter()
+            .map(|(key, value)| key.len() + value.map_or(0, |value| value.len()) + ENTRY_OVERHEAD)
+            .sum();
+        Ok(Memtable {
+            id,
+            dir,
+            entries,
+            size: AtomicUsize::new(size),
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the entries of the memtable, sorted by key.
+    fn sorted_entries(&self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut entries: Vec<_> = self.entries.map().iter().collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+}
+
+impl Drop for Memtable {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// The memtables and tables reads go through.
+struct State {
+    active: Arc<Memtable>,
+    /// Memtables waiting for their flush, oldest first.
+    frozen: Vec<Arc<Memtable>>,
+    /// Tables of every level. Level 0 is sorted oldest first and its tables
+    /// may overlap, the tables of deeper levels are sorted by key and do not.
+    levels: Arc<Vec<Vec<Arc<Table>>>>,
+}
+
+/// Background work, as handed out to the compaction threads.
+enum Job {
+    Flush(Arc<Memtable>),
+    Compact(Task),
+}
+
+/// What the compaction threads are busy with.
+#[derive(Default)]
+struct Scheduler {
+    /// Whether a memtable is being flushed. Flushes run one at a time, so
+    /// that tables are added to level 0 in the order of their memtables.
+    flushing: bool,
+    /// Number of compactions running.
+    running: usize,
+    /// Ids of the tables being compacted.
+    busy: HashSet<u64>,
+    /// Largest key of the last table compacted in every level.
+    pointers: Vec<Vec<u8>>,
+    /// Error of the first flush or compaction that failed, after which no
+    /// other one is started.
+    error: Option<(io::ErrorKind, String)>,
+    shutdown: bool,
+}
+
+impl Scheduler {
+    fn check(&self) -> io::Result<()> {
+        match &self.error {
+            Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Shared between the engine handle and its compaction threads.
+struct Inner {
+    dir: PathBuf,
+    options: LsmBuilder,
+    state: PriorityRwLock<State>,
+    next_id: AtomicU64,
+    scheduler: Mutex<Scheduler>,
+    /// Signaled whenever a job is due or completes.
+    wakeup: Condvar,
+    /// Serializes the edits of the levels along with their manifest writes.
+    manifest: Mutex<()>,
+    limiter: Option<RateLimiter>,
+    counters: Counters,
+    lock_policy: LockPolicy,
+}
+
+/// Thread-Safe log-structured merge-tree storage engine.
+///
+/// Writes go to a memtable, a [`Map`](crate::Map) whose writes are logged
+/// to a write-ahead log. Once full, the memtable is flushed to an immutable,
+/// sorted table file in level 0, made of checksummed data blocks, a block
+/// index and a bloom filter, so that reads skip most tables not holding the
+/// key without reading any block.
+///
+/// Tables are then merged according to the [`CompactionStrategy`]: with the
+/// default leveled strategy, levels past 0 hold tables with disjoint key
+/// ranges, each level about ten times larger than the one above it, and
+/// tables are merged into the level below when level 0 collects too many
+/// tables or another level grows past its size.
+///
+/// Flushes and compactions run on a pool of background threads owned by the
+/// engine, compactions on disjoint tables running concurrently. Writes only
+/// wait for them when memtables fill up faster than they are flushed. The
+/// threads are stopped and joined when the engine is dropped, work left
+/// undone being resumed when it is opened again.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{LsmEngine, StorageEngine};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-lsm");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = LsmEngine::open(&dir).unwrap();
+/// engine.put(b"alice", b"100").unwrap();
+/// engine.flush_memtable().unwrap();
+/// engine.put(b"alice", b"70").unwrap();
+/// drop(engine);
+///
+/// let engine = LsmEngine::open(&dir).unwrap();
+/// assert_eq!(engine.get(b"alice").unwrap(), Some(b"70".to_vec()));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct LsmEngine {
+    inner: Arc<Inner>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl LsmEngine {
+    /// Opens the engine stored in `dir` with the default configuration, see
+    /// [`LsmBuilder::open`].
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        LsmBuilder::new().open(dir)
+    }
+
+    fn with_builder(options: LsmBuilder, dir: &Path) -> io::Result<Self> {
+        let dir = dir.to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let manifest = manifest::read(&dir)?;
+        let (mut next_id, level_ids) = match manifest {
+            Some(manifest) => (manifest.next_id, manifest.levels),
+            None => (1, Vec::new()),
+        };
+        let mut levels = vec![Vec::new(); options.levels.max(level_ids.len())];
+        for (level, ids) in level_ids.iter().enumerate() {
+            for id in ids {
+                let table = Table::open(
+                    table_path(&dir, *id),
+                    *id,
+                    options.encryption.as_ref(),
+                    options.block_cache.clone(),
+                )?;
+                levels[level].push(Arc::new(table));
+            }
+        }
+
+        let mut memtable_ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if let Some(id) = name.strip_prefix("wal-").and_then(|id| id.parse().ok()) {
+                memtable_ids.push(id);
+            } else if let Some(id) = name.strip_suffix(".sst").and_then(|id| id.parse().ok()) {
+                if !level_ids.iter().flatten().any(|live| *live == id) {
+                    // output of a compaction that did not complete
+                    fs::remove_file(table_path(&dir, id))?;
+                }
+                next_id = next_id.max(id + 1);
+            }
+        }
+        memtable_ids.sort_unstable();
+        if let Some(last) = memtable_ids.last() {
+            next_id = next_id.max(last + 1);
+        }
+
+        // memtables whose flush did not complete, only the newest one can
+        // still be written to
+        let active = match memtable_ids.pop() {
+            Some(id) => Memtable::open(&dir, id, options.wal_builder(options.sync_policy))?,
+            None => {
+                next_id += 1;
+                Memtable::open(&dir, next_id - 1, options.wal_builder(options.sync_policy))?
+            }
+        };
+        let frozen = memtable_ids
+            .into_iter()
+            .map(|id| {
+                Memtable::open(&dir, id, options.wal_builder(SyncPolicy::Never)).map(Arc::new)
+            })
+            .collect::<io::Result<_>>()?;
+
+        let inner = Arc::new(Inner {
+            state: PriorityRwLock::new(State {
+                active: Arc::new(active),
+                frozen,
+                levels: Arc::new(levels),
+            }),
+            limiter: options.compaction_rate_limit.map(RateLimiter::new),
+            options,
+            dir,
+            next_id: AtomicU64::new(next_id),
+            scheduler: Mutex::new(Scheduler::default()),
+            wakeup: Condvar::new(),
+            manifest: Mutex::new(()),
+            counters: Counters::default(),
+            lock_policy: LockPolicy::default(),
+        });
+        let mut engine = LsmEngine {
+            inner,
+            workers: Vec::new(),
+        };
+        for _ in 0..engine.inner.options.compaction_threads {
+            let inner = engine.inner.clone();
+            let worker = thread::Builder::new()
+                .name("palladiumdb-compaction".to_string())
+                .spawn(move || inner.work())?;
+            engine.workers.push(worker);
+        }
+        Ok(engine)
+    }
+
+    /// Returns the directory the engine is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.inner.dir
+    }
+
+    /// Returns the cache the data blocks of the tables read are kept in.
+    pub fn block_cache(&self) -> &Arc<BlockCache> {
+        &self.inner.options.block_cache
+    }
+
+    /// Returns the number of tables in every level, level 0 first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::storage::{LsmEngine, StorageEngine};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-lsm-tables");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let engine = LsmEngine::open(&dir).unwrap();
+    /// engine.put(b"key", b"value").unwrap();
+    /// engine.flush_memtable().unwrap();
+    ///
+    /// assert_eq!(engine.table_counts()[0], 1);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn table_counts(&self) -> Vec<usize> {
+        let inner = &self.inner;
+        inner
+            .state
+            .read(inner.lock_policy.read)
+            .levels
+            .iter()
+            .map(Vec::len)
+            .collect()
+    }
+
+    /// Returns the activity of flushes and compactions since the engine was
+    /// opened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::storage::{LsmEngine, StorageEngine};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-lsm-stats");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let engine = LsmEngine::open(&dir).unwrap();
+    /// engine.put(b"key", b"value").unwrap();
+    /// engine.flush_memtable().unwrap();
+    ///
+    /// let stats = engine.compaction_stats();
+    /// assert_eq!(stats.flushes, 1);
+    /// assert_eq!(stats.bytes_ingested, 8);
+    /// assert!(stats.write_amplification() > 1.0);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.inner.counters.snapshot()
+    }
+
+    /// Flushes the memtable to a table of level 0, whatever its size, and
+    /// waits for the flush to complete. Compactions it makes due run in the
+    /// background.
+    ///
+    /// # Returns
+    ///
+    /// The error of the flush, or of any flush or compaction that failed
+    /// before.
+    pub fn flush_memtable(&self) -> io::Result<()> {
+        let inner = &self.inner;
+        let id = match inner.rotate(true)? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        inner.wait_until(|_| {
+            let state = inner.state.read(inner.lock_policy.read);
+            !state.frozen.iter().any(|memtable| memtable.id == id)
+        })
+    }
+
+    /// Waits until every full memtable is flushed and no compaction is due
+    /// or running.
+    ///
+    /// # Returns
+    ///
+    /// The error of any flush or compaction that failed.
+    pub fn wait_for_compactions(&self) -> io::Result<()> {
+        let inner = &self.inner;
+        inner.wait_until(|scheduler| {
+            let state = inner.state.read(inner.lock_policy.read);
+            let mut pointers = scheduler.pointers.clone();
+            state.frozen.is_empty()
+                && !scheduler.flushing
+                && scheduler.running == 0
+                && compaction::pick(
+                    &state.levels,
+                    &inner.options,
+                    &mut pointers,
+                    &scheduler.busy,
+                )
+                .is_none()
+        })
+    }
+}
+
+impl Inner {
+    fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Blocks until `done` returns `true`, evaluating it whenever a job
+    /// completes.
+    fn wait_until<F>(&self, mut done: F) -> io::Result<()>
+    where
+        F: FnMut(&mut Scheduler) -> bool,
+    {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        loop {
+            scheduler.check()?;
+            if done(&mut scheduler) {
+                return Ok(());
+            }
+            scheduler = self.wakeup.wait(scheduler).unwrap();
+        }
+    }
+
+    /// Runs jobs until the engine is dropped.
+    fn work(&self) {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        while !scheduler.shutdown {
+            let job = match self.next_job(&mut scheduler) {
+                Some(job) => job,
+                None => {
+                    scheduler = self.wakeup.wait(scheduler).unwrap();
+                    continue;
+                }
+            };
+            drop(scheduler);
+            let result = match &job {
+                Job::Flush(memtable) => self.flush(memtable),
+                Job::Compact(task) => self.compact(task),
+            };
+
+            scheduler = self.scheduler.lock().unwrap();
+            match job {
+                Job::Flush(_) => scheduler.flushing = false,
+                Job::Compact(task) => {
+                    for table in task.tables() {
+                        scheduler.busy.remove(&table.id());
+                    }
+                    scheduler.running -= 1;
+                }
+            }
+            if let Err(err) = result {
+                if scheduler.error.is_none() {
+                    scheduler.error = Some((err.kind(), err.to_string()));
+                }
+            }
+            self.wakeup.notify_all();
+        }
+    }
+
+    /// Picks the next job to run, flushes first, if any is due.
+    fn next_job(&self, scheduler: &mut MutexGuard<'_, Scheduler>) -> Option<Job> {
+        if scheduler.error.is_some() {
+            return None;
+        }
+        let state = self.state.read(self.lock_policy.read);
+        if !scheduler.flushing {
+            if let Some(memtable) = state.frozen.first() {
+                scheduler.flushing = true;
+                return Some(Job::Flush(memtable.clone()));
+            }
+        }
+
+        let scheduler = &mut **scheduler;
+        let task = compaction::pick(
+            &state.levels,
+            &self.options,
+            &mut scheduler.pointers,
+            &scheduler.busy,
+        )?;
+        scheduler.busy.extend(task.tables().map(|table| table.id()));
+        scheduler.running += 1;
+        Some(Job::Compact(task))
+    }
+
+    /// Replaces the active memtable with an empty one, handing it to the
+    /// compaction threads, if it is full or `force` is set. Waits first while
+    /// too many memtables are waiting for their flush.
+    ///
+    /// # Returns
+    ///
+    /// The id of the memtable replaced, if any.
+    fn rotate(&self, force: bool) -> io::Result<Option<u64>> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        loop {
+            scheduler.check()?;
+            let state = self.state.read(self.lock_policy.read);
+            let size = state.active.size.load(Ordering::SeqCst);
+            if size == 0 || (!force && size < self.options.memtable_size) {
+                return Ok(None);
+            }
+            if state.frozen.len() < MAX_FROZEN {
+                break;
+            }
+            drop(state);
+            scheduler = self.wakeup.wait(scheduler).unwrap();
+        }
+
+        let memtable = Memtable::open(
+            &self.dir,
+            self.new_id(),
+            self.options.wal_builder(self.options.sync_policy),
+        )?;
+        let mut state = self.state.write(self.lock_policy.write);
+        let memtable = mem::replace(&mut state.active, Arc::new(memtable));
+        state.frozen.push(memtable.clone());
+        self.wakeup.notify_all();
+        Ok(Some(memtable.id))
+    }
+
+    /// Writes `memtable` to a table of level 0, then drops it along with its
+    /// log.
+    fn flush(&self, memtable: &Arc<Memtable>) -> io::Result<()> {
+        let entries = memtable.sorted_entries();
+        let table = match entries.is_empty() {
+            true => None,
+            false => {
+                let id = self.new_id();
+                let mut writer = self.options.create_table(table_path(&self.dir, id))?;
+                for (key, value) in &entries {
+                    writer.add(key, value.as_deref())?;
+                }
+                Some(Arc::new(writer.finish(id)?))
+            }
+        };
+        let flushed = table.as_ref().map_or(0, |table| table.size());
+
+        self.edit_levels(
+            |levels| levels[0].extend(table),
+            |state| {
+                state.frozen.retain(|frozen| frozen.id != memtable.id);
+            },
+        )?;
+        memtable.obsolete.store(true, Ordering::SeqCst);
+        self.counters.flushes.fetch_add(1, Ordering::SeqCst);
+        self.counters
+            .bytes_flushed
+            .fetch_add(flushed, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Merges the tables of `task`, then replaces them with the tables
+    /// merged.
+    fn compact(&self, task: &Task) -> io::Result<()> {
+        // tables merged within level 0 are kept whole, so that they grow into
+        // larger tiers
+        let table_size = match task.output_level {
+            0 => u64::MAX,
+            _ => self.options.table_size,
+        };
+        let outputs = compaction::run(task, table_size, self.limiter.as_ref(), || {
+            let id = self.new_id();
+            self.options
+                .create_table(table_path(&self.dir, id))
+                .map(|writer| (id, writer))
+        })?;
+        let bytes_in: u64 = task.tables().map(|table| table.size()).sum();
+        let bytes_out: u64 = outputs.iter().map(|table| table.size()).sum();
+
+        let compacted: HashSet<u64> = task.tables().map(|table| table.id()).collect();
+        self.edit_levels(
+            |levels| {
+                let output = &mut levels[task.output_level];
+                let position = output
+                    .iter()
+                    .position(|table| compacted.contains(&table.id()))
+                    .unwrap_or(output.len());
+                for tables in levels.iter_mut() {
+                    tables.retain(|table| !compacted.contains(&table.id()));
+                }
+                let output = &mut levels[task.output_level];
+                match task.output_level {
+                    // in place of the inputs, which are consecutive
+                    0 => drop(output.splice(position..position, outputs)),
+                    _ => {
+                        output.extend(outputs);
+                        output.sort_unstable_by(|a, b| a.smallest().cmp(b.smallest()));
+                    }
+                }
+            },
+            |_| {},
+        )?;
+        for table in task.tables() {
+            table.mark_obsolete();
+        }
+
+        let counters = &self.counters;
+        counters.compactions.fetch_add(1, Ordering::SeqCst);
+        counters
+            .bytes_compacted_in
+            .fetch_add(bytes_in, Ordering::SeqCst);
+        counters
+            .bytes_compacted_out
+            .fetch_add(bytes_out, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Applies `edit` to the levels and records them in the manifest, then
+    /// installs them, along with `update` to the rest of the state.
+    fn edit_levels<E, U>(&self, edit: E, update: U) -> io::Result<()>
+    where
+        E: FnOnce(&mut Vec<Vec<Arc<Table>>>),
+        U: FnOnce(&mut State),
+    {
+        let _manifest = self.manifest.lock().unwrap();
+        let mut levels = (*self.state.read(self.lock_policy.read).levels).clone();
+        edit(&mut levels);
+        manifest::write(&self.dir, self.next_id.load(Ordering::SeqCst), &levels)?;
+
+        let mut state = self.state.write(self.lock_policy.write);
+        state.levels = Arc::new(levels);
+        update(&mut state);
+        Ok(())
+    }
+
+    fn write(&self, key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+        let full = {
+            let state = self.state.read(self.lock_policy.read);
+            state
+                .active
+                .entries
+                .put(&key.to_vec(), value.map(<[u8]>::to_vec))?;
+            let len = key.len() + value.map_or(0, <[u8]>::len);
+            self.counters
+                .bytes_ingested
+                .fetch_add(len as u64, Ordering::SeqCst);
+            let size = len + ENTRY_OVERHEAD;
+            state.active.size.fetch_add(size, Ordering::SeqCst) + size >= self.options.memtable_size
+        };
+        if full {
+            self.rotate(false)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LsmEngine {
+    fn drop(&mut self) {
+        self.inner.scheduler.lock().unwrap().shutdown = true;
+        self.inner.wakeup.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl StorageEngine for LsmEngine {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let inner = &self.inner;
+        let (memtables, levels) = {
+            let state = inner.state.read(inner.lock_policy.read);
+            let mut memtables = vec![state.active.clone()];
+            memtables.extend(state.frozen.iter().rev().cloned());
+            (memtables, state.levels.clone())
+        };
+
+        let key_vec = key.to_vec();
+        for memtable in &memtables {
+            if let Some(value) = memtable.entries.get(&key_vec) {
+                return Ok(value);
+            }
+        }
+        get_from_tables(&levels, key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.inner.write(key, Some(value))
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        if self.get(key)?.is_none() {
+            return Ok(false);
+        }
+        self.inner.write(key, None)?;
+        Ok(true)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.snapshot()?.scan(range)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let inner = &self.inner;
+        inner
+            .state
+            .read(inner.lock_policy.read)
+            .active
+            .entries
+            .sync()
+    }
+
+    /// Flushes the memtable to a table of level 0, so that its log need not
+    /// be replayed when the engine is opened again.
+    fn close(&self) -> io::Result<()> {
+        self.flush_memtable()
+    }
+
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        let inner = &self.inner;
+        // writes go to the active memtable under the read lock
+        let state = inner.state.write(inner.lock_policy.write);
+        let mut memtables = vec![state.active.sorted_entries()];
+        memtables.extend(
+            state
+                .frozen
+                .iter()
+                .rev()
+                .map(|memtable| memtable.sorted_entries()),
+        );
+        Ok(Box::new(LsmSnapshot {
+            memtables,
+            levels: state.levels.clone(),
+        }))
+    }
+
+    /// Checks the manifest, every block of the tables and the logs of the
+    /// memtables.
+    fn verify(&self) -> io::Result<VerifyReport> {
+        let inner = &self.inner;
+        let (memtables, levels) = {
+            let state = inner.state.read(inner.lock_policy.read);
+            let mut memtables = vec![state.active.clone()];
+            memtables.extend(state.frozen.iter().cloned());
+            (memtables, state.levels.clone())
+        };
+
+        let mut report = VerifyReport::default();
+        let path = inner.dir.join(manifest::MANIFEST);
+        match fs::metadata(&path) {
+            Ok(metadata) => {
+                match manifest::read(&inner.dir) {
+                    Err(err) if verify::is_corruption(&err) => {
+                        report.corrupted(&path, 0, metadata.len(), &err)
+                    }
+                    manifest => drop(manifest?),
+                }
+                report.checked(metadata.len());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        for table in levels.iter().flatten() {
+            table.verify(&mut report)?;
+        }
+        for memtable in &memtables {
+            report.merge(memtable.entries.wal().verify()?);
+        }
+        Ok(report)
+    }
+}
+
+/// Looks `key` up in the tables of `levels`, newest first.
+fn get_from_tables(levels: &[Vec<Arc<Table>>], key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    for table in levels[0].iter().rev() {
+        if let Some(value) = table.get(key)? {
+            return Ok(value);
+        }
+    }
+    for tables in &levels[1..] {
+        let position = tables.partition_point(|table| table.largest() < key);
+        if let Some(table) = tables.get(position) {
+            if table.smallest() <= key {
+                if let Some(value) = table.get(key)? {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns whether `key` sorts after every key of `range`.
+fn past_end(range: &KeyRange<'_>, key: &[u8]) -> bool {
+    match range.1 {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+/// Returns the entries of `table` within `range`, or `None` if it holds
+/// none.
+fn clip<'a>(table: &'a Table, range: KeyRange<'a>) -> Option<Source<'a>> {
+    let before_start = match range.0 {
+        Bound::Included(start) => table.largest() < start,
+        Bound::Excluded(start) => table.largest() <= start,
+        Bound::Unbounded => false,
+    };
+    if before_start || past_end(&range, table.smallest()) {
+        return None;
+    }
+    let entries = table
+        .iter()
+        .take_while(move |entry| {
+            entry
+                .as_ref()
+                .map_or(true, |(key, _)| !past_end(&range, key))
+        })
+        .filter(move |entry| {
+            entry.as_ref().map_or(true, |(key, _)| {
+                RangeBounds::<[u8]>::contains(&range, &key[..])
+            })
+        });
+    Some(Box::new(entries))
+}
+
+/// Snapshot of an [`LsmEngine`]: a copy of its memtables, along with the
+/// tables of the time, which are kept on disk until the snapshot is dropped
+/// even if compacted away meanwhile.
+struct LsmSnapshot {
+    /// Entries of the memtables, newest first.
+    memtables: Vec<Vec<Entry>>,
+    levels: Arc<Vec<Vec<Arc<Table>>>>,
+}
+
+impl StorageSnapshot for LsmSnapshot {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        for entries in &self.memtables {
+            if let Ok(position) = entries.binary_search_by(|(entry_key, _)| entry_key[..].cmp(key))
+            {
+                return Ok(entries[position].1.clone());
+            }
+        }
+        get_from_tables(&self.levels, key)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut sources: Vec<Source<'_>> = Vec::new();
+        for entries in &self.memtables {
+            let entries = entries
+                .iter()
+                .filter(move |(key, _)| RangeBounds::<[u8]>::contains(&range, &key[..]))
+                .map(|entry| Ok(entry.clone()));
+            sources.push(Box::new(entries));
+        }
+        sources.extend(
+            self.levels[0]
+                .iter()
+                .rev()
+                .filter_map(|table| clip(table, range)),
+        );
+        for tables in &self.levels[1..] {
+            // tables of deeper levels are disjoint, so chained they are sorted
+            let run = tables
+                .iter()
+                .filter_map(|table| clip(table, range))
+                .flatten();
+            sources.push(Box::new(run));
+        }
+        MergeIter::new(sources)
+            .filter_map(|entry| match entry {
+                Ok((key, Some(value))) => Some(Ok((key, value))),
+                Ok((_, None)) => None,
+                Err(error) => Some(Err(error)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LsmBuilder;
+    use crate::storage::StorageEngine;
+    use crate::wal::SyncPolicy;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_compacts_into_deeper_levels_and_survives_reopen() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-lsm-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let builder = LsmBuilder::new()
+            .memtable_size(32 << 10)
+            .table_size(16 << 10)
+            .level_size_base(64 << 10)
+            .level_multiplier(2)
+            .sync_policy(SyncPolicy::Never);
+        let value = |key: u32, round: u32| format!("{}-{}", key, round).repeat(8);
+
+        let engine = Arc::new(builder.clone().open(&dir).unwrap());
+        let writers: Vec<_> = (0..4u32)
+            .map(|thread| {
+                let engine = engine.clone();
+                thread::spawn(move || {
+                    for round in 0..3 {
+                        for key in (thread..4000).step_by(4) {
+                            let bytes = key.to_be_bytes();
+                            engine.put(&bytes, value(key, round).as_bytes()).unwrap();
+                        }
+                    }
+                    for key in (thread..4000).step_by(12) {
+                        assert!(engine.delete(&key.to_be_bytes()).unwrap());
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        engine.wait_for_compactions().unwrap();
+
+        let counts = engine.table_counts();
+        assert!(counts[2..].iter().any(|count| *count > 0), "{:?}", counts);
+        let check = |engine: &dyn StorageEngine| {
+            for key in 0..4000u32 {
+                let expected = match key % 12 < 4 {
+                    true => None,
+                    false => Some(value(key, 2).into_bytes()),
+                };
+                assert_eq!(engine.get(&key.to_be_bytes()).unwrap(), expected);
+            }
+        };
+        check(&*engine);
+        drop(engine);
+
+        let engine = builder.open(&dir).unwrap();
+        check(&engine);
+        engine.wait_for_compactions().unwrap();
+        let sst_files = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+            .count();
+        assert_eq!(sst_files, engine.table_counts().iter().sum::<usize>());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,616 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::checksum::crc32_update;
+use crate::codec::{
+    decode_bytes, decode_len, encode_bytes, encode_len, invalid_data, Decode, Encode,
+};
+use crate::collections::utils::mix;
+use crate::compression::{self, Compression};
+use crate::encryption::Encryption;
+use crate::storage::BlockCache;
+use crate::verify::{self, VerifyReport};
+
+/// A key along with its value, `None` for a deletion.
+pub(super) type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// Data blocks are cut once they reach this size.
+const BLOCK_SIZE: usize = 4096;
+
+/// Magic numbers of the tables written before their footer had flags, of
+/// plain and of encrypted tables.
+const MAGIC: u64 = u64::from_le_bytes(*b"PLDBSST1");
+const ENCRYPTED_MAGIC: u64 = u64::from_le_bytes(*b"PLDBSSTE");
+
+/// Magic number of the tables whose footer has flags.
+const FLAGS_MAGIC: u64 = u64::from_le_bytes(*b"PLDBSST2");
+
+/// Length of the footer: offsets and lengths of the index and bloom blocks,
+/// entry count, flags unless the table predates them, and magic number.
+const FOOTER_LEN: usize = 56;
+const LEGACY_FOOTER_LEN: usize = 48;
+
+/// Flags telling how the blocks of a table are stored.
+const ENCRYPTED: u64 = 1;
+const PACKED: u64 = 2;
+
+const BLOOM_BITS_PER_KEY: usize = 10;
+const BLOOM_PROBES: u32 = 7;
+
+/// Returns the two hashes the bloom filter probes of `key` derive from.
+fn bloom_hashes(key: &[u8]) -> (u64, u64) {
+    // FNV-1a, stable across platforms and releases unlike std hashers
+    let hash = key.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (mix(hash), mix(!hash) | 1)
+}
+
+/// Compresses `block`, to be written at `offset`, if `compression` is
+/// enabled, encrypts it if `encryption` is set, then appends the CRC-32 of
+/// the result to it.
+fn seal(
+    mut block: Vec<u8>,
+    offset: u64,
+    compression: Compression,
+    encryption: Option<&Encryption>,
+) -> io::Result<Vec<u8>> {
+    if compression.is_enabled() {
+        let mut packed = Vec::new();
+        compression::pack(compression, &block, &mut packed);
+        block = packed;
+    }
+    let mut block = match encryption {
+        // bound to its offset, so blocks cannot be swapped around
+        Some(encryption) => encryption.seal(&block, &offset.to_le_bytes())?,
+        None => block,
+    };
+    let crc = crc32_update(0, &block);
+    block.extend_from_slice(&crc.to_le_bytes());
+    Ok(block)
+}
+
+/// Checks the CRC-32 ending `block`, read at `offset`, then decrypts it if
+/// `encryption` is set and decompresses it if `packed`, returning the block
+/// without its CRC-32.
+fn unseal(
+    mut block: Vec<u8>,
+    offset: u64,
+    packed: bool,
+    encryption: Option<&Encryption>,
+) -> io::Result<Vec<u8>> {
+    if block.len() < 4 {
+        return Err(invalid_data("truncated table block"));
+    }
+    let (body, crc) = block.split_at(block.len() - 4);
+    if crc32_update(0, body).to_le_bytes() != crc {
+        return Err(invalid_data("table block checksum mismatch"));
+    }
+    let block = match encryption {
+        Some(encryption) => encryption.open(body, &offset.to_le_bytes())?,
+        None => {
+            block.truncate(block.len() - 4);
+            block
+        }
+    };
+    match packed {
+        true => compression::unpack(block),
+        false => Ok(block),
+    }
+}
+
+/// Location of a data block, along with the last key stored in it.
+struct BlockHandle {
+    last_key: Vec<u8>,
+    offset: u64,
+    len: u32,
+}
+
+/// Writes a sorted run of entries to an SSTable file.
+pub(super) struct TableWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+    offset: u64,
+    block: Vec<u8>,
+    index: Vec<BlockHandle>,
+    smallest: Option<Vec<u8>>,
+    last_key: Vec<u8>,
+    hashes: Vec<(u64, u64)>,
+    encryption: Option<Encryption>,
+    compression: Compression,
+    cache: Arc<BlockCache>,
+}
+
+impl TableWriter {
+    /// Creates the table at `path`, whose blocks are compressed with
+    /// `compression` and encrypted if `encryption` is set.
+    pub(super) fn create(
+        path: PathBuf,
+        encryption: Option<Encryption>,
+        compression: Compression,
+        cache: Arc<BlockCache>,
+    ) -> io::Result<Self> {
+        Ok(TableWriter {
+            out: BufWriter::new(File::create(&path)?),
+            path,
+            offset: 0,
+            block: Vec::new(),
+            index: Vec::new(),
+            smallest: None,
+            last_key: Vec::new(),
+            hashes: Vec::new(),
+            encryption,
+            compression,
+            cache,
+        })
+    }
+
+    /// Adds an entry, whose key must be greater than every key added before.
+    pub(super) fn add(&mut self, key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+        debug_assert!(self.smallest.is_none() || key > &self.last_key[..]);
+        if self.smallest.is_none() {
+            self.smallest = Some(key.to_vec());
+        }
+        encode_bytes(key, &mut self.block);
+        match value {
+            Some(value) => {
+                self.block.push(1);
+                encode_bytes(value, &mut self.block);
+            }
+            None => self.block.push(0),
+        }
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.hashes.push(bloom_hashes(key));
+
+        if self.block.len() >= BLOCK_SIZE {
+            self.finish_block()?;
+        }
+        Ok(())
+    }
+
+    fn finish_block(&mut self) -> io::Result<()> {
+        let block = seal(
+            std::mem::take(&mut self.block),
+            self.offset,
+            self.compression,
+            self.encryption.as_ref(),
+        )?;
+        self.out.write_all(&block)?;
+        self.index.push(BlockHandle {
+            last_key: self.last_key.clone(),
+            offset: self.offset,
+            len: block.len() as u32,
+        });
+        self.offset += block.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the size the table would have if finished now, without its
+    /// index and bloom filter.
+    pub(super) fn estimated_size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    /// Writes the index and bloom filter, then syncs the table and opens it
+    /// for reading.
+    pub(super) fn finish(mut self, id: u64) -> io::Result<Table> {
+        if !self.block.is_empty() {
+            self.finish_block()?;
+        }
+
+        let words = (self.hashes.len() * BLOOM_BITS_PER_KEY).div_ceil(64).max(1);
+        let mut bloom = Bloom {
+            probes: BLOOM_PROBES,
+            words: vec![0u64; words],
+        };
+        for hashes in &self.hashes {
+            bloom.insert(*hashes);
+        }
+        let mut bloom_block = Vec::new();
+        bloom.probes.encode(&mut bloom_block);
+        bloom.words.encode(&mut bloom_block);
+
+        let mut block = Vec::new();
+        encode_bytes(self.smallest.as_deref().unwrap_or_default(), &mut block);
+        encode_len(self.index.len(), &mut block);
+        for handle in &self.index {
+            encode_bytes(&handle.last_key, &mut block);
+            handle.offset.encode(&mut block);
+            handle.len.encode(&mut block);
+        }
+        let encryption = self.encryption.as_ref();
+        let index_block = seal(block, self.offset, self.compression, encryption)?;
+        let bloom_offset = self.offset + index_block.len() as u64;
+        let bloom_block = seal(bloom_block, bloom_offset, self.compression, encryption)?;
+        let mut flags = 0;
+        if encryption.is_some() {
+            flags |= ENCRYPTED;
+        }
+        if self.compression.is_enabled() {
+            flags |= PACKED;
+        }
+
+        let mut footer = Vec::with_capacity(FOOTER_LEN);
+        self.offset.encode(&mut footer);
+        (index_block.len() as u64).encode(&mut footer);
+        bloom_offset.encode(&mut footer);
+        (bloom_block.len() as u64).encode(&mut footer);
+        (self.hashes.len() as u64).encode(&mut footer);
+        flags.encode(&mut footer);
+        FLAGS_MAGIC.encode(&mut footer);
+
+        self.out.write_all(&index_block)?;
+        self.out.write_all(&bloom_block)?;
+        self.out.write_all(&footer)?;
+        self.out.into_inner()?.sync_all()?;
+        Table::open(self.path, id, self.encryption.as_ref(), self.cache)
+    }
+}
+
+/// Bloom filter of the keys of a table, telling most absent keys apart
+/// without reading any block.
+struct Bloom {
+    probes: u32,
+    words: Vec<u64>,
+}
+
+impl Bloom {
+    fn positions(&self, (first, second): (u64, u64)) -> impl Iterator<Item = usize> {
+        let bits = self.words.len() as u64 * 64;
+        (0..u64::from(self.probes))
+            .map(move |probe| (first.wrapping_add(probe.wrapping_mul(second)) % bits) as usize)
+    }
+
+    fn insert(&mut self, hashes: (u64, u64)) {
+        for position in self.positions(hashes).collect::<Vec<_>>() {
+            self.words[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    fn maybe_contains(&self, key: &[u8]) -> bool {
+        self.positions(bloom_hashes(key))
+            .all(|position| self.words[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+/// An immutable, sorted table of entries stored in a file, whose index and
+/// bloom filter are kept in memory.
+pub(super) struct Table {
+    id: u64,
+    path: PathBuf,
+    file: Mutex<File>,
+    index: Vec<BlockHandle>,
+    bloom: Bloom,
+    smallest: Vec<u8>,
+    size: u64,
+    obsolete: AtomicBool,
+    encryption: Option<Encryption>,
+    packed: bool,
+    cache: Arc<BlockCache>,
+    /// Id of the table file in the cache.
+    cache_file: u64,
+}
+
+impl Table {
+    /// Opens the table at `path`. Tables written unencrypted are read as
+    /// well when `encryption` is set, and compressed tables whatever the
+    /// compression tables are written with.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if the table is encrypted but
+    /// `encryption` is not set.
+    pub(super) fn open(
+        path: PathBuf,
+        id: u64,
+        encryption: Option<&Encryption>,
+        cache: Arc<BlockCache>,
+    ) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        if size < LEGACY_FOOTER_LEN as u64 {
+            return Err(invalid_data("truncated table"));
+        }
+        let magic = read_at(&mut file, size - 8, 8)?;
+        let footer_len = match u64::decode(&mut &magic[..])? {
+            MAGIC | ENCRYPTED_MAGIC => LEGACY_FOOTER_LEN,
+            FLAGS_MAGIC if size >= FOOTER_LEN as u64 => FOOTER_LEN,
+            _ => return Err(invalid_data("not a table")),
+        };
+        let footer = read_at(&mut file, size - footer_len as u64, footer_len)?;
+        let mut input = &footer[..];
+        let mut field = || u64::decode(&mut input);
+        let (index_offset, index_len) = (field()?, field()?);
+        let (bloom_offset, bloom_len) = (field()?, field()?);
+        let _entries = field()?;
+        // legacy footers end with the magic number where flags come after
+        let flags = match field()? {
+            MAGIC => 0,
+            ENCRYPTED_MAGIC => ENCRYPTED,
+            flags if flags & !(ENCRYPTED | PACKED) == 0 => flags,
+            _ => return Err(invalid_data("table written by a later release")),
+        };
+        let packed = flags & PACKED != 0;
+        let encryption = match flags & ENCRYPTED != 0 {
+            false => None,
+            true => Some(encryption.cloned().ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "encrypted table opened without encryption configured",
+                )
+            })?),
+        };
+        if index_offset + index_len > size || bloom_offset + bloom_len > size {
+            return Err(invalid_data("not a table"));
+        }
+
+        let block = read_at(&mut file, bloom_offset, bloom_len as usize)?;
+        let block = unseal(block, bloom_offset, packed, encryption.as_ref())?;
+        let mut input = &block[..];
+        let bloom = Bloom {
+            probes: u32::decode(&mut input)?,
+            words: Vec::decode(&mut input)?,
+        };
+        if bloom.words.is_empty() {
+            return Err(invalid_data("empty table bloom filter"));
+        }
+
+        let block = read_at(&mut file, index_offset, index_len as usize)?;
+        let block = unseal(block, index_offset, packed, encryption.as_ref())?;
+        let mut input = &block[..];
+        let smallest = decode_bytes(&mut input)?.to_vec();
+        let mut index = Vec::new();
+        for _ in 0..decode_len(&mut input)? {
+            index.push(BlockHandle {
+                last_key: decode_bytes(&mut input)?.to_vec(),
+                offset: u64::decode(&mut input)?,
+                len: u32::decode(&mut input)?,
+            });
+        }
+        if index.is_empty() {
+            return Err(invalid_data("empty table"));
+        }
+
+        Ok(Table {
+            id,
+            path,
+            file: Mutex::new(file),
+            index,
+            bloom,
+            smallest,
+            size,
+            obsolete: AtomicBool::new(false),
+            encryption,
+            packed,
+            cache_file: cache.file_id(),
+            cache,
+        })
+    }
+
+    pub(super) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the size of the table file.
+    pub(super) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(super) fn smallest(&self) -> &[u8] {
+        &self.smallest
+    }
+
+    pub(super) fn largest(&self) -> &[u8] {
+        &self.index[self.index.len() - 1].last_key
+    }
+
+    /// Returns `true` if some keys of the table are within `smallest` and
+    /// `largest`, included.
+    pub(super) fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest() <= largest && smallest <= self.largest()
+    }
+
+    /// Deletes the table file once the last reference to the table is
+    /// dropped.
+    pub(super) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::SeqCst);
+    }
+
+    /// Reads the data block of `handle` through the cache, adding it to the
+    /// cache if `fill` is set.
+    fn read_block(&self, handle: &BlockHandle, fill: bool) -> io::Result<Arc<[u8]>> {
+        let key = (self.cache_file, handle.offset);
+        if let Some(block) = self.cache.get(key) {
+            return Ok(block);
+        }
+        let block = read_at(
+            &mut self.file.lock().unwrap(),
+            handle.offset,
+            handle.len as usize,
+        )?;
+        let block: Arc<[u8]> =
+            unseal(block, handle.offset, self.packed, self.encryption.as_ref())?.into();
+        if fill {
+            self.cache.insert(key, block.clone(), false);
+        }
+        Ok(block)
+    }
+
+    /// Checks the checksum of every block of the table, bypassing the
+    /// cache, adding the blocks failing it to `report`.
+    pub(super) fn verify(&self, report: &mut VerifyReport) -> io::Result<()> {
+        for handle in &self.index {
+            let block = read_at(
+                &mut self.file.lock().unwrap(),
+                handle.offset,
+                handle.len as usize,
+            );
+            let unsealed = block.and_then(|block| {
+                unseal(block, handle.offset, self.packed, self.encryption.as_ref())
+            });
+            match unsealed {
+                Err(err) if verify::is_corruption(&err) => {
+                    report.corrupted(&self.path, handle.offset, u64::from(handle.len), &err)
+                }
+                unsealed => drop(unsealed?),
+            }
+        }
+        // the index, the bloom filter and the footer follow the data blocks
+        let last = &self.index[self.index.len() - 1];
+        let end = last.offset + u64::from(last.len);
+        let reopened = Table::open(
+            self.path.clone(),
+            self.id,
+            self.encryption.as_ref(),
+            self.cache.clone(),
+        );
+        match reopened {
+            Err(err) if verify::is_corruption(&err) => {
+                report.corrupted(&self.path, end, self.size - end, &err)
+            }
+            reopened => drop(reopened?),
+        }
+        report.checked(self.size);
+        Ok(())
+    }
+
+    /// Looks `key` up in the table.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the table has no entry for `key`, `Some(None)` if it holds
+    /// a deletion of `key`.
+    pub(super) fn get(&self, key: &[u8]) -> io::Result<Option<Option<Vec<u8>>>> {
+        if !self.bloom.maybe_contains(key) {
+            return Ok(None);
+        }
+        let position = self
+            .index
+            .partition_point(|handle| &handle.last_key[..] < key);
+        let handle = match self.index.get(position) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        let block = self.read_block(handle, true)?;
+        for entry in BlockEntries::new(&block) {
+            let (entry_key, value) = entry?;
+            if entry_key == key {
+                return Ok(Some(value.map(<[u8]>::to_vec)));
+            }
+            if entry_key > key {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns an iterator over the entries of the table, in key order,
+    /// reading one block at a time. Blocks are read through the cache but
+    /// not added to it, so that scans and compactions do not evict the
+    /// blocks point reads keep hitting.
+    pub(super) fn iter(&self) -> TableIter<'_> {
+        TableIter {
+            table: self,
+            block: 0,
+            entries: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Decodes the entries of a data block, unsealed.
+struct BlockEntries<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> BlockEntries<'a> {
+    fn new(block: &'a [u8]) -> Self {
+        BlockEntries { input: block }
+    }
+
+    fn decode(&mut self) -> io::Result<(&'a [u8], Option<&'a [u8]>)> {
+        let key = decode_bytes(&mut self.input)?;
+        let value = match u8::decode(&mut self.input)? {
+            0 => None,
+            1 => Some(decode_bytes(&mut self.input)?),
+            _ => return Err(invalid_data("invalid table entry")),
+        };
+        Ok((key, value))
+    }
+}
+
+impl<'a> Iterator for BlockEntries<'a> {
+    type Item = io::Result<(&'a [u8], Option<&'a [u8]>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None;
+        }
+        let entry = self.decode();
+        if entry.is_err() {
+            self.input = &[];
+        }
+        Some(entry)
+    }
+}
+
+/// Iterator over the entries of a [`Table`], see [`Table::iter`].
+pub(super) struct TableIter<'a> {
+    table: &'a Table,
+    block: usize,
+    entries: std::vec::IntoIter<Entry>,
+}
+
+impl TableIter<'_> {
+    fn load_block(&mut self) -> io::Result<bool> {
+        let handle = match self.table.index.get(self.block) {
+            Some(handle) => handle,
+            None => return Ok(false),
+        };
+        self.block += 1;
+        let block = self.table.read_block(handle, false)?;
+        let entries = BlockEntries::new(&block)
+            .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.map(<[u8]>::to_vec))))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.entries = entries.into_iter();
+        Ok(true)
+    }
+}
+
+impl Iterator for TableIter<'_> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+            match self.load_block() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(error) => {
+                    self.block = self.table.index.len();
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::io;
+
+use super::{sorted_in_range, CopiedSnapshot, KeyRange, StorageEngine, StorageSnapshot};
+use crate::Map;
+
+/// Storage engine keeping every entry in memory, in a [`Map`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{MemoryEngine, StorageEngine};
+///
+/// let engine = MemoryEngine::new();
+/// engine.put(b"key", b"value").unwrap();
+/// assert_eq!(engine.get(b"key").unwrap(), Some(b"value".to_vec()));
+/// ```
+#[derive(Default)]
+pub struct MemoryEngine {
+    map: Map<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryEngine {
+    /// Creates an empty `MemoryEngine`.
+    pub fn new() -> Self {
+        MemoryEngine { map: Map::new() }
+    }
+
+    /// Creates a `MemoryEngine` storing its entries in `map`.
+    pub fn with_map(map: Map<Vec<u8>, Vec<u8>>) -> Self {
+        MemoryEngine { map }
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.map.get(&key.to_vec()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.map.put(&key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        Ok(self.map.remove(&key.to_vec()).is_some())
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let frozen = self.map.freeze();
+        let entries = frozen
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()));
+        Ok(sorted_in_range(range, entries))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        let frozen = self.map.freeze();
+        let snapshot: CopiedSnapshot = frozen
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(Box::new(snapshot))
+    }
+}
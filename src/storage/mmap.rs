@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use memmap2::MmapMut;
+
+use super::{sorted_in_range, KeyRange, StorageEngine, StorageSnapshot, VacuumReport};
+use crate::checksum::crc32_update;
+use crate::codec::invalid_data;
+use crate::collections::utils::{LockPolicy, PriorityRwLock};
+use crate::files;
+use crate::verify::VerifyReport;
+use crate::Map;
+
+/// Name of the data file within the directory of the engine.
+pub const DATA_FILE: &str = "entries.mmap";
+
+/// Size the data file is created with, it then doubles whenever full.
+pub const INITIAL_SIZE: u64 = 1 << 20;
+
+/// Length of the header of a record: CRC-32, key length and value length.
+const HEADER_LEN: usize = 12;
+
+/// Value length marking a record as the deletion of its key.
+const TOMBSTONE: u32 = u32::MAX;
+
+/// The mapped data file, along with the offset records are appended at.
+struct Mapping {
+    file: File,
+    mmap: MmapMut,
+    tail: u64,
+}
+
+/// A record read from the data file.
+struct Record<'a> {
+    key: &'a [u8],
+    value: Option<&'a [u8]>,
+    len: usize,
+}
+
+/// Reads the record at `offset` of `data`.
+///
+/// # Returns
+///
+/// `None` at the end of the records, that is on the zeroes the file is
+/// extended with, or on a record torn by a crash.
+fn read_record(data: &[u8], offset: usize) -> Option<Record<'_>> {
+    let header = data.get(offset..offset + HEADER_LEN)?;
+    let field = |at: usize| {
+        u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+    let (crc, key_len, value_len) = (field(0), field(4) as usize, field(8));
+
+    let body_len = match value_len {
+        TOMBSTONE => key_len,
+        value_len => key_len + value_len as usize,
+    };
+    let end = offset + HEADER_LEN + body_len;
+    let body = data.get(offset + HEADER_LEN..end)?;
+    if crc32_update(crc32_update(0, &header[4..]), body) != crc {
+        return None;
+    }
+    let (key, value) = body.split_at(key_len);
+    Some(Record {
+        key,
+        value: (value_len != TOMBSTONE).then_some(value),
+        len: HEADER_LEN + body_len,
+    })
+}
+
+/// Storage engine whose entries live in a memory-mapped file, located
+/// through an in-memory hash index.
+///
+/// Records are appended to the data file, whose pages the OS loads and
+/// evicts on demand, so the dataset can be larger than RAM while only keys
+/// and offsets are kept in memory. Opening the engine scans the records once
+/// to rebuild the index, without copying any value. Overwritten and deleted
+/// records are left in the file as garbage, until a
+/// [vacuum](StorageEngine::vacuum) rewrites it.
+///
+/// Writes take the mapping exclusively while appending their record, as the
+/// file may have to be grown and remapped, reads share it.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{MmapEngine, StorageEngine};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-mmap");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = MmapEngine::open(&dir).unwrap();
+/// engine.put(b"alice", b"100").unwrap();
+/// drop(engine);
+///
+/// let engine = MmapEngine::open(&dir).unwrap();
+/// assert_eq!(engine.get(b"alice").unwrap(), Some(b"100".to_vec()));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct MmapEngine {
+    dir: PathBuf,
+    mapping: PriorityRwLock<Mapping>,
+    index: Map<Box<[u8]>, u64>,
+    lock_policy: LockPolicy,
+    // snapshots open, whose offsets would be moved by a vacuum
+    snapshots: AtomicUsize,
+}
+
+impl MmapEngine {
+    /// Opens the engine stored in `dir`, creating the directory and the data
+    /// file if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(DATA_FILE))?;
+        if file.metadata()?.len() < INITIAL_SIZE {
+            file.set_len(INITIAL_SIZE)?;
+        }
+        // SAFETY: the data file belongs to the engine, it is not modified
+        // through other means while mapped.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let index = Map::new();
+        let mut tail = 0;
+        while let Some(record) = read_record(&mmap, tail) {
+            let key = Box::from(record.key);
+            match record.value {
+                Some(_) => index.put(&key, tail as u64),
+                None => index.unmap(&key),
+            }
+            tail += record.len;
+        }
+
+        Ok(MmapEngine {
+            dir,
+            mapping: PriorityRwLock::new(Mapping {
+                file,
+                mmap,
+                tail: tail as u64,
+            }),
+            index,
+            lock_policy: LockPolicy::default(),
+            snapshots: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the directory the engine is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of bytes of records in the data file, live or not.
+    pub fn data_len(&self) -> u64 {
+        self.mapping.read(self.lock_policy.read).tail
+    }
+
+    /// Returns the number of entries in the engine.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if the engine contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns the value of the live record at `offset`.
+    fn read_value(mapping: &Mapping, offset: u64) -> io::Result<Vec<u8>> {
+        read_record(&mapping.mmap, offset as usize)
+            .and_then(|record| record.value)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| invalid_data("indexed record is corrupted"))
+    }
+
+    /// Appends a record for `key`, `None` values marking deletions.
+    ///
+    /// # Returns
+    ///
+    /// The offset of the record.
+    fn append(&self, mapping: &mut Mapping, key: &[u8], value: Option<&[u8]>) -> io::Result<u64> {
+        let value_len = match value {
+            Some(value) if value.len() >= TOMBSTONE as usize => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "value too large",
+                ))
+            }
+            Some(value) => value.len() as u32,
+            None => TOMBSTONE,
+        };
+        let value = value.unwrap_or_default();
+        let len = (HEADER_LEN + key.len() + value.len()) as u64;
+
+        let offset = mapping.tail;
+        if offset + len > mapping.mmap.len() as u64 {
+            let size = (mapping.mmap.len() as u64 * 2).max(offset + len);
+            mapping.mmap.flush()?;
+            mapping.file.set_len(size)?;
+            // SAFETY: see MmapEngine::open
+            mapping.mmap = unsafe { MmapMut::map_mut(&mapping.file)? };
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        header[4..8].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        header[8..].copy_from_slice(&value_len.to_le_bytes());
+        let crc = crc32_update(crc32_update(crc32_update(0, &header[4..]), key), value);
+        header[..4].copy_from_slice(&crc.to_le_bytes());
+
+        let start = offset as usize;
+        let data = &mut mapping.mmap[start..start + len as usize];
+        data[..HEADER_LEN].copy_from_slice(&header);
+        data[HEADER_LEN..HEADER_LEN + key.len()].copy_from_slice(key);
+        data[HEADER_LEN + key.len()..].copy_from_slice(value);
+        mapping.tail += len;
+        Ok(offset)
+    }
+}
+
+impl StorageEngine for MmapEngine {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mapping = self.mapping.read(self.lock_policy.read);
+        let offset = match self.index.get(&Box::from(key)) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        Self::read_value(&mapping, offset).map(Some)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let mut mapping = self.mapping.write(self.lock_policy.write);
+        let offset = self.append(&mut mapping, key, Some(value))?;
+        self.index.put(&Box::from(key), offset);
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        let mut mapping = self.mapping.write(self.lock_policy.write);
+        let key = Box::from(key);
+        if self.index.get(&key).is_none() {
+            return Ok(false);
+        }
+        self.append(&mut mapping, &key, None)?;
+        self.index.unmap(&key);
+        Ok(true)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mapping = self.mapping.read(self.lock_policy.read);
+        let offsets = self
+            .index
+            .iter()
+            .map(|(key, offset)| (key.into_vec(), offset));
+        sorted_in_range(range, offsets)
+            .into_iter()
+            .map(|(key, offset)| Ok((key, Self::read_value(&mapping, offset)?)))
+            .collect()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.mapping.read(self.lock_policy.read).mmap.flush()
+    }
+
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        // under the lock a vacuum holds, which checks for snapshots
+        let _mapping = self.mapping.read(self.lock_policy.read);
+        let offsets = self
+            .index
+            .iter()
+            .map(|(key, offset)| (key.into_vec(), offset))
+            .collect();
+        self.snapshots.fetch_add(1, Ordering::SeqCst);
+        Ok(Box::new(MmapSnapshot {
+            engine: self,
+            offsets,
+        }))
+    }
+
+    /// Rewrites the data file with the live records alone if its share of
+    /// garbage is at least `garbage_ratio`, renaming the new file over it
+    /// once synced. Reads and writes wait for the rewrite. The data file is
+    /// left as it is while snapshots of the engine are open, as they read
+    /// the records where they were.
+    fn vacuum(&self, garbage_ratio: f64) -> io::Result<VacuumReport> {
+        let mut mapping = self.mapping.write(self.lock_policy.write);
+        let mut live: Vec<_> = self.index.iter().collect();
+        live.sort_unstable_by_key(|(_, offset)| *offset);
+        let mut records = Vec::with_capacity(live.len());
+        for (key, offset) in live {
+            let record = read_record(&mapping.mmap, offset as usize)
+                .ok_or_else(|| invalid_data("indexed record is corrupted"))?;
+            records.push((key, offset, record.len));
+        }
+        let live_len: u64 = records.iter().map(|(_, _, len)| *len as u64).sum();
+        let garbage = mapping.tail - live_len;
+        if garbage == 0
+            || (garbage as f64) < garbage_ratio * mapping.tail as f64
+            || self.snapshots.load(Ordering::SeqCst) > 0
+        {
+            return Ok(VacuumReport::default());
+        }
+
+        let path = self.dir.join(DATA_FILE);
+        let temp = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&temp)?);
+        let mut moved = Vec::with_capacity(records.len());
+        let mut tail = 0;
+        for (key, offset, len) in records {
+            let start = offset as usize;
+            out.write_all(&mapping.mmap[start..start + len])?;
+            moved.push((key, tail));
+            tail += len as u64;
+        }
+        let file = out.into_inner()?;
+        file.set_len(tail.max(INITIAL_SIZE))?;
+        file.sync_all()?;
+        drop(file);
+        files::rename_durably(&temp, &path)?;
+
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        // SAFETY: see MmapEngine::open
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        *mapping = Mapping { file, mmap, tail };
+        for (key, offset) in moved {
+            self.index.put(&key, offset);
+        }
+        Ok(VacuumReport {
+            files_rewritten: 1,
+            files_removed: 0,
+            bytes_reclaimed: garbage,
+        })
+    }
+
+    /// Checks every record of the data file, live or not, then that nothing
+    /// but the zeroes the file is extended with follows them. The records
+    /// after one failing its checksum cannot be located, so they are
+    /// reported along with it. Data following the records is reported as
+    /// well, be it records lost to a corrupted one the engine was reopened
+    /// on, or the remains of a record torn by a crash.
+    fn verify(&self) -> io::Result<VerifyReport> {
+        let mapping = self.mapping.read(self.lock_policy.read);
+        let path = self.dir.join(DATA_FILE);
+        let mut report = VerifyReport::default();
+        let tail = mapping.tail as usize;
+        let mut offset = 0;
+        while offset < tail {
+            match read_record(&mapping.mmap, offset) {
+                Some(record) => offset += record.len,
+                None => {
+                    let err = invalid_data("record checksum mismatch");
+                    report.corrupted(&path, offset as u64, (tail - offset) as u64, &err);
+                    break;
+                }
+            }
+        }
+        let trailing = &mapping.mmap[tail..];
+        if let Some(last) = trailing.iter().rposition(|byte| *byte != 0) {
+            let err = invalid_data("unreadable data past the last record");
+            report.corrupted(&path, tail as u64, last as u64 + 1, &err);
+        }
+        report.checked(mapping.mmap.len() as u64);
+        Ok(report)
+    }
+}
+
+/// Snapshot of an [`MmapEngine`], holding the offsets of the records live
+/// when it was taken, which stay in the data file as it is append-only.
+struct MmapSnapshot<'a> {
+    engine: &'a MmapEngine,
+    offsets: BTreeMap<Vec<u8>, u64>,
+}
+
+impl StorageSnapshot for MmapSnapshot<'_> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let engine = self.engine;
+        let mapping = engine.mapping.read(engine.lock_policy.read);
+        self.offsets
+            .get(key)
+            .map(|offset| MmapEngine::read_value(&mapping, *offset))
+            .transpose()
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let engine = self.engine;
+        let mapping = engine.mapping.read(engine.lock_policy.read);
+        self.offsets
+            .range::<[u8], _>(range)
+            .map(|(key, offset)| Ok((key.clone(), MmapEngine::read_value(&mapping, *offset)?)))
+            .collect()
+    }
+}
+
+impl Drop for MmapSnapshot<'_> {
+    fn drop(&mut self) {
+        self.engine.snapshots.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MmapEngine, INITIAL_SIZE};
+    use crate::storage::{StorageEngine, VacuumReport};
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_writes_grow_file_and_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-mmap-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let engine = Arc::new(MmapEngine::open(&dir).unwrap());
+        let value = vec![7u8; 1000];
+
+        let writers: Vec<_> = (0..4u32)
+            .map(|thread| {
+                let engine = engine.clone();
+                let value = value.clone();
+                thread::spawn(move || {
+                    for i in 0..1000u32 {
+                        let key = (thread * 1000 + i).to_le_bytes();
+                        engine.put(&key, &value).unwrap();
+                        assert_eq!(engine.get(&key).unwrap().as_ref(), Some(&value));
+                        if i % 2 == 0 {
+                            assert!(engine.delete(&key).unwrap());
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert!(engine.data_len() > INITIAL_SIZE);
+        engine.flush().unwrap();
+        drop(engine);
+
+        let engine = MmapEngine::open(&dir).unwrap();
+        assert_eq!(engine.len(), 2000);
+        for key in 0..4000u32 {
+            let expected = if key % 2 == 1 { Some(&value) } else { None };
+            assert_eq!(engine.get(&key.to_le_bytes()).unwrap().as_ref(), expected);
+        }
+
+        // the deleted records are rewritten away, unless a snapshot reads them
+        let len = engine.data_len();
+        let snapshot = engine.snapshot().unwrap();
+        assert_eq!(engine.vacuum(0.0).unwrap(), VacuumReport::default());
+        drop(snapshot);
+        assert_eq!(engine.vacuum(0.9).unwrap(), VacuumReport::default());
+        let report = engine.vacuum(0.5).unwrap();
+        assert_eq!(report.files_rewritten, 1);
+        assert_eq!(report.bytes_reclaimed, len - engine.data_len());
+        assert_eq!(engine.vacuum(0.0).unwrap(), VacuumReport::default());
+        engine.put(&1u32.to_le_bytes(), b"after").unwrap();
+        drop(engine);
+
+        let engine = MmapEngine::open(&dir).unwrap();
+        assert_eq!(engine.len(), 2000);
+        assert_eq!(
+            engine.get(&1u32.to_le_bytes()).unwrap(),
+            Some(b"after".to_vec())
+        );
+        assert_eq!(engine.get(&3u32.to_le_bytes()).unwrap(), Some(value));
+        assert!(engine.verify().unwrap().is_clean());
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+mod blob;
+mod btree;
+mod cache;
+mod lsm;
+mod memory;
+#[cfg(feature = "mmap")]
+mod mmap;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+use std::path::PathBuf;
+
+use crate::verify::VerifyReport;
+
+pub use self::blob::{BlobBuilder, BlobEngine, BlobGcStats};
+pub use self::btree::{BTreeBuilder, BTreeEngine};
+pub use self::cache::{BlockCache, CacheStats};
+pub use self::lsm::{CompactionStats, CompactionStrategy, LsmBuilder, LsmEngine};
+pub use self::memory::MemoryEngine;
+#[cfg(feature = "mmap")]
+pub use self::mmap::MmapEngine;
+
+/// Thread-Safe store of byte string keys and values, the interface every
+/// storage backend implements.
+pub trait StorageEngine: Send + Sync {
+    /// Returns the value corresponding to the key.
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Establishes a key value mapping for the key value pair.
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()>;
+
+    /// Erases the value associated with `key`, returning `true` if it was
+    /// present.
+    fn delete(&self, key: &[u8]) -> io::Result<bool>;
+
+    /// Returns the entries whose key is within `range`, sorted by key.
+    ///
+    /// The entries are those of a single point in time, writes made while
+    /// scanning being either all or not at all visible.
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Flushes the writes made so far to stable storage, if the engine has
+    /// any.
+    fn flush(&self) -> io::Result<()>;
+
+    /// Flushes the writes made so far to stable storage as the
+    /// [`Database`](crate::db::Database) of the engine is closed, writing
+    /// out what it keeps in memory so that opening it again has nothing to
+    /// recover. Engines have nothing more to do than [flush](Self::flush)
+    /// unless they say otherwise.
+    fn close(&self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Returns a read-only view of the entries as they are now, which later
+    /// writes to the engine do not change.
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>>;
+
+    /// Checks the checksums of everything the engine stored on disk,
+    /// reporting the corrupted regions rather than failing on the first
+    /// one, see [`crate::verify`]. Engines storing nothing on disk have
+    /// nothing to check.
+    fn verify(&self) -> io::Result<VerifyReport> {
+        Ok(VerifyReport::default())
+    }
+
+    /// Reclaims the disk space taken by overwritten and deleted entries:
+    /// files whose share of garbage is at least `garbage_ratio`, from 0 to
+    /// 1, are rewritten with their live entries alone, and files holding
+    /// nothing live are removed. Files without garbage are left as they
+    /// are. Engines storing nothing on disk, or reclaiming space on their
+    /// own as they go, have nothing to do.
+    fn vacuum(&self, garbage_ratio: f64) -> io::Result<VacuumReport> {
+        let _ = garbage_ratio;
+        Ok(VacuumReport::default())
+    }
+}
+
+impl<E: StorageEngine + ?Sized> StorageEngine for Box<E> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        (**self).put(key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        (**self).delete(key)
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        (**self).scan(range)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        (**self).close()
+    }
+
+    fn snapshot(&self) -> io::Result<Box<dyn StorageSnapshot + '_>> {
+        (**self).snapshot()
+    }
+
+    fn verify(&self) -> io::Result<VerifyReport> {
+        (**self).verify()
+    }
+
+    fn vacuum(&self, garbage_ratio: f64) -> io::Result<VacuumReport> {
+        (**self).vacuum(garbage_ratio)
+    }
+}
+
+/// Disk space reclaimed by a [vacuum](StorageEngine::vacuum).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VacuumReport {
+    /// Number of files rewritten with their live data alone.
+    pub files_rewritten: u64,
+    /// Number of files removed.
+    pub files_removed: u64,
+    /// Bytes of garbage reclaimed.
+    pub bytes_reclaimed: u64,
+}
+
+impl VacuumReport {
+    /// Adds the files and bytes reclaimed by `other`.
+    pub(crate) fn merge(&mut self, other: VacuumReport) {
+        self.files_rewritten += other.files_rewritten;
+        self.files_removed += other.files_removed;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+    }
+}
+
+/// Bounds of the keys of a [scan](StorageEngine::scan), `(Unbounded,
+/// Unbounded)` scanning every entry.
+pub type KeyRange<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+/// Read-only view of the entries of a [`StorageEngine`] at a point in time,
+/// see [`StorageEngine::snapshot`].
+pub trait StorageSnapshot: Send + Sync {
+    /// Returns the value corresponding to the key.
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Returns the entries whose key is within `range`, sorted by key.
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>>;
+}
+
+/// Snapshot holding a copy of every entry, for engines with no cheaper way
+/// to keep the entries of a point in time around.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{CopiedSnapshot, StorageSnapshot};
+/// use std::ops::Bound;
+///
+/// let entries = vec![(b"b".to_vec(), b"2".to_vec()), (b"a".to_vec(), b"1".to_vec())];
+/// let snapshot: CopiedSnapshot = entries.into_iter().collect();
+///
+/// assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+/// let entries = snapshot.scan((Bound::Excluded(&b"a"[..]), Bound::Unbounded)).unwrap();
+/// assert_eq!(entries, vec![(b"b".to_vec(), b"2".to_vec())]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CopiedSnapshot {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl FromIterator<(Vec<u8>, Vec<u8>)> for CopiedSnapshot {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(entries: I) -> Self {
+        CopiedSnapshot {
+            entries: entries.into_iter().collect(),
+        }
+    }
+}
+
+impl StorageSnapshot for CopiedSnapshot {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn scan(&self, range: KeyRange<'_>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .entries
+            .range::<[u8], _>(range)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+/// Sorts `entries` by key, keeping those within `range`.
+fn sorted_in_range<T>(
+    range: KeyRange<'_>,
+    entries: impl Iterator<Item = (Vec<u8>, T)>,
+) -> Vec<(Vec<u8>, T)> {
+    let mut entries: Vec<_> = entries
+        .filter(|(key, _)| RangeBounds::<[u8]>::contains(&range, &key[..]))
+        .collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+/// Fills `buf` with the bytes of `file` at `offset`, without moving its
+/// cursor, so that threads can read a shared file at once.
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The storage backends a [`StorageBuilder`] can open.
+#[derive(Clone, Debug)]
+pub enum Backend {
+    /// Entries live in memory, see [`MemoryEngine`].
+    Memory,
+    /// Entries live in a log-structured merge-tree in the given directory,
+    /// see [`LsmEngine`].
+    Lsm(PathBuf),
+    /// Entries live in a copy-on-write B-tree in the given directory, see
+    /// [`BTreeEngine`].
+    BTree(PathBuf),
+    /// Entries live in a memory-mapped file in the given directory, see
+    /// [`MmapEngine`].
+    #[cfg(feature = "mmap")]
+    Mmap(PathBuf),
+}
+
+/// Configures and opens a [`StorageEngine`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::storage::{Backend, StorageBuilder};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-storage-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let engine = StorageBuilder::new()
+///     .backend(Backend::BTree(dir.clone()))
+///     .open()
+///     .unwrap();
+///
+/// engine.put(b"key", b"value").unwrap();
+/// assert_eq!(engine.get(b"key").unwrap(), Some(b"value".to_vec()));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct StorageBuilder {
+    backend: Backend,
+    blobs: Option<(PathBuf, BlobBuilder)>,
+}
+
+impl Default for StorageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageBuilder {
+    /// Creates a builder for the in-memory backend.
+    pub fn new() -> Self {
+        StorageBuilder {
+            backend: Backend::Memory,
+            blobs: None,
+        }
+    }
+
+    /// Sets the backend storing the entries.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Stores large values out of the backend, in a value log in `dir`
+    /// configured by `blobs`, see [`BlobEngine`].
+    pub fn blobs<P: Into<PathBuf>>(mut self, dir: P, blobs: BlobBuilder) -> Self {
+        self.blobs = Some((dir.into(), blobs));
+        self
+    }
+
+    /// Opens the configured backend.
+    pub fn open(self) -> io::Result<Box<dyn StorageEngine>> {
+        let engine: Box<dyn StorageEngine> = match self.backend {
+            Backend::Memory => Box::new(MemoryEngine::new()),
+            Backend::Lsm(dir) => Box::new(LsmEngine::open(dir)?),
+            Backend::BTree(dir) => Box::new(BTreeEngine::open(dir)?),
+            #[cfg(feature = "mmap")]
+            Backend::Mmap(dir) => Box::new(MmapEngine::open(dir)?),
+        };
+        Ok(match self.blobs {
+            Some((dir, blobs)) => Box::new(blobs.open(dir, engine)?),
+            None => engine,
+        })
+    }
+}
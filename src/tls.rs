@@ -0,0 +1,308 @@
+//! TLS of the [server](crate::server) and of the `client`, with the `tls`
+//! feature, by rustls.
+//!
+//! Certificates and private keys are read in PEM. A server presents its
+//! certificate chain to clients, which check it against the certificate
+//! authorities they trust; with mutual TLS, the server checks the
+//! certificate of its clients as well, refusing those without one.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use palladiumdb::db::Database;
+//! use palladiumdb::server::ServerBuilder;
+//! use palladiumdb::tls::ServerTls;
+//! use std::fs;
+//! use std::sync::Arc;
+//!
+//! let tls = ServerTls::new(
+//!     &fs::read("server.pem").unwrap(),
+//!     &fs::read("server.key").unwrap(),
+//! )
+//! .unwrap();
+//! let server = ServerBuilder::new()
+//!     .tls(tls)
+//!     .bind(Arc::new(Database::new()))
+//!     .unwrap();
+//! ```
+
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+fn invalid_input<E: ToString>(err: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, err.to_string())
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn certificates(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_input)?;
+    if certificates.is_empty() {
+        return Err(invalid_input("no certificate found"));
+    }
+    Ok(certificates)
+}
+
+fn private_key(pem: &[u8]) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_slice(pem).map_err(invalid_input)
+}
+
+fn roots(pem: &[u8]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(pem)? {
+        roots.add(certificate).map_err(invalid_input)?;
+    }
+    Ok(roots)
+}
+
+/// TLS of a server, see [`ServerBuilder::tls`](crate::server::ServerBuilder::tls).
+#[derive(Clone, Debug)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+}
+
+impl ServerTls {
+    /// Creates the TLS of a server presenting `certificate_chain`, its own
+    /// certificate first, signed with `private_key`, both in PEM.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if the certificates or the key cannot
+    /// be read, or do not match.
+    pub fn new(certificate_chain: &[u8], private_key: &[u8]) -> io::Result<Self> {
+        Self::build(certificate_chain, private_key, None)
+    }
+
+    /// Creates the TLS of a server as [`ServerTls::new`] does, which only
+    /// accepts clients presenting a certificate signed by one of the
+    /// certificate authorities of `client_ca`, in PEM.
+    pub fn mutual(
+        certificate_chain: &[u8],
+        private_key: &[u8],
+        client_ca: &[u8],
+    ) -> io::Result<Self> {
+        Self::build(certificate_chain, private_key, Some(client_ca))
+    }
+
+    fn build(certificate_chain: &[u8], key: &[u8], client_ca: Option<&[u8]>) -> io::Result<Self> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_input)?;
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(roots(client_ca)?),
+                    provider(),
+                )
+                .build()
+                .map_err(invalid_input)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certificates(certificate_chain)?, private_key(key)?)
+            .map_err(invalid_input)?;
+        Ok(ServerTls {
+            config: Arc::new(config),
+        })
+    }
+
+    pub(crate) fn config(&self) -> &Arc<ServerConfig> {
+        &self.config
+    }
+}
+
+/// TLS of a client, see `ClientBuilder::tls`, with the `client` feature.
+#[derive(Clone, Debug)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl ClientTls {
+    /// Creates the TLS of a client trusting the certificate authorities of
+    /// `ca`, in PEM.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if the certificates cannot be read.
+    pub fn new(ca: &[u8]) -> io::Result<Self> {
+        Self::build(ca, None)
+    }
+
+    /// Creates the TLS of a client as [`ClientTls::new`] does, presenting
+    /// `certificate_chain` signed with `private_key` to servers requiring
+    /// mutual TLS, both in PEM.
+    pub fn mutual(ca: &[u8], certificate_chain: &[u8], private_key: &[u8]) -> io::Result<Self> {
+        Self::build(ca, Some((certificate_chain, private_key)))
+    }
+
+    fn build(ca: &[u8], identity: Option<(&[u8], &[u8])>) -> io::Result<Self> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_input)?
+            .with_root_certificates(roots(ca)?);
+        let config = match identity {
+            Some((certificate_chain, key)) => builder
+                .with_client_auth_cert(certificates(certificate_chain)?, private_key(key)?)
+                .map_err(invalid_input)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(ClientTls {
+            config: Arc::new(config),
+            server_name: None,
+        })
+    }
+
+    /// Sets the name the certificate of the server must hold, the host of
+    /// the address connected to by default.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if `server_name` is neither a DNS name
+    /// nor an IP address.
+    pub fn server_name(mut self, server_name: &str) -> io::Result<Self> {
+        self.server_name =
+            Some(ServerName::try_from(server_name.to_string()).map_err(invalid_input)?);
+        Ok(self)
+    }
+
+    /// Returns the configuration of rustls, for clients other than
+    /// `Client`.
+    pub fn config(&self) -> &Arc<ClientConfig> {
+        &self.config
+    }
+
+    /// Returns the name the certificate of the server at `address` must
+    /// hold.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidInput` if the host of `address` is neither
+    /// a DNS name nor an IP address.
+    pub fn server_name_of(&self, address: &str) -> io::Result<ServerName<'static>> {
+        if let Some(server_name) = &self.server_name {
+            return Ok(server_name.clone());
+        }
+        let host = match address.rsplit_once(':') {
+            Some((host, _)) => host,
+            None => address,
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        ServerName::try_from(host.to_string()).map_err(invalid_input)
+    }
+}
+
+/// Certificates generated for tests: those of a certificate authority, of
+/// `localhost` and of a client it signed, with the keys of the latter two.
+#[cfg(test)]
+pub(crate) struct TestCertificates {
+    pub ca: String,
+    pub server: String,
+    pub server_key: String,
+    pub client: String,
+    pub client_key: String,
+}
+
+#[cfg(test)]
+impl TestCertificates {
+    pub fn generate() -> Self {
+        use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        let sign = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            let certificate = params.signed_by(&key, &ca).unwrap();
+            (certificate.pem(), key.serialize_pem())
+        };
+        let (server, server_key) = sign("localhost");
+        let (client, client_key) = sign("client");
+        TestCertificates {
+            ca: ca.pem(),
+            server,
+            server_key,
+            client,
+            client_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientTls, ServerTls, TestCertificates};
+    use crate::db::Database;
+    use crate::server::{Protocol, ServerBuilder};
+    use rustls::{ClientConnection, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_http_over_mutual_tls() {
+        let certificates = TestCertificates::generate();
+        let TestCertificates {
+            ca,
+            server,
+            server_key,
+            client,
+            client_key,
+        } = &certificates;
+        assert!(ServerTls::new(b"", server_key.as_bytes()).is_err());
+        assert!(ServerTls::new(client.as_bytes(), server_key.as_bytes()).is_err());
+        let tls = ServerTls::mutual(server.as_bytes(), server_key.as_bytes(), ca.as_bytes());
+        let server = ServerBuilder::new()
+            .bind_address("127.0.0.1:0")
+            .workers(2)
+            .protocol(Protocol::Http)
+            .tls(tls.unwrap())
+            .bind(Arc::new(Database::new()))
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let get = |tls: ClientTls| {
+            let server_name = tls.server_name_of("localhost:7380").unwrap();
+            let connection = ClientConnection::new(tls.config().clone(), server_name).unwrap();
+            let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+            let request = "GET /keys/alice HTTP/1.1\r\nConnection: close\r\n\r\n";
+            stream.write_all(request.as_bytes())?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok::<_, std::io::Error>(response)
+        };
+        let tls = ClientTls::mutual(ca.as_bytes(), client.as_bytes(), client_key.as_bytes());
+        let response = get(tls.unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        // clients without a certificate are refused
+        assert!(get(ClientTls::new(ca.as_bytes()).unwrap()).is_err());
+        // as are plaintext ones
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /keys/alice HTTP/1.1\r\n\r\n")
+            .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP"));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
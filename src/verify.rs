@@ -0,0 +1,236 @@
+//! Scrubbing of the files of a database, see [`Database::verify`].
+//!
+//! Every record, block and page written to disk carries a CRC-32 checked
+//! whenever it is read, so corruption is reported as an error of kind
+//! `InvalidData` rather than returned as garbage. Reads only check what they
+//! read though: a verification reads everything, reporting every region
+//! failing its checksum in a [`VerifyReport`] rather than stopping at the
+//! first one, so that corruption is found before the data is needed.
+//!
+//! [`Database::verify`]: crate::Database::verify
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// A region of a file failing its checksum, or otherwise unreadable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// The file holding the region.
+    pub path: PathBuf,
+    /// Offset of the region within the file.
+    pub offset: u64,
+    /// Length of the region.
+    pub len: u64,
+    /// What is wrong with the region.
+    pub reason: String,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes at offset {}: {}",
+            self.path.display(),
+            self.len,
+            self.offset,
+            self.reason
+        )
+    }
+}
+
+/// Outcome of a verification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of files checked.
+    pub files: u64,
+    /// Number of bytes checked.
+    pub bytes: u64,
+    /// Regions found corrupted, in the order they were checked.
+    pub corruptions: Vec<Corruption>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no corruption was found.
+    pub fn is_clean(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+
+    /// Copies the bytes of every corrupted region to a file of its own in
+    /// `dir`, created if needed, named after the file and offset of the
+    /// region, so that they can be inspected or repaired by hand. The files
+    /// verified are left as they are, their corrupted regions being skipped
+    /// or refused when read.
+    ///
+    /// # Returns
+    ///
+    /// The paths of the files written, one per region still found in its
+    /// file.
+    pub fn quarantine<P: AsRef<Path>>(&self, dir: P) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut quarantined = Vec::new();
+        for (index, corruption) in self.corruptions.iter().enumerate() {
+            let mut file = match File::open(&corruption.path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut bytes = Vec::new();
+            file.seek(SeekFrom::Start(corruption.offset))?;
+            file.take(corruption.len).read_to_end(&mut bytes)?;
+
+            let name = corruption.path.file_name().unwrap_or_default();
+            let path = dir.join(format!(
+                "{:04}-{}-{}",
+                index,
+                name.to_string_lossy(),
+                corruption.offset
+            ));
+            fs::write(&path, bytes)?;
+            quarantined.push(path);
+        }
+        Ok(quarantined)
+    }
+
+    /// Counts `len` bytes of a file as checked.
+    pub(crate) fn checked(&mut self, len: u64) {
+        self.files += 1;
+        self.bytes += len;
+    }
+
+    /// Records the region of `path` at `offset`, `len` bytes long, as
+    /// corrupted for the reason `err`.
+    pub(crate) fn corrupted(&mut self, path: &Path, offset: u64, len: u64, err: &io::Error) {
+        self.corruptions.push(Corruption {
+            path: path.to_path_buf(),
+            offset,
+            len,
+            reason: err.to_string(),
+        });
+    }
+
+    /// Adds the files checked and the corruptions found by `other`.
+    pub(crate) fn merge(&mut self, mut other: VerifyReport) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.corruptions.append(&mut other.corruptions);
+    }
+}
+
+/// Returns `true` if `err` tells of corrupted or truncated data.
+pub(crate) fn is_corruption(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DatabaseBuilder;
+    use crate::storage::{BTreeEngine, LsmBuilder, StorageEngine};
+    use crate::wal::Wal;
+    use std::fs::{self, OpenOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+
+    fn flip_byte(path: &Path, offset: u64) {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let mut byte = [0];
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[!byte[0]]).unwrap();
+    }
+
+    fn only_file(dir: &Path, extension: &str) -> PathBuf {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == extension))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reports_and_quarantines_corrupted_regions() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-verify-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        // records of 16 bytes of header and 1 of payload
+        let wal = Wal::open(dir.join("wal")).unwrap();
+        for record in [b"a", b"b", b"c"] {
+            wal.append(record).unwrap();
+        }
+        wal.sync().unwrap();
+        assert!(wal.verify().unwrap().is_clean());
+        let segment = only_file(&dir.join("wal"), "wal");
+        flip_byte(&segment, 17 + 16);
+        let report = wal.verify().unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(report.corruptions.len(), 1);
+        assert_eq!(
+            (report.corruptions[0].offset, report.corruptions[0].len),
+            (17, 17)
+        );
+
+        let lsm = LsmBuilder::new().open(dir.join("lsm")).unwrap();
+        for key in 0..100u32 {
+            lsm.put(&key.to_be_bytes(), &[1; 100]).unwrap();
+        }
+        lsm.flush_memtable().unwrap();
+        lsm.wait_for_compactions().unwrap();
+        assert!(lsm.verify().unwrap().is_clean());
+        let table = only_file(&dir.join("lsm"), "sst");
+        flip_byte(&table, 0);
+        let corruptions = lsm.verify().unwrap().corruptions;
+        assert_eq!(corruptions.len(), 1);
+        assert_eq!((&corruptions[0].path, corruptions[0].offset), (&table, 0));
+
+        // the overflow pages of the large value come first in the file
+        let btree = BTreeEngine::open(dir.join("btree")).unwrap();
+        btree.put(b"large", &[3; 20_000]).unwrap();
+        for key in 0..1000u32 {
+            btree.put(&key.to_be_bytes(), &[2; 100]).unwrap();
+        }
+        let db = DatabaseBuilder::new()
+            .engine(btree)
+            .open(dir.join("db"))
+            .unwrap();
+        let report = db.verify().unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.files, 2);
+        let page_size = db.engine().page_size() as u64;
+        flip_byte(&dir.join("btree").join("tree.btree"), page_size + 10);
+        let report = db.verify().unwrap();
+        assert_eq!(report.corruptions.len(), 1);
+        assert_eq!(report.corruptions[0].offset, page_size);
+
+        let quarantined = report.quarantine(dir.join("quarantine")).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(fs::read(&quarantined[0]).unwrap().len() as u64, page_size);
+        drop(db);
+
+        #[cfg(feature = "mmap")]
+        {
+            use crate::storage::MmapEngine;
+
+            let engine = MmapEngine::open(dir.join("mmap")).unwrap();
+            engine.put(b"a", b"1").unwrap();
+            engine.put(b"b", b"2").unwrap();
+            engine.flush().unwrap();
+            assert!(engine.verify().unwrap().is_clean());
+            // the key of the first record, 12 bytes of header in
+            flip_byte(&dir.join("mmap").join("entries.mmap"), 12);
+            let corruptions = engine.verify().unwrap().corruptions;
+            assert_eq!(corruptions.len(), 1);
+            assert_eq!((corruptions[0].offset, corruptions[0].len), (0, 28));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+
+use super::{Wal, WalBuilder};
+use crate::codec::{decode_all, invalid_data, Decode, Encode};
+use crate::collections::map::{Map, MapBuilder};
+
+/// A write to a [`Map`], as logged in a [`Wal`].
+pub(crate) enum Mutation<K, V> {
+    Put(K, V),
+    Remove(K),
+}
+
+impl<K: Encode, V: Encode> Mutation<K, V> {
+    pub(crate) fn encode_put(buf: &mut Vec<u8>, key: &K, value: &V) {
+        buf.push(0);
+        key.encode(buf);
+        value.encode(buf);
+    }
+
+    pub(crate) fn encode_remove(buf: &mut Vec<u8>, key: &K) {
+        buf.push(1);
+        key.encode(buf);
+    }
+}
+
+impl<K: Decode, V: Decode> Decode for Mutation<K, V> {
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(input)? {
+            0 => Ok(Mutation::Put(K::decode(input)?, V::decode(input)?)),
+            1 => Ok(Mutation::Remove(K::decode(input)?)),
+            _ => Err(invalid_data("unknown mutation")),
+        }
+    }
+}
+
+/// Thread-Safe [`Map`] whose writes are logged to a [`Wal`] before being
+/// applied, so they survive a restart of the process.
+///
+/// Opening a `Durable` map replays its log from the start. The log entry of
+/// a write is appended while the bucket of its key is write-locked, so the
+/// order of the writes to a key in the log is the order they were applied
+/// in, and replaying the log restores the exact same entries.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::wal::Durable;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-durable");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let map = Durable::open(&dir).unwrap();
+/// map.put(&"alice".to_string(), 100u64).unwrap();
+/// map.put(&"bob".to_string(), 20).unwrap();
+/// map.remove(&"bob".to_string()).unwrap();
+/// drop(map);
+///
+/// let map: Durable<String, u64> = Durable::open(&dir).unwrap();
+/// assert_eq!(map.get(&"alice".to_string()), Some(100));
+/// assert_eq!(map.get(&"bob".to_string()), None);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Durable<K, V, H = RandomState> {
+    map: Map<K, V, H>,
+    wal: Arc<Wal>,
+    /// Bytes every record starts with, telling apart the maps sharing a log.
+    prefix: Vec<u8>,
+}
+
+impl<K, V> Durable<K, V, RandomState>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+{
+    /// Opens the map logged in `dir` with the default configuration,
+    /// creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Self::with_builders(MapBuilder::new(), WalBuilder::new(), dir)
+    }
+}
+
+impl<K, V, H> Durable<K, V, H>
+where
+    K: Hash + Eq + Clone + Encode + Decode,
+    V: Clone + Encode + Decode,
+    H: BuildHasher,
+{
+    /// Opens the map logged in `dir`, configured by `map_builder`, with its
+    /// log configured by `wal_builder`.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a record of the log is corrupted or
+    /// was not written for these key and value types.
+    pub fn with_builders<P: AsRef<Path>>(
+        map_builder: MapBuilder<H>,
+        wal_builder: WalBuilder,
+        dir: P,
+    ) -> io::Result<Self> {
+        let map = map_builder.build();
+        let wal = wal_builder.open(dir)?;
+        for record in wal.iter_from(0)? {
+            let (_, payload) = record?;
+            apply_mutation(&map, &payload)?;
+        }
+        Ok(Durable::with_shared_wal(map, Arc::new(wal), Vec::new()))
+    }
+
+    /// Wraps `map`, logging its writes to `wal` in records starting with
+    /// `prefix`. The log is not replayed.
+    pub(crate) fn with_shared_wal(map: Map<K, V, H>, wal: Arc<Wal>, prefix: Vec<u8>) -> Self {
+        Durable { map, wal, prefix }
+    }
+
+    /// Returns the record logging the put of `value` to `key`, or the
+    /// removal of `key` if `None`.
+    pub(crate) fn record_of(&self, key: &K, value: Option<&V>) -> Vec<u8> {
+        let mut record = self.prefix.clone();
+        match value {
+            Some(value) => Mutation::encode_put(&mut record, key, value),
+            None => Mutation::<K, V>::encode_remove(&mut record, key),
+        }
+        record
+    }
+
+    /// Logs then establishes a key value mapping for the key value pair.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the log, in which case the map is left
+    /// unchanged.
+    pub fn put(&self, key: &K, value: V) -> io::Result<()> {
+        let record = self.record_of(key, Some(&value));
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            self.wal.append(&record)?;
+            locked.put(key, value);
+            Ok(())
+        })
+    }
+
+    /// Logs then establishes a key value mapping for the key value pair,
+    /// syncing the log before returning whatever its sync policy, for the
+    /// writes which must not be lost while others can.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the log, in which case the map is left
+    /// unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::collections::map::MapBuilder;
+    /// use palladiumdb::wal::{Durable, SyncPolicy, WalBuilder};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-durable-put-durable");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal_builder = WalBuilder::new().sync_policy(SyncPolicy::Never);
+    /// let orders = Durable::with_builders(MapBuilder::new(), wal_builder, &dir).unwrap();
+    /// orders.put(&1u64, "pending".to_string()).unwrap();
+    /// orders.put_durable(&2u64, "paid".to_string()).unwrap();
+    /// # drop(orders);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn put_durable(&self, key: &K, value: V) -> io::Result<()> {
+        let record = self.record_of(key, Some(&value));
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            self.wal.append_durable(&record)?;
+            locked.put(key, value);
+            Ok(())
+        })
+    }
+
+    /// Logs then erases the value associated with `key`, returning it if it
+    /// was present. Nothing is logged if the key is absent.
+    ///
+    /// # Returns
+    ///
+    /// The error of appending to the log, in which case the map is left
+    /// unchanged.
+    pub fn remove(&self, key: &K) -> io::Result<Option<V>> {
+        self.map.with_keys_locked(slice::from_ref(key), |locked| {
+            let value = locked.get(key);
+            if value.is_some() {
+                self.wal.append(&self.record_of(key, None))?;
+                locked.unmap(key);
+            }
+            Ok(value)
+        })
+    }
+
+    /// Returns the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Flushes every logged write to stable storage, regardless of the sync
+    /// policy of the log.
+    pub fn sync(&self) -> io::Result<()> {
+        self.wal.sync()
+    }
+
+    /// Returns the log of the map.
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    /// Returns the map itself, for reads bypassing the log.
+    pub(crate) fn map(&self) -> &Map<K, V, H> {
+        &self.map
+    }
+}
+
+/// Applies to `map` the write logged as `record`.
+///
+/// # Returns
+///
+/// An error of kind `InvalidData` if the record was not written for these
+/// key and value types.
+pub(crate) fn apply_mutation<K, V, H>(map: &Map<K, V, H>, record: &[u8]) -> io::Result<()>
+where
+    K: Hash + Eq + Clone + Decode,
+    V: Clone + Decode,
+    H: BuildHasher,
+{
+    match decode_all(record)? {
+        Mutation::Put(key, value) => map.put(&key, value),
+        Mutation::Remove(key) => map.unmap(&key),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Durable;
+    use crate::collections::map::MapBuilder;
+    use crate::wal::{SyncPolicy, WalBuilder};
+    use std::fs;
+
+    #[test]
+    fn test_durable_puts_sync_whatever_the_policy() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-durable-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let wal_builder = WalBuilder::new().sync_policy(SyncPolicy::Never);
+        let map = Durable::with_builders(MapBuilder::new(), wal_builder, &dir).unwrap();
+        let synced_lsn = || map.wal.group_commit.lock().unwrap().synced_lsn;
+
+        map.put(&1u64, 1u64).unwrap();
+        map.remove(&1).unwrap();
+        assert_eq!(synced_lsn(), 1);
+        map.put_durable(&2, 2).unwrap();
+        assert_eq!(synced_lsn(), map.wal.next_lsn());
+        map.put(&3, 3).unwrap();
+        assert!(synced_lsn() < map.wal.next_lsn());
+        drop(map);
+
+        let map: Durable<u64, u64> = Durable::open(&dir).unwrap();
+        assert_eq!(
+            (map.get(&1), map.get(&2), map.get(&3)),
+            (None, Some(2), Some(3))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
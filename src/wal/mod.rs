@@ -0,0 +1,1364 @@
+mod durable;
+mod segment;
+
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use self::segment::{list_segments, segment_path, RecordReader, HEADER_LEN, MARK, PACKED, SEALED};
+use crate::checksum::crc32_update;
+use crate::codec::invalid_data;
+use crate::compression::{self, Compression};
+use crate::encryption::Encryption;
+use crate::files;
+use crate::verify::{self, VerifyReport};
+
+pub use self::durable::Durable;
+pub(crate) use self::durable::{apply_mutation, Mutation};
+
+/// Log sequence number, the position of a record in a [`Wal`].
+pub type Lsn = u64;
+
+/// Segments are rolled over once they reach this size, unless configured
+/// otherwise.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 << 20;
+
+/// When the records appended to a [`Wal`] are flushed to stable storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Every append is synced before returning, records are never lost.
+    #[default]
+    Always,
+    /// Appends are synced when at least that many milliseconds passed since
+    /// the last sync, records of the last interval can be lost on a crash.
+    EveryNMillis(u64),
+    /// Records are synced by checkpoints, those of a
+    /// [`Database`](crate::db::Database), which roll its log over, and the
+    /// rewrites of an [`Aof`](crate::aof::Aof), records written since the
+    /// last one can be lost on a crash. Logs are also synced as they are
+    /// rolled over on their own.
+    OnCheckpoint,
+    /// Records are only synced by [`Wal::sync`], and otherwise left for the
+    /// OS to write back.
+    Never,
+}
+
+impl SyncPolicy {
+    /// Returns `true` if a write made now must be synced, the last sync
+    /// having happened at `last_sync`.
+    pub(crate) fn is_due(self, last_sync: Instant) -> bool {
+        match self {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryNMillis(millis) => {
+                last_sync.elapsed() >= Duration::from_millis(millis)
+            }
+            SyncPolicy::OnCheckpoint | SyncPolicy::Never => false,
+        }
+    }
+}
+
+/// How long the segments of a [`Wal`] are kept once no longer needed, see
+/// [`Wal::remove_before`], such as for replicas to catch up from or to
+/// recover to a point in time. A segment is kept if either rule keeps it.
+///
+/// By default, segments are removed as soon as they are no longer needed.
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::wal::{Retention, WalBuilder};
+/// use std::time::Duration;
+///
+/// let builder = WalBuilder::new().retention(Retention {
+///     keep_segments: 16,
+///     keep_for: Duration::from_secs(6 * 3600),
+/// });
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Number of the most recent segments kept, on top of the one being
+    /// appended to.
+    pub keep_segments: usize,
+    /// How long segments are kept after they were last appended to.
+    pub keep_for: Duration,
+}
+
+/// A segment of a [`Wal`], see [`Wal::segments`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// LSN of the first record of the segment, which names it.
+    pub start: Lsn,
+    /// Path of the file of the segment.
+    pub path: PathBuf,
+    /// Length in bytes of the complete records of the segment.
+    pub len: u64,
+    /// Whether the segment is sealed, records being appended to the last
+    /// segment only.
+    pub sealed: bool,
+}
+
+/// End of a [`Wal`], as recorded when it is closed so that it need not be
+/// read through when opened again, see [`WalBuilder::tail`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Tail {
+    /// LSN of the first record of the last segment.
+    pub(crate) segment_start: Lsn,
+    /// Length in bytes of the last segment.
+    pub(crate) segment_len: u64,
+    /// LSN of the next record appended.
+    pub(crate) next_lsn: Lsn,
+}
+
+/// Configures and opens a [`Wal`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::wal::{SyncPolicy, WalBuilder};
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-builder");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let wal = WalBuilder::new()
+///     .segment_size(1 << 20)
+///     .sync_policy(SyncPolicy::EveryNMillis(10))
+///     .open(&dir)
+///     .unwrap();
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct WalBuilder {
+    segment_size: u64,
+    sync_policy: SyncPolicy,
+    skip_corrupted: bool,
+    read_only: bool,
+    encryption: Option<Encryption>,
+    compression: Compression,
+    retention: Retention,
+    group_commit_window: Duration,
+    time_marks: Option<Duration>,
+    tail: Option<Tail>,
+}
+
+impl Default for WalBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WalBuilder {
+    /// Creates a builder with every option set to its default.
+    pub fn new() -> Self {
+        WalBuilder {
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            sync_policy: SyncPolicy::default(),
+            skip_corrupted: false,
+            read_only: false,
+            encryption: None,
+            compression: Compression::None,
+            retention: Retention::default(),
+            group_commit_window: Duration::ZERO,
+            time_marks: None,
+            tail: None,
+        }
+    }
+
+    /// Sets the size in bytes past which segments are rolled over.
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Sets when appended records are synced to stable storage.
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Sets whether records failing their checksum are skipped when reading
+    /// the log, rather than failing the read. Skipped records are counted by
+    /// [`Iter::corrupted`].
+    pub fn skip_corrupted(mut self, skip_corrupted: bool) -> Self {
+        self.skip_corrupted = skip_corrupted;
+        self
+    }
+
+    /// Sets whether the log is opened without being modified: a record left
+    /// partially written at its end is not cut off, and appends, roll-overs
+    /// and removals fail with an error of kind `PermissionDenied`.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Encrypts the payloads of the records appended from now on, see
+    /// [`crate::encryption`]. Records appended unencrypted before are still
+    /// read, so encryption can be turned on for an existing log.
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Compresses the payloads of the records appended from now on, see
+    /// [`crate::compression`]. Records are decompressed as they are read
+    /// whatever the compression, so it can be changed for an existing log.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how long segments are kept once no longer needed.
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Sets how long a sync waits for more records to be appended before it
+    /// starts, so that it makes them durable along with the records it was
+    /// started for, see [`Wal::append_durable`]. Concurrent appends are
+    /// synced together even without a window, those arriving while a sync
+    /// is in progress waiting for the next one; a window trades the latency
+    /// of every synced append for fewer syncs under moderate concurrency.
+    pub fn group_commit_window(mut self, window: Duration) -> Self {
+        self.group_commit_window = window;
+        self
+    }
+
+    /// Appends a time mark to the log before the first record appended once
+    /// `interval` elapsed since the last one, so that the records appended
+    /// by a given time can be found, see [`Wal::lsn_at`]. Marks take up an
+    /// LSN each, but are not returned by [`Wal::iter_from`].
+    pub fn time_marks(mut self, interval: Duration) -> Self {
+        self.time_marks = Some(interval);
+        self
+    }
+
+    /// Returns the interval of the time marks, if they are appended.
+    pub(crate) fn time_marks_interval(&self) -> Option<Duration> {
+        self.time_marks
+    }
+
+    /// Takes the end of the log to be `tail`, recorded by [`Wal::tail`] when
+    /// it was last closed, rather than reading its last segment through,
+    /// unless that segment is not the one recorded or its length changed.
+    pub(crate) fn tail(mut self, tail: Option<Tail>) -> Self {
+        self.tail = tail;
+        self
+    }
+
+    /// Opens the log stored in `dir`, creating the directory if needed.
+    ///
+    /// A record left partially written at the end of the log by a crash is
+    /// cut off, so appends resume right after the last complete record, see
+    /// [`Wal::truncated_len`].
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `InvalidData` if a record of the last segment fails
+    /// its checksum, unless corrupted records are skipped, of kind `NotFound`
+    /// if the log is opened read-only and does not exist.
+    pub fn open<P: AsRef<Path>>(self, dir: P) -> io::Result<Wal> {
+        let dir = dir.as_ref().to_path_buf();
+        if !self.read_only {
+            fs::create_dir_all(&dir)?;
+        }
+
+        let segments = list_segments(&dir)?;
+        let mut truncated_len = 0;
+        let (segment_start, segment_len, next_lsn) = match segments.last() {
+            Some(&start) => {
+                let path = segment_path(&dir, start);
+                let len = fs::metadata(&path)?.len();
+                match self.tail {
+                    // left as it was when the log was closed
+                    Some(tail) if tail.segment_start == start && tail.segment_len == len => {
+                        (start, len, tail.next_lsn)
+                    }
+                    _ => {
+                        let mut reader = RecordReader::open(&path, start, self.skip_corrupted)?;
+                        while reader.next_record(true)?.is_some() {}
+                        let valid_len = reader.offset();
+                        if len > valid_len {
+                            truncated_len = len - valid_len;
+                            if !self.read_only {
+                                let file = OpenOptions::new().write(true).open(&path)?;
+                                file.set_len(valid_len)?;
+                                file.sync_all()?;
+                            }
+                        }
+                        (start, valid_len, reader.next_lsn())
+                    }
+                }
+            }
+            None if self.read_only => {
+                return Err(io::Error::new(ErrorKind::NotFound, "log has no segment"))
+            }
+            None => (1, 0, 1),
+        };
+
+        let file = match self.read_only {
+            true => File::open(segment_path(&dir, segment_start))?,
+            false => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(segment_path(&dir, segment_start))?;
+                if segments.is_empty() {
+                    files::sync_dir(&dir)?;
+                }
+                file
+            }
+        };
+
+        Ok(Wal {
+            dir,
+            segment_size: self.segment_size,
+            sync_policy: self.sync_policy,
+            skip_corrupted: self.skip_corrupted,
+            read_only: self.read_only,
+            follower: AtomicBool::new(false),
+            encryption: self.encryption,
+            compression: self.compression,
+            retention: self.retention,
+            truncated_len,
+            writer: Mutex::new(Writer {
+                file: Arc::new(file),
+                segment_start,
+                segment_len,
+                next_lsn,
+                last_sync: Instant::now(),
+                appended_len: 0,
+                unsynced_durable: false,
+                last_mark: None,
+                failed: false,
+            }),
+            group_commit_window: self.group_commit_window,
+            time_marks: self.time_marks,
+            group_commit: Mutex::new(GroupCommit {
+                synced_lsn: next_lsn,
+                syncing: false,
+            }),
+            synced: Condvar::new(),
+        })
+    }
+}
+
+/// State of the segment being appended to.
+struct Writer {
+    // shared with the syncs in progress, which run without the writer
+    // locked
+    file: Arc<File>,
+    segment_start: Lsn,
+    segment_len: u64,
+    next_lsn: Lsn,
+    last_sync: Instant,
+    appended_len: u64,
+    // set once a record waiting for a sync is appended to the segment, which
+    // is then synced as it is rolled over whatever the sync policy
+    unsynced_durable: bool,
+    last_mark: Option<Instant>,
+    // set once a record written in part could not be cut off the segment,
+    // which would end the log there on reopening, so that nothing is
+    // appended after it
+    failed: bool,
+}
+
+impl Writer {
+    /// Starts a new segment at the next LSN, syncing the current one first
+    /// unless the sync policy is `Never` and no record waits for a sync.
+    fn roll_over(&mut self, dir: &Path, sync_policy: SyncPolicy) -> io::Result<()> {
+        if sync_policy != SyncPolicy::Never || self.unsynced_durable {
+            self.file.sync_data()?;
+            self.last_sync = Instant::now();
+        }
+        self.file = Arc::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(dir, self.next_lsn))?,
+        );
+        // the records of the new segment are lost with it if its directory
+        // entry is not durable
+        files::sync_dir(dir)?;
+        self.unsynced_durable = false;
+        self.segment_start = self.next_lsn;
+        self.segment_len = 0;
+        Ok(())
+    }
+
+    /// Appends a record of `payload`, already packed and sealed as `flags`
+    /// tell, at the next LSN, rolling the segment over first if the record
+    /// would take it over `segment_size`. A record written in part is cut
+    /// off the segment, the log refusing appends if it cannot be.
+    fn write_record(
+        &mut self,
+        dir: &Path,
+        segment_size: u64,
+        sync_policy: SyncPolicy,
+        payload: &[u8],
+        flags: u32,
+    ) -> io::Result<Lsn> {
+        if self.failed {
+            return Err(io::Error::other(
+                "log ends with a record written in part, reopen it to cut it off",
+            ));
+        }
+        let lsn = self.next_lsn;
+        let record_len = (HEADER_LEN + payload.len()) as u64;
+        if self.segment_len > 0 && self.segment_len + record_len > segment_size {
+            self.roll_over(dir, sync_policy)?;
+        }
+
+        let lsn_bytes = lsn.to_le_bytes();
+        let crc = crc32_update(crc32_update(0, &lsn_bytes), payload);
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&(payload.len() as u32 | flags).to_le_bytes());
+        record.extend_from_slice(&crc.to_le_bytes());
+        record.extend_from_slice(&lsn_bytes);
+        record.extend_from_slice(payload);
+        if let Err(error) = (&*self.file).write_all(&record) {
+            if self.file.set_len(self.segment_len).is_err() {
+                self.failed = true;
+            }
+            return Err(error);
+        }
+        self.segment_len += record_len;
+        self.appended_len += record_len;
+        self.next_lsn += 1;
+        Ok(lsn)
+    }
+}
+
+/// Returns the time `time` as milliseconds since the Unix epoch, as stored
+/// in the time marks.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Progress of the syncs of a [`Wal`], which appends waiting for a sync
+/// share: one of them syncs every record appended so far while the others
+/// wait for it.
+struct GroupCommit {
+    /// Every record before this LSN is synced.
+    synced_lsn: Lsn,
+    /// Whether a sync is in progress.
+    syncing: bool,
+}
+
+/// Thread-Safe write-ahead log, stored as a sequence of segment files.
+///
+/// Records are opaque byte strings, each numbered by its [`Lsn`] and
+/// followed on disk by a CRC-32 so corruption is detected on read. A
+/// segment is named after the LSN of its first record and is rolled over
+/// once it reaches the configured segment size.
+///
+/// Appends waiting for a sync at once are made durable by a single one,
+/// see [`WalBuilder::group_commit_window`].
+///
+/// # Examples
+///
+/// ```
+/// use palladiumdb::wal::Wal;
+///
+/// let dir = std::env::temp_dir().join("palladiumdb-doc-wal");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let wal = Wal::open(&dir).unwrap();
+/// let first = wal.append(b"put a 1").unwrap();
+/// wal.append(b"put b 2").unwrap();
+/// drop(wal);
+///
+/// let wal = Wal::open(&dir).unwrap();
+/// let records: Vec<_> = wal.iter_from(first).unwrap().map(|r| r.unwrap().1).collect();
+/// assert_eq!(records, vec![b"put a 1".to_vec(), b"put b 2".to_vec()]);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Wal {
+    dir: PathBuf,
+    segment_size: u64,
+    sync_policy: SyncPolicy,
+    skip_corrupted: bool,
+    read_only: bool,
+    /// Set while the log only takes the records replicated from a primary,
+    /// see [`crate::replication`].
+    follower: AtomicBool,
+    encryption: Option<Encryption>,
+    compression: Compression,
+    retention: Retention,
+    truncated_len: u64,
+    writer: Mutex<Writer>,
+    group_commit_window: Duration,
+    time_marks: Option<Duration>,
+    group_commit: Mutex<GroupCommit>,
+    synced: Condvar,
+}
+
+impl Wal {
+    /// Opens the log stored in `dir` with the default configuration, see
+    /// [`WalBuilder::open`].
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Wal> {
+        WalBuilder::new().open(dir)
+    }
+
+    /// Returns the directory the log is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of bytes cut off the end of the log when it was
+    /// opened, left there by a crash in the middle of an append. Logs opened
+    /// read-only ignore these bytes rather than cut them off.
+    pub fn truncated_len(&self) -> u64 {
+        self.truncated_len
+    }
+
+    /// Returns `true` if the log was opened read-only, see
+    /// [`WalBuilder::read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        match self.read_only {
+            true => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "log opened read-only",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Appends a record to the log, synced according to the sync policy.
+    ///
+    /// # Returns
+    ///
+    /// The LSN of the record.
+    pub fn append(&self, payload: &[u8]) -> io::Result<Lsn> {
+        self.append_with(payload, false)
+    }
+
+    /// Appends a record to the log, synced before returning whatever the
+    /// sync policy, along with every record appended before it. Records
+    /// appended concurrently are synced together.
+    ///
+    /// # Returns
+    ///
+    /// The LSN of the record.
+    pub fn append_durable(&self, payload: &[u8]) -> io::Result<Lsn> {
+        self.append_with(payload, true)
+    }
+
+    /// Appends a record replicated from a primary, see
+    /// [`Wal::set_follower`].
+    #[cfg(feature = "server")]
+    pub(crate) fn append_replicated(&self, payload: &[u8]) -> io::Result<Lsn> {
+        self.append_record(payload, false)
+    }
+
+    /// Refuses the appends of records other than replicated ones while
+    /// `follower` is set.
+    #[cfg(feature = "server")]
+    pub(crate) fn set_follower(&self, follower: bool) {
+        self.follower.store(follower, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the log only takes replicated records.
+    pub(crate) fn is_follower(&self) -> bool {
+        self.follower.load(Ordering::SeqCst)
+    }
+
+    fn append_with(&self, payload: &[u8], sync: bool) -> io::Result<Lsn> {
+        if self.is_follower() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "log of a follower, only appended to by replication",
+            ));
+        }
+        self.append_record(payload, sync)
+    }
+
+    fn append_record(&self, payload: &[u8], sync: bool) -> io::Result<Lsn> {
+        self.check_writable()?;
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(interval) = self.time_marks {
+            if writer
+                .last_mark
+                .is_none_or(|last| last.elapsed() >= interval)
+            {
+                // the time along with the interval, within which the records
+                // appended after the mark are
+                let mut mark = unix_millis(SystemTime::now()).to_le_bytes().to_vec();
+                mark.extend_from_slice(&(interval.as_millis() as u64).to_le_bytes());
+                writer.write_record(&self.dir, self.segment_size, self.sync_policy, &mark, MARK)?;
+                writer.last_mark = Some(Instant::now());
+            }
+        }
+        let lsn = writer.next_lsn;
+
+        let mut packed = Vec::new();
+        let sealed;
+        let (payload, mut flags) = match self.compression.is_enabled() {
+            true => {
+                compression::pack(self.compression, payload, &mut packed);
+                (&packed[..], PACKED)
+            }
+            false => (payload, 0),
+        };
+        let payload = match &self.encryption {
+            Some(encryption) => {
+                // bound to its LSN, so records cannot be swapped around
+                sealed = encryption.seal(payload, &lsn.to_le_bytes())?;
+                flags |= SEALED;
+                &sealed[..]
+            }
+            None => payload,
+        };
+        writer.write_record(
+            &self.dir,
+            self.segment_size,
+            self.sync_policy,
+            payload,
+            flags,
+        )?;
+
+        if sync || self.sync_policy.is_due(writer.last_sync) {
+            writer.unsynced_durable = true;
+            drop(writer);
+            self.sync_until(lsn + 1)?;
+        }
+        Ok(lsn)
+    }
+
+    /// Returns the end of the log as it is now, see [`WalBuilder::tail`].
+    pub(crate) fn tail(&self) -> Tail {
+        let writer = self.writer.lock().unwrap();
+        Tail {
+            segment_start: writer.segment_start,
+            segment_len: writer.segment_len,
+            next_lsn: writer.next_lsn,
+        }
+    }
+
+    /// Flushes every record appended so far to stable storage.
+    pub fn sync(&self) -> io::Result<()> {
+        let next_lsn = self.writer.lock().unwrap().next_lsn;
+        self.sync_until(next_lsn)
+    }
+
+    /// Waits for every record before `lsn` to be synced, syncing them along
+    /// with every record appended so far unless a sync in progress already
+    /// does.
+    fn sync_until(&self, lsn: Lsn) -> io::Result<()> {
+        let mut group_commit = self.group_commit.lock().unwrap();
+        loop {
+            if group_commit.synced_lsn >= lsn {
+                return Ok(());
+            }
+            if !group_commit.syncing {
+                break;
+            }
+            group_commit = self.synced.wait(group_commit).unwrap();
+        }
+        group_commit.syncing = true;
+        drop(group_commit);
+
+        if !self.group_commit_window.is_zero() {
+            std::thread::sleep(self.group_commit_window);
+        }
+        // the records of the segments rolled over were synced with them
+        let (file, next_lsn) = {
+            let mut writer = self.writer.lock().unwrap();
+            writer.last_sync = Instant::now();
+            writer.unsynced_durable = false;
+            (writer.file.clone(), writer.next_lsn)
+        };
+        let result = file.sync_data();
+
+        let mut group_commit = self.group_commit.lock().unwrap();
+        group_commit.syncing = false;
+        if result.is_ok() {
+            group_commit.synced_lsn = group_commit.synced_lsn.max(next_lsn);
+        }
+        self.synced.notify_all();
+        result
+    }
+
+    /// Returns the LSN the next appended record will get.
+    pub fn next_lsn(&self) -> Lsn {
+        self.writer.lock().unwrap().next_lsn
+    }
+
+    /// Returns the number of bytes appended since the log was opened.
+    pub fn appended_len(&self) -> u64 {
+        self.writer.lock().unwrap().appended_len
+    }
+
+    /// Starts a new segment, unless the current one is empty, so that the
+    /// records appended from now on can be kept while every record before
+    /// them is removed, see [`Wal::remove_before`].
+    ///
+    /// # Returns
+    ///
+    /// The LSN of the first record of the new segment.
+    pub fn roll_over(&self) -> io::Result<Lsn> {
+        self.check_writable()?;
+        let mut writer = self.writer.lock().unwrap();
+        if writer.segment_len > 0 {
+            writer.roll_over(&self.dir, self.sync_policy)?;
+        }
+        Ok(writer.next_lsn)
+    }
+
+    /// Removes the segments holding only records before `lsn`, unless the
+    /// retention policy keeps them, see [`WalBuilder::retention`]. The
+    /// segment being appended to is always kept, and a segment is never
+    /// removed while one before it is kept, so the records kept follow one
+    /// another.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::wal::Wal;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-remove");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal = Wal::open(&dir).unwrap();
+    /// wal.append(b"put a 1").unwrap();
+    /// let lsn = wal.roll_over().unwrap();
+    /// wal.append(b"put b 2").unwrap();
+    ///
+    /// assert!(wal.remove_before(lsn).unwrap() > 0);
+    /// let records: Vec<_> = wal.iter_from(0).unwrap().map(|r| r.unwrap().1).collect();
+    /// assert_eq!(records, vec![b"put b 2".to_vec()]);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn remove_before(&self, lsn: Lsn) -> io::Result<u64> {
+        self.check_writable()?;
+        let segments = list_segments(&self.dir)?;
+        let sealed = segments.len().saturating_sub(1);
+        let removable = sealed.saturating_sub(self.retention.keep_segments);
+        let mut removed = 0;
+        for pair in segments.windows(2).take(removable) {
+            if pair[1] > lsn {
+                break;
+            }
+            let path = segment_path(&self.dir, pair[0]);
+            let metadata = fs::metadata(&path)?;
+            let age = SystemTime::now()
+                .duration_since(metadata.modified()?)
+                .unwrap_or_default();
+            if age < self.retention.keep_for {
+                break;
+            }
+            removed += metadata.len();
+            fs::remove_file(path)?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns every segment of the log, oldest first, the last one being
+    /// appended to. Records are only ever appended to that one, so the
+    /// others can be copied or shipped as they are, such as after
+    /// [`Wal::roll_over`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::wal::Wal;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-segments");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal = Wal::open(&dir).unwrap();
+    /// wal.append(b"put a 1").unwrap();
+    /// let lsn = wal.roll_over().unwrap();
+    ///
+    /// let segments = wal.segments().unwrap();
+    /// assert_eq!(segments.len(), 2);
+    /// assert!(segments[0].sealed && segments[0].len > 0);
+    /// assert_eq!((segments[1].start, segments[1].len), (lsn, 0));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn segments(&self) -> io::Result<Vec<Segment>> {
+        // nothing is rolled over, nor appended, while listing
+        let writer = self.writer.lock().unwrap();
+        let mut segments = Vec::new();
+        for start in list_segments(&self.dir)? {
+            let path = segment_path(&self.dir, start);
+            let sealed = start != writer.segment_start;
+            let len = match sealed {
+                true => match fs::metadata(&path) {
+                    Ok(metadata) => metadata.len(),
+                    // removed in the meantime
+                    Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                },
+                false => writer.segment_len,
+            };
+            segments.push(Segment {
+                start,
+                path,
+                len,
+                sealed,
+            });
+        }
+        Ok(segments)
+    }
+
+    /// Opens the segment starting at `start` for reading its bytes as they
+    /// are stored, such as to stream it to a replica or to an archive, from
+    /// which the log can be put back together by copying the segments into
+    /// a directory of their own. Sealed segments are read whole, the one
+    /// being appended to up to the end of the records appended so far.
+    ///
+    /// # Returns
+    ///
+    /// An error of kind `NotFound` if the log has no such segment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::wal::Wal;
+    /// use std::io::Read;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-read-segment");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal = Wal::open(&dir).unwrap();
+    /// let lsn = wal.append(b"put a 1").unwrap();
+    ///
+    /// let mut bytes = Vec::new();
+    /// wal.read_segment(lsn).unwrap().read_to_end(&mut bytes).unwrap();
+    /// assert_eq!(bytes.len() as u64, wal.segments().unwrap()[0].len);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn read_segment(&self, start: Lsn) -> io::Result<io::Take<File>> {
+        let writer = self.writer.lock().unwrap();
+        let file = File::open(segment_path(&self.dir, start))?;
+        let len = match start == writer.segment_start {
+            true => writer.segment_len,
+            false => file.metadata()?.len(),
+        };
+        Ok(file.take(len))
+    }
+
+    /// Checks the checksum of every record of the log, reporting the records
+    /// failing it along with the unreadable parts of segments, see
+    /// [`crate::verify`]. A record left partially written at the end of the
+    /// log by a crash is not reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::wal::Wal;
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-verify");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal = Wal::open(&dir).unwrap();
+    /// wal.append(b"put a 1").unwrap();
+    /// wal.sync().unwrap();
+    ///
+    /// let report = wal.verify().unwrap();
+    /// assert!(report.is_clean());
+    /// assert_eq!(report.files, 1);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn verify(&self) -> io::Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let segments = list_segments(&self.dir)?;
+        for (index, start) in segments.iter().enumerate() {
+            let path = segment_path(&self.dir, *start);
+            // segments removed in the meantime are skipped
+            let mut reader = match RecordReader::open(&path, *start, true) {
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                reader => reader?,
+            };
+            let tail = index + 1 == segments.len();
+            let result = loop {
+                match reader.next_record(tail) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+            let len = fs::metadata(&path)?.len();
+            for (offset, record_len) in reader.corrupted_regions() {
+                let err = invalid_data("record checksum mismatch");
+                report.corrupted(&path, *offset, *record_len, &err);
+            }
+            match result {
+                Err(err) if verify::is_corruption(&err) => {
+                    // the records after it cannot be located
+                    let offset = reader.offset();
+                    report.corrupted(&path, offset, len.saturating_sub(offset), &err);
+                }
+                result => result?,
+            }
+            report.checked(len);
+        }
+        Ok(report)
+    }
+
+    /// Returns the LSN of the first record appended after `time`, the next
+    /// LSN if there is none, or the first LSN of the log if no time mark was
+    /// appended by then, see [`WalBuilder::time_marks`]. Records failing
+    /// their checksum are skipped.
+    ///
+    /// Records are only known to be appended by a time if a mark appended
+    /// after them says so, or if no mark was appended for an interval after
+    /// a mark before them. In between, within an interval of the last mark
+    /// before `time`, only the record the mark was appended along with
+    /// counts as appended by then, so the LSN found is up to the interval
+    /// of the marks early, never late.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use palladiumdb::wal::WalBuilder;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let dir = std::env::temp_dir().join("palladiumdb-doc-wal-lsn-at");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// let wal = WalBuilder::new()
+    ///     .time_marks(Duration::ZERO)
+    ///     .open(&dir)
+    ///     .unwrap();
+    /// wal.append(b"put a 1").unwrap();
+    /// std::thread::sleep(Duration::from_millis(5));
+    /// let before = SystemTime::now();
+    /// std::thread::sleep(Duration::from_millis(5));
+    /// let lsn = wal.append(b"delete a").unwrap();
+    ///
+    /// // the delete is preceded by its time mark
+    /// assert_eq!(wal.lsn_at(before).unwrap(), lsn - 1);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn lsn_at(&self, time: SystemTime) -> io::Result<Lsn> {
+        let time = unix_millis(time);
+        let segments = list_segments(&self.dir)?;
+        let first = segments.first().copied().unwrap_or_else(|| self.next_lsn());
+        // the records from the last mark at or before `time` to the next
+        // one were appended by then if another mark was due by then
+        let resolve = |last: Option<(Lsn, u64, u64)>, next: Lsn| match last {
+            Some((_, marked, interval)) if time >= marked.saturating_add(interval) => next,
+            Some((lsn, _, _)) => (lsn + 2).min(next),
+            None => first,
+        };
+        let mut last = None;
+        let mut next = first;
+        for (index, start) in segments.iter().enumerate() {
+            let path = segment_path(&self.dir, *start);
+            let mut reader = RecordReader::open(&path, *start, true)?;
+            let tail = index + 1 == segments.len();
+            while let Some((lsn, payload, flags)) = reader.next_record(tail)? {
+                if flags & MARK == 0 {
+                    continue;
+                }
+                if payload.len() != 16 {
+                    return Err(invalid_data("time mark corrupted"));
+                }
+                let marked = u64::from_le_bytes(payload[..8].try_into().unwrap());
+                let interval = u64::from_le_bytes(payload[8..].try_into().unwrap());
+                if marked > time {
+                    return Ok(resolve(last, lsn));
+                }
+                last = Some((lsn, marked, interval));
+            }
+            next = reader.next_lsn();
+        }
+        Ok(resolve(last, next))
+    }
+
+    /// Removes every record from `lsn` on, such as to recover to a point in
+    /// time, appends resuming at `lsn`.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes removed, an error of kind `InvalidInput` if the
+    /// records before `lsn` were removed already.
+    pub fn truncate_from(&self, lsn: Lsn) -> io::Result<u64> {
+        self.check_writable()?;
+        let mut writer = self.writer.lock().unwrap();
+        if lsn >= writer.next_lsn {
+            return Ok(0);
+        }
+        let segments = list_segments(&self.dir)?;
+        let holding = match segments.iter().rposition(|start| *start <= lsn) {
+            Some(holding) => segments[holding],
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "log truncated before the records removed",
+                ))
+            }
+        };
+
+        let path = segment_path(&self.dir, holding);
+        let mut reader = RecordReader::open(&path, holding, true)?;
+        while reader.next_lsn() < lsn && reader.next_record(true)?.is_some() {}
+        let file = OpenOptions::new().write(true).open(&path)?;
+        let mut removed = file.metadata()?.len() - reader.offset();
+        file.set_len(reader.offset())?;
+        file.sync_all()?;
+        for start in segments.into_iter().filter(|start| *start > holding) {
+            let path = segment_path(&self.dir, start);
+            removed += fs::metadata(&path)?.len();
+            fs::remove_file(path)?;
+        }
+
+        writer.file = Arc::new(OpenOptions::new().append(true).open(&path)?);
+        writer.segment_start = holding;
+        writer.segment_len = reader.offset();
+        writer.next_lsn = lsn;
+        // the next append is marked anew
+        writer.last_mark = None;
+        writer.failed = false;
+        let mut group_commit = self.group_commit.lock().unwrap();
+        group_commit.synced_lsn = group_commit.synced_lsn.min(lsn);
+        Ok(removed)
+    }
+
+    /// Returns an iterator over the records of the log from `lsn` on, each
+    /// along with its LSN.
+    ///
+    /// Records appended while iterating may or may not be returned. Unless
+    /// corrupted records are skipped, the iterator returns an error of kind
+    /// `InvalidData` on a record failing its checksum, and stops after it.
+    pub fn iter_from(&self, lsn: Lsn) -> io::Result<Iter> {
+        let mut segments = list_segments(&self.dir)?;
+        // the last segment starting at or before lsn holds it
+        let first = segments
+            .iter()
+            .rposition(|start| *start <= lsn)
+            .unwrap_or(0);
+        segments.drain(..first);
+        Ok(Iter {
+            dir: self.dir.clone(),
+            segments: segments.into_iter(),
+            reader: None,
+            from: lsn,
+            skip_corrupted: self.skip_corrupted,
+            encryption: self.encryption.clone(),
+            corrupted: 0,
+            failed: false,
+        })
+    }
+}
+
+/// Iterator over the records of a [`Wal`], see [`Wal::iter_from`].
+pub struct Iter {
+    dir: PathBuf,
+    segments: std::vec::IntoIter<Lsn>,
+    reader: Option<RecordReader>,
+    from: Lsn,
+    skip_corrupted: bool,
+    encryption: Option<Encryption>,
+    corrupted: u64,
+    failed: bool,
+}
+
+impl Iter {
+    /// Returns the number of records skipped so far for failing their
+    /// checksum, see [`WalBuilder::skip_corrupted`].
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted + self.reader.as_ref().map_or(0, RecordReader::corrupted)
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<(Lsn, Vec<u8>)>> {
+        loop {
+            if self.reader.is_none() {
+                match self.segments.next() {
+                    Some(start) => {
+                        let path = segment_path(&self.dir, start);
+                        let reader = RecordReader::open(&path, start, self.skip_corrupted)?;
+                        self.reader = Some(reader);
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            // a partial record can only be the one being appended, at the
+            // end of the last segment
+            let last = self.segments.len() == 0;
+            match self.reader.as_mut().unwrap().next_record(last)? {
+                Some((lsn, _, _)) if lsn < self.from => {}
+                Some((_, _, flags)) if flags & MARK != 0 => {}
+                Some((lsn, mut payload, flags)) => {
+                    if flags & SEALED != 0 {
+                        let encryption = self.encryption.as_ref().ok_or_else(|| {
+                            io::Error::new(
+                                ErrorKind::InvalidInput,
+                                "encrypted record read without encryption configured",
+                            )
+                        })?;
+                        payload = encryption.open(&payload, &lsn.to_le_bytes())?;
+                    }
+                    if flags & PACKED != 0 {
+                        payload = compression::unpack(payload)?;
+                    }
+                    return Ok(Some((lsn, payload)));
+                }
+                None => self.corrupted += self.reader.take().unwrap().corrupted(),
+            }
+        }
+    }
+}
+
+impl Iterator for Iter {
+    type Item = io::Result<(Lsn, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = self.next_record().transpose();
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Retention, SyncPolicy, Wal, WalBuilder};
+    use std::convert::TryInto;
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reopen_cuts_torn_tail_across_segments() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-wal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let builder = WalBuilder::new()
+            .segment_size(256)
+            .sync_policy(SyncPolicy::Never);
+
+        let wal = Arc::new(builder.clone().open(&dir).unwrap());
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let wal = wal.clone();
+                thread::spawn(move || {
+                    for i in 0..50u64 {
+                        wal.append(&(thread * 50 + i).to_le_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        wal.sync().unwrap();
+        drop(wal);
+
+        // simulate a crash in the middle of appending the last record
+        let mut segments: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert!(segments.len() > 1);
+        segments.sort();
+        let last = OpenOptions::new()
+            .write(true)
+            .open(segments.last().unwrap())
+            .unwrap();
+        let len = last.metadata().unwrap().len();
+        last.set_len(len - 3).unwrap();
+
+        let wal = builder.open(&dir).unwrap();
+        assert_eq!(wal.next_lsn(), 200);
+        let lsn = wal.append(b"after").unwrap();
+        assert_eq!(lsn, 200);
+
+        let records: Vec<_> = wal.iter_from(1).unwrap().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 200);
+        assert!(records
+            .iter()
+            .enumerate()
+            .all(|(i, (lsn, _))| *lsn == i as u64 + 1));
+        let mut values: Vec<_> = records[..199]
+            .iter()
+            .map(|(_, payload)| u64::from_le_bytes(payload[..].try_into().unwrap()))
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), 199);
+        assert_eq!(records[199].1, b"after");
+        assert_eq!(wal.iter_from(150).unwrap().count(), 51);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_appends_leave_no_record_in_part() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-wal-failed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let builder = WalBuilder::new().sync_policy(SyncPolicy::Never);
+        let wal = builder.clone().open(&dir).unwrap();
+        wal.append(b"before").unwrap();
+
+        // a segment that can neither be written nor cut off
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let writable = {
+            let mut writer = wal.writer.lock().unwrap();
+            std::mem::replace(&mut writer.file, Arc::new(File::open(&path).unwrap()))
+        };
+        assert!(wal.append(b"lost").is_err());
+        wal.writer.lock().unwrap().file = writable;
+        assert!(wal.append(b"refused").is_err());
+        assert_eq!(wal.next_lsn(), 2);
+        drop(wal);
+
+        let wal = builder.open(&dir).unwrap();
+        assert_eq!(wal.truncated_len(), 0);
+        assert_eq!(wal.append(b"after").unwrap(), 2);
+        let records: Vec<_> = wal.iter_from(1).unwrap().map(|r| r.unwrap().1).collect();
+        assert_eq!(records, vec![b"before".to_vec(), b"after".to_vec()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_durable_appends_share_syncs() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-wal-group-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let window = Duration::from_millis(20);
+        let wal = Arc::new(
+            WalBuilder::new()
+                .segment_size(512)
+                .sync_policy(SyncPolicy::Never)
+                .group_commit_window(window)
+                .open(&dir)
+                .unwrap(),
+        );
+
+        let started = std::time::Instant::now();
+        let writers: Vec<_> = (0..8u64)
+            .map(|thread| {
+                let wal = wal.clone();
+                thread::spawn(move || {
+                    for i in 0..10u64 {
+                        wal.append_durable(&(thread * 10 + i).to_le_bytes())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        // one window per append would take 80 of them
+        assert!(started.elapsed() < window * 60);
+        assert!(wal.segments().unwrap().len() > 1);
+        drop(wal);
+
+        let wal = Wal::open(&dir).unwrap();
+        let mut values: Vec<_> = wal
+            .iter_from(0)
+            .unwrap()
+            .map(|record| u64::from_le_bytes(record.unwrap().1[..].try_into().unwrap()))
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..80).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_on_checkpoint_defers_syncs_to_roll_overs() {
+        let dir =
+            std::env::temp_dir().join(format!("palladiumdb-wal-defer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let wal = WalBuilder::new()
+            .segment_size(256)
+            .sync_policy(SyncPolicy::OnCheckpoint)
+            .open(&dir)
+            .unwrap();
+        let last_sync = || wal.writer.lock().unwrap().last_sync;
+        let synced_lsn = || wal.group_commit.lock().unwrap().synced_lsn;
+
+        let opened = last_sync();
+        thread::sleep(Duration::from_millis(1));
+        wal.append(b"deferred").unwrap();
+        wal.append(b"deferred").unwrap();
+        assert_eq!((last_sync(), synced_lsn()), (opened, 1));
+
+        // synced by a checkpoint, which rolls the log over
+        wal.roll_over().unwrap();
+        let rolled_over = last_sync();
+        assert!(rolled_over > opened);
+
+        // or as the log rolls over on its own
+        thread::sleep(Duration::from_millis(1));
+        while wal.segments().unwrap().len() < 3 {
+            assert_eq!(last_sync(), rolled_over);
+            wal.append(&[0; 64]).unwrap();
+        }
+        assert!(last_sync() > rolled_over);
+
+        // unless synced on demand
+        let lsn = wal.append_durable(b"durable").unwrap();
+        assert_eq!(synced_lsn(), lsn + 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retention_keeps_segments_to_stream() {
+        let dir = std::env::temp_dir().join(format!("palladiumdb-wal-keep-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let builder = WalBuilder::new().segment_size(64).retention(Retention {
+            keep_segments: 2,
+            keep_for: Duration::ZERO,
+        });
+        let wal = builder.clone().open(dir.join("primary")).unwrap();
+        for i in 0..40u64 {
+            wal.append(&i.to_le_bytes()).unwrap();
+        }
+        assert!(wal.segments().unwrap().len() > 3);
+        assert!(wal.remove_before(wal.next_lsn()).unwrap() > 0);
+        let segments = wal.segments().unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(
+            segments.iter().map(|s| s.sealed).collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+
+        // the segments streamed make up a log of their own
+        let replica = dir.join("replica");
+        fs::create_dir_all(&replica).unwrap();
+        for segment in &segments {
+            let mut copy = File::create(replica.join(segment.path.file_name().unwrap())).unwrap();
+            let copied = io::copy(&mut wal.read_segment(segment.start).unwrap(), &mut copy);
+            assert_eq!(copied.unwrap(), segment.len);
+        }
+        let replica = Wal::open(&replica).unwrap();
+        assert_eq!(replica.next_lsn(), wal.next_lsn());
+        let records: Vec<_> = replica.iter_from(0).unwrap().map(Result::unwrap).collect();
+        assert_eq!(records.first().unwrap().0, segments[0].start);
+        assert_eq!(records.last().unwrap().1, 39u64.to_le_bytes());
+        assert_eq!(
+            wal.read_segment(1).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // segments appended to recently are kept whatever their number
+        drop(wal);
+        let wal = builder
+            .retention(Retention {
+                keep_segments: 0,
+                keep_for: Duration::from_secs(3600),
+            })
+            .open(dir.join("primary"))
+            .unwrap();
+        wal.roll_over().unwrap();
+        assert_eq!(wal.remove_before(wal.next_lsn()).unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
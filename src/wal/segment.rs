@@ -0,0 +1,167 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use super::Lsn;
+use crate::checksum::crc32_update;
+use crate::codec::invalid_data;
+
+/// Length of the header of a record: payload length, CRC-32 and LSN.
+pub(super) const HEADER_LEN: usize = 16;
+
+/// Flag set in the length of records whose payload is encrypted.
+pub(super) const SEALED: u32 = 1 << 31;
+/// Flag set in the length of records whose payload is compressed, before
+/// being encrypted if it is.
+pub(super) const PACKED: u32 = 1 << 30;
+/// Flag set in the length of the time marks of the log, whose payload is
+/// the time they were appended at, see [`WalBuilder::time_marks`].
+///
+/// [`WalBuilder::time_marks`]: super::WalBuilder::time_marks
+pub(super) const MARK: u32 = 1 << 29;
+const FLAGS: u32 = SEALED | PACKED | MARK;
+
+const EXTENSION: &str = "wal";
+
+/// Returns the path of the segment of `dir` starting at `start`.
+pub(super) fn segment_path(dir: &Path, start: Lsn) -> PathBuf {
+    dir.join(format!("{:020}.{}", start, EXTENSION))
+}
+
+/// Returns the first LSN of every segment of `dir`, in ascending order.
+pub(super) fn list_segments(dir: &Path) -> io::Result<Vec<Lsn>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+            continue;
+        }
+        if let Some(start) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            segments.push(start);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Reads the records of a segment one after the other, checking each one.
+pub(super) struct RecordReader {
+    reader: BufReader<File>,
+    offset: u64,
+    next_lsn: Lsn,
+    skip_corrupted: bool,
+    /// Offsets and lengths of the records skipped.
+    corrupted: Vec<(u64, u64)>,
+}
+
+impl RecordReader {
+    /// Opens the segment at `path`, starting at `start`. Records failing
+    /// their checksum are skipped if `skip_corrupted` is set.
+    pub(super) fn open(path: &Path, start: Lsn, skip_corrupted: bool) -> io::Result<Self> {
+        Ok(RecordReader {
+            reader: BufReader::new(File::open(path)?),
+            offset: 0,
+            next_lsn: start,
+            skip_corrupted,
+            corrupted: Vec::new(),
+        })
+    }
+
+    /// Returns the number of records skipped for failing their checksum.
+    pub(super) fn corrupted(&self) -> u64 {
+        self.corrupted.len() as u64
+    }
+
+    /// Returns the offsets and lengths of the records skipped for failing
+    /// their checksum.
+    pub(super) fn corrupted_regions(&self) -> &[(u64, u64)] {
+        &self.corrupted
+    }
+
+    /// Returns the offset right after the last record read.
+    pub(super) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the LSN the next record must have.
+    pub(super) fn next_lsn(&self) -> Lsn {
+        self.next_lsn
+    }
+
+    /// Reads as many bytes as available into `buf`, up to its length.
+    fn read_up_to(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.reader.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(read)
+    }
+
+    /// Reads the next record, along with whether its payload is encrypted.
+    ///
+    /// # Returns
+    ///
+    /// `None` at the end of the segment. A partially written record, or a
+    /// zeroed header as left by a crash after the file was extended, also
+    /// counts as the end if `tail` is set, and as an error otherwise. So
+    /// does a record failing its checksum at the very end of the segment,
+    /// as left by a crash in the middle of writing its payload. Records come
+    /// with the flags set in their length.
+    pub(super) fn next_record(&mut self, tail: bool) -> io::Result<Option<(Lsn, Vec<u8>, u32)>> {
+        loop {
+            let mut header = [0u8; HEADER_LEN];
+            let read = self.read_up_to(&mut header)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            let torn = read < HEADER_LEN || header.iter().all(|byte| *byte == 0);
+
+            let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let mut payload = Vec::new();
+            let complete = !torn && {
+                payload.resize((len & !FLAGS) as usize, 0);
+                self.read_up_to(&mut payload)? == payload.len()
+            };
+            if !complete {
+                return match tail {
+                    true => Ok(None),
+                    false => Err(invalid_data("truncated record in sealed segment")),
+                };
+            }
+
+            let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            let mut lsn_bytes = [0u8; 8];
+            lsn_bytes.copy_from_slice(&header[8..]);
+            if crc32_update(crc32_update(0, &lsn_bytes), &payload) != crc {
+                if tail && self.reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                if !self.skip_corrupted {
+                    return Err(invalid_data("record checksum mismatch"));
+                }
+                let len = (HEADER_LEN + payload.len()) as u64;
+                self.corrupted.push((self.offset, len));
+                self.offset += len;
+                self.next_lsn += 1;
+                continue;
+            }
+            let lsn = Lsn::from_le_bytes(lsn_bytes);
+            if lsn != self.next_lsn {
+                return Err(invalid_data("record out of sequence"));
+            }
+
+            self.offset += (HEADER_LEN + payload.len()) as u64;
+            self.next_lsn += 1;
+            return Ok(Some((lsn, payload, len & FLAGS)));
+        }
+    }
+}